@@ -0,0 +1,145 @@
+//! Criterion benchmarks for SQL execution
+//!
+//! Bulk inserts, nested loop and hash joins of the same tables, joins and
+//! grouping of text columns, and aggregation over 1,000,000 rows. Run with
+//! `cargo bench --bench sql`.
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use rustdb::{
+    db::Database,
+    error::Result,
+    sql::executor::ResultSet,
+    storage::{memory::MemoryEngine, mvcc::MvccTransaction},
+};
+
+/// Rows per INSERT statement
+const INSERT_ROWS: usize = 1000;
+/// Rows of each join side
+const JOIN_ROWS: usize = 1000;
+/// Rows of each side of the text join
+const TEXT_ROWS: usize = 1000;
+/// Rows aggregated
+const AGGREGATE_ROWS: usize = 1_000_000;
+
+/// Columns of a table named `t`: `t_id int primary key, t_k int, t_v float`,
+/// distinct per table since joins don't qualify column names
+fn create(table: &str) -> String {
+    format!("create table {0} ({0}_id int primary key, {0}_k int, {0}_v float);", table)
+}
+
+/// An INSERT of `rows` rows into a table, numbered from `start`
+fn insert(table: &str, start: usize, rows: usize) -> String {
+    let values = (start..start + rows)
+        .map(|i| format!("({}, {}, {}.5)", i, i % 100, i % 1000))
+        .collect::<Vec<_>>();
+    format!("insert into {} values {};", table, values.join(", "))
+}
+
+/// Columns of a table named `t`: `t_id int primary key, t_k text, t_v text`,
+/// with 64-character values, so copying rows would copy their text
+fn create_text(table: &str) -> String {
+    format!("create table {0} ({0}_id int primary key, {0}_k text, {0}_v text);", table)
+}
+
+/// An INSERT of `rows` rows into a text table, numbered from `start`
+fn insert_text(table: &str, start: usize, rows: usize) -> String {
+    let values = (start..start + rows)
+        .map(|i| format!("({}, '{:064}', '{:064}')", i, i % 100, i))
+        .collect::<Vec<_>>();
+    format!("insert into {} values {};", table, values.join(", "))
+}
+
+/// A database with the given tables of `rows` rows each, and a read
+/// transaction on it that the benchmark iterations share (a transaction per
+/// iteration would pass the 255 versions `scan_prefix` can bound)
+fn load(tables: &[&str], rows: usize) -> Result<(Database<MemoryEngine>, MvccTransaction<MemoryEngine>)> {
+    load_with(tables, rows, create, insert)
+}
+
+/// `load` with the tables created and filled by the given statements
+fn load_with(
+    tables: &[&str],
+    rows: usize,
+    create: fn(&str) -> String,
+    insert: fn(&str, usize, usize) -> String,
+) -> Result<(Database<MemoryEngine>, MvccTransaction<MemoryEngine>)> {
+    let db = Database::new(MemoryEngine::new());
+    let txn = db.kv_txn()?;
+    for table in tables {
+        db.execute_in(&txn, &create(table))?;
+        for start in (0..rows).step_by(10_000) {
+            db.execute_in(&txn, &insert(table, start, 10_000.min(rows - start)))?;
+        }
+    }
+    txn.commit()?;
+    let txn = db.kv_txn()?;
+    Ok((db, txn))
+}
+
+/// Row count of a query
+fn query(db: &Database<MemoryEngine>, txn: &MvccTransaction<MemoryEngine>, sql: &str) -> usize {
+    match db.execute_in(txn, sql).unwrap() {
+        ResultSet::Scan { rows, .. } => rows.len(),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let sql = insert("t", 0, INSERT_ROWS);
+    c.bench_function("bulk_insert_1000", |b| {
+        b.iter_batched(
+            || {
+                let mut session = Database::new(MemoryEngine::new()).session().unwrap();
+                session.execute(&create("t")).unwrap();
+                session
+            },
+            |mut session| session.execute(black_box(&sql)).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+fn bench_join(c: &mut Criterion) {
+    let (db, txn) = load(&["t1", "t2"], JOIN_ROWS).unwrap();
+    let mut group = c.benchmark_group("join_1000x1000");
+    group.sample_size(20);
+    // `t1_k + 0` hides the column equality from the planner, keeping the nested loop
+    group.bench_function("nested_loop", |b| {
+        b.iter(|| query(&db, &txn, black_box("select * from t1 join t2 on t1_k + 0 = t2_k;")))
+    });
+    group.bench_function("hash", |b| b.iter(|| query(&db, &txn, black_box("select * from t1 join t2 on t1_k = t2_k;"))));
+    group.finish();
+    txn.commit().unwrap();
+}
+
+/// Joins and grouping that clone rows of text columns many times over
+fn bench_text(c: &mut Criterion) {
+    let (db, txn) = load_with(&["t1", "t2"], TEXT_ROWS, create_text, insert_text).unwrap();
+    let mut group = c.benchmark_group("text_1000x1000");
+    group.sample_size(20);
+    group.bench_function("hash_join", |b| b.iter(|| query(&db, &txn, black_box("select * from t1 join t2 on t1_k = t2_k;"))));
+    group.bench_function("group_by", |b| {
+        b.iter(|| query(&db, &txn, black_box("select t1_k, count(t1_id), max(t2_v) from t1 join t2 on t1_k = t2_k group by t1_k;")))
+    });
+    group.finish();
+    txn.commit().unwrap();
+}
+
+fn bench_aggregate(c: &mut Criterion) {
+    let (db, txn) = load(&["t"], AGGREGATE_ROWS).unwrap();
+    let mut group = c.benchmark_group("aggregate_1m");
+    group.sample_size(10);
+    group.bench_function("count_sum", |b| {
+        b.iter(|| query(&db, &txn, black_box("select count(t_id), sum(t_v), max(t_k) from t;")))
+    });
+    group.bench_function("group_by", |b| {
+        b.iter(|| query(&db, &txn, black_box("select t_k, count(t_id), avg(t_v) from t group by t_k;")))
+    });
+    group.finish();
+    txn.commit().unwrap();
+}
+
+criterion_group!(benches, bench_insert, bench_join, bench_text, bench_aggregate);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! Criterion benchmarks for MVCC storage reads
+//!
+//! Point gets and prefix scans on a `Mvcc<MemoryEngine>` of 100,000 keys. Run
+//! with `cargo bench --bench storage`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use rustdb::{
+    error::Result,
+    storage::{memory::MemoryEngine, mvcc::Mvcc},
+};
+
+/// Key prefixes, each with `KEYS_PER_PREFIX` keys
+const PREFIXES: usize = 100;
+const KEYS_PER_PREFIX: usize = 1000;
+
+fn key(prefix: usize, n: usize) -> Vec<u8> {
+    format!("p{:03}-k{:04}", prefix, n).into_bytes()
+}
+
+/// Loads every key in one committed transaction
+fn load() -> Result<Mvcc<MemoryEngine>> {
+    let mvcc = Mvcc::new(MemoryEngine::new());
+    let txn = mvcc.begin()?;
+    for prefix in 0..PREFIXES {
+        for n in 0..KEYS_PER_PREFIX {
+            txn.set(key(prefix, n), vec![0xab; 64])?;
+        }
+    }
+    txn.commit()?;
+    Ok(mvcc)
+}
+
+// Iterations share one read transaction: a transaction per iteration would
+// pass the 255 versions `scan_prefix` can bound
+fn bench_reads(c: &mut Criterion) {
+    let mvcc = load().unwrap();
+    let txn = mvcc.begin().unwrap();
+
+    let mut n = 0;
+    c.bench_function("point_get", |b| {
+        b.iter(|| {
+            n = (n + 7919) % (PREFIXES * KEYS_PER_PREFIX);
+            txn.get(black_box(key(n / KEYS_PER_PREFIX, n % KEYS_PER_PREFIX))).unwrap()
+        })
+    });
+
+    let mut prefix = 0;
+    c.bench_function("prefix_scan_1000", |b| {
+        b.iter(|| {
+            prefix = (prefix + 37) % PREFIXES;
+            let results = txn.scan_prefix(black_box(format!("p{:03}-", prefix).into_bytes())).unwrap();
+            assert_eq!(results.len(), KEYS_PER_PREFIX);
+            results
+        })
+    });
+    txn.commit().unwrap();
+}
+
+criterion_group!(benches, bench_reads);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+//! Compiles the gRPC service definition when the `grpc` feature is on
+//!
+//! Uses protox, a pure-Rust protobuf compiler, so no `protoc` is needed.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/rustdb.proto");
+        let descriptors = protox::compile(["proto/rustdb.proto"], ["proto"]).expect("invalid proto/rustdb.proto");
+        tonic_prost_build::configure()
+            .compile_fds(descriptors)
+            .expect("failed to generate the gRPC service");
+    }
+}
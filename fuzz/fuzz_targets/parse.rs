@@ -0,0 +1,24 @@
+//! Lexes and parses arbitrary text, which must never panic
+//!
+//! Whatever parses must print back to SQL that parses to the same statement.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustdb::sql::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(sql) = std::str::from_utf8(data) else { return };
+    let Ok(statements) = Parser::new(sql).parse_script() else { return };
+    // Lowercasing some non-ASCII identifiers changes how they lex
+    if !sql.is_ascii() {
+        return;
+    }
+    for stmt in statements {
+        let printed = stmt.to_sql();
+        match Parser::new(&printed).parse() {
+            Ok(reparsed) => assert_eq!(reparsed, stmt, "{}", printed),
+            Err(err) => panic!("{} does not parse: {}", printed, err),
+        }
+    }
+});
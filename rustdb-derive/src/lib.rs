@@ -0,0 +1,105 @@
+//! `#[derive(Record)]` - maps a struct to the rows of a table
+//!
+//! Implements `rustdb::sql::record::Record`, whose docs describe the
+//! attributes. Use it through rustdb's `derive` feature, which re-exports it.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, LitStr, parse_macro_input};
+
+#[proc_macro_derive(Record, attributes(record))]
+pub fn derive_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let mut table = snake_case(&ident.to_string());
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("record")) {
+        attr.parse_nested_meta(|meta| match meta.path.is_ident("table") {
+            true => {
+                table = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            }
+            false => Err(meta.error("unknown record attribute, expected `table = \"name\"`")),
+        })?;
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(ident, "Record needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(ident, "Record can only be derived for structs")),
+    };
+
+    let (mut idents, mut names, mut types, mut primary_keys) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for field in fields {
+        let field_ident = field.ident.as_ref().expect("named field");
+        let mut name = field_ident.to_string().trim_start_matches("r#").to_string();
+        let mut primary_key = false;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("record")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("primary_key") {
+                    primary_key = true;
+                } else if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                } else {
+                    return Err(meta.error("unknown record attribute, expected `primary_key` or `rename = \"name\"`"));
+                }
+                Ok(())
+            })?;
+        }
+        idents.push(field_ident);
+        names.push(name);
+        types.push(&field.ty);
+        primary_keys.push(primary_key);
+    }
+    if primary_keys.iter().filter(|pk| **pk).count() != 1 {
+        return Err(syn::Error::new_spanned(ident, "Record needs exactly one field marked #[record(primary_key)]"));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rustdb::sql::record::Record for #ident #ty_generics #where_clause {
+            const TABLE: &'static str = #table;
+
+            fn columns() -> ::std::vec::Vec<::rustdb::sql::record::RecordColumn> {
+                ::std::vec![#(::rustdb::sql::record::RecordColumn {
+                    name: #names,
+                    datatype: <#types as ::rustdb::sql::record::SqlValue>::DATATYPE,
+                    nullable: <#types as ::rustdb::sql::record::SqlValue>::NULLABLE,
+                    primary_key: #primary_keys,
+                }),*]
+            }
+
+            fn to_row(&self) -> ::rustdb::sql::types::Row {
+                ::std::vec![#(::rustdb::sql::record::SqlValue::to_value(&self.#idents)),*]
+            }
+
+            fn from_row(
+                columns: &[::std::string::String],
+                mut row: ::rustdb::sql::types::Row,
+            ) -> ::rustdb::error::Result<Self> {
+                ::std::result::Result::Ok(Self {
+                    #(#idents: ::rustdb::sql::record::take_column(columns, &mut row, #names)?),*
+                })
+            }
+        }
+    })
+}
+
+/// The table name of a struct name, e.g. order_item for OrderItem
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
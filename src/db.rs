@@ -0,0 +1,1014 @@
+//! Embeddable database handle
+//!
+//! `Database` owns a storage engine and offers two data models over it:
+//! SQL sessions, and raw key-value transactions on the MVCC layer.
+//! Both share the same transactional guarantees and can be mixed in one commit.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex, mpsc::Receiver},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use crate::storage::gc::GcWorker;
+use crate::{
+    error::{Error, Result},
+    sql::{
+        analyzer::Analyzer,
+        engine::{
+            Engine, Session, Transaction, changefeed::ChangeEvent, kv::{CheckReport, KVEngine, KVTransaction}, querylog::QueryLog,
+            tenant::Tenancy,
+        },
+        executor::ResultSet,
+        parser::{Parser, ast},
+        plan::Plan,
+        schema::{Partition, Table, literal},
+        types::Value,
+    },
+    storage::{
+        config::{self, AnyEngine, Config, EngineKind},
+        engine::{Durability, Engine as StorageEngine, EngineOptions},
+        gc::{GcOptions, GcStats},
+        snapshot::ReadOnlySnapshotEngine,
+        mvcc::{MvccTransaction, Version},
+    },
+};
+
+/// Rows per INSERT statement of a dump
+const DUMP_BATCH: usize = 100;
+
+/// Database handle over a storage engine
+pub struct Database<E: StorageEngine> {
+    engine: KVEngine<E>,
+    /// Set on every session opened
+    query_log: Option<QueryLog>,
+    /// Engines of the tenants that opened sessions, by tenant id
+    tenants: Arc<Mutex<HashMap<String, KVEngine<E>>>>,
+}
+
+impl<E: StorageEngine> Clone for Database<E> {
+    fn clone(&self) -> Self {
+        Self {
+            engine: self.engine.clone(),
+            query_log: self.query_log.clone(),
+            tenants: self.tenants.clone(),
+        }
+    }
+}
+
+impl Database<AnyEngine> {
+    /// Opens a database on an engine chosen at runtime, with its data at `path`
+    ///
+    /// The memory engine ignores the path; see `storage::config` for the kinds.
+    pub fn open(path: &Path, kind: EngineKind, options: &EngineOptions) -> Result<Self> {
+        Ok(Self::new(config::open(path, kind, options)?).with_durability(options.durability))
+    }
+
+    /// Opens a database on the engine a config file describes
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self::new(config.open()?).with_durability(config.options.durability))
+    }
+}
+
+impl Database<ReadOnlySnapshotEngine> {
+    /// Opens a snapshot file written by `export_snapshot`
+    ///
+    /// Every transaction is read-only, and sees the data as exported.
+    pub fn open_snapshot(path: &Path) -> Result<Self> {
+        let mut db = Self::new(ReadOnlySnapshotEngine::open(path)?);
+        db.engine.kv = db.engine.kv.with_read_only();
+        Ok(db)
+    }
+}
+
+impl<E: StorageEngine + 'static> Database<E> {
+    /// Creates a database on top of the given storage engine
+    pub fn new(engine: E) -> Self {
+        Self {
+            engine: KVEngine::new(engine),
+            query_log: None,
+            tenants: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Logs the statements of every session the database opens
+    ///
+    /// Statements run through `execute_in` aren't logged: their transaction
+    /// is still open when they return.
+    pub fn with_query_log(mut self, log: QueryLog) -> Self {
+        self.query_log = Some(log);
+        self
+    }
+
+    /// Sets when transactions flush their writes to the engine (see
+    /// `Durability`); sessions can choose their own with `SET durability`
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.engine.kv = self.engine.kv.with_durability(durability);
+        self
+    }
+
+    /// Keeps the results of up to `capacity` queries, which sessions return
+    /// again while the tables they read don't change (see `sql::engine::resultcache`)
+    ///
+    /// Statements run through `execute_in` drop the results they make stale
+    /// when their transaction commits; raw `kv_txn` writes don't.
+    pub fn with_result_cache(mut self, capacity: usize) -> Self {
+        self.engine = self.engine.with_result_cache(capacity);
+        self
+    }
+
+    /// Has tenants share the schema of the database: the tables created
+    /// outside tenants are in every tenant, each with its own rows (see
+    /// `sql::engine::tenant`)
+    pub fn with_shared_tenant_schema(mut self) -> Self {
+        self.engine.tenancy = Tenancy::SharedSchema;
+        self
+    }
+
+    /// Opens a SQL session (each statement runs in its own transaction)
+    pub fn session(&self) -> Result<Session<KVEngine<E>>> {
+        let mut session = self.engine.session()?;
+        session.set_query_log(self.query_log.clone());
+        Ok(session)
+    }
+
+    /// Opens a SQL session of a tenant, whose tables and rows are kept apart
+    /// from those of other tenants and of `session`
+    pub fn session_for_tenant(&self, id: &str) -> Result<Session<KVEngine<E>>> {
+        if id.is_empty() {
+            return Err(Error::Internal("tenant id can't be empty".into()));
+        }
+        let engine = {
+            let mut tenants = self.tenants.lock()?;
+            match tenants.get(id) {
+                Some(engine) => engine.clone(),
+                None => {
+                    // Schema changes then cover the tenant's rows
+                    if self.engine.tenancy == Tenancy::SharedSchema {
+                        let txn = self.engine.begin()?;
+                        match txn.add_tenant(id) {
+                            Ok(()) => txn.commit()?,
+                            Err(err) => return txn.rollback().and(Err(err)),
+                        }
+                    }
+                    let engine = self.engine.for_tenant(id)?;
+                    tenants.insert(id.to_string(), engine.clone());
+                    engine
+                }
+            }
+        };
+        let mut session = engine.session()?;
+        session.set_query_log(self.query_log.clone());
+        Ok(session)
+    }
+
+    /// Begins a raw key-value transaction on the MVCC layer
+    ///
+    /// The returned transaction supports `get`/`set`/`delete`/`scan_prefix`
+    /// and must be finished with `commit` or `rollback`. SQL statements can
+    /// join it through [`Database::execute_in`].
+    ///
+    /// Raw keys share the keyspace with SQL data. SQL catalog and row keys
+    /// always start with a small tag byte (`0x00`, `0x01`, ...), so raw keys
+    /// should use a readable prefix such as `b"app:"` to avoid collisions.
+    pub fn kv_txn(&self) -> Result<MvccTransaction<E>> {
+        self.engine.kv.begin()
+    }
+
+    /// Executes a SQL statement inside an existing key-value transaction
+    ///
+    /// Unlike `Session::execute`, the transaction is neither committed nor
+    /// rolled back here; the caller decides once all work is done.
+    /// Row changes made this way are not published to subscribers, since the
+    /// commit happens outside the SQL layer. For the same reason, writes to a
+    /// table fail while one of its indexes is being built.
+    pub fn execute_in(&self, txn: &MvccTransaction<E>, sql: &str) -> Result<ResultSet> {
+        self.execute_statement_in(txn, Parser::new(sql).parse()?)
+    }
+
+    /// Executes a parsed statement inside an existing key-value transaction
+    pub(crate) fn execute_statement_in(&self, txn: &MvccTransaction<E>, stmt: ast::Statement) -> Result<ResultSet> {
+        if stmt.as_of().is_some() {
+            return Err(Error::Internal("AS OF queries run in their own transaction".into()));
+        }
+        let mut txn = KVTransaction::new(txn.clone()).with_stats(self.engine.stats.clone());
+        if let Some(results) = &self.engine.results {
+            txn = txn.with_result_cache(results.clone());
+        }
+        let stmt = Analyzer::new(&txn).analyze(stmt)?;
+        let result = Plan::build(stmt).and_then(|plan| plan.execute(&mut txn));
+        // A failed statement may have written some rows, which stay
+        txn.drop_results_on_commit()?;
+        result
+    }
+
+    /// Lists active transaction versions with their age, oldest first
+    pub fn active_transactions(&self) -> Result<Vec<(Version, Duration)>> {
+        self.engine.kv.active_transactions()
+    }
+
+    /// Aborts a stuck active transaction, discarding its writes
+    ///
+    /// The version stays invisible from then on, and its owner can no
+    /// longer commit.
+    pub fn abort_transaction(&self, version: Version) -> Result<()> {
+        self.engine.kv.abort(version)
+    }
+
+    /// Shuts the database down, waiting up to `timeout` for active
+    /// transactions to finish
+    ///
+    /// New transactions fail from the start, in every handle and session
+    /// sharing the database. Once none are active the storage engine is
+    /// flushed and marked as cleanly shut down, see `clean_shutdown`.
+    pub fn close(&self, timeout: Duration) -> Result<()> {
+        self.engine.kv.close(timeout)
+    }
+
+    /// Whether the storage was closed with `close` when it was last open
+    pub fn clean_shutdown(&self) -> bool {
+        self.engine.kv.clean_shutdown()
+    }
+
+    /// Checks that the stored data matches the catalog, e.g. after opening a
+    /// persistent engine: no keys of missing tables or indexes, and index
+    /// entries (a sample of each index) pointing at their rows
+    ///
+    /// See `KVTransaction::check`; `repair` deletes what this reports.
+    pub fn check(&self) -> Result<CheckReport> {
+        let txn = KVTransaction::new(self.kv_txn()?);
+        let result = txn.check(false);
+        txn.rollback()?;
+        result
+    }
+
+    /// Deletes the orphaned keys and dangling index entries `check` finds,
+    /// in one transaction, returning what was found
+    pub fn repair(&self) -> Result<CheckReport> {
+        let txn = KVTransaction::new(self.kv_txn()?);
+        match txn.check(true) {
+            Ok(report) => {
+                txn.commit()?;
+                Ok(report)
+            }
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Removes MVCC versions no transaction can read anymore, keeping the
+    /// latest `options.retain_versions` readable AS OF
+    ///
+    /// Waits for the transactions active at the start to finish. See
+    /// `start_gc` to run this in the background.
+    pub fn collect_garbage(&self, options: &GcOptions) -> Result<GcStats> {
+        self.engine.kv.collect_garbage(options)
+    }
+
+    /// Writes the committed data to a self-contained, immutable snapshot
+    /// file, which other processes open with `open_snapshot`; returns the
+    /// number of keys written
+    pub fn export_snapshot(&self, path: &Path) -> Result<u64> {
+        self.engine.kv.export_snapshot(path)
+    }
+
+    /// Subscribes to committed row changes of a table
+    ///
+    /// Events arrive in commit order; the receiver doubles as a blocking
+    /// iterator via `iter()`. Dropping it ends the subscription.
+    pub fn subscribe(&self, table: &str) -> Result<Receiver<ChangeEvent>> {
+        self.engine.changefeed.subscribe(table)
+    }
+
+    /// Calls `callback` after each commit changing a table, with the rows the
+    /// transaction inserted, updated and deleted there
+    ///
+    /// It runs on the committing thread, after the commit, and may use the
+    /// database; see `Changefeed::on_change` for ordering.
+    pub fn on_change(&self, table: &str, callback: impl Fn(&[ChangeEvent]) + Send + Sync + 'static) -> Result<()> {
+        self.engine.changefeed.on_change(table, Arc::new(callback))
+    }
+
+    /// Rewrites rows stored in the old bincode encoding in the compact one
+    /// (see `sql::codec`), in one transaction, returning how many were
+    ///
+    /// Both encodings are read alike, so this only reclaims space; it can run
+    /// any time, and again.
+    pub fn migrate_row_format(&self) -> Result<usize> {
+        let txn = KVTransaction::new(self.kv_txn()?);
+        match txn.migrate_rows() {
+            Ok(count) => {
+                txn.commit()?;
+                Ok(count)
+            }
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Describes every table, with its columns, options and indexes, as a
+    /// JSON document, e.g. to compare the schemas of two databases
+    ///
+    /// Defaults and partial index predicates are SQL expressions, and
+    /// materialized views have their query. Audit tables are left out, like
+    /// from a dump. `import_schema_json` creates what a document describes.
+    pub fn schema_json(&self) -> Result<String> {
+        let txn = KVTransaction::new(self.kv_txn()?);
+        let tables = txn
+            .get_table_names()
+            .and_then(|names| names.into_iter().map(|name| TableJson::new(&txn.must_get_table(name)?)).collect());
+        txn.commit()?;
+        Ok(serde_json::to_string_pretty(&SchemaJson { tables: tables? })?)
+    }
+
+    /// Creates the tables and indexes of a `schema_json` document that
+    /// don't exist yet, in one transaction, returning their names
+    ///
+    /// Existing tables are left as they are, even if defined differently.
+    /// Materialized views are created last, from the tables' rows.
+    pub fn import_schema_json(&self, json: &str) -> Result<Vec<String>> {
+        let schema: SchemaJson = serde_json::from_str(json)?;
+        let txn = KVTransaction::new(self.kv_txn()?);
+        let (mut created, mut script) = (Vec::new(), String::new());
+        let (views, tables): (Vec<_>, Vec<_>) = schema.tables.iter().partition(|table| table.view.is_some());
+        let result: Result<()> = tables.into_iter().chain(views).try_for_each(|table| {
+            let indexes = match txn.get_table(table.name.clone())? {
+                Some(existing) => table.indexes.iter().filter(|i| existing.index(&i.name).is_err()).collect(),
+                None => {
+                    script += &format!("{};\n", table.create_sql()?);
+                    created.push(table.name.clone());
+                    table.indexes.iter().collect::<Vec<_>>()
+                }
+            };
+            for index in indexes {
+                script += &format!("{};\n", index.create_sql(&table.name));
+                created.push(index.name.clone());
+            }
+            Ok(())
+        });
+        txn.rollback()?;
+        result?;
+        if !script.is_empty() {
+            self.session()?.execute_script(&script)?;
+        }
+        Ok(created)
+    }
+
+    /// Writes every table as SQL text: a `CREATE TABLE` statement, then
+    /// `INSERT` statements for its rows, all from one snapshot
+    ///
+    /// `Session::execute_script` loads a dump into another database. Audit
+    /// tables aren't dumped; loading recreates them, logging the loaded rows
+    /// as inserts. Materialized views come last, as their `CREATE
+    /// MATERIALIZED VIEW` statements, which fill them from the loaded tables.
+    pub fn dump(&self, mut writer: impl Write) -> Result<()> {
+        let txn = KVTransaction::new(self.kv_txn()?);
+        let result = (|| {
+            let mut views = Vec::new();
+            for name in txn.get_table_names()? {
+                let table = txn.must_get_table(name.clone())?;
+                if table.view.is_some() {
+                    views.push(table);
+                    continue;
+                }
+                writeln!(writer, "{}", table.create_statement().to_sql())?;
+                // Read a batch at a time, so tables larger than memory can be dumped
+                let mut scan = txn.scan_table(name.clone(), None)?.peekable();
+                while scan.peek().is_some() {
+                    let rows = scan.by_ref().take(DUMP_BATCH).collect::<Result<Vec<_>>>()?;
+                    let values = rows.iter().map(|row| row.iter().map(literal).collect()).collect();
+                    let insert = ast::Statement::Insert { table_name: name.clone(), columns: None, values };
+                    writeln!(writer, "{}", insert.to_sql())?;
+                }
+                // Indexes come after the rows, so loading builds each in one pass
+                for create in table.index_statements() {
+                    writeln!(writer, "{}", create.to_sql())?;
+                }
+            }
+            for view in views {
+                writeln!(writer, "{}", view.to_ddl()?)?;
+            }
+            writer.flush()?;
+            Ok(())
+        })();
+        txn.commit()?;
+        result
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl<E: StorageEngine + Send + Sync + 'static> Database<E> {
+    /// Starts garbage collection and compaction on a background thread, see
+    /// `Mvcc::start_gc`; the returned worker pauses, resumes and stops it
+    pub fn start_gc(&self, options: GcOptions) -> GcWorker {
+        self.engine.kv.start_gc(options)
+    }
+}
+
+/// Tables in `Database::schema_json`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SchemaJson {
+    tables: Vec<TableJson>,
+}
+
+/// A table's definition in `Database::schema_json`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TableJson {
+    name: String,
+    columns: Vec<ColumnJson>,
+    /// Hash partitions of the primary key, None if not partitioned
+    #[serde(default)]
+    partitions: Option<u64>,
+    /// WITH options (see `schema::TABLE_OPTIONS`)
+    #[serde(default)]
+    options: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    indexes: Vec<IndexJson>,
+    /// Query of a materialized view, None for tables
+    #[serde(default)]
+    view: Option<String>,
+}
+
+/// A column's definition in `Database::schema_json`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ColumnJson {
+    name: String,
+    /// SQL type name, e.g. INTEGER
+    #[serde(rename = "type")]
+    datatype: String,
+    nullable: bool,
+    #[serde(default)]
+    primary_key: bool,
+    /// Default as a SQL expression, e.g. 'x' or uuid()
+    #[serde(default)]
+    default: Option<String>,
+    #[serde(default)]
+    collation: Option<String>,
+}
+
+/// An index's definition in `Database::schema_json`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct IndexJson {
+    name: String,
+    columns: Vec<String>,
+    /// WHERE predicate of a partial index, as a SQL expression
+    #[serde(default)]
+    predicate: Option<String>,
+}
+
+impl TableJson {
+    fn new(table: &Table) -> Result<Self> {
+        let ast::Statement::CreateTable { columns, .. } = table.create_statement() else {
+            unreachable!("create_statement builds a CREATE TABLE");
+        };
+        Ok(Self {
+            name: table.name.clone(),
+            columns: columns
+                .into_iter()
+                .map(|c| ColumnJson {
+                    name: c.name,
+                    datatype: c.datatype.to_string(),
+                    nullable: c.nullable.unwrap_or(true),
+                    primary_key: c.primary_key,
+                    default: c.default.map(|expr| expr.to_string()),
+                    collation: c.collation,
+                })
+                .collect(),
+            partitions: table.partition.map(|Partition::Hash { partitions }| partitions),
+            options: table
+                .options
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        Value::Null => serde_json::Value::Null,
+                        Value::Boolean(b) => serde_json::Value::Bool(*b),
+                        Value::Integer(i) => serde_json::to_value(i)?,
+                        Value::Float(f) => serde_json::to_value(f)?,
+                        value => serde_json::Value::String(value.to_string()),
+                    };
+                    Ok((name.clone(), value))
+                })
+                .collect::<Result<_>>()?,
+            indexes: table
+                .indexes
+                .iter()
+                .filter(|index| !index.building)
+                .map(|index| IndexJson {
+                    name: index.name.clone(),
+                    columns: index.columns.clone(),
+                    predicate: index.predicate.as_ref().map(|expr| expr.to_string()),
+                })
+                .collect(),
+            view: table.view.clone(),
+        })
+    }
+
+    /// The CREATE TABLE statement of the table, or CREATE MATERIALIZED VIEW
+    /// of a view, as SQL text
+    fn create_sql(&self) -> Result<String> {
+        if let Some(query) = &self.view {
+            return Ok(format!("CREATE MATERIALIZED VIEW {} AS {}", self.name, query));
+        }
+        let columns = self.columns.iter().map(|c| {
+            let mut sql = format!("{} {} {}", c.name, c.datatype, if c.nullable { "NULL" } else { "NOT NULL" });
+            if let Some(default) = &c.default {
+                sql += &format!(" DEFAULT {}", default);
+            }
+            if c.primary_key {
+                sql += " PRIMARY KEY";
+            }
+            if let Some(collation) = &c.collation {
+                sql += &format!(" COLLATE {}", collation);
+            }
+            sql
+        });
+        let mut sql = format!("CREATE TABLE {} ({})", self.name, columns.collect::<Vec<_>>().join(", "));
+        if let Some(partitions) = self.partitions {
+            let pk = self.columns.iter().find(|c| c.primary_key).map_or("", |c| c.name.as_str());
+            sql += &format!(" PARTITION BY HASH ({}) PARTITIONS {}", pk, partitions);
+        }
+        if !self.options.is_empty() {
+            let options = self.options.iter().map(|(name, value)| {
+                let value = match value {
+                    serde_json::Value::Null => ast::Consts::Null,
+                    serde_json::Value::Bool(b) => ast::Consts::Boolean(*b),
+                    serde_json::Value::Number(n) => match n.as_i64() {
+                        Some(i) => ast::Consts::Integer(i.into()),
+                        None => ast::Consts::Float(n.as_f64().unwrap_or_default()),
+                    },
+                    serde_json::Value::String(s) => ast::Consts::String(s.clone()),
+                    value => {
+                        return Err(Error::Internal(format!("invalid value {} for table option {}", value, name)));
+                    }
+                };
+                Ok(format!("{} = {}", name, value))
+            });
+            sql += &format!(" WITH ({})", options.collect::<Result<Vec<_>>>()?.join(", "));
+        }
+        Ok(sql)
+    }
+}
+
+impl IndexJson {
+    /// The CREATE INDEX statement of the index on a table, as SQL text
+    fn create_sql(&self, table: &str) -> String {
+        let mut sql = format!("CREATE INDEX {} ON {} ({})", self.name, table, self.columns.join(", "));
+        if let Some(predicate) = &self.predicate {
+            sql += &format!(" WHERE {}", predicate);
+        }
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::Database;
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Session, Transaction, changefeed::ChangeEvent, kv::KVTransaction},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::{gc::GcOptions, memory::MemoryEngine},
+    };
+
+    #[test]
+    fn test_kv_txn_mixed_with_sql() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        db.session()?.execute("create table t1 (a int primary key, b text);")?;
+
+        let txn = db.kv_txn()?;
+        txn.set(b"app:counter".to_vec(), b"1".to_vec())?;
+        db.execute_in(&txn, "insert into t1 values (1, 'a');")?;
+        txn.commit()?;
+
+        let txn = db.kv_txn()?;
+        txn.set(b"app:counter".to_vec(), b"2".to_vec())?;
+        db.execute_in(&txn, "insert into t1 values (2, 'b');")?;
+        txn.rollback()?;
+
+        let txn = db.kv_txn()?;
+        assert_eq!(txn.get(b"app:counter".to_vec())?, Some(b"1".to_vec()));
+        assert_eq!(txn.scan_prefix(b"app:".to_vec())?.len(), 1);
+        txn.commit()?;
+
+        match db.session()?.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows, vec![vec![Value::Integer(1), Value::String("a".into())]]);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_cache_execute_in() -> Result<()> {
+        let db = Database::new(MemoryEngine::new()).with_result_cache(10);
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("insert into t1 values (1);")?;
+        let count = |s: &mut Session<_>| match s.execute("select count(*) from t1;") {
+            Ok(ResultSet::Scan { rows, .. }) => rows[0][0].clone(),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(count(&mut s), Value::Integer(1));
+        assert_eq!(count(&mut s), Value::Integer(1));
+
+        // The raw commit drops the result cached before it, and one cached
+        // while it was open
+        let txn = db.kv_txn()?;
+        db.execute_in(&txn, "insert into t1 values (2);")?;
+        assert_eq!(count(&mut s), Value::Integer(1));
+        txn.commit()?;
+        assert_eq!(count(&mut s), Value::Integer(2));
+
+        // A rolled back write leaves it
+        let txn = db.kv_txn()?;
+        db.execute_in(&txn, "insert into t1 values (3);")?;
+        txn.rollback()?;
+        assert_eq!(count(&mut s), Value::Integer(2));
+        assert_eq!(db.engine.results.as_ref().map(|results| results.len()).transpose()?, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_in_index_build() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20);")?;
+
+        // An earlier transaction holds the build up while the others write
+        let earlier = db.kv_txn()?;
+        let builder = db.clone();
+        let build = std::thread::spawn(move || builder.session()?.execute("create index idx_b on t1 (b);"));
+        let building = || -> Result<bool> {
+            let txn = KVTransaction::new(db.kv_txn()?);
+            let building = txn.must_get_table("t1".into())?.indexes.iter().any(|index| index.building);
+            txn.rollback()?;
+            Ok(building)
+        };
+        while !building()? {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // The build can't see raw commits, so writes outside sessions fail
+        let txn = db.kv_txn()?;
+        assert!(db.execute_in(&txn, "insert into t1 values (3, 30);").is_err());
+        assert!(db.execute_in(&txn, "update t1 set b = 11 where a = 1;").is_err());
+        assert!(db.execute_in(&txn, "delete from t1 where a = 2;").is_err());
+        assert!(db.execute_in(&txn, "select a from t1;").is_ok());
+        txn.rollback()?;
+        s.execute("insert into t1 values (4, 40);")?;
+
+        earlier.rollback()?;
+        assert!(matches!(build.join().unwrap()?, ResultSet::Command { .. }));
+        let txn = db.kv_txn()?;
+        db.execute_in(&txn, "update t1 set b = 21 where a = 2;")?;
+        txn.commit()?;
+        for (b, a) in [(21, 2), (40, 4)] {
+            match s.execute(&format!("select a from t1 where b = {};", b))? {
+                ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(a)]]),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        assert!(db.check()?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscribe() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (a int primary key);")?;
+
+        let feed = db.subscribe("t1")?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b');")?;
+        s.execute("insert into t2 values (1);")?;
+        s.execute("update t1 set b = 'c' where a = 1;")?;
+        s.execute("update t1 set a = 3 where a = 2;")?;
+        s.execute("delete from t1 where a = 1;")?;
+        // Failed statements are rolled back and publish nothing
+        assert!(s.execute("insert into t1 values (3, 'd');").is_err());
+
+        let row = |a: i128, b: &str| vec![Value::Integer(a), Value::String(b.into())];
+        let events: Vec<ChangeEvent> = feed.try_iter().collect();
+        assert!(events.windows(2).all(|w| w[0].version <= w[1].version));
+        assert!(events.iter().all(|e| e.table == "t1"));
+        assert_eq!(
+            events
+                .into_iter()
+                .map(|e| (e.pk, e.old, e.new))
+                .collect::<Vec<_>>(),
+            vec![
+                (Value::Integer(1), None, Some(row(1, "a"))),
+                (Value::Integer(2), None, Some(row(2, "b"))),
+                (Value::Integer(1), Some(row(1, "a")), Some(row(1, "c"))),
+                (Value::Integer(2), Some(row(2, "b")), None),
+                (Value::Integer(3), None, Some(row(3, "b"))),
+                (Value::Integer(1), Some(row(1, "c")), None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_change() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table log (version int primary key, changes int);")?;
+
+        // Hooks can write to the database themselves
+        let (logger, seen) = (db.clone(), Arc::new(Mutex::new(Vec::new())));
+        let hook_seen = seen.clone();
+        db.on_change("t1", move |events| {
+            hook_seen.lock().unwrap().push(events.iter().map(|e| (e.pk.clone(), e.new.is_some())).collect::<Vec<_>>());
+            let sql = format!("insert into log values ({}, {});", events[0].version, events.len());
+            logger.session().unwrap().execute(&sql).unwrap();
+        })?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b');")?;
+        s.execute("delete from t1 where a = 1;")?;
+        s.execute("update t1 set b = 'x' where a = 9;")?;
+        assert!(s.execute("insert into t1 values (2, 'c');").is_err());
+
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(
+            seen,
+            vec![vec![(Value::Integer(1), true), (Value::Integer(2), true)], vec![(Value::Integer(1), false)]]
+        );
+        let logged = s.execute("select changes from log;")?.rows()?.map(|row| row.get::<i64>(0)).collect::<Vec<_>>();
+        assert_eq!(logged, vec![2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_abort_transaction() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let stuck = db.kv_txn()?;
+        stuck.set(b"app:a".to_vec(), b"1".to_vec())?;
+        let other = db.kv_txn()?;
+        other.commit()?;
+
+        let active = db.active_transactions()?;
+        assert_eq!(active.iter().map(|(v, _)| *v).collect::<Vec<_>>(), vec![stuck.version()]);
+        db.abort_transaction(stuck.version())?;
+        assert!(db.active_transactions()?.is_empty());
+        assert!(db.abort_transaction(stuck.version()).is_err());
+
+        // Writes made after the abort stay invisible, and the owner can't commit
+        stuck.set(b"app:b".to_vec(), b"2".to_vec())?;
+        let txn = db.kv_txn()?;
+        assert_eq!(txn.get(b"app:a".to_vec())?, None);
+        assert_eq!(txn.get(b"app:b".to_vec())?, None);
+        assert!(stuck.commit().is_err());
+        assert!(txn.scan_prefix(b"app:".to_vec())?.is_empty());
+        txn.commit()?;
+
+        let sql = format!("select status from system.transactions where version = {};", stuck.version());
+        match db.session()?.execute(&sql)? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::String("aborted".into())]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_and_load() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        db.session()?.execute_script(
+            "create table t1 (a int primary key, b text not null default 'it''s' collate nocase, c float, \
+                 d boolean, e point, f uuid default uuid()) partition by hash (a) partitions 4;
+             create table t2 (a text primary key, b bigint default -3) with (storage = 'columnar', audit = true);
+             create table t3 (a int primary key);
+             insert into t1 values (-1, 'x''y', -0.5, true, point(1.5, -2), '67e55044-10b1-426f-9247-bb680e5fe0c8');
+             insert into t1 (a, b, c) values (2, 'z', 0.0 / 0.0), (3, 'w', 1e999);
+             insert into t1 (a) values (4);
+             create materialized view v1 as select b, count(*) as n from t1 group by b;",
+        )?;
+        let rows = (0..250).map(|i| vec![Value::String(format!("k{}", i).into()), Value::Integer(i)]).collect();
+        db.session()?.insert_rows("t2", Vec::new(), rows)?;
+
+        let mut dump = Vec::new();
+        db.dump(&mut dump)?;
+        let dump = String::from_utf8(dump).unwrap();
+        assert_eq!(dump.lines().filter(|l| l.starts_with("INSERT INTO t2")).count(), 3);
+        // The view comes after the tables it reads, without its rows
+        assert_eq!(
+            dump.lines().last(),
+            Some("CREATE MATERIALIZED VIEW v1 AS SELECT b, count(*) AS n FROM t1 GROUP BY b;")
+        );
+
+        let loaded = Database::new(MemoryEngine::new());
+        let results = loaded.session()?.execute_script(&dump)?;
+        assert_eq!(results.len(), 8);
+        let mut redump = Vec::new();
+        loaded.dump(&mut redump)?;
+        assert_eq!(String::from_utf8(redump).unwrap(), dump);
+        for sql in ["select * from t1;", "select * from t2;", "select * from v1;"] {
+            let (ResultSet::Scan { rows: a, .. }, ResultSet::Scan { rows: b, .. }) =
+                (db.session()?.execute(sql)?, loaded.session()?.execute(sql)?)
+            else {
+                unreachable!()
+            };
+            // NaN isn't equal to itself, so compare how the rows print
+            assert_eq!(format!("{:?}", a), format!("{:?}", b));
+        }
+        // The loaded rows were audited as inserts
+        match loaded.session()?.execute("select operation from _audit_t2;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 250),
+            _ => unreachable!(),
+        }
+
+        // A failing script leaves nothing behind
+        let mut s = loaded.session()?;
+        assert!(s.execute_script("insert into t3 values (1); insert into t3 values (1);").is_err());
+        assert!(s.execute_script("insert into t3 values (1); select from;").is_err());
+        match s.execute("select * from t3;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.is_empty()),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_json() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        db.session()?.execute_script(
+            "create table t1 (a int primary key, b text not null default 'it''s' collate nocase, c float, \
+                 f uuid default uuid()) partition by hash (a) partitions 4;
+             create table t2 (a text primary key, b bigint default -3) with (storage = 'columnar', audit = true);
+             create index t2_b on t2 (b) where b > 0;
+             create materialized view t0 as select b, count(*) as n from t2 group by b;",
+        )?;
+        let json = db.schema_json()?;
+        let schema: serde_json::Value = serde_json::from_str(&json)?;
+        assert_eq!(schema["tables"][0]["view"], "SELECT b, count(*) AS n FROM t2 GROUP BY b");
+        assert_eq!(
+            schema["tables"][1]["columns"][1],
+            serde_json::json!({
+                "name": "b", "type": "STRING", "nullable": false, "primary_key": false,
+                "default": "'it''s'", "collation": "nocase",
+            })
+        );
+        assert_eq!(schema["tables"][1]["partitions"], 4);
+        assert_eq!(schema["tables"][2]["options"], serde_json::json!({ "audit": true, "storage": "columnar" }));
+        assert_eq!(
+            schema["tables"][2]["indexes"],
+            serde_json::json!([{ "name": "t2_b", "columns": ["b"], "predicate": "b > 0" }])
+        );
+
+        // Importing creates what's missing, here all but t1, views last
+        let other = Database::new(MemoryEngine::new());
+        other.session()?.execute("create table t1 (a int primary key);")?;
+        assert_eq!(other.import_schema_json(&json)?, vec!["t2", "t2_b", "t0"]);
+        assert_eq!(other.import_schema_json(&json)?, Vec::<String>::new());
+        other.session()?.execute("drop table t1;")?;
+        assert_eq!(other.import_schema_json(&json)?, vec!["t1"]);
+        assert_eq!(other.schema_json()?, json);
+        other.session()?.execute("insert into t2 (a) values ('x');")?;
+        match other.session()?.execute("select b from t2;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(-3)]]),
+            _ => unreachable!(),
+        }
+
+        let invalid = r#"{"tables": [{"name": "t3", "columns": [{"name": "a", "type": "INTEGER", "nullable": false, "primary_key": true}], "options": {"ttl": 60}}]}"#;
+        assert!(other.import_schema_json(invalid).is_err());
+        assert!(other.import_schema_json("{").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_close() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("begin;")?;
+        s.execute("insert into t1 values (1);")?;
+
+        // Close waits for the open transaction, which can still commit
+        let closer = db.clone();
+        let handle = std::thread::spawn(move || closer.close(Duration::from_secs(5)));
+        while db.session()?.execute("select * from t1;").is_ok() {
+            std::thread::yield_now();
+        }
+        s.execute("commit;")?;
+        handle.join().unwrap()?;
+        assert!(db.kv_txn().is_err());
+        assert!(!db.clean_shutdown());
+        Ok(())
+    }
+    #[test]
+    fn test_collect_garbage() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("insert into t1 values (1, 0);")?;
+        for i in 1..=5 {
+            s.execute(&format!("update t1 set b = {} where a = 1;", i))?;
+        }
+        let stats = db.collect_garbage(&GcOptions { retain_versions: 0, ..Default::default() })?;
+        assert_eq!(stats.versions_removed, 5);
+        let result = s.execute("select b from t1;")?;
+        assert_eq!(result.rows()?.next().unwrap().get::<i64>("b"), 5);
+        Ok(())
+    }
+    #[test]
+    fn test_snapshot() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create index t1_b on t1 (b);")?;
+        s.execute("insert into t1 values (1, 'x'), (2, 'y'), (3, 'x');")?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("t1.snapshot");
+        db.export_snapshot(&path)?;
+        s.execute("delete from t1 where a = 1;")?;
+
+        let snapshot = Database::open_snapshot(&path)?;
+        let mut s = snapshot.session()?;
+        let result = s.execute("select a from t1 where b = 'x' order by a;")?;
+        assert_eq!(result.rows()?.map(|row| row.get::<i64>("a")).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(s.execute("insert into t1 values (4, 'z');").is_err());
+        assert!(snapshot.kv_txn()?.set(b"app:a".to_vec(), vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tenants() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        db.session()?.execute("create table t1 (a int primary key, b text);")?;
+        db.session()?.execute("insert into t1 values (1, 'x');")?;
+        let rows = |s: &mut Session<_>, sql: &str| -> Result<Vec<Vec<Value>>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows),
+                result => panic!("unexpected result {:?}", result),
+            }
+        };
+
+        // Each tenant has its own tables, with the same keys free of conflicts
+        let (mut a, mut b) = (db.session_for_tenant("a")?, db.session_for_tenant("b")?);
+        assert!(a.execute("select * from t1;").is_err());
+        a.execute("create table t1 (a int primary key, b text);")?;
+        b.execute("create table t1 (a int primary key);")?;
+        a.execute("begin;")?;
+        a.execute("insert into t1 values (1, 'a');")?;
+        b.execute("insert into t1 values (1);")?;
+        a.execute("commit;")?;
+        a.execute("create index t1_b on t1 (b);")?;
+        assert_eq!(rows(&mut a, "select a from t1 where b = 'a';")?, vec![vec![Value::Integer(1)]]);
+        assert_eq!(rows(&mut b, "select * from t1;")?, vec![vec![Value::Integer(1)]]);
+        let mut s = db.session()?;
+        assert_eq!(rows(&mut s, "select b from t1;")?, vec![vec![Value::String("x".into())]]);
+
+        // Sessions of a tenant share its data
+        b.execute("drop table t1;")?;
+        assert!(db.session_for_tenant("b")?.execute("select * from t1;").is_err());
+        assert_eq!(rows(&mut db.session_for_tenant("a")?, "select b from t1;")?.len(), 1);
+        assert!(db.session_for_tenant("").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_tenant_schema() -> Result<()> {
+        let db = Database::new(MemoryEngine::new()).with_shared_tenant_schema();
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        let (mut a, mut b) = (db.session_for_tenant("a")?, db.session_for_tenant("b")?);
+        a.execute("insert into t1 values (1, 'a'), (2, 'x');")?;
+        b.execute("insert into t1 values (1, 'b');")?;
+        let count = |s: &mut Session<_>| -> Result<usize> { Ok(s.execute("select * from t1;")?.rows()?.count()) };
+        assert_eq!((count(&mut s)?, count(&mut a)?, count(&mut b)?), (0, 2, 1));
+
+        // Tenants can't change the schema, and changes outside cover their rows
+        assert!(a.execute("create table t2 (a int primary key);").is_err());
+        assert!(a.execute("create index t1_b on t1 (b);").is_err());
+        s.execute("create index t1_b on t1 (b);")?;
+        let result = a.execute("select a from t1 where b = 'x';")?;
+        assert_eq!(result.rows()?.map(|row| row.get::<i64>("a")).collect::<Vec<_>>(), vec![2]);
+        assert!(s.execute("create materialized view v1 as select a, b from t1;").is_err());
+
+        s.execute("drop table t1;")?;
+        assert!(b.execute("select * from t1;").is_err());
+        s.execute("create table t1 (a int primary key, b text);")?;
+        assert_eq!(count(&mut b)?, 0);
+        Ok(())
+    }
+}
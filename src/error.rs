@@ -1,10 +1,14 @@
-use std::{array::TryFromSliceError, fmt::Display, string::FromUtf8Error, sync::PoisonError};
+use alloc::string::{FromUtf8Error, String, ToString};
+use core::{array::TryFromSliceError, fmt::Display};
+#[cfg(feature = "std")]
+use std::sync::PoisonError;
 
+#[cfg(feature = "std")]
 use bincode::ErrorKind;
 use serde::{de, ser};
 
 /// Custom Result type for RustDB operations
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// Error types for RustDB
 #[derive(Debug, Clone, PartialEq)]
@@ -13,28 +17,43 @@ pub enum Error {
     Parse(String),
     /// Internal error (storage, serialization, etc.)
     Internal(String),
-    /// MVCC write conflict
-    WriteConflict,
+    /// MVCC write conflict: a concurrent transaction wrote the key first
+    WriteConflict {
+        /// Primary key of the conflicting row, or the escaped storage key
+        /// outside SQL tables
+        key: String,
+        /// Table of the conflicting row
+        table: Option<String>,
+        /// Version of the transaction whose write blocks this one
+        version: u64,
+    },
+    /// A query buffered more row data than its memory budget (in bytes) allows
+    OutOfMemoryBudget(usize),
+    /// A statement of the session's explicit transaction failed, so it only
+    /// takes ROLLBACK (or COMMIT, which rolls back) until it ends
+    TransactionAborted,
 }
 
-impl From<std::num::ParseIntError> for Error {
-    fn from(value: std::num::ParseIntError) -> Self {
+impl From<core::num::ParseIntError> for Error {
+    fn from(value: core::num::ParseIntError) -> Self {
         Error::Parse(value.to_string())
     }
 }
 
-impl From<std::num::ParseFloatError> for Error {
-    fn from(value: std::num::ParseFloatError) -> Self {
+impl From<core::num::ParseFloatError> for Error {
+    fn from(value: core::num::ParseFloatError) -> Self {
         Error::Parse(value.to_string())
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> From<PoisonError<T>> for Error {
     fn from(value: PoisonError<T>) -> Self {
         Error::Internal(value.to_string())
     }
 }
 
+#[cfg(feature = "std")]
 impl From<Box<ErrorKind>> for Error {
     fn from(value: Box<ErrorKind>) -> Self {
         Error::Internal(value.to_string())
@@ -53,7 +72,21 @@ impl From<FromUtf8Error> for Error {
     }
 }
 
-impl std::error::Error for Error {}
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Internal(value.to_string())
+    }
+}
+
+impl core::error::Error for Error {}
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
@@ -68,11 +101,24 @@ impl de::Error for Error {
 }
 
 impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::Parse(err) => write!(f, "parse error {}", err),
             Error::Internal(err) => write!(f, "internal error {}", err),
-            Error::WriteConflict => write!(f, "write conflict, try transaction"),
+            Error::WriteConflict { key, table: Some(table), version } => write!(
+                f,
+                "write conflict on row {} of table {} with transaction {}, retry the transaction",
+                key, table, version
+            ),
+            Error::WriteConflict { key, table: None, version } => {
+                write!(f, "write conflict on key {} with transaction {}, retry the transaction", key, version)
+            }
+            Error::OutOfMemoryBudget(limit) => {
+                write!(f, "query exceeded its memory budget of {} bytes", limit)
+            }
+            Error::TransactionAborted => {
+                write!(f, "current transaction is aborted, commands ignored until end of transaction block")
+            }
         }
     }
 }
\ No newline at end of file
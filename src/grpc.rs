@@ -0,0 +1,291 @@
+//! gRPC service for remote sessions (feature `grpc`)
+//!
+//! Serves a [`Database`] over the `RustDb` service of `proto/rustdb.proto`,
+//! so clients in any language can run SQL and drive multi-statement
+//! transactions. A statement without a transaction id runs in its own
+//! transaction, like `Session::execute`. `Begin` opens a transaction that
+//! `Execute` calls join (see `Database::execute_in`) until `Commit` or
+//! `Rollback`; a failed statement leaves it open for the client to decide.
+//!
+//! Query results stream as a `Columns` header with the column metadata,
+//! followed by `Rows` messages of up to `BATCH_SIZE` rows.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex};
+
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    db::Database,
+    error::{Error, Result},
+    sql::{
+        executor::{ColumnMetadata, ResultSet, batch::BATCH_SIZE},
+        types::Value,
+    },
+    storage::{engine::Engine as StorageEngine, mvcc::MvccTransaction},
+};
+
+/// Messages and stubs generated from `proto/rustdb.proto`
+pub mod proto {
+    tonic::include_proto!("rustdb");
+}
+
+use proto::{
+    BeginRequest, BeginResponse, ExecuteRequest, ExecuteResponse, TransactionRequest, TransactionResponse,
+    execute_response,
+    rust_db_server::{RustDb, RustDbServer},
+    value,
+};
+
+/// The `RustDb` service over a database
+pub struct Service<E: StorageEngine> {
+    db: Database<E>,
+    /// Transactions opened by `Begin`, by version
+    transactions: Mutex<HashMap<u64, MvccTransaction<E>>>,
+}
+
+impl<E: StorageEngine + Send + Sync + 'static> Service<E> {
+    pub fn new(db: Database<E>) -> Self {
+        Self { db, transactions: Mutex::new(HashMap::new()) }
+    }
+
+    /// Serves the service on a TCP address until the future is dropped
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        self.serve_listener(TcpListener::bind(addr).await?).await
+    }
+
+    /// Serves the service on a bound listener, e.g., one on port 0
+    pub async fn serve_listener(self, listener: TcpListener) -> Result<()> {
+        tonic::transport::Server::builder()
+            .add_service(RustDbServer::new(self))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    /// Looks up an open transaction
+    fn transaction(&self, id: u64) -> std::result::Result<MvccTransaction<E>, Status> {
+        match self.transactions.lock().map_err(|e| status(e.into()))?.get(&id) {
+            Some(txn) => Ok(txn.clone()),
+            None => Err(Status::not_found(format!("transaction {} is not open", id))),
+        }
+    }
+
+    /// Removes an open transaction to finish it
+    fn take_transaction(&self, id: u64) -> std::result::Result<MvccTransaction<E>, Status> {
+        match self.transactions.lock().map_err(|e| status(e.into()))?.remove(&id) {
+            Some(txn) => Ok(txn),
+            None => Err(Status::not_found(format!("transaction {} is not open", id))),
+        }
+    }
+}
+
+type ExecuteStream = tokio_stream::Iter<std::vec::IntoIter<std::result::Result<ExecuteResponse, Status>>>;
+
+#[tonic::async_trait]
+impl<E: StorageEngine + Send + Sync + 'static> RustDb for Service<E> {
+    async fn begin(&self, _: Request<BeginRequest>) -> std::result::Result<Response<BeginResponse>, Status> {
+        let txn = self.db.kv_txn().map_err(status)?;
+        let transaction_id = txn.version();
+        self.transactions.lock().map_err(|e| status(e.into()))?.insert(transaction_id, txn);
+        Ok(Response::new(BeginResponse { transaction_id }))
+    }
+
+    type ExecuteStream = ExecuteStream;
+
+    async fn execute(
+        &self,
+        request: Request<ExecuteRequest>,
+    ) -> std::result::Result<Response<Self::ExecuteStream>, Status> {
+        let request = request.into_inner();
+        let result = match request.transaction_id {
+            Some(id) => self.db.execute_in(&self.transaction(id)?, &request.sql),
+            None => self.db.session().and_then(|mut s| s.execute(&request.sql)),
+        };
+        let responses = responses(result.map_err(status)?).into_iter().map(Ok).collect::<Vec<_>>();
+        Ok(Response::new(tokio_stream::iter(responses)))
+    }
+
+    async fn commit(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> std::result::Result<Response<TransactionResponse>, Status> {
+        self.take_transaction(request.into_inner().transaction_id)?.commit().map_err(status)?;
+        Ok(Response::new(TransactionResponse {}))
+    }
+
+    async fn rollback(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> std::result::Result<Response<TransactionResponse>, Status> {
+        self.take_transaction(request.into_inner().transaction_id)?.rollback().map_err(status)?;
+        Ok(Response::new(TransactionResponse {}))
+    }
+}
+
+/// Maps an error to a gRPC status
+fn status(err: Error) -> Status {
+    match err {
+        Error::Parse(_) => Status::invalid_argument(err.to_string()),
+        Error::WriteConflict { .. } => Status::aborted(err.to_string()),
+        Error::OutOfMemoryBudget(_) => Status::resource_exhausted(err.to_string()),
+        Error::TransactionAborted => Status::failed_precondition(err.to_string()),
+        Error::Internal(_) => Status::internal(err.to_string()),
+    }
+}
+
+/// Splits a result into the messages of an `Execute` stream
+fn responses(result: ResultSet) -> Vec<ExecuteResponse> {
+    let message = |response| ExecuteResponse { response: Some(response) };
+    let completion = |tag: &str, count: usize| {
+        message(execute_response::Response::Completion(proto::Completion { tag: tag.into(), count: count as u64 }))
+    };
+    match result {
+        ResultSet::Scan { columns, rows, metadata } => {
+            let columns = columns
+                .into_iter()
+                .zip(metadata)
+                .map(|(name, ColumnMetadata { datatype, nullable, table })| proto::Column {
+                    name,
+                    datatype: datatype.map(|dt| dt.to_string()),
+                    nullable,
+                    table,
+                })
+                .collect();
+            let mut responses = vec![message(execute_response::Response::Columns(proto::Columns { columns }))];
+            responses.extend(rows.chunks(BATCH_SIZE).map(|chunk| {
+                let rows = chunk
+                    .iter()
+                    .map(|row| proto::Row { values: row.iter().map(to_proto).collect() })
+                    .collect();
+                message(execute_response::Response::Rows(proto::Rows { rows }))
+            }));
+            responses
+        }
+        ResultSet::CreateTable { .. } => vec![completion("CREATE TABLE", 0)],
+        ResultSet::Insert { count } => vec![completion("INSERT", count)],
+        ResultSet::Update { count } => vec![completion("UPDATE", count)],
+        ResultSet::Delete { count } => vec![completion("DELETE", count)],
+        ResultSet::Copy { count } => vec![completion("COPY", count)],
+        ResultSet::Command { tag } => vec![completion(&tag, 0)],
+    }
+}
+
+/// Converts a value to its wire form
+pub fn to_proto(v: &Value) -> proto::Value {
+    let value = match v {
+        Value::Null => None,
+        Value::Boolean(b) => Some(value::Value::Boolean(*b)),
+        Value::Integer(i) => Some(match i64::try_from(*i) {
+            Ok(i) => value::Value::Integer(i),
+            Err(_) => value::Value::BigInteger(i.to_string()),
+        }),
+        Value::Float(f) => Some(value::Value::Float(*f)),
+        Value::String(s) => Some(value::Value::String(s.to_string())),
+        Value::Point(x, y) => Some(value::Value::Point(proto::Point { x: *x, y: *y })),
+        Value::Uuid(u) => Some(value::Value::Uuid(u.to_vec())),
+    };
+    proto::Value { value }
+}
+
+/// Converts a value from its wire form
+pub fn from_proto(v: proto::Value) -> Result<Value> {
+    Ok(match v.value {
+        None => Value::Null,
+        Some(value::Value::Boolean(b)) => Value::Boolean(b),
+        Some(value::Value::Integer(i)) => Value::Integer(i.into()),
+        Some(value::Value::BigInteger(s)) => Value::Integer(s.parse()?),
+        Some(value::Value::Float(f)) => Value::Float(f),
+        Some(value::Value::String(s)) => Value::String(s.into()),
+        Some(value::Value::Point(p)) => Value::Point(p.x, p.y),
+        Some(value::Value::Uuid(u)) => Value::Uuid(u.as_slice().try_into()?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tonic::{Code, transport::Channel};
+
+    use super::{
+        Service, from_proto,
+        proto::{
+            BeginRequest, ExecuteRequest, ExecuteResponse, TransactionRequest, execute_response::Response,
+            rust_db_client::RustDbClient,
+        },
+    };
+    use crate::{db::Database, error::Result, sql::types::Value, storage::memory::MemoryEngine};
+
+    async fn execute(
+        client: &mut RustDbClient<Channel>,
+        transaction_id: Option<u64>,
+        sql: &str,
+    ) -> std::result::Result<Vec<ExecuteResponse>, tonic::Status> {
+        let mut stream = client.execute(ExecuteRequest { transaction_id, sql: sql.into() }).await?.into_inner();
+        let mut responses = Vec::new();
+        while let Some(response) = stream.message().await? {
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+
+    /// Values of the rows in a query's responses
+    fn rows(responses: Vec<ExecuteResponse>) -> Result<Vec<Vec<Value>>> {
+        let mut rows = Vec::new();
+        for response in responses {
+            if let Some(Response::Rows(batch)) = response.response {
+                for row in batch.rows {
+                    rows.push(row.values.into_iter().map(from_proto).collect::<Result<_>>()?);
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    #[tokio::test]
+    async fn test_service() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(Service::new(Database::new(MemoryEngine::new())).serve_listener(listener));
+        let mut client = RustDbClient::connect(format!("http://{}", addr)).await.unwrap();
+
+        let created = execute(&mut client, None, "create table t1 (a bigint primary key, b text);").await.unwrap();
+        assert!(matches!(&created[0].response, Some(Response::Completion(c)) if c.tag == "CREATE TABLE"));
+
+        // Statements in a transaction are only visible to it until commit
+        let id = client.begin(BeginRequest {}).await.unwrap().into_inner().transaction_id;
+        execute(&mut client, Some(id), "insert into t1 values (1, 'a'), (170141183460469231731687303715884105727, null);")
+            .await
+            .unwrap();
+        assert!(rows(execute(&mut client, None, "select * from t1;").await.unwrap())?.is_empty());
+        let responses = execute(&mut client, Some(id), "select * from t1;").await.unwrap();
+        match &responses[0].response {
+            Some(Response::Columns(header)) => {
+                assert_eq!(header.columns[0].name, "a");
+                assert_eq!(header.columns[0].datatype.as_deref(), Some("BIGINT"));
+                assert!(!header.columns[0].nullable);
+                assert_eq!(header.columns[1].table.as_deref(), Some("t1"));
+            }
+            r => panic!("unexpected response {:?}", r),
+        }
+        let expected = vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(i128::MAX), Value::Null],
+        ];
+        assert_eq!(rows(responses)?, expected);
+        client.commit(TransactionRequest { transaction_id: id }).await.unwrap();
+        assert_eq!(rows(execute(&mut client, None, "select * from t1;").await.unwrap())?, expected);
+
+        // Rolled back work is discarded, and finished transactions are gone
+        let id = client.begin(BeginRequest {}).await.unwrap().into_inner().transaction_id;
+        execute(&mut client, Some(id), "delete from t1;").await.unwrap();
+        client.rollback(TransactionRequest { transaction_id: id }).await.unwrap();
+        assert_eq!(rows(execute(&mut client, None, "select * from t1;").await.unwrap())?.len(), 2);
+        let err = execute(&mut client, Some(id), "select * from t1;").await.unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+        let err = execute(&mut client, None, "select from").await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        Ok(())
+    }
+}
@@ -5,7 +5,35 @@
 //! - Query planning and execution
 //! - MVCC-based transaction support
 //! - Pluggable storage engines
+//! - A [`db::Database`] handle mixing SQL and raw key-value access
+//! - Structs mapped to table rows, with `#[derive(Record)]` (feature `derive`)
+//! - Versioned schema migrations from SQL files ([`migrations`])
+//! - A gRPC service for remote sessions (feature `grpc`)
+//! - Browser bindings for an in-browser playground (feature `wasm`)
+//! - A random SQL generator for fuzzing (feature `fuzzing`, targets in `fuzz/`)
+//! - `tracing` spans for statements, planning, executors and MVCC operations
+//!   (feature `tracing`), to diagnose slow queries with any subscriber
+//!
+//! Without the default `std` feature the crate is `no_std` (alloc only) and
+//! builds just the SQL front-end, `sql::parser` and `sql::types`, e.g. to
+//! validate queries on embedded targets.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+// Lets the code of #[derive(Record)] name this crate from its own tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as rustdb;
 
+#[cfg(feature = "std")]
+pub mod db;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "std")]
+pub mod migrations;
 pub mod sql;
+#[cfg(feature = "std")]
 pub mod storage;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,328 @@
+//! Schema migrations - versioned SQL scripts applied in order
+//!
+//! A `Migrator` holds numbered migrations, typically read from a directory
+//! of files named `<version>_<name>.up.sql`, with an optional
+//! `<version>_<name>.down.sql` to revert it (`<version>_<name>.sql` alone is
+//! an up script). Applied versions are recorded in the `schema_migrations`
+//! table, created on first use:
+//!
+//! ```text
+//! schema_migrations (version bigint primary key, name string, applied_at bigint)
+//! ```
+//!
+//! Each migration runs in its own transaction together with its record, so a
+//! failing script leaves neither its changes nor its version behind, and the
+//! migrations before it stay applied. A dry run applies the same scripts in
+//! one transaction that is rolled back, checking them without changing
+//! anything.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{
+    db::Database,
+    error::{Error, Result},
+    sql::{
+        engine::{Transaction, kv::KVTransaction},
+        executor::{ResultSet, insert_rows},
+        parser::Parser,
+        types::Value,
+    },
+    storage::{engine::Engine as StorageEngine, mvcc::{MvccTransaction, now_millis}},
+};
+
+/// Table recording applied migrations
+pub const TABLE: &str = "schema_migrations";
+
+/// A numbered schema change
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub version: u64,
+    pub name: String,
+    /// Script applying the change
+    pub up: String,
+    /// Script reverting the change, if it can be
+    pub down: Option<String>,
+}
+
+/// Applies and reverts a set of migrations
+pub struct Migrator {
+    /// By version
+    migrations: BTreeMap<u64, Migration>,
+    dry_run: bool,
+}
+
+impl Migrator {
+    /// Creates a migrator, failing on duplicate versions
+    pub fn new(migrations: Vec<Migration>) -> Result<Self> {
+        let mut by_version = BTreeMap::new();
+        for migration in migrations {
+            let version = migration.version;
+            if by_version.insert(version, migration).is_some() {
+                return Err(Error::Internal(format!("duplicate migration version {}", version)));
+            }
+        }
+        Ok(Self { migrations: by_version, dry_run: false })
+    }
+
+    /// Reads the migrations of a directory, ignoring files not ending in `.sql`
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self> {
+        let mut migrations: BTreeMap<u64, Migration> = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let Some(stem) = file_name.strip_suffix(".sql") else {
+                continue;
+            };
+            let (stem, down) = match (stem.strip_suffix(".up"), stem.strip_suffix(".down")) {
+                (Some(stem), _) => (stem, false),
+                (_, Some(stem)) => (stem, true),
+                _ => (stem, false),
+            };
+            let (version, name) = stem.split_once('_').unwrap_or((stem, ""));
+            let version = version.parse::<u64>().map_err(|_| {
+                Error::Internal(format!("migration file {} doesn't start with a version number", file_name))
+            })?;
+            let script = fs::read_to_string(&path)?;
+            let migration = migrations.entry(version).or_insert_with(|| Migration {
+                version,
+                name: name.to_string(),
+                up: String::new(),
+                down: None,
+            });
+            if migration.name != name {
+                return Err(Error::Internal(format!("migration {} has files with different names", version)));
+            }
+            match down {
+                true => migration.down = Some(script),
+                false => migration.up = script,
+            }
+        }
+        if let Some(migration) = migrations.values().find(|m| m.up.is_empty()) {
+            return Err(Error::Internal(format!("migration {} has no up script", migration.version)));
+        }
+        Self::new(migrations.into_values().collect())
+    }
+
+    /// Makes `up` and `down` only check the scripts, rolling everything back
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Versions applied to a database, in order
+    pub fn applied<E: StorageEngine + 'static>(&self, db: &Database<E>) -> Result<Vec<u64>> {
+        let txn = db.kv_txn()?;
+        let applied = applied(db, &txn);
+        txn.commit()?;
+        applied
+    }
+
+    /// Applies the migrations not applied yet, in version order, returning their versions
+    pub fn up<E: StorageEngine + 'static>(&self, db: &Database<E>) -> Result<Vec<u64>> {
+        let applied = self.applied(db)?;
+        let pending = self.migrations.values().filter(|m| !applied.contains(&m.version));
+        self.run(db, pending.map(|m| (m, true)).collect())
+    }
+
+    /// Reverts the applied migrations above `target`, newest first, returning their versions
+    ///
+    /// `down_to(db, 0)` reverts everything. Fails before changing anything if
+    /// one of them is unknown or has no down script.
+    pub fn down_to<E: StorageEngine + 'static>(&self, db: &Database<E>, target: u64) -> Result<Vec<u64>> {
+        let mut steps = Vec::new();
+        for version in self.applied(db)?.into_iter().rev().filter(|v| *v > target) {
+            match self.migrations.get(&version) {
+                Some(migration) if migration.down.is_some() => steps.push((migration, false)),
+                Some(_) => return Err(Error::Internal(format!("migration {} has no down script", version))),
+                None => return Err(Error::Internal(format!("applied migration {} is unknown", version))),
+            }
+        }
+        self.run(db, steps)
+    }
+
+    /// Runs migration scripts up (true) or down, each in its own transaction
+    /// unless this is a dry run
+    fn run<E: StorageEngine + 'static>(&self, db: &Database<E>, steps: Vec<(&Migration, bool)>) -> Result<Vec<u64>> {
+        let dry_run = match self.dry_run {
+            true => Some(db.kv_txn()?),
+            false => None,
+        };
+        let mut versions = Vec::new();
+        for (migration, up) in steps {
+            let txn = match &dry_run {
+                Some(txn) => txn.clone(),
+                None => db.kv_txn()?,
+            };
+            let result = step(db, &txn, migration, up).map_err(|err| {
+                Error::Internal(format!("migration {} ({}) failed: {}", migration.version, migration.name, err))
+            });
+            match result {
+                Ok(()) if dry_run.is_none() => txn.commit()?,
+                Ok(()) => {}
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                }
+            }
+            versions.push(migration.version);
+        }
+        if let Some(txn) = dry_run {
+            txn.rollback()?;
+        }
+        Ok(versions)
+    }
+}
+
+/// Versions recorded in a transaction's view of the migrations table
+fn applied<E: StorageEngine + 'static>(db: &Database<E>, txn: &MvccTransaction<E>) -> Result<Vec<u64>> {
+    if KVTransaction::new(txn.clone()).get_table(TABLE.into())?.is_none() {
+        return Ok(Vec::new());
+    }
+    match db.execute_in(txn, &format!("select version from {} order by version;", TABLE))? {
+        ResultSet::Scan { rows, .. } => rows
+            .into_iter()
+            .map(|row| match row.first() {
+                Some(Value::Integer(version)) => Ok(*version as u64),
+                value => Err(Error::Internal(format!("unexpected migration version {:?}", value))),
+            })
+            .collect(),
+        result => Err(Error::Internal(format!("unexpected result {:?}", result))),
+    }
+}
+
+/// Runs a migration's script and updates its record, in the transaction
+fn step<E: StorageEngine + 'static>(
+    db: &Database<E>,
+    txn: &MvccTransaction<E>,
+    migration: &Migration,
+    up: bool,
+) -> Result<()> {
+    let script = match up {
+        true => &migration.up,
+        false => migration.down.as_deref().unwrap_or_default(),
+    };
+    let stmts = Parser::new(script).parse_script().map_err(|errors| {
+        Error::Parse(errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n"))
+    })?;
+    for stmt in stmts {
+        db.execute_statement_in(txn, stmt)?;
+    }
+
+    let mut kv = KVTransaction::new(txn.clone());
+    if kv.get_table(TABLE.into())?.is_none() {
+        let sql = format!("create table {} (version bigint primary key, name string, applied_at bigint);", TABLE);
+        db.execute_in(txn, &sql)?;
+    }
+    match up {
+        true => {
+            let row = vec![
+                Value::Integer(migration.version.into()),
+                Value::String(migration.name.as_str().into()),
+                Value::Integer(now_millis()?.into()),
+            ];
+            insert_rows(&mut kv, TABLE, &Vec::new(), vec![row])?;
+        }
+        false => {
+            db.execute_in(txn, &format!("delete from {} where version = {};", TABLE, migration.version))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Migration, Migrator};
+    use crate::{
+        db::Database,
+        error::Result,
+        sql::{executor::ResultSet, types::Value},
+        storage::memory::MemoryEngine,
+    };
+
+    fn migration(version: u64, up: &str, down: Option<&str>) -> Migration {
+        Migration { version, name: format!("m{}", version), up: up.into(), down: down.map(String::from) }
+    }
+
+    fn t1(db: &Database<MemoryEngine>) -> Result<Vec<Vec<Value>>> {
+        match db.session()?.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => Ok(rows),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_up_down() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let migrator = Migrator::new(vec![
+            migration(3, "update t1 set b = 'z' where a = 1;", Some("update t1 set b = 'x' where a = 1;")),
+            migration(1, "create table t1 (a int primary key, b text); insert into t1 values (1, 'x');", None),
+            migration(2, "insert into t1 values (2, 'y');", Some("delete from t1 where a = 2;")),
+        ])?;
+        let row = |a: i128, b: &str| vec![Value::Integer(a), Value::String(b.into())];
+        assert_eq!(migrator.applied(&db)?, Vec::<u64>::new());
+
+        // A dry run checks the scripts and leaves nothing behind
+        let dry_run = Migrator::new(migrator.migrations.values().cloned().collect())?.dry_run(true);
+        assert_eq!(dry_run.up(&db)?, vec![1, 2, 3]);
+        assert!(t1(&db).is_err());
+        assert_eq!(migrator.applied(&db)?, Vec::<u64>::new());
+
+        assert_eq!(migrator.up(&db)?, vec![1, 2, 3]);
+        assert_eq!(migrator.up(&db)?, Vec::<u64>::new());
+        assert_eq!(migrator.applied(&db)?, vec![1, 2, 3]);
+        assert_eq!(t1(&db)?, vec![row(1, "z"), row(2, "y")]);
+        match db.session()?.execute("select version, name from schema_migrations;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows[0], vec![Value::Integer(1), Value::String("m1".into())]),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        assert_eq!(migrator.down_to(&db, 1)?, vec![3, 2]);
+        assert_eq!(migrator.applied(&db)?, vec![1]);
+        assert_eq!(t1(&db)?, vec![row(1, "x")]);
+        assert_eq!(migrator.up(&db)?, vec![2, 3]);
+        // Migration 1 can't be reverted, so nothing is
+        assert!(migrator.down_to(&db, 0).unwrap_err().to_string().contains("no down script"));
+        assert_eq!(migrator.applied(&db)?, vec![1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        let migrator = Migrator::new(vec![
+            migration(1, "create table t1 (a int primary key);", None),
+            migration(2, "create table t2 (a int primary key); insert into missing values (1);", None),
+        ])?;
+        let err = migrator.up(&db).unwrap_err().to_string();
+        assert!(err.contains("migration 2 (m2) failed"), "{}", err);
+        // The failed migration is rolled back as a whole, earlier ones stay
+        assert_eq!(migrator.applied(&db)?, vec![1]);
+        assert!(db.session()?.execute("select * from t2;").is_err());
+
+        assert!(Migrator::new(vec![migration(1, "", None), migration(1, "", None)]).is_err());
+        assert!(Migrator::new(vec![migration(3, "create table", None)])?.up(&db).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("0001_users.up.sql"), "create table t1 (a int primary key);")?;
+        fs::write(dir.path().join("0001_users.down.sql"), "delete from t1;")?;
+        fs::write(dir.path().join("0002_more.sql"), "create table t2 (a int primary key);")?;
+        fs::write(dir.path().join("README.md"), "not a migration")?;
+        let migrator = Migrator::from_dir(dir.path())?;
+        assert_eq!(
+            migrator.migrations.values().map(|m| (m.version, m.name.as_str(), m.down.is_some())).collect::<Vec<_>>(),
+            vec![(1, "users", true), (2, "more", false)]
+        );
+
+        fs::write(dir.path().join("first.sql"), "")?;
+        assert!(Migrator::from_dir(dir.path()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,686 @@
+//! Semantic analysis - binds a parsed statement to the catalog
+//!
+//! Runs between parsing and planning: column references are resolved to
+//! their position in the source rows, expression types are checked, and
+//! invalid queries (unknown tables or columns, type mismatches, misused
+//! aggregates) are rejected before execution. Missing names come with a
+//! "did you mean" suggestion when a catalog name is close.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Transaction, system},
+        executor::audit,
+        functions,
+        parser::ast::{self, Consts, Expression, Operation},
+        schema::Index,
+        types::DataType,
+    },
+};
+
+/// A column visible to a statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeColumn {
+    /// Source table, None for select outputs
+    pub table: Option<String>,
+    pub name: String,
+    /// None when not known statically (e.g., NULL)
+    pub datatype: Option<DataType>,
+    /// Whether the column may hold NULL (conservatively true for computed columns)
+    pub nullable: bool,
+    /// Whether this is the primary key of its table
+    pub primary_key: bool,
+}
+
+/// Columns visible to a statement, in source row order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scope {
+    pub columns: Vec<ScopeColumn>,
+}
+
+impl Scope {
+    /// Resolves a column name to its row position
+    ///
+    /// Like the executors, the first column with a matching name wins.
+    pub fn resolve(&self, name: &str) -> Result<(usize, &ScopeColumn)> {
+        match self.columns.iter().enumerate().find(|(_, c)| c.name == name) {
+            Some(found) => Ok(found),
+            None => {
+                let names = self.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+                Err(Error::Internal(format!(
+                    "column {} does not exist{}",
+                    name,
+                    did_you_mean(name, &names)
+                )))
+            }
+        }
+    }
+}
+
+/// A statement whose references have been resolved against the catalog
+#[derive(Debug, PartialEq)]
+pub struct BoundStatement {
+    pub statement: ast::Statement,
+    /// Columns of the rows the statement reads (FROM clause or target table)
+    pub scope: Scope,
+    /// Secondary indexes of the tables in the scope, by table name
+    pub indexes: BTreeMap<String, Vec<Index>>,
+}
+
+/// Wraps a statement without binding it (empty scope), for planning without a catalog
+///
+/// Plans built this way don't know their scan columns, so they're only fit for inspection.
+impl From<ast::Statement> for BoundStatement {
+    fn from(statement: ast::Statement) -> Self {
+        Self { statement, scope: Scope::default(), indexes: BTreeMap::new() }
+    }
+}
+
+/// Rejects writes to audit tables, whose rows only come from changes to the audited table
+fn check_audit_write(stmt: &ast::Statement) -> Result<()> {
+    match stmt {
+        ast::Statement::CreateTable { name, .. } | ast::Statement::CreateMaterializedView { name, .. }
+            if audit::is_audit_table(name) =>
+        {
+            Err(Error::Internal(format!(
+                "table name {} is reserved: names starting with {} are for audit tables",
+                name,
+                audit::PREFIX
+            )))
+        }
+        ast::Statement::Insert { table_name, .. }
+        | ast::Statement::Update { table_name, .. }
+        | ast::Statement::Delete { table_name, .. }
+        | ast::Statement::Copy { table_name, direction: ast::CopyDirection::From, .. }
+        | ast::Statement::LoadData { table_name, .. }
+            if audit::is_audit_table(table_name) =>
+        {
+            Err(Error::Internal(format!("audit table {} is read-only", table_name)))
+        }
+        ast::Statement::DropTable { name, .. } if audit::is_audit_table(name) => {
+            Err(Error::Internal(format!("audit table {} is dropped with its table", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Catalog-aware binder
+pub struct Analyzer<'a, T: Transaction> {
+    txn: &'a T,
+}
+
+impl<'a, T: Transaction> Analyzer<'a, T> {
+    pub fn new(txn: &'a T) -> Self {
+        Self { txn }
+    }
+
+    /// Binds a statement, rejecting it if it's semantically invalid
+    pub fn analyze(&self, stmt: ast::Statement) -> Result<BoundStatement> {
+        // EXPLAIN binds the statement it explains
+        if let ast::Statement::Explain { statement, analyze, format } = stmt {
+            let bound = self.analyze(*statement)?;
+            let statement = ast::Statement::Explain { statement: Box::new(bound.statement), analyze, format };
+            return Ok(BoundStatement { statement, scope: bound.scope, indexes: bound.indexes });
+        }
+        check_audit_write(&stmt)?;
+        // CREATE MATERIALIZED VIEW binds its query, which it plans with
+        if let ast::Statement::CreateMaterializedView { name, query } = stmt {
+            if query.as_of().is_some() {
+                return Err(Error::Internal(format!("materialized view {} can't read AS OF a version", name)));
+            }
+            let bound = self.analyze(*query)?;
+            let statement = ast::Statement::CreateMaterializedView { name, query: Box::new(bound.statement) };
+            return Ok(BoundStatement { statement, scope: bound.scope, indexes: bound.indexes });
+        }
+        self.check_view_write(&stmt)?;
+        let scope = match &stmt {
+            ast::Statement::CreateTable { .. } => Scope::default(),
+            ast::Statement::CreateIndex { table_name, columns, predicate, .. } => {
+                let scope = self.table_scope(table_name)?;
+                for col in columns {
+                    scope.resolve(col)?;
+                }
+                if let Some(expr) = predicate {
+                    check_predicate(expr, &scope, "WHERE")?;
+                }
+                Scope::default()
+            }
+            ast::Statement::DropTable { name, .. } => {
+                self.table_scope(name)?;
+                Scope::default()
+            }
+            ast::Statement::Insert { table_name, columns, values } => {
+                let scope = self.table_scope(table_name)?;
+                let targets = match columns {
+                    Some(columns) => columns
+                        .iter()
+                        .map(|c| Ok(scope.resolve(c)?.1))
+                        .collect::<Result<Vec<_>>>()?,
+                    None => scope.columns.iter().collect(),
+                };
+                // Values can't reference columns
+                for row in values {
+                    for (expr, target) in row.iter().zip(&targets) {
+                        let datatype = expr_type(expr, &Scope::default(), "VALUES")?;
+                        check_assign(target, datatype)?;
+                    }
+                }
+                scope
+            }
+            ast::Statement::Select {
+                select,
+                hints,
+                from,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+                ..
+            } => {
+                let mut scope = Scope::default();
+                self.bind_from(from, &mut scope)?;
+                self.check_hints(hints, &scope)?;
+                if let Some(expr) = where_clause {
+                    check_predicate(expr, &scope, "WHERE")?;
+                }
+                if let Some(expr) = group_by {
+                    expr_type(expr, &scope, "GROUP BY")?;
+                }
+                let outputs = bind_select(select, group_by, &scope)?;
+
+                // HAVING and ORDER BY may also name select outputs (aliases, aggregates)
+                let mut output_scope = scope.clone();
+                output_scope.columns.extend(outputs);
+                if let Some(expr) = having {
+                    check_predicate(expr, &output_scope, "HAVING")?;
+                }
+                for (col, _) in order_by {
+                    output_scope.resolve(col)?;
+                }
+                scope
+            }
+            ast::Statement::Update { table_name, columns, where_clause } => {
+                let scope = self.table_scope(table_name)?;
+                for (col, expr) in columns {
+                    let (_, target) = scope.resolve(col)?;
+                    check_assign(target, expr_type(expr, &scope, "SET")?)?;
+                }
+                if let Some(expr) = where_clause {
+                    check_predicate(expr, &scope, "WHERE")?;
+                }
+                scope
+            }
+            ast::Statement::Delete { table_name, where_clause } => {
+                let scope = self.table_scope(table_name)?;
+                if let Some(expr) = where_clause {
+                    check_predicate(expr, &scope, "WHERE")?;
+                }
+                scope
+            }
+            ast::Statement::Copy { table_name, .. } | ast::Statement::LoadData { table_name, .. } => {
+                self.table_scope(table_name)?
+            }
+            ast::Statement::ShowStats => self.table_scope(system::STATS)?,
+            ast::Statement::ShowCreateTable { name, .. } => {
+                self.table_scope(name)?;
+                Scope::default()
+            }
+            ast::Statement::RefreshMaterializedView { name } => {
+                self.table_scope(name)?;
+                if self.txn.must_get_table(name.clone())?.view.is_none() {
+                    return Err(Error::Internal(format!("{} is not a materialized view", name)));
+                }
+                Scope::default()
+            }
+            ast::Statement::CreateMaterializedView { .. } => unreachable!("CREATE MATERIALIZED VIEW is bound above"),
+            ast::Statement::Explain { .. } => unreachable!("EXPLAIN is bound above"),
+            // Sessions run these themselves
+            ast::Statement::Begin | ast::Statement::Commit | ast::Statement::Rollback | ast::Statement::Set { .. } => {
+                return Err(Error::Internal(format!("{} is only allowed as a session statement", stmt)));
+            }
+        };
+        let mut indexes = BTreeMap::new();
+        for table in scope.columns.iter().filter_map(|c| c.table.as_ref()) {
+            if !indexes.contains_key(table)
+                && let Some(table) = self.txn.get_table(table.clone())?
+                && table.indexes.iter().any(|index| !index.building)
+            {
+                indexes.insert(table.name, table.indexes.into_iter().filter(|index| !index.building).collect());
+            }
+        }
+        let mut stmt = stmt;
+        stmt.transform_expressions(&mut |expr| widen(expr, &scope));
+        Ok(BoundStatement { statement: stmt, scope, indexes })
+    }
+
+    /// Rejects writes to materialized views, whose rows only come from their query
+    fn check_view_write(&self, stmt: &ast::Statement) -> Result<()> {
+        match stmt {
+            ast::Statement::Insert { table_name, .. }
+            | ast::Statement::Update { table_name, .. }
+            | ast::Statement::Delete { table_name, .. }
+            | ast::Statement::Copy { table_name, direction: ast::CopyDirection::From, .. }
+            | ast::Statement::LoadData { table_name, .. }
+                if self.txn.get_table(table_name.clone())?.is_some_and(|table| table.view.is_some()) =>
+            {
+                Err(Error::Internal(format!(
+                    "materialized view {} is read-only: REFRESH MATERIALIZED VIEW updates it",
+                    table_name
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Columns of a table, suggesting a similar table name if it doesn't exist
+    fn table_scope(&self, table_name: &str) -> Result<Scope> {
+        match self.txn.get_table(table_name.to_string())? {
+            Some(table) => Ok(Scope {
+                columns: table
+                    .columns
+                    .into_iter()
+                    .map(|c| ScopeColumn {
+                        table: Some(table.name.clone()),
+                        name: c.name,
+                        datatype: Some(c.datatype),
+                        nullable: c.nullable,
+                        primary_key: c.primary_key,
+                    })
+                    .collect(),
+            }),
+            None => Err(Error::Internal(format!(
+                "table {} does not exist{}",
+                table_name,
+                did_you_mean(table_name, &self.txn.get_table_names()?)
+            ))),
+        }
+    }
+
+    /// Checks that hints name tables of the FROM clause, and indexes of those tables
+    fn check_hints(&self, hints: &[ast::Hint], scope: &Scope) -> Result<()> {
+        let in_from = |table: &str| {
+            match scope.columns.iter().any(|c| c.table.as_deref() == Some(table)) {
+                true => Ok(()),
+                false => Err(Error::Internal(format!("hint table {} is not in the FROM clause", table))),
+            }
+        };
+        for hint in hints {
+            match hint {
+                ast::Hint::Index { table, index } => {
+                    in_from(table)?;
+                    if self.txn.must_get_table(table.clone())?.index(index)?.building {
+                        return Err(Error::Internal(format!("index {} is still being built", index)));
+                    }
+                }
+                ast::Hint::NoIndex { table: Some(table) } => in_from(table)?,
+                ast::Hint::NoIndex { table: None } => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the columns of a FROM clause to the scope, checking join predicates
+    ///
+    /// Joined rows are the left row followed by the right row.
+    fn bind_from(&self, item: &ast::FromItem, scope: &mut Scope) -> Result<()> {
+        match item {
+            ast::FromItem::Table { name } => scope.columns.extend(self.table_scope(name)?.columns),
+            ast::FromItem::Join { left, right, predicate, .. } => {
+                self.bind_from(left, scope)?;
+                self.bind_from(right, scope)?;
+                if let Some(expr) = predicate {
+                    check_predicate(expr, scope, "JOIN ON")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the select list, returning its output columns
+///
+/// With aggregates or GROUP BY, every output must be an aggregate or the group column.
+fn bind_select(
+    select: &[(Expression, Option<String>)],
+    group_by: &Option<Expression>,
+    scope: &Scope,
+) -> Result<Vec<ScopeColumn>> {
+    let aggregated = group_by.is_some() || select.iter().any(|(e, _)| matches!(e, Expression::Function(..)));
+    let mut outputs = Vec::new();
+    for (expr, alias) in select {
+        let (name, datatype, nullable) = match expr {
+            // The parser only accepts * in COUNT(*)
+            Expression::Function(func, col) if col == ast::ALL_ROWS => (func.clone(), Some(DataType::Integer), false),
+            Expression::Function(func, col) => {
+                (func.clone(), aggregate_type(func, scope.resolve(col)?.1)?, aggregate_nullable(func))
+            }
+            Expression::Field(col) => {
+                let (_, source) = scope.resolve(col)?;
+                let (datatype, nullable) = (source.datatype, source.nullable);
+                if aggregated && group_by.as_ref() != Some(expr) {
+                    return Err(Error::Internal(format!(
+                        "column {} must appear in GROUP BY or be used in aggregate function",
+                        col
+                    )));
+                }
+                (col.clone(), datatype, nullable)
+            }
+            expr => {
+                let datatype = expr_type(expr, scope, "expressions")?;
+                if aggregated {
+                    return Err(Error::Internal(
+                        "expressions can't be selected with aggregates or GROUP BY".into(),
+                    ));
+                }
+                let name = match expr {
+                    Expression::ScalarFunction(name, _) => name.clone(),
+                    _ => "?column?".into(),
+                };
+                (name, datatype, true)
+            }
+        };
+        outputs.push(ScopeColumn {
+            table: None,
+            name: alias.clone().unwrap_or(name),
+            datatype,
+            nullable,
+            primary_key: false,
+        });
+    }
+    Ok(outputs)
+}
+
+/// Whether an aggregate function can return NULL (all but COUNT do over no rows)
+pub(crate) fn aggregate_nullable(func: &str) -> bool {
+    !func.eq_ignore_ascii_case("COUNT")
+}
+
+/// Result type of an aggregate function over a column
+pub(crate) fn aggregate_type(func: &str, col: &ScopeColumn) -> Result<Option<DataType>> {
+    let numeric = col.datatype.is_none_or(|dt| is_numeric(&dt));
+    Ok(match func.to_uppercase().as_ref() {
+        "COUNT" => Some(DataType::Integer),
+        // Integer sums widen to BIGINT, so they only overflow past 128 bits
+        "SUM" if numeric => col.datatype.map(|dt| if dt.is_integer() { DataType::BigInt } else { dt }),
+        "AVG" if numeric => Some(DataType::Float),
+        "SUM" | "AVG" => {
+            return Err(Error::Internal(format!(
+                "can not calc {} of {} column {}",
+                func,
+                col.datatype.map(|dt| dt.to_string()).unwrap_or_default(),
+                col.name
+            )))
+        }
+        _ => col.datatype,
+    })
+}
+
+/// Puts the BIGINT function in place of arithmetic over a BIGINT operand,
+/// whose result may use all 128 bits (see `ast::BIGINT_FUNCTIONS`)
+fn widen(expr: &mut Expression, scope: &Scope) {
+    if !matches!(expr, Expression::Operation(_)) || !matches!(expr_type(expr, scope, ""), Ok(Some(DataType::BigInt))) {
+        return;
+    }
+    if let Expression::Operation(operation) = std::mem::replace(expr, Consts::Null.into()) {
+        *expr = operation.into_bigint();
+    }
+}
+
+/// Checks that a filter expression is a boolean
+fn check_predicate(expr: &Expression, scope: &Scope, clause: &str) -> Result<()> {
+    match expr_type(expr, scope, clause)? {
+        None | Some(DataType::Boolean) => Ok(()),
+        Some(dt) => Err(Error::Internal(format!(
+            "{} condition must be a boolean, got {}",
+            clause, dt
+        ))),
+    }
+}
+
+/// Checks that a value of the given type can be stored in a column
+fn check_assign(target: &ScopeColumn, datatype: Option<DataType>) -> Result<()> {
+    let ok = match (target.datatype, datatype) {
+        (_, None) | (None, _) => true,
+        (Some(col), Some(dt)) => {
+            col == dt
+                || (col.is_integer() && dt.is_integer())
+                // UUID literals are written as strings
+                || (col == DataType::Uuid && dt == DataType::String)
+        }
+    };
+    if !ok {
+        return Err(Error::Internal(format!("column {} type mismatch", target.name)));
+    }
+    Ok(())
+}
+
+fn is_numeric(dt: &DataType) -> bool {
+    dt.is_integer() || *dt == DataType::Float
+}
+
+/// Infers the type of an expression, checking its column references and operand types
+///
+/// Aggregates are only valid at the top of a select list, so `clause` names
+/// the context being checked for the error.
+pub(crate) fn expr_type(expr: &Expression, scope: &Scope, clause: &str) -> Result<Option<DataType>> {
+    Ok(match expr {
+        Expression::Field(col) => scope.resolve(col)?.1.datatype,
+        Expression::Consts(c) => match c {
+            Consts::Null => None,
+            Consts::Boolean(_) => Some(DataType::Boolean),
+            // Only a BIGINT holds literals beyond INTEGER
+            Consts::Integer(i) if i64::try_from(*i).is_err() => Some(DataType::BigInt),
+            Consts::Integer(_) => Some(DataType::Integer),
+            Consts::Float(_) => Some(DataType::Float),
+            Consts::String(_) => Some(DataType::String),
+        },
+        Expression::Function(func, _) => {
+            return Err(Error::Internal(format!(
+                "aggregate function {} is not allowed in {}",
+                func, clause
+            )))
+        }
+        Expression::Operation(op) => match op {
+            Operation::Equal(l, r) | Operation::GreaterThan(l, r) | Operation::LessThan(l, r) => {
+                let (lt, rt) = (expr_type(l, scope, clause)?, expr_type(r, scope, clause)?);
+                let comparable = match (lt, rt) {
+                    (None, _) | (_, None) => true,
+                    (Some(l), Some(r)) => {
+                        l == r
+                            || (is_numeric(&l) && is_numeric(&r))
+                            || matches!((l, r), (DataType::Uuid, DataType::String) | (DataType::String, DataType::Uuid))
+                    }
+                };
+                if !comparable {
+                    return Err(Error::Internal(format!(
+                        "can not compare {} and {}",
+                        lt.unwrap(),
+                        rt.unwrap()
+                    )));
+                }
+                Some(DataType::Boolean)
+            }
+            Operation::Add(l, r)
+            | Operation::Subtract(l, r)
+            | Operation::Multiply(l, r)
+            | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) => {
+                match (expr_type(l, scope, clause)?, expr_type(r, scope, clause)?) {
+                    (None, _) | (_, None) => None,
+                    (Some(DataType::Float), Some(r)) if is_numeric(&r) => Some(DataType::Float),
+                    (Some(l), Some(DataType::Float)) if is_numeric(&l) => Some(DataType::Float),
+                    (Some(DataType::BigInt), Some(r)) | (Some(r), Some(DataType::BigInt)) if r.is_integer() => {
+                        Some(DataType::BigInt)
+                    }
+                    (Some(l), Some(r)) if l.is_integer() && r.is_integer() => Some(DataType::Integer),
+                    (Some(l), Some(r)) => {
+                        return Err(Error::Internal(format!(
+                            "can not apply arithmetic to {} and {}",
+                            l, r
+                        )))
+                    }
+                }
+            }
+        },
+        Expression::ScalarFunction(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| expr_type(arg, scope, clause))
+                .collect::<Result<Vec<_>>>()?;
+            functions::return_type(name, &args)
+        }
+    })
+}
+
+/// Formats a suggestion for the candidate closest to the given name, if any is close
+fn did_you_mean(name: &str, candidates: &[String]) -> String {
+    let name = name.to_lowercase();
+    // Allow roughly one edit per three characters, and at least one
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|c| (edit_distance(&name, &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| format!(", did you mean `{}`?", c))
+        .unwrap_or_default()
+}
+
+/// Levenshtein distance between two strings, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Analyzer;
+    use crate::{
+        error::{Error, Result},
+        sql::{
+            engine::{kv::KVEngine, Engine, Session, Transaction},
+            executor::ResultSet,
+            parser::Parser,
+            types::{DataType, Value},
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_analyzer_suggestions() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table products (id int primary key, product_name text, price float);")?;
+        s.execute("create table orders (id int primary key, product int);")?;
+
+        assert_eq!(
+            s.execute("select * from prodcts;").err(),
+            Some(Error::Internal("table prodcts does not exist, did you mean `products`?".into()))
+        );
+        assert_eq!(
+            s.execute("select product_nme from products;").err(),
+            Some(Error::Internal("column product_nme does not exist, did you mean `product_name`?".into()))
+        );
+        assert_eq!(
+            s.execute("select * from products join orders on id = prodct;").err(),
+            Some(Error::Internal("column prodct does not exist, did you mean `product`?".into()))
+        );
+        assert_eq!(
+            s.execute("update products set cost = 1;").err(),
+            Some(Error::Internal("column cost does not exist".into()))
+        );
+        assert_eq!(
+            s.execute("insert into products (id, prise) values (1, 2.0);").err(),
+            Some(Error::Internal("column prise does not exist, did you mean `price`?".into()))
+        );
+
+        // Select outputs may be referenced by HAVING and ORDER BY
+        s.execute("insert into products values (1, 'pen', 2.5);")?;
+        s.execute("select product_name, sum(price) from products group by product_name having sum > 1 order by sum;")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_analyzer_binding() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        s.execute("create table t2 (d int primary key, e bool);")?;
+
+        let txn = kvengine.begin()?;
+        let stmt = Parser::new("select * from t1 join t2 on a = d;").parse()?;
+        let bound = Analyzer::new(&txn).analyze(stmt)?;
+        let (pos, col) = bound.scope.resolve("e")?;
+        assert_eq!((pos, col.table.as_deref(), col.datatype), (4, Some("t2"), Some(DataType::Boolean)));
+
+        let err = |sql: &str| {
+            let stmt = Parser::new(sql).parse().unwrap();
+            Analyzer::new(&txn).analyze(stmt).unwrap_err()
+        };
+        assert_eq!(err("select * from t1 where b = 1;"), Error::Internal("can not compare STRING and INTEGER".into()));
+        assert_eq!(err("select * from t1 where c;"), Error::Internal("WHERE condition must be a boolean, got FLOAT".into()));
+        assert_eq!(err("select b + 1 from t1;"), Error::Internal("can not apply arithmetic to STRING and INTEGER".into()));
+        assert_eq!(err("update t1 set c = 'x';"), Error::Internal("column c type mismatch".into()));
+        assert_eq!(err("insert into t2 values (1, 2);"), Error::Internal("column e type mismatch".into()));
+        assert_eq!(err("select sum(b) from t1;"), Error::Internal("can not calc sum of STRING column b".into()));
+        assert_eq!(
+            err("select a, count(b) from t1;"),
+            Error::Internal("column a must appear in GROUP BY or be used in aggregate function".into())
+        );
+        assert_eq!(err("select * from t1 where max(a) > 1;"), Error::Internal("aggregate function max is not allowed in WHERE".into()));
+        assert_eq!(err("select sum(a) + 1 from t1;"), Error::Internal("aggregate function sum is not allowed in expressions".into()));
+
+        // Integers of any width mix, and NULL is compatible with everything
+        let stmt = Parser::new("select a * 2 + c from t1 where c > 1 + a;").parse()?;
+        Analyzer::new(&txn).analyze(stmt)?;
+        let stmt = Parser::new("update t1 set a = a + 1, b = null where c > 1;").parse()?;
+        Analyzer::new(&txn).analyze(stmt)?;
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_analyzer_bigint() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a bigint primary key, b int);")?;
+        s.execute("insert into t1 values (-1, 9223372036854775807);")?;
+
+        // Arithmetic over a BIGINT uses the BIGINT functions, printed as operators
+        let txn = kvengine.begin()?;
+        let sql = "SELECT a * (b + 1), b * 2 - 1, b % 170141183460469231731687303715884105727 FROM t1";
+        let bound = Analyzer::new(&txn).analyze(Parser::new(&format!("{};", sql)).parse()?)?;
+        let text = format!("{:?}", bound.statement);
+        assert!(text.contains("bigint_mul") && text.contains("bigint_mod") && !text.contains("bigint_add"), "{}", text);
+        assert_eq!(bound.statement.to_string(), sql);
+        txn.rollback()?;
+
+        // Other integer results must fit INTEGER
+        let value = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => Ok(rows[0][0].clone()),
+            Ok(other) => panic!("unexpected result {:?}", other),
+            Err(err) => Err(err),
+        };
+        assert_eq!(
+            value(&mut s, "select 9223372036854775807 + 1 from t1;"),
+            Err(Error::Internal("integer overflow: 9223372036854775807 + 1".into()))
+        );
+        assert!(value(&mut s, "select b * 2 from t1;").is_err());
+        assert_eq!(value(&mut s, "select a * b * 2 from t1;")?, Value::Integer(-18446744073709551614));
+        assert_eq!(value(&mut s, "select 9223372036854775808 - 1 from t1;")?, Value::Integer(9223372036854775807));
+        s.execute("update t1 set a = b + a * -2;")?;
+        assert_eq!(value(&mut s, "select a from t1;")?, Value::Integer(9223372036854775809));
+        Ok(())
+    }
+}
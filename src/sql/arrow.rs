@@ -0,0 +1,369 @@
+//! Apache Arrow interchange (feature `arrow`)
+//!
+//! Converts query results to Arrow `RecordBatch`es, and record batches back
+//! into rows to insert, so data moves to and from the Rust data ecosystem
+//! (Polars, DataFusion, Parquet writers) without going through SQL text.
+//!
+//! Table rows, and result columns read straight from a table, convert by
+//! their column types (see `arrow_type`). Computed result columns may hold
+//! values outside their static type (e.g., INT arithmetic beyond i64), so
+//! their Arrow type follows the values:
+//!
+//! | Value   | Arrow                                              |
+//! |---------|----------------------------------------------------|
+//! | Boolean | Boolean                                            |
+//! | Integer | Int64, or Decimal128(38, 0) beyond i64 (BIGINT)    |
+//! | Float   | Float64                                            |
+//! | String  | Utf8                                               |
+//! | Point   | Struct { x: Float64, y: Float64 }                  |
+//! | Uuid    | FixedSizeBinary(16)                                |
+//!
+//! All-NULL computed columns become Null arrays. Importing also accepts the other
+//! integer and float widths, unsigned integers and large/view strings.
+
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, ArrayRef, ArrowPrimitiveType, BooleanArray, Decimal128Array, FixedSizeBinaryArray, Float64Array,
+    Int16Array, Int64Array, NullArray, RecordBatch, RecordBatchOptions, StringArray, StructArray,
+    cast::AsArray,
+    types::{
+        Decimal128Type, Float16Type, Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type,
+        UInt16Type, UInt32Type, UInt64Type,
+    },
+};
+use arrow_buffer::NullBuffer;
+use arrow_schema::{DataType, Field, Fields, Schema};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Session},
+        executor::{ColumnMetadata, ResultSet},
+        schema::Table,
+        types::{self, Row, Value},
+    },
+};
+
+/// Converts a `ResultSet::Scan` into a record batch with one field per column
+pub fn to_record_batch(result: &ResultSet) -> Result<RecordBatch> {
+    let ResultSet::Scan { columns, rows, metadata } = result else {
+        return Err(Error::Internal("only query results convert to a record batch".into()));
+    };
+    if let Some(row) = rows.iter().find(|row| row.len() != columns.len()) {
+        return Err(Error::Internal(format!(
+            "row has {} values for {} columns",
+            row.len(),
+            columns.len()
+        )));
+    }
+    let fields = columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match metadata.get(i) {
+            Some(ColumnMetadata { datatype: Some(datatype), nullable, table: Some(_) }) => {
+                Field::new(name, arrow_type(*datatype), *nullable)
+            }
+            _ => {
+                let values = rows.iter().map(|row| &row[i]).collect::<Vec<_>>();
+                Field::new(name, infer_type(&values), values.iter().any(|v| **v == Value::Null))
+            }
+        })
+        .collect();
+    build(fields, rows)
+}
+
+/// Converts table rows into a record batch typed by the table schema (see `arrow_type`)
+pub fn table_to_record_batch(table: &Table, rows: &[Row]) -> Result<RecordBatch> {
+    let fields = table
+        .columns
+        .iter()
+        .map(|c| Field::new(&c.name, arrow_type(c.datatype), c.nullable))
+        .collect();
+    build(fields, rows)
+}
+
+/// Arrow type of a column type
+///
+/// Stored values are in range for their column, so INT fits Int64 and only
+/// the 128-bit BIGINT needs a decimal.
+pub fn arrow_type(datatype: types::DataType) -> DataType {
+    match datatype {
+        types::DataType::Boolean => DataType::Boolean,
+        types::DataType::SmallInt => DataType::Int16,
+        types::DataType::Integer => DataType::Int64,
+        types::DataType::BigInt => DataType::Decimal128(38, 0),
+        types::DataType::Float => DataType::Float64,
+        types::DataType::String => DataType::Utf8,
+        types::DataType::Point => point_type(),
+        types::DataType::Uuid => DataType::FixedSizeBinary(16),
+    }
+}
+
+fn point_type() -> DataType {
+    DataType::Struct(Fields::from(vec![
+        Field::new("x", DataType::Float64, false),
+        Field::new("y", DataType::Float64, false),
+    ]))
+}
+
+/// Converts a record batch into column names and rows, e.g., to insert with `Session::insert_rows`
+pub fn from_record_batch(batch: &RecordBatch) -> Result<(Vec<String>, Vec<Row>)> {
+    let schema = batch.schema();
+    let mut rows = vec![Vec::with_capacity(batch.num_columns()); batch.num_rows()];
+    for (field, array) in schema.fields().iter().zip(batch.columns()) {
+        for (row, value) in rows.iter_mut().zip(from_array(field.name(), array.as_ref())?) {
+            row.push(value);
+        }
+    }
+    let columns = schema.fields().iter().map(|f| f.name().clone()).collect();
+    Ok((columns, rows))
+}
+
+impl<E: Engine + 'static> Session<E> {
+    /// Inserts the rows of a record batch into a table in one transaction,
+    /// matching fields to columns by name
+    pub fn insert_record_batch(&mut self, table_name: &str, batch: &RecordBatch) -> Result<ResultSet> {
+        let (columns, rows) = from_record_batch(batch)?;
+        self.insert_rows(table_name, columns, rows)
+    }
+}
+
+fn arrow_error(err: arrow_schema::ArrowError) -> Error {
+    Error::Internal(err.to_string())
+}
+
+fn build(fields: Vec<Field>, rows: &[Row]) -> Result<RecordBatch> {
+    let arrays = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let values = rows.iter().map(|row| &row[i]).collect::<Vec<_>>();
+            to_array(field.name(), &values, field.data_type())
+        })
+        .collect::<Result<_>>()?;
+    let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+    RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), arrays, &options).map_err(arrow_error)
+}
+
+/// Arrow type of a result column, following its non-NULL values
+fn infer_type(values: &[&Value]) -> DataType {
+    match values.iter().find(|v| ***v != Value::Null) {
+        None => DataType::Null,
+        Some(Value::Boolean(_)) => DataType::Boolean,
+        Some(Value::Integer(_)) => {
+            match values.iter().all(|v| !matches!(v, Value::Integer(i) if i64::try_from(*i).is_err())) {
+                true => DataType::Int64,
+                false => DataType::Decimal128(38, 0),
+            }
+        }
+        Some(Value::Float(_)) => DataType::Float64,
+        Some(Value::String(_)) => DataType::Utf8,
+        Some(Value::Point(..)) => point_type(),
+        Some(Value::Uuid(_)) => DataType::FixedSizeBinary(16),
+        Some(Value::Null) => unreachable!(),
+    }
+}
+
+/// Builds the array of one column
+fn to_array(name: &str, values: &[&Value], datatype: &DataType) -> Result<ArrayRef> {
+    let mismatch = |v: &Value| Error::Internal(format!("column {} of arrow type {} can't hold {}", name, datatype, v));
+    let int = |v: &Value| match v {
+        Value::Integer(i) => Some(*i),
+        _ => None,
+    };
+    Ok(match datatype {
+        DataType::Null => match values.iter().find(|v| ***v != Value::Null) {
+            Some(v) => return Err(mismatch(v)),
+            None => Arc::new(NullArray::new(values.len())),
+        },
+        DataType::Boolean => Arc::new(collect::<_, BooleanArray>(values, mismatch, |v| match v {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        })?),
+        DataType::Int16 => Arc::new(collect::<_, Int16Array>(values, mismatch, |v| int(v)?.try_into().ok())?),
+        DataType::Int64 => Arc::new(collect::<_, Int64Array>(values, mismatch, |v| int(v)?.try_into().ok())?),
+        DataType::Decimal128(38, 0) => Arc::new(
+            collect::<_, Decimal128Array>(values, mismatch, int)?
+                .with_precision_and_scale(38, 0)
+                .map_err(arrow_error)?,
+        ),
+        DataType::Float64 => Arc::new(collect::<_, Float64Array>(values, mismatch, |v| match v {
+            Value::Float(f) => Some(*f),
+            _ => None,
+        })?),
+        DataType::Utf8 => Arc::new(collect::<_, StringArray>(values, mismatch, |v| match v {
+            Value::String(s) => Some(&**s),
+            _ => None,
+        })?),
+        DataType::Struct(fields) if *datatype == point_type() => {
+            let points = collect::<_, Vec<_>>(values, mismatch, |v| match v {
+                Value::Point(x, y) => Some((*x, *y)),
+                _ => None,
+            })?;
+            let coordinate = |f: fn((f64, f64)) -> f64| -> ArrayRef {
+                Arc::new(points.iter().map(|p| p.map_or(0.0, f)).collect::<Float64Array>())
+            };
+            let nulls = NullBuffer::from(points.iter().map(Option::is_some).collect::<Vec<_>>());
+            Arc::new(
+                StructArray::try_new(fields.clone(), vec![coordinate(|p| p.0), coordinate(|p| p.1)], Some(nulls))
+                    .map_err(arrow_error)?,
+            )
+        }
+        DataType::FixedSizeBinary(16) => {
+            let uuids = collect::<_, Vec<_>>(values, mismatch, |v| match v {
+                Value::Uuid(u) => Some(*u),
+                _ => None,
+            })?;
+            Arc::new(FixedSizeBinaryArray::try_from_sparse_iter_with_size(uuids.into_iter(), 16).map_err(arrow_error)?)
+        }
+        datatype => return Err(Error::Internal(format!("column {} has unsupported arrow type {}", name, datatype))),
+    })
+}
+
+/// Collects the values of a column, failing on any that `f` doesn't convert
+fn collect<'v, T, A: FromIterator<Option<T>>>(
+    values: &[&'v Value],
+    mismatch: impl Fn(&Value) -> Error,
+    f: impl Fn(&'v Value) -> Option<T>,
+) -> Result<A> {
+    values
+        .iter()
+        .map(|v| match v {
+            Value::Null => Ok(None),
+            v => f(v).map(Some).ok_or_else(|| mismatch(v)),
+        })
+        .collect()
+}
+
+/// Reads one column of a record batch as values
+fn from_array(name: &str, array: &dyn Array) -> Result<Vec<Value>> {
+    fn primitive<T: ArrowPrimitiveType>(array: &dyn Array, f: impl Fn(T::Native) -> Value) -> Vec<Value> {
+        array.as_primitive::<T>().iter().map(|v| v.map_or(Value::Null, &f)).collect()
+    }
+    let int = |i: i128| Value::Integer(i);
+    Ok(match array.data_type() {
+        DataType::Null => vec![Value::Null; array.len()],
+        DataType::Boolean => array.as_boolean().iter().map(|b| b.map_or(Value::Null, Value::Boolean)).collect(),
+        DataType::Int8 => primitive::<Int8Type>(array, |i| int(i.into())),
+        DataType::Int16 => primitive::<Int16Type>(array, |i| int(i.into())),
+        DataType::Int32 => primitive::<Int32Type>(array, |i| int(i.into())),
+        DataType::Int64 => primitive::<Int64Type>(array, |i| int(i.into())),
+        DataType::UInt8 => primitive::<UInt8Type>(array, |i| int(i.into())),
+        DataType::UInt16 => primitive::<UInt16Type>(array, |i| int(i.into())),
+        DataType::UInt32 => primitive::<UInt32Type>(array, |i| int(i.into())),
+        DataType::UInt64 => primitive::<UInt64Type>(array, |i| int(i.into())),
+        DataType::Decimal128(_, 0) => primitive::<Decimal128Type>(array, int),
+        DataType::Float16 => primitive::<Float16Type>(array, |f| Value::Float(f.into())),
+        DataType::Float32 => primitive::<Float32Type>(array, |f| Value::Float(f.into())),
+        DataType::Float64 => primitive::<Float64Type>(array, Value::Float),
+        DataType::Utf8 => strings(array.as_string::<i32>().iter()),
+        DataType::LargeUtf8 => strings(array.as_string::<i64>().iter()),
+        DataType::Utf8View => strings(array.as_string_view().iter()),
+        DataType::FixedSizeBinary(16) => array
+            .as_fixed_size_binary()
+            .iter()
+            .map(|u| u.map_or(Value::Null, |u| Value::Uuid(u.try_into().expect("16 byte values"))))
+            .collect(),
+        DataType::Struct(fields)
+            if fields.len() == 2 && fields[0].name() == "x" && fields[1].name() == "y" =>
+        {
+            let points = array.as_struct();
+            let x = from_array(name, points.column(0).as_ref())?;
+            let y = from_array(name, points.column(1).as_ref())?;
+            x.into_iter()
+                .zip(y)
+                .enumerate()
+                .map(|(i, coordinates)| match coordinates {
+                    _ if points.is_null(i) => Ok(Value::Null),
+                    (Value::Float(x), Value::Float(y)) => Ok(Value::Point(x, y)),
+                    _ => Err(Error::Internal(format!("column {} has a point without float coordinates", name))),
+                })
+                .collect::<Result<_>>()?
+        }
+        datatype => {
+            return Err(Error::Internal(format!(
+                "column {} has unsupported arrow type {}",
+                name, datatype
+            )))
+        }
+    })
+}
+
+fn strings<'a>(iter: impl Iterator<Item = Option<&'a str>>) -> Vec<Value> {
+    iter.map(|s| s.map_or(Value::Null, |s| Value::String(s.into()))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, Int32Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::{from_record_batch, to_record_batch};
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_record_batch() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        for name in ["t1", "t2"] {
+            s.execute(&format!(
+                "create table {} (a bigint primary key, b bool, c float, d text, e point, f uuid, g int);",
+                name
+            ))?;
+        }
+        s.execute(
+            "insert into t1 values
+                (1, true, 1.5, 'x', point(1, 2), '67e55044-10b1-426f-9247-bb680e5fe0c8', null),
+                (170141183460469231731687303715884105727, null, null, null, null, null, null);",
+        )?;
+
+        let result = s.execute("select * from t1;")?;
+        let batch = to_record_batch(&result)?;
+        assert_eq!(batch.num_rows(), 2);
+        let types = batch.schema().fields().iter().map(|f| f.data_type().clone()).collect::<Vec<_>>();
+        assert_eq!(types[0], DataType::Decimal128(38, 0));
+        assert_eq!(types[1..4], [DataType::Boolean, DataType::Float64, DataType::Utf8]);
+        assert!(matches!(types[4], DataType::Struct(_)));
+        assert_eq!(types[5..], [DataType::FixedSizeBinary(16), DataType::Int64]);
+        assert!(batch.column(4).is_null(1));
+        assert!(!batch.schema().field(0).is_nullable());
+
+        // Computed columns follow their values
+        let computed = to_record_batch(&s.execute("select a, g + 1 from t1;")?)?;
+        assert_eq!(computed.schema().field(1).data_type(), &DataType::Null);
+
+        // Round trip through an insert
+        s.insert_record_batch("t2", &batch)?;
+        assert_eq!(to_record_batch(&s.execute("select * from t2;")?)?, batch);
+
+        // Narrower foreign types widen into values
+        let schema = Schema::new(vec![Field::new("g", DataType::Int32, true), Field::new("d", DataType::Utf8, true)]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Int32Array::from(vec![Some(7), None])), Arc::new(StringArray::from(vec!["p", "q"]))],
+        )
+        .unwrap();
+        let (columns, rows) = from_record_batch(&batch)?;
+        assert_eq!(columns, vec!["g", "d"]);
+        assert_eq!(rows[0], vec![Value::Integer(7), Value::String("p".into())]);
+        assert_eq!(rows[1], vec![Value::Null, Value::String("q".into())]);
+
+        // Only query results convert
+        assert!(to_record_batch(&ResultSet::Insert { count: 1 }).is_err());
+        let rows = vec![vec![Value::Integer(1)], vec![Value::Float(1.0)]];
+        let mixed = ResultSet::Scan { columns: vec!["a".into()], rows, metadata: vec![] };
+        assert!(to_record_batch(&mixed).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+//! Compact row encoding
+//!
+//! Rows and index entries are stored as:
+//!
+//! ```text
+//! 0xFF VERSION count:varint null-bitmap values...
+//! ```
+//!
+//! The null bitmap has a bit per column (LSB first), set for NULLs, which
+//! take no further space. Every other value is a tag byte, followed by:
+//!
+//! - booleans: nothing, the tag is the value
+//! - integers: the zigzag-encoded value as a LEB128 varint
+//! - floats: 8 bytes, little endian
+//! - strings: the byte length as a varint, then the UTF-8 bytes
+//! - points: x then y, 8 bytes each
+//! - UUIDs: the 16 raw bytes
+//!
+//! Compared to bincode's 4-byte enum tags, 16-byte integers and 8-byte
+//! lengths, a typical row takes a fraction of the space. Values are
+//! self-delimiting, so `decode_columns` can skip the columns it doesn't
+//! need without building them.
+//!
+//! Rows written before this format are bincode sequences, which start with
+//! an 8-byte little-endian length. No such row has 0xFF, 0x01 and a non-zero
+//! byte as its first three (that takes over 65,000 columns), so both formats
+//! are told apart by the header and read alike. `Database::migrate_row_format`
+//! rewrites old rows in this format.
+
+use bincode::Options;
+use serde::{
+    Deserialize, Deserializer,
+    de::{DeserializeSeed, SeqAccess, Visitor},
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::types::{Row, Value},
+};
+
+/// First byte of every encoded row
+const MAGIC: u8 = 0xFF;
+/// Format version, bumped on incompatible changes
+pub const VERSION: u8 = 1;
+
+const TAG_FALSE: u8 = 0;
+const TAG_TRUE: u8 = 1;
+const TAG_INTEGER: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_POINT: u8 = 5;
+const TAG_UUID: u8 = 6;
+
+/// Encodes a row
+pub fn encode_row(row: &[Value]) -> Vec<u8> {
+    let mut bytes = vec![MAGIC, VERSION];
+    write_varint(&mut bytes, row.len() as u128);
+    let bitmap = bytes.len();
+    bytes.resize(bitmap + row.len().div_ceil(8), 0);
+    for (i, value) in row.iter().enumerate() {
+        match value {
+            Value::Null => bytes[bitmap + i / 8] |= 1 << (i % 8),
+            Value::Boolean(false) => bytes.push(TAG_FALSE),
+            Value::Boolean(true) => bytes.push(TAG_TRUE),
+            Value::Integer(i) => {
+                bytes.push(TAG_INTEGER);
+                write_varint(&mut bytes, ((i << 1) ^ (i >> 127)) as u128);
+            }
+            Value::Float(f) => {
+                bytes.push(TAG_FLOAT);
+                bytes.extend(f.to_le_bytes());
+            }
+            Value::String(s) => {
+                bytes.push(TAG_STRING);
+                write_varint(&mut bytes, s.len() as u128);
+                bytes.extend(s.as_bytes());
+            }
+            Value::Point(x, y) => {
+                bytes.push(TAG_POINT);
+                bytes.extend(x.to_le_bytes());
+                bytes.extend(y.to_le_bytes());
+            }
+            Value::Uuid(uuid) => {
+                bytes.push(TAG_UUID);
+                bytes.extend(uuid);
+            }
+        }
+    }
+    bytes
+}
+
+/// Decodes a row, in either format
+pub fn decode_row(bytes: &[u8]) -> Result<Row> {
+    match is_legacy(bytes) {
+        true => Ok(bincode::deserialize(bytes)?),
+        false => decode(bytes, &[]),
+    }
+}
+
+/// Decodes the wanted columns of a row, leaving the others NULL
+///
+/// Columns past the end of `wanted` are decoded.
+pub fn decode_columns(bytes: &[u8], wanted: &[bool]) -> Result<Row> {
+    match is_legacy(bytes) {
+        true => deserialize_columns(bytes, wanted),
+        false => decode(bytes, wanted),
+    }
+}
+
+/// Whether a row is in the bincode format rows were stored in before
+pub fn is_legacy(bytes: &[u8]) -> bool {
+    // Rows in this format without columns are 3 bytes, bincode ones at least 8
+    bytes.len() >= 8 && !(bytes[0] == MAGIC && bytes[1] == VERSION && bytes[2] != 0)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut n: u128) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+/// Reads an encoded row front to back
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.bytes.len() {
+            return Err(Error::Internal("truncated row".into()));
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("slice of N bytes"))
+    }
+
+    fn varint(&mut self) -> Result<u128> {
+        let mut n = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7F) as u128) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(Error::Internal("varint overflows 128 bits".into()))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.varint()?).map_err(|_| Error::Internal("length out of range".into()))
+    }
+
+    /// Reads the value under a tag, or skips it and returns NULL
+    fn value(&mut self, tag: u8, wanted: bool) -> Result<Value> {
+        let value = match tag {
+            TAG_FALSE => Value::Boolean(false),
+            TAG_TRUE => Value::Boolean(true),
+            TAG_INTEGER => {
+                let n = self.varint()?;
+                Value::Integer((n >> 1) as i128 ^ -((n & 1) as i128))
+            }
+            TAG_FLOAT => Value::Float(f64::from_le_bytes(self.array()?)),
+            TAG_STRING => {
+                let len = self.len()?;
+                let bytes = self.take(len)?;
+                if !wanted {
+                    return Ok(Value::Null);
+                }
+                let s = std::str::from_utf8(bytes)
+                    .map_err(|err| Error::Internal(format!("invalid string in row: {}", err)))?;
+                Value::String(s.into())
+            }
+            TAG_POINT => Value::Point(f64::from_le_bytes(self.array()?), f64::from_le_bytes(self.array()?)),
+            TAG_UUID => Value::Uuid(self.array()?),
+            tag => return Err(Error::Internal(format!("unknown value tag {}", tag))),
+        };
+        Ok(if wanted { value } else { Value::Null })
+    }
+}
+
+fn decode(bytes: &[u8], wanted: &[bool]) -> Result<Row> {
+    let mut reader = Reader { bytes };
+    match reader.array()? {
+        [MAGIC, VERSION] => {}
+        [MAGIC, version] => return Err(Error::Internal(format!("unsupported row format version {}", version))),
+        _ => return Err(Error::Internal("invalid row header".into())),
+    }
+    let count = reader.len()?;
+    let bitmap = reader.take(count.div_ceil(8))?;
+    let mut row = Vec::with_capacity(count);
+    for i in 0..count {
+        if bitmap[i / 8] & (1 << (i % 8)) != 0 {
+            row.push(Value::Null);
+            continue;
+        }
+        let tag = reader.byte()?;
+        row.push(reader.value(tag, wanted.get(i).copied().unwrap_or(true))?);
+    }
+    Ok(row)
+}
+
+/// A stored value read only to be skipped, borrowing its string
+///
+/// Mirrors the variants of `Value`, so it decodes the same bincode.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+enum SkippedValue<'a> {
+    Null,
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    String(&'a str),
+    Point(f64, f64),
+    Uuid([u8; 16]),
+}
+
+/// Decodes the wanted columns of a bincode row, leaving the others NULL
+fn deserialize_columns(bytes: &[u8], wanted: &[bool]) -> Result<Row> {
+    struct Columns<'a>(&'a [bool]);
+
+    impl<'de> DeserializeSeed<'de> for Columns<'_> {
+        type Value = Row;
+
+        fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Row, D::Error> {
+            deserializer.deserialize_seq(self)
+        }
+    }
+
+    impl<'de> Visitor<'de> for Columns<'_> {
+        type Value = Row;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a row")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Row, A::Error> {
+            let mut row = Vec::with_capacity(self.0.len());
+            loop {
+                let value = match self.0.get(row.len()).copied().unwrap_or(true) {
+                    true => seq.next_element::<Value>()?,
+                    false => seq.next_element::<SkippedValue>()?.map(|_| Value::Null),
+                };
+                match value {
+                    Some(value) => row.push(value),
+                    None => return Ok(row),
+                }
+            }
+        }
+    }
+
+    // The options of bincode::deserialize
+    let options = bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes();
+    Ok(options.deserialize_seed(Columns(wanted), bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Row {
+        vec![
+            Value::Integer(1),
+            Value::Null,
+            Value::Boolean(true),
+            Value::Integer(-300),
+            Value::Integer(i128::MIN),
+            Value::Integer(i128::MAX),
+            Value::Float(-1.5),
+            Value::String("héllo".into()),
+            Value::String("".into()),
+            Value::Point(1.0, -2.0),
+            Value::Uuid([7; 16]),
+            Value::Boolean(false),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let row = sample();
+        assert_eq!(decode_row(&encode_row(&row))?, row);
+        assert_eq!(decode_row(&encode_row(&[]))?, Vec::<Value>::new());
+        let nulls = vec![Value::Null; 20];
+        assert_eq!(decode_row(&encode_row(&nulls))?, nulls);
+        assert!(!is_legacy(&encode_row(&row)) && !is_legacy(&encode_row(&[])));
+        Ok(())
+    }
+
+    #[test]
+    fn test_smaller_than_bincode() -> Result<()> {
+        let row = vec![Value::Integer(42), Value::String("abc".into()), Value::Null, Value::Boolean(true)];
+        // Header 3, bitmap 1, integer 2, string 5, boolean 1
+        assert_eq!(encode_row(&row).len(), 12);
+        assert_eq!(bincode::serialize(&row)?.len(), 52);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_columns() -> Result<()> {
+        let row = sample();
+        let mut wanted = vec![false; row.len()];
+        wanted[3] = true;
+        wanted[7] = true;
+        let expect = row
+            .iter()
+            .enumerate()
+            .map(|(i, v)| if wanted[i] { v.clone() } else { Value::Null })
+            .collect::<Vec<_>>();
+        assert_eq!(decode_columns(&encode_row(&row), &wanted)?, expect);
+        assert_eq!(decode_columns(&bincode::serialize(&row)?, &wanted)?, expect);
+        // Columns past the mask are decoded
+        assert_eq!(decode_columns(&encode_row(&row), &[])?, row);
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy() -> Result<()> {
+        let row = sample();
+        let bytes = bincode::serialize(&row)?;
+        assert!(is_legacy(&bytes));
+        assert_eq!(decode_row(&bytes)?, row);
+        assert!(is_legacy(&bincode::serialize(&Vec::<Value>::new())?));
+
+        // Rejects corrupt rows
+        let bytes = encode_row(&row);
+        assert!(decode_row(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_row(&[MAGIC, VERSION + 1, 1, 0, 9]).is_err());
+        assert!(decode_row(&[MAGIC, VERSION, 1, 0, 9]).is_err());
+        Ok(())
+    }
+}
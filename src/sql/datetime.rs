@@ -0,0 +1,279 @@
+//! Timezone-naive dates and times for the date functions
+//!
+//! There are no temporal column types: like in SQLite, dates are text,
+//! `YYYY-MM-DD` for dates and `YYYY-MM-DD HH:MM:SS` for timestamps (a `T`
+//! may separate the two when parsed), which sort and compare correctly as
+//! strings. Integers are read as Unix timestamps in seconds.
+//!
+//! No time zone applies: NOW() is UTC, and every day has 86,400 seconds.
+
+use std::fmt::{self, Display};
+
+use crate::error::{Error, Result};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A date or timestamp, in seconds since 1970-01-01 00:00:00
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    seconds: i64,
+    /// Whether it was given as a date, so prints without a time of day
+    date_only: bool,
+}
+
+impl DateTime {
+    /// Parses a date or timestamp
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || Error::Internal(format!("invalid date {}", s));
+        let number = |range: std::ops::Range<usize>| -> Result<i64> {
+            let digits = s.get(range).filter(|d| d.bytes().all(|b| b.is_ascii_digit())).ok_or_else(invalid)?;
+            digits.parse().map_err(|_| invalid())
+        };
+        let b = s.as_bytes();
+        if !(b.len() == 10 || b.len() == 19) || b[4] != b'-' || b[7] != b'-' {
+            return Err(invalid());
+        }
+        let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        let mut datetime = DateTime { seconds: days_from_civil(year, month, day) * SECONDS_PER_DAY, date_only: true };
+        if b.len() == 19 {
+            if !matches!(b[10], b' ' | b'T') || b[13] != b':' || b[16] != b':' {
+                return Err(invalid());
+            }
+            let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+            if hour > 23 || minute > 59 || second > 59 {
+                return Err(invalid());
+            }
+            datetime.seconds += hour * 3600 + minute * 60 + second;
+            datetime.date_only = false;
+        }
+        Ok(datetime)
+    }
+
+    /// A timestamp of seconds since the Unix epoch
+    pub fn from_unix(seconds: i64) -> Result<Self> {
+        DateTime { seconds, date_only: false }.checked()
+    }
+
+    /// The current time, in UTC
+    pub fn now() -> Result<Self> {
+        Self::from_unix(unix_now())
+    }
+
+    /// Adds a number of units (second, minute, hour, day, week, month or
+    /// year, or their plurals) to the time
+    ///
+    /// Months and years keep the day of the month, clamped to the last day
+    /// of a shorter month. Units below a day make a date a timestamp.
+    pub fn add(self, n: i64, unit: &str) -> Result<Self> {
+        let unit = unit.to_lowercase();
+        let overflow = || Error::Internal("date out of range".into());
+        let seconds = |per_unit: i64| n.checked_mul(per_unit).and_then(|s| self.seconds.checked_add(s)).ok_or_else(overflow);
+        let datetime = match unit.strip_suffix('s').unwrap_or(&unit) {
+            "second" => DateTime { seconds: seconds(1)?, date_only: false },
+            "minute" => DateTime { seconds: seconds(60)?, date_only: false },
+            "hour" => DateTime { seconds: seconds(3600)?, date_only: false },
+            "day" => DateTime { seconds: seconds(SECONDS_PER_DAY)?, ..self },
+            "week" => DateTime { seconds: seconds(7 * SECONDS_PER_DAY)?, ..self },
+            "month" | "year" => {
+                let months = if unit.starts_with("year") { n.checked_mul(12).ok_or_else(overflow)? } else { n };
+                let (year, month, day) = civil_from_days(self.days());
+                let month = (year * 12 + month - 1).checked_add(months).ok_or_else(overflow)?;
+                let (year, month) = (month.div_euclid(12), month.rem_euclid(12) + 1);
+                let day = day.min(days_in_month(year, month));
+                let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY + self.time_of_day();
+                DateTime { seconds, ..self }
+            }
+            _ => return Err(Error::Internal(format!("unknown date unit {}", unit))),
+        };
+        datetime.checked()
+    }
+
+    /// A field of the time: year, month, day, hour, minute, second, dow
+    /// (day of the week, 0 for Sunday), doy (day of the year, from 1) or
+    /// epoch (seconds since 1970)
+    pub fn extract(&self, field: &str) -> Result<i64> {
+        let (year, month, day) = civil_from_days(self.days());
+        let time = self.time_of_day();
+        Ok(match field.to_lowercase().as_str() {
+            "year" => year,
+            "month" => month,
+            "day" => day,
+            "hour" => time / 3600,
+            "minute" => time / 60 % 60,
+            "second" => time % 60,
+            // 1970-01-01 was a Thursday
+            "dow" => (self.days() + 4).rem_euclid(7),
+            "doy" => self.days() - days_from_civil(year, 1, 1) + 1,
+            "epoch" => self.seconds,
+            field => return Err(Error::Internal(format!("unknown date field {}", field))),
+        })
+    }
+
+    /// Formats the time like C's strftime, with %Y, %m, %d, %H, %M, %S, %j
+    /// (day of the year), %w (day of the week), %s (Unix seconds) and %%
+    pub fn format(&self, format: &str) -> Result<String> {
+        let mut output = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+            let (field, width) = match chars.next() {
+                Some('Y') => ("year", 4),
+                Some('m') => ("month", 2),
+                Some('d') => ("day", 2),
+                Some('H') => ("hour", 2),
+                Some('M') => ("minute", 2),
+                Some('S') => ("second", 2),
+                Some('j') => ("doy", 3),
+                Some('w') => ("dow", 1),
+                Some('s') => ("epoch", 1),
+                Some('%') => {
+                    output.push('%');
+                    continue;
+                }
+                Some(c) => return Err(Error::Internal(format!("unknown format specifier %{}", c))),
+                None => return Err(Error::Internal("format ends in %".into())),
+            };
+            output.push_str(&format!("{:0width$}", self.extract(field)?, width = width));
+        }
+        Ok(output)
+    }
+
+    fn days(&self) -> i64 {
+        self.seconds.div_euclid(SECONDS_PER_DAY)
+    }
+
+    fn time_of_day(&self) -> i64 {
+        self.seconds.rem_euclid(SECONDS_PER_DAY)
+    }
+
+    /// Errors for years that don't have four digits
+    fn checked(self) -> Result<Self> {
+        let min = days_from_civil(0, 1, 1) * SECONDS_PER_DAY;
+        let max = days_from_civil(10_000, 1, 1) * SECONDS_PER_DAY;
+        match (min..max).contains(&self.seconds) {
+            true => Ok(self),
+            false => Err(Error::Internal("date out of range".into())),
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.days());
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+        if !self.date_only {
+            let time = self.time_of_day();
+            write!(f, " {:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+///
+/// From Howard Hinnant's `days_from_civil`, counting in 400-year eras.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year.div_euclid(400), year.rem_euclid(400));
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of a number of days since 1970-01-01, the inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let (era, day_of_era) = (days.div_euclid(146_097), days.rem_euclid(146_097));
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn unix_now() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Browsers have no system clock for std, so ask JavaScript
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn unix_now() -> i64 {
+    (js_sys::Date::now() / 1000.0) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_round_trip() {
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert!((1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month));
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    }
+
+    #[test]
+    fn test_parse_and_add() -> Result<()> {
+        let ts = DateTime::parse("2024-01-31 23:59:30")?;
+        assert_eq!(ts, DateTime::parse("2024-01-31T23:59:30")?);
+        assert_eq!(ts.to_string(), "2024-01-31 23:59:30");
+        assert_eq!(ts.add(1, "month")?.to_string(), "2024-02-29 23:59:30");
+        assert_eq!(ts.add(-13, "months")?.to_string(), "2022-12-31 23:59:30");
+        assert_eq!(ts.add(1, "year")?.add(1, "month")?.to_string(), "2025-02-28 23:59:30");
+        assert_eq!(ts.add(45, "seconds")?.to_string(), "2024-02-01 00:00:15");
+
+        let date = DateTime::parse("2024-02-28")?;
+        assert_eq!(date.add(2, "day")?.to_string(), "2024-03-01");
+        assert_eq!(date.add(1, "hour")?.to_string(), "2024-02-28 01:00:00");
+        assert_eq!(DateTime::from_unix(-1)?.to_string(), "1969-12-31 23:59:59");
+
+        for invalid in ["2023-02-29", "2024-13-01", "2024-1-01", "2024-01-01 24:00:00", "2024-01-01 10:00", "x"] {
+            assert!(DateTime::parse(invalid).is_err(), "{}", invalid);
+        }
+        assert!(date.add(8000, "years").is_err());
+        assert!(date.add(i64::MAX, "days").is_err());
+        assert!(date.add(1, "fortnight").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_and_format() -> Result<()> {
+        let ts = DateTime::parse("2024-03-10 07:05:09")?;
+        let fields = ["year", "month", "day", "hour", "minute", "second", "dow", "doy"];
+        let values = fields.iter().map(|f| ts.extract(f)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(values, vec![2024, 3, 10, 7, 5, 9, 0, 70]);
+        assert_eq!(ts.extract("epoch")?, 1_710_054_309);
+        assert_eq!(ts.format("%Y/%m/%d %H:%M:%S, day %j of 100%%, weekday %w")?, "2024/03/10 07:05:09, day 070 of 100%, weekday 0");
+        assert_eq!(ts.format("%s")?, "1710054309");
+        assert!(ts.format("%q").is_err() && ts.format("%").is_err());
+        assert!(ts.extract("week").is_err());
+        Ok(())
+    }
+}
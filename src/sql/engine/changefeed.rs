@@ -0,0 +1,113 @@
+//! Changefeed - change data capture for committed row changes
+//!
+//! Transactions buffer their row changes and publish them on commit, in
+//! commit order, to every subscriber of the affected table. Change hooks
+//! are called with them right after the commit.
+
+use std::sync::{
+    Arc, Mutex, MutexGuard,
+    mpsc::{self, Receiver, Sender},
+};
+
+use crate::{
+    error::Result,
+    sql::types::{Row, Value},
+    storage::mvcc::Version,
+};
+
+/// A committed change to a single row
+///
+/// `old` is None for inserts and `new` is None for deletes. An update that
+/// changes the primary key is published as a delete followed by an insert.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// Version of the transaction that made the change
+    pub version: Version,
+    pub table: String,
+    /// Primary key of the changed row
+    pub pk: Value,
+    pub old: Option<Row>,
+    pub new: Option<Row>,
+}
+
+/// Callback of `Changefeed::on_change`, given a transaction's changes to a table
+pub type ChangeHook = Arc<dyn Fn(&[ChangeEvent]) + Send + Sync>;
+
+enum Subscriber {
+    /// Channel feeding a consumer, until it's dropped
+    Channel(Sender<ChangeEvent>),
+    Hook(ChangeHook),
+}
+
+/// Subscribed table names and their subscribers
+type Subscribers = Vec<(String, Subscriber)>;
+
+/// Registry of change subscribers, shared by all transactions of an engine
+#[derive(Clone, Default)]
+pub struct Changefeed {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl Changefeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes to committed changes of a table
+    pub fn subscribe(&self, table: &str) -> Result<Receiver<ChangeEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock()?.push((table.to_string(), Subscriber::Channel(tx)));
+        Ok(rx)
+    }
+
+    /// Calls `hook` after each commit that changed the table, with the changes
+    ///
+    /// The hook runs on the committing thread once the feed is unlocked, so
+    /// it may use the database, even commit; hooks of concurrent commits can
+    /// run concurrently and out of order (see `ChangeEvent::version`). It
+    /// stays registered for the life of the feed.
+    pub fn on_change(&self, table: &str, hook: ChangeHook) -> Result<()> {
+        self.subscribers.lock()?.push((table.to_string(), Subscriber::Hook(hook)));
+        Ok(())
+    }
+
+    /// Returns whether anyone is listening (transactions skip recording otherwise)
+    pub fn has_subscribers(&self) -> Result<bool> {
+        Ok(!self.subscribers.lock()?.is_empty())
+    }
+
+    /// Locks the feed; holding the guard across a commit keeps events in commit order
+    pub fn lock(&self) -> Result<ChangefeedGuard<'_>> {
+        Ok(ChangefeedGuard {
+            subscribers: self.subscribers.lock()?,
+        })
+    }
+}
+
+/// Exclusive access to the feed while a transaction commits
+pub struct ChangefeedGuard<'a> {
+    subscribers: MutexGuard<'a, Subscribers>,
+}
+
+impl ChangefeedGuard<'_> {
+    /// Delivers events to the channels subscribed to their table, dropping
+    /// closed channels, and returns the hooks to call once the feed is
+    /// unlocked, with their table's events
+    #[must_use]
+    pub fn publish(&mut self, events: &[ChangeEvent]) -> Vec<(ChangeHook, Vec<ChangeEvent>)> {
+        let mut calls = Vec::new();
+        self.subscribers.retain(|(table, subscriber)| {
+            let mut events = events.iter().filter(|e| e.table == *table).peekable();
+            match subscriber {
+                Subscriber::Channel(tx) => events.all(|e| tx.send(e.clone()).is_ok()),
+                Subscriber::Hook(hook) => {
+                    if events.peek().is_some() {
+                        calls.push((hook.clone(), events.cloned().collect()));
+                    }
+                    true
+                }
+            }
+        });
+        calls
+    }
+}
@@ -1,24 +1,71 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, fmt, sync::mpsc::Receiver, thread, time::{Duration, Instant}};
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{Error, Result},
     sql::{
-        parser::ast::{Expression, evaluate_expr}, schema::Table, types::{Row, Value}
+        codec,
+        executor::{audit, batch, view}, parser::ast::Expression, schema::{Collation, Index, Partition, StorageFormat, Table},
+        types::{DataType, Row, Value},
     },
-    storage::{self, engine::Engine as StorageEngine, keycode::serialize_key},
+    storage::{self, engine::{Durability, Engine as StorageEngine}, keycode::{self, serialize_key}},
+};
+
+use super::{
+    Engine, Rows, Transaction,
+    changefeed::{ChangeEvent, Changefeed},
+    resultcache::ResultCache,
+    sort_desc,
+    stats::Stats,
+    system,
+    tenant::{Keyspace, Tenancy, Tenant},
 };
 
-use super::{Engine, Transaction};
+/// Rows `bulk_insert` writes per storage batch
+const BULK_CHUNK_ROWS: usize = 1024;
+
+/// Rows `TableCursor` reads per page
+const SCAN_PAGE_ROWS: usize = 1024;
+
+/// Keys `check` reads per page
+const CHECK_PAGE_KEYS: usize = 1024;
+
+/// Row count deltas of a table past which a commit writing it folds them
+/// into one, so COUNT(*) reads a bounded number of keys
+const ROW_COUNT_DELTAS: usize = 64;
+
+/// Entries per index `check` compares with their rows, spread evenly
+pub const CHECK_INDEX_SAMPLE: usize = 1000;
+
+/// How long CREATE INDEX waits by default for the transactions that began
+/// before it to end
+pub const INDEX_BUILD_WAIT: Duration = Duration::from_secs(30);
 
 /// Key-value store backed SQL engine
 pub struct KVEngine<E: StorageEngine> {
     pub kv: storage::mvcc::Mvcc<E>,
+    /// Subscribers to committed row changes
+    pub changefeed: Changefeed,
+    /// Write conflict and plan cache counters, shown by `SHOW STATS`
+    pub stats: Stats,
+    /// Results of recent queries, if kept (see `resultcache`)
+    pub results: Option<ResultCache>,
+    /// Whose keys the transactions use (see `tenant`)
+    pub tenancy: Tenancy,
+    /// How long an online index build waits for earlier transactions
+    pub index_build_wait: Duration,
 }
 
 impl<E: StorageEngine> Clone for KVEngine<E> {
     fn clone(&self) -> Self {
         Self {
             kv: self.kv.clone(),
+            changefeed: self.changefeed.clone(),
+            stats: self.stats.clone(),
+            results: self.results.clone(),
+            tenancy: self.tenancy.clone(),
+            index_build_wait: self.index_build_wait,
         }
     }
 }
@@ -27,42 +74,412 @@ impl<E: StorageEngine> KVEngine<E> {
     pub fn new(engine: E) -> Self {
         Self {
             kv: storage::mvcc::Mvcc::new(engine),
+            changefeed: Changefeed::new(),
+            stats: Stats::new(),
+            results: None,
+            tenancy: Tenancy::None,
+            index_build_wait: INDEX_BUILD_WAIT,
         }
     }
+
+    /// An engine for the sessions of a tenant, on the same store
+    ///
+    /// Its changefeed, stats and result cache are the tenant's own. Tenants
+    /// sharing the schema keep no results, as schema changes outside them
+    /// wouldn't drop those.
+    pub fn for_tenant(&self, id: &str) -> Result<Self> {
+        let shared_schema = self.tenancy == Tenancy::SharedSchema;
+        Ok(Self {
+            kv: self.kv.clone(),
+            changefeed: Changefeed::new(),
+            stats: Stats::new(),
+            results: self.results.as_ref().filter(|_| !shared_schema).map(ResultCache::like).transpose()?,
+            tenancy: Tenancy::Tenant(Tenant { id: id.to_string(), shared_schema }),
+            index_build_wait: self.index_build_wait,
+        })
+    }
+
+    /// Keeps the results of up to `capacity` queries for the sessions to
+    /// share (see `resultcache`)
+    pub fn with_result_cache(mut self, capacity: usize) -> Self {
+        self.results = Some(ResultCache::new(capacity));
+        self
+    }
+
+    /// Sets how long CREATE INDEX waits for the transactions that began
+    /// before it to end, failing after (`INDEX_BUILD_WAIT` by default)
+    pub fn with_index_build_wait(mut self, wait: Duration) -> Self {
+        self.index_build_wait = wait;
+        self
+    }
+
+    /// Begins a transaction of the SQL engine on an MVCC transaction
+    fn transaction(&self, txn: storage::mvcc::MvccTransaction<E>) -> Result<KVTransaction<E>> {
+        let txn = KVTransaction::new(txn)
+            .with_tenancy(self.tenancy.clone())?
+            .with_changefeed(self.changefeed.clone())
+            .with_stats(self.stats.clone())
+            .with_store(self.kv.clone());
+        Ok(match &self.results {
+            Some(results) => txn.with_result_cache(results.clone()),
+            None => txn,
+        })
+    }
 }
 
 impl<E: StorageEngine> Engine for KVEngine<E> {
     type Transaction = KVTransaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin()?))
+        self.transaction(self.kv.begin()?)
+    }
+
+    fn begin_serializable(&self) -> Result<Self::Transaction> {
+        self.transaction(self.kv.begin_serializable()?)
+    }
+
+    fn begin_as_of(&self, version: u64) -> Result<Self::Transaction> {
+        Ok(Self::Transaction::new(self.kv.begin_as_of(version)?)
+            .with_tenancy(self.tenancy.clone())?
+            .with_stats(self.stats.clone()))
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    fn result_cache(&self) -> Option<ResultCache> {
+        self.results.clone()
+    }
+
+    /// Builds the index online, in steps of their own short transactions:
+    ///
+    /// 1. The index is added to the schema as building, so writers from then
+    ///    on keep its entries, while queries ignore it. Transactions without
+    ///    the changefeed can't write the table, as their changes go unlogged.
+    /// 2. Once the writers that began earlier are done, the rows of a
+    ///    snapshot are indexed, a chunk per transaction. If they're still
+    ///    running after `index_build_wait`, the build fails.
+    /// 3. The rows changed since, which the changefeed logged, are indexed
+    ///    again as they are now, and the index is marked built.
+    ///
+    /// If a step fails, the index is dropped again. With tenants sharing the
+    /// schema it's built in one transaction instead, indexing their rows too.
+    fn build_index(&self, table_name: &str, mut index: Index) -> Result<()> {
+        if self.tenancy == Tenancy::SharedSchema {
+            let mut txn = self.begin()?;
+            return match txn.create_index(table_name, index) {
+                Ok(()) => txn.commit(),
+                Err(err) => txn.rollback().and(Err(err)),
+            };
+        }
+        let changes = self.changefeed.subscribe(table_name)?;
+        index.building = true;
+        let mut txn = self.begin()?;
+        match txn.add_index(table_name, index.clone()) {
+            Ok(_) => txn.commit()?,
+            Err(err) => return txn.rollback().and(Err(err)),
+        }
+        let result = self.backfill_index(table_name, &index.name, &changes);
+        if result.is_err() {
+            self.drop_building_index(table_name, &index.name)?;
+        }
+        result
+    }
+}
+
+impl<E: StorageEngine> KVEngine<E> {
+    /// Writes the entries of an index in the building state, then marks it built
+    fn backfill_index(&self, table_name: &str, index_name: &str, changes: &Receiver<ChangeEvent>) -> Result<()> {
+        // Transactions that began before the index was added don't keep its entries
+        let earlier = self.kv.active_transactions()?.into_iter().map(|(version, _)| version).collect::<HashSet<_>>();
+        let deadline = Instant::now() + self.index_build_wait;
+        loop {
+            let mut waiting = self.kv.active_transactions()?.into_iter().map(|(version, _)| version).collect::<Vec<_>>();
+            waiting.retain(|version| earlier.contains(version));
+            if waiting.is_empty() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                let versions = waiting.iter().map(|version| version.to_string()).collect::<Vec<_>>();
+                return Err(Error::Internal(format!(
+                    "timed out building index {}, waiting for transactions {} that began before it to end",
+                    index_name,
+                    versions.join(", ")
+                )));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let snapshot = self.transaction(self.kv.begin_read_only()?)?;
+        let contended = self.index_snapshot(&snapshot, table_name, index_name);
+        snapshot.rollback()?;
+        let mut touched: HashMap<Value, Vec<Row>> = HashMap::new();
+        for (pk, row) in contended? {
+            touched.entry(pk).or_default().push(row);
+        }
+
+        // With the feed locked no commit is half published, so every change
+        // this transaction sees is already logged
+        let mut txn = {
+            let _feed = self.changefeed.lock()?;
+            self.begin()?
+        };
+        for event in changes.try_iter() {
+            touched.entry(event.pk).or_default().extend(event.old.into_iter().chain(event.new));
+        }
+        match txn.reindex_rows(table_name, index_name, &touched) {
+            Ok(()) => txn.commit(),
+            Err(err) => txn.rollback().and(Err(err)),
+        }
+    }
+
+    /// Writes index entries for the rows of a snapshot, a chunk per transaction,
+    /// returning the rows whose entries a concurrent writer held
+    fn index_snapshot(
+        &self,
+        snapshot: &KVTransaction<E>,
+        table_name: &str,
+        index_name: &str,
+    ) -> Result<Vec<(Value, Row)>> {
+        let table = snapshot.must_get_table(table_name.to_string())?;
+        let index = table.index(index_name)?;
+        let mut contended = Vec::new();
+        let mut rows = snapshot.scan_table(table.name.clone(), None)?;
+        loop {
+            let chunk = rows.by_ref().take(BULK_CHUNK_ROWS).collect::<Result<Vec<_>>>()?;
+            if chunk.is_empty() {
+                return Ok(contended);
+            }
+            let mut writes = Vec::new();
+            for row in chunk {
+                if KVTransaction::<E>::indexed(&table, index, &row)? {
+                    let pk = table.get_primary_key(&row)?;
+                    writes.push((KVTransaction::<E>::index_entry(&table, index, &pk, &row)?, pk, row));
+                }
+            }
+            let txn = self.transaction(self.kv.begin()?)?.txn;
+            for ((key, value), pk, row) in writes {
+                match txn.set(key, value) {
+                    Ok(()) => {}
+                    Err(Error::WriteConflict { .. }) => contended.push((pk, row)),
+                    Err(err) => return txn.rollback().and(Err(err)),
+                }
+            }
+            txn.commit()?;
+        }
+    }
+
+    /// Removes an index left building by a failed build, with its entries
+    fn drop_building_index(&self, table_name: &str, index_name: &str) -> Result<()> {
+        let mut txn = self.begin()?;
+        let mut table = txn.must_get_table(table_name.to_string())?;
+        table.indexes.retain(|index| index.name != index_name || !index.building);
+        txn.save_table(&table)?;
+        let prefix = KeyPrefix::Index(table_name.to_string(), index_name.to_string()).encode()?;
+        for result in txn.txn.scan_prefix(prefix)? {
+            txn.txn.delete(result.key)?;
+        }
+        txn.commit()
     }
 }
 
 /// Key-value transaction (wrapper around MVCC transaction)
 pub struct KVTransaction<E: StorageEngine> {
-    txn: storage::mvcc::MvccTransaction<E>,
+    txn: Keyspace<E>,
+    /// Whose keys the transaction uses
+    tenancy: Tenancy,
+    /// Feed receiving this transaction's row changes on commit
+    changefeed: Option<Changefeed>,
+    /// Row changes buffered until commit
+    changes: Vec<ChangeEvent>,
+    /// Counters this transaction's write conflicts add to
+    stats: Stats,
+    /// Table schemas already read by this transaction, by name. Schemas only
+    /// change through the transaction's own DDL, which updates the cache.
+    tables: RefCell<HashMap<String, Table>>,
+    /// Cache whose results read from the tables this transaction writes
+    /// are dropped on commit
+    results: Option<ResultCache>,
+    /// Tables whose rows or schema this transaction wrote
+    written: RefCell<HashSet<String>>,
+    /// Store the transaction runs on, which row count deltas are folded in
+    /// after its commit (see `fold_row_count`)
+    store: Option<storage::mvcc::Mvcc<E>>,
+    /// Tables whose row count this transaction changed
+    counted: RefCell<HashSet<String>>,
 }
 
 impl<E: StorageEngine> KVTransaction<E> {
     pub fn new(txn: storage::mvcc::MvccTransaction<E>) -> Self {
-        Self { txn }
+        Self {
+            txn: Keyspace::new(txn),
+            tenancy: Tenancy::None,
+            changefeed: None,
+            changes: Vec::new(),
+            stats: Stats::new(),
+            tables: RefCell::new(HashMap::new()),
+            results: None,
+            written: RefCell::new(HashSet::new()),
+            store: None,
+            counted: RefCell::new(HashSet::new()),
+        }
     }
-}
 
-impl<E: StorageEngine> Transaction for KVTransaction<E> {
-    fn commit(&self) -> Result<()> {
-        self.txn.commit()
+    /// Uses the keys of a tenant, or those outside tenants (see `tenant`)
+    pub fn with_tenancy(mut self, tenancy: Tenancy) -> Result<Self> {
+        if let Tenancy::Tenant(tenant) = &tenancy {
+            let prefix = KeyPrefix::TenantKeys(tenant.id.clone()).encode()?;
+            let shared = match tenant.shared_schema {
+                true => Some(KeyPrefix::Table.encode()?),
+                false => None,
+            };
+            self.txn = Keyspace::new(self.txn.mvcc().clone()).with_prefix(prefix, shared);
+        }
+        self.tenancy = tenancy;
+        Ok(self)
     }
 
-    fn rollback(&self) -> Result<()> {
-        self.txn.rollback()
+    /// Publishes row changes to the feed when the transaction commits
+    pub fn with_changefeed(mut self, changefeed: Changefeed) -> Self {
+        self.changefeed = Some(changefeed);
+        self
     }
 
-    fn create_row(&mut self, table_name: String, row: Row) -> Result<()> {
-        let table = self.must_get_table(table_name.clone())?;
+    /// Counts write conflicts in the engine's stats, instead of the transaction's own
+    pub fn with_stats(mut self, stats: Stats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Drops the cached results of queries reading the tables this
+    /// transaction writes when it commits
+    pub fn with_result_cache(mut self, results: ResultCache) -> Self {
+        self.results = Some(results);
+        self
+    }
+
+    /// Has the commit of the underlying transaction, made outside the SQL
+    /// layer (see `Database::execute_in`), drop the cached results of the
+    /// tables written so far
+    pub fn drop_results_on_commit(&self) -> Result<()> {
+        let Some(results) = self.results.clone().filter(|_| !self.written.borrow().is_empty()) else {
+            return Ok(());
+        };
+        let tables = self.written.borrow().clone();
+        self.txn.mvcc().on_commit(move || results.commit(&tables, || Ok(())))
+    }
+
+    /// Folds the row count deltas of the tables it wrote into one after
+    /// committing, once there are more than `ROW_COUNT_DELTAS`
+    pub fn with_store(mut self, store: storage::mvcc::Mvcc<E>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Commits, folding the row count deltas of the tables written if due
+    fn fold_commit(&self) -> Result<()> {
+        // Serializable scans would lock the deltas, conflicting with writers
+        let mut fold = Vec::new();
+        if self.store.is_some() && !self.txn.mvcc().is_serializable() {
+            for table in self.counted.borrow().iter() {
+                let prefix = KeyPrefix::RowCount(table.clone()).encode()?;
+                if self.txn.scan_prefix_limit(prefix, ROW_COUNT_DELTAS + 1, |_, _| Ok(true))?.len() > ROW_COUNT_DELTAS {
+                    fold.push(table.clone());
+                }
+            }
+        }
+        match &self.results {
+            Some(results) if !self.written.borrow().is_empty() => {
+                results.commit(&self.written.borrow(), || self.publish_commit())?
+            }
+            _ => self.publish_commit()?,
+        }
+        if let Some(store) = &self.store {
+            for table in fold {
+                // Another commit folding them at once wins; later ones retry
+                match self.fold_row_count(store, &table) {
+                    Ok(()) | Err(Error::WriteConflict { .. }) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces a table's committed row count deltas with their sum, in a
+    /// transaction of its own
+    ///
+    /// Writers only add their own delta keys, so the fold conflicts with
+    /// nothing but another fold of the table.
+    fn fold_row_count(&self, store: &storage::mvcc::Mvcc<E>, table: &str) -> Result<()> {
+        let txn = KVTransaction::new(store.begin()?).with_tenancy(self.tenancy.clone())?;
+        let fold = || -> Result<()> {
+            let mut count = 0;
+            for result in txn.txn.scan_prefix(KeyPrefix::RowCount(table.to_string()).encode()?)? {
+                count += bincode::deserialize::<i64>(&result.value)?;
+                txn.txn.delete(result.key)?;
+            }
+            txn.txn.set(Key::RowCount(table.to_string(), txn.txn.version()).encode()?, bincode::serialize(&count)?)
+        };
+        match fold() {
+            Ok(()) => txn.txn.commit(),
+            Err(err) => txn.txn.rollback().and(Err(err)),
+        }
+    }
+
+    /// Commits, publishing the row changes to the feed
+    fn publish_commit(&self) -> Result<()> {
+        match &self.changefeed {
+            Some(feed) if !self.changes.is_empty() => {
+                // Hold the feed across the commit so events arrive in commit order
+                let calls = {
+                    let mut feed = feed.lock()?;
+                    self.txn.commit()?;
+                    feed.publish(&self.changes)
+                };
+                for (hook, events) in calls {
+                    hook(&events);
+                }
+                Ok(())
+            }
+            _ => self.txn.commit(),
+        }
+    }
+
+    /// Notes a table whose rows or schema the transaction writes
+    fn wrote(&self, table: &str) {
+        if self.results.is_some() && !self.written.borrow().contains(table) {
+            self.written.borrow_mut().insert(table.to_string());
+        }
+    }
+
+    /// Whether row changes need to be recorded (only when someone subscribed)
+    fn recording(&self) -> Result<bool> {
+        match &self.changefeed {
+            Some(feed) => feed.has_subscribers(),
+            None => Ok(false),
+        }
+    }
+
+    fn record(&mut self, table: &str, pk: Value, old: Option<Row>, new: Option<Row>) {
+        self.changes.push(ChangeEvent {
+            version: self.txn.version(),
+            table: table.to_string(),
+            pk,
+            old,
+            new,
+        });
+    }
 
+    /// Checks a row against the table's column types, nullability and ranges
+    fn check_row(table: &Table, row: &mut Row) -> Result<()> {
         for (i, col) in table.columns.iter().enumerate() {
+            // UUID literals are written as strings
+            if let (Value::String(s), DataType::Uuid) = (&row[i], &col.datatype) {
+                row[i] = Value::parse_uuid(s)
+                    .ok_or(Error::Internal(format!("invalid uuid {} for column {}", s, col.name)))?;
+            }
             match row[i].datatype() {
                 None if col.nullable => {}
                 None => {
@@ -71,7 +488,13 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
                         col.name
                     )))
                 }
-                Some(dt) if dt != col.datatype => {
+                Some(DataType::Integer) if !col.datatype.contains(&row[i]) && col.datatype.is_integer() => {
+                    return Err(Error::Internal(format!(
+                        "value {} out of range for column {}",
+                        row[i], col.name
+                    )))
+                }
+                Some(_) if !col.datatype.contains(&row[i]) => {
                     return Err(Error::Internal(format!(
                         "column {} type mismatch",
                         col.name
@@ -80,210 +503,1337 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
                 _ => {}
             }
         }
+        Ok(())
+    }
 
-        let pk = table.get_primary_key(&row)?;
-        let id = Key::Row(table_name.clone(), pk.clone()).encode()?;
-        if self.txn.get(id.clone())?.is_some() {
-            return Err(Error::Internal(format!(
-                "Duplicate data for primary key {} in table {}",
-                pk, table_name
-            )));
+    /// Encodes the storage key of a row, routing partitioned tables to their shard
+    ///
+    /// Collated primary keys are stored under their collation key, so e.g.
+    /// 'Foo' and 'foo' collide under nocase.
+    fn row_key(table: &Table, id: &Value) -> Result<Vec<u8>> {
+        let id = &Self::key_id(table, id);
+        match table.shard_of(id)? {
+            Some(shard) => Key::ShardRow(shard, table.name.clone(), id.clone()).encode(),
+            None => Key::Row(table.name.clone(), id.clone()).encode(),
         }
+    }
 
-        let value = bincode::serialize(&row)?;
-        self.txn.set(id, value)?;
-
-        Ok(())
+    /// Encodes the storage key of one column value of a columnar table row
+    fn column_key(table: &Table, column: usize, id: &Value) -> Result<Vec<u8>> {
+        Key::Column(column as u64, table.name.clone(), Self::key_id(table, id)).encode()
     }
 
-    /// Updates a row - if primary key changes, delete old data and insert new
-    fn update_row(&mut self, table: &Table, id: &Value, row: Row) -> Result<()> {
-        let new_pk = table.get_primary_key(&row)?;
-        if *id != new_pk {
-            let oldKey = Key::Row(table.name.clone(), id.clone()).encode()?;
-            self.txn.delete(oldKey)?;
-        }
-        let key = Key::Row(table.name.clone(), new_pk.clone()).encode()?;
-        let value = bincode::serialize(&row)?;
-        self.txn.set(key, value)?;
+    /// The primary key as stored, folded by its collation
+    fn key_id(table: &Table, id: &Value) -> Value {
+        let collation = table.columns.iter().find(|c| c.primary_key).map(|c| c.collation);
+        collation.unwrap_or_default().fold(id)
+    }
 
-        Ok(())
+    fn pk_index(table: &Table) -> usize {
+        table.columns.iter().position(|c| c.primary_key).unwrap_or(0)
     }
 
-    /// Deletes a row by primary key
-    fn delete_row(&mut self, table: &Table, id: &Value) -> Result<()> {
-        let key = Key::Row(table.name.clone(), id.clone()).encode()?;
-        self.txn.delete(key)
+    /// Whether a row with the primary key exists
+    fn row_exists(&self, table: &Table, id: &Value) -> Result<bool> {
+        let key = match table.storage {
+            StorageFormat::Row => Self::row_key(table, id)?,
+            // Every row has a value in every column
+            StorageFormat::Columnar => Self::column_key(table, Self::pk_index(table), id)?,
+        };
+        Ok(self.txn.get(key)?.is_some())
     }
 
-    fn scan_table(
-        &self,
-        table_name: String,
-        filter: Option<Expression>,
-    ) -> Result<Vec<Row>> {
-        let prefix = KeyPrefix::Row(table_name.clone()).encode()?;
-        let table = self.must_get_table(table_name)?;
-        let results = self.txn.scan_prefix(prefix)?;
-
-        let mut rows = Vec::new();
-        for result in results {
-            let row: Row = bincode::deserialize(&result.value)?;
-            if let Some(expr) = &filter {
-                let cols = table.columns.iter().map(|c| c.name.clone()).collect();
-                // When lcols = rcols, both sides reference the same table (single table scan)
-                // This reuses the same evaluate_expr function used for JOIN execution
-                match evaluate_expr(expr, &cols, &row, &cols, &row)? {
-                    Value::Null => {}
-                    Value::Boolean(false) => {}
-                    Value::Boolean(true) => {
-                        rows.push(row);
-                    }
-                    _ => return Err(Error::Internal("Unexpected expression".into())),
+    /// Stores a row under its primary key
+    fn write_row(&self, table: &Table, id: &Value, row: &Row) -> Result<()> {
+        self.wrote(&table.name);
+        let result = match table.storage {
+            StorageFormat::Row => self.txn.set(Self::row_key(table, id)?, codec::encode_row(row)),
+            StorageFormat::Columnar => {
+                for (i, value) in row.iter().enumerate() {
+                    self.txn.set(Self::column_key(table, i, id)?, bincode::serialize(value)?)?;
                 }
-            } else {
-                // No filter means select all rows
-                rows.push(row);
+                Ok(())
             }
-        }
-        Ok(rows)
+        };
+        result.map_err(|err| self.row_conflict(table, id, err))
     }
 
-    fn create_table(&mut self, table: Table) -> Result<()> {
-        if self.get_table(table.name.clone())?.is_some() {
-            return Err(Error::Internal(format!(
-                "table {} already exists",
-                table.name
-            )));
+    /// The storage keys of a row: its row key, or for columnar tables one per column
+    fn row_keys(table: &Table, id: &Value) -> Result<Vec<Vec<u8>>> {
+        match table.storage {
+            StorageFormat::Row => Ok(vec![Self::row_key(table, id)?]),
+            StorageFormat::Columnar => (0..table.columns.len()).map(|i| Self::column_key(table, i, id)).collect(),
         }
+    }
 
-        table.validate()?;
-
-        let key = Key::Table(table.name.clone()).encode()?;
-        let value = bincode::serialize(&table)?;
-        self.txn.set(key, value)?;
-
-        Ok(())
+    /// The keys and values `write_row` stores a row as
+    fn row_writes(table: &Table, id: &Value, row: &Row) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let values = match table.storage {
+            StorageFormat::Row => vec![codec::encode_row(row)],
+            StorageFormat::Columnar => {
+                row.iter().map(|value| Ok(bincode::serialize(value)?)).collect::<Result<Vec<_>>>()?
+            }
+        };
+        Ok(Self::row_keys(table, id)?.into_iter().zip(values).collect())
     }
 
-    fn get_table(&self, table_name: String) -> Result<Option<Table>> {
-        let key = Key::Table(table_name).encode()?;
-        Ok(self
-            .txn
-            .get(key)?
-            .map(|v| bincode::deserialize(&v))
-            .transpose()?)
+    /// Removes the row stored under a primary key
+    fn remove_row(&self, table: &Table, id: &Value) -> Result<()> {
+        self.wrote(&table.name);
+        let result = match table.storage {
+            StorageFormat::Row => self.txn.delete(Self::row_key(table, id)?),
+            StorageFormat::Columnar => {
+                for i in 0..table.columns.len() {
+                    self.txn.delete(Self::column_key(table, i, id)?)?;
+                }
+                Ok(())
+            }
+        };
+        result.map_err(|err| self.row_conflict(table, id, err))
     }
-}
 
-/// Key types for KV storage operations
-#[derive(Debug, Serialize, Deserialize)]
-enum Key {
-    /// Table schema key (table name)
-    Table(String),
-    /// Row data key (table name + primary key value)
-    Row(String, Value),
-}
+    /// The key and value of a row's entry in an index
+    ///
+    /// The key holds the indexed values folded by their collations, then the
+    /// stored primary key; the value holds the values as written, then the
+    /// primary key.
+    fn index_entry(table: &Table, index: &Index, id: &Value, row: &Row) -> Result<(Vec<u8>, Vec<u8>)> {
+        let positions = table.index_columns(index)?;
+        let values = positions.iter().map(|&i| table.columns[i].collation.fold(&row[i])).collect();
+        let key = Key::Index(table.name.clone(), index.name.clone(), values, Self::key_id(table, id)).encode()?;
+        let mut entry = positions.iter().map(|&i| row[i].clone()).collect::<Vec<_>>();
+        entry.push(id.clone());
+        Ok((key, codec::encode_row(&entry)))
+    }
 
-// Use custom serialization for prefix matching support with variable-length strings
-impl Key {
-    pub fn encode(&self) -> Result<Vec<u8>> {
-        serialize_key(self)
+    /// Whether a row has an entry in an index: partial indexes only hold the
+    /// rows matching their predicate
+    fn indexed(table: &Table, index: &Index, row: &Row) -> Result<bool> {
+        let Some(predicate) = &index.predicate else {
+            return Ok(true);
+        };
+        let names = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        Ok(!batch::filter(vec![row.clone()], &names, &table.collate_filter(predicate.clone()))?.is_empty())
     }
-}
 
-/// Key prefix types for prefix scanning
-///
-/// In bincode, enums are serialized as [variant_index][variant_data...].
-/// Variant indices start from 0 in definition order.
-#[derive(Debug, Serialize, Deserialize)]
-enum KeyPrefix {
-    Table,
-    Row(String),
-}
+    /// The keys and values of a row's entries in the table's indexes
+    fn index_writes(table: &Table, id: &Value, row: &Row) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut writes = Vec::with_capacity(table.indexes.len());
+        for index in &table.indexes {
+            if Self::indexed(table, index, row)? {
+                writes.push(Self::index_entry(table, index, id, row)?);
+            }
+        }
+        Ok(writes)
+    }
 
-impl KeyPrefix {
-    pub fn encode(&self) -> Result<Vec<u8>> {
-        serialize_key(self)
+    /// Adds a row's index entries
+    fn write_indexes(&self, table: &Table, id: &Value, row: &Row) -> Result<()> {
+        for (key, value) in Self::index_writes(table, id, row)? {
+            self.txn.set(key, value).map_err(|err| self.row_conflict(table, id, err))?;
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Removes a row's index entries
+    fn remove_indexes(&self, table: &Table, id: &Value, row: &Row) -> Result<()> {
+        for (key, _) in Self::index_writes(table, id, row)? {
+            self.txn.delete(key).map_err(|err| self.row_conflict(table, id, err))?;
+        }
+        Ok(())
+    }
 
-    use super::KVEngine;
-    use crate::storage::engine::Engine as StorageEngine;
-    use crate::{
-        error::Result,
-        sql::{
-            engine::{Engine, Session},
-            executor::ResultSet,
-            types::{Row, Value},
-        },
-        storage::memory::MemoryEngine,
-    };
+    /// Errors if the table has an index being built and the transaction has
+    /// no changefeed, since the build would miss its changes
+    fn check_published(&self, table: &Table) -> Result<()> {
+        match table.indexes.iter().find(|index| index.building) {
+            Some(index) if self.changefeed.is_none() => Err(Error::Internal(format!(
+                "can't write table {} outside a session while index {} is being built",
+                table.name, index.name
+            ))),
+            _ => Ok(()),
+        }
+    }
 
-    fn setup_table<E: StorageEngine + 'static>(s: &mut Session<KVEngine<E>>) -> Result<()> {
-        s.execute(
-            "create table t1 (
-                     a int primary key,
-                     b text default 'vv',
-                     c integer default 100
-                 );",
-        )?;
+    /// Adds an index to a table's schema, without writing any entries
+    fn add_index(&mut self, table_name: &str, index: Index) -> Result<Table> {
+        let mut table = self.must_get_table(table_name.to_string())?;
+        system::check_writable(&table)?;
+        table.indexes.push(index);
+        table.validate()?;
+        self.save_table(&table)?;
+        Ok(table)
+    }
 
-        s.execute(
-            "create table t2 (
-                     a int primary key,
-                     b integer default 100,
-                     c float default 1.1,
-                     d bool default false,
-                     e boolean default true,
-                     f text default 'v1',
-                     g string default 'v2',
-                     h varchar default 'v3'
-                 );",
-        )?;
+    /// Tables depending on a table, which can only be dropped along with them
+    fn dependents(&self, table: &Table) -> Result<Vec<Table>> {
+        let mut dependents = Vec::new();
+        // Indexes are part of the table, but an audit table outlives rows it logs
+        if table.audit
+            && let Some(audit) = self.get_table(audit::table_name(&table.name))?
+        {
+            dependents.push(audit);
+        }
+        for name in &table.readers {
+            dependents.push(self.must_get_table(name.clone())?);
+        }
+        // Their own dependents go with them, e.g. views reading a view
+        let mut all: Vec<Table> = Vec::new();
+        for dependent in dependents {
+            let indirect = self.dependents(&dependent)?;
+            for table in [dependent].into_iter().chain(indirect) {
+                if !all.iter().any(|t| t.name == table.name) {
+                    all.push(table);
+                }
+            }
+        }
+        Ok(all)
+    }
 
-        s.execute(
-            "create table t3 (
-                     a int primary key,
-                     b int default 12 null,
-                     c integer default NULL,
-                     d float not NULL
-                 );",
-        )?;
+    /// Deletes a table's schema and every key holding its data
+    fn remove_table(&mut self, table: &Table) -> Result<()> {
+        self.check_schema_writable()?;
+        self.remove_data(table)?;
+        for tenant in self.tenants()? {
+            tenant.remove_data(table)?;
+        }
+        self.txn.delete(Key::Table(table.name.clone()).encode()?)?;
+        self.tables.borrow_mut().remove(&table.name);
+        self.wrote(&table.name);
+        Ok(())
+    }
 
-        s.execute(
-            "create table t4 (
-                     a bool primary key,
-                     b int default 12,
-                     d boolean default true
-                 );",
-        )?;
+    /// Deletes every key holding a table's data: rows, row counts and index entries
+    fn remove_data(&self, table: &Table) -> Result<()> {
+        let mut prefixes = match table.partition {
+            Some(Partition::Hash { partitions }) => (0..partitions)
+                .map(|shard| KeyPrefix::ShardRow(shard, table.name.clone()).encode())
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![KeyPrefix::Row(table.name.clone()).encode()?],
+        };
+        if table.storage == StorageFormat::Columnar {
+            for i in 0..table.columns.len() {
+                prefixes.push(KeyPrefix::Column(i as u64, table.name.clone()).encode()?);
+            }
+        }
+        prefixes.push(KeyPrefix::RowCount(table.name.clone()).encode()?);
+        for index in &table.indexes {
+            prefixes.push(KeyPrefix::Index(table.name.clone(), index.name.clone()).encode()?);
+        }
+        for prefix in prefixes {
+            for result in self.txn.scan_prefix(prefix)? {
+                self.txn.delete(result.key)?;
+            }
+        }
         Ok(())
     }
 
-    fn scan_table_and_compare<E: StorageEngine + 'static>(
-        s: &mut Session<KVEngine<E>>,
-        table_name: &str,
-        expect: Vec<Row>,
-    ) -> Result<()> {
-        match s.execute(&format!("select * from {};", table_name))? {
-            ResultSet::Scan { columns: _, rows } => {
-                assert_eq!(rows, expect);
+    /// Writes the entries of an index for the rows of its table
+    fn index_rows(&self, table: &Table, index: &Index) -> Result<()> {
+        for row in self.scan_table(table.name.clone(), None)? {
+            let row = row?;
+            if !Self::indexed(table, index, &row)? {
+                continue;
             }
-            _ => unreachable!(),
+            let pk = table.get_primary_key(&row)?;
+            let (key, value) = Self::index_entry(table, index, &pk, &row)?;
+            self.txn.set(key, value).map_err(|err| self.row_conflict(table, &pk, err))?;
         }
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Fails for a tenant sharing the database's schema, which only changes
+    /// outside tenants
+    fn check_schema_writable(&self) -> Result<()> {
+        match &self.tenancy {
+            Tenancy::Tenant(Tenant { id, shared_schema: true }) => Err(Error::Internal(format!(
+                "tenant {} shares the database's schema, which changes outside tenants only",
+                id
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a tenant sharing the database's schema, so schema changes
+    /// cover its rows as well
+    pub fn add_tenant(&self, id: &str) -> Result<()> {
+        let key = Key::Tenant(id.to_string()).encode()?;
+        if self.txn.get(key.clone())?.is_none() {
+            self.txn.set(Key::Tenant(String::new()).encode()?, vec![])?;
+            self.txn.set(key, vec![])?;
+        }
+        Ok(())
+    }
+
+    /// The transaction's view of each tenant sharing the schema, none
+    /// unless they do
+    ///
+    /// Writes `Key::Tenant("")` like `add_tenant`, so a tenant added
+    /// concurrently fails one of the two with a write conflict rather than
+    /// go unseen.
+    fn tenants(&self) -> Result<Vec<Self>> {
+        if self.tenancy != Tenancy::SharedSchema {
+            return Ok(Vec::new());
+        }
+        self.txn.set(Key::Tenant(String::new()).encode()?, vec![])?;
+        let mut tenants = Vec::new();
+        for result in self.txn.scan_prefix(KeyPrefix::Tenant.encode()?)? {
+            let Key::Tenant(id) = keycode::deserialize_key(&result.key)? else {
+                return Err(Error::Internal("unexpected key in the tenant registry".into()));
+            };
+            if !id.is_empty() {
+                let tenancy = Tenancy::Tenant(Tenant { id, shared_schema: true });
+                tenants.push(Self::new(self.txn.mvcc().clone()).with_tenancy(tenancy)?);
+            }
+        }
+        Ok(tenants)
+    }
+
+    /// Writes a changed schema, keeping the cache in step
+    fn save_table(&mut self, table: &Table) -> Result<()> {
+        self.check_schema_writable()?;
+        self.txn.set(Key::Table(table.name.clone()).encode()?, bincode::serialize(table)?)?;
+        self.tables.borrow_mut().insert(table.name.clone(), table.clone());
+        self.wrote(&table.name);
+        Ok(())
+    }
+
+    /// Rewrites rows and index entries stored as bincode in the compact
+    /// encoding, returning the number of rows rewritten
+    ///
+    /// Columnar tables store values one by one and are left as they are.
+    pub fn migrate_rows(&self) -> Result<usize> {
+        let mut count = 0;
+        for name in self.get_table_names()? {
+            let table = self.must_get_table(name)?;
+            let mut prefixes = Vec::new();
+            if table.storage == StorageFormat::Row {
+                match table.partition {
+                    Some(Partition::Hash { partitions }) => {
+                        for shard in 0..partitions {
+                            prefixes.push((true, KeyPrefix::ShardRow(shard, table.name.clone()).encode()?));
+                        }
+                    }
+                    None => prefixes.push((true, KeyPrefix::Row(table.name.clone()).encode()?)),
+                }
+            }
+            for index in &table.indexes {
+                prefixes.push((false, KeyPrefix::Index(table.name.clone(), index.name.clone()).encode()?));
+            }
+            for (rows, prefix) in prefixes {
+                for result in self.txn.scan_prefix(prefix)? {
+                    if codec::is_legacy(&result.value) {
+                        self.txn.set(result.key, codec::encode_row(&codec::decode_row(&result.value)?))?;
+                        count += rows as usize;
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Checks that stored data matches the catalog: every row, row count and
+    /// index key belongs to a table (and index) with a schema, and a sample
+    /// of each index's entries point at rows that still hold the indexed
+    /// values. With `repair` the keys found wrong are deleted.
+    ///
+    /// Indexes still building are skipped, their entries catch up at the end.
+    pub fn check(&self, repair: bool) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        let mut wrong = Vec::new();
+        // The data keys: every `Key` variant after `Table`
+        for tag in 1..=5u8 {
+            let prefix = vec![tag];
+            let mut after = prefix.clone();
+            // Index being sampled, its entries so far, and every how many one is checked
+            let (mut sampled, mut seen, mut stride) = (String::new(), 0, 1);
+            loop {
+                let page = self.txn.scan_prefix_after(prefix.clone(), after, CHECK_PAGE_KEYS)?;
+                let Some(last) = page.last() else { break };
+                after = last.key.clone();
+                for result in page {
+                    let (table_name, index_name) = key_owner(&result.key)?;
+                    let table = self.get_table(table_name.clone())?;
+                    let Some(index_name) = index_name else {
+                        if table.is_none() {
+                            *report.orphaned_keys.entry(table_name).or_default() += 1;
+                            wrong.push(result.key);
+                        }
+                        continue;
+                    };
+                    let owner = format!("{}.{}", table_name, index_name);
+                    let Some((table, index)) =
+                        table.and_then(|t| t.indexes.iter().find(|i| i.name == index_name).cloned().map(|i| (t, i)))
+                    else {
+                        *report.orphaned_keys.entry(owner).or_default() += 1;
+                        wrong.push(result.key);
+                        continue;
+                    };
+                    if index.building {
+                        continue;
+                    }
+                    if sampled != owner {
+                        sampled = owner.clone();
+                        seen = 0;
+                        stride = (self.count_rows(&table)? / CHECK_INDEX_SAMPLE).max(1);
+                    }
+                    seen += 1;
+                    if (seen - 1) % stride != 0 {
+                        continue;
+                    }
+                    report.index_entries_checked += 1;
+                    let entry = codec::decode_row(&result.value)?;
+                    let Some(pk) = entry.last() else {
+                        return Err(Error::Internal(format!("index {} has an empty entry", owner)));
+                    };
+                    let current = match self.get_row(&table, pk)? {
+                        Some(row) if Self::indexed(&table, &index, &row)? => {
+                            Some(Self::index_entry(&table, &index, pk, &row)?.0)
+                        }
+                        _ => None,
+                    };
+                    if current.as_ref() != Some(&result.key) {
+                        report.dangling_index_entries.push((owner, pk.clone()));
+                        wrong.push(result.key);
+                    }
+                }
+            }
+        }
+        if repair {
+            for key in wrong {
+                self.txn.delete(key)?;
+            }
+            report.repaired = true;
+        }
+        Ok(report)
+    }
+
+    /// Replaces the entries of rows in a building index with those of the rows
+    /// as they are now, given every version of them it may hold, and marks it built
+    fn reindex_rows(&mut self, table_name: &str, index_name: &str, rows: &HashMap<Value, Vec<Row>>) -> Result<()> {
+        let mut table = self.must_get_table(table_name.to_string())?;
+        let index = table.index(index_name)?.clone();
+        for (pk, versions) in rows {
+            for row in versions {
+                if Self::indexed(&table, &index, row)? {
+                    self.txn.delete(Self::index_entry(&table, &index, pk, row)?.0)?;
+                }
+            }
+            if let Some(row) = self.get_row(&table, pk)?
+                && Self::indexed(&table, &index, &row)?
+            {
+                let (key, value) = Self::index_entry(&table, &index, pk, &row)?;
+                self.txn.set(key, value)?;
+            }
+        }
+        table.indexes.iter_mut().filter(|i| i.name == index.name).for_each(|i| i.building = false);
+        self.save_table(&table)
+    }
+
+    /// Names the row of a storage-level write conflict, counting it in the stats
+    fn row_conflict(&self, table: &Table, id: &Value, err: Error) -> Error {
+        let Error::WriteConflict { version, .. } = err else {
+            return err;
+        };
+        if let Err(err) = self.stats.record_conflict(&table.name, id) {
+            return err;
+        }
+        Error::WriteConflict { key: id.to_string(), table: Some(table.name.clone()), version }
+    }
+
+    /// Adds to a table's row count
+    ///
+    /// Each transaction keeps its own delta under its version, so concurrent
+    /// writers of a table don't conflict on the count; commits fold them
+    /// together once they pile up.
+    fn add_row_count(&self, table: &Table, delta: i64) -> Result<()> {
+        if !self.counted.borrow().contains(&table.name) {
+            self.counted.borrow_mut().insert(table.name.clone());
+        }
+        let key = Key::RowCount(table.name.clone(), self.txn.version()).encode()?;
+        let count = match self.txn.get(key.clone())? {
+            Some(value) => bincode::deserialize::<i64>(&value)?,
+            None => 0,
+        };
+        self.txn.set(key, bincode::serialize(&(count + delta))?)
+    }
+
+    /// Reads the columns of a columnar table in primary key order, leaving
+    /// unread columns NULL
+    ///
+    /// Each column is one contiguous key range in primary key order, so the
+    /// n-th value of every column belongs to the n-th row. The primary key
+    /// is always read, since it gives the rows.
+    fn scan_columnar(&self, table: &Table, columns: Option<&[String]>, limit: usize) -> Result<Vec<Row>> {
+        let read = |i: usize| -> Result<Vec<Value>> {
+            self.txn
+                .scan_prefix_limit(KeyPrefix::Column(i as u64, table.name.clone()).encode()?, limit, |_, _| Ok(true))?
+                .into_iter()
+                .map(|result| Ok(bincode::deserialize(&result.value)?))
+                .collect()
+        };
+        let pk = Self::pk_index(table);
+        let mut rows = read(pk)?
+            .into_iter()
+            .map(|id| {
+                let mut row = vec![Value::Null; table.columns.len()];
+                row[pk] = id;
+                row
+            })
+            .collect::<Vec<_>>();
+        for (i, column) in table.columns.iter().enumerate() {
+            if i == pk || columns.is_some_and(|read| !read.contains(&column.name)) {
+                continue;
+            }
+            let values = read(i)?;
+            if values.len() != rows.len() {
+                return Err(Error::Internal(format!(
+                    "column {} of table {} has {} values for {} rows",
+                    column.name, table.name, values.len(), rows.len()
+                )));
+            }
+            for (row, value) in rows.iter_mut().zip(values) {
+                row[i] = value;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Scans keys with prefix for up to `limit` entries `accept` takes,
+    /// backwards if `reverse`
+    fn scan_keys(
+        &self,
+        prefix: Vec<u8>,
+        limit: usize,
+        reverse: bool,
+        accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<storage::mvcc::ScanResult>> {
+        match reverse {
+            true => self.txn.scan_prefix_rev(prefix, limit, accept),
+            false => self.txn.scan_prefix_limit(prefix, limit, accept),
+        }
+    }
+
+    /// Scans a table, reading only the given columns of columnar tables, and
+    /// stopping after `limit` rows if given; in descending primary key order
+    /// if `reverse`
+    fn scan(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: Option<usize>,
+        reverse: bool,
+    ) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name.clone())?;
+        // Row keys are in primary key order for unpartitioned row tables under a
+        // binary key collation, so they're read backwards; others sort a full scan
+        let backwards = system::table(&table_name).is_none()
+            && table.storage == StorageFormat::Row
+            && table.partition.is_none()
+            && table.columns[Self::pk_index(&table)].collation == Collation::Binary;
+        if reverse && !backwards {
+            let mut rows = self.scan(table_name, filter, columns, None, false)?;
+            sort_desc(&table, &mut rows);
+            rows.truncate(limit.unwrap_or(usize::MAX));
+            return Ok(rows);
+        }
+        // Partitioned tables fan out over every shard
+        let prefixes = match table.partition {
+            Some(Partition::Hash { partitions }) => (0..partitions)
+                .map(|shard| KeyPrefix::ShardRow(shard, table_name.clone()).encode())
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![KeyPrefix::Row(table_name.clone()).encode()?],
+        };
+        let filter = filter.map(|f| table.collate_filter(f));
+        let cols = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        // The filter needs its columns too
+        let mut read = columns.map(<[String]>::to_vec);
+        if let (Some(read), Some(expr)) = (&mut read, &filter) {
+            expr.walk_fields(&mut |col| read.push(col.to_string()));
+        }
+        let scanned = match system::table(&table_name) {
+            Some(_) => system::rows(self.txn.mvcc(), &self.stats, &table_name)?,
+            None if table.storage == StorageFormat::Columnar => {
+                // Filters need every row to pick from
+                let limit = match filter {
+                    Some(_) => usize::MAX,
+                    None => limit.unwrap_or(usize::MAX),
+                };
+                self.scan_columnar(&table, read.as_deref(), limit)?
+            }
+            None => {
+                // Only the read columns are decoded, and the primary key partitions sort by
+                let pk = Self::pk_index(&table);
+                let wanted = read.map(|read| {
+                    cols.iter().enumerate().map(|(i, col)| i == pk || read.contains(col)).collect::<Vec<_>>()
+                });
+                let decoder = RowDecoder::new(&cols, filter.as_ref(), wanted);
+                let mut rows = Vec::new();
+                for prefix in prefixes {
+                    match (limit, &filter) {
+                        // Filtered while reading, so the scan stops at enough matches
+                        (Some(limit), Some(_)) => {
+                            let results = self.scan_keys(prefix, limit, reverse, |_, value| decoder.passes(value))?;
+                            for result in results {
+                                rows.push(decoder.decode(&result.value)?);
+                            }
+                        }
+                        (limit, _) => {
+                            let results = self.scan_keys(prefix, limit.unwrap_or(usize::MAX), reverse, |_, _| Ok(true))?;
+                            rows.extend(decoder.decode_matching(&results)?);
+                        }
+                    }
+                }
+                rows
+            }
+        };
+
+        // No filter means select all rows; stored rows were filtered as they were decoded
+        let filtered = system::table(&table_name).is_none() && table.storage == StorageFormat::Row;
+        let mut rows = match &filter {
+            Some(expr) if !filtered => batch::filter(scanned, &cols, expr)?,
+            _ => scanned,
+        };
+        // Merge shards back into primary key order, as for unpartitioned tables
+        if table.partition.is_some() {
+            let pk = Self::pk_index(&table);
+            rows.sort_by(|a, b| a[pk].partial_cmp(&b[pk]).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        if let Some(limit) = limit {
+            rows.truncate(limit);
+        }
+        Ok(rows)
+    }
+}
+
+impl<E: StorageEngine> Transaction for KVTransaction<E> {
+    fn commit(&self) -> Result<()> {
+        self.fold_commit()
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.txn.rollback()
+    }
+
+    fn version(&self) -> u64 {
+        self.txn.version()
+    }
+
+    fn set_durability(&mut self, durability: Durability) {
+        self.txn.set_durability(durability);
+    }
+
+    fn create_row(&mut self, table_name: String, mut row: Row) -> Result<()> {
+        let table = self.must_get_table(table_name.clone())?;
+        system::check_writable(&table)?;
+        self.check_published(&table)?;
+
+        Self::check_row(&table, &mut row)?;
+
+        let pk = table.get_primary_key(&row)?;
+        if self.row_exists(&table, &pk)? {
+            return Err(Error::Internal(format!(
+                "Duplicate data for primary key {} in table {}",
+                pk, table_name
+            )));
+        }
+
+        self.write_row(&table, &pk, &row)?;
+        self.write_indexes(&table, &pk, &row)?;
+        self.add_row_count(&table, 1)?;
+        view::maintain(self, &table, &[(None, Some(&row))])?;
+
+        if self.recording()? {
+            self.record(&table_name, pk, None, Some(row));
+        }
+        Ok(())
+    }
+
+    /// Checks every row, then writes them in chunks of `BULK_CHUNK_ROWS`, each
+    /// one batched storage write with a single write set entry per shard
+    fn bulk_insert(&mut self, table_name: String, mut rows: Vec<Row>) -> Result<usize> {
+        let table = self.must_get_table(table_name.clone())?;
+        system::check_writable(&table)?;
+        self.check_published(&table)?;
+
+        let mut ids = Vec::with_capacity(rows.len());
+        let mut keys = HashSet::with_capacity(rows.len());
+        for row in rows.iter_mut() {
+            Self::check_row(&table, row)?;
+            let pk = table.get_primary_key(row)?;
+            if !keys.insert(Self::row_key(&table, &pk)?) || self.row_exists(&table, &pk)? {
+                return Err(Error::Internal(format!(
+                    "Duplicate data for primary key {} in table {}",
+                    pk, table_name
+                )));
+            }
+            ids.push(pk);
+        }
+
+        self.wrote(&table.name);
+        for (ids, rows) in ids.chunks(BULK_CHUNK_ROWS).zip(rows.chunks(BULK_CHUNK_ROWS)) {
+            let mut writes = Vec::with_capacity(rows.len());
+            for (id, row) in ids.iter().zip(rows) {
+                writes.extend(Self::row_writes(&table, id, row)?);
+                writes.extend(Self::index_writes(&table, id, row)?);
+            }
+            self.txn.set_batch(writes).map_err(|err| {
+                // Name the row whose key conflicted
+                let row = match &err {
+                    Error::WriteConflict { key, .. } => ids.iter().find(|id| {
+                        Self::row_keys(&table, id)
+                            .is_ok_and(|keys| keys.iter().any(|k| k.escape_ascii().to_string() == *key))
+                    }),
+                    _ => None,
+                };
+                match row {
+                    Some(id) => self.row_conflict(&table, id, err),
+                    None => err,
+                }
+            })?;
+        }
+        let count = rows.len();
+        self.add_row_count(&table, count as i64)?;
+        if !table.views.is_empty() {
+            view::maintain(self, &table, &rows.iter().map(|row| (None, Some(row))).collect::<Vec<_>>())?;
+        }
+
+        if self.recording()? {
+            for (pk, row) in ids.into_iter().zip(rows) {
+                self.record(&table_name, pk, None, Some(row));
+            }
+        }
+        Ok(count)
+    }
+
+    /// Updates a row - if primary key changes, delete old data and insert new
+    fn update_row(&mut self, table: &Table, id: &Value, mut row: Row) -> Result<()> {
+        system::check_writable(table)?;
+        self.check_published(table)?;
+        Self::check_row(table, &mut row)?;
+        let recording = self.recording()?;
+        // The old row's index entries are replaced by the new one's, and
+        // views are maintained from the difference
+        let old = match recording || !table.indexes.is_empty() || !table.views.is_empty() {
+            true => self.get_row(table, id)?,
+            false => None,
+        };
+        if let Some(old) = &old {
+            self.remove_indexes(table, id, old)?;
+        }
+
+        let new_pk = table.get_primary_key(&row)?;
+        let mut replaced = None;
+        if *id != new_pk {
+            // A row already under the new key is overwritten
+            replaced = self.get_row(table, &new_pk)?;
+            if let Some(replaced) = &replaced {
+                self.remove_indexes(table, &new_pk, replaced)?;
+                self.add_row_count(table, -1)?;
+            }
+            self.remove_row(table, id)?;
+        }
+        self.write_row(table, &new_pk, &row)?;
+        self.write_indexes(table, &new_pk, &row)?;
+        view::maintain(self, table, &[(old.as_ref(), Some(&row)), (replaced.as_ref(), None)])?;
+
+        if let Some(old) = old.filter(|_| recording) {
+            if *id != new_pk {
+                self.record(&table.name, id.clone(), Some(old), None);
+                self.record(&table.name, new_pk, None, Some(row));
+            } else {
+                self.record(&table.name, new_pk, Some(old), Some(row));
+            }
+        }
+        Ok(())
+    }
+
+    fn get_row(&self, table: &Table, id: &Value) -> Result<Option<Row>> {
+        if system::table(&table.name).is_some() {
+            let rows = system::rows(self.txn.mvcc(), &self.stats, &table.name)?;
+            return Ok(rows.into_iter().find(|row| row[0] == *id));
+        }
+        if table.storage == StorageFormat::Columnar {
+            if !self.row_exists(table, id)? {
+                return Ok(None);
+            }
+            let mut row = Vec::with_capacity(table.columns.len());
+            for i in 0..table.columns.len() {
+                let value = self.txn.get(Self::column_key(table, i, id)?)?.ok_or_else(|| {
+                    Error::Internal(format!("row {} of table {} is missing column {}", id, table.name, i))
+                })?;
+                row.push(bincode::deserialize(&value)?);
+            }
+            return Ok(Some(row));
+        }
+        let key = Self::row_key(table, id)?;
+        self.txn.get(key)?.map(|v| codec::decode_row(&v)).transpose()
+    }
+
+    /// Deletes a row by primary key
+    fn delete_row(&mut self, table: &Table, id: &Value) -> Result<()> {
+        system::check_writable(table)?;
+        self.check_published(table)?;
+        let recording = self.recording()?;
+        let old = match recording || !table.indexes.is_empty() || !table.views.is_empty() {
+            true => self.get_row(table, id)?,
+            false => None,
+        };
+        if let Some(old) = &old {
+            self.remove_indexes(table, id, old)?;
+        }
+
+        if self.row_exists(table, id)? {
+            self.remove_row(table, id)?;
+            self.add_row_count(table, -1)?;
+        }
+        // Maintained after the removal, in case a group is aggregated again
+        if let Some(old) = &old {
+            view::maintain(self, table, &[(Some(old), None)])?;
+        }
+
+        if let Some(old) = old.filter(|_| recording) {
+            self.record(&table.name, id.clone(), Some(old), None);
+        }
+        Ok(())
+    }
+
+    fn lock_row(&mut self, table: &Table, id: &Value) -> Result<()> {
+        system::check_writable(table)?;
+        // Writers set every column's key, so the first one stands for the row
+        let key = match table.storage {
+            StorageFormat::Row => Self::row_key(table, id)?,
+            StorageFormat::Columnar => Self::column_key(table, 0, id)?,
+        };
+        self.txn.lock(key).map_err(|err| self.row_conflict(table, id, err))
+    }
+
+    /// Sums the table's row count deltas, without reading rows
+    fn count_rows(&self, table: &Table) -> Result<usize> {
+        if system::table(&table.name).is_some() {
+            return Ok(system::rows(self.txn.mvcc(), &self.stats, &table.name)?.len());
+        }
+        let mut count = 0;
+        for result in self.txn.scan_prefix(KeyPrefix::RowCount(table.name.clone()).encode()?)? {
+            count += bincode::deserialize::<i64>(&result.value)?;
+        }
+        usize::try_from(count)
+            .map_err(|_| Error::Internal(format!("table {} has a negative row count {}", table.name, count)))
+    }
+
+    /// Pages through unpartitioned row tables; others are read in one go
+    fn scan_table(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+    ) -> Result<Rows<'_>> {
+        let table = self.must_get_table(table_name.clone())?;
+        if system::table(&table_name).is_some() || table.storage != StorageFormat::Row || table.partition.is_some() {
+            return Ok(Box::new(self.scan(table_name, filter, None, None, false)?.into_iter().map(Ok)));
+        }
+        Ok(Box::new(TableCursor::new(&self.txn, &table, filter)?))
+    }
+
+    fn scan_table_columns(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: &[String],
+    ) -> Result<Vec<Row>> {
+        self.scan(table_name, filter, Some(columns), None, false)
+    }
+
+    fn scan_table_limit(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: usize,
+    ) -> Result<Vec<Row>> {
+        self.scan(table_name, filter, columns, Some(limit), false)
+    }
+
+    fn scan_table_rev(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Row>> {
+        self.scan(table_name, filter, columns, limit, true)
+    }
+
+    /// Reads the entries under the index's prefix, extended by the first
+    /// column's collation key when looking up a value
+    fn scan_index(
+        &self,
+        table_name: String,
+        index: &str,
+        value: Option<&Value>,
+        filter: Option<Expression>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name)?;
+        let index = table.index(index)?;
+        let positions = table.index_columns(index)?;
+        let mut prefix = KeyPrefix::Index(table.name.clone(), index.name.clone()).encode()?;
+        if let Some(value) = value {
+            prefix.extend(serialize_key(&table.columns[positions[0]].collation.fold(value))?);
+        }
+        let pk = Self::pk_index(&table);
+        let decode = |value: &[u8]| -> Result<Row> {
+            let mut entry = codec::decode_row(value)?;
+            let mut row = vec![Value::Null; table.columns.len()];
+            row[pk] = entry.pop().ok_or_else(|| {
+                Error::Internal(format!("empty entry in index {} of table {}", index.name, table.name))
+            })?;
+            for (&i, value) in positions.iter().zip(entry) {
+                row[i] = value;
+            }
+            Ok(row)
+        };
+        let filter = filter.map(|f| table.collate_filter(f));
+        let cols = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        // Filtered while reading, so the scan stops at enough matches
+        let results = self.txn.scan_prefix_limit(prefix, limit.unwrap_or(usize::MAX), |_, value| match &filter {
+            Some(expr) => Ok(!batch::filter(vec![decode(value)?], &cols, expr)?.is_empty()),
+            None => Ok(true),
+        })?;
+        results.into_iter().map(|result| decode(&result.value)).collect()
+    }
+
+    fn create_table(&mut self, table: Table) -> Result<()> {
+        if self.get_table(table.name.clone())?.is_some() {
+            return Err(Error::Internal(format!(
+                "table {} already exists",
+                table.name
+            )));
+        }
+        self.check_schema_writable()?;
+        // Tenants' rows of the view would be missing
+        if table.view.is_some() && self.tenancy == Tenancy::SharedSchema {
+            return Err(Error::Internal("materialized views can't be created in a schema tenants share".into()));
+        }
+
+        table.validate()?;
+
+        let key = Key::Table(table.name.clone()).encode()?;
+        let value = bincode::serialize(&table)?;
+        self.txn.set(key, value)?;
+        self.wrote(&table.name);
+        self.tables.borrow_mut().insert(table.name.clone(), table);
+
+        Ok(())
+    }
+
+    fn create_index(&mut self, table_name: &str, index: Index) -> Result<()> {
+        let table = self.add_index(table_name, index.clone())?;
+        self.index_rows(&table, &index)?;
+        for tenant in self.tenants()? {
+            tenant.index_rows(&table, &index)?;
+        }
+        Ok(())
+    }
+
+    fn add_view(&mut self, table_name: &str, view: &str, maintained: bool) -> Result<()> {
+        let mut table = self.must_get_table(table_name.to_string())?;
+        system::check_writable(&table)?;
+        table.readers.push(view.to_string());
+        if maintained {
+            table.views.push(view.to_string());
+        }
+        self.save_table(&table)
+    }
+
+    fn drop_table(&mut self, table_name: &str, cascade: bool) -> Result<()> {
+        let table = self.must_get_table(table_name.to_string())?;
+        system::check_writable(&table)?;
+        let dependents = self.dependents(&table)?;
+        if let Some(dependent) = dependents.first()
+            && !cascade
+        {
+            return Err(Error::Internal(format!(
+                "cannot drop table {}: table {} depends on it, use DROP TABLE {} CASCADE",
+                table.name, dependent.name, table.name
+            )));
+        }
+        let dropped = dependents.iter().chain([&table]).map(|t| t.name.clone()).collect::<HashSet<_>>();
+        for table in dependents.iter().chain([&table]) {
+            // The tables a dropped view read, which stay, no longer have it
+            for source in view::source_tables(table)? {
+                if dropped.contains(&source) {
+                    continue;
+                }
+                if let Some(mut source) = self.get_table(source)? {
+                    source.readers.retain(|name| *name != table.name);
+                    source.views.retain(|name| *name != table.name);
+                    self.save_table(&source)?;
+                }
+            }
+            self.remove_table(table)?;
+        }
+        Ok(())
+    }
+
+    fn get_table(&self, table_name: String) -> Result<Option<Table>> {
+        if let Some(table) = system::table(&table_name) {
+            return Ok(Some(table));
+        }
+        if let Some(table) = self.tables.borrow().get(&table_name) {
+            return Ok(Some(table.clone()));
+        }
+        let key = Key::Table(table_name.clone()).encode()?;
+        let table: Option<Table> = self.txn.get(key)?.map(|v| bincode::deserialize(&v)).transpose()?;
+        if let Some(table) = &table {
+            self.tables.borrow_mut().insert(table_name, table.clone());
+        }
+        Ok(table)
+    }
+
+    fn get_table_names(&self) -> Result<Vec<String>> {
+        // The table prefix is a single zero byte, which MVCC escapes into a 0xFF ending
+        // that scan_prefix can't increment, so scan everything and filter instead
+        let prefix = KeyPrefix::Table.encode()?;
+        let results = match &self.tenancy {
+            // The shared catalog is outside the tenant's keys, which the scan covers
+            Tenancy::Tenant(Tenant { shared_schema: true, .. }) => self.txn.scan_prefix(prefix.clone())?,
+            _ => self.txn.scan_prefix(Vec::new())?,
+        };
+        results
+            .into_iter()
+            .filter(|result| result.key.starts_with(&prefix))
+            .map(|result| Ok(bincode::deserialize::<Table>(&result.value)?.name))
+            // Audit tables are hidden, though queryable by name
+            .filter(|name| !name.as_ref().is_ok_and(|name| audit::is_audit_table(name)))
+            .collect()
+    }
+}
+
+/// Cursor over the rows of an unpartitioned row table, in primary key order
+///
+/// Rows are read a page at a time from the transaction's snapshot, each page
+/// resuming after the last key of the one before, so only a page is held.
+struct TableCursor<'a, E: StorageEngine> {
+    txn: &'a Keyspace<E>,
+    prefix: Vec<u8>,
+    filter: Option<Expression>,
+    /// Column names, for evaluating the filter
+    columns: Vec<String>,
+    /// Last key read, None before the first page
+    last: Option<Vec<u8>>,
+    page: std::vec::IntoIter<Row>,
+    /// Whether the last page was read, or reading failed
+    done: bool,
+}
+
+impl<'a, E: StorageEngine> TableCursor<'a, E> {
+    fn new(txn: &'a Keyspace<E>, table: &Table, filter: Option<Expression>) -> Result<Self> {
+        Ok(Self {
+            txn,
+            prefix: KeyPrefix::Row(table.name.clone()).encode()?,
+            filter: filter.map(|f| table.collate_filter(f)),
+            columns: table.columns.iter().map(|c| c.name.clone()).collect(),
+            last: None,
+            page: Vec::new().into_iter(),
+            done: false,
+        })
+    }
+
+    /// Reads the next page, keeping the rows that pass the filter
+    fn next_page(&mut self) -> Result<Vec<Row>> {
+        let results = match self.last.take() {
+            Some(last) => self.txn.scan_prefix_after(self.prefix.clone(), last, SCAN_PAGE_ROWS)?,
+            None => self.txn.scan_prefix_limit(self.prefix.clone(), SCAN_PAGE_ROWS, |_, _| Ok(true))?,
+        };
+        self.done = results.len() < SCAN_PAGE_ROWS;
+        self.last = results.last().map(|result| result.key.clone());
+        RowDecoder::new(&self.columns, self.filter.as_ref(), None).decode_matching(&results)
+    }
+}
+
+impl<E: StorageEngine> Iterator for TableCursor<'_, E> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        loop {
+            if let Some(row) = self.page.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            match self.next_page() {
+                Ok(rows) => self.page = rows.into_iter(),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Key types for KV storage operations
+#[derive(Debug, Serialize, Deserialize)]
+enum Key {
+    /// Table schema key (table name)
+    Table(String),
+    /// Row data key (table name + primary key value)
+    Row(String, Value),
+    /// Row data key of a hash-partitioned table (shard + table name + primary key value)
+    ///
+    /// The shard leads so each shard is a contiguous key range.
+    ShardRow(u64, String, Value),
+    /// Column value of a columnar table (column index + table name + primary key value)
+    ///
+    /// The column leads so each column is a contiguous key range in primary key order.
+    Column(u64, String, Value),
+    /// Row count delta of a table made by one transaction (table name + version)
+    ///
+    /// The table's row count is the sum of its deltas. A fold replaces the
+    /// committed ones with their sum, under its own version.
+    RowCount(String, u64),
+    /// Secondary index entry (table name + index name + indexed values + primary key value)
+    ///
+    /// Entries of one index are a contiguous key range in indexed value order.
+    Index(String, String, Vec<Value>, Value),
+    /// Tenant sharing the database's schema (tenant id, see `tenant`)
+    ///
+    /// The empty id is written by every change to the registry and by the
+    /// schema changes covering its tenants, to order them.
+    Tenant(String),
+}
+
+/// The table a data key belongs to, and the index for index entries,
+/// read from the leading fields without decoding the rest
+///
+/// Index keys can't be decoded whole: their indexed values run up to the
+/// primary key without a length.
+fn key_owner(key: &[u8]) -> Result<(String, Option<String>)> {
+    let Some((&tag, mut input)) = key.split_first() else {
+        return Err(Error::Internal("empty key".into()));
+    };
+    // Shard and column numbers (u64) come before the table name
+    if tag == 2 || tag == 3 {
+        input = input.get(8..).ok_or_else(|| Error::Internal("unexpected end of key".into()))?;
+    }
+    let table = keycode::decode_str(&mut input)?;
+    let index = if tag == 5 { Some(keycode::decode_str(&mut input)?) } else { None };
+    Ok((table, index))
+}
+
+/// Findings of `KVTransaction::check`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CheckReport {
+    /// Number of keys stored for a missing table or index, by table name or
+    /// `table.index`
+    pub orphaned_keys: BTreeMap<String, usize>,
+    /// Index entries compared with their rows
+    pub index_entries_checked: usize,
+    /// Sampled index entries whose row is gone or no longer holds the indexed
+    /// values, by `table.index` and primary key
+    pub dangling_index_entries: Vec<(String, Value)>,
+    /// Whether the keys found wrong were deleted
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// Whether nothing wrong was found
+    pub fn is_ok(&self) -> bool {
+        self.orphaned_keys.is_empty() && self.dangling_index_entries.is_empty()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (owner, count) in &self.orphaned_keys {
+            writeln!(f, "{}: {} orphaned keys", owner, count)?;
+        }
+        for (owner, pk) in &self.dangling_index_entries {
+            writeln!(f, "{}: dangling entry for primary key {}", owner, pk)?;
+        }
+        let verdict = match (self.is_ok(), self.repaired) {
+            (true, _) => "ok",
+            (false, true) => "repaired",
+            (false, false) => "inconsistent",
+        };
+        write!(f, "{} index entries checked, {}", self.index_entries_checked, verdict)
+    }
+}
+
+// Use custom serialization for prefix matching support with variable-length strings
+impl Key {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serialize_key(self)
+    }
+}
+
+/// Key prefix types for prefix scanning
+///
+/// In bincode, enums are serialized as [variant_index][variant_data...].
+/// Variant indices start from 0 in definition order.
+#[derive(Debug, Serialize, Deserialize)]
+enum KeyPrefix {
+    Table,
+    Row(String),
+    ShardRow(u64, String),
+    Column(u64, String),
+    RowCount(String),
+    Index(String, String),
+    Tenant,
+    /// Keys of a tenant: this prefix, then the key as it is outside tenants
+    TenantKeys(String),
+}
+
+impl KeyPrefix {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serialize_key(self)
+    }
+}
+
+/// Decodes the stored rows of a scan lazily
+///
+/// Rows are first decoded only as far as the filter reads them, and the
+/// rest of a row only once it passes, so scans that filter most rows out
+/// don't build the values they'd throw away.
+struct RowDecoder<'a> {
+    /// Column names, for evaluating the filter
+    names: &'a [String],
+    filter: Option<&'a Expression>,
+    /// Columns the filter reads
+    tested: Vec<bool>,
+    /// Columns the scan returns, None for all
+    wanted: Option<Vec<bool>>,
+}
+
+impl<'a> RowDecoder<'a> {
+    fn new(names: &'a [String], filter: Option<&'a Expression>, wanted: Option<Vec<bool>>) -> Self {
+        let mut tested = vec![false; names.len()];
+        if let Some(expr) = filter {
+            expr.walk_fields(&mut |col| {
+                if let Some(i) = names.iter().position(|name| name == col) {
+                    tested[i] = true;
+                }
+            });
+        }
+        Self { names, filter, tested, wanted }
+    }
+
+    /// Whether a stored row passes the filter
+    fn passes(&self, bytes: &[u8]) -> Result<bool> {
+        match self.filter {
+            Some(expr) => Ok(batch::matches(vec![codec::decode_columns(bytes, &self.tested)?], self.names, expr)?[0]),
+            None => Ok(true),
+        }
+    }
+
+    /// Decodes the returned columns of a stored row
+    fn decode(&self, bytes: &[u8]) -> Result<Row> {
+        match &self.wanted {
+            Some(wanted) => codec::decode_columns(bytes, wanted),
+            None => codec::decode_row(bytes),
+        }
+    }
+
+    /// Decodes the stored rows that pass the filter, testing them a batch at a time
+    fn decode_matching(&self, results: &[storage::mvcc::ScanResult]) -> Result<Vec<Row>> {
+        let Some(expr) = self.filter else {
+            return results.iter().map(|result| self.decode(&result.value)).collect();
+        };
+        let tested = results
+            .iter()
+            .map(|result| codec::decode_columns(&result.value, &self.tested))
+            .collect::<Result<Vec<_>>>()?;
+        let matched = batch::matches(tested, self.names, expr)?;
+        results
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| *matched)
+            .map(|(result, _)| self.decode(&result.value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{KVEngine, KVTransaction};
+    use crate::storage::engine::Engine as StorageEngine;
+    use crate::{
+        error::{Error, Result},
+        sql::{
+            engine::{Engine, InsertPolicy, Session, Transaction},
+            executor::{ColumnMetadata, ResultSet},
+            codec,
+            parser::{Parser, ast},
+            types::{DataType, Row, Value},
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn setup_table<E: StorageEngine + 'static>(s: &mut Session<KVEngine<E>>) -> Result<()> {
+        s.execute(
+            "create table t1 (
+                     a int primary key,
+                     b text default 'vv',
+                     c integer default 100
+                 );",
+        )?;
+
+        s.execute(
+            "create table t2 (
+                     a int primary key,
+                     b integer default 100,
+                     c float default 1.1,
+                     d bool default false,
+                     e boolean default true,
+                     f text default 'v1',
+                     g string default 'v2',
+                     h varchar default 'v3'
+                 );",
+        )?;
+
+        s.execute(
+            "create table t3 (
+                     a int primary key,
+                     b int default 12 null,
+                     c integer default NULL,
+                     d float not NULL
+                 );",
+        )?;
+
+        s.execute(
+            "create table t4 (
+                     a bool primary key,
+                     b int default 12,
+                     d boolean default true
+                 );",
+        )?;
+        Ok(())
+    }
+
+    fn scan_table_and_compare<E: StorageEngine + 'static>(
+        s: &mut Session<KVEngine<E>>,
+        table_name: &str,
+        expect: Vec<Row>,
+    ) -> Result<()> {
+        match s.execute(&format!("select * from {};", table_name))? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows, expect);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
     fn scan_table_and_print<E: StorageEngine + 'static>(
         s: &mut Session<KVEngine<E>>,
         table_name: &str,
     ) -> Result<()> {
         match s.execute(&format!("select * from {};", table_name))? {
-            ResultSet::Scan { columns: _, rows } => {
+            ResultSet::Scan { rows, .. } => {
                 for row in rows {
                     println!("{:?}", row);
                 }
@@ -294,439 +1844,1983 @@ mod tests {
     }
 
     #[test]
-    fn test_create_table() -> Result<()> {
+    fn test_create_table() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        setup_table(&mut s)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        setup_table(&mut s)?;
+
+        // t1
+        s.execute("insert into t1 (a) values (1);")?;
+        s.execute("insert into t1 values (2, 'a', 2);")?;
+        s.execute("insert into t1(b,a) values ('b', 3);")?;
+
+        scan_table_and_compare(
+            &mut s,
+            "t1",
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::String("vv".into()),
+                    Value::Integer(100),
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::String("a".into()),
+                    Value::Integer(2),
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::String("b".into()),
+                    Value::Integer(100),
+                ],
+            ],
+        )?;
+
+        // t2
+        s.execute("insert into t2 (a) values (1);")?;
+        scan_table_and_compare(
+            &mut s,
+            "t2",
+            vec![vec![
+                Value::Integer(1),
+                Value::Integer(100),
+                Value::Float(1.1),
+                Value::Boolean(false),
+                Value::Boolean(true),
+                Value::String("v1".into()),
+                Value::String("v2".into()),
+                Value::String("v3".into()),
+            ]],
+        )?;
+
+        // t3
+        s.execute("insert into t3 (a, d) values (1, 1.1);")?;
+        scan_table_and_compare(
+            &mut s,
+            "t3",
+            vec![vec![
+                Value::Integer(1),
+                Value::Integer(12),
+                Value::Null,
+                Value::Float(1.1),
+            ]],
+        )?;
+
+        // t4
+        s.execute("insert into t4 (a) values (true);")?;
+        scan_table_and_compare(
+            &mut s,
+            "t4",
+            vec![vec![
+                Value::Boolean(true),
+                Value::Integer(12),
+                Value::Boolean(true),
+            ]],
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        setup_table(&mut s)?;
+
+        s.execute("insert into t2 values (1, 1, 1.1, true, true, 'v1', 'v2', 'v3');")?;
+        s.execute("insert into t2 values (2, 2, 2.2, false, false, 'v4', 'v5', 'v6');")?;
+        s.execute("insert into t2 values (3, 3, 3.3, true, false, 'v7', 'v8', 'v9');")?;
+        s.execute("insert into t2 values (4, 4, 4.4, false, true, 'v10', 'v11', 'v12');")?;
+
+        let res = s.execute("update t2 set b = 100 where a = 1;")?;
+        assert_eq!(res, ResultSet::Update { count: 1 });
+        let res = s.execute("update t2 set d = false where d = true;")?;
+        assert_eq!(res, ResultSet::Update { count: 2 });
+
+        scan_table_and_compare(
+            &mut s,
+            "t2",
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Integer(100),
+                    Value::Float(1.1),
+                    Value::Boolean(false),
+                    Value::Boolean(true),
+                    Value::String("v1".into()),
+                    Value::String("v2".into()),
+                    Value::String("v3".into()),
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(2),
+                    Value::Float(2.2),
+                    Value::Boolean(false),
+                    Value::Boolean(false),
+                    Value::String("v4".into()),
+                    Value::String("v5".into()),
+                    Value::String("v6".into()),
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(3),
+                    Value::Float(3.3),
+                    Value::Boolean(false),
+                    Value::Boolean(false),
+                    Value::String("v7".into()),
+                    Value::String("v8".into()),
+                    Value::String("v9".into()),
+                ],
+                vec![
+                    Value::Integer(4),
+                    Value::Integer(4),
+                    Value::Float(4.4),
+                    Value::Boolean(false),
+                    Value::Boolean(true),
+                    Value::String("v10".into()),
+                    Value::String("v11".into()),
+                    Value::String("v12".into()),
+                ],
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        setup_table(&mut s)?;
+
+        s.execute("insert into t2 values (1, 1, 1.1, true, true, 'v1', 'v2', 'v3');")?;
+        s.execute("insert into t2 values (2, 2, 2.2, false, false, 'v4', 'v5', 'v6');")?;
+        s.execute("insert into t2 values (3, 3, 3.3, true, false, 'v7', 'v8', 'v9');")?;
+        s.execute("insert into t2 values (4, 4, 4.4, false, true, 'v10', 'v11', 'v12');")?;
+
+        let res = s.execute("delete from t2 where a = 1;")?;
+        assert_eq!(res, ResultSet::Delete { count: 1 });
+        scan_table_and_compare(
+            &mut s,
+            "t2",
+            vec![
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(2),
+                    Value::Float(2.2),
+                    Value::Boolean(false),
+                    Value::Boolean(false),
+                    Value::String("v4".into()),
+                    Value::String("v5".into()),
+                    Value::String("v6".into()),
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(3),
+                    Value::Float(3.3),
+                    Value::Boolean(true),
+                    Value::Boolean(false),
+                    Value::String("v7".into()),
+                    Value::String("v8".into()),
+                    Value::String("v9".into()),
+                ],
+                vec![
+                    Value::Integer(4),
+                    Value::Integer(4),
+                    Value::Float(4.4),
+                    Value::Boolean(false),
+                    Value::Boolean(true),
+                    Value::String("v10".into()),
+                    Value::String("v11".into()),
+                    Value::String("v12".into()),
+                ],
+            ],
+        )?;
+
+        let res = s.execute("delete from t2 where d = false;")?;
+        assert_eq!(res, ResultSet::Delete { count: 2 });
+        scan_table_and_compare(
+            &mut s,
+            "t2",
+            vec![vec![
+                Value::Integer(3),
+                Value::Integer(3),
+                Value::Float(3.3),
+                Value::Boolean(true),
+                Value::Boolean(false),
+                Value::String("v7".into()),
+                Value::String("v8".into()),
+                Value::String("v9".into()),
+            ]],
+        )?;
+
+        let res = s.execute("delete from t2;")?;
+        assert_eq!(res, ResultSet::Delete { count: 1 });
+        scan_table_and_compare(&mut s, "t2", vec![])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        setup_table(&mut s)?;
+
+        s.execute("insert into t3 values (1, 34, 22, 1.22);")?;
+        s.execute("insert into t3 values (4, 23, 65, 4.23);")?;
+        s.execute("insert into t3 values (3, 56, 22, 2.88);")?;
+        s.execute("insert into t3 values (2, 87, 57, 6.78);")?;
+        s.execute("insert into t3 values (5, 87, 14, 3.28);")?;
+        s.execute("insert into t3 values (7, 87, 82, 9.52);")?;
+
+        match s.execute("select a, b as col2 from t3 order by c, a desc limit 100;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(2, columns.len());
+                assert_eq!(6, rows.len());
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_special_values() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a float primary key, b int);")?;
+        s.execute("create table t2 (a int primary key, b float);")?;
+        s.execute("create index idx_b on t2 (b);")?;
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            result => panic!("{}: {:?}", sql, result),
+        };
+
+        // NaN and infinities are accepted; NaN and zeros of either sign are one key each
+        s.execute("insert into t1 values (1e999 - 1e999, 1), (1e999, 2), (-1e999, 3), (0.0, 4);")?;
+        assert!(s.execute("insert into t1 values (1e999 - 1e999, 5);").is_err());
+        assert!(s.execute("insert into t1 values (0.0 * -1.0, 5);").is_err());
+        assert_eq!(rows(&mut s, "select b from t1 where a = 0.0 * -1.0;"), vec![vec![Value::Integer(4)]]);
+
+        // NaN equals itself and is greater than every number, infinity included
+        assert_eq!(rows(&mut s, "select b from t1 where a = 1e999 - 1e999;"), vec![vec![Value::Integer(1)]]);
+        assert_eq!(rows(&mut s, "select b from t1 where a > 1e999;"), vec![vec![Value::Integer(1)]]);
+        assert_eq!(rows(&mut s, "select b from t1 where a < 0;"), vec![vec![Value::Integer(3)]]);
+
+        // Groups and index lookups gather every NaN
+        s.execute("insert into t2 values (1, 1e999 - 1e999), (2, 0.0 - (1e999 - 1e999)), (3, 1.5), (4, 0.0 * -1.0), (5, 0.0);")?;
+        let mut groups = rows(&mut s, "select b, count(a) from t2 group by b;");
+        groups.sort_by(|x, y| x[0].partial_cmp(&y[0]).unwrap());
+        assert_eq!(groups.iter().map(|row| row[1].clone()).collect::<Vec<_>>(), [2, 1, 2].map(Value::Integer));
+        assert!(matches!(groups[2][0], Value::Float(f) if f.is_nan()));
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.scan_index("t2".into(), "idx_b", Some(&Value::Float(f64::NAN)), None, None)?.len(), 2);
+        assert_eq!(txn.scan_index("t2".into(), "idx_b", Some(&Value::Float(-0.0)), None, None)?.len(), 2);
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_nan() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b float);")?;
+        // Every third value is NaN (infinity minus infinity)
+        let values = (0..50)
+            .map(|i| match i % 3 {
+                0 => format!("({}, 1e999 - 1e999)", i),
+                _ => format!("({}, {}.5)", i, 50 - i),
+            })
+            .collect::<Vec<_>>();
+        s.execute(&format!("insert into t1 values {};", values.join(", ")))?;
+
+        // NaN sorts above every number
+        match s.execute("select b from t1 order by b;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows[0], vec![Value::Float(1.5)]);
+                assert!(rows[33..].iter().all(|row| matches!(row[0], Value::Float(f) if f.is_nan())));
+            }
+            _ => unreachable!(),
+        }
+        match s.execute("select min(b), max(b) from t1;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows[0][0], Value::Float(1.5));
+                assert!(matches!(rows[0][1], Value::Float(f) if f.is_nan()));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cross_join() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("create table t2 (b int primary key);")?;
+        s.execute("create table t3 (c int primary key);")?;
+
+        s.execute("insert into t1 values (1), (2), (3);")?;
+        s.execute("insert into t2 values (4), (5), (6);")?;
+        s.execute("insert into t3 values (7), (8), (9);")?;
+
+        match s.execute("select * from t1 cross join t2 cross join t3;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(3, columns.len());
+                assert_eq!(27, rows.len());
+                // for row in rows {
+                //     println!("{:?}", row);
+                // }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("create table t2 (b int primary key);")?;
+        s.execute("create table t3 (c int primary key);")?;
+
+        s.execute("insert into t1 values (1), (2), (3);")?;
+        s.execute("insert into t2 values (2), (3), (4);")?;
+        s.execute("insert into t3 values (3), (8), (9);")?;
+
+        match s.execute("select * from t1 right join t2 on a = b join t3 on a = c;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(3, columns.len());
+                assert_eq!(1, rows.len());
+                // for row in rows {
+                //     println!("{:?}", row);
+                // }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_join() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (x bigint primary key, y int);")?;
+        s.execute("insert into t1 values (1, 10), (2, null), (3, 30), (4, 20);")?;
+        s.execute("insert into t2 values (5, 10), (6, 10), (7, null), (8, 30), (9, 40);")?;
+
+        // Hash joins (on b = y) produce the rows of nested loop joins (on b + 0 = y),
+        // in the same order: duplicate keys, NULL keys and unmatched rows included
+        for (join, count) in [("join", 3), ("left join", 5), ("right join", 5)] {
+            let mut rows = |on: &str| -> Result<Vec<Vec<Value>>> {
+                match s.execute(&format!("select * from t1 {} t2 on {};", join, on))? {
+                    ResultSet::Scan { rows, .. } => Ok(rows),
+                    _ => unreachable!(),
+                }
+            };
+            let hashed = rows("b = y")?;
+            assert_eq!(hashed, rows("b + 0 = y")?, "{}", join);
+            assert_eq!(hashed.len(), count, "{}", join);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_agg() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+
+        s.execute("insert into t1 values (1, 'aa', 3.1);")?;
+        s.execute("insert into t1 values (2, 'cc', 5.3);")?;
+        s.execute("insert into t1 values (3, null, NULL);")?;
+        s.execute("insert into t1 values (4, 'dd', 4.6);")?;
+
+        match s.execute("select count(a) as total, max(b), min(a), sum(c), avg(c) from t1;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["total", "max", "min", "sum", "avg"]);
+                assert_eq!(
+                    rows,
+                    vec![vec![
+                        Value::Integer(4),
+                        Value::String("dd".into()),
+                        Value::Integer(1),
+                        Value::Float(13.0),
+                        Value::Float(13.0 / 3.0)
+                    ]]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        // Integer sums stay integers, averages are floats
+        match s.execute("select sum(a), avg(a) from t1;")? {
+            ResultSet::Scan { rows, metadata, .. } => {
+                assert_eq!(rows, vec![vec![Value::Integer(10), Value::Float(2.5)]]);
+                assert_eq!(metadata[0].datatype, Some(DataType::BigInt));
+            }
+            _ => unreachable!(),
+        }
+
+        s.execute("create table t2 (a int primary key, b text, c float);")?;
+        s.execute("insert into t2 values (1, NULL, NULL);")?;
+        s.execute("insert into t2 values (2, NULL, NULL);")?;
+        match s.execute("select count(a) as total, max(b), min(a), sum(c), avg(c) from t2;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["total", "max", "min", "sum", "avg"]);
+                assert_eq!(
+                    rows,
+                    vec![vec![
+                        Value::Integer(2),
+                        Value::Null,
+                        Value::Integer(1),
+                        Value::Null,
+                        Value::Null
+                    ]]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+
+        s.execute("insert into t1 values (1, 'aa', 3.1);")?;
+        s.execute("insert into t1 values (2, 'bb', 5.3);")?;
+        s.execute("insert into t1 values (3, null, NULL);")?;
+        s.execute("insert into t1 values (4, null, 4.6);")?;
+        s.execute("insert into t1 values (5, 'bb', 5.8);")?;
+        s.execute("insert into t1 values (6, 'dd', 1.4);")?;
+
+        match s.execute("select b, min(c), max(a), avg(c) from t1 group by b order by avg;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["b", "min", "max", "avg"]);
+                assert_eq!(
+                    rows,
+                    vec![
+                        vec![
+                            Value::String("dd".into()),
+                            Value::Float(1.4),
+                            Value::Integer(6),
+                            Value::Float(1.4)
+                        ],
+                        vec![
+                            Value::String("aa".into()),
+                            Value::Float(3.1),
+                            Value::Integer(1),
+                            Value::Float(3.1)
+                        ],
+                        vec![
+                            Value::Null,
+                            Value::Float(4.6),
+                            Value::Integer(4),
+                            Value::Float(4.6)
+                        ],
+                        vec![
+                            Value::String("bb".into()),
+                            Value::Float(5.3),
+                            Value::Integer(5),
+                            Value::Float(5.55)
+                        ],
+                    ]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        setup_table(&mut s)?;
+        s.execute("create table t1 (a int primary key, b text, c float, d bool);")?;
+
+        s.execute("insert into t1 values (1, 'aa', 3.1, true);")?;
+        s.execute("insert into t1 values (2, 'bb', 5.3, true);")?;
+        s.execute("insert into t1 values (3, null, NULL, false);")?;
+        s.execute("insert into t1 values (4, null, 4.6, false);")?;
+        s.execute("insert into t1 values (5, 'bb', 5.8, true);")?;
+        s.execute("insert into t1 values (6, 'dd', 1.4, false);")?;
+
+        match s.execute("select * from t1 where d < true;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(4, columns.len());
+                assert_eq!(3, rows.len());
+            }
+            _ => unreachable!(),
+        }
+
+        match s.execute("select b, sum(c) from t1 group by b having sum < 5 order by sum;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(2, columns.len());
+                assert_eq!(3, rows.len());
+            }
+            _ => unreachable!(),
+        }
+
         Ok(())
     }
 
     #[test]
-    fn test_insert() -> Result<()> {
+    fn test_hash_partition() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        setup_table(&mut s)?;
-
-        // t1
-        s.execute("insert into t1 (a) values (1);")?;
-        s.execute("insert into t1 values (2, 'a', 2);")?;
-        s.execute("insert into t1(b,a) values ('b', 3);")?;
+        s.execute("create table t1 (a int primary key, b text) partition by hash (a) partitions 4;")?;
+        assert!(s.execute("create table t2 (a int primary key, b int) partition by hash (b) partitions 4;").is_err());
 
+        for i in 1..=8 {
+            s.execute(&format!("insert into t1 values ({}, 'v{}');", i, i))?;
+        }
+        assert!(s.execute("insert into t1 values (3, 'dup');").is_err());
+
+        // Rows land in more than one shard
+        let txn = kvengine.kv.begin()?;
+        let shards = (0..4)
+            .filter(|shard| {
+                let prefix = super::KeyPrefix::ShardRow(*shard, "t1".into()).encode().unwrap();
+                !txn.scan_prefix(prefix).unwrap().is_empty()
+            })
+            .count();
+        assert!(shards > 1);
+        txn.commit()?;
+
+        s.execute("update t1 set a = 10 where a = 2;")?;
+        s.execute("delete from t1 where a > 5;")?;
         scan_table_and_compare(
             &mut s,
             "t1",
-            vec![
-                vec![
-                    Value::Integer(1),
-                    Value::String("vv".to_string()),
-                    Value::Integer(100),
-                ],
-                vec![
-                    Value::Integer(2),
-                    Value::String("a".to_string()),
-                    Value::Integer(2),
-                ],
-                vec![
-                    Value::Integer(3),
-                    Value::String("b".to_string()),
-                    Value::Integer(100),
-                ],
-            ],
-        )?;
-
-        // t2
-        s.execute("insert into t2 (a) values (1);")?;
-        scan_table_and_compare(
-            &mut s,
-            "t2",
-            vec![vec![
-                Value::Integer(1),
-                Value::Integer(100),
-                Value::Float(1.1),
-                Value::Boolean(false),
-                Value::Boolean(true),
-                Value::String("v1".to_string()),
-                Value::String("v2".to_string()),
-                Value::String("v3".to_string()),
-            ]],
+            [1, 3, 4, 5]
+                .iter()
+                .map(|i| vec![Value::Integer(*i), Value::String(format!("v{}", i).into())])
+                .collect(),
         )?;
+        Ok(())
+    }
 
-        // t3
-        s.execute("insert into t3 (a, d) values (1, 1.1);")?;
-        scan_table_and_compare(
-            &mut s,
-            "t3",
-            vec![vec![
-                Value::Integer(1),
-                Value::Integer(12),
-                Value::Null,
-                Value::Float(1.1),
-            ]],
-        )?;
+    #[test]
+    fn test_point() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b point default point(0, 0));")?;
+        s.execute("insert into t1 values (1, point(1.0, 2.0)), (2, point(3, 4)), (3, point(5.5, 0.5));")?;
+        s.execute("insert into t1 (a) values (4);")?;
+        assert!(s.execute("insert into t1 values (5, 1);").is_err());
+
+        let ids = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> Result<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.into_iter().map(|r| r[0].clone()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(
+            ids(&mut s, "select a from t1 where distance(b, point(0, 0)) < 3;")?,
+            vec![Value::Integer(1), Value::Integer(4)]
+        );
+        assert_eq!(
+            ids(&mut s, "select a from t1 where within(b, point(4, 4), point(0.5, 1));")?,
+            vec![Value::Integer(1), Value::Integer(2)]
+        );
+        assert_eq!(
+            ids(&mut s, "select a from t1 where b = point(3, 4);")?,
+            vec![Value::Integer(2)]
+        );
+
+        match s.execute("select a, distance(b, point(0, 0)) as d from t1 where a = 2;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["a".to_string(), "d".to_string()]);
+                assert_eq!(rows, vec![vec![Value::Integer(2), Value::Float(5.0)]]);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
 
-        // t4
-        s.execute("insert into t4 (a) values (true);")?;
-        scan_table_and_compare(
-            &mut s,
-            "t4",
-            vec![vec![
-                Value::Boolean(true),
-                Value::Integer(12),
-                Value::Boolean(true),
-            ]],
-        )?;
+    #[test]
+    fn test_uuid() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a uuid primary key default gen_random_uuid(), b int);")?;
+        s.execute("insert into t1 (b) values (1), (2);")?;
+        s.execute("insert into t1 values ('00000000-0000-4000-8000-000000000001', 3);")?;
+        s.execute("insert into t1 values ('ffffffff-ffff-4fff-bfff-ffffffffffff', 4);")?;
+        assert!(s.execute("insert into t1 values ('00000000-0000-4000-8000-000000000001', 5);").is_err());
+        assert!(s.execute("insert into t1 values ('not a uuid', 6);").is_err());
+
+        let rows = match s.execute("select * from t1 order by a;")? {
+            ResultSet::Scan { rows, .. } => rows,
+            _ => unreachable!(),
+        };
+        assert_eq!(rows.len(), 4);
+        // Generated defaults are distinct, version 4 UUIDs
+        assert_ne!(rows[1][0], rows[2][0]);
+        for row in &rows {
+            let text = row[0].to_string();
+            assert_eq!(text.len(), 36);
+            assert_eq!(&text[14..15], "4");
+            assert_eq!(Value::parse_uuid(&text), Some(row[0].clone()));
+        }
+        assert_eq!(rows[0][1], Value::Integer(3));
+        assert_eq!(rows[3][1], Value::Integer(4));
 
+        match s.execute("select b from t1 where a = '00000000000040008000000000000001';")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(3)]]),
+            _ => unreachable!(),
+        }
         Ok(())
     }
 
     #[test]
-    fn test_update() -> Result<()> {
+    fn test_collation() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        setup_table(&mut s)?;
+        s.execute("create table t1 (a text primary key collate nocase, b text collate unicode, c int);")?;
+        assert!(s.execute("create table t2 (a int primary key collate nocase);").is_err());
+        assert!(s.execute("create table t2 (a text primary key collate klingon);").is_err());
+
+        s.execute("insert into t1 values ('Foo', 'Äpfel', 1), ('bar', 'äpfel', 2), ('Baz', 'birne', 3);")?;
+        // Primary key uniqueness ignores case
+        assert!(s.execute("insert into t1 values ('foo', 'x', 4);").is_err());
+
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> Result<Vec<Row>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows),
+                _ => unreachable!(),
+            }
+        };
+        let text = |v: &str| Value::String(v.into());
+        assert_eq!(
+            rows(&mut s, "select a from t1 order by a;")?,
+            vec![vec![text("bar")], vec![text("Baz")], vec![text("Foo")]]
+        );
+        assert_eq!(rows(&mut s, "select c from t1 where a = 'FOO';")?, vec![vec![Value::Integer(1)]]);
+        assert_eq!(rows(&mut s, "select c from t1 where a > 'BAR';")?.len(), 2);
+        assert_eq!(
+            rows(&mut s, "select c from t1 where b = 'ÄPFEL';")?,
+            vec![vec![Value::Integer(2)], vec![Value::Integer(1)]]
+        );
+
+        let mut groups = rows(&mut s, "select b, count(c) from t1 group by b;")?;
+        groups.sort_by(|x, y| x[1].partial_cmp(&y[1]).unwrap());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1][1], Value::Integer(2));
+
+        s.execute("update t1 set c = 10 where a = 'BAZ';")?;
+        s.execute("delete from t1 where a = 'FOO';")?;
+        assert_eq!(
+            rows(&mut s, "select a, c from t1;")?,
+            vec![vec![text("bar"), Value::Integer(2)], vec![text("Baz"), Value::Integer(10)]]
+        );
+        Ok(())
+    }
 
-        s.execute("insert into t2 values (1, 1, 1.1, true, true, 'v1', 'v2', 'v3');")?;
-        s.execute("insert into t2 values (2, 2, 2.2, false, false, 'v4', 'v5', 'v6');")?;
-        s.execute("insert into t2 values (3, 3, 3.3, true, false, 'v7', 'v8', 'v9');")?;
-        s.execute("insert into t2 values (4, 4, 4.4, false, true, 'v10', 'v11', 'v12');")?;
+    #[test]
+    fn test_integer_widths() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b smallint default 1, c bigint);")?;
+        assert!(s.execute("create table t2 (a int primary key, b smallint default 40000);").is_err());
+
+        s.execute("insert into t1 values (1, 32767, 170141183460469231731687303715884105727);")?;
+        s.execute("insert into t1 values (9223372036854775807, 2 * 3 + 1, 9223372036854775808 * 2 - 2);")?;
+        assert!(s.execute("insert into t1 values (2, 32768, 0);").is_err());
+        // INTEGER arithmetic overflows past 64 bits, even into a BIGINT
+        assert!(s.execute("insert into t1 values (2, 1, 9223372036854775807 * 2);").is_err());
+        assert!(s.execute("insert into t1 values (9223372036854775806 + 2, 1, 0);").is_err());
+        assert!(s.execute("insert into t1 values (3, 1, 170141183460469231731687303715884105727 + 1);").is_err());
+        assert!(s.execute("insert into t1 values (3, 1, 1701411834604692317316873037158841057270);").is_err());
+        assert!(s.execute("insert into t1 values (3, 1 / 0, 0);").is_err());
+
+        s.execute("update t1 set c = c - 1 where a = 1;")?;
+        assert!(s.execute("update t1 set b = b + 1 where a = 1;").is_err());
+
+        match s.execute("select a, b * 2 + c / 4 as d from t1 where b + 0 < 100;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["a".to_string(), "d".to_string()]);
+                assert_eq!(
+                    rows,
+                    vec![vec![Value::Integer(9223372036854775807), Value::Integer(14 + 4611686018427387903)]]
+                );
+            }
+            _ => unreachable!(),
+        }
+        match s.execute("select c from t1 where a = 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(i128::MAX - 1)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
 
-        let res = s.execute("update t2 set b = 100 where a = 1;")?;
-        assert_eq!(res, ResultSet::Update { count: 1 });
-        let res = s.execute("update t2 set d = false where d = true;")?;
-        assert_eq!(res, ResultSet::Update { count: 2 });
+    #[test]
+    fn test_primary_key_lookup() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (a text primary key collate nocase, b int) partition by hash (a) partitions 4;")?;
+        for i in 1..=5 {
+            s.execute(&format!("insert into t1 values ({}, 'v{}');", i, i))?;
+            s.execute(&format!("insert into t2 values ('k{}', {});", i, i))?;
+        }
 
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(rows(&mut s, "select b from t1 where a = 3;"), vec![vec![Value::String("v3".into())]]);
+        assert_eq!(rows(&mut s, "select * from t1 where 4 = a;").len(), 1);
+        assert!(rows(&mut s, "select * from t1 where a = 9;").is_empty());
+        assert_eq!(rows(&mut s, "select b from t2 where a = 'K2';"), vec![vec![Value::Integer(2)]]);
+
+        s.execute("update t1 set b = 'x' where a = 2;")?;
+        s.execute("delete from t2 where a = 'k5';")?;
         scan_table_and_compare(
             &mut s,
-            "t2",
+            "t1",
             vec![
-                vec![
-                    Value::Integer(1),
-                    Value::Integer(100),
-                    Value::Float(1.1),
-                    Value::Boolean(false),
-                    Value::Boolean(true),
-                    Value::String("v1".to_string()),
-                    Value::String("v2".to_string()),
-                    Value::String("v3".to_string()),
-                ],
-                vec![
-                    Value::Integer(2),
-                    Value::Integer(2),
-                    Value::Float(2.2),
-                    Value::Boolean(false),
-                    Value::Boolean(false),
-                    Value::String("v4".to_string()),
-                    Value::String("v5".to_string()),
-                    Value::String("v6".to_string()),
-                ],
-                vec![
-                    Value::Integer(3),
-                    Value::Integer(3),
-                    Value::Float(3.3),
-                    Value::Boolean(false),
-                    Value::Boolean(false),
-                    Value::String("v7".to_string()),
-                    Value::String("v8".to_string()),
-                    Value::String("v9".to_string()),
-                ],
-                vec![
-                    Value::Integer(4),
-                    Value::Integer(4),
-                    Value::Float(4.4),
-                    Value::Boolean(false),
-                    Value::Boolean(true),
-                    Value::String("v10".to_string()),
-                    Value::String("v11".to_string()),
-                    Value::String("v12".to_string()),
-                ],
+                vec![Value::Integer(1), Value::String("v1".into())],
+                vec![Value::Integer(2), Value::String("x".into())],
+                vec![Value::Integer(3), Value::String("v3".into())],
+                vec![Value::Integer(4), Value::String("v4".into())],
+                vec![Value::Integer(5), Value::String("v5".into())],
             ],
         )?;
-
+        assert_eq!(rows(&mut s, "select * from t2;").len(), 4);
         Ok(())
     }
 
     #[test]
-    fn test_delete() -> Result<()> {
+    fn test_select_as_of() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        setup_table(&mut s)?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("insert into t1 values (1, 'a');")?;
+        let version = kvengine.kv.begin()?.version();
+        s.execute("update t1 set b = 'b' where a = 1;")?;
+        s.execute("insert into t1 values (2, 'c');")?;
+
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let sql = format!("select * from t1 as of version {} where a > 0;", version);
+        assert_eq!(rows(&mut s, &sql), vec![vec![Value::Integer(1), Value::String("a".into())]]);
+        let sql = format!("select b from t1 as of version {} where a = 1;", version);
+        assert_eq!(rows(&mut s, &sql), vec![vec![Value::String("a".into())]]);
+        assert_eq!(rows(&mut s, "select * from t1;").len(), 2);
+
+        // Version 1 created the table, and future versions don't exist yet
+        assert!(rows(&mut s, "select * from t1 as of version 1;").is_empty());
+        assert!(s.execute("select * from t1 as of version 1000;").is_err());
+        Ok(())
+    }
 
-        s.execute("insert into t2 values (1, 1, 1.1, true, true, 'v1', 'v2', 'v3');")?;
-        s.execute("insert into t2 values (2, 2, 2.2, false, false, 'v4', 'v5', 'v6');")?;
-        s.execute("insert into t2 values (3, 3, 3.3, true, false, 'v7', 'v8', 'v9');")?;
-        s.execute("insert into t2 values (4, 4, 4.4, false, true, 'v10', 'v11', 'v12');")?;
+    #[test]
+    fn test_show_create_table_as_of() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        let before = kvengine.kv.begin()?.version();
+        s.execute("create table t1 (a int primary key, b text default 'x');")?;
+        let version = kvengine.kv.begin()?.version();
+        s.execute("drop table t1;")?;
+        s.execute("create table t1 (a int primary key, c float not null) with (audit = true);")?;
+
+        let show = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { columns, rows, .. }) => {
+                assert_eq!(columns, vec!["table", "create_table"]);
+                assert_eq!(rows[0][0], Value::String("t1".into()));
+                rows[0][1].to_string()
+            }
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(
+            show(&mut s, "show create table t1;"),
+            "CREATE TABLE t1 (a INTEGER NOT NULL PRIMARY KEY, c FLOAT NOT NULL) WITH (audit = TRUE);"
+        );
+        assert_eq!(
+            show(&mut s, &format!("show create table t1 as of version {};", version)),
+            "CREATE TABLE t1 (a INTEGER NOT NULL PRIMARY KEY, b STRING NULL DEFAULT 'x');"
+        );
+        assert!(s.execute(&format!("show create table t1 as of version {};", before)).is_err());
+        assert!(s.execute("show create table t2;").is_err());
+        Ok(())
+    }
 
-        let res = s.execute("delete from t2 where a = 1;")?;
-        assert_eq!(res, ResultSet::Delete { count: 1 });
-        scan_table_and_compare(
-            &mut s,
-            "t2",
-            vec![
-                vec![
-                    Value::Integer(2),
-                    Value::Integer(2),
-                    Value::Float(2.2),
-                    Value::Boolean(false),
-                    Value::Boolean(false),
-                    Value::String("v4".to_string()),
-                    Value::String("v5".to_string()),
-                    Value::String("v6".to_string()),
-                ],
-                vec![
-                    Value::Integer(3),
-                    Value::Integer(3),
-                    Value::Float(3.3),
-                    Value::Boolean(true),
-                    Value::Boolean(false),
-                    Value::String("v7".to_string()),
-                    Value::String("v8".to_string()),
-                    Value::String("v9".to_string()),
-                ],
-                vec![
-                    Value::Integer(4),
-                    Value::Integer(4),
-                    Value::Float(4.4),
-                    Value::Boolean(false),
-                    Value::Boolean(true),
-                    Value::String("v10".to_string()),
-                    Value::String("v11".to_string()),
-                    Value::String("v12".to_string()),
-                ],
-            ],
+    #[test]
+    fn test_show_create_table() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute(
+            "create table t1 (a text primary key collate nocase, b int default 1 + 2, c uuid default uuid()) \
+             partition by hash (a) partitions 4;",
         )?;
+        s.execute("create index t1_b on t1 (b) where b > 1;")?;
+        s.execute("create index on t1 (c, b);")?;
+        let ddl = match s.execute("show create table t1;")? {
+            ResultSet::Scan { rows, .. } => rows[0][1].to_string(),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(
+            ddl,
+            "CREATE TABLE t1 (a STRING NOT NULL PRIMARY KEY COLLATE nocase, b INTEGER NULL DEFAULT 3, \
+             c UUID NULL DEFAULT uuid()) PARTITION BY HASH (a) PARTITIONS 4;\n\
+             CREATE INDEX t1_b ON t1 (b) WHERE b > 1;\n\
+             CREATE INDEX t1_c_b_idx ON t1 (c, b);"
+        );
+
+        // The DDL recreates the same schema
+        let other = KVEngine::new(MemoryEngine::new());
+        other.session()?.execute_script(&ddl)?;
+        let txn = other.begin()?;
+        assert_eq!(txn.must_get_table("t1".into())?, kvengine.begin()?.must_get_table("t1".into())?);
+        txn.commit()?;
+        Ok(())
+    }
 
-        let res = s.execute("delete from t2 where d = false;")?;
-        assert_eq!(res, ResultSet::Delete { count: 2 });
-        scan_table_and_compare(
-            &mut s,
-            "t2",
-            vec![vec![
-                Value::Integer(3),
-                Value::Integer(3),
-                Value::Float(3.3),
-                Value::Boolean(true),
-                Value::Boolean(false),
-                Value::String("v7".to_string()),
-                Value::String("v8".to_string()),
-                Value::String("v9".to_string()),
-            ]],
-        )?;
+    #[test]
+    fn test_materialized_view() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text collate nocase, c int);")?;
+        s.execute("insert into t1 values (1, 'x', 10), (2, 'y', 20), (3, 'x', 30);")?;
+        // Filtered, so only refreshed
+        s.execute("create materialized view v1 as select b, count(*) as n, sum(c) as total from t1 where a > 0 group by b;")?;
+        let query = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let sql = "select * from v1 order by b;";
+        let int = |i: i64| Value::Integer(i.into());
+        let str = |s: &str| Value::String(s.into());
+        assert_eq!(query(&mut s, sql), vec![vec![str("x"), int(2), int(40)], vec![str("y"), int(1), int(20)]]);
+
+        // The view keeps its rows until refreshed, which writes only changes
+        s.execute("insert into t1 values (4, 'z', 5);")?;
+        s.execute("update t1 set c = 25 where a = 2;")?;
+        s.execute("delete from t1 where b = 'x';")?;
+        assert_eq!(query(&mut s, sql).len(), 2);
+        let version = kvengine.begin()?.version();
+        s.execute("refresh materialized view v1;")?;
+        assert_eq!(query(&mut s, sql), vec![vec![str("y"), int(1), int(25)], vec![str("z"), int(1), int(5)]]);
+        // The key column took its source's collation
+        assert_eq!(query(&mut s, "select n from v1 where b = 'Y';"), vec![vec![int(1)]]);
+        assert_eq!(query(&mut s, &format!("select * from v1 as of version {};", version)).len(), 2);
+
+        // A failed refresh rolls back with its transaction
+        s.execute("insert into t1 values (5, null, 1);")?;
+        assert!(s.execute("refresh materialized view v1;").is_err());
+        assert_eq!(query(&mut s, sql).len(), 2);
+
+        // Views are only written by refreshes
+        for sql in ["insert into v1 values ('w', 1, 1);", "update v1 set n = 0;", "delete from v1;"] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        for sql in [
+            "refresh materialized view t1;",
+            "refresh materialized view v2;",
+            "create materialized view v1 as select a from t1;",
+            "create materialized view v2 as select null from t1;",
+            "create materialized view v2 as select b from t1;",
+            "create materialized view v2 as select a from t1 as of version 1;",
+        ] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
 
-        let res = s.execute("delete from t2;")?;
-        assert_eq!(res, ResultSet::Delete { count: 1 });
-        scan_table_and_compare(&mut s, "t2", vec![])?;
+        let ddl = match s.execute("show create table v1;")? {
+            ResultSet::Scan { rows, .. } => rows[0][1].to_string(),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(
+            ddl,
+            "CREATE MATERIALIZED VIEW v1 AS SELECT b, count(*) AS n, sum(c) AS total FROM t1 WHERE a > 0 GROUP BY b;"
+        );
+        s.execute("drop table v1;")?;
+        assert!(kvengine.begin()?.get_table("v1".into())?.is_none());
+        Ok(())
+    }
 
+    #[test]
+    fn test_count_rows() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (a text primary key collate nocase) with (storage = 'columnar');")?;
+        s.execute("create table t3 (a int primary key) partition by hash (a) partitions 4;")?;
+        let count = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows[0][0].clone(),
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(count(&mut s, "select count(*) from t1;"), Value::Integer(0));
+
+        s.execute("insert into t1 values (1, 'a'), (2, null), (3, 'c'), (4, 'd');")?;
+        s.execute("insert into t2 values ('x'), ('y');")?;
+        s.execute("insert into t3 values (1), (2), (3);")?;
+        let version = kvengine.kv.begin()?.version();
+        s.execute("delete from t1 where a > 2;")?;
+        // Moving a row onto another's key overwrites it
+        s.execute("update t1 set a = 1 where a = 2;")?;
+        s.execute("update t2 set a = 'z' where a = 'y';")?;
+        s.execute("delete from t3 where a = 9;")?;
+        assert!(s.execute("insert into t2 values ('Z');").is_err());
+        assert!(s.execute("insert into t3 values (4), (1);").is_err());
+
+        for (table, rows) in [("t1", 1), ("t2", 2), ("t3", 3)] {
+            let sql = format!("select count(*) from {};", table);
+            assert_eq!(count(&mut s, &sql), Value::Integer(rows), "{}", table);
+            match s.execute(&format!("select * from {};", table))? {
+                ResultSet::Scan { rows: scanned, .. } => assert_eq!(scanned.len() as i128, rows, "{}", table),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        let sql = format!("select count(*) from t1 as of version {};", version);
+        assert_eq!(count(&mut s, &sql), Value::Integer(4));
+
+        // Concurrent writers keep separate counts, so they don't conflict
+        let (mut txn1, mut txn2) = (kvengine.begin()?, kvengine.begin()?);
+        txn1.create_row("t1".into(), vec![Value::Integer(10), Value::Null])?;
+        txn2.create_row("t1".into(), vec![Value::Integer(11), Value::Null])?;
+        txn1.commit()?;
+        txn2.commit()?;
+        assert_eq!(count(&mut s, "select count(*) from t1;"), Value::Integer(3));
+
+        // Commits fold the deltas, so COUNT(*) reads a bounded number of keys
+        let version = kvengine.kv.begin()?.version();
+        for i in 100..400 {
+            s.execute(&format!("insert into t1 values ({}, null);", i))?;
+        }
+        let txn = kvengine.begin()?;
+        let deltas = txn.txn.scan_prefix(super::KeyPrefix::RowCount("t1".into()).encode()?)?.len();
+        assert!(deltas <= super::ROW_COUNT_DELTAS, "{} row count deltas", deltas);
+        txn.rollback()?;
+        assert_eq!(count(&mut s, "select count(*) from t1;"), Value::Integer(303));
+        let sql = format!("select count(*) from t1 as of version {};", version);
+        assert_eq!(count(&mut s, &sql), Value::Integer(3));
         Ok(())
     }
 
     #[test]
-    fn test_sort() -> Result<()> {
+    fn test_scan_limit() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        setup_table(&mut s)?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (a int primary key, b int) with (storage = 'columnar');")?;
+        s.execute("create table t3 (a int primary key, b int) partition by hash (a) partitions 4;")?;
+        for table in ["t1", "t2", "t3"] {
+            let values = (1..=20).map(|i| format!("({}, {})", i, i % 3)).collect::<Vec<_>>();
+            s.execute(&format!("insert into {} values {};", table, values.join(", ")))?;
+            // Older versions, deleted rows and rows in the middle of the scan
+            s.execute(&format!("update {} set b = 5 where b = 2;", table))?;
+            s.execute(&format!("delete from {} where a = 1;", table))?;
+        }
 
-        s.execute("insert into t3 values (1, 34, 22, 1.22);")?;
-        s.execute("insert into t3 values (4, 23, 65, 4.23);")?;
-        s.execute("insert into t3 values (3, 56, 22, 2.88);")?;
-        s.execute("insert into t3 values (2, 87, 57, 6.78);")?;
-        s.execute("insert into t3 values (5, 87, 14, 3.28);")?;
-        s.execute("insert into t3 values (7, 87, 82, 9.52);")?;
+        // A limited scan returns the first rows of the full scan
+        let rows = |txn: &KVTransaction<MemoryEngine>, table: &str, filter: &str, limit| -> Result<Vec<Row>> {
+            let filter = match Parser::new(&format!("select * from t where {};", filter)).parse()? {
+                ast::Statement::Select { where_clause, .. } => where_clause,
+                stmt => panic!("unexpected statement {:?}", stmt),
+            };
+            match limit {
+                Some(limit) => txn.scan_table_limit(table.into(), filter, None, limit),
+                None => txn.scan_table(table.into(), filter)?.collect(),
+            }
+        };
+        let mut txn = kvengine.begin()?;
+        txn.create_row("t1".into(), vec![Value::Integer(0), Value::Integer(5)])?;
+        for table in ["t1", "t2", "t3"] {
+            for filter in ["a > 0", "b = 5", "b = 9"] {
+                for limit in [0, 1, 3, 30] {
+                    let mut expected = rows(&txn, table, filter, None)?;
+                    expected.truncate(limit);
+                    assert_eq!(rows(&txn, table, filter, Some(limit))?, expected, "{} {} {}", table, filter, limit);
+                }
+            }
+        }
+        txn.rollback()?;
+        Ok(())
+    }
 
-        match s.execute("select a, b as col2 from t3 order by c, a desc limit 100;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(2, columns.len());
-                assert_eq!(6, rows.len());
+    #[test]
+    fn test_scan_rev() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (a int primary key, b int) with (storage = 'columnar');")?;
+        s.execute("create table t3 (a int primary key, b int) partition by hash (a) partitions 4;")?;
+        for table in ["t1", "t2", "t3"] {
+            let values = (-10..=10).map(|i| format!("({}, {})", i, i % 3)).collect::<Vec<_>>();
+            s.execute(&format!("insert into {} values {};", table, values.join(", ")))?;
+            s.execute(&format!("update {} set b = 5 where b = 2;", table))?;
+            s.execute(&format!("delete from {} where a = 1;", table))?;
+        }
+        s.execute("create table t4 (a text primary key collate nocase, b int);")?;
+        s.execute("insert into t4 values ('b', 1), ('C', 2), ('a', 3), ('D', 4);")?;
+
+        // A reverse scan returns the rows of a full scan, largest key first
+        let filter = |sql: &str| -> Result<Option<ast::Expression>> {
+            match Parser::new(&format!("select * from t where {};", sql)).parse()? {
+                ast::Statement::Select { where_clause, .. } => Ok(where_clause),
+                stmt => panic!("unexpected statement {:?}", stmt),
+            }
+        };
+        let mut txn = kvengine.begin()?;
+        txn.create_row("t1".into(), vec![Value::Integer(20), Value::Integer(5)])?;
+        for table in ["t1", "t2", "t3", "t4"] {
+            for sql in ["b > -5", "b = 5", "b = 9"] {
+                let mut expected = txn.scan_table(table.into(), filter(sql)?)?.collect::<Result<Vec<_>>>()?;
+                super::sort_desc(&txn.must_get_table(table.into())?, &mut expected);
+                for limit in [0, 1, 3, 30] {
+                    let rows = txn.scan_table_rev(table.into(), filter(sql)?, None, Some(limit))?;
+                    assert_eq!(rows[..], expected[..limit.min(expected.len())], "{} {} {}", table, sql, limit);
+                }
+                assert_eq!(txn.scan_table_rev(table.into(), filter(sql)?, None, None)?, expected);
             }
-            _ => unreachable!(),
         }
+        txn.rollback()?;
 
+        // ORDER BY pk DESC reads backwards, from the largest (positive) key
+        let mut keys = |sql: &str| -> Result<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.into_iter().map(|row| row[0].clone()).collect()),
+                result => panic!("unexpected result {:?}", result),
+            }
+        };
+        let ints = |values: &[i128]| values.iter().map(|v| Value::Integer(*v)).collect::<Vec<_>>();
+        assert_eq!(keys("select a from t1 order by a desc limit 3;")?, ints(&[10, 9, 8]));
+        assert_eq!(keys("select a from t1 where a < 0 order by a desc limit 2 offset 1;")?, ints(&[-2, -3]));
+        assert_eq!(keys("select a from t3 order by a desc limit 2;")?, ints(&[10, 9]));
+        assert_eq!(keys("select a from t1 order by a limit 2;")?, ints(&[-10, -9]));
+        let strings = keys("select a from t4 order by a desc;")?;
+        assert_eq!(strings, ["D", "C", "b", "a"].map(|s| Value::String(s.into())));
         Ok(())
     }
 
     #[test]
-    fn test_cross_join() -> Result<()> {
+    fn test_scan_columns() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key);")?;
-        s.execute("create table t2 (b int primary key);")?;
-        s.execute("create table t3 (c int primary key);")?;
+        s.execute("create table t1 (a int primary key, b bool, c text, d float, e point, f uuid, g text);")?;
+        s.execute("create table t2 (a int primary key, b text, c text) partition by hash (a) partitions 2;")?;
+        s.execute(
+            "insert into t1 values (1, true, 'one', 1.5, point(1, 2), '67e55044-10b1-426f-9247-bb680e5fe0c8', 'x'), \
+             (2, null, 'two', null, null, null, 'y');",
+        )?;
+        s.execute("insert into t2 values (1, 'x', 'y'), (2, 'z', 'w'), (3, null, 'v');")?;
+
+        // Row tables decode only the read columns and the primary key, skipping values of every type
+        let txn = kvengine.begin()?;
+        let full = txn.scan_table("t1".into(), None)?.collect::<Result<Vec<_>>>()?;
+        for read in [vec![], vec!["g"], vec!["b", "e"], vec!["c", "d", "f"]] {
+            let read = read.into_iter().map(String::from).collect::<Vec<_>>();
+            let expected = full
+                .iter()
+                .map(|row| {
+                    let names = ["a", "b", "c", "d", "e", "f", "g"];
+                    let keep = |(v, name): (&Value, &str)| match name == "a" || read.iter().any(|r| r == name) {
+                        true => v.clone(),
+                        false => Value::Null,
+                    };
+                    row.iter().zip(names).map(keep).collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(txn.scan_table_columns("t1".into(), None, &read)?, expected, "{:?}", read);
+        }
+        // Filters read their columns too
+        let filter = match Parser::new("select * from t1 where g = 'y';").parse()? {
+            ast::Statement::Select { where_clause, .. } => where_clause,
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        let (null, text) = (Value::Null, |s: &str| Value::String(s.into()));
+        assert_eq!(
+            txn.scan_table_columns("t1".into(), filter, &["c".into()])?,
+            vec![vec![Value::Integer(2), null.clone(), text("two"), null.clone(), null.clone(), null, text("y")]]
+        );
+        txn.rollback()?;
+
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(rows(&mut s, "select c from t1 where b = true;"), vec![vec![Value::String("one".into())]]);
+        assert_eq!(rows(&mut s, "select c from t2 where b = 'z' limit 1;"), vec![vec![Value::String("w".into())]]);
+        assert_eq!(rows(&mut s, "select a from t2 order by c;"), [3, 2, 1].map(|a| vec![Value::Integer(a)]).to_vec());
+        Ok(())
+    }
 
-        s.execute("insert into t1 values (1), (2), (3);")?;
-        s.execute("insert into t2 values (4), (5), (6);")?;
-        s.execute("insert into t3 values (7), (8), (9);")?;
+    #[test]
+    fn test_session_transactions() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let (mut s, mut other) = (kvengine.session()?, kvengine.session()?);
+        s.execute("create table t1 (a int primary key);")?;
+        let count = |s: &mut Session<KVEngine<MemoryEngine>>| match s.execute("select count(*) from t1;") {
+            Ok(ResultSet::Scan { rows, .. }) => rows[0][0].clone(),
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        // Explicit transactions keep their writes to themselves until COMMIT
+        assert_eq!(s.execute("begin;")?, ResultSet::Command { tag: "BEGIN".into() });
+        s.execute("insert into t1 values (1);")?;
+        assert!(s.execute("begin;").is_err());
+        assert_eq!((count(&mut s), count(&mut other)), (Value::Integer(1), Value::Integer(0)));
+        assert_eq!(s.execute("commit;")?, ResultSet::Command { tag: "COMMIT".into() });
+        assert_eq!(count(&mut other), Value::Integer(1));
+
+        s.execute("begin transaction;")?;
+        s.execute("insert into t1 values (2);")?;
+        s.execute("rollback;")?;
+        // A failed statement rolls back its whole transaction, which then only takes ROLLBACK or COMMIT
+        s.execute("begin;")?;
+        s.execute("insert into t1 values (3);")?;
+        assert!(s.execute("insert into t1 values (1);").is_err());
+        assert!(s.in_transaction() && s.is_aborted());
+        for sql in ["select * from t1;", "insert into t1 values (3);", "begin;", "set autocommit = off;"] {
+            assert_eq!(s.execute(sql), Err(Error::TransactionAborted), "{}", sql);
+        }
+        assert!(s.insert_rows("t1", Vec::new(), vec![vec![Value::Integer(3)]]).is_err());
+        assert!(s.execute_script("select * from t1;").is_err());
+        assert_eq!(s.execute("commit;")?, ResultSet::Command { tag: "ROLLBACK".into() });
+        assert!(!s.in_transaction());
+        assert_eq!(count(&mut s), Value::Integer(1));
+        // Malformed statements abort it too
+        s.execute("begin;")?;
+        s.execute("insert into t1 values (3);")?;
+        assert!(matches!(s.execute("insert t1 values (4);"), Err(Error::Parse(_))));
+        assert!(matches!(s.execute("select from;"), Err(Error::Parse(_))));
+        assert_eq!(s.execute("select * from t1;"), Err(Error::TransactionAborted));
+        s.execute("rollback;")?;
+        assert_eq!(count(&mut s), Value::Integer(1));
+
+        // With autocommit off, statements run in one transaction until COMMIT or ROLLBACK
+        s.execute("set autocommit = off;")?;
+        s.execute("insert into t1 values (4);")?;
+        assert!(s.in_transaction());
+        assert_eq!(count(&mut other), Value::Integer(1));
+        s.execute("commit;")?;
+        s.execute("insert into t1 values (5);")?;
+        s.execute("rollback;")?;
+        assert_eq!(count(&mut other), Value::Integer(2));
+        // Failures end the implicit transaction of autocommit off instead of aborting it
+        s.execute("insert into t1 values (5);")?;
+        assert!(s.execute("insert into t1 values (1);").is_err());
+        assert!(!s.in_transaction() && !s.is_aborted());
+        // BEGIN, and turning autocommit back on, commit the open transaction
+        s.execute("insert into t1 values (6);")?;
+        s.execute("begin;")?;
+        assert_eq!(count(&mut other), Value::Integer(3));
+        s.execute("rollback;")?;
+        s.execute("insert into t1 values (7);")?;
+        s.execute("set autocommit = 1;")?;
+        assert!(!s.in_transaction());
+        s.execute("insert into t1 values (8);")?;
+        assert_eq!(count(&mut other), Value::Integer(5));
+
+        // COMMIT and ROLLBACK without a transaction do nothing
+        assert_eq!(s.execute("rollback;")?, ResultSet::Command { tag: "ROLLBACK".into() });
+        for sql in ["set autocommit = maybe;", "set isolation = 1;", "set durability = never;"] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        for durability in ["always", "periodic", "off", "default"] {
+            s.execute(&format!("set durability = {};", durability))?;
+            s.execute("insert into t1 values (10);")?;
+            s.execute("delete from t1 where a = 10;")?;
+        }
+        // Scripts can't run inside an open transaction
+        s.execute("begin;")?;
+        assert!(s.execute_script("insert into t1 values (9);").is_err());
+        assert!(other.execute_script("begin; insert into t1 values (9); commit;").is_err());
+        s.execute("insert into t1 values (9);")?;
+        // Dropped sessions roll back their transaction
+        drop(s);
+        assert_eq!(count(&mut other), Value::Integer(5));
+        Ok(())
+    }
 
-        match s.execute("select * from t1 cross join t2 cross join t3;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(3, columns.len());
-                assert_eq!(27, rows.len());
-                // for row in rows {
-                //     println!("{:?}", row);
-                // }
+    #[test]
+    fn test_serializable_isolation() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let (mut s, mut other) = (kvengine.session()?, kvengine.session()?);
+        s.execute("create table t1 (a int primary key, oncall bool);")?;
+        s.execute("insert into t1 values (1, true), (2, true);")?;
+
+        // Both go off call after seeing the other on call: snapshot isolation
+        // lets both commit (write skew), serializable fails the second
+        for (isolation, skewed) in [("snapshot", true), ("serializable", false)] {
+            s.execute(&format!("set isolation = {};", isolation))?;
+            other.execute(&format!("set isolation = {};", isolation))?;
+            s.execute("update t1 set oncall = true;")?;
+            s.execute("begin;")?;
+            other.execute("begin;")?;
+            s.execute("select * from t1 where oncall = true;")?;
+            other.execute("select * from t1 where oncall = true;")?;
+            s.execute("update t1 set oncall = false where a = 1;")?;
+            s.execute("commit;")?;
+            other.execute("update t1 set oncall = false where a = 2;")?;
+            match other.execute("commit;") {
+                Ok(_) => assert!(skewed),
+                Err(err) => assert!(!skewed && matches!(err, Error::WriteConflict { .. }), "{}", err),
             }
-            _ => unreachable!(),
+            assert!(!other.in_transaction());
         }
 
+        // Scanning a range with a concurrent insert fails right away
+        other.execute("begin;")?;
+        other.execute("insert into t1 values (3, true);")?;
+        assert!(matches!(s.execute("select * from t1;"), Err(Error::WriteConflict { .. })));
+        other.execute("commit;")?;
+        assert!(s.execute("select * from t1;").is_ok());
+        assert!(s.execute("set isolation = repeatable;").is_err());
         Ok(())
     }
 
     #[test]
-    fn test_join() -> Result<()> {
+    fn test_select_for_update() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let (mut s, mut other) = (kvengine.session()?, kvengine.session()?);
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (c int primary key) with (storage = 'columnar');")?;
+        s.execute("insert into t1 values (1, 10), (2, 20), (3, 30);")?;
+        s.execute("insert into t2 values (1);")?;
+
+        // Locked rows reject other writers and lockers right away, until the holder ends
+        s.execute("begin;")?;
+        let rows = match s.execute("select b from t1 where a = 1 for update;")? {
+            ResultSet::Scan { rows, .. } => rows,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(rows, vec![vec![Value::Integer(10)]]);
+        let version = kvengine.kv.active_transactions()?[0].0;
+        assert_eq!(
+            other.execute("update t1 set b = 11 where a = 1;"),
+            Err(Error::WriteConflict { key: "1".into(), table: Some("t1".into()), version })
+        );
+        assert!(other.execute("select * from t1 where a = 1 for update;").is_err());
+        assert!(other.execute("select * from t1 where a = 1;").is_ok());
+        other.execute("update t1 set b = 21 where a = 2;")?;
+        // The holder's own writes go through
+        s.execute("update t1 set b = 11 where a = 1;")?;
+        s.execute("commit;")?;
+        other.execute("update t1 set b = 12 where a = 1;")?;
+
+        // Joins lock the rows of every table; LIMIT only locks the rows it returns
+        s.execute("begin;")?;
+        s.execute("select * from t1 cross join t2 limit 1 for update;")?;
+        assert!(other.execute("delete from t2;").is_err());
+        assert!(other.execute("update t1 set b = 0 where a = 1;").is_err());
+        other.execute("update t1 set b = 0 where a = 2;")?;
+        s.execute("rollback;")?;
+        other.execute("delete from t2;")?;
+
+        for sql in [
+            "select count(a) from t1 for update;",
+            "select b from t1 group by b for update;",
+            "select * from system.transactions for update;",
+        ] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_budget() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key);")?;
-        s.execute("create table t2 (b int primary key);")?;
-        s.execute("create table t3 (c int primary key);")?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        for i in 0..20 {
+            s.execute(&format!("insert into t1 values ({}, 'row{}');", i, i))?;
+        }
 
-        s.execute("insert into t1 values (1), (2), (3);")?;
-        s.execute("insert into t2 values (2), (3), (4);")?;
-        s.execute("insert into t3 values (3), (8), (9);")?;
+        // 400 joined rows don't fit in 10KB, but the plain scan and small sorts do
+        s.set_memory_budget(Some(10_000));
+        assert_eq!(s.execute("select * from t1 cross join t1;"), Err(Error::OutOfMemoryBudget(10_000)));
+        assert!(s.execute("select * from t1;").is_ok());
+        assert!(s.execute("select * from t1 order by b;").is_ok());
+        assert!(s.execute("select count(a) from t1 group by b;").is_ok());
+
+        s.set_memory_budget(Some(100));
+        assert_eq!(s.execute("select * from t1 order by b;"), Err(Error::OutOfMemoryBudget(100)));
+        assert_eq!(s.execute("select count(a) from t1 group by b;"), Err(Error::OutOfMemoryBudget(100)));
+        // A top-N sort only holds the rows it returns
+        assert!(s.execute("select * from t1 order by b limit 1;").is_ok());
+
+        s.set_memory_budget(None);
+        match s.execute("select * from t1 cross join t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 400),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
 
-        match s.execute("select * from t1 right join t2 on a = b join t3 on a = c;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(3, columns.len());
-                assert_eq!(1, rows.len());
-                // for row in rows {
-                //     println!("{:?}", row);
-                // }
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing() -> Result<()> {
+        use std::{
+            fmt::Debug,
+            sync::{Arc, Mutex},
+        };
+        use tracing::{
+            Event, Id, Metadata, Subscriber,
+            field::{Field, Visit},
+            span::{Attributes, Record},
+        };
+
+        /// Records each new span as its name and fields
+        #[derive(Clone, Default)]
+        struct Spans(Arc<Mutex<Vec<String>>>);
+
+        struct Fields(String);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0 += &format!(" {}={:?}", field.name(), value);
             }
-            _ => unreachable!(),
         }
 
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields(span.metadata().name().to_string());
+                span.record(&mut fields);
+                let mut spans = self.0.lock().unwrap();
+                spans.push(fields.0);
+                Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let spans = Spans::default();
+        tracing::subscriber::with_default(spans.clone(), || -> Result<()> {
+            let mut s = KVEngine::new(MemoryEngine::new()).session()?;
+            s.execute("create table t1 (a int primary key);")?;
+            s.execute("insert into t1 values (1), (2);")?;
+            s.execute("select a from t1 order by a;")?;
+            Ok(())
+        })?;
+
+        let spans = spans.0.lock().unwrap();
+        let has = |prefix: &str| spans.iter().any(|s| s.starts_with(prefix));
+        assert!(has("session.execute sql=\"select a from t1 order by a;\""), "{:?}", spans);
+        for prefix in [
+            "plan",
+            "executor node=\"Projection\"",
+            "executor node=\"Order\"",
+            "executor node=\"Scan\"",
+            "mvcc.begin",
+            "mvcc.write",
+            "mvcc.scan_prefix",
+            "mvcc.commit",
+        ] {
+            assert!(has(prefix), "no {} span in {:?}", prefix, spans);
+        }
         Ok(())
     }
 
     #[test]
-    fn test_agg() -> Result<()> {
+    fn test_columnar() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        for (name, with) in [("r", ""), ("c", " with (storage = 'columnar')")] {
+            s.execute(&format!("create table {} (a int primary key, b text, c float default 1.5){};", name, with))?;
+            s.execute(&format!("insert into {} (a, b) values (3, 'x'), (1, 'y'), (2, null);", name))?;
+            s.execute(&format!("update {} set a = 4 where a = 2;", name))?;
+            s.execute(&format!("update {} set b = 'z' where a = 3;", name))?;
+            s.execute(&format!("delete from {} where a = 1;", name))?;
+        }
 
-        s.execute("insert into t1 values (1, 'aa', 3.1);")?;
-        s.execute("insert into t1 values (2, 'cc', 5.3);")?;
-        s.execute("insert into t1 values (3, null, NULL);")?;
-        s.execute("insert into t1 values (4, 'dd', 4.6);")?;
+        // Columnar tables answer every query the same as row tables
+        let mut rows = |query: &str| match s.execute(query)? {
+            ResultSet::Scan { rows, .. } => Ok(rows),
+            r => Err(Error::Internal(format!("unexpected result {:?}", r))),
+        };
+        for query in [
+            "select * from {};",
+            "select b from {} where c > 1 order by a;",
+            "select a + 1 from {} where b = 'z';",
+            "select count(a), max(c) from {};",
+            "select * from {} where a = 4;",
+        ] {
+            let expected = rows(&query.replace("{}", "r"))?;
+            assert_eq!(rows(&query.replace("{}", "c"))?, expected, "{}", query);
+        }
+        assert!(s.execute("insert into c values (3, 'dup', 0.0);").is_err());
 
-        match s.execute("select count(a) as total, max(b), min(a), sum(c), avg(c) from t1;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(columns, vec!["total", "max", "min", "sum", "avg"]);
-                assert_eq!(
-                    rows,
-                    vec![vec![
-                        Value::Integer(4),
-                        Value::String("dd".to_string()),
-                        Value::Integer(1),
-                        Value::Float(13.0),
-                        Value::Float(13.0 / 3.0)
-                    ]]
-                );
+        // Projections are pushed down: unread columns come back NULL
+        let txn = kvengine.begin()?;
+        assert_eq!(
+            txn.scan_table_columns("c".into(), None, &["b".into()])?,
+            vec![
+                vec![Value::Integer(3), Value::String("z".into()), Value::Null],
+                vec![Value::Integer(4), Value::Null, Value::Null],
+            ]
+        );
+        txn.rollback()?;
+
+        assert!(s.execute("create table p (a int primary key) partition by hash (a) partitions 2 with (storage = 'columnar');").is_err());
+        assert!(s.execute("create table u (a int primary key) with (storage = 'sideways');").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_metadata() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text not null, c float);")?;
+        s.execute("create table t2 (d int primary key, e bool not null);")?;
+        s.execute("insert into t1 values (1, 'x', null);")?;
+
+        let mut metadata = |query: &str| match s.execute(query)? {
+            ResultSet::Scan { columns, metadata, .. } => {
+                assert_eq!(columns.len(), metadata.len());
+                Ok(metadata)
             }
-            _ => unreachable!(),
+            r => Err(Error::Internal(format!("unexpected result {:?}", r))),
+        };
+        let column = |datatype, nullable, table: Option<&str>| ColumnMetadata {
+            datatype,
+            nullable,
+            table: table.map(Into::into),
+        };
+
+        assert_eq!(
+            metadata("select * from t1 where a = 1;")?,
+            vec![
+                column(Some(DataType::Integer), false, Some("t1")),
+                column(Some(DataType::String), false, Some("t1")),
+                column(Some(DataType::Float), true, Some("t1")),
+            ]
+        );
+        assert_eq!(
+            metadata("select b as name, a + 1, null from t1 order by a limit 1;")?,
+            vec![
+                column(Some(DataType::String), false, Some("t1")),
+                column(Some(DataType::Integer), true, None),
+                column(None, true, None),
+            ]
+        );
+        // The padded side of an outer join is nullable
+        assert_eq!(
+            metadata("select b, e from t1 left join t2 on a = d;")?,
+            vec![column(Some(DataType::String), false, Some("t1")), column(Some(DataType::Boolean), true, Some("t2"))]
+        );
+        assert_eq!(metadata("select * from t1 cross join t2;")?[4], column(Some(DataType::Boolean), false, Some("t2")));
+        assert_eq!(
+            metadata("select count(a), max(b) from t1;")?,
+            vec![column(Some(DataType::Integer), false, None), column(Some(DataType::String), true, None)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_cache() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+
+        // Schemas are decoded once per transaction, then served from the cache
+        let mut txn = kvengine.begin()?;
+        assert!(txn.tables.borrow().is_empty());
+        let table = txn.must_get_table("t1".into())?;
+        assert_eq!(txn.tables.borrow().get("t1"), Some(&table));
+        assert_eq!(txn.must_get_table("t1".into())?, table);
+        assert!(txn.get_table("t2".into())?.is_none());
+
+        // The transaction's own DDL is visible right away, to it alone
+        let other = kvengine.begin()?;
+        let mut t2 = table.clone();
+        t2.name = "t2".into();
+        txn.create_table(t2.clone())?;
+        assert_eq!(txn.get_table("t2".into())?, Some(t2));
+        assert!(txn.create_table(table).is_err());
+        assert!(other.get_table("t2".into())?.is_none());
+        txn.commit()?;
+        other.rollback()?;
+
+        s.execute("insert into t2 values (1, 2);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_insert() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (a int primary key, b int) with (storage = 'columnar');")?;
+        s.execute("insert into t1 values (0, 'zero');")?;
+        let rows = |from: i128, to: i128| (from..to).map(|i| vec![Value::Integer(i), Value::Null]).collect::<Vec<_>>();
+
+        // Rows span several chunks, each one write set entry per shard
+        let mut txn = kvengine.begin()?;
+        assert_eq!(txn.bulk_insert("t1".into(), rows(1, 3000))?, 2999);
+        assert_eq!(txn.bulk_insert("t2".into(), rows(1, 10))?, 9);
+        let info = txn.txn.mvcc().transactions()?.into_iter().find(|info| info.version == txn.version());
+        assert!(info.is_some_and(|info| info.record.keys > 3000));
+        txn.commit()?;
+        match s.execute("select count(*) from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(3000)]]),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match s.execute("select * from t2 where a = 9;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(9), Value::Null]]),
+            other => panic!("unexpected result {:?}", other),
         }
 
-        s.execute("create table t2 (a int primary key, b text, c float);")?;
-        s.execute("insert into t2 values (1, NULL, NULL);")?;
-        s.execute("insert into t2 values (2, NULL, NULL);")?;
-        match s.execute("select count(a) as total, max(b), min(a), sum(c), avg(c) from t2;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(columns, vec!["total", "max", "min", "sum", "avg"]);
-                assert_eq!(
-                    rows,
-                    vec![vec![
-                        Value::Integer(2),
-                        Value::Null,
-                        Value::Integer(1),
-                        Value::Null,
-                        Value::Null
-                    ]]
-                );
+        // Rows are all checked before any is written
+        let mut txn = kvengine.begin()?;
+        assert!(txn.bulk_insert("t1".into(), rows(5000, 5010).into_iter().chain(rows(1, 2)).collect()).is_err());
+        assert!(txn.bulk_insert("t1".into(), [rows(6000, 6002), rows(6001, 6002)].concat()).is_err());
+        assert!(txn.bulk_insert("t1".into(), vec![vec![Value::Null, Value::Null]]).is_err());
+        assert!(txn.get_row(&txn.must_get_table("t1".into())?, &Value::Integer(5000))?.is_none());
+        txn.rollback()?;
+
+        // A concurrent write to a loaded row conflicts, naming the row
+        let mut txn1 = kvengine.begin()?;
+        let mut txn2 = kvengine.begin()?;
+        txn1.create_row("t1".into(), vec![Value::Integer(7000), Value::Null])?;
+        match txn2.bulk_insert("t1".into(), rows(6990, 7010)) {
+            Err(Error::WriteConflict { key, table, .. }) => {
+                assert_eq!((key.as_str(), table.as_deref()), ("7000", Some("t1")));
             }
-            _ => unreachable!(),
+            other => panic!("unexpected result {:?}", other),
         }
+        txn2.rollback()?;
+        txn1.commit()?;
+        Ok(())
+    }
 
+    #[test]
+    fn test_scan_cursor() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        let mut txn = kvengine.begin()?;
+        let rows = (0..2500).map(|i| vec![Value::Integer(i), Value::Integer(i % 10)]).collect::<Vec<_>>();
+        txn.bulk_insert("t1".into(), rows.clone())?;
+        txn.commit()?;
+
+        // Rows come a page at a time, in primary key order, from the snapshot
+        let txn = kvengine.begin()?;
+        let mut scan = txn.scan_table("t1".into(), None)?;
+        assert_eq!(scan.next().transpose()?, Some(rows[0].clone()));
+        s.execute("insert into t1 values (2500, 0);")?;
+        s.execute("delete from t1 where a = 2000;")?;
+        assert_eq!(scan.collect::<Result<Vec<_>>>()?, rows[1..]);
+
+        // Filters apply across pages
+        let filter = match Parser::new("select * from t1 where b = 3;").parse()? {
+            ast::Statement::Select { where_clause, .. } => where_clause,
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        let scanned = txn.scan_table("t1".into(), filter)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(scanned, rows.iter().filter(|row| row[1] == Value::Integer(3)).cloned().collect::<Vec<_>>());
+        txn.commit()?;
         Ok(())
     }
 
     #[test]
-    fn test_group_by() -> Result<()> {
+    fn test_index_scan() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        s.execute("create table t1 (a int primary key, b text collate nocase, c float, d int);")?;
+        s.execute("insert into t1 values (1, 'x', 1.5, 10), (2, 'Y', -2.0, 20), (3, 'X', 0.5, 30);")?;
+        s.execute("create index idx_b on t1 (b, c);")?;
+        assert!(s.execute("create index idx_b on t1 (c);").is_err());
+        assert!(s.execute("create index idx_e on t1 (e);").is_err());
+        s.execute("insert into t1 values (4, 'y', 3.0, 40);")?;
+
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let plan = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| {
+            let lines = rows(s, &format!("explain {}", sql));
+            lines.last().map(|row| row[0].to_string().trim_start_matches([' ', '-', '>']).to_string()).unwrap()
+        };
+        // Entries of the value are read, under the column's collation
+        let sql = "select a, c from t1 where b = 'x';";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b, value: x, filter: b = 'x')");
+        assert_eq!(
+            rows(&mut s, sql),
+            vec![vec![Value::Integer(3), Value::Float(0.5)], vec![Value::Integer(1), Value::Float(1.5)]]
+        );
+        // Other filters read the whole index, in index order
+        let sql = "select b from t1 where c > 0.0 limit 2;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b, filter: c > 0.0, limit: 2)");
+        assert_eq!(rows(&mut s, sql), vec![vec![Value::String("X".into())], vec![Value::String("x".into())]]);
+        // Columns the index lacks need the table
+        assert!(plan(&mut s, "select d from t1 where b = 'x';").starts_with("Scan t1"));
+
+        // Writes keep the entries in step with the rows
+        s.execute("update t1 set b = 'z' where a = 1;")?;
+        s.execute("update t1 set a = 5 where a = 2;")?;
+        s.execute("delete from t1 where a = 3;")?;
+        assert_eq!(rows(&mut s, "select a from t1 where b = 'x';"), Vec::<Row>::new());
+        assert_eq!(rows(&mut s, "select a from t1 where b = 'y';"), vec![vec![Value::Integer(5)], vec![Value::Integer(4)]]);
+        assert_eq!(rows(&mut s, "select a, b from t1 where b = 'Z';"), vec![vec![Value::Integer(1), Value::String("z".into())]]);
+        Ok(())
+    }
 
-        s.execute("insert into t1 values (1, 'aa', 3.1);")?;
-        s.execute("insert into t1 values (2, 'bb', 5.3);")?;
-        s.execute("insert into t1 values (3, null, NULL);")?;
-        s.execute("insert into t1 values (4, null, 4.6);")?;
-        s.execute("insert into t1 values (5, 'bb', 5.8);")?;
-        s.execute("insert into t1 values (6, 'dd', 1.4);")?;
+    #[test]
+    fn test_index_hints() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int, c text);")?;
+        s.execute("insert into t1 values (1, 20, 'x'), (2, 10, 'y'), (3, 20, 'z');")?;
+        s.execute("create index idx_b on t1 (b);")?;
+
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let plan = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| {
+            let lines = rows(s, &format!("explain {}", sql));
+            lines.last().map(|row| row[0].to_string().trim_start_matches([' ', '-', '>']).to_string()).unwrap()
+        };
+        // The hinted index is used even without a filter, fetching the rows it lacks columns of
+        let sql = "select /*+ INDEX(t1 idx_b) */ a, c from t1;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b, fetch)");
+        assert_eq!(
+            rows(&mut s, sql),
+            vec![
+                vec![Value::Integer(2), Value::String("y".into())],
+                vec![Value::Integer(1), Value::String("x".into())],
+                vec![Value::Integer(3), Value::String("z".into())],
+            ]
+        );
+        let sql = "select /*+ INDEX(t1 idx_b) */ c from t1 where c = 'z' limit 1;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b, filter: c = 'z', fetch, limit: 1)");
+        assert_eq!(rows(&mut s, sql), vec![vec![Value::String("z".into())]]);
+
+        // NO_INDEX keeps the table scan an index would have replaced
+        let sql = "select a from t1 where b = 20;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b, value: 20, filter: b = 20)");
+        let sql = "select /*+ NO_INDEX */ a from t1 where b = 20;";
+        assert_eq!(plan(&mut s, sql), "Scan t1 (filter: b = 20)");
+        assert_eq!(rows(&mut s, sql), vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]);
+
+        // Hints name indexes of the queried tables
+        assert!(s.execute("select /*+ INDEX(t1 idx_c) */ a from t1;").is_err());
+        assert!(s.execute("select /*+ INDEX(t2 idx_b) */ a from t1;").is_err());
+        Ok(())
+    }
 
-        match s.execute("select b, min(c), max(a), avg(c) from t1 group by b order by avg;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(columns, vec!["b", "min", "max", "avg"]);
-                assert_eq!(
-                    rows,
-                    vec![
-                        vec![
-                            Value::String("dd".to_string()),
-                            Value::Float(1.4),
-                            Value::Integer(6),
-                            Value::Float(1.4)
-                        ],
-                        vec![
-                            Value::String("aa".to_string()),
-                            Value::Float(3.1),
-                            Value::Integer(1),
-                            Value::Float(3.1)
-                        ],
-                        vec![
-                            Value::Null,
-                            Value::Float(4.6),
-                            Value::Integer(4),
-                            Value::Float(4.6)
-                        ],
-                        vec![
-                            Value::String("bb".to_string()),
-                            Value::Float(5.3),
-                            Value::Integer(5),
-                            Value::Float(5.55)
-                        ],
-                    ]
-                );
-            }
-            _ => unreachable!(),
+    #[test]
+    fn test_partial_index() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int, active boolean);")?;
+        s.execute("insert into t1 values (1, 20, true), (2, 10, false), (3, 30, true);")?;
+        s.execute("create index idx_b on t1 (b) where active = true;")?;
+        s.execute("create index idx_a on t1 (b) where a > 1;")?;
+
+        let entries = |index: &str| -> Result<Vec<Row>> {
+            kvengine.begin()?.scan_index("t1".into(), index, None, None, None)
+        };
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let plan = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| {
+            let lines = rows(s, &format!("explain {}", sql));
+            lines.last().map(|row| row[0].to_string().trim_start_matches([' ', '-', '>']).to_string()).unwrap()
+        };
+        // Only qualifying rows get entries, on backfill and on insert
+        assert_eq!(entries("idx_b")?.len(), 2);
+        s.execute("insert into t1 values (4, 40, false), (5, 50, true);")?;
+        assert_eq!(entries("idx_b")?.len(), 3);
+
+        // The predicate itself is answered by the index alone
+        let sql = "select a, b from t1 where active = true;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_b)");
+        assert_eq!(
+            rows(&mut s, sql),
+            vec![
+                vec![Value::Integer(1), Value::Integer(20)],
+                vec![Value::Integer(3), Value::Integer(30)],
+                vec![Value::Integer(5), Value::Integer(50)],
+            ]
+        );
+        // A filter implying the predicate keeps its own check
+        let sql = "select b from t1 where a > 3;";
+        assert_eq!(plan(&mut s, sql), "IndexScan t1 (index: idx_a, filter: a > 3)");
+        assert_eq!(rows(&mut s, sql), vec![vec![Value::Integer(40)], vec![Value::Integer(50)]]);
+        // Filters selecting rows outside the predicate can't use it, hinted or not
+        assert_eq!(plan(&mut s, "select b from t1 where a > 0;"), "Scan t1 (filter: a > 0)");
+        assert_eq!(plan(&mut s, "select b from t1 where active = false;"), "Scan t1 (filter: active = FALSE)");
+        let sql = "select /*+ INDEX(t1 idx_b) */ b from t1 where b = 10;";
+        assert_eq!(plan(&mut s, sql), "Scan t1 (filter: b = 10)");
+        assert_eq!(rows(&mut s, sql), vec![vec![Value::Integer(10)]]);
+
+        // Updates move rows in and out of the index
+        s.execute("update t1 set active = false where a = 1;")?;
+        s.execute("update t1 set active = true where a = 2;")?;
+        let b = |rows: Vec<Row>| rows.into_iter().map(|row| row[1].clone()).collect::<Vec<_>>();
+        assert_eq!(b(entries("idx_b")?), vec![Value::Integer(10), Value::Integer(30), Value::Integer(50)]);
+        s.execute("delete from t1 where a = 3;")?;
+        assert_eq!(b(entries("idx_b")?), vec![Value::Integer(10), Value::Integer(50)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_online_index_build() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20), (3, 30);")?;
+
+        // A transaction older than the index holds the build up until it ends
+        let mut old = kvengine.session()?;
+        old.execute("begin;")?;
+        old.execute("update t1 set b = 11 where a = 1;")?;
+        let engine = kvengine.clone();
+        let build = std::thread::spawn(move || engine.session()?.execute("create index idx_b on t1 (b);"));
+        // Each look is a transaction of its own, ended so the build doesn't wait on it
+        let building = || -> Result<bool> {
+            let txn = kvengine.begin()?;
+            let building = txn.must_get_table("t1".into())?.indexes.iter().any(|index| index.building);
+            txn.rollback()?;
+            Ok(building)
+        };
+        while !building()? {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        // Meanwhile writers go on, keeping the entries, and queries ignore the index
+        s.execute("insert into t1 values (4, 40);")?;
+        s.execute("delete from t1 where a = 2;")?;
+        assert!(s.execute("select /*+ INDEX(t1 idx_b) */ a from t1;").is_err());
+        match s.execute("explain select a from t1 where b = 40;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.iter().all(|row| !row[0].to_string().contains("IndexScan"))),
+            other => panic!("unexpected result {:?}", other),
+        }
+        old.execute("commit;")?;
+        assert!(matches!(build.join().unwrap()?, ResultSet::Command { .. }));
+
+        let entries = kvengine.begin()?.scan_index("t1".into(), "idx_b", None, None, None)?;
+        assert_eq!(
+            entries,
+            vec![
+                vec![Value::Integer(1), Value::Integer(11)],
+                vec![Value::Integer(3), Value::Integer(30)],
+                vec![Value::Integer(4), Value::Integer(40)],
+            ]
+        );
+        match s.execute("select a from t1 where b = 40;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(4)]]),
+            other => panic!("unexpected result {:?}", other),
         }
 
+        // A failed build leaves no index behind
+        assert!(s.execute("create index idx_c on t1 (c);").is_err());
+        assert_eq!(kvengine.begin()?.must_get_table("t1".into())?.indexes.len(), 1);
+
+        // A transaction left open fails the build once the wait is over
+        let kvengine = kvengine.with_index_build_wait(std::time::Duration::from_millis(20));
+        let idle = kvengine.begin()?;
+        match kvengine.session()?.execute("create index idx_c on t1 (b);") {
+            Err(Error::Internal(msg)) => assert!(msg.contains(&idle.version().to_string()), "{}", msg),
+            other => panic!("unexpected result {:?}", other),
+        }
+        idle.rollback()?;
+        assert_eq!(kvengine.begin()?.must_get_table("t1".into())?.indexes.len(), 1);
         Ok(())
     }
 
     #[test]
-    fn test_filter() -> Result<()> {
+    fn test_drop_table() -> Result<()> {
         let kvengine = KVEngine::new(MemoryEngine::new());
         let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float, d bool);")?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20);")?;
+        s.execute("create index idx_b on t1 (b);")?;
+        s.execute("create table t2 (a int primary key) with (audit = true);")?;
+        s.execute("insert into t2 values (1);")?;
+
+        // Dropping a table takes its rows and indexes, so a new one starts empty
+        s.execute("drop table t1;")?;
+        assert!(s.execute("select * from t1;").is_err());
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create index idx_b on t1 (b);")?;
+        let txn = kvengine.begin()?;
+        let table = txn.must_get_table("t1".into())?;
+        assert_eq!(txn.scan_table("t1".into(), None)?.count(), 0);
+        assert_eq!(txn.count_rows(&table)?, 0);
+        assert!(txn.scan_index("t1".into(), "idx_b", None, None, None)?.is_empty());
+        txn.rollback()?;
+
+        // The audit table depends on its table, and only goes with CASCADE
+        assert!(s.execute("drop table t2;").is_err());
+        assert!(s.execute("drop table _audit_t2;").is_err());
+        s.execute("drop table t2 cascade;")?;
+        assert_eq!(kvengine.begin()?.get_table_names()?, vec!["t1".to_string()]);
+        assert!(kvengine.begin()?.get_table("_audit_t2".into())?.is_none());
+        assert!(s.execute("drop table t2;").is_err());
+        Ok(())
+    }
 
-        s.execute("insert into t1 values (1, 'aa', 3.1, true);")?;
-        s.execute("insert into t1 values (2, 'bb', 5.3, true);")?;
-        s.execute("insert into t1 values (3, null, NULL, false);")?;
-        s.execute("insert into t1 values (4, null, 4.6, false);")?;
-        s.execute("insert into t1 values (5, 'bb', 5.8, true);")?;
-        s.execute("insert into t1 values (6, 'dd', 1.4, false);")?;
+    #[test]
+    fn test_row_decoder() -> Result<()> {
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let rows = (0..10)
+            .map(|i| vec![Value::Integer(i), Value::String(format!("b{}", i).into()), Value::Integer(i % 3)])
+            .collect::<Vec<_>>();
+        let results = rows
+            .iter()
+            .map(|row| Ok(crate::storage::mvcc::ScanResult { key: vec![], value: codec::encode_row(row) }))
+            .collect::<Result<Vec<_>>>()?;
+        let filter = Parser::new("select * from t where c = 1;").parse()?;
+        let ast::Statement::Select { where_clause: Some(filter), .. } = filter else { unreachable!() };
+
+        // Matching rows come back whole, or with just the wanted columns
+        let decoder = super::RowDecoder::new(&names, Some(&filter), None);
+        let matching = rows.iter().filter(|row| row[2] == Value::Integer(1)).cloned().collect::<Vec<_>>();
+        assert_eq!(decoder.decode_matching(&results)?, matching);
+        assert!(decoder.passes(&results[4].value)? && !decoder.passes(&results[5].value)?);
+        let decoder = super::RowDecoder::new(&names, Some(&filter), Some(vec![true, false, false]));
+        assert_eq!(
+            decoder.decode_matching(&results)?,
+            matching.iter().map(|row| vec![row[0].clone(), Value::Null, Value::Null]).collect::<Vec<_>>()
+        );
+        assert_eq!(super::RowDecoder::new(&names, None, None).decode_matching(&results)?, rows);
+        Ok(())
+    }
 
-        match s.execute("select * from t1 where d < true;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(4, columns.len());
-                assert_eq!(3, rows.len());
+    #[test]
+    fn test_migrate_rows() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key, b text, c int);")?;
+        s.execute("insert into t values (1, 'x', 10), (2, null, 20), (3, 'z', 30);")?;
+        s.execute("create index idx_b on t (b);")?;
+        let rows = kvengine.begin()?.scan_table("t".into(), None)?.collect::<Result<Vec<_>>>()?;
+
+        // Store the rows and index entries as bincode, as before the codec
+        let txn = kvengine.begin()?;
+        for prefix in [super::KeyPrefix::Row("t".into()), super::KeyPrefix::Index("t".into(), "idx_b".into())] {
+            for result in txn.txn.scan_prefix(prefix.encode()?)? {
+                txn.txn.set(result.key, bincode::serialize(&codec::decode_row(&result.value)?)?)?;
             }
-            _ => unreachable!(),
         }
-
-        match s.execute("select b, sum(c) from t1 group by b having sum < 5 order by sum;")? {
-            ResultSet::Scan { columns, rows } => {
-                assert_eq!(2, columns.len());
-                assert_eq!(3, rows.len());
-            }
-            _ => unreachable!(),
+        txn.commit()?;
+
+        // Old rows read as before, and are rewritten once
+        let read = |s: &mut Session<KVEngine<MemoryEngine>>| s.execute("select a from t where b = 'z';");
+        let found = read(&mut s)?;
+        assert_eq!(kvengine.begin()?.scan_table("t".into(), None)?.collect::<Result<Vec<_>>>()?, rows);
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.migrate_rows()?, 3);
+        assert_eq!(txn.migrate_rows()?, 0);
+        txn.commit()?;
+        assert_eq!(read(&mut s)?, found);
+        assert_eq!(kvengine.begin()?.scan_table("t".into(), None)?.collect::<Result<Vec<_>>>()?, rows);
+        let txn = kvengine.begin()?;
+        for result in txn.txn.scan_prefix(super::KeyPrefix::Index("t".into(), "idx_b".into()).encode()?)? {
+            assert!(!codec::is_legacy(&result.value));
         }
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute_script(
+            "create table t1 (a int primary key, b text) partition by hash (a) partitions 3;
+             create table t2 (a int primary key, b int) with (storage = 'columnar');
+             create index t1_b on t1 (b) where b > 'a';
+             create index t2_b on t2 (b);
+             insert into t1 values (1, 'x'), (2, 'y'), (3, 'a');
+             insert into t2 values (1, 10), (2, 20);",
+        )?;
+        let txn = kvengine.begin()?;
+        let report = txn.check(false)?;
+        assert!(report.is_ok());
+        assert_eq!(report.index_entries_checked, 4);
+
+        // Keys of a dropped table, of a dropped index, and an entry whose row changed
+        txn.txn.set(super::Key::Row("t3".into(), Value::Integer(1)).encode()?, vec![])?;
+        txn.txn.set(super::Key::RowCount("t3".into(), 1).encode()?, vec![])?;
+        let entry = codec::encode_row(&[Value::Integer(1)]);
+        txn.txn.set(super::Key::Index("t1".into(), "t1_c".into(), vec![], Value::Integer(1)).encode()?, entry)?;
+        let (key, value) = super::Key::Index("t2".into(), "t2_b".into(), vec![Value::Integer(15)], Value::Integer(1))
+            .encode()
+            .map(|key| (key, codec::encode_row(&[Value::Integer(15), Value::Integer(1)])))?;
+        txn.txn.set(key, value)?;
+        let report = txn.check(true)?;
+        assert_eq!(
+            report.orphaned_keys,
+            [("t1.t1_c".to_string(), 1), ("t3".to_string(), 2)].into_iter().collect()
+        );
+        assert_eq!(report.dangling_index_entries, vec![("t2.t2_b".to_string(), Value::Integer(1))]);
+        assert_eq!(report.to_string().lines().last(), Some("5 index entries checked, repaired"));
+        assert!(txn.check(false)?.is_ok());
+        txn.commit()?;
+        assert_eq!(s.execute("select a from t2 where b = 10;")?, s.execute("select a from t2 where a = 1;")?);
+        Ok(())
+    }
 
+    #[test]
+    fn test_session_insert_iter() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text default 'x');")?;
+        let count = |s: &mut Session<KVEngine<MemoryEngine>>| match s.execute("select count(*) from t1;") {
+            Ok(ResultSet::Scan { rows, .. }) => rows[0][0].clone(),
+            other => panic!("unexpected result {:?}", other),
+        };
+        let rows = |range: std::ops::Range<i128>| range.map(|i| vec![Value::Integer(i)]);
+
+        // Chunks are consumed lazily, and all go in one transaction
+        let result = s.insert_iter("t1", vec!["a".into()], rows(0..2500))?;
+        assert_eq!(result, ResultSet::Insert { count: 2500 });
+        match s.execute("select b from t1 where a = 2499;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::String("x".into())]]),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // A duplicate in the last chunk undoes them all
+        assert!(s.insert_iter("t1", vec!["a".into()], rows(3000..5000).chain(rows(0..1))).is_err());
+        assert_eq!(count(&mut s), Value::Integer(2500));
+        assert!(s.insert_iter("t1", vec!["c".into()], rows(3000..3001)).is_err());
+
+        // Committed chunks stay when a later one fails
+        let policy = InsertPolicy { chunk_rows: 100, commit_chunks: true };
+        let result = s.insert_iter_with("t1", vec!["a".into()], rows(3000..3250).chain(rows(0..1)), policy);
+        assert!(result.is_err());
+        assert_eq!(count(&mut s), Value::Integer(2700));
+        let policy = InsertPolicy { chunk_rows: 0, ..policy };
+        assert!(s.insert_iter_with("t1", Vec::new(), rows(4000..4001), policy).is_err());
+
+        // In a transaction rows join it, and chunks can't commit on their own
+        s.execute("begin;")?;
+        s.insert_iter("t1", Vec::new(), rows(4000..4010))?;
+        let policy = InsertPolicy { commit_chunks: true, ..Default::default() };
+        assert!(s.insert_iter_with("t1", Vec::new(), rows(5000..5001), policy).is_err());
+        s.execute("rollback;")?;
+        assert_eq!(count(&mut s), Value::Integer(2700));
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,18 +1,77 @@
-use crate::{error::{Error, Result}, sql::{parser::ast::Expression, types::Value}};
+use std::{cmp::Ordering, time::Instant};
 
-use super::{executor::ResultSet, parser::Parser, plan::Plan, schema::Table, types::Row};
+use crate::{error::{Error, Result}, sql::{parser::ast::{self, Expression}, types::Value}, storage::engine::Durability};
 
-mod kv;
+use super::{analyzer::Analyzer, executor::{DEFAULT_MEMORY_BUDGET, MemoryBudget, ResultSet, bulk_insert_rows, insert_rows}, parser::Parser, plan::{Node, Plan}, record::Record, schema::{Index, Table}, types::Row};
+
+pub mod changefeed;
+pub mod kv;
+pub mod plancache;
+pub mod querylog;
+pub mod resultcache;
+pub mod stats;
+pub mod system;
+pub mod tenant;
+
+use plancache::{CachedPlan, DEFAULT_PLAN_CACHE_CAPACITY, PlanCache};
+use querylog::{Outcome, QueryLog, row_count};
+use resultcache::ResultCache;
+use stats::Stats;
+
+/// Rows of a scan, read from the transaction's snapshot as the iterator advances
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Row>> + 'a>;
 
 /// SQL engine trait
 pub trait Engine: Clone {
     type Transaction: Transaction;
 
     fn begin(&self) -> Result<Self::Transaction>;
+    /// Begins a transaction that also fails on phantoms: rows a concurrent
+    /// transaction writes into the ranges it scanned
+    fn begin_serializable(&self) -> Result<Self::Transaction>;
+    /// Begins a read-only transaction on the snapshot of a past version
+    fn begin_as_of(&self, version: u64) -> Result<Self::Transaction>;
+
+    /// Creates an index on its own, for a CREATE INDEX outside a transaction
+    ///
+    /// By default this builds it in one transaction; engines can instead build
+    /// it online, without holding back the table's writers for the duration.
+    fn build_index(&self, table_name: &str, index: Index) -> Result<()> {
+        let mut txn = self.begin()?;
+        match txn.create_index(table_name, index) {
+            Ok(()) => txn.commit(),
+            Err(err) => txn.rollback().and(Err(err)),
+        }
+    }
+
+    /// Statistics of the engine, shown by SHOW STATS
+    ///
+    /// By default a fresh set, so sessions' counts go unseen.
+    fn stats(&self) -> Stats {
+        Stats::new()
+    }
+
+    /// Cache of query results shared by the engine's sessions (see
+    /// `resultcache`), by default none
+    fn result_cache(&self) -> Option<ResultCache> {
+        None
+    }
 
     fn session(&self) -> Result<Session<Self>> {
         Ok(Session {
             engine: self.clone(),
+            stats: self.stats(),
+            plan_cache: PlanCache::new(DEFAULT_PLAN_CACHE_CAPACITY),
+            result_cache: self.result_cache(),
+            memory_budget: Some(DEFAULT_MEMORY_BUDGET),
+            query_log: None,
+            txn: None,
+            explicit: false,
+            aborted: false,
+            autocommit: true,
+            serializable: false,
+            lenient: false,
+            durability: None,
         })
     }
 }
@@ -24,22 +83,134 @@ pub trait Engine: Clone {
 pub trait Transaction {
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
+    /// Version of the transaction, ordering it among others
+    fn version(&self) -> u64;
+    /// Sets when the transaction's writes are flushed to storage; engines
+    /// without buffered writes ignore it
+    fn set_durability(&mut self, _durability: Durability) {}
 
     fn create_row(&mut self, table_name: String, row: Row) -> Result<()>;
+    /// Inserts full rows in bulk, e.g. for a data load, returning how many
+    ///
+    /// By default this creates them one by one; engines can instead batch
+    /// the writes of many rows.
+    fn bulk_insert(&mut self, table_name: String, rows: Vec<Row>) -> Result<usize> {
+        let count = rows.len();
+        for row in rows {
+            self.create_row(table_name.clone(), row)?;
+        }
+        Ok(count)
+    }
     /// Updates a row, id is the primary key
     fn update_row(&mut self, table: &Table, id: &Value, row: Row) -> Result<()>;
+    /// Reads a row by primary key
+    fn get_row(&self, table: &Table, id: &Value) -> Result<Option<Row>>;
     /// Deletes a row by primary key
     fn delete_row(&mut self, table: &Table, id: &Value) -> Result<()>;
-    /// Scans table with optional filter
+    /// Locks a row by primary key until the transaction ends (SELECT ... FOR UPDATE)
+    ///
+    /// Fails with a write conflict if another transaction wrote or locked the
+    /// row concurrently; while held, other writers fail the same way.
+    fn lock_row(&mut self, table: &Table, id: &Value) -> Result<()>;
+    /// Counts the rows of a table
+    ///
+    /// By default this scans the table; engines keeping row counts answer
+    /// without reading rows.
+    fn count_rows(&self, table: &Table) -> Result<usize> {
+        Ok(self.scan_table_columns(table.name.clone(), None, &[])?.len())
+    }
+    /// Scans table with optional filter, yielding rows as they're read
+    ///
+    /// Engines can read the table a part at a time, so it needn't fit in
+    /// memory to be scanned.
     fn scan_table(
         &self,
         table_name: String,
         filter: Option<Expression>,
+    ) -> Result<Rows<'_>>;
+    /// Scans table reading only the given columns; the others may come back as NULL
+    ///
+    /// Engines can then skip reading or decoding the other columns. By default
+    /// this is a full scan.
+    fn scan_table_columns(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        _columns: &[String],
+    ) -> Result<Vec<Row>> {
+        self.scan_table(table_name, filter)?.collect()
+    }
+    /// Scans table for up to `limit` rows, of the given columns (None for all)
+    ///
+    /// The rows are the first of a full scan. By default this is a full scan
+    /// that stops reading at `limit` rows.
+    fn scan_table_limit(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: usize,
+    ) -> Result<Vec<Row>> {
+        match columns {
+            Some(columns) => {
+                let mut rows = self.scan_table_columns(table_name, filter, columns)?;
+                rows.truncate(limit);
+                Ok(rows)
+            }
+            None => self.scan_table(table_name, filter)?.take(limit).collect(),
+        }
+    }
+    /// Scans table in descending primary key order, for up to `limit` rows
+    /// if given, of the given columns (None for all)
+    ///
+    /// Serves ORDER BY pk DESC without a sort. By default this is a full
+    /// scan, sorted.
+    fn scan_table_rev(
+        &self,
+        table_name: String,
+        filter: Option<Expression>,
+        columns: Option<&[String]>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name.clone())?;
+        let mut rows = match columns {
+            Some(columns) => self.scan_table_columns(table_name, filter, columns)?,
+            None => self.scan_table(table_name, filter)?.collect::<Result<_>>()?,
+        };
+        sort_desc(&table, &mut rows);
+        rows.truncate(limit.unwrap_or(usize::MAX));
+        Ok(rows)
+    }
+    /// Reads rows from an index's entries alone, in index order, for up to
+    /// `limit` rows if given
+    ///
+    /// Only the index's columns and the primary key are set; the others are
+    /// NULL. `value` restricts the scan to entries whose first column equals it.
+    fn scan_index(
+        &self,
+        table_name: String,
+        index: &str,
+        value: Option<&Value>,
+        filter: Option<Expression>,
+        limit: Option<usize>,
     ) -> Result<Vec<Row>>;
 
     // DDL operations
     fn create_table(&mut self, table: Table) -> Result<()>;
+    /// Adds an index to a table, building its entries for the existing rows
+    fn create_index(&mut self, table_name: &str, index: Index) -> Result<()>;
+    /// Records a materialized view reading a table, which also keeps it up
+    /// to date with the table's row changes if `maintained` (see
+    /// `executor::view`)
+    fn add_view(&mut self, table_name: &str, view: &str, maintained: bool) -> Result<()>;
+    /// Drops a table with its rows and indexes
+    ///
+    /// Objects depending on the table, like its audit table, fail the drop,
+    /// unless `cascade` drops them as well.
+    fn drop_table(&mut self, table_name: &str, cascade: bool) -> Result<()>;
     fn get_table(&self, table_name: String) -> Result<Option<Table>>;
+    /// Returns the names of all tables
+    fn get_table_names(&self) -> Result<Vec<String>>;
     /// Returns table info, returns error if table doesn't exist
     fn must_get_table(&self, table_name: String) -> Result<Table> {
         self.get_table(table_name.clone())?
@@ -50,28 +221,597 @@ pub trait Transaction {
     }
 }
 
+/// Sorts rows by their table's primary key, largest first, under its collation
+pub(crate) fn sort_desc(table: &Table, rows: &mut [Row]) {
+    let pk = table.columns.iter().position(|c| c.primary_key).unwrap_or(0);
+    let collation = table.columns[pk].collation;
+    rows.sort_by(|a, b| collation.compare(&b[pk], &a[pk]).unwrap_or(Ordering::Equal));
+}
+
+/// Rows `Session::insert_iter` writes at a time by default
+pub const DEFAULT_INSERT_CHUNK_ROWS: usize = 1000;
+
+/// How `Session::insert_iter_with` writes the rows of an iterator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InsertPolicy {
+    /// Rows validated and written at a time, so only these are in memory
+    pub chunk_rows: usize,
+    /// Whether each chunk commits in a transaction of its own, so a failed
+    /// chunk keeps those before it; otherwise all rows are written in one
+    /// transaction, the open one if any, and either all are inserted or none
+    pub commit_chunks: bool,
+}
+
+impl Default for InsertPolicy {
+    fn default() -> Self {
+        Self { chunk_rows: DEFAULT_INSERT_CHUNK_ROWS, commit_chunks: false }
+    }
+}
+
 /// SQL session for executing statements
 pub struct Session<E: Engine> {
     engine: E,
+    /// The engine's stats, counting plan cache hits and misses
+    stats: Stats,
+    /// Plans of recently executed statements, by SQL text
+    plan_cache: PlanCache,
+    /// The engine's cache of query results, if it keeps one
+    result_cache: Option<ResultCache>,
+    /// Bytes of rows each statement may buffer, None for unlimited
+    memory_budget: Option<usize>,
+    /// Receives a record of each executed statement
+    query_log: Option<QueryLog>,
+    /// The open transaction: one started by BEGIN, or with autocommit off
+    /// the one statements run in until COMMIT or ROLLBACK
+    txn: Option<E::Transaction>,
+    /// Whether the open transaction was started by BEGIN
+    explicit: bool,
+    /// Whether a statement of the explicit transaction failed, rolling it
+    /// back; other statements then fail until ROLLBACK or COMMIT, like in Postgres
+    aborted: bool,
+    /// Whether statements outside BEGIN ... COMMIT commit on their own
+    autocommit: bool,
+    /// Whether new transactions are serializable rather than snapshot isolated
+    serializable: bool,
+    /// Whether sql_mode is lenient rather than strict (see `set_lenient`)
+    lenient: bool,
+    /// Durability of new transactions, None for the engine's
+    durability: Option<Durability>,
+}
+
+impl<E: Engine> Drop for Session<E> {
+    /// Rolls back the open transaction, as when a client disconnects
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            let _ = txn.rollback();
+        }
+    }
 }
 
 impl<E: Engine + 'static> Session<E> {
+    /// Sets the per-statement memory budget (see `MemoryBudget`)
+    pub fn set_memory_budget(&mut self, limit: Option<usize>) {
+        self.memory_budget = limit;
+    }
+
+    /// Sets the log recording each statement (see `querylog`), None for none
+    pub fn set_query_log(&mut self, log: Option<QueryLog>) {
+        self.query_log = log;
+    }
+
+    /// Sets whether statements outside BEGIN ... COMMIT commit on their own
+    ///
+    /// With autocommit off they all run in one transaction until COMMIT or
+    /// ROLLBACK, like MySQL's `SET autocommit = off`. Turning it back on
+    /// commits that transaction.
+    pub fn set_autocommit(&mut self, on: bool) -> Result<()> {
+        if on
+            && !self.explicit
+            && let Some(txn) = self.txn.take()
+        {
+            txn.commit()?;
+        }
+        self.autocommit = on;
+        Ok(())
+    }
+
+    /// Sets whether transactions begun from now on are serializable
+    ///
+    /// Serializable transactions lock the ranges they scan, so a concurrent
+    /// write into one fails the scan or the commit with a write conflict,
+    /// instead of going unseen as under snapshot isolation (the default).
+    pub fn set_serializable(&mut self, on: bool) {
+        self.serializable = on;
+    }
+
+    /// Sets whether sql_mode is lenient: integer division (`/` and `%`) by
+    /// zero then gives NULL instead of failing the statement, as in strict
+    /// mode (the default)
+    pub fn set_lenient(&mut self, on: bool) {
+        self.lenient = on;
+    }
+
+    /// Sets when transactions begun from now on flush their writes (see
+    /// `Durability`), None for the engine's setting
+    pub fn set_durability(&mut self, durability: Option<Durability>) {
+        self.durability = durability;
+    }
+
+    /// Sets how many statement plans the session keeps (see `plancache`),
+    /// 0 to plan every statement anew
+    pub fn set_plan_cache_capacity(&mut self, capacity: usize) {
+        self.plan_cache.set_capacity(capacity);
+    }
+
+    /// Begins a transaction at the session's isolation level
+    fn begin_transaction(&self) -> Result<E::Transaction> {
+        let mut txn = match self.serializable {
+            true => self.engine.begin_serializable()?,
+            false => self.engine.begin()?,
+        };
+        if let Some(durability) = self.durability {
+            txn.set_durability(durability);
+        }
+        Ok(txn)
+    }
+
+    /// Whether a transaction is open, so statements don't commit on their own
+    pub fn in_transaction(&self) -> bool {
+        self.txn.is_some() || self.aborted
+    }
+
+    /// Whether the explicit transaction failed and awaits ROLLBACK
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
     /// Executes a SQL statement
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "session.execute", level = "debug", skip(self), err(level = "debug"))
+    )]
     pub fn execute(&mut self, sql: &str) -> Result<ResultSet> {
-        match Parser::new(sql).parse()? {
-            stmt => {
-                let mut txn = self.engine.begin()?;
-                match Plan::build(stmt)?.execute(&mut txn) {
-                    Ok(result) => {
-                        txn.commit()?;
-                        Ok(result)
-                    }
-                    Err(err) => {
-                        txn.rollback()?;
-                        Err(err)
-                    }
+        let Some(log) = self.query_log.clone() else {
+            return self.execute_statement(sql).0;
+        };
+        let start = Instant::now();
+        let (result, outcome) = self.execute_statement(sql);
+        log.log(sql, start.elapsed(), result.as_ref().map(row_count), outcome);
+        result
+    }
+
+    /// Executes a script of `;`-terminated statements in one transaction,
+    /// e.g. a `Database::dump`, returning each statement's result
+    ///
+    /// Either every statement takes effect, or none does. The query log
+    /// records the script as one statement.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "session.execute_script", level = "debug", skip_all, err(level = "debug"))
+    )]
+    pub fn execute_script(&mut self, script: &str) -> Result<Vec<ResultSet>> {
+        let Some(log) = self.query_log.clone() else {
+            return self.execute_script_statements(script).0;
+        };
+        let start = Instant::now();
+        let (result, outcome) = self.execute_script_statements(script);
+        log.log(script, start.elapsed(), result.as_ref().map(|r| r.iter().map(row_count).sum()), outcome);
+        result
+    }
+
+    fn execute_script_statements(&mut self, script: &str) -> (Result<Vec<ResultSet>>, Outcome) {
+        if self.in_transaction() {
+            let err = Error::Internal("scripts run in their own transaction, COMMIT or ROLLBACK first".into());
+            return (Err(err), Outcome::Open);
+        }
+        let mut stmts = match Parser::new(script).parse_script() {
+            Ok(stmts) => stmts,
+            Err(errors) => {
+                let message = errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n");
+                return (Err(Error::Parse(message)), Outcome::NotStarted);
+            }
+        };
+        stmts.iter_mut().for_each(|stmt| apply_sql_mode(stmt, self.lenient));
+        if stmts.iter().any(|stmt| stmt.as_of().is_some()) {
+            let err = Error::Internal("AS OF queries run in their own transaction".into());
+            return (Err(err), Outcome::NotStarted);
+        }
+        let mut txn = match self.begin_transaction() {
+            Ok(txn) => txn,
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        };
+        let result = stmts
+            .into_iter()
+            .map(|stmt| {
+                let stmt = Analyzer::new(&txn).analyze(stmt)?;
+                let budget = MemoryBudget::new(self.memory_budget);
+                Plan::build(stmt)?.execute_with_budget(&mut txn, &budget)
+            })
+            .collect::<Result<Vec<_>>>();
+        match result {
+            Ok(results) => match txn.commit() {
+                Ok(()) => (Ok(results), Outcome::Committed),
+                Err(err) => (Err(err), Outcome::RolledBack),
+            },
+            Err(err) => (txn.rollback().and(Err(err)), Outcome::RolledBack),
+        }
+    }
+
+    /// Executes a SQL statement, also returning what became of its transaction
+    fn execute_statement(&mut self, sql: &str) -> (Result<ResultSet>, Outcome) {
+        // A query with a cached plan may have a cached result too, found by its parsed text
+        let results = self.result_cache.clone().filter(|_| self.autocommit && !self.in_transaction());
+        if results.is_none()
+            && let Some(cached) = self.plan_cache.take(sql)
+        {
+            return self.execute_cached(sql, Some(cached), None);
+        }
+        let mut stmt = match Parser::new(sql).parse() {
+            Ok(stmt) => stmt,
+            // Like any failed statement, a malformed one aborts the explicit transaction
+            Err(err) => match self.txn.take() {
+                Some(txn) if self.explicit => {
+                    self.aborted = true;
+                    return (txn.rollback().and(Err(err)), Outcome::RolledBack);
+                }
+                txn => {
+                    self.txn = txn;
+                    return (Err(err), Outcome::NotStarted);
+                }
+            },
+        };
+        if self.aborted && !matches!(stmt, ast::Statement::Commit | ast::Statement::Rollback) {
+            return (Err(Error::TransactionAborted), Outcome::NotStarted);
+        }
+        apply_sql_mode(&mut stmt, self.lenient);
+        if let Some(results) = results
+            && resultcache::is_cacheable(&mut stmt)
+        {
+            return self.execute_result_cached(sql, stmt, results);
+        }
+        if self.plan_cache.capacity() > 0 && plancache::is_cacheable(&stmt) {
+            let cached = self.plan_cache.take(sql);
+            return self.execute_cached(sql, cached, Some(stmt));
+        }
+        let budget = MemoryBudget::new(self.memory_budget);
+        let execute = |txn: &mut E::Transaction, stmt| {
+            let stmt = Analyzer::new(&*txn).analyze(stmt)?;
+            Plan::build(stmt)?.execute_with_budget(txn, &budget)
+        };
+        let stmt = match stmt {
+            ast::Statement::Begin => return self.begin(),
+            ast::Statement::Commit => return self.end(true),
+            ast::Statement::Rollback => return self.end(false),
+            ast::Statement::Set { name, value } => return self.set(&name, &value),
+            stmt @ ast::Statement::CreateIndex { .. } if !self.in_transaction() => return self.create_index(stmt),
+            stmt => stmt,
+        };
+        // AS OF queries read a past snapshot, outside any open transaction
+        let Some(version) = stmt.as_of() else {
+            return self.run(|txn| execute(txn, stmt));
+        };
+        let mut txn = match self.engine.begin_as_of(version) {
+            Ok(txn) => txn,
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        };
+        match execute(&mut txn, stmt) {
+            Ok(result) => match txn.commit() {
+                Ok(()) => (Ok(result), Outcome::Committed),
+                Err(err) => (Err(err), Outcome::RolledBack),
+            },
+            Err(err) => (txn.rollback().and(Err(err)), Outcome::RolledBack),
+        }
+    }
+
+    /// Executes a cacheable statement with its cached plan, if its tables
+    /// kept their schemas, or else with a new plan, of the given parsed
+    /// statement or of the SQL text; the plan is then cached
+    fn execute_cached(
+        &mut self,
+        sql: &str,
+        cached: Option<CachedPlan>,
+        stmt: Option<ast::Statement>,
+    ) -> (Result<ResultSet>, Outcome) {
+        let budget = MemoryBudget::new(self.memory_budget);
+        let (stats, lenient) = (self.stats.clone(), self.lenient);
+        let mut entry = None;
+        let result = self.run(|txn| {
+            let cached = match cached {
+                Some(cached) if cached.is_valid(&*txn)? => {
+                    stats.record_plan_cache(cached.table_names(), true)?;
+                    cached
                 }
+                _ => {
+                    let stmt = match stmt {
+                        Some(stmt) => stmt,
+                        None => {
+                            let mut stmt = Parser::new(sql).parse()?;
+                            apply_sql_mode(&mut stmt, lenient);
+                            stmt
+                        }
+                    };
+                    stats.record_plan_cache(plancache::table_names(&stmt).iter().map(String::as_str), false)?;
+                    CachedPlan::build(&*txn, stmt)?
+                }
+            };
+            let plan = cached.plan.clone();
+            entry = Some(cached);
+            plan.execute_with_budget(txn, &budget)
+        });
+        if let Some(cached) = entry {
+            self.plan_cache.insert(sql, cached);
+        }
+        result
+    }
+
+    /// Executes a query outside a transaction with its result cached, if the
+    /// engine has it for the current data version, or else caches the result
+    fn execute_result_cached(
+        &mut self,
+        sql: &str,
+        stmt: ast::Statement,
+        results: ResultCache,
+    ) -> (Result<ResultSet>, Outcome) {
+        let key = stmt.to_string();
+        match results.get(&key) {
+            Ok(Some(result)) => return (Ok(result), Outcome::NotStarted),
+            Ok(None) => {}
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        }
+        let tables = plancache::table_names(&stmt);
+        let version = match results.version() {
+            Ok(version) => version,
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        };
+        match self.begin_transaction() {
+            Ok(txn) => self.txn = Some(txn),
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        }
+        // The snapshot holds the commits the version counts only if none began or ended meanwhile
+        let version = version.filter(|version| matches!(results.version(), Ok(Some(v)) if v == *version));
+        let cached = self.plan_cache.take(sql);
+        let (result, outcome) = self.execute_cached(sql, cached, Some(stmt));
+        if let (Some(version), Ok(result), Outcome::Committed) = (version, &result, outcome)
+            && let Err(err) = results.insert(key, version, tables, result.clone())
+        {
+            return (Err(err), outcome);
+        }
+        (result, outcome)
+    }
+
+    /// Runs a statement in the open transaction, or in a new one that
+    /// commits with it, or stays open with autocommit off
+    fn run<R>(&mut self, f: impl FnOnce(&mut E::Transaction) -> Result<R>) -> (Result<R>, Outcome) {
+        if self.aborted {
+            return (Err(Error::TransactionAborted), Outcome::NotStarted);
+        }
+        let mut txn = match self.txn.take() {
+            Some(txn) => txn,
+            None => match self.begin_transaction() {
+                Ok(txn) => txn,
+                Err(err) => return (Err(err), Outcome::NotStarted),
+            },
+        };
+        match f(&mut txn) {
+            Ok(result) if self.explicit || !self.autocommit => {
+                self.txn = Some(txn);
+                (Ok(result), Outcome::Open)
+            }
+            // A failed commit discards the transaction's writes
+            Ok(result) => match txn.commit() {
+                Ok(()) => (Ok(result), Outcome::Committed),
+                Err(err) => (Err(err), Outcome::RolledBack),
+            },
+            // A statement's writes can't be undone alone, so its whole transaction
+            // is, and an explicit one waits for the client to end it
+            Err(err) => {
+                self.aborted = self.explicit;
+                (txn.rollback().and(Err(err)), Outcome::RolledBack)
             }
         }
     }
+
+    /// CREATE INDEX outside a transaction, which the engine may build online
+    fn create_index(&mut self, stmt: ast::Statement) -> (Result<ResultSet>, Outcome) {
+        let plan = self.engine.begin().and_then(|txn| {
+            let plan = Analyzer::new(&txn).analyze(stmt).and_then(Plan::build);
+            txn.rollback().and(plan)
+        });
+        let (table_name, index) = match plan {
+            Ok(Plan(Node::CreateIndex { table_name, index })) => (table_name, index),
+            Ok(plan) => unreachable!("CREATE INDEX planned as {}", plan.0.name()),
+            Err(err) => return (Err(err), Outcome::NotStarted),
+        };
+        match self.engine.build_index(&table_name, index) {
+            Ok(()) => (Ok(ResultSet::Command { tag: "CREATE INDEX".into() }), Outcome::Committed),
+            Err(err) => (Err(err), Outcome::RolledBack),
+        }
+    }
+
+    /// BEGIN: opens an explicit transaction, committing the one of autocommit off
+    fn begin(&mut self) -> (Result<ResultSet>, Outcome) {
+        if self.explicit {
+            return (Err(Error::Internal("a transaction is already open".into())), Outcome::Open);
+        }
+        if let Some(txn) = self.txn.take()
+            && let Err(err) = txn.commit()
+        {
+            return (Err(err), Outcome::RolledBack);
+        }
+        match self.begin_transaction() {
+            Ok(txn) => {
+                self.txn = Some(txn);
+                self.explicit = true;
+                (Ok(ResultSet::Command { tag: "BEGIN".into() }), Outcome::Open)
+            }
+            Err(err) => (Err(err), Outcome::NotStarted),
+        }
+    }
+
+    /// COMMIT or ROLLBACK of the open transaction, if any
+    fn end(&mut self, commit: bool) -> (Result<ResultSet>, Outcome) {
+        // The aborted transaction is already rolled back, so COMMIT reports ROLLBACK
+        if self.aborted {
+            (self.aborted, self.explicit) = (false, false);
+            return (Ok(ResultSet::Command { tag: "ROLLBACK".into() }), Outcome::RolledBack);
+        }
+        let tag = if commit { "COMMIT" } else { "ROLLBACK" };
+        let Some(txn) = self.txn.take() else {
+            return (Ok(ResultSet::Command { tag: tag.into() }), Outcome::NotStarted);
+        };
+        self.explicit = false;
+        let result = match commit {
+            true => txn.commit(),
+            false => txn.rollback(),
+        };
+        let outcome = match commit && result.is_ok() {
+            true => Outcome::Committed,
+            false => Outcome::RolledBack,
+        };
+        (result.map(|()| ResultSet::Command { tag: tag.into() }), outcome)
+    }
+
+    /// SET name = value, of a session setting
+    fn set(&mut self, name: &str, value: &str) -> (Result<ResultSet>, Outcome) {
+        let tag = || Ok(ResultSet::Command { tag: "SET".into() });
+        match name {
+            "autocommit" => {}
+            // Takes effect from the next transaction, as the open one's snapshot is taken
+            "isolation" => {
+                match value.to_lowercase().as_str() {
+                    "serializable" => self.set_serializable(true),
+                    "snapshot" => self.set_serializable(false),
+                    _ => return (Err(Error::Internal(format!("invalid isolation {}", value))), Outcome::NotStarted),
+                }
+                return (tag(), Outcome::NotStarted);
+            }
+            "sql_mode" => {
+                match value.to_lowercase().as_str() {
+                    "strict" => self.set_lenient(false),
+                    "lenient" => self.set_lenient(true),
+                    _ => return (Err(Error::Internal(format!("invalid sql_mode {}", value))), Outcome::NotStarted),
+                }
+                // Cached plans have the previous mode applied
+                self.plan_cache.clear();
+                return (tag(), Outcome::NotStarted);
+            }
+            // Takes effect from the next transaction, like isolation
+            "durability" => {
+                let durability = match value.to_lowercase().as_str() {
+                    "default" => None,
+                    name => match Durability::from_name(name) {
+                        Ok(durability) => Some(durability),
+                        Err(err) => return (Err(err), Outcome::NotStarted),
+                    },
+                };
+                self.set_durability(durability);
+                return (tag(), Outcome::NotStarted);
+            }
+            _ => return (Err(Error::Internal(format!("unknown setting {}", name))), Outcome::NotStarted),
+        }
+        let on = match value.to_lowercase().as_str() {
+            "on" | "true" | "1" => true,
+            "off" | "false" | "0" => false,
+            _ => return (Err(Error::Internal(format!("invalid autocommit value {}", value))), Outcome::NotStarted),
+        };
+        let commits = on && !self.explicit && self.txn.is_some();
+        let outcome = if commits { Outcome::Committed } else { Outcome::NotStarted };
+        match self.set_autocommit(on) {
+            Ok(()) => (tag(), outcome),
+            Err(err) => (Err(err), Outcome::RolledBack),
+        }
+    }
+
+    /// Inserts already evaluated rows, like `INSERT INTO table (columns)
+    /// VALUES ...` (empty columns for all), in the open transaction if any
+    pub fn insert_rows(&mut self, table_name: &str, columns: Vec<String>, rows: Vec<Row>) -> Result<ResultSet> {
+        let result = self.run(|txn| insert_rows(txn, table_name, &columns, rows)).0;
+        result.map(|count| ResultSet::Insert { count })
+    }
+
+    /// Inserts the rows of an iterator, like `insert_rows`, in chunks of
+    /// `DEFAULT_INSERT_CHUNK_ROWS` within one transaction, e.g. to load the
+    /// output of an ETL job without holding it all in memory
+    pub fn insert_iter(
+        &mut self,
+        table_name: &str,
+        columns: Vec<String>,
+        rows: impl IntoIterator<Item = Row>,
+    ) -> Result<ResultSet> {
+        self.insert_iter_with(table_name, columns, rows, InsertPolicy::default())
+    }
+
+    /// Inserts the rows of an iterator in chunks, committed as the policy says
+    ///
+    /// Committing each chunk needs the session outside a transaction; on an
+    /// error, the rows of the chunks before the failing one stay inserted.
+    pub fn insert_iter_with(
+        &mut self,
+        table_name: &str,
+        columns: Vec<String>,
+        rows: impl IntoIterator<Item = Row>,
+        policy: InsertPolicy,
+    ) -> Result<ResultSet> {
+        if policy.chunk_rows == 0 {
+            return Err(Error::Internal("insert chunks need at least one row".into()));
+        }
+        let mut rows = rows.into_iter();
+        let mut chunks = std::iter::from_fn(|| {
+            let chunk = rows.by_ref().take(policy.chunk_rows).collect::<Vec<_>>();
+            (!chunk.is_empty()).then_some(chunk)
+        });
+        if !policy.commit_chunks {
+            let insert = |txn: &mut E::Transaction| {
+                chunks.try_fold(0, |count, chunk| Ok(count + bulk_insert_rows(txn, table_name, &columns, chunk)?))
+            };
+            return self.run(insert).0.map(|count| ResultSet::Insert { count });
+        }
+        if self.in_transaction() {
+            return Err(Error::Internal("chunks commit on their own, COMMIT or ROLLBACK first".into()));
+        }
+        let mut count = 0;
+        for chunk in chunks {
+            let mut txn = self.begin_transaction()?;
+            match bulk_insert_rows(&mut txn, table_name, &columns, chunk) {
+                Ok(inserted) => {
+                    txn.commit()?;
+                    count += inserted;
+                }
+                Err(err) => return txn.rollback().and(Err(err)),
+            }
+        }
+        Ok(ResultSet::Insert { count })
+    }
+
+    /// Inserts records into their table (see `record`)
+    pub fn insert_records<'a, T: Record + 'a>(&mut self, records: impl IntoIterator<Item = &'a T>) -> Result<ResultSet> {
+        let columns = T::columns().into_iter().map(|c| c.name.to_string()).collect();
+        self.insert_rows(T::TABLE, columns, records.into_iter().map(T::to_row).collect())
+    }
+
+    /// Executes a query, reading each result row as a record by column name
+    pub fn query_as<T: Record>(&mut self, sql: &str) -> Result<Vec<T>> {
+        match self.execute(sql)? {
+            ResultSet::Scan { columns, rows, .. } => rows.into_iter().map(|row| T::from_row(&columns, row)).collect(),
+            result => Err(Error::Internal(format!("expected a query, got {:?}", result))),
+        }
+    }
+}
+
+/// Applies the sql_mode to a parsed statement
+fn apply_sql_mode(stmt: &mut ast::Statement, lenient: bool) {
+    if !lenient {
+        return;
+    }
+    stmt.transform_expressions(&mut |expr| {
+        *expr = match std::mem::replace(expr, ast::Consts::Null.into()) {
+            Expression::Operation(ast::Operation::Divide(l, r)) => {
+                Expression::ScalarFunction("try_divide".into(), vec![*l, *r])
+            }
+            Expression::Operation(ast::Operation::Modulo(l, r)) => {
+                Expression::ScalarFunction("try_mod".into(), vec![*l, *r])
+            }
+            expr => expr,
+        }
+    });
 }
@@ -0,0 +1,240 @@
+//! Plan cache - the plans of a session's recent statements, by SQL text
+//!
+//! Parsing, binding and planning can take longer than executing a small
+//! statement, e.g. a point lookup. A session keeps the plans of the SELECT,
+//! INSERT, UPDATE and DELETE statements it executed, and runs a copy when the
+//! same text comes again. A plan holds on to the schemas of its tables, so
+//! each entry notes them, and is rebuilt once one changed, by any session:
+//! the executing transaction reads them anew. Hits and misses are counted
+//! per table in `system.stats` (`SHOW STATS`).
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    error::Result,
+    sql::{
+        analyzer::Analyzer,
+        engine::{Transaction, system},
+        parser::ast,
+        plan::Plan,
+        schema::Table,
+    },
+};
+
+/// Plans a session keeps by default
+pub const DEFAULT_PLAN_CACHE_CAPACITY: usize = 64;
+
+/// A plan, with the table schemas it was built with
+pub struct CachedPlan {
+    pub plan: Plan,
+    /// Each table the statement names, None for those that didn't exist
+    tables: Vec<(String, Option<Table>)>,
+}
+
+impl CachedPlan {
+    /// Binds and plans a statement, noting the schemas of its tables
+    pub fn build(txn: &impl Transaction, stmt: ast::Statement) -> Result<Self> {
+        let names = table_names(&stmt);
+        let plan = Plan::build(Analyzer::new(txn).analyze(stmt)?)?;
+        let tables = names
+            .into_iter()
+            .map(|name| Ok((name.clone(), txn.get_table(name)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { plan, tables })
+    }
+
+    /// Whether the tables still have the schemas the plan was built with
+    pub fn is_valid(&self, txn: &impl Transaction) -> Result<bool> {
+        for (name, table) in &self.tables {
+            if txn.get_table(name.clone())? != *table {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Names of the tables the statement reads or writes
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.tables.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+/// Whether the plan of a statement can be cached: SELECT (except AS OF),
+/// INSERT, UPDATE and DELETE
+pub fn is_cacheable(stmt: &ast::Statement) -> bool {
+    match stmt {
+        ast::Statement::Select { as_of, .. } => as_of.is_none(),
+        ast::Statement::Insert { .. } | ast::Statement::Update { .. } | ast::Statement::Delete { .. } => true,
+        _ => false,
+    }
+}
+
+/// Names of the tables a cacheable statement reads or writes, other than
+/// system tables, whose schemas don't change
+pub fn table_names(stmt: &ast::Statement) -> Vec<String> {
+    fn from_tables(item: &ast::FromItem, names: &mut Vec<String>) {
+        match item {
+            ast::FromItem::Table { name } => names.push(name.clone()),
+            ast::FromItem::Join { left, right, .. } => {
+                from_tables(left, names);
+                from_tables(right, names);
+            }
+        }
+    }
+
+    let mut names = Vec::new();
+    match stmt {
+        ast::Statement::Select { from, .. } => from_tables(from, &mut names),
+        ast::Statement::Insert { table_name, .. }
+        | ast::Statement::Update { table_name, .. }
+        | ast::Statement::Delete { table_name, .. } => names.push(table_name.clone()),
+        _ => {}
+    }
+    names.retain(|name| system::table(name).is_none());
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Plans by SQL text, dropping the least recently used beyond the capacity
+pub struct PlanCache {
+    capacity: usize,
+    plans: HashMap<String, CachedPlan>,
+    /// SQL texts from least to most recently used
+    order: VecDeque<String>,
+}
+
+impl PlanCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, plans: HashMap::new(), order: VecDeque::new() }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Sets the capacity, 0 to cache nothing
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// Removes the plan of a statement, for `insert` to put back after use
+    pub fn take(&mut self, sql: &str) -> Option<CachedPlan> {
+        let plan = self.plans.remove(sql)?;
+        self.order.retain(|key| key != sql);
+        Some(plan)
+    }
+
+    /// Adds the plan of a statement as the most recently used
+    pub fn insert(&mut self, sql: &str, plan: CachedPlan) {
+        if self.plans.insert(sql.to_string(), plan).is_some() {
+            self.order.retain(|key| key != sql);
+        }
+        self.order.push_back(sql.to_string());
+        self.evict();
+    }
+
+    /// Drops every plan, e.g. when a setting they were built with changes
+    pub fn clear(&mut self) {
+        self.plans.clear();
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.plans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+
+    fn evict(&mut self) {
+        while self.plans.len() > self.capacity {
+            let Some(sql) = self.order.pop_front() else { break };
+            self.plans.remove(&sql);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CachedPlan, PlanCache};
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, Transaction, kv::KVEngine},
+            executor::ResultSet,
+            parser::Parser,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn rows(result: ResultSet) -> Vec<Vec<Value>> {
+        match result {
+            ResultSet::Scan { rows, .. } => rows,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_plan_cache_lru() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key);")?;
+        let txn = kvengine.begin()?;
+        let plan = |sql: &str| CachedPlan::build(&txn, Parser::new(sql).parse()?);
+
+        let mut cache = PlanCache::new(2);
+        cache.insert("a", plan("select * from t1;")?);
+        cache.insert("b", plan("select a from t1;")?);
+        // Using a makes b the least recently used
+        let a = cache.take("a").unwrap();
+        cache.insert("a", a);
+        cache.insert("c", plan("delete from t1;")?);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.take("b").is_none());
+        assert!(cache.take("a").is_some() && cache.take("c").is_some());
+
+        cache.set_capacity(0);
+        cache.insert("a", plan("select * from t1;")?);
+        assert!(cache.is_empty());
+        txn.rollback()
+    }
+
+    #[test]
+    fn test_plan_cache() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let (mut s1, mut s2) = (kvengine.session()?, kvengine.session()?);
+        s1.execute("create table t1 (a int primary key, b int);")?;
+        s1.execute("insert into t1 values (1, 10), (2, 20);")?;
+        let query = "select a from t1 where b = 20;";
+        assert_eq!(rows(s1.execute(query)?), vec![vec![Value::Integer(2)]]);
+        assert_eq!(rows(s1.execute(query)?), vec![vec![Value::Integer(2)]]);
+
+        // Another session's DDL invalidates the plan
+        s2.execute("drop table t1;")?;
+        assert!(s1.execute(query).is_err());
+        s2.execute("create table t1 (b int primary key, a text);")?;
+        s2.execute("insert into t1 values (20, 'x');")?;
+        assert_eq!(rows(s1.execute(query)?), vec![vec![Value::String("x".into())]]);
+        assert_eq!(rows(s1.execute(query)?), vec![vec![Value::String("x".into())]]);
+
+        // Statements on t1: s1's insert, first query, failed query and query
+        // after the table came back missed, as did s2's insert
+        let stats = "select plan_cache_hits, plan_cache_misses from system.stats where table_name = 't1';";
+        assert_eq!(rows(s1.execute(stats)?), vec![vec![Value::Integer(2), Value::Integer(5)]]);
+
+        // Plans are rebuilt under a new sql_mode
+        let divide = "select b / 0 from t1;";
+        assert!(s1.execute(divide).is_err());
+        s1.execute("set sql_mode = lenient;")?;
+        assert_eq!(rows(s1.execute(divide)?), vec![vec![Value::Null]]);
+
+        // Without the cache nothing is counted
+        s1.set_plan_cache_capacity(0);
+        s1.execute(query)?;
+        assert_eq!(rows(s1.execute(stats)?), vec![vec![Value::Integer(2), Value::Integer(7)]]);
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Query log - a record of each statement a session executes
+//!
+//! A `QueryLog` hands every statement's SQL text, duration, row count and
+//! transaction outcome to a `QuerySink`. With a slow-query threshold it only
+//! logs statements that take at least that long.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{error::Error, sql::executor::ResultSet};
+
+/// What became of a logged statement's transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Committed,
+    RolledBack,
+    /// No transaction began, e.g. as the statement failed to parse
+    NotStarted,
+    /// The statement ran in a transaction that is still open, see `Session::set_autocommit`
+    Open,
+}
+
+/// One executed statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryRecord {
+    pub sql: String,
+    /// Time from parsing to the end of the commit or rollback
+    pub duration: Duration,
+    /// Rows returned by a query, or affected by INSERT, UPDATE, DELETE and COPY
+    pub rows: usize,
+    pub outcome: Outcome,
+    /// Error message of a failed statement
+    pub error: Option<String>,
+}
+
+/// Destination of query records, e.g. a file, a metrics system or a test buffer
+///
+/// Closures taking a `&QueryRecord` are sinks too.
+pub trait QuerySink: Send + Sync {
+    fn record(&self, record: &QueryRecord);
+}
+
+impl<F: Fn(&QueryRecord) + Send + Sync> QuerySink for F {
+    fn record(&self, record: &QueryRecord) {
+        self(record)
+    }
+}
+
+/// Logs the statements of the sessions it's set on to a sink
+///
+/// Clones share the sink, so one log can serve every session of a database.
+#[derive(Clone)]
+pub struct QueryLog {
+    sink: Arc<dyn QuerySink>,
+    /// Statements faster than this aren't logged
+    slow_threshold: Duration,
+}
+
+impl QueryLog {
+    /// Logs every statement to the sink
+    pub fn new(sink: impl QuerySink + 'static) -> Self {
+        Self { sink: Arc::new(sink), slow_threshold: Duration::ZERO }
+    }
+
+    /// Only logs statements taking at least `threshold`, like a slow query log
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = threshold;
+        self
+    }
+
+    /// Records a finished statement, if it was slow enough
+    pub(crate) fn log(&self, sql: &str, duration: Duration, result: Result<usize, &Error>, outcome: Outcome) {
+        if duration < self.slow_threshold {
+            return;
+        }
+        self.sink.record(&QueryRecord {
+            sql: sql.to_string(),
+            duration,
+            rows: *result.as_ref().unwrap_or(&0),
+            outcome,
+            error: result.err().map(|err| err.to_string()),
+        });
+    }
+}
+
+/// Rows a statement returned or affected, as logged
+pub(crate) fn row_count(result: &ResultSet) -> usize {
+    match result {
+        ResultSet::Scan { rows, .. } => rows.len(),
+        ResultSet::Insert { count }
+        | ResultSet::Update { count }
+        | ResultSet::Delete { count }
+        | ResultSet::Copy { count } => *count,
+        ResultSet::CreateTable { .. } | ResultSet::Command { .. } => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Outcome, QueryLog, QueryRecord};
+    use crate::{db::Database, error::Result, storage::memory::MemoryEngine};
+
+    /// A log collecting its records, and the records
+    fn collect() -> (QueryLog, Arc<Mutex<Vec<QueryRecord>>>) {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        (QueryLog::new(move |record: &QueryRecord| sink.lock().unwrap().push(record.clone())), records)
+    }
+
+    #[test]
+    fn test_query_log() -> Result<()> {
+        let (log, records) = collect();
+        let db = Database::new(MemoryEngine::new()).with_query_log(log);
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("insert into t1 values (1), (2), (3);")?;
+        s.execute("select * from t1 where a > 1;")?;
+        assert!(s.execute("insert into t1 values (1);").is_err());
+        assert!(s.execute("select from").is_err());
+        db.session()?.execute("delete from t1;")?;
+        s.execute("begin;")?;
+        s.execute("insert into t1 values (4);")?;
+        s.execute("commit;")?;
+
+        let records = records.lock().unwrap();
+        let summary =
+            records.iter().map(|r| (r.sql.as_str(), r.rows, r.outcome, r.error.is_some())).collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("create table t1 (a int primary key);", 0, Outcome::Committed, false),
+                ("insert into t1 values (1), (2), (3);", 3, Outcome::Committed, false),
+                ("select * from t1 where a > 1;", 2, Outcome::Committed, false),
+                ("insert into t1 values (1);", 0, Outcome::RolledBack, true),
+                ("select from", 0, Outcome::NotStarted, true),
+                ("delete from t1;", 3, Outcome::Committed, false),
+                ("begin;", 0, Outcome::Open, false),
+                ("insert into t1 values (4);", 1, Outcome::Open, false),
+                ("commit;", 0, Outcome::Committed, false),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_threshold() -> Result<()> {
+        let (log, records) = collect();
+        let mut s = Database::new(MemoryEngine::new()).session()?;
+        s.set_query_log(Some(log.clone().with_slow_threshold(Duration::from_secs(3600))));
+        s.execute("create table t1 (a int primary key);")?;
+        assert!(records.lock().unwrap().is_empty());
+
+        s.set_query_log(Some(log));
+        s.execute("select * from t1;")?;
+        s.set_query_log(None);
+        s.execute("select * from t1;")?;
+        assert_eq!(records.lock().unwrap().len(), 1);
+        Ok(())
+    }
+}
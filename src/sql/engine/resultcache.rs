@@ -0,0 +1,276 @@
+//! Result cache - the results of recent queries, shared by an engine's sessions
+//!
+//! A query gives the same rows as long as its tables don't change, so an
+//! engine can keep the results of the SELECTs sessions run outside a
+//! transaction, and return one without reading anything when the same query
+//! comes again. Entries are keyed by the normalized SQL text, the parsed
+//! statement printed back, and the data version: the number of commits that
+//! wrote to tables so far. Such a commit bumps the version, dropping the
+//! entries of queries reading a table it wrote; the others carry over.
+//!
+//! A query only uses the cache when its snapshot holds exactly the commits a
+//! version counts, i.e. no writing commit was under way as it began. The
+//! commit of a raw key-value transaction (`Database::kv_txn`) drops the
+//! results read from the tables that SQL statements run in it wrote
+//! (`Database::execute_in`); its raw writes go unseen, as they do by the
+//! changefeed.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    error::Result,
+    sql::{
+        engine::system,
+        executor::ResultSet,
+        functions,
+        parser::ast::{self, Expression},
+    },
+};
+
+/// A query result, with the tables it was read from
+struct Entry {
+    tables: Vec<String>,
+    result: ResultSet,
+}
+
+struct Inner {
+    capacity: usize,
+    /// Commits of transactions that wrote to tables
+    version: u64,
+    /// Such commits under way
+    committing: usize,
+    entries: HashMap<String, Entry>,
+    /// SQL texts from least to most recently used
+    order: VecDeque<String>,
+    hits: u64,
+}
+
+/// Query results by normalized SQL text, dropping the least recently used
+/// beyond the capacity
+///
+/// Clones share the entries, so one cache serves every session of an engine.
+#[derive(Clone)]
+pub struct ResultCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity,
+                version: 0,
+                committing: 0,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                hits: 0,
+            })),
+        }
+    }
+
+    /// An empty cache of the same capacity
+    pub fn like(&self) -> Result<Self> {
+        Ok(Self::new(self.inner.lock()?.capacity))
+    }
+
+    /// The data version a snapshot taken now holds, None while a writing
+    /// commit is under way
+    pub fn version(&self) -> Result<Option<u64>> {
+        let inner = self.inner.lock()?;
+        Ok((inner.committing == 0).then_some(inner.version))
+    }
+
+    /// The result of a query as of now, if cached
+    pub fn get(&self, sql: &str) -> Result<Option<ResultSet>> {
+        let mut inner = self.inner.lock()?;
+        if inner.committing > 0 {
+            return Ok(None);
+        }
+        let Some(result) = inner.entries.get(sql).map(|entry| entry.result.clone()) else {
+            return Ok(None);
+        };
+        inner.hits += 1;
+        inner.order.retain(|key| key != sql);
+        inner.order.push_back(sql.to_string());
+        Ok(Some(result))
+    }
+
+    /// Adds the result of a query read at a data version, unless a commit
+    /// has bumped the version since
+    pub fn insert(&self, sql: String, version: u64, tables: Vec<String>, result: ResultSet) -> Result<()> {
+        let mut inner = self.inner.lock()?;
+        if inner.version != version || inner.capacity == 0 {
+            return Ok(());
+        }
+        if inner.entries.insert(sql.clone(), Entry { tables, result }).is_some() {
+            inner.order.retain(|key| *key != sql);
+        }
+        inner.order.push_back(sql);
+        while inner.entries.len() > inner.capacity {
+            let Some(sql) = inner.order.pop_front() else { break };
+            inner.entries.remove(&sql);
+        }
+        Ok(())
+    }
+
+    /// Runs the commit of a transaction that wrote to the given tables, then
+    /// bumps the version and drops the results read from them
+    ///
+    /// They're dropped even if the commit fails, as it may have taken effect.
+    pub fn commit(&self, tables: &HashSet<String>, commit: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.inner.lock()?.committing += 1;
+        let result = commit();
+        let mut inner = self.inner.lock()?;
+        inner.committing -= 1;
+        inner.version += 1;
+        inner.entries.retain(|_, entry| !entry.tables.iter().any(|table| tables.contains(table)));
+        let Inner { entries, order, .. } = &mut *inner;
+        order.retain(|sql| entries.contains_key(sql));
+        result
+    }
+
+    /// Queries answered from the cache
+    pub fn hits(&self) -> Result<u64> {
+        Ok(self.inner.lock()?.hits)
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.inner.lock()?.entries.len())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.inner.lock()?.entries.is_empty())
+    }
+}
+
+/// Whether the result of a statement can be cached: a SELECT (except AS OF
+/// and FOR UPDATE) of user tables, calling no volatile function like now()
+pub fn is_cacheable(stmt: &mut ast::Statement) -> bool {
+    fn user_tables(item: &ast::FromItem) -> bool {
+        match item {
+            ast::FromItem::Table { name } => system::table(name).is_none(),
+            ast::FromItem::Join { left, right, .. } => user_tables(left) && user_tables(right),
+        }
+    }
+
+    let ast::Statement::Select { from, as_of: None, for_update: false, .. } = stmt else {
+        return false;
+    };
+    if !user_tables(from) {
+        return false;
+    }
+    let mut volatile = false;
+    stmt.transform_expressions(&mut |expr| {
+        if let Expression::ScalarFunction(name, _) = expr {
+            volatile |= functions::is_volatile(name);
+        }
+    });
+    !volatile
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::ResultCache;
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn rows(result: ResultSet) -> Vec<Vec<Value>> {
+        match result {
+            ResultSet::Scan { rows, .. } => rows,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_result_cache() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new()).with_result_cache(8);
+        let results = kvengine.results.clone().unwrap();
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (a int primary key);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20);")?;
+
+        let sum = "select sum(b) from t1;";
+        assert_eq!(rows(s.execute(sum)?), vec![vec![Value::Integer(30)]]);
+        assert_eq!(results.hits()?, 0);
+        // The normalized text matches, from any session
+        let mut other = kvengine.session()?;
+        assert_eq!(rows(other.execute("SELECT  SUM(b)  FROM t1 ;")?), vec![vec![Value::Integer(30)]]);
+        assert_eq!(results.hits()?, 1);
+
+        // Writing another table keeps the result
+        s.execute("insert into t2 values (1);")?;
+        s.execute(sum)?;
+        assert_eq!(results.hits()?, 2);
+
+        // Writing the table drops it, unless the write rolls back
+        s.execute("update t1 set b = 15 where a = 1;")?;
+        assert_eq!(rows(s.execute(sum)?), vec![vec![Value::Integer(35)]]);
+        s.execute("begin;")?;
+        s.execute("delete from t1 where a = 2;")?;
+        // Queries in a transaction don't use the cache
+        assert_eq!(rows(s.execute(sum)?), vec![vec![Value::Integer(15)]]);
+        s.execute("rollback;")?;
+        assert_eq!(rows(s.execute(sum)?), vec![vec![Value::Integer(35)]]);
+        assert_eq!(rows(s.execute(sum)?), vec![vec![Value::Integer(35)]]);
+        assert_eq!(results.hits()?, 4);
+
+        // Neither do AS OF, FOR UPDATE, volatile functions nor system tables
+        for sql in [
+            "select * from t1 as of version 1;",
+            "select * from t1 for update;",
+            "select now() from t1;",
+            "select * from system.stats;",
+        ] {
+            s.execute(sql)?;
+            s.execute(sql)?;
+        }
+        assert_eq!(results.hits()?, 4);
+
+        // A dropped table's results go too
+        s.execute("drop table t2;")?;
+        s.execute("select * from t2;").unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_cache_commits() -> Result<()> {
+        let cache = ResultCache::new(1);
+        let result = || ResultSet::Command { tag: "x".into() };
+        let t1 = HashSet::from(["t1".to_string()]);
+
+        // No snapshot holds a version while a commit is under way
+        cache.insert("a".into(), 0, vec!["t1".into()], result())?;
+        cache.commit(&HashSet::new(), || {
+            assert_eq!(cache.version()?, None);
+            assert_eq!(cache.get("a")?, None);
+            Ok(())
+        })?;
+        assert_eq!(cache.version()?, Some(1));
+        assert_eq!(cache.get("a")?, Some(result()));
+
+        // Results read before a commit aren't added after it
+        cache.commit(&t1, || Ok(()))?;
+        assert_eq!(cache.get("a")?, None);
+        cache.insert("a".into(), 1, vec!["t1".into()], result())?;
+        assert_eq!(cache.len()?, 0);
+
+        // Beyond the capacity the least recently used goes
+        cache.insert("a".into(), 2, vec![], result())?;
+        cache.insert("b".into(), 2, vec![], result())?;
+        assert_eq!((cache.get("a")?, cache.len()?), (None, 1));
+        Ok(())
+    }
+}
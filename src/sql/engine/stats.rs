@@ -0,0 +1,69 @@
+//! Engine statistics - write conflicts and plan cache use per table
+//!
+//! Transactions are optimistic: a write to a row that a concurrent
+//! transaction already wrote fails with `Error::WriteConflict` rather than
+//! waiting, and the client retries. Counting these failures per table, with
+//! the row that conflicted last, shows where the hot rows are. Sessions also
+//! count how often their statements on a table found a plan in their plan
+//! cache (see `plancache`). The counters live in memory, from engine start,
+//! and are read through the `system.stats` table (`SHOW STATS`).
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{error::Result, sql::types::Value};
+
+/// Counters of one table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableStats {
+    /// Writes that failed on a write conflict
+    pub write_conflicts: u64,
+    /// Primary key of the row the latest conflict was on
+    pub last_conflict_key: Option<Value>,
+    /// Statements that ran a session's cached plan
+    pub plan_cache_hits: u64,
+    /// Cacheable statements that had to be planned
+    pub plan_cache_misses: u64,
+}
+
+/// Statistics shared by all transactions of an engine
+#[derive(Clone, Default)]
+pub struct Stats {
+    tables: Arc<Mutex<BTreeMap<String, TableStats>>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a write conflict on a table's row
+    pub fn record_conflict(&self, table: &str, key: &Value) -> Result<()> {
+        let mut tables = self.tables.lock()?;
+        let stats = tables.entry(table.to_string()).or_default();
+        stats.write_conflicts += 1;
+        stats.last_conflict_key = Some(key.clone());
+        Ok(())
+    }
+
+    /// Counts a plan cache lookup of a statement on the given tables
+    pub fn record_plan_cache<'a>(&self, tables: impl IntoIterator<Item = &'a str>, hit: bool) -> Result<()> {
+        let mut stats = self.tables.lock()?;
+        for table in tables {
+            let stats = stats.entry(table.to_string()).or_default();
+            match hit {
+                true => stats.plan_cache_hits += 1,
+                false => stats.plan_cache_misses += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Counters of each table that had a write conflict or plan cache
+    /// lookup, by table name
+    pub fn tables(&self) -> Result<BTreeMap<String, TableStats>> {
+        Ok(self.tables.lock()?.clone())
+    }
+}
@@ -0,0 +1,224 @@
+//! System tables - read-only virtual tables over engine metadata
+//!
+//! System tables live under the `system.` schema and are computed on every
+//! scan rather than stored. They can't be written, and `get_table_names`
+//! doesn't list them.
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::stats::Stats,
+        schema::{Collation, Column, Table},
+        types::{DataType, Row, Value},
+    },
+    storage::{engine::Engine as StorageEngine, mvcc::MvccTransaction},
+};
+
+/// Active and recently finished transactions
+pub const TRANSACTIONS: &str = "system.transactions";
+/// Write conflicts and plan cache use per table since the engine started,
+/// see `stats`
+pub const STATS: &str = "system.stats";
+
+/// Schema of a system table, if the name is one
+pub fn table(name: &str) -> Option<Table> {
+    let column = |name: &str, datatype, nullable, primary_key| Column {
+        name: name.to_string(),
+        datatype,
+        nullable,
+        default: None,
+        default_fn: None,
+        primary_key,
+        collation: Collation::default(),
+    };
+    match name {
+        TRANSACTIONS => Some(Table {
+            name: name.to_string(),
+            columns: vec![
+                column("version", DataType::BigInt, false, true),
+                column("status", DataType::String, false, false),
+                // Unix millis, NULL unless committed
+                column("commit_time", DataType::BigInt, true, false),
+                column("key_count", DataType::BigInt, false, false),
+            ],
+            partition: None,
+            audit: false,
+            options: Default::default(),
+            storage: Default::default(),
+            indexes: vec![],
+            view: None,
+            views: vec![],
+            readers: vec![],
+        }),
+        STATS => Some(Table {
+            name: name.to_string(),
+            columns: vec![
+                column("table_name", DataType::String, false, true),
+                column("write_conflicts", DataType::BigInt, false, false),
+                // Primary key of the row the latest conflict was on, as text
+                column("last_conflict_key", DataType::String, true, false),
+                column("plan_cache_hits", DataType::BigInt, false, false),
+                column("plan_cache_misses", DataType::BigInt, false, false),
+            ],
+            partition: None,
+            audit: false,
+            options: Default::default(),
+            storage: Default::default(),
+            indexes: vec![],
+            view: None,
+            views: vec![],
+            readers: vec![],
+        }),
+        _ => None,
+    }
+}
+
+/// Computes the rows of a system table, in primary key order
+pub fn rows<E: StorageEngine>(txn: &MvccTransaction<E>, stats: &Stats, name: &str) -> Result<Vec<Row>> {
+    match name {
+        TRANSACTIONS => Ok(txn
+            .transactions()?
+            .into_iter()
+            .map(|info| {
+                vec![
+                    Value::Integer(info.version.into()),
+                    Value::String(info.record.status.to_string().into()),
+                    info.record.committed_at.map_or(Value::Null, |t| Value::Integer(t.into())),
+                    Value::Integer(info.record.keys.into()),
+                ]
+            })
+            .collect()),
+        STATS => Ok(stats
+            .tables()?
+            .into_iter()
+            .map(|(table, stats)| {
+                vec![
+                    Value::String(table.into()),
+                    Value::Integer(stats.write_conflicts.into()),
+                    stats.last_conflict_key.map_or(Value::Null, |key| Value::String(key.to_string().into())),
+                    Value::Integer(stats.plan_cache_hits.into()),
+                    Value::Integer(stats.plan_cache_misses.into()),
+                ]
+            })
+            .collect()),
+        _ => Err(Error::Internal(format!("table {} does not exist", name))),
+    }
+}
+
+/// Rejects writes to system tables
+pub fn check_writable(table: &Table) -> Result<()> {
+    match self::table(&table.name) {
+        Some(_) => Err(Error::Internal(format!("system table {} is read-only", table.name))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::{Error, Result},
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_system_transactions() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("insert into t1 values (1), (2);")?;
+        assert!(s.execute("insert into t1 values (1);").is_err());
+        let open = kvengine.kv.begin()?;
+        open.set(b"key".to_vec(), vec![])?;
+
+        let rows = match s.execute("select * from system.transactions;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["version", "status", "commit_time", "key_count"]);
+                rows
+            }
+            r => panic!("unexpected result {:?}", r),
+        };
+        let summary = rows
+            .iter()
+            .map(|row| (row[0].clone(), row[1].clone(), row[2] != Value::Null, row[3].clone()))
+            .collect::<Vec<_>>();
+        let status = |s: &str| Value::String(s.into());
+        assert_eq!(
+            summary,
+            vec![
+                (Value::Integer(1), status("committed"), true, Value::Integer(1)),
+                // Two rows and the transaction's row count delta
+                (Value::Integer(2), status("committed"), true, Value::Integer(3)),
+                (Value::Integer(3), status("rolled back"), false, Value::Integer(0)),
+                (Value::Integer(4), status("active"), false, Value::Integer(1)),
+                // The scanning statement itself
+                (Value::Integer(5), status("active"), false, Value::Integer(0)),
+            ]
+        );
+
+        match s.execute("select status from system.transactions where version = 3;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![status("rolled back")]]),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("delete from system.transactions;").is_err());
+        assert!(s.execute("insert into system.transactions values (9, 'x', null, 0);").is_err());
+        open.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_stats() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let (mut s1, mut s2) = (kvengine.session()?, kvengine.session()?);
+        s1.execute("create table t1 (a int primary key, b int);")?;
+        s1.execute("create table t2 (a text primary key);")?;
+        s1.execute("insert into t1 values (1, 1), (2, 2);")?;
+        s1.execute("insert into t2 values ('x');")?;
+        s1.execute("begin;")?;
+        let version = match s1.execute("update t1 set b = 10 where a = 1;")? {
+            ResultSet::Update { .. } => kvengine.kv.active_transactions()?[0].0,
+            r => panic!("unexpected result {:?}", r),
+        };
+        s1.execute("delete from t2;")?;
+
+        // The losing writer learns the row, table and blocking transaction
+        assert_eq!(
+            s2.execute("update t1 set b = 20 where a = 1;"),
+            Err(Error::WriteConflict { key: "1".into(), table: Some("t1".into()), version })
+        );
+        assert!(s2.execute("delete from t1 where a = 1;").is_err());
+        assert!(s2.execute("update t2 set a = 'y';").is_err());
+        s2.execute("update t1 set b = 20 where a = 2;")?;
+        s1.execute("commit;")?;
+
+        let text = |s: &str| Value::String(s.into());
+        match s2.execute("show stats;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(
+                    columns,
+                    vec!["table_name", "write_conflicts", "last_conflict_key", "plan_cache_hits", "plan_cache_misses"]
+                );
+                // Each statement on a table had a different text, so missed the plan cache
+                let int = Value::Integer;
+                assert_eq!(
+                    rows,
+                    vec![
+                        vec![text("t1"), int(2), text("1"), int(0), int(5)],
+                        vec![text("t2"), int(1), text("x"), int(0), int(3)],
+                    ]
+                );
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s2.execute("select table_name from system.stats where write_conflicts > 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![text("t1")]]),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s2.execute("delete from system.stats;").is_err());
+        Ok(())
+    }
+}
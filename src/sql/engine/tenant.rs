@@ -0,0 +1,218 @@
+//! Tenants - databases kept apart within one storage engine
+//!
+//! A tenant's sessions (`Database::session_for_tenant`) read and write every
+//! key under a prefix of the tenant's id, so a tenant sees only its own
+//! tables and rows, and its transactions never conflict with another's.
+//!
+//! Tenants can share the schema of the database instead: catalog keys are
+//! then left unprefixed, and only rows, row counts and index entries are kept
+//! apart. The schema is changed outside tenants, for all of them at once;
+//! tenant sessions can't change it.
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        engine::{Durability, Engine as StorageEngine},
+        mvcc::{MvccTransaction, ScanResult},
+    },
+};
+
+/// A tenant of a database
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tenant {
+    pub id: String,
+    /// Whether the tenant shares the database's schema, keeping only its rows apart
+    pub shared_schema: bool,
+}
+
+/// Whose keys an engine's transactions use
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Tenancy {
+    /// The keys outside tenants, of a database whose tenants have their own schemas
+    #[default]
+    None,
+    /// The keys outside tenants, of a database whose tenants share its schema
+    SharedSchema,
+    /// The keys of a tenant
+    Tenant(Tenant),
+}
+
+/// An MVCC transaction's view of the keys of a tenant, or of those outside
+/// tenants
+///
+/// Keys are written and read under the tenant's prefix, which scans strip
+/// from the keys they return, so callers use the same keys in every tenant.
+pub struct Keyspace<E: StorageEngine> {
+    txn: MvccTransaction<E>,
+    /// Prefix of the tenant's keys, empty outside tenants
+    prefix: Vec<u8>,
+    /// Keys starting with this are left unprefixed, shared by all tenants
+    shared: Option<Vec<u8>>,
+}
+
+impl<E: StorageEngine> Keyspace<E> {
+    /// The keys outside tenants
+    pub fn new(txn: MvccTransaction<E>) -> Self {
+        Self { txn, prefix: Vec::new(), shared: None }
+    }
+
+    /// The keys under a tenant's prefix, but for those starting with `shared`
+    pub fn with_prefix(mut self, prefix: Vec<u8>, shared: Option<Vec<u8>>) -> Self {
+        self.prefix = prefix;
+        self.shared = shared;
+        self
+    }
+
+    /// The underlying transaction, seeing every key
+    pub fn mvcc(&self) -> &MvccTransaction<E> {
+        &self.txn
+    }
+
+    /// Whether a key, or the keys starting with a scan prefix, are the tenant's
+    fn prefixed(&self, key: &[u8]) -> bool {
+        !self.prefix.is_empty() && !self.shared.as_ref().is_some_and(|shared| key.starts_with(shared))
+    }
+
+    /// The storage key of a key
+    fn key(&self, key: Vec<u8>) -> Vec<u8> {
+        match self.prefixed(&key) {
+            true => [self.prefix.as_slice(), &key].concat(),
+            false => key,
+        }
+    }
+
+    /// Strips the prefix from the keys of a scan with the given prefix
+    fn strip(&self, prefix: &[u8], mut results: Vec<ScanResult>) -> Vec<ScanResult> {
+        if self.prefixed(prefix) {
+            results.iter_mut().for_each(|result| {
+                result.key.drain(..self.prefix.len());
+            });
+        }
+        results
+    }
+
+    /// Names the key of a write conflict without the prefix
+    fn conflict(&self, err: Error) -> Error {
+        match err {
+            Error::WriteConflict { key, table, version } => {
+                let prefix = self.prefix.escape_ascii().to_string();
+                let key = key.strip_prefix(&prefix).map(str::to_string).unwrap_or(key);
+                Error::WriteConflict { key, table, version }
+            }
+            err => err,
+        }
+    }
+
+    pub fn commit(&self) -> Result<()> {
+        self.txn.commit()
+    }
+
+    pub fn rollback(&self) -> Result<()> {
+        self.txn.rollback()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.txn.version()
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.txn.set_durability(durability);
+    }
+
+    pub fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.txn.get(self.key(key))
+    }
+
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.txn.set(self.key(key), value).map_err(|err| self.conflict(err))
+    }
+
+    pub fn delete(&self, key: Vec<u8>) -> Result<()> {
+        self.txn.delete(self.key(key)).map_err(|err| self.conflict(err))
+    }
+
+    pub fn set_batch(&self, writes: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let writes = writes.into_iter().map(|(key, value)| (self.key(key), value)).collect();
+        self.txn.set_batch(writes).map_err(|err| self.conflict(err))
+    }
+
+    pub fn lock(&self, key: Vec<u8>) -> Result<()> {
+        self.txn.lock(self.key(key)).map_err(|err| self.conflict(err))
+    }
+
+    pub fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<ScanResult>> {
+        self.scan_prefix_limit(prefix, usize::MAX, |_, _| Ok(true))
+    }
+
+    pub fn scan_prefix_limit(
+        &self,
+        prefix: Vec<u8>,
+        limit: usize,
+        mut accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<ScanResult>> {
+        let skip = if self.prefixed(&prefix) { self.prefix.len() } else { 0 };
+        let results = self.txn.scan_prefix_limit(self.key(prefix.clone()), limit, |key, value| accept(&key[skip..], value))?;
+        Ok(self.strip(&prefix, results))
+    }
+
+    pub fn scan_prefix_after(&self, prefix: Vec<u8>, after: Vec<u8>, limit: usize) -> Result<Vec<ScanResult>> {
+        let results = self.txn.scan_prefix_after(self.key(prefix.clone()), self.key(after), limit)?;
+        Ok(self.strip(&prefix, results))
+    }
+
+    pub fn scan_prefix_rev(
+        &self,
+        prefix: Vec<u8>,
+        limit: usize,
+        mut accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<ScanResult>> {
+        let skip = if self.prefixed(&prefix) { self.prefix.len() } else { 0 };
+        let results = self.txn.scan_prefix_rev(self.key(prefix.clone()), limit, |key, value| accept(&key[skip..], value))?;
+        Ok(self.strip(&prefix, results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keyspace;
+    use crate::{
+        error::{Error, Result},
+        storage::{
+            memory::MemoryEngine,
+            mvcc::{Mvcc, ScanResult},
+        },
+    };
+
+    #[test]
+    fn test_keyspace() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let keyspace = |prefix: &[u8]| -> Result<_> {
+            Ok(Keyspace::new(mvcc.begin()?).with_prefix(prefix.to_vec(), Some(b"s".to_vec())))
+        };
+
+        let txn = keyspace(b"a/")?;
+        txn.set(b"k1".to_vec(), vec![1])?;
+        txn.set_batch(vec![(b"k2".to_vec(), vec![2])])?;
+        txn.set(b"s1".to_vec(), vec![3])?;
+        txn.commit()?;
+
+        // Scans return the keys as written, and shared keys are seen by all
+        let txn = keyspace(b"b/")?;
+        assert!(txn.scan_prefix(b"k".to_vec())?.is_empty());
+        assert_eq!(txn.get(b"s1".to_vec())?, Some(vec![3]));
+        let txn = keyspace(b"a/")?;
+        let keys = |results: Vec<ScanResult>| results.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys(txn.scan_prefix(b"k".to_vec())?), vec![b"k1".to_vec(), b"k2".to_vec()]);
+        assert_eq!(keys(txn.scan_prefix_after(b"k".to_vec(), b"k1".to_vec(), 10)?), vec![b"k2".to_vec()]);
+        assert_eq!(keys(txn.scan_prefix_rev(b"k".to_vec(), 1, |key, _| Ok(key == b"k1"))?), vec![b"k1".to_vec()]);
+        let raw = mvcc.begin()?;
+        assert_eq!(raw.get(b"a/k1".to_vec())?, Some(vec![1]));
+        assert_eq!(raw.get(b"s1".to_vec())?, Some(vec![3]));
+
+        // Conflicts name the key without the prefix
+        let other = keyspace(b"a/")?;
+        txn.set(b"k1".to_vec(), vec![4])?;
+        assert!(matches!(other.set(b"k1".to_vec(), vec![5]), Err(Error::WriteConflict { key, .. }) if key == "k1"));
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap};
 
 use crate::{
     error::{Error, Result},
@@ -9,7 +9,7 @@ use crate::{
     },
 };
 
-use super::{Executor, ResultSet};
+use super::{ColumnMetadata, Executor, MemoryBudget, ResultSet, collations};
 
 /// Aggregate executor for COUNT, SUM, MIN, MAX, AVG functions
 ///
@@ -19,6 +19,11 @@ pub struct Aggregate<T: Transaction> {
     source: Box<dyn Executor<T>>,
     exprs: Vec<(Expression, Option<String>)>,
     group_by: Option<Expression>,
+    tables: Vec<String>,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+    budget: MemoryBudget,
 }
 
 impl<T: Transaction> Aggregate<T> {
@@ -26,38 +31,36 @@ impl<T: Transaction> Aggregate<T> {
         source: Box<dyn Executor<T>>,
         exprs: Vec<(Expression, Option<String>)>,
         group_by: Option<Expression>,
+        tables: Vec<String>,
+        output: Vec<String>,
+        metadata: Vec<ColumnMetadata>,
+        budget: MemoryBudget,
     ) -> Box<Self> {
         Box::new(Self {
             source,
             exprs,
             group_by,
+            tables,
+            columns: output,
+            metadata,
+            budget,
         })
     }
 }
 
 impl<T: Transaction> Executor<T> for Aggregate<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        if let ResultSet::Scan { columns, rows } = self.source.execute(txn)? {
-            let mut new_cols = Vec::new();
+        if let ResultSet::Scan { columns, rows, .. } = self.source.execute(txn)? {
             let mut new_rows = Vec::new();
 
             // Compute aggregate values for a group of rows
-            let mut calc = |col_val: Option<&Value>, rows: &Vec<Vec<Value>>| -> Result<Vec<Value>> {
+            let calc = |col_val: Option<&Value>, rows: &Vec<Vec<Value>>| -> Result<Vec<Value>> {
                 let mut new_row = Vec::new();
-                for (expr, alias) in &self.exprs {
+                for (expr, _) in &self.exprs {
                     match expr {
                         ast::Expression::Function(func_name, col_name) => {
                             let calculator = <dyn Calculator>::build(&func_name)?;
                             let val = calculator.calc(&col_name, &columns, rows)?;
-
-                            // Use alias if provided, otherwise use function name
-                            if new_cols.len() < self.exprs.len() {
-                                new_cols.push(if let Some(a) = alias {
-                                    a.clone()
-                                } else {
-                                    func_name.clone()
-                                });
-                            }
                             new_row.push(val);
                         }
                         // Group key column
@@ -76,14 +79,6 @@ impl<T: Transaction> Executor<T> for Aggregate<T> {
                                     )));
                                 }
                             }
-
-                            if new_cols.len() < self.exprs.len() {
-                                new_cols.push(if let Some(a) = alias {
-                                    a.clone()
-                                } else {
-                                    col.clone()
-                                });
-                            }
                             new_row.push(col_val.unwrap().clone());
                         }
                         _ => return Err(Error::Internal("unexpected expression".into())),
@@ -103,15 +98,18 @@ impl<T: Transaction> Executor<T> for Aggregate<T> {
                     }
                 };
 
-                // Group rows by the group key
-                let mut agg_map: HashMap<&Value, Vec<Vec<Value>>> = HashMap::new();
+                // Group rows by the collation key of the group column,
+                // reporting each group under its first value
+                let collation = collations(txn, &self.tables, &columns)?[pos];
+                let mut agg_map: HashMap<Value, (&Value, Vec<Vec<Value>>)> = HashMap::new();
                 for row in rows.iter() {
                     let key = &row[pos];
-                    let value = agg_map.entry(key).or_insert(Vec::new());
-                    value.push(row.clone());
+                    let value = agg_map.entry(collation.fold(key)).or_insert((key, Vec::new()));
+                    self.budget.charge(row)?;
+                    value.1.push(row.clone());
                 }
 
-                for (key, group_rows) in agg_map {
+                for (_, (key, group_rows)) in agg_map {
                     let row = calc(Some(key), &group_rows)?;
                     new_rows.push(row);
                 }
@@ -122,8 +120,9 @@ impl<T: Transaction> Executor<T> for Aggregate<T> {
             }
 
             return Ok(ResultSet::Scan {
-                columns: new_cols,
+                columns: self.columns,
                 rows: new_rows,
+                metadata: self.metadata,
             });
         }
         Err(Error::Internal("Unexpected result set".into()))
@@ -163,6 +162,9 @@ impl Count {
 
 impl Calculator for Count {
     fn calc(&self, col_name: &String, cols: &Vec<String>, rows: &Vec<Vec<Value>>) -> Result<Value> {
+        if col_name == ast::ALL_ROWS {
+            return Ok(Value::Integer(rows.len() as i128));
+        }
         let pos = match cols.iter().position(|c| *c == *col_name) {
             Some(pos) => pos,
             None => return Err(Error::Internal(format!("column {} not in table", col_name))),
@@ -202,7 +204,8 @@ impl Calculator for Min {
             }
         }
         if !values.is_empty() {
-            values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            // Incomparable values (points) count as equal, as in ORDER BY
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
             min_val = values[0].clone();
         }
         Ok(min_val)
@@ -233,7 +236,7 @@ impl Calculator for Max {
             }
         }
         if !values.is_empty() {
-            values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
             max_val = values[values.len() - 1].clone();
         }
         Ok(max_val)
@@ -256,30 +259,24 @@ impl Calculator for Sum {
             None => return Err(Error::Internal(format!("column {} not in table", col_name))),
         };
 
+        // Integers sum exactly, as integers; a float anywhere makes the sum a float
         let mut sum = None;
         for row in rows.iter() {
-            match row[pos] {
-                Value::Null => {}
-                Value::Integer(v) => {
-                    if sum == None {
-                        sum = Some(0.0);
-                    }
-                    sum = Some(sum.unwrap() + v as f64);
-                }
-                Value::Float(v) => {
-                    if sum == None {
-                        sum = Some(0.0);
-                    }
-                    sum = Some(sum.unwrap() + v);
-                }
+            sum = match (sum, &row[pos]) {
+                (sum, Value::Null) => sum,
+                (None, v @ (Value::Integer(_) | Value::Float(_))) => Some(v.clone()),
+                (Some(Value::Integer(s)), Value::Integer(v)) => Some(Value::Integer(s.checked_add(*v).ok_or_else(
+                    || Error::Internal(format!("integer overflow in sum of column {}", col_name)),
+                )?)),
+                (Some(Value::Integer(s)), Value::Float(v)) => Some(Value::Float(s as f64 + v)),
+                (Some(Value::Float(s)), Value::Integer(v)) => Some(Value::Float(s + *v as f64)),
+                (Some(Value::Float(s)), Value::Float(v)) => Some(Value::Float(s + v)),
                 _ => return Err(Error::Internal(format!("can not calc column {}", col_name))),
-            }
+            };
         }
 
-        Ok(match sum {
-            Some(s) => Value::Float(s),
-            None => Value::Null,
-        })
+        // Over no values (or only NULLs) the sum is NULL, not 0
+        Ok(sum.unwrap_or(Value::Null))
     }
 }
 
@@ -297,6 +294,7 @@ impl Calculator for Avg {
         let sum = Sum::new().calc(col_name, cols, rows)?;
         let count = Count::new().calc(col_name, cols, rows)?;
         Ok(match (sum, count) {
+            (Value::Integer(s), Value::Integer(c)) => Value::Float(s as f64 / c as f64),
             (Value::Float(s), Value::Integer(c)) => Value::Float(s / c as f64),
             _ => Value::Null,
         })
@@ -0,0 +1,226 @@
+//! Audit log - before and after images of an audited table's rows
+//!
+//! A table created `WITH (audit = true)` gets a hidden companion table
+//! `_audit_<table>`, which the mutation executors append a row to for every
+//! row they insert, update or delete, in the same transaction. Its columns:
+//!
+//! - `id`: primary key, ordered by transaction and then by change
+//! - `version`: version of the transaction that made the change
+//! - `timestamp`: wall-clock time of the change, in Unix millis
+//! - `operation`: `INSERT`, `UPDATE` or `DELETE`
+//! - `old_<column>` and `new_<column>` per table column: the row before and
+//!   after the change, NULL for the side that doesn't exist
+//!
+//! Audit tables can be queried like any table, but not written: their rows
+//! only come from changes to the audited table.
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::Transaction,
+        schema::{Collation, Column, StorageFormat, Table},
+        types::{DataType, Row, Value},
+    },
+    storage::mvcc::now_millis,
+};
+
+/// Name prefix of audit tables, reserved for them
+pub const PREFIX: &str = "_audit_";
+
+/// Name of a table's audit table
+pub fn table_name(table: &str) -> String {
+    format!("{}{}", PREFIX, table)
+}
+
+/// Whether a table name is an audit table's
+pub fn is_audit_table(table: &str) -> bool {
+    table.starts_with(PREFIX)
+}
+
+/// Schema of an audited table's audit table
+pub fn audit_table(table: &Table) -> Table {
+    let column = |name: String, datatype, nullable, primary_key, collation| Column {
+        name,
+        datatype,
+        nullable,
+        default: None,
+        default_fn: None,
+        primary_key,
+        collation,
+    };
+    let mut columns = vec![
+        column("id".into(), DataType::BigInt, false, true, Collation::default()),
+        column("version".into(), DataType::BigInt, false, false, Collation::default()),
+        column("timestamp".into(), DataType::BigInt, false, false, Collation::default()),
+        column("operation".into(), DataType::String, false, false, Collation::default()),
+    ];
+    for side in ["old", "new"] {
+        for c in &table.columns {
+            columns.push(column(format!("{}_{}", side, c.name), c.datatype, true, false, c.collation));
+        }
+    }
+    Table {
+        name: table_name(&table.name),
+        columns,
+        partition: None,
+        storage: StorageFormat::Row,
+        audit: false,
+        options: Default::default(),
+        indexes: vec![],
+        view: None,
+        views: vec![],
+        readers: vec![],
+    }
+}
+
+/// Appends a mutation's changes to a table's audit table
+pub struct AuditLog {
+    table: String,
+    /// Columns of the audited table
+    width: usize,
+    version: u64,
+    /// Changes the transaction logged before this statement, plus this statement's so far
+    changes: u64,
+}
+
+impl AuditLog {
+    /// Opens the audit log of a table, None if the table isn't audited
+    pub fn open<T: Transaction>(txn: &T, table: &Table) -> Result<Option<Self>> {
+        if !table.audit {
+            return Ok(None);
+        }
+        let audit = txn.must_get_table(table_name(&table.name))?;
+        let version = txn.version();
+        // Earlier statements of the transaction took ids 1..n; find n by
+        // galloping up to a free id, then bisecting
+        let taken = |n: u64| -> Result<bool> { Ok(txn.get_row(&audit, &id(version, n)?)?.is_some()) };
+        let mut high = 1;
+        while taken(high)? {
+            high *= 2;
+        }
+        let mut low = high / 2;
+        while low + 1 < high {
+            let mid = (low + high) / 2;
+            match taken(mid)? {
+                true => low = mid,
+                false => high = mid,
+            }
+        }
+        Ok(Some(Self { table: audit.name, width: table.columns.len(), version, changes: low }))
+    }
+
+    /// Appends a change; `old` is None for inserts and `new` for deletes
+    pub fn append<T: Transaction>(&mut self, txn: &mut T, old: Option<&Row>, new: Option<&Row>) -> Result<()> {
+        let operation = match (old, new) {
+            (None, Some(_)) => "INSERT",
+            (Some(_), Some(_)) => "UPDATE",
+            (Some(_), None) => "DELETE",
+            (None, None) => return Err(Error::Internal("audited change without a row".into())),
+        };
+        self.changes += 1;
+        let mut row = vec![
+            id(self.version, self.changes)?,
+            Value::Integer(self.version.into()),
+            Value::Integer(now_millis()?.into()),
+            Value::String(operation.into()),
+        ];
+        for image in [old, new] {
+            match image {
+                Some(image) => row.extend(image.iter().cloned()),
+                None => row.extend((0..self.width).map(|_| Value::Null)),
+            }
+        }
+        txn.create_row(self.table.clone(), row)
+    }
+}
+
+/// Id of a transaction's `n`th change, 1-based
+fn id(version: u64, n: u64) -> Result<Value> {
+    if n >= 1 << 32 {
+        return Err(Error::Internal(format!("transaction {} made too many audited changes", version)));
+    }
+    Ok(Value::Integer(((version as i128) << 32) | n as i128))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::Database,
+        error::Result,
+        sql::{
+            engine::{Engine, Transaction, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn rows(result: ResultSet) -> Vec<Vec<Value>> {
+        match result {
+            ResultSet::Scan { rows, .. } => rows,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_audit() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text) with (audit = true);")?;
+        s.execute("create table t2 (a int primary key);")?;
+        s.execute("insert into t1 values (1, 'x'), (2, 'y');")?;
+        s.execute("update t1 set b = 'z' where a = 1;")?;
+        s.execute("delete from t1 where a = 2;")?;
+        // Rolled back changes leave no audit rows
+        assert!(s.execute("insert into t1 values (3, 'w'), (1, 'v');").is_err());
+
+        let (i, text) = (Value::Integer, |s: &str| Value::String(s.into()));
+        assert_eq!(
+            rows(s.execute("select operation, old_a, old_b, new_a, new_b from _audit_t1;")?),
+            vec![
+                vec![text("INSERT"), Value::Null, Value::Null, i(1), text("x")],
+                vec![text("INSERT"), Value::Null, Value::Null, i(2), text("y")],
+                vec![text("UPDATE"), i(1), text("x"), i(1), text("z")],
+                vec![text("DELETE"), i(2), text("y"), Value::Null, Value::Null],
+            ]
+        );
+        let meta = rows(s.execute("select id, version, timestamp from _audit_t1;")?);
+        assert!(meta.windows(2).all(|w| w[0][0] < w[1][0] && w[0][1] <= w[1][1]));
+        assert_eq!(meta[0][1], meta[1][1]);
+        assert!(meta.iter().all(|row| row[2] > Value::Integer(0)));
+
+        // Audit tables are read-only, hidden and only exist for audited tables
+        for sql in [
+            "insert into _audit_t1 values (1, 1, 1, 'INSERT', null, null, null, null);",
+            "update _audit_t1 set operation = 'DELETE';",
+            "delete from _audit_t1;",
+            "create table _audit_t3 (a int primary key);",
+            "select * from _audit_t2;",
+        ] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.get_table_names()?, vec!["t1", "t2"]);
+        txn.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit_statements_in_transaction() -> Result<()> {
+        let db = Database::new(MemoryEngine::new());
+        db.session()?.execute("create table t1 (a int primary key) with (audit = true);")?;
+
+        // Each statement continues the transaction's ids
+        let txn = db.kv_txn()?;
+        db.execute_in(&txn, "insert into t1 values (1), (2), (3);")?;
+        db.execute_in(&txn, "insert into t1 values (4);")?;
+        db.execute_in(&txn, "delete from t1 where a > 0;")?;
+        txn.commit()?;
+
+        let audit = rows(db.session()?.execute("select id, version from _audit_t1;")?);
+        let base = (txn.version() as i128) << 32;
+        let expected = (1..=8).map(|n| vec![Value::Integer(base | n), Value::Integer(txn.version().into())]);
+        assert_eq!(audit, expected.collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(feature = "parquet")]
+use crate::sql::parquet;
+use crate::{error::Result, sql::{engine::Transaction, executor::ResultSet, parser::ast::CopyDirection}};
+
+use super::{Executor, bulk_insert_rows, insert_rows};
+
+/// COPY executor - exports a table to, or imports rows from, a Parquet file
+///
+/// Imported fields are matched to columns by name; missing columns take their defaults.
+pub struct Copy {
+    table_name: String,
+    direction: CopyDirection,
+    path: String,
+}
+
+impl Copy {
+    pub fn new(table_name: String, direction: CopyDirection, path: String) -> Box<Self> {
+        Box::new(Self { table_name, direction, path })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Copy {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name.clone())?;
+        let count = match self.direction {
+            CopyDirection::To => {
+                let rows = txn.scan_table(self.table_name, None)?.collect::<Result<Vec<_>>>()?;
+                parquet::write(&self.path, &table, &rows)?;
+                rows.len()
+            }
+            CopyDirection::From => {
+                let (columns, rows) = parquet::read(&self.path)?;
+                insert_rows(txn, &self.table_name, &columns, rows)?
+            }
+        };
+        Ok(ResultSet::Copy { count })
+    }
+}
+
+/// LOAD DATA executor - bulk loads rows from a Parquet file
+///
+/// Fields are matched to columns as for COPY FROM, but the rows are written
+/// in batches by `Transaction::bulk_insert`, for large loads.
+pub struct LoadData {
+    table_name: String,
+    path: String,
+}
+
+impl LoadData {
+    pub fn new(table_name: String, path: String) -> Box<Self> {
+        Box::new(Self { table_name, path })
+    }
+}
+
+impl<T: Transaction> Executor<T> for LoadData {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (columns, rows) = parquet::read(&self.path)?;
+        let count = bulk_insert_rows(txn, &self.table_name, &columns, rows)?;
+        Ok(ResultSet::Copy { count })
+    }
+}
+
+/// Stand-in for builds without Parquet support, where COPY and LOAD DATA fail
+#[cfg(not(feature = "parquet"))]
+mod parquet {
+    use crate::{error::{Error, Result}, sql::{schema::Table, types::Row}};
+
+    fn unsupported() -> Error {
+        Error::Internal("COPY and LOAD DATA require the parquet feature".into())
+    }
+
+    pub fn write(_path: &str, _table: &Table, _rows: &[Row]) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn read(_path: &str) -> Result<(Vec<String>, Vec<Row>)> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,335 @@
+//! EXPLAIN executor - renders a plan tree, and with ANALYZE runs it
+//!
+//! EXPLAIN ANALYZE builds the plan's executors with a `Profile`: each one
+//! is wrapped to time its `execute` and count the rows it returns. A node's
+//! time includes its inputs', which run within it. The statement's effects
+//! are real, as in Postgres: EXPLAIN ANALYZE of a DELETE deletes the rows.
+//!
+//! FORMAT = JSON serializes the same tree for tools, and FORMAT = DOT draws
+//! it as a Graphviz graph.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+    iter::Peekable,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::Result,
+    sql::{
+        engine::{Transaction, querylog::row_count},
+        executor::{ColumnMetadata, Executor, MemoryBudget, ResultSet},
+        parser::ast::ExplainFormat,
+        plan::Node,
+        types::Value,
+    },
+};
+
+/// EXPLAIN [ANALYZE] executor, returning the plan as rows of text: one per
+/// node in the tree format, or a single one holding a JSON or dot document
+pub struct Explain {
+    source: Node,
+    analyze: bool,
+    format: ExplainFormat,
+    budget: MemoryBudget,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+}
+
+impl Explain {
+    pub fn new(
+        source: Node,
+        analyze: bool,
+        format: ExplainFormat,
+        budget: MemoryBudget,
+        columns: Vec<String>,
+        metadata: Vec<ColumnMetadata>,
+    ) -> Box<Self> {
+        Box::new(Self { source, analyze, format, budget, columns, metadata })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for Explain {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let plan = match self.analyze {
+            true => {
+                let profile = Rc::new(Profile::default());
+                <dyn Executor<T>>::build_profiled(self.source, &self.budget, &profile).execute(txn)?;
+                profile.plan()
+            }
+            false => PlanNode::new(&self.source),
+        };
+        let lines = match self.format {
+            ExplainFormat::Tree => {
+                let mut lines = Vec::new();
+                plan.render(0, &mut lines);
+                lines
+            }
+            ExplainFormat::Json => vec![serde_json::to_string_pretty(&plan)?],
+            ExplainFormat::Dot => vec![plan.dot()],
+        };
+        let rows = lines.into_iter().map(|line| vec![Value::String(line.into())]).collect();
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
+
+/// A plan node as EXPLAIN shows it, serialized as is for FORMAT = JSON
+#[derive(Serialize)]
+struct PlanNode {
+    /// Node type, e.g. Scan
+    node: &'static str,
+    /// The node's description, e.g. `Scan t1 (filter: a > 1)`
+    label: String,
+    /// With ANALYZE, the rows returned or affected and the milliseconds
+    /// taken, null if never executed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<Option<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_ms: Option<Option<f64>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn new(node: &Node) -> Self {
+        PlanNode {
+            node: node.name(),
+            label: node.describe(),
+            rows: None,
+            time_ms: None,
+            children: node.children().into_iter().map(PlanNode::new).collect(),
+        }
+    }
+
+    /// The label, with the ANALYZE results if any
+    fn summary(&self) -> String {
+        match (self.rows, self.time_ms) {
+            (Some(Some(rows)), Some(Some(ms))) => format!("{} (rows={} time={:.3}ms)", self.label, rows, ms),
+            (Some(_), _) => format!("{} (never executed)", self.label),
+            _ => self.label.clone(),
+        }
+    }
+
+    /// Appends a line per node of the tree, children indented under their parent
+    fn render(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = match depth {
+            0 => String::new(),
+            depth => format!("{}-> ", "   ".repeat(depth - 1)),
+        };
+        lines.push(format!("{}{}", indent, self.summary()));
+        for child in &self.children {
+            child.render(depth + 1, lines);
+        }
+    }
+
+    /// The tree as a Graphviz digraph, edges pointing from a node to its inputs
+    fn dot(&self) -> String {
+        fn write_node(node: &PlanNode, dot: &mut String, next_id: &mut usize) -> usize {
+            let id = *next_id;
+            *next_id += 1;
+            let label = node.summary().replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(dot, "  n{} [label=\"{}\"];", id, label).unwrap();
+            for child in &node.children {
+                let child_id = write_node(child, dot, next_id);
+                writeln!(dot, "  n{} -> n{};", id, child_id).unwrap();
+            }
+            id
+        }
+
+        let mut dot = String::from("digraph plan {\n  node [shape=box];\n");
+        write_node(self, &mut dot, &mut 0);
+        dot.push('}');
+        dot
+    }
+}
+
+/// What one executed plan node did
+struct NodeStats {
+    name: &'static str,
+    label: String,
+    depth: usize,
+    /// Rows returned or affected, and the time taken; None if never executed
+    run: Option<(usize, Duration)>,
+}
+
+/// Row counts and times of a plan's nodes, in plan order
+#[derive(Default)]
+pub(super) struct Profile {
+    nodes: RefCell<Vec<NodeStats>>,
+    /// Depth of the node whose executor is being built
+    depth: Cell<usize>,
+}
+
+impl Profile {
+    /// Adds a node whose executor is about to be built, returning its index;
+    /// the executors built until `leave` are its inputs
+    pub(super) fn enter(&self, node: &Node) -> usize {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(NodeStats { name: node.name(), label: node.describe(), depth: self.depth.get(), run: None });
+        self.depth.set(self.depth.get() + 1);
+        nodes.len() - 1
+    }
+
+    pub(super) fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// The profiled plan as a tree
+    fn plan(&self) -> PlanNode {
+        fn build(nodes: &mut Peekable<impl Iterator<Item = NodeStats>>) -> PlanNode {
+            let stats = nodes.next().expect("a profiled node");
+            let mut node = PlanNode {
+                node: stats.name,
+                label: stats.label,
+                rows: Some(stats.run.map(|(rows, _)| rows)),
+                time_ms: Some(stats.run.map(|(_, elapsed)| elapsed.as_secs_f64() * 1000.0)),
+                children: Vec::new(),
+            };
+            while nodes.peek().is_some_and(|child| child.depth == stats.depth + 1) {
+                node.children.push(build(nodes));
+            }
+            node
+        }
+
+        build(&mut self.nodes.take().into_iter().peekable())
+    }
+}
+
+/// Records the rows and time of an executor in a profile
+pub(super) struct Profiled<T: Transaction> {
+    inner: Box<dyn Executor<T>>,
+    profile: Rc<Profile>,
+    /// Index of the node in the profile
+    id: usize,
+}
+
+impl<T: Transaction> Profiled<T> {
+    pub(super) fn new(inner: Box<dyn Executor<T>>, profile: Rc<Profile>, id: usize) -> Box<Self> {
+        Box::new(Self { inner, profile, id })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Profiled<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let start = Instant::now();
+        let result = self.inner.execute(txn)?;
+        self.profile.nodes.borrow_mut()[self.id].run = Some((row_count(&result), start.elapsed()));
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn lines(result: ResultSet) -> Vec<String> {
+        match result {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["plan"]);
+                rows.into_iter()
+                    .map(|row| match &row[..] {
+                        [Value::String(line)] => line.to_string(),
+                        row => panic!("unexpected row {:?}", row),
+                    })
+                    .collect()
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (c int primary key, d int);")?;
+        s.execute("insert into t1 values (1, 'x'), (2, 'y'), (3, 'x');")?;
+        s.execute("insert into t2 values (1, 10), (3, 30);")?;
+
+        assert_eq!(
+            lines(s.execute("explain select b from t1 join t2 on a = c where b = 'x' order by b limit 2;")?),
+            vec![
+                "Projection (b)",
+                "-> Limit (limit: 2)",
+                "   -> TopN (order: b, limit: 2)",
+                "      -> HashJoin (on: a = c)",
+                "         -> Scan t1 (filter: b = 'x')",
+                "         -> Scan t2 (filter: b = 'x')",
+            ]
+        );
+        // EXPLAIN alone runs nothing
+        assert_eq!(lines(s.execute("explain delete from t1;")?), vec!["Delete t1", "-> Scan t1"]);
+
+        let analyzed = lines(s.execute("explain analyze select * from t1 where b = 'x' order by a desc;")?);
+        assert_eq!(analyzed.len(), 1);
+        assert!(analyzed[0].starts_with("Scan t1 (filter: b = 'x', reverse) (rows=2 time="), "{:?}", analyzed);
+        assert!(analyzed[0].ends_with("ms)"), "{:?}", analyzed);
+
+        // ANALYZE runs the statement, writes included
+        let analyzed = lines(s.execute("explain analyze delete from t1 where a > 1;")?);
+        assert!(analyzed[0].starts_with("Delete t1 (rows=2 "), "{:?}", analyzed);
+        assert!(analyzed[1].starts_with("-> Scan t1 (filter: a > 1) (rows=2 "), "{:?}", analyzed);
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(s.execute("explain analyze select * from t3;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_formats() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("insert into t1 values (1, 'x'), (2, 'y');")?;
+
+        let json = lines(s.execute("explain format = json select b from t1 where b = 'x';")?);
+        assert_eq!(json.len(), 1);
+        let plan: serde_json::Value = serde_json::from_str(&json[0])?;
+        assert_eq!(
+            plan,
+            serde_json::json!({
+                "node": "Projection",
+                "label": "Projection (b)",
+                "children": [{"node": "Scan", "label": "Scan t1 (filter: b = 'x')"}],
+            })
+        );
+
+        // ANALYZE adds the row counts and times
+        let json = lines(s.execute("explain analyze format = json select b from t1 where b = 'x';")?);
+        let plan: serde_json::Value = serde_json::from_str(&json[0])?;
+        assert_eq!(plan["rows"], 1);
+        assert_eq!(plan["children"][0]["rows"], 1);
+        assert!(plan["children"][0]["time_ms"].is_f64());
+
+        let dot = lines(s.execute("explain format = dot select b from t1 where b = 'x';")?);
+        assert_eq!(
+            dot,
+            vec![
+                "digraph plan {\n  node [shape=box];\n  n0 [label=\"Projection (b)\"];\n  \
+                 n1 [label=\"Scan t1 (filter: b = 'x')\"];\n  n0 -> n1;\n}"
+            ]
+        );
+
+        // The tree format is the default
+        assert_eq!(
+            lines(s.execute("explain format = tree select b from t1;")?),
+            lines(s.execute("explain select b from t1;")?)
+        );
+        Ok(())
+    }
+}
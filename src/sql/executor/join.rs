@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+
 use crate::{
     error::{Error, Result},
     sql::{engine::Transaction, parser::ast::{self, Expression, evaluate_expr}, types::Value},
 };
 
-use super::{Executor, ResultSet};
+use super::{ColumnMetadata, Executor, MemoryBudget, ResultSet};
 
 /// Nested Loop Join executor - produces Cartesian product of two tables
 pub struct NestedLoopJoin<T: Transaction> {
@@ -11,6 +13,9 @@ pub struct NestedLoopJoin<T: Transaction> {
     right: Box<dyn Executor<T>>,
     predicate: Option<Expression>,
     outer: bool,
+    /// Rows to stop joining after, None for all
+    limit: Option<usize>,
+    budget: MemoryBudget,
 }
 
 impl<T: Transaction> NestedLoopJoin<T> {
@@ -19,12 +24,16 @@ impl<T: Transaction> NestedLoopJoin<T> {
         right: Box<dyn Executor<T>>,
         predicate: Option<Expression>,
         outer: bool,
+        limit: Option<usize>,
+        budget: MemoryBudget,
     ) -> Box<Self> {
         Box::new(Self {
             left,
             right,
             predicate,
             outer,
+            limit,
+            budget,
         })
     }
 }
@@ -35,21 +44,28 @@ impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
         if let ResultSet::Scan {
             columns: lcols,
             rows: lrows,
+            metadata: lmeta,
         } = self.left.execute(txn)?
         {
             let mut new_rows = Vec::new();
             let mut new_cols = lcols.clone();
+            let mut new_meta = lmeta;
             // Execute right side
             if let ResultSet::Scan {
                 columns: rcols,
                 rows: rrows,
+                metadata: rmeta,
             } = self.right.execute(txn)?
             {
-                // Extend columns
+                // Extend columns; outer joins may pad the right side with NULLs
                 new_cols.extend(rcols.clone());
+                new_meta.extend(rmeta.into_iter().map(|m| ColumnMetadata { nullable: m.nullable || self.outer, ..m }));
 
                 // Nested loop: for each left row, iterate through all right rows
                 for lrow in &lrows {
+                    if self.limit.is_some_and(|limit| new_rows.len() >= limit) {
+                        break;
+                    }
                     let mut matched = false;
                     for rrow in &rrows {
                         let mut row = lrow.clone();
@@ -60,6 +76,7 @@ impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
                                 Value::Boolean(false) => {}
                                 Value::Boolean(true) => {
                                     row.extend(rrow.clone());
+                                    self.budget.charge(&row)?;
                                     new_rows.push(row);
                                     matched = true;
                                 }
@@ -68,6 +85,7 @@ impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
                         } else {
                             // No predicate means CROSS JOIN
                             row.extend(rrow.clone());
+                            self.budget.charge(&row)?;
                             new_rows.push(row);
                         }
                     }
@@ -75,12 +93,16 @@ impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
                     // For outer joins, fill with NULL if no match found
                     if self.outer && !matched {
                         let mut row = lrow.clone();
-                        for _ in 0..rrows[0].len() {
+                        for _ in 0..rcols.len() {
                             row.push(Value::Null);
                         }
+                        self.budget.charge(&row)?;
                         new_rows.push(row);
                     }
                 }
+                if let Some(limit) = self.limit {
+                    new_rows.truncate(limit);
+                }
             }
             /*
             Note: When two tables have duplicate column names in a CROSS JOIN,
@@ -98,9 +120,115 @@ impl<T: Transaction> Executor<T> for NestedLoopJoin<T> {
             return Ok(ResultSet::Scan {
                 columns: new_cols,
                 rows: new_rows,
+                metadata: new_meta,
             });
         }
         Err(Error::Internal("Unexpected result set".into()))
     }
 }
 
+
+/// Hash Join executor - joins rows whose key columns are equal
+///
+/// Builds a hash table of the right rows by key, then probes it with each
+/// left row, producing the same rows in the same order as a nested loop join
+/// on `left_key = right_key`. NULL keys match nothing.
+pub struct HashJoin<T: Transaction> {
+    left: Box<dyn Executor<T>>,
+    right: Box<dyn Executor<T>>,
+    left_key: String,
+    right_key: String,
+    outer: bool,
+    /// Rows to stop joining after, None for all
+    limit: Option<usize>,
+    budget: MemoryBudget,
+}
+
+impl<T: Transaction> HashJoin<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Box<dyn Executor<T>>,
+        left_key: String,
+        right_key: String,
+        outer: bool,
+        limit: Option<usize>,
+        budget: MemoryBudget,
+    ) -> Box<Self> {
+        Box::new(Self {
+            left,
+            right,
+            left_key,
+            right_key,
+            outer,
+            limit,
+            budget,
+        })
+    }
+}
+
+impl<T: Transaction> Executor<T> for HashJoin<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (
+            ResultSet::Scan { columns: mut new_cols, rows: lrows, metadata: mut new_meta },
+            ResultSet::Scan { columns: rcols, rows: rrows, metadata: rmeta },
+        ) = (self.left.execute(txn)?, self.right.execute(txn)?)
+        else {
+            return Err(Error::Internal("Unexpected result set".into()));
+        };
+        let position = |cols: &[String], key: &str| {
+            cols.iter()
+                .position(|c| c == key)
+                .ok_or_else(|| Error::Internal(format!("column {} is not in table", key)))
+        };
+        let lpos = position(&new_cols, &self.left_key)?;
+        let rpos = position(&rcols, &self.right_key)?;
+
+        // Build: right rows by key, in their original order
+        let mut table: HashMap<&Value, Vec<&Vec<Value>>> = HashMap::new();
+        for rrow in &rrows {
+            if rrow[rpos] != Value::Null {
+                table.entry(&rrow[rpos]).or_default().push(rrow);
+            }
+        }
+
+        // Probe: each left row with its matches, or NULLs for outer joins
+        let mut new_rows = Vec::new();
+        for lrow in &lrows {
+            if self.limit.is_some_and(|limit| new_rows.len() >= limit) {
+                break;
+            }
+            let matches = match &lrow[lpos] {
+                Value::Null => None,
+                key => table.get(key),
+            };
+            match matches {
+                Some(matches) => {
+                    for rrow in matches {
+                        let mut row = lrow.clone();
+                        row.extend(rrow.iter().cloned());
+                        self.budget.charge(&row)?;
+                        new_rows.push(row);
+                    }
+                }
+                None if self.outer => {
+                    let mut row = lrow.clone();
+                    row.extend(rcols.iter().map(|_| Value::Null));
+                    self.budget.charge(&row)?;
+                    new_rows.push(row);
+                }
+                None => {}
+            }
+        }
+        if let Some(limit) = self.limit {
+            new_rows.truncate(limit);
+        }
+
+        new_cols.extend(rcols);
+        new_meta.extend(rmeta.into_iter().map(|m| ColumnMetadata { nullable: m.nullable || self.outer, ..m }));
+        Ok(ResultSet::Scan {
+            columns: new_cols,
+            rows: new_rows,
+            metadata: new_meta,
+        })
+    }
+}
@@ -1,10 +1,19 @@
-use crate::{error::Result, sql::{engine::Transaction, executor::{agg::Aggregate, join::NestedLoopJoin, mutation::{Delete, Insert, Update}, query::{Filter, Limit, Offset, Order, Projection, Scan}, schema::CreateTable}, plan::Node, types::Row}};
+use std::{cell::Cell, rc::Rc};
+
+use crate::{error::{Error, Result}, sql::{analyzer::Scope, engine::{Rows, Transaction}, executor::{agg::Aggregate, copy::{Copy, LoadData}, explain::{Explain, Profile, Profiled}, join::{HashJoin, NestedLoopJoin}, mutation::{Delete, Insert, Update}, query::{Filter, Get, IndexScan, Limit, Lock, Offset, Order, Projection, RowCount, Scan, TopN}, schema::{CreateIndex, CreateTable, DropTable, ShowCreateTable}, view::{CreateMaterializedView, RefreshMaterializedView}}, plan::Node, record::ResultRow, schema::Collation, types::{DataType, Row, Value}}};
 
 mod agg;
+pub mod audit;
+mod copy;
+mod explain;
+pub(crate) mod batch;
 mod schema;
 mod mutation;
 mod query;
 mod join;
+pub mod view;
+
+pub(crate) use mutation::{bulk_insert_rows, insert_rows};
 
 /// Executor trait for running execution plan nodes
 ///
@@ -26,61 +35,324 @@ pub trait Executor<T: Transaction> {
     /// Takes `Box<Self>` to allow executors to consume themselves,
     /// avoiding additional allocation when building executor chains.
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet>;
+
+    /// Executes the plan node into rows yielded as they're produced, so a
+    /// consumer like LIMIT can stop before its input is read
+    ///
+    /// By default the node is executed and its result's rows yielded; table
+    /// scans, and filters and limits over them, stream instead.
+    fn stream<'a>(self: Box<Self>, txn: &'a mut T) -> Result<RowStream<'a>> {
+        RowStream::from_result(self.execute(txn)?)
+    }
+}
+
+/// Rows of a query as an executor yields them, with the result's columns
+pub struct RowStream<'a> {
+    pub columns: Vec<String>,
+    pub rows: Rows<'a>,
+    pub metadata: Vec<ColumnMetadata>,
+}
+
+impl RowStream<'_> {
+    /// Yields the rows of a query result
+    pub fn from_result(result: ResultSet) -> Result<Self> {
+        match result {
+            ResultSet::Scan { columns, rows, metadata } => {
+                Ok(Self { columns, rows: Box::new(rows.into_iter().map(Ok)), metadata })
+            }
+            _ => Err(Error::Internal("Unexpected result set".into())),
+        }
+    }
+
+    /// Reads the remaining rows into a result
+    pub fn collect(self) -> Result<ResultSet> {
+        let rows = self.rows.collect::<Result<_>>()?;
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
 }
 
 /// Builds an executor from a plan node
 ///
 /// The `'static` bound is required for trait object usage in recursive executor building.
+/// Row-buffering executors charge the rows they hold against `budget`.
 impl<T: Transaction + 'static> dyn Executor<T> {
-    pub fn build(node: Node) -> Box<dyn Executor<T>> {
+    pub fn build(node: Node, budget: &MemoryBudget) -> Box<dyn Executor<T>> {
+        Self::build_with(node, budget, None)
+    }
+
+    /// Builds an executor recording each node's rows and time in `profile`
+    /// (EXPLAIN ANALYZE)
+    fn build_profiled(node: Node, budget: &MemoryBudget, profile: &Rc<Profile>) -> Box<dyn Executor<T>> {
+        Self::build_with(node, budget, Some(profile))
+    }
+
+    fn build_with(node: Node, budget: &MemoryBudget, profile: Option<&Rc<Profile>>) -> Box<dyn Executor<T>> {
+        let id = profile.map(|profile| profile.enter(&node));
+        #[cfg(feature = "tracing")]
+        let executor = Traced::new(node.name(), Self::build_node(node, budget, profile));
+        #[cfg(not(feature = "tracing"))]
+        let executor = Self::build_node(node, budget, profile);
+        match (profile, id) {
+            (Some(profile), Some(id)) => {
+                profile.leave();
+                Profiled::new(executor, profile.clone(), id)
+            }
+            _ => executor,
+        }
+    }
+
+    fn build_node(node: Node, budget: &MemoryBudget, profile: Option<&Rc<Profile>>) -> Box<dyn Executor<T>> {
+        let build = |node: Box<Node>| Self::build_with(*node, budget, profile);
         match node {
             Node::CreateTable { schema } => CreateTable::new(schema),
+            Node::CreateIndex { table_name, index } => CreateIndex::new(table_name, index),
+            Node::DropTable { table_name, cascade } => DropTable::new(table_name, cascade),
+            Node::CreateMaterializedView { schema, query } => {
+                let sources = query.output().iter().map(|c| c.table.clone()).collect();
+                CreateMaterializedView::new(schema, sources, build(query))
+            }
+            Node::RefreshMaterializedView { table_name } => RefreshMaterializedView::new(table_name, budget.clone()),
             Node::Insert {
                 table_name,
                 columns,
                 values,
             } => Insert::new(table_name, columns, values),
-            Node::Scan { table_name, filter } => Scan::new(table_name, filter),
+            Node::Scan { table_name, filter, columns, limit, reverse, output } => {
+                Scan::new(table_name, filter, columns, limit, reverse, names(&output), metadata(&output))
+            },
+            Node::IndexScan { table_name, index, value, filter, fetch, limit, output } => {
+                IndexScan::new(table_name, index, value, filter, fetch, limit, names(&output), metadata(&output))
+            }
+            Node::Get { table_name, key, output } => Get::new(table_name, key, names(&output), metadata(&output)),
+            Node::RowCount { table_name, output } => RowCount::new(table_name, names(&output), metadata(&output)),
+            Node::Lock { source, table_name, .. } => Lock::new(build(source), table_name),
             Node::Update {
                 table_name,
                 source,
                 columns,
             } => Update::new(
                 table_name,
-                Self::build(*source),
+                build(source),
                 columns),
-            Node::Delete { table_name, source } => Delete::new(table_name, Self::build(*source)),
-            Node::Order { source, order_by } => Order::new(Self::build(*source), order_by),
-            Node::Limit { source, limit } => Limit::new(Self::build(*source), limit),
-            Node::Offset { source, offset } => Offset::new(Self::build(*source), offset),
-            Node::Projection { source, exprs } => Projection::new(Self::build(*source), exprs),
+            Node::Delete { table_name, source } => Delete::new(table_name, build(source)),
+            Node::Copy { table_name, direction, path } => Copy::new(table_name, direction, path),
+            Node::LoadData { table_name, path } => LoadData::new(table_name, path),
+            Node::ShowCreateTable { table_name, output } => {
+                ShowCreateTable::new(table_name, names(&output), metadata(&output))
+            }
+            Node::Order { source, order_by, tables, .. } => {
+                Order::new(build(source), order_by, tables, budget.clone())
+            }
+            Node::TopN { source, order_by, tables, limit, .. } => {
+                TopN::new(build(source), order_by, tables, limit, budget.clone())
+            }
+            Node::Limit { source, limit, .. } => Limit::new(build(source), limit),
+            Node::Offset { source, offset, .. } => Offset::new(build(source), offset),
+            Node::Projection { source, exprs, output } => {
+                Projection::new(build(source), exprs, names(&output), metadata(&output))
+            }
             Node::NestedLoopJoin {
                 left,
                 right,
                 predicate,
                 outer,
-            } => NestedLoopJoin::new(Self::build(*left), Self::build(*right), predicate, outer),
+                limit,
+                ..
+            } => NestedLoopJoin::new(build(left), build(right), predicate, outer, limit, budget.clone()),
+            Node::HashJoin { left, right, left_key, right_key, outer, limit, output: _ } => {
+                HashJoin::new(build(left), build(right), left_key, right_key, outer, limit, budget.clone())
+            }
             Node::Aggregate {
                 source,
                 exprs,
                 group_by,
-            } => Aggregate::new(Self::build(*source), exprs, group_by),
-            Node::Filter { source, predicate } => Filter::new(Self::build(*source), predicate),
+                tables,
+                output,
+            } => {
+                let metadata = metadata(&output);
+                Aggregate::new(build(source), exprs, group_by, tables, names(&output), metadata, budget.clone())
+            }
+            Node::Filter { source, predicate, .. } => Filter::new(build(source), predicate),
+            Node::Explain { source, analyze, format, output } => {
+                Explain::new(*source, analyze, format, budget.clone(), names(&output), metadata(&output))
+            }
+        }
+    }
+}
+
+/// Runs an executor in an `executor` span, recording its node type and row count
+#[cfg(feature = "tracing")]
+struct Traced<T: Transaction> {
+    node: &'static str,
+    inner: Box<dyn Executor<T>>,
+}
+
+#[cfg(feature = "tracing")]
+impl<T: Transaction> Traced<T> {
+    fn new(node: &'static str, inner: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { node, inner })
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<T: Transaction> Executor<T> for Traced<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let span = tracing::debug_span!("executor", node = self.node, rows = tracing::field::Empty);
+        let _entered = span.enter();
+        let result = self.inner.execute(txn);
+        match &result {
+            Ok(ResultSet::Scan { rows, .. }) => {
+                span.record("rows", rows.len());
+            }
+            Ok(
+                ResultSet::Insert { count }
+                | ResultSet::Update { count }
+                | ResultSet::Delete { count }
+                | ResultSet::Copy { count },
+            ) => {
+                span.record("rows", count);
+            }
+            Ok(ResultSet::CreateTable { .. } | ResultSet::Command { .. }) => {}
+            Err(err) => tracing::debug!(error = %err, "executor failed"),
+        }
+        result
+    }
+
+    /// Streams the node's rows; the span covers starting the stream, and
+    /// leaves the row count unrecorded
+    fn stream<'a>(self: Box<Self>, txn: &'a mut T) -> Result<RowStream<'a>> {
+        let _entered = tracing::debug_span!("executor", node = self.node).entered();
+        self.inner.stream(txn)
+    }
+}
+
+/// Column names of a planned output
+fn names(output: &Scope) -> Vec<String> {
+    output.columns.iter().map(|c| c.name.clone()).collect()
+}
+
+/// Column metadata of a planned output
+fn metadata(output: &Scope) -> Vec<ColumnMetadata> {
+    output
+        .columns
+        .iter()
+        .map(|c| ColumnMetadata { datatype: c.datatype, nullable: c.nullable, table: c.table.clone() })
+        .collect()
+}
+
+/// Resolves the collation of result columns from the tables they come from
+///
+/// A column takes the collation of the first source table that has it.
+fn collations<T: Transaction>(txn: &T, tables: &[String], columns: &[String]) -> Result<Vec<Collation>> {
+    let tables = tables
+        .iter()
+        .map(|t| txn.get_table(t.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(columns
+        .iter()
+        .map(|col| {
+            tables
+                .iter()
+                .flatten()
+                .find_map(|t| t.columns.iter().find(|c| c.name == *col).map(|c| c.collation))
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+/// Default per-query memory budget (256 MiB)
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+/// Per-query limit on the row data buffered by executors (Order, TopN, Aggregate, Join)
+///
+/// Sizes are estimates: each value counts its in-memory size plus string
+/// contents. Clones share one running total, so every executor of a query
+/// draws from the same budget.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    /// Limit in bytes, None for unlimited
+    limit: Option<usize>,
+    used: Rc<Cell<usize>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { limit, used: Rc::new(Cell::new(0)) }
+    }
+
+    /// Bytes charged so far
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Charges a buffered row, failing once the budget is exceeded
+    pub fn charge(&self, row: &Row) -> Result<()> {
+        let size = row.iter().map(value_size).sum::<usize>() + size_of::<Row>();
+        let used = self.used.get() + size;
+        self.used.set(used);
+        match self.limit {
+            Some(limit) if used > limit => Err(Error::OutOfMemoryBudget(limit)),
+            _ => Ok(()),
         }
     }
+
+    /// Charges every row of a buffered result
+    pub fn charge_all(&self, rows: &[Row]) -> Result<()> {
+        rows.iter().try_for_each(|row| self.charge(row))
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(Some(DEFAULT_MEMORY_BUDGET))
+    }
+}
+
+fn value_size(value: &Value) -> usize {
+    size_of::<Value>()
+        + match value {
+            Value::String(s) => s.len(),
+            _ => 0,
+        }
 }
 
 /// Execution result returned by SQL statements
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResultSet {
     /// CREATE TABLE result
     CreateTable { table_name: String },
     /// INSERT result with number of rows inserted
     Insert { count: usize },
-    /// SELECT/SCAN result with column names and row data
-    Scan { columns: Vec<String>, rows: Vec<Row> },
+    /// SELECT/SCAN result with column names, row data, and per-column metadata
+    Scan { columns: Vec<String>, rows: Vec<Row>, metadata: Vec<ColumnMetadata> },
     /// UPDATE result with number of rows modified
     Update { count: usize },
     /// DELETE result with number of rows deleted
     Delete { count: usize },
+    /// COPY result with number of rows exported or imported
+    Copy { count: usize },
+    /// CREATE INDEX, BEGIN, COMMIT, ROLLBACK or SET result, tagged with the command
+    Command { tag: String },
+}
+
+impl ResultSet {
+    /// Rows of a query result, whose values can be read by column name or
+    /// position (see `ResultRow`)
+    pub fn rows(&self) -> Result<impl Iterator<Item = ResultRow<'_>>> {
+        match self {
+            ResultSet::Scan { columns, rows, .. } => Ok(rows.iter().map(|values| ResultRow::new(columns, values))),
+            result => Err(Error::Internal(format!("expected a query result, got {:?}", result))),
+        }
+    }
+}
+
+/// Description of a result column beyond its name, for typed clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMetadata {
+    /// None when not known statically (e.g., NULL or unbound plans)
+    pub datatype: Option<DataType>,
+    /// False only when the column can never be NULL
+    pub nullable: bool,
+    /// Table the column is read from, None for computed columns
+    pub table: Option<String>,
 }
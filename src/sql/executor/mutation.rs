@@ -1,8 +1,8 @@
 use std::collections::{BTreeMap, HashMap};
 
-use crate::{error::{Error, Result}, sql::{engine::Transaction, executor::ResultSet, parser::ast::Expression, schema::Table, types::{Row, Value}}};
+use crate::{error::{Error, Result}, sql::{engine::Transaction, functions, executor::ResultSet, parser::ast::{Expression, evaluate_const_expr, evaluate_expr}, schema::Table, types::Row}};
 
-use super::Executor;
+use super::{Executor, audit::AuditLog};
 
 /// INSERT executor
 pub struct Insert {
@@ -29,7 +29,9 @@ impl Insert {
 fn pad_row(table: &Table, row: &Row) -> Result<Row> {
     let mut results = row.clone();
     for column in table.columns.iter().skip(row.len()) {
-        if let Some(default) = &column.default {
+        if let Some(name) = &column.default_fn {
+            results.push(functions::call(name, Vec::new())?);
+        } else if let Some(default) = &column.default {
             results.push(default.clone());
         } else {
             return Err(Error::Internal(format!(
@@ -56,6 +58,8 @@ fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> Result<Row> {
     for col in table.columns.iter() {
         if let Some(value) = inputs.get(&col.name) {
             results.push(value.clone());
+        } else if let Some(name) = &col.default_fn {
+            results.push(functions::call(name, Vec::new())?);
         } else if let Some(value) = &col.default {
             results.push(value.clone());
         } else {
@@ -71,25 +75,78 @@ fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> Result<Row> {
 
 impl<T: Transaction> Executor<T> for Insert {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        let table = txn.must_get_table(self.table_name.clone())?;
-        let mut count = 0;
-
-        for exprs in self.values {
-            let row: Row = exprs.into_iter()
-                .map(Value::from_expression)
-                .collect();
+        let rows = self
+            .values
+            .iter()
+            .map(|exprs| exprs.iter().map(evaluate_const_expr).collect::<Result<Row>>())
+            .collect::<Result<Vec<_>>>()?;
+        let count = insert_rows(txn, &self.table_name, &self.columns, rows)?;
+        Ok(ResultSet::Insert { count })
+    }
+}
 
-            let insert_row = if self.columns.is_empty() {
-                pad_row(&table, &row)?
-            } else {
-                make_row(&table, &self.columns, &row)?
-            };
+/// Inserts evaluated rows, given for `columns` (empty for all, in table order)
+pub(crate) fn insert_rows<T: Transaction>(
+    txn: &mut T,
+    table_name: &str,
+    columns: &Vec<String>,
+    rows: Vec<Row>,
+) -> Result<usize> {
+    let table = txn.must_get_table(table_name.to_string())?;
+    for col in columns {
+        table.get_col_index(col)?;
+    }
+    let mut audit = AuditLog::open(txn, &table)?;
+    let mut count = 0;
+    for row in rows {
+        let insert_row = if columns.is_empty() {
+            pad_row(&table, &row)?
+        } else {
+            make_row(&table, columns, &row)?
+        };
 
-            txn.create_row(self.table_name.clone(), insert_row)?;
-            count += 1;
+        // Changes are audited once made, so failed writes aren't logged
+        match &mut audit {
+            Some(audit) => {
+                txn.create_row(table_name.to_string(), insert_row.clone())?;
+                audit.append(txn, None, Some(&insert_row))?;
+            }
+            None => txn.create_row(table_name.to_string(), insert_row)?,
         }
-        Ok(ResultSet::Insert { count })
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Inserts evaluated rows like `insert_rows`, writing them all at once by
+/// `Transaction::bulk_insert`
+pub(crate) fn bulk_insert_rows<T: Transaction>(
+    txn: &mut T,
+    table_name: &str,
+    columns: &Vec<String>,
+    rows: Vec<Row>,
+) -> Result<usize> {
+    let table = txn.must_get_table(table_name.to_string())?;
+    for col in columns {
+        table.get_col_index(col)?;
+    }
+    let rows = rows
+        .iter()
+        .map(|row| match columns.is_empty() {
+            true => pad_row(&table, row),
+            false => make_row(&table, columns, row),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Changes are audited once made, so failed writes aren't logged
+    let Some(mut audit) = AuditLog::open(txn, &table)? else {
+        return txn.bulk_insert(table_name.to_string(), rows);
+    };
+    let count = txn.bulk_insert(table_name.to_string(), rows.clone())?;
+    for row in &rows {
+        audit.append(txn, None, Some(row))?;
     }
+    Ok(count)
 }
 
 /// UPDATE executor
@@ -118,18 +175,40 @@ impl<T: Transaction> Executor<T> for Update<T> {
     fn execute(self: Box<Self>, txn:&mut T) -> Result<ResultSet> {
         let mut count = 0;
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns, rows } => {
+            ResultSet::Scan { columns, rows, .. } => {
                 let table = txn.must_get_table(self.table_name)?;
+                let mut audit = AuditLog::open(txn, &table)?;
+                // SET targets and expressions refer to the table's columns, whatever
+                // order the source returned its columns in
+                let names = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+                let positions = names
+                    .iter()
+                    .map(|name| match columns.iter().position(|c| c == name) {
+                        Some(i) => Ok(i),
+                        None => Err(Error::Internal(format!("update of {} is missing column {}", table.name, name))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let targets = self
+                    .columns
+                    .iter()
+                    .map(|(col, expr)| Ok((table.get_col_index(col)?, expr)))
+                    .collect::<Result<Vec<_>>>()?;
                 for row in rows {
+                    let row = positions.iter().map(|i| row[*i].clone()).collect::<Row>();
                     let mut new_row = row.clone();
                     let pk = table.get_primary_key(&row)?;
 
-                    for (i, col) in columns.iter().enumerate() {
-                        if let Some(expr) = self.columns.get(col) {
-                            new_row[i] = Value::from_expression(expr.clone());
+                    // Every expression sees the row as it was before the update
+                    for (i, expr) in &targets {
+                        new_row[*i] = evaluate_expr(expr, &names, &row, &names, &row)?;
+                    }
+                    match &mut audit {
+                        Some(audit) => {
+                            txn.update_row(&table, &pk, new_row.clone())?;
+                            audit.append(txn, Some(&row), Some(&new_row))?;
                         }
+                        None => txn.update_row(&table, &pk, new_row)?,
                     }
-                    txn.update_row(&table, &pk, new_row)?;
                     count += 1;
                 }
             },
@@ -154,12 +233,16 @@ impl<T: Transaction> Delete<T> {
 impl<T: Transaction> Executor<T> for Delete<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns: _, rows } => {
+            ResultSet::Scan { rows, .. } => {
                 let mut count = 0;
                 let table = txn.must_get_table(self.table_name)?;
+                let mut audit = AuditLog::open(txn, &table)?;
                 for row in rows {
                     let pk = table.get_primary_key(&row)?;
                     txn.delete_row(&table, &pk)?;
+                    if let Some(audit) = &mut audit {
+                        audit.append(txn, Some(&row), None)?;
+                    }
                     count += 1;
                 }
 
@@ -168,4 +251,61 @@ impl<T: Transaction> Executor<T> for Delete<T> {
             _ => return Err(Error::Internal("Unexpected result set".into())),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::Update;
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, Transaction, kv::KVEngine},
+            executor::{Executor, ResultSet},
+            parser::ast::{Expression, Operation},
+            types::{Row, Value},
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    /// A source returning fixed rows
+    struct Rows(Vec<String>, Vec<Row>);
+
+    impl<T: Transaction> Executor<T> for Rows {
+        fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+            Ok(ResultSet::Scan { columns: self.0, rows: self.1, metadata: Vec::new() })
+        }
+    }
+
+    #[test]
+    fn test_update_source_column_order() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int, c int);")?;
+        s.execute("insert into t1 values (1, 10, 100), (2, 20, 200);")?;
+
+        // The source lists the columns in another order than the table
+        let (i, field) = (Value::Integer, |name: &str| Box::new(Expression::Field(name.into())));
+        let columns = ["c", "a", "b"].map(String::from).to_vec();
+        let rows = vec![vec![i(100), i(1), i(10)], vec![i(200), i(2), i(20)]];
+        let set = BTreeMap::from([
+            ("b".to_string(), Expression::Operation(Operation::Add(field("b"), field("c")))),
+            ("c".to_string(), Expression::Field("a".into())),
+        ]);
+        let mut txn = kvengine.begin()?;
+        let update = Update::new("t1".into(), Box::new(Rows(columns, rows)), set);
+        assert_eq!(update.execute(&mut txn)?, ResultSet::Update { count: 2 });
+        txn.commit()?;
+
+        let txn = kvengine.begin()?;
+        assert_eq!(txn.scan_table("t1".into(), None)?.collect::<Result<Vec<_>>>()?, vec![vec![i(1), i(110), i(1)], vec![i(2), i(220), i(2)]]);
+        txn.rollback()?;
+
+        // Sources missing a column can't be updated from
+        let mut txn = kvengine.begin()?;
+        let source = Box::new(Rows(vec!["a".into(), "b".into()], vec![vec![i(1), i(10)]]));
+        assert!(Update::new("t1".into(), source, BTreeMap::new()).execute(&mut txn).is_err());
+        txn.rollback()?;
+        Ok(())
+    }
+}
@@ -1,29 +1,190 @@
-use std::{cmp::Ordering, collections::HashMap};
+use std::{cmp::Ordering, collections::BinaryHeap};
 
-use crate::{error::{Error, Result}, sql::{engine::Transaction, executor::ResultSet, parser::ast::{Expression, OrderDirection, evaluate_expr}, types::Value}};
+use crate::{error::{Error, Result}, sql::{engine::Transaction, executor::ResultSet, parser::ast::{Expression, OrderDirection}, schema::Collation, types::{Row, Value}}};
 
-use super::Executor;
+use super::{ColumnMetadata, Executor, MemoryBudget, RowStream, batch, collations};
 
 /// Table scan executor (SELECT)
 pub struct Scan {
     table_name: String,
     filter: Option<Expression>,
+    /// Columns the query reads, None for all
+    read: Option<Vec<String>>,
+    /// Rows to stop reading after, None for all
+    limit: Option<usize>,
+    /// Whether to read in descending primary key order
+    reverse: bool,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
 }
 
 impl Scan {
-    pub fn new(table_name: String, filter: Option<Expression>) -> Box<Self> {
-        Box::new(Self { table_name, filter })
+    pub fn new(
+        table_name: String,
+        filter: Option<Expression>,
+        read: Option<Vec<String>>,
+        limit: Option<usize>,
+        reverse: bool,
+        columns: Vec<String>,
+        metadata: Vec<ColumnMetadata>,
+    ) -> Box<Self> {
+        Box::new(Self { table_name, filter, read, limit, reverse, columns, metadata })
     }
 }
 
 impl<T: Transaction> Executor<T> for Scan {
     fn execute(self:Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if self.reverse {
+            let rows = txn.scan_table_rev(self.table_name, self.filter, self.read.as_deref(), self.limit)?;
+            return Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata });
+        }
+        let rows = match (self.read, self.limit) {
+            (read, Some(limit)) => txn.scan_table_limit(self.table_name.clone(), self.filter, read.as_deref(), limit)?,
+            (Some(read), None) => txn.scan_table_columns(self.table_name.clone(), self.filter, &read)?,
+            (None, None) => txn.scan_table(self.table_name.clone(), self.filter)?.collect::<Result<_>>()?,
+        };
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+
+    /// Yields rows as the table is read, of every column; scans in reverse
+    /// or with a limit are read in one go
+    fn stream<'a>(self: Box<Self>, txn: &'a mut T) -> Result<RowStream<'a>> {
+        if self.reverse || self.limit.is_some() {
+            return RowStream::from_result(self.execute(txn)?);
+        }
+        let rows = txn.scan_table(self.table_name, self.filter)?;
+        Ok(RowStream { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
+
+/// Index scan executor, reading rows from index entries, and with `fetch`
+/// the rows they point to
+pub struct IndexScan {
+    table_name: String,
+    index: String,
+    /// Value of the index's first column to read, None for the whole index
+    value: Option<Value>,
+    filter: Option<Expression>,
+    /// Whether to read each entry's row
+    fetch: bool,
+    /// Rows to stop reading after, None for all
+    limit: Option<usize>,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+}
+
+impl IndexScan {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        table_name: String,
+        index: String,
+        value: Option<Value>,
+        filter: Option<Expression>,
+        fetch: bool,
+        limit: Option<usize>,
+        columns: Vec<String>,
+        metadata: Vec<ColumnMetadata>,
+    ) -> Box<Self> {
+        Box::new(Self { table_name, index, value, filter, fetch, limit, columns, metadata })
+    }
+}
+
+impl<T: Transaction> Executor<T> for IndexScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if !self.fetch {
+            let rows = txn.scan_index(self.table_name, &self.index, self.value.as_ref(), self.filter, self.limit)?;
+            return Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata });
+        }
+        // The filter may need columns only the rows have, so it applies to them
         let table = txn.must_get_table(self.table_name.clone())?;
-        let rows = txn.scan_table(self.table_name.clone(), self.filter)?;
-        Ok(ResultSet::Scan { 
-            columns: table.columns.into_iter().map(|c| c.name.clone()).collect(), 
-            rows 
-        })
+        let limit = self.limit.filter(|_| self.filter.is_none());
+        let entries = txn.scan_index(self.table_name, &self.index, self.value.as_ref(), None, limit)?;
+        let pk = table.columns.iter().position(|c| c.primary_key).unwrap_or(0);
+        let mut rows = Vec::with_capacity(entries.len());
+        for entry in entries {
+            rows.extend(txn.get_row(&table, &entry[pk])?);
+        }
+        if let Some(filter) = self.filter {
+            let names = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+            rows = batch::filter(rows, &names, &table.collate_filter(filter))?;
+            rows.truncate(self.limit.unwrap_or(usize::MAX));
+        }
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
+
+/// Primary key lookup executor (SELECT ... WHERE pk = constant)
+pub struct Get {
+    table_name: String,
+    key: Value,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+}
+
+impl Get {
+    pub fn new(table_name: String, key: Value, columns: Vec<String>, metadata: Vec<ColumnMetadata>) -> Box<Self> {
+        Box::new(Self { table_name, key, columns, metadata })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Get {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        let rows = txn.get_row(&table, &self.key)?.into_iter().collect();
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
+
+/// Row count executor (SELECT COUNT(*) FROM table)
+pub struct RowCount {
+    table_name: String,
+    /// Planned output column name and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+}
+
+impl RowCount {
+    pub fn new(table_name: String, columns: Vec<String>, metadata: Vec<ColumnMetadata>) -> Box<Self> {
+        Box::new(Self { table_name, columns, metadata })
+    }
+}
+
+impl<T: Transaction> Executor<T> for RowCount {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        let count = txn.count_rows(&table)?;
+        let rows = vec![vec![Value::Integer(count as i128)]];
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
+
+/// Row lock executor (SELECT ... FOR UPDATE) - locks the rows of a table scan
+pub struct Lock<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    table_name: String,
+}
+
+impl<T: Transaction> Lock<T> {
+    pub fn new(source: Box<dyn Executor<T>>, table_name: String) -> Box<Self> {
+        Box::new(Self { source, table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Lock<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, rows, metadata } => {
+                for row in &rows {
+                    txn.lock_row(&table, &table.get_primary_key(row)?)?;
+                }
+                Ok(ResultSet::Scan { columns, rows, metadata })
+            }
+            _ => Err(Error::Internal("Unexpected result set".into())),
+        }
     }
 }
 
@@ -43,86 +204,182 @@ impl<T: Transaction> Filter<T> {
 impl<T: Transaction> Executor<T> for Filter<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns, rows } => {
-                let mut new_rows = Vec::new();
-                for row in rows {
-                    match evaluate_expr(&self.predicate, &columns, &row, &columns, &row)? {
-                        Value::Null => {}
-                        Value::Boolean(false) => {}
-                        Value::Boolean(true) => {
-                            new_rows.push(row);
-                        }
-                        _ => return Err(Error::Internal("Unexpected expression".into())),
-                    }
-                }
-                Ok(ResultSet::Scan {
-                    columns,
-                    rows: new_rows,
-                })
+            ResultSet::Scan { columns, rows, metadata } => {
+                let rows = batch::filter(rows, &columns, &self.predicate)?;
+                Ok(ResultSet::Scan { columns, rows, metadata })
             }
             _ => return Err(Error::Internal("Unexpected result set".into())),
         }
     }
+
+    /// Filters the source's rows a batch at a time, as they're read
+    fn stream<'a>(self: Box<Self>, txn: &'a mut T) -> Result<RowStream<'a>> {
+        let RowStream { columns, mut rows, metadata } = self.source.stream(txn)?;
+        let (names, predicate) = (columns.clone(), self.predicate);
+        let mut kept = Vec::new().into_iter();
+        let rows = std::iter::from_fn(move || loop {
+            if let Some(row) = kept.next() {
+                return Some(Ok(row));
+            }
+            let chunk = match rows.by_ref().take(batch::BATCH_SIZE).collect::<Result<Vec<_>>>() {
+                Ok(chunk) if chunk.is_empty() => return None,
+                Ok(chunk) => chunk,
+                Err(err) => return Some(Err(err)),
+            };
+            match batch::filter(chunk, &names, &predicate) {
+                Ok(rows) => kept = rows.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        });
+        Ok(RowStream { columns, rows: Box::new(rows), metadata })
+    }
 }
 
 /// ORDER BY executor - sorts rows by specified columns
 pub struct Order<T: Transaction> {
     source: Box<dyn Executor<T>>,
     order_by: Vec<(String, OrderDirection)>,
+    tables: Vec<String>,
+    budget: MemoryBudget,
 }
 
 impl<T: Transaction> Order<T> {
-    pub fn new(source: Box<dyn Executor<T>>, order_by: Vec<(String, OrderDirection)>) -> Box<Self> {
-        Box::new(Self { source, order_by })
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        order_by: Vec<(String, OrderDirection)>,
+        tables: Vec<String>,
+        budget: MemoryBudget,
+    ) -> Box<Self> {
+        Box::new(Self { source, order_by, tables, budget })
     }
 }
 
 impl<T: Transaction> Executor<T> for Order<T> {
     fn execute(self: Box<Self>, txn:&mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns, mut rows } => {
-                // Map ORDER BY column positions to actual table column positions
-                // e.g., "ORDER BY c, a, b" where table columns are [a, b, c]
-                let mut order_col_index = HashMap::new();
-                for (i, (col_name, _)) in self.order_by.iter().enumerate() {
-                    match columns.iter().position(|c| *c == *col_name) {
-                        Some(pos) => order_col_index.insert(i, pos),
-                        None => {
-                            return Err(Error::Internal(format!(
-                                "order by column {} is not in table",
-                                col_name
-                            )))
-                        }
-                    };
-                }
+            ResultSet::Scan { columns, mut rows, metadata } => {
+                // Sorting holds the whole input
+                self.budget.charge_all(&rows)?;
+                let keys = sort_keys(txn, &self.order_by, &self.tables, &columns)?;
+                rows.sort_by(|row1, row2| compare_rows(&keys, row1, row2));
+                Ok(ResultSet::Scan { columns, rows, metadata })
+            }
+            _ => return Err(Error::Internal("Unexpected result set".into())),
+        }
+    }
+}
 
-                // Multi-column sort: compare rows column by column according to ORDER BY clause
-                // - If comparison is Equal, continue to next column
-                // - If Less/Greater, apply ASC/DESC direction and return
-                // - If types are incomparable (None), continue to next column
-                rows.sort_by(|col1, col2| {
-                    for (i, (_, direction)) in self.order_by.iter().enumerate() {
-                        let col_index = order_col_index.get(&i).unwrap();
-                        let x = &col1[*col_index];
-                        let y = &col2[*col_index];
-                        match x.partial_cmp(y) {
-                            Some(Ordering::Equal) => {}
-                            Some(o) => {
-                                return if *direction == OrderDirection::Asc {
-                                    o
-                                } else {
-                                    o.reverse()
-                                }
-                            }
-                            None => {}
-                        }
-                    }
-                    Ordering::Equal
-                });
+/// ORDER BY column positions in the rows, with their direction and collation
+type SortKeys = Vec<(usize, OrderDirection, Collation)>;
+
+fn sort_keys<T: Transaction>(
+    txn: &T,
+    order_by: &[(String, OrderDirection)],
+    tables: &[String],
+    columns: &[String],
+) -> Result<SortKeys> {
+    let collations = collations(txn, tables, columns)?;
+    // Map ORDER BY columns to their row positions
+    // e.g., "ORDER BY c, a, b" where table columns are [a, b, c]
+    order_by
+        .iter()
+        .map(|(col_name, direction)| match columns.iter().position(|c| *c == *col_name) {
+            Some(pos) => Ok((pos, *direction, collations[pos])),
+            None => Err(Error::Internal(format!("order by column {} is not in table", col_name))),
+        })
+        .collect()
+}
 
-                Ok(ResultSet::Scan { columns, rows })
+/// Multi-column comparison: compare rows column by column according to ORDER BY clause
+/// - If comparison is Equal, continue to next column
+/// - If Less/Greater, apply ASC/DESC direction and return
+/// - If types are incomparable (None), continue to next column
+fn compare_rows(keys: &SortKeys, row1: &Row, row2: &Row) -> Ordering {
+    for (index, direction, collation) in keys {
+        match collation.compare(&row1[*index], &row2[*index]) {
+            Some(Ordering::Equal) | None => {}
+            Some(o) if *direction == OrderDirection::Asc => return o,
+            Some(o) => return o.reverse(),
+        }
+    }
+    Ordering::Equal
+}
+
+/// Top-N executor - the first `limit` rows in ORDER BY order, for ORDER BY
+/// under a LIMIT
+///
+/// Keeps the best rows seen so far in a bounded max-heap instead of sorting
+/// the whole input, so it holds `limit` rows rather than all of them. Ties
+/// keep their input order, as with a full sort.
+pub struct TopN<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    order_by: Vec<(String, OrderDirection)>,
+    tables: Vec<String>,
+    limit: usize,
+    budget: MemoryBudget,
+}
+
+impl<T: Transaction> TopN<T> {
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        order_by: Vec<(String, OrderDirection)>,
+        tables: Vec<String>,
+        limit: usize,
+        budget: MemoryBudget,
+    ) -> Box<Self> {
+        Box::new(Self { source, order_by, tables, limit, budget })
+    }
+}
+
+/// A heap row, ordered by the sort keys and then by input position
+struct HeapRow<'a> {
+    row: Row,
+    position: usize,
+    keys: &'a SortKeys,
+}
+
+impl Ord for HeapRow<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_rows(self.keys, &self.row, &other.row).then(self.position.cmp(&other.position))
+    }
+}
+
+impl PartialOrd for HeapRow<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapRow<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapRow<'_> {}
+
+impl<T: Transaction> Executor<T> for TopN<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, rows, metadata } => {
+                let keys = sort_keys(txn, &self.order_by, &self.tables, &columns)?;
+                // The heap's largest row is the first to give way to a smaller one
+                let mut heap = BinaryHeap::with_capacity(self.limit.min(rows.len()));
+                for (position, row) in rows.into_iter().enumerate() {
+                    let row = HeapRow { row, position, keys: &keys };
+                    if heap.len() < self.limit {
+                        heap.push(row);
+                    } else if let Some(mut largest) = heap.peek_mut()
+                        && row < *largest
+                    {
+                        *largest = row;
+                    }
+                }
+                let rows = heap.into_sorted_vec().into_iter().map(|r| r.row).collect::<Vec<_>>();
+                self.budget.charge_all(&rows)?;
+                Ok(ResultSet::Scan { columns, rows, metadata })
             }
-            _ => return Err(Error::Internal("Unexpected result set".into())),
+            _ => Err(Error::Internal("Unexpected result set".into())),
         }
     }
 }
@@ -140,14 +397,14 @@ impl<T: Transaction> Limit<T> {
 }
 
 impl<T: Transaction> Executor<T> for Limit<T> {
+    /// Stops reading the source once it yields `limit` rows
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
-        match self.source.execute(txn)? {
-            ResultSet::Scan { columns, rows } => Ok(ResultSet::Scan {
-                columns,
-                rows: rows.into_iter().take(self.limit).collect(),
-            }),
-            _ => return Err(Error::Internal("Unexpected result set".into())),
-        }
+        self.stream(txn)?.collect()
+    }
+
+    fn stream<'a>(self: Box<Self>, txn: &'a mut T) -> Result<RowStream<'a>> {
+        let RowStream { columns, rows, metadata } = self.source.stream(txn)?;
+        Ok(RowStream { columns, rows: Box::new(rows.take(self.limit)), metadata })
     }
 }
 
@@ -166,9 +423,10 @@ impl<T: Transaction> Offset<T> {
 impl<T: Transaction> Executor<T> for Offset<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns, rows } => Ok(ResultSet::Scan {
+            ResultSet::Scan { columns, rows, metadata } => Ok(ResultSet::Scan {
                 columns,
                 rows: rows.into_iter().skip(self.offset).collect(),
+                metadata,
             }),
             _ => return Err(Error::Internal("Unexpected result set".into())),
         }
@@ -178,60 +436,88 @@ impl<T: Transaction> Executor<T> for Offset<T> {
 pub struct Projection<T: Transaction> {
     source: Box<dyn Executor<T>>,
     exprs: Vec<(Expression, Option<String>)>,
+    /// Planned output column names and metadata
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
 }
 
 impl<T: Transaction> Projection<T> {
     pub fn new(
         source: Box<dyn Executor<T>>,
         exprs: Vec<(Expression, Option<String>)>,
+        columns: Vec<String>,
+        metadata: Vec<ColumnMetadata>,
     ) -> Box<Self> {
-        Box::new(Self { source, exprs })
+        Box::new(Self { source, exprs, columns, metadata })
     }
 }
 
 impl<T: Transaction> Executor<T> for Projection<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
-            ResultSet::Scan { columns, rows } => {
-                // Find column positions and build new column names (with aliases)
-                let mut selected = Vec::new();
-                let mut new_columns = Vec::new();
-                for (expr, alias) in self.exprs {
-                    if let Expression::Field(col_name) = expr {
-                        let pos = match columns.iter().position(|c| *c == col_name) {
-                            Some(pos) => pos,
-                            None => {
-                                return Err(Error::Internal(format!(
-                                    "column {} not in table",
-                                    col_name
-                                )));
-                            }
-                        };
-                        selected.push(pos);
-                        new_columns.push(if alias.is_some() {
-                            alias.unwrap()
-                        } else {
-                            col_name
-                        });
-                    }
+            ResultSet::Scan { columns, rows, .. } => {
+                // Check the referenced columns exist, even if there are no rows to evaluate on
+                let mut missing = None;
+                for (expr, _) in &self.exprs {
+                    expr.walk_fields(&mut |col| {
+                        if missing.is_none() && !columns.iter().any(|c| c == col) {
+                            missing = Some(col.to_string());
+                        }
+                    });
                 }
-
-                // Build new rows with only selected columns
-                let mut new_rows = Vec::new();
-                for row in rows.into_iter() {
-                    let mut new_row = Vec::new();
-                    for i in selected.iter() {
-                        new_row.push(row[*i].clone());
-                    }
-                    new_rows.push(new_row);
+                if let Some(col_name) = missing {
+                    return Err(Error::Internal(format!("column {} not in table", col_name)));
                 }
 
-                Ok(ResultSet::Scan {
-                    columns: new_columns,
-                    rows: new_rows,
-                })
+                // Every expression, comparisons included, gives a column of the output
+                let selected = self.exprs.into_iter().map(|(expr, _)| expr).collect::<Vec<_>>();
+                let rows = batch::project(rows, &columns, &selected)?;
+                Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
             }
             _ => return Err(Error::Internal("Unexpected result set".into())),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Limit, Scan};
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, Transaction, kv::KVEngine},
+            executor::{Executor, ResultSet},
+            parser::ast::{Consts, Expression, Operation},
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_stream_scan() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        let values = (1..=3000).map(|a| format!("({})", a)).collect::<Vec<_>>();
+        s.execute(&format!("insert into t1 values {};", values.join(", ")))?;
+
+        // 2500 / (a - 2500) < 0 holds below 2500, and fails at it
+        let (int, field) = (|i| Box::new(Expression::Consts(Consts::Integer(i))), || Box::new(Expression::Field("a".into())));
+        let divisor = Box::new(Expression::Operation(Operation::Subtract(field(), int(2500))));
+        let quotient = Box::new(Expression::Operation(Operation::Divide(int(2500), divisor)));
+        let predicate = Expression::Operation(Operation::LessThan(quotient, int(0)));
+        let filter = || {
+            let scan = Scan::new("t1".into(), None, None, None, false, vec!["a".into()], Vec::new());
+            Filter::new(scan, predicate.clone())
+        };
+
+        // A limit stops reading once it has its rows, before the failing one
+        let mut txn = kvengine.begin()?;
+        match Limit::new(filter(), 3).execute(&mut txn)? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, (1..=3).map(|a| vec![Value::Integer(a)]).collect::<Vec<_>>()),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(filter().execute(&mut txn).is_err());
+        assert!(Limit::new(filter(), 2600).execute(&mut txn).is_err());
+        txn.rollback()
+    }
+}
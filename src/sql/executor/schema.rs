@@ -1,4 +1,12 @@
-use crate::{error::Result, sql::{engine::Transaction, executor::{Executor, ResultSet}, schema::Table}};
+use crate::{
+    error::Result,
+    sql::{
+        engine::Transaction,
+        executor::{ColumnMetadata, Executor, ResultSet, audit},
+        schema::{Index, Table},
+        types::Value,
+    },
+};
 
 /// CREATE TABLE executor
 pub struct CreateTable {
@@ -14,7 +22,70 @@ impl CreateTable {
 impl<T: Transaction> Executor<T> for CreateTable {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table_name = self.schema.name.clone();
+        if self.schema.audit {
+            txn.create_table(audit::audit_table(&self.schema))?;
+        }
         txn.create_table(self.schema)?;
         Ok(ResultSet::CreateTable { table_name })
     }
 }
+
+/// CREATE INDEX executor
+pub struct CreateIndex {
+    table_name: String,
+    index: Index,
+}
+
+impl CreateIndex {
+    pub fn new(table_name: String, index: Index) -> Box<Self> {
+        Box::new(Self { table_name, index })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateIndex {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.create_index(&self.table_name, self.index)?;
+        Ok(ResultSet::Command { tag: "CREATE INDEX".into() })
+    }
+}
+
+/// DROP TABLE executor
+pub struct DropTable {
+    table_name: String,
+    cascade: bool,
+}
+
+impl DropTable {
+    pub fn new(table_name: String, cascade: bool) -> Box<Self> {
+        Box::new(Self { table_name, cascade })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.drop_table(&self.table_name, self.cascade)?;
+        Ok(ResultSet::Command { tag: "DROP TABLE".into() })
+    }
+}
+
+/// SHOW CREATE TABLE executor
+pub struct ShowCreateTable {
+    table_name: String,
+    columns: Vec<String>,
+    metadata: Vec<ColumnMetadata>,
+}
+
+impl ShowCreateTable {
+    pub fn new(table_name: String, columns: Vec<String>, metadata: Vec<ColumnMetadata>) -> Box<Self> {
+        Box::new(Self { table_name, columns, metadata })
+    }
+}
+
+impl<T: Transaction> Executor<T> for ShowCreateTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        let ddl = table.to_ddl()?;
+        let rows = vec![vec![Value::String(table.name.into()), Value::String(ddl.into())]];
+        Ok(ResultSet::Scan { columns: self.columns, rows, metadata: self.metadata })
+    }
+}
@@ -0,0 +1,629 @@
+//! Materialized views - tables holding the rows of a query
+//!
+//! `CREATE MATERIALIZED VIEW v AS SELECT ...` runs the query and stores its
+//! rows in a table `v`, whose schema keeps the query (`Table::view`). The
+//! query's first column is the key: its values must be unique and not NULL,
+//! e.g. the GROUP BY column of an aggregation. Queries naming `v` read the
+//! stored rows like any table's, as of the last refresh, and
+//! `REFRESH MATERIALIZED VIEW v` runs the query again, in the statement's
+//! transaction, writing only the rows that changed.
+//!
+//! A view aggregating a single table, `SELECT k, ... FROM t GROUP BY k`
+//! with `COUNT(*)`, `COUNT(column)` and `SUM(column)` columns, is also
+//! maintained incrementally: each write to `t` adds the changed rows' deltas
+//! to the counts and sums of their groups, in the same transaction, so the
+//! view is always current. Sums must be of integers, which add up exactly.
+//! When the deltas can't tell a group's new row, i.e. whether rows left it
+//! empty without a `COUNT(*)`, or whether a sum back at 0 has any non-NULL
+//! values left, the group is aggregated again from `t`'s rows. As with a
+//! refresh, a write giving `k` a NULL group fails, since the view's key
+//! can't be NULL.
+//!
+//! Views can't be written otherwise. DROP TABLE drops one. A table (or view)
+//! can only be dropped along with the views reading it, by CASCADE, so no
+//! view is left reading a table that's gone.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        analyzer::{Analyzer, ScopeColumn},
+        engine::{Transaction, plancache, system},
+        executor::{Executor, MemoryBudget, ResultSet},
+        parser::ast::{self, Expression},
+        plan::Plan,
+        schema::{Collation, Column, StorageFormat, Table},
+        types::{Row, Value},
+    },
+};
+
+/// Schema of a materialized view of a query (as SQL), from its output columns
+pub fn view_table(name: &str, query: String, output: &[ScopeColumn]) -> Result<Table> {
+    let columns = output
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let Some(datatype) = c.datatype else {
+                return Err(Error::Internal(format!(
+                    "column {} of materialized view {} has no type",
+                    c.name, name
+                )));
+            };
+            let nullable = i > 0 && c.nullable;
+            Ok(Column {
+                name: c.name.clone(),
+                datatype,
+                nullable,
+                default: nullable.then_some(Value::Null),
+                default_fn: None,
+                primary_key: i == 0,
+                collation: Collation::default(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    let table = Table {
+        name: name.to_string(),
+        columns,
+        partition: None,
+        storage: StorageFormat::Row,
+        audit: false,
+        options: Default::default(),
+        indexes: vec![],
+        view: Some(query),
+        views: vec![],
+        readers: vec![],
+    };
+    table.validate()?;
+    Ok(table)
+}
+
+/// Aggregate of an incrementally maintained view column
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    /// COUNT(*)
+    Rows,
+    /// COUNT of a column's non-NULL values, by its row position
+    Count(usize),
+    /// SUM of a column, by its row position
+    Sum(usize),
+}
+
+/// How a view is maintained from its table's row changes
+#[derive(Debug, PartialEq)]
+struct Incremental {
+    /// Row position of the GROUP BY column
+    key: usize,
+    /// Aggregates of the view's columns after the key
+    columns: Vec<Aggregate>,
+    /// Position in `columns` of a COUNT(*), if any
+    rows: Option<usize>,
+}
+
+/// Changes to a group of an incrementally maintained view
+struct Delta {
+    /// Rows added less rows removed
+    rows: i128,
+    /// Change of each aggregate in `Incremental::columns`
+    values: Vec<i128>,
+    /// Whether non-NULL values were added to each sum, or removed from it
+    added: Vec<bool>,
+    removed: Vec<bool>,
+}
+
+impl Incremental {
+    /// The maintenance of a view query over a table, None unless the query
+    /// is an aggregation this module can maintain (see the module docs)
+    fn new(query: &ast::Statement, table: &Table) -> Option<Self> {
+        let ast::Statement::Select {
+            select,
+            from: ast::FromItem::Table { name },
+            as_of: None,
+            where_clause: None,
+            group_by: Some(Expression::Field(key)),
+            having: None,
+            order_by,
+            limit: None,
+            offset: None,
+            for_update: false,
+            ..
+        } = query
+        else {
+            return None;
+        };
+        if *name != table.name || !order_by.is_empty() {
+            return None;
+        }
+        // Summed columns are integers; counted ones can be anything
+        let column = |name: &str, counted: bool| {
+            let i = table.columns.iter().position(|c| c.name == name)?;
+            (counted || table.columns[i].datatype.is_integer()).then_some(i)
+        };
+        let (first, rest) = select.split_first()?;
+        let key = match first {
+            (Expression::Field(col), _) if col == key => table.columns.iter().position(|c| c.name == *col)?,
+            _ => return None,
+        };
+        let columns = rest
+            .iter()
+            .map(|(expr, _)| match expr {
+                Expression::Function(func, col) if func.eq_ignore_ascii_case("count") && col == ast::ALL_ROWS => {
+                    Some(Aggregate::Rows)
+                }
+                Expression::Function(func, col) if func.eq_ignore_ascii_case("count") => {
+                    column(col, true).map(Aggregate::Count)
+                }
+                Expression::Function(func, col) if func.eq_ignore_ascii_case("sum") => {
+                    column(col, false).map(Aggregate::Sum)
+                }
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let rows = columns.iter().position(|a| *a == Aggregate::Rows);
+        Some(Self { key, columns, rows })
+    }
+
+    /// The deltas of each group a table's row changes touch, by key
+    fn deltas(&self, view: &Table, changes: &[(Option<&Row>, Option<&Row>)]) -> Result<HashMap<Value, Delta>> {
+        let mut deltas: HashMap<Value, Delta> = HashMap::new();
+        for (row, sign) in changes.iter().flat_map(|(old, new)| [(old, -1), (new, 1)]) {
+            let Some(row) = row else { continue };
+            let key = row[self.key].clone();
+            if key == Value::Null {
+                return Err(Error::Internal(format!(
+                    "materialized view {} has a NULL key in its first column {}",
+                    view.name, view.columns[0].name
+                )));
+            }
+            let n = self.columns.len();
+            let delta = deltas.entry(key).or_insert_with(|| Delta {
+                rows: 0,
+                values: vec![0; n],
+                added: vec![false; n],
+                removed: vec![false; n],
+            });
+            delta.rows += sign;
+            for (i, aggregate) in self.columns.iter().enumerate() {
+                delta.values[i] += sign
+                    * match aggregate {
+                        Aggregate::Rows => 1,
+                        Aggregate::Count(c) => i128::from(row[*c] != Value::Null),
+                        Aggregate::Sum(c) => match row[*c] {
+                            Value::Integer(value) => {
+                                match sign > 0 {
+                                    true => delta.added[i] = true,
+                                    false => delta.removed[i] = true,
+                                }
+                                value
+                            }
+                            _ => 0,
+                        },
+                    };
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// A group's new row from its old one and its delta, Some(None) if it's
+    /// left empty, None if the delta can't tell
+    fn apply(&self, view: &Table, key: &Value, old: Option<&Row>, delta: &Delta) -> Result<Option<Option<Row>>> {
+        match self.rows {
+            Some(i) => {
+                let count = old.map_or(0, |old| match old[1 + i] {
+                    Value::Integer(count) => count,
+                    _ => 0,
+                });
+                if count + delta.values[i] == 0 {
+                    return Ok(Some(None));
+                }
+            }
+            // Without COUNT(*), rows leaving a group may leave it empty
+            None if delta.rows < 0 => return Ok(None),
+            None if old.is_none() && delta.rows == 0 => return Ok(Some(None)),
+            None => {}
+        }
+        let mut row = match old {
+            Some(old) => old.clone(),
+            None => [key.clone()]
+                .into_iter()
+                .chain(self.columns.iter().map(|a| match a {
+                    Aggregate::Sum(_) => Value::Null,
+                    _ => Value::Integer(0),
+                }))
+                .collect(),
+        };
+        for (i, aggregate) in self.columns.iter().enumerate() {
+            let value = &mut row[1 + i];
+            let sum = match (aggregate, &*value) {
+                // A sum is NULL until a non-NULL value is added
+                (Aggregate::Sum(_), Value::Null) if !delta.added[i] => continue,
+                (Aggregate::Sum(_), Value::Null) => Some(delta.values[i]),
+                (_, Value::Integer(value)) => value.checked_add(delta.values[i]),
+                _ => None,
+            };
+            let Some(sum) = sum else {
+                return Err(Error::Internal(format!(
+                    "integer overflow in column {} of materialized view {}",
+                    view.columns[1 + i].name, view.name
+                )));
+            };
+            // Back at 0, a sum may have non-NULL values left or none
+            if matches!(aggregate, Aggregate::Sum(_)) && sum == 0 && delta.removed[i] {
+                return Ok(None);
+            }
+            *value = Value::Integer(sum);
+        }
+        Ok(Some(Some(row)))
+    }
+
+    /// A group's row aggregated from the table's rows, None if it has none
+    fn aggregate<T: Transaction>(&self, txn: &T, table: &Table, view: &Table, key: &Value) -> Result<Option<Row>> {
+        let mut row: Option<Row> = None;
+        for source in txn.scan_table(table.name.clone(), None)? {
+            let source = source?;
+            if source[self.key] != *key {
+                continue;
+            }
+            let row = row.get_or_insert_with(|| {
+                [key.clone()]
+                    .into_iter()
+                    .chain(self.columns.iter().map(|a| match a {
+                        Aggregate::Sum(_) => Value::Null,
+                        _ => Value::Integer(0),
+                    }))
+                    .collect()
+            });
+            for (i, aggregate) in self.columns.iter().enumerate() {
+                let add = match aggregate {
+                    Aggregate::Rows => 1,
+                    Aggregate::Count(c) if source[*c] == Value::Null => continue,
+                    Aggregate::Count(_) => 1,
+                    Aggregate::Sum(c) => match source[*c] {
+                        Value::Integer(value) => value,
+                        _ => continue,
+                    },
+                };
+                let value = match &row[1 + i] {
+                    Value::Integer(value) => value.checked_add(add),
+                    _ => Some(add),
+                };
+                row[1 + i] = Value::Integer(value.ok_or_else(|| {
+                    Error::Internal(format!(
+                        "integer overflow in column {} of materialized view {}",
+                        view.columns[1 + i].name, view.name
+                    ))
+                })?);
+            }
+        }
+        Ok(row)
+    }
+}
+
+/// The table a view is maintained from, with how, None if it isn't
+/// maintained incrementally
+fn incremental_source<T: Transaction>(txn: &T, view: &Table) -> Result<Option<(Table, Incremental)>> {
+    let Some(query) = view.view_query()? else {
+        return Ok(None);
+    };
+    let ast::Statement::Select { from: ast::FromItem::Table { name }, .. } = &query else {
+        return Ok(None);
+    };
+    // System tables are computed, never written
+    if system::table(name).is_some() {
+        return Ok(None);
+    }
+    Ok(match txn.get_table(name.clone())? {
+        Some(table) => Incremental::new(&query, &table).map(|incremental| (table, incremental)),
+        None => None,
+    })
+}
+
+/// Name of the table a view is maintained from, None if it isn't
+/// maintained incrementally
+fn maintained_from<T: Transaction>(txn: &T, view: &Table) -> Result<Option<String>> {
+    Ok(incremental_source(txn, view)?.map(|(table, _)| table.name))
+}
+
+/// Names of the tables a view's query reads, other than system tables;
+/// none for tables
+pub fn source_tables(view: &Table) -> Result<Vec<String>> {
+    Ok(view.view_query()?.map(|query| plancache::table_names(&query)).unwrap_or_default())
+}
+
+/// Applies row changes of a table, as (old, new) row pairs, to the views
+/// maintained from it: each changed group of a view is read and written once
+pub fn maintain<T: Transaction>(txn: &mut T, table: &Table, changes: &[(Option<&Row>, Option<&Row>)]) -> Result<()> {
+    for name in &table.views {
+        let view = txn.must_get_table(name.clone())?;
+        let Some((_, incremental)) = incremental_source(txn, &view)? else {
+            return Err(Error::Internal(format!(
+                "materialized view {} can't be maintained from table {}",
+                name, table.name
+            )));
+        };
+        for (key, delta) in incremental.deltas(&view, changes)? {
+            // e.g. an update of columns the view doesn't aggregate
+            if delta.rows == 0 && delta.values.iter().all(|d| *d == 0) && delta.added == delta.removed {
+                continue;
+            }
+            let old = txn.get_row(&view, &key)?;
+            let new = match incremental.apply(&view, &key, old.as_ref(), &delta)? {
+                Some(new) => new,
+                None => incremental.aggregate(txn, table, &view, &key)?,
+            };
+            match (old, new) {
+                (Some(old), Some(new)) if old == new => {}
+                (Some(_), Some(new)) => txn.update_row(&view, &key, new)?,
+                (Some(_), None) => txn.delete_row(&view, &key)?,
+                (None, Some(new)) => txn.create_row(view.name.clone(), new)?,
+                (None, None) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a view's new rows, keyed by their first column, over its stored
+/// ones: changed rows are updated, missing ones deleted and new ones inserted
+fn write_rows<T: Transaction>(txn: &mut T, table: &Table, rows: Vec<Row>) -> Result<()> {
+    let mut stored = txn
+        .scan_table(table.name.clone(), None)?
+        .map(|row| row.map(|row| (row[0].clone(), row)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let mut keys = HashSet::new();
+    for row in rows {
+        let key = row[0].clone();
+        if key == Value::Null || !keys.insert(key.clone()) {
+            let problem = if key == Value::Null { "a NULL key".to_string() } else { format!("duplicate key {}", key) };
+            return Err(Error::Internal(format!(
+                "materialized view {} has {} in its first column {}",
+                table.name, problem, table.columns[0].name
+            )));
+        }
+        match stored.remove(&key) {
+            Some(old) if old == row => {}
+            Some(_) => txn.update_row(table, &key, row)?,
+            None => txn.create_row(table.name.clone(), row)?,
+        }
+    }
+    for key in stored.keys() {
+        txn.delete_row(table, key)?;
+    }
+    Ok(())
+}
+
+/// Rows of a query result
+fn query_rows(result: ResultSet) -> Result<Vec<Row>> {
+    match result {
+        ResultSet::Scan { rows, .. } => Ok(rows),
+        result => Err(Error::Internal(format!("expected a query result, got {:?}", result))),
+    }
+}
+
+/// CREATE MATERIALIZED VIEW executor
+pub struct CreateMaterializedView<T: Transaction> {
+    schema: Table,
+    /// Table each column is read from, whose collation it takes
+    sources: Vec<Option<String>>,
+    query: Box<dyn Executor<T>>,
+}
+
+impl<T: Transaction> CreateMaterializedView<T> {
+    pub fn new(schema: Table, sources: Vec<Option<String>>, query: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { schema, sources, query })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateMaterializedView<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut schema = self.schema;
+        let rows = query_rows(self.query.execute(txn)?)?;
+        for (column, source) in schema.columns.iter_mut().zip(self.sources) {
+            if let Some(source) = source
+                && let Some(source) = txn.get_table(source)?
+                && let Some(c) = source.columns.iter().find(|c| c.name == column.name)
+            {
+                column.collation = c.collation;
+            }
+        }
+        let table_name = schema.name.clone();
+        txn.create_table(schema.clone())?;
+        write_rows(txn, &schema, rows)?;
+        let maintained = maintained_from(txn, &schema)?;
+        for source in source_tables(&schema)? {
+            let incremental = maintained.as_ref() == Some(&source);
+            txn.add_view(&source, &table_name, incremental)?;
+        }
+        Ok(ResultSet::CreateTable { table_name })
+    }
+}
+
+/// REFRESH MATERIALIZED VIEW executor
+///
+/// The stored query is bound and planned anew, so it reads the current
+/// schemas of its tables.
+pub struct RefreshMaterializedView {
+    table_name: String,
+    budget: MemoryBudget,
+}
+
+impl RefreshMaterializedView {
+    pub fn new(table_name: String, budget: MemoryBudget) -> Box<Self> {
+        Box::new(Self { table_name, budget })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for RefreshMaterializedView {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        let (Some(sql), Some(query)) = (table.view.clone(), table.view_query()?) else {
+            return Err(Error::Internal(format!("{} is not a materialized view", table.name)));
+        };
+        let Plan(node) = Plan::build(Analyzer::new(&*txn).analyze(query)?)?;
+        // The view's columns are fixed, so its query must still give the same
+        let columns = |t: &Table| t.columns.iter().map(|c| (c.name.clone(), c.datatype)).collect::<Vec<_>>();
+        if columns(&view_table(&table.name, sql, node.output())?) != columns(&table) {
+            return Err(Error::Internal(format!(
+                "the query of materialized view {} no longer gives its columns",
+                table.name
+            )));
+        }
+        let rows = query_rows(<dyn Executor<T>>::build(node, &self.budget).execute(txn)?)?;
+        write_rows(txn, &table, rows)?;
+        Ok(ResultSet::Command { tag: "REFRESH MATERIALIZED VIEW".into() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, Session, Transaction, kv::KVEngine},
+            executor::ResultSet,
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    fn rows(result: ResultSet) -> Vec<Vec<Value>> {
+        match result {
+            ResultSet::Scan { rows, .. } => rows,
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_incremental() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text not null, c int not null, d int);")?;
+        s.execute("insert into t1 values (1, 'x', 10, null), (2, 'y', 20, 1), (3, 'x', 30, 2);")?;
+        s.execute(
+            "create materialized view v1 as select b, count(*) as n, sum(c) as total, count(d) as d \
+             from t1 group by b;",
+        )?;
+        // Not maintainable: filtered
+        s.execute("create materialized view v2 as select b, count(*) from t1 where a > 1 group by b;")?;
+        // A sum of a nullable column, and no COUNT(*)
+        s.execute("create materialized view v4 as select b, count(*), sum(d) from t1 group by b;")?;
+        s.execute("create materialized view v5 as select b, sum(c) from t1 group by b;")?;
+        assert_eq!(kvengine.begin()?.must_get_table("t1".into())?.views, vec!["v1", "v4", "v5"]);
+
+        // After each write the views hold what their queries give
+        let check = |s: &mut Session<KVEngine<MemoryEngine>>| -> Result<()> {
+            for (view, query) in [
+                ("v1", "select b, count(*), sum(c), count(d) from t1 group by b order by b;"),
+                ("v4", "select b, count(*), sum(d) from t1 group by b order by b;"),
+                ("v5", "select b, sum(c) from t1 group by b order by b;"),
+            ] {
+                let expected = rows(s.execute(query)?);
+                assert_eq!(rows(s.execute(&format!("select * from {} order by b;", view))?), expected, "{}", view);
+            }
+            Ok(())
+        };
+        check(&mut s)?;
+        for sql in [
+            "insert into t1 values (4, 'z', 5, 5);",
+            "update t1 set c = c + 1, d = 7 where b = 'x';",
+            "update t1 set b = 'y' where a = 1;",
+            "update t1 set a = 1000 where a = 2;",
+            "delete from t1 where b = 'z';",
+            "update t1 set d = null;",
+        ] {
+            s.execute(sql)?;
+            check(&mut s)?;
+        }
+        let bulk = (5..105).map(|i| vec![Value::Integer(i), Value::String(format!("k{}", i % 3).into()), Value::Integer(i)]);
+        s.insert_rows("t1", vec!["a".into(), "b".into(), "c".into()], bulk.collect())?;
+        check(&mut s)?;
+
+        // Within a transaction the view reads its writes, and rolls back with them
+        s.execute("begin;")?;
+        s.execute("delete from t1;")?;
+        assert!(rows(s.execute("select * from v1;")?).is_empty());
+        s.execute("rollback;")?;
+        check(&mut s)?;
+
+        // The view goes with its table, and no longer maintained once dropped
+        assert!(s.execute("drop table t1;").is_err());
+        s.execute("drop table v1;")?;
+        assert_eq!(kvengine.begin()?.must_get_table("t1".into())?.views, vec!["v4", "v5"]);
+        s.execute("insert into t1 values (200, 'x', 1, 1);")?;
+        s.execute("create materialized view v1 as select b, count(*) from t1 group by b;")?;
+        s.execute("drop table t1 cascade;")?;
+        assert!(kvengine.begin()?.get_table("v1".into())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_nulls() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key, k int, n int);")?;
+        s.execute("insert into t values (1, 1, null), (2, 1, 5), (3, 2, -5), (4, 2, 5);")?;
+        s.execute("create materialized view v as select k, sum(n) as total, count(n) as c from t group by k;")?;
+        assert_eq!(kvengine.begin()?.must_get_table("t".into())?.views, vec!["v"]);
+
+        let check = |s: &mut Session<KVEngine<MemoryEngine>>| -> Result<()> {
+            let expected = rows(s.execute("select k, sum(n), count(n) from t group by k order by k;")?);
+            assert_eq!(rows(s.execute("select * from v order by k;")?), expected);
+            Ok(())
+        };
+        check(&mut s)?;
+        for sql in [
+            // A sum left with only NULLs is NULL, and a non-NULL value sets it again
+            "update t set n = null where a = 2;",
+            "update t set n = 0 where a = 1;",
+            // A sum back at 0 with non-NULL values left stays 0
+            "delete from t where a = 4;",
+            "insert into t values (4, 2, 5);",
+            // Without COUNT(*), a group emptied of rows goes, one with rows stays
+            "delete from t where a = 1;",
+            "update t set n = null where k = 2;",
+            "delete from t where a = 2;",
+            "update t set k = 3 where a = 3;",
+            // A row overwritten by an update's new key leaves its group
+            "update t set a = 4 where a = 3;",
+        ] {
+            s.execute(sql)?;
+            check(&mut s)?;
+        }
+
+        // The view's key can't be NULL
+        assert!(s.execute("insert into t values (5, null, 1);").is_err());
+        check(&mut s)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_sources() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (k int primary key);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20);")?;
+        s.execute("create materialized view v1 as select a, b from t1 join t2 on a = k;")?;
+        s.execute("create materialized view v2 as select a, b from v1;")?;
+        s.execute("create materialized view v3 as select k from t2;")?;
+        let readers = |name: &str| -> Result<Vec<String>> { Ok(kvengine.begin()?.must_get_table(name.into())?.readers) };
+        assert_eq!(readers("t1")?, vec!["v1"]);
+        assert_eq!(readers("t2")?, vec!["v1", "v3"]);
+
+        // A table read by a view, even one not maintained from it, only goes
+        // with CASCADE, which drops the views reading those views too
+        assert!(s.execute("drop table t1;").is_err());
+        assert!(s.execute("drop table v1;").is_err());
+        s.execute("refresh materialized view v2;")?;
+        s.execute("drop table t1 cascade;")?;
+        let txn = kvengine.begin()?;
+        assert!(txn.get_table("v1".into())?.is_none());
+        assert!(txn.get_table("v2".into())?.is_none());
+        // The tables left no longer have the dropped views
+        assert_eq!(txn.must_get_table("t2".into())?.readers, vec!["v3"]);
+        txn.rollback()?;
+        s.execute("drop table v3;")?;
+        s.execute("drop table t2;")?;
+        Ok(())
+    }
+}
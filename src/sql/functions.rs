@@ -0,0 +1,301 @@
+//! Scalar SQL functions
+//!
+//! Scalar functions map argument values to a single value per row, unlike
+//! aggregate functions which reduce a group of rows (see `executor::agg`).
+
+use std::{
+    cmp,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::{datetime::DateTime, parser::ast::evaluate_arithmetic, schema::Collation, types::{DataType, Value}},
+};
+
+/// Calls a scalar function by (case-insensitive) name
+pub fn call(name: &str, args: Vec<Value>) -> Result<Value> {
+    match name.to_uppercase().as_ref() {
+        "POINT" => point(args),
+        "DISTANCE" => distance(args),
+        "WITHIN" => within(args),
+        "UPPER" => case("upper", args, str::to_uppercase),
+        "LOWER" => case("lower", args, str::to_lowercase),
+        "UUID" | "GEN_RANDOM_UUID" => uuid(args),
+        "COLLATE" => collate(args),
+        "COALESCE" => coalesce(args),
+        "IFNULL" => expect_args("ifnull", &args, 2).and_then(|()| coalesce(args)),
+        "NULLIF" => nullif(args),
+        "NOW" => now(args),
+        "DATE_ADD" => date_add(args),
+        "EXTRACT" => extract(args),
+        "STRFTIME" => strftime(args),
+        "TRY_DIVIDE" => try_arithmetic("try_divide", "/", args),
+        "TRY_MOD" => try_arithmetic("try_mod", "%", args),
+        "BIGINT_ADD" => bigint_arithmetic("bigint_add", "+", args),
+        "BIGINT_SUB" => bigint_arithmetic("bigint_sub", "-", args),
+        "BIGINT_MUL" => bigint_arithmetic("bigint_mul", "*", args),
+        "BIGINT_DIV" => bigint_arithmetic("bigint_div", "/", args),
+        "BIGINT_MOD" => bigint_arithmetic("bigint_mod", "%", args),
+        _ => Err(Error::Internal(format!("unknown function {}", name))),
+    }
+}
+
+/// Result type of a function given its argument types, if known statically
+pub fn return_type(name: &str, args: &[Option<DataType>]) -> Option<DataType> {
+    match name.to_uppercase().as_ref() {
+        "POINT" => Some(DataType::Point),
+        "DISTANCE" => Some(DataType::Float),
+        "WITHIN" => Some(DataType::Boolean),
+        "UPPER" | "LOWER" | "NOW" | "DATE_ADD" | "STRFTIME" => Some(DataType::String),
+        "EXTRACT" => Some(DataType::Integer),
+        "UUID" | "GEN_RANDOM_UUID" => Some(DataType::Uuid),
+        "COLLATE" | "NULLIF" => args.first().copied().flatten(),
+        // The type the arguments share, unknown if they differ
+        "COALESCE" | "IFNULL" => {
+            let mut types = args.iter().flatten();
+            let first = types.next().copied();
+            first.filter(|first| types.all(|t| t == first))
+        }
+        "TRY_DIVIDE" | "TRY_MOD" | "BIGINT_ADD" | "BIGINT_SUB" | "BIGINT_MUL" | "BIGINT_DIV" | "BIGINT_MOD" => {
+            match args {
+                [Some(DataType::Float), _] | [_, Some(DataType::Float)] => Some(DataType::Float),
+                [Some(DataType::BigInt), Some(r)] | [Some(r), Some(DataType::BigInt)] if r.is_integer() => {
+                    Some(DataType::BigInt)
+                }
+                [Some(l), Some(r)] if l.is_integer() && r.is_integer() => Some(DataType::Integer),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether a function yields a new value on every call
+///
+/// Column defaults using such functions are evaluated per inserted row.
+pub fn is_volatile(name: &str) -> bool {
+    matches!(name.to_uppercase().as_ref(), "UUID" | "GEN_RANDOM_UUID" | "NOW")
+}
+
+/// Checks the argument count of a function
+fn expect_args(name: &str, args: &[Value], count: usize) -> Result<()> {
+    if args.len() != count {
+        return Err(Error::Internal(format!(
+            "function {} takes {} arguments, got {}",
+            name,
+            count,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Converts a numeric argument to a float coordinate
+fn coordinate(value: &Value) -> Result<f64> {
+    match value {
+        Value::Integer(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        v => Err(Error::Internal(format!("coordinate {} is not a number", v))),
+    }
+}
+
+/// Extracts the coordinates of a point argument
+fn as_point(value: &Value) -> Result<(f64, f64)> {
+    match value {
+        Value::Point(x, y) => Ok((*x, *y)),
+        v => Err(Error::Internal(format!("{} is not a point", v))),
+    }
+}
+
+/// POINT(x, y) - builds a point from two numbers
+fn point(args: Vec<Value>) -> Result<Value> {
+    expect_args("point", &args, 2)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    Ok(Value::Point(coordinate(&args[0])?, coordinate(&args[1])?))
+}
+
+/// distance(p1, p2) - Euclidean distance between two points
+fn distance(args: Vec<Value>) -> Result<Value> {
+    expect_args("distance", &args, 2)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    let ((x1, y1), (x2, y2)) = (as_point(&args[0])?, as_point(&args[1])?);
+    Ok(Value::Float((x1 - x2).hypot(y1 - y2)))
+}
+
+/// within(p, corner1, corner2) - whether a point lies in the bounding box of two corners
+///
+/// The corners may be given in any order; points on the edge are inside.
+fn within(args: Vec<Value>) -> Result<Value> {
+    expect_args("within", &args, 3)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    let ((x, y), (x1, y1), (x2, y2)) = (as_point(&args[0])?, as_point(&args[1])?, as_point(&args[2])?);
+    Ok(Value::Boolean(
+        x >= x1.min(x2) && x <= x1.max(x2) && y >= y1.min(y2) && y <= y1.max(y2),
+    ))
+}
+
+/// upper(s) / lower(s) - a string in upper or lower case
+fn case(name: &str, args: Vec<Value>, convert: fn(&str) -> String) -> Result<Value> {
+    expect_args(name, &args, 1)?;
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::String(s) => Ok(Value::String(convert(s).into())),
+        v => Err(Error::Internal(format!("{} of {} is not a string", name, v))),
+    }
+}
+
+/// Varying input for UUIDs: the clock in nanoseconds
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn entropy() -> u128 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())
+}
+
+/// Browsers have neither a system clock nor OS randomness for std, so ask JavaScript
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+fn entropy() -> u128 {
+    ((js_sys::Date::now() as u128) << 64) | js_sys::Math::random().to_bits() as u128
+}
+
+/// uuid() / gen_random_uuid() - generates a random (version 4) UUID
+fn uuid(args: Vec<Value>) -> Result<Value> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    expect_args("uuid", &args, 0)?;
+    let nanos = entropy();
+    // RandomState is seeded from OS randomness (fixed in the browser, see `entropy`)
+    let state = RandomState::new();
+    let mut bytes = [0; 16];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(nanos);
+        hasher.write_usize(i);
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Ok(Value::Uuid(bytes))
+}
+
+/// collate(value, name) - the key a value compares by under a collation
+fn collate(args: Vec<Value>) -> Result<Value> {
+    expect_args("collate", &args, 2)?;
+    match &args[1] {
+        Value::String(name) => Ok(Collation::from_name(name)?.fold(&args[0])),
+        v => Err(Error::Internal(format!("collation name {} is not a string", v))),
+    }
+}
+
+/// coalesce(a, b, ...) / ifnull(a, b) - the first argument that isn't NULL, or NULL
+fn coalesce(args: Vec<Value>) -> Result<Value> {
+    if args.is_empty() {
+        return Err(Error::Internal("function coalesce takes at least 1 argument".into()));
+    }
+    Ok(args.into_iter().find(|arg| *arg != Value::Null).unwrap_or(Value::Null))
+}
+
+/// nullif(a, b) - NULL if a equals b, else a
+fn nullif(args: Vec<Value>) -> Result<Value> {
+    expect_args("nullif", &args, 2)?;
+    let mut args = args.into_iter();
+    let (a, b) = (args.next().unwrap(), args.next().unwrap());
+    if a == Value::Null || b == Value::Null {
+        return Ok(a);
+    }
+    match a.partial_cmp(&b) {
+        Some(cmp::Ordering::Equal) => Ok(Value::Null),
+        Some(_) => Ok(a),
+        None => Err(Error::Internal(format!("can not compare {} and {}", a, b))),
+    }
+}
+
+/// Reads a date argument: text, or an integer of Unix seconds (see `datetime`)
+fn as_datetime(value: &Value) -> Result<DateTime> {
+    match value {
+        Value::String(s) => DateTime::parse(s),
+        Value::Integer(i) => DateTime::from_unix(
+            i64::try_from(*i).map_err(|_| Error::Internal("date out of range".into()))?,
+        ),
+        v => Err(Error::Internal(format!("{} is not a date", v))),
+    }
+}
+
+/// Reads a string argument of a date function, e.g. a unit or format
+fn as_str<'a>(name: &str, value: &'a Value) -> Result<&'a str> {
+    match value {
+        Value::String(s) => Ok(s),
+        v => Err(Error::Internal(format!("{} takes a string, got {}", name, v))),
+    }
+}
+
+/// now() - the current time in UTC, as a timestamp
+fn now(args: Vec<Value>) -> Result<Value> {
+    expect_args("now", &args, 0)?;
+    Ok(Value::String(DateTime::now()?.to_string().into()))
+}
+
+/// date_add(date, n, unit) - the date n units (e.g. 'day' or 'months') later
+fn date_add(args: Vec<Value>) -> Result<Value> {
+    expect_args("date_add", &args, 3)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    let n = match &args[1] {
+        Value::Integer(n) => i64::try_from(*n).map_err(|_| Error::Internal("date out of range".into()))?,
+        v => return Err(Error::Internal(format!("date_add takes an integer, got {}", v))),
+    };
+    let date = as_datetime(&args[0])?.add(n, as_str("date_add", &args[2])?)?;
+    Ok(Value::String(date.to_string().into()))
+}
+
+/// extract(field, date), also written EXTRACT(field FROM date) - a field
+/// of a date as an integer, e.g. its year
+fn extract(args: Vec<Value>) -> Result<Value> {
+    expect_args("extract", &args, 2)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    Ok(Value::Integer(as_datetime(&args[1])?.extract(as_str("extract", &args[0])?)? as i128))
+}
+
+/// strftime(format, date) - a date formatted like C's strftime
+fn strftime(args: Vec<Value>) -> Result<Value> {
+    expect_args("strftime", &args, 2)?;
+    if args.contains(&Value::Null) {
+        return Ok(Value::Null);
+    }
+    Ok(Value::String(as_datetime(&args[1])?.format(as_str("strftime", &args[0])?)?.into()))
+}
+
+/// try_divide(a, b) / try_mod(a, b) - a / b or a % b, but NULL for an integer
+/// divided by zero instead of an error
+///
+/// `SET sql_mode = lenient` rewrites `/` and `%` into these.
+fn try_arithmetic(name: &str, symbol: &str, args: Vec<Value>) -> Result<Value> {
+    expect_args(name, &args, 2)?;
+    let mut args = args.into_iter();
+    match (args.next().unwrap(), args.next().unwrap()) {
+        (Value::Integer(_), Value::Integer(0)) => Ok(Value::Null),
+        (l, r) => evaluate_arithmetic(symbol, l, r, DataType::Integer),
+    }
+}
+
+/// bigint_add(a, b) etc. - arithmetic over a BIGINT operand, whose result
+/// may use all 128 bits
+///
+/// The analyzer puts these in place of the operations, see `ast::BIGINT_FUNCTIONS`.
+fn bigint_arithmetic(name: &str, symbol: &str, args: Vec<Value>) -> Result<Value> {
+    expect_args(name, &args, 2)?;
+    let mut args = args.into_iter();
+    evaluate_arithmetic(symbol, args.next().unwrap(), args.next().unwrap(), DataType::BigInt)
+}
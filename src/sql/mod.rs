@@ -2,15 +2,45 @@
 //!
 //! This module provides:
 //! - `parser`: SQL lexer and parser
+//! - `analyzer`: Semantic analysis, binding statements to the catalog
 //! - `types`: SQL data types
+//! - `functions`: Scalar functions
+//! - `datetime`: Timezone-naive dates and times, for the date functions
 //! - `schema`: Table and column schema definitions
+//! - `codec`: Compact encoding of stored rows
 //! - `plan`: Execution plan generation
 //! - `executor`: Query and mutation execution
 //! - `engine`: Storage engine abstraction
+//! - `record`: Rust structs as table rows (`#[derive(Record)]` with feature `derive`)
+//! - `arrow`: Apache Arrow interchange (feature `arrow`)
+//! - `parquet`: Parquet files for COPY (feature `parquet`)
+//! - `slt`: sqllogictest runner for the scripts in `tests/slt` (tests only)
+//!
+//! Only `parser` and `types` are built without the `std` feature.
 
 pub mod parser;
+#[cfg(feature = "std")]
+pub mod analyzer;
 pub mod types;
+#[cfg(feature = "std")]
+pub mod functions;
+#[cfg(feature = "std")]
+pub mod datetime;
+#[cfg(feature = "std")]
 pub mod schema;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(feature = "std")]
 pub mod plan;
+#[cfg(feature = "std")]
 pub mod executor;
-pub mod engine;
\ No newline at end of file
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(all(test, feature = "std"))]
+mod slt;
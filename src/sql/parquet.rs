@@ -0,0 +1,141 @@
+//! Parquet files for COPY (feature `parquet`)
+//!
+//! Tables are written through their Arrow form (see `sql::arrow`), which
+//! fixes the Parquet types of the columns:
+//!
+//! | Column   | Parquet physical type     | Logical type        |
+//! |----------|---------------------------|---------------------|
+//! | BOOLEAN  | BOOLEAN                   |                     |
+//! | SMALLINT | INT32                     | INTEGER(16, signed) |
+//! | INT      | INT64                     |                     |
+//! | BIGINT   | FIXED_LEN_BYTE_ARRAY(16)  | DECIMAL(38, 0)      |
+//! | FLOAT    | DOUBLE                    |                     |
+//! | STRING   | BYTE_ARRAY                | STRING              |
+//! | POINT    | group { x: DOUBLE, y: DOUBLE }                  |
+//! | UUID     | FIXED_LEN_BYTE_ARRAY(16)  |                     |
+//!
+//! The Arrow schema is embedded in the file, so our own exports read back
+//! with the same types. Files from elsewhere are read as their Arrow types
+//! convert (see `sql::arrow::from_record_batch`).
+
+use std::fs::File;
+
+use arrow_array::RecordBatchReader;
+use parquet::{
+    arrow::{ArrowWriter, arrow_reader::ParquetRecordBatchReaderBuilder},
+    errors::ParquetError,
+};
+
+use crate::{
+    error::{Error, Result},
+    sql::{arrow, executor::batch::BATCH_SIZE, schema::Table, types::Row},
+};
+
+/// Writes table rows to a new Parquet file, replacing any existing one
+pub fn write(path: &str, table: &Table, rows: &[Row]) -> Result<()> {
+    let schema = arrow::table_to_record_batch(table, &[])?.schema();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None).map_err(parquet_error)?;
+    for chunk in rows.chunks(BATCH_SIZE) {
+        writer.write(&arrow::table_to_record_batch(table, chunk)?).map_err(parquet_error)?;
+    }
+    writer.close().map_err(parquet_error)?;
+    Ok(())
+}
+
+/// Reads a Parquet file as column names and rows
+pub fn read(path: &str) -> Result<(Vec<String>, Vec<Row>)> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+        .and_then(|builder| builder.with_batch_size(BATCH_SIZE).build())
+        .map_err(parquet_error)?;
+    let columns = reader.schema().fields().iter().map(|f| f.name().clone()).collect();
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch.map_err(|e| Error::Internal(e.to_string()))?;
+        rows.extend(arrow::from_record_batch(&batch)?.1);
+    }
+    Ok((columns, rows))
+}
+
+fn parquet_error(err: ParquetError) -> Error {
+    Error::Internal(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::{
+        basic::{LogicalType, Type},
+        file::reader::{FileReader, SerializedFileReader},
+    };
+
+    use crate::{
+        error::Result,
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            executor::ResultSet,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_copy() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("t1.parquet");
+        let path = path.to_str().unwrap();
+
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute(
+            "create table t1 (a bigint primary key, b smallint, c float, d text, e point, f uuid, g bool);",
+        )?;
+        s.execute("create table t2 (a bigint primary key, b smallint, c float, d text, e point, f uuid, g bool);")?;
+        s.execute("create table t3 (a bigint primary key, d text default 'none', h int default 7);")?;
+        s.execute(
+            "insert into t1 values
+                (1, 2, 1.5, 'x', point(1, 2), '67e55044-10b1-426f-9247-bb680e5fe0c8', true),
+                (2, null, null, null, null, null, null);",
+        )?;
+        let values = (3..2000).map(|i| format!("({}, 'row{}')", i, i)).collect::<Vec<_>>();
+        s.execute(&format!("insert into t1 (a, d) values {};", values.join(", ")))?;
+
+        assert_eq!(s.execute(&format!("copy t1 to '{}';", path))?, ResultSet::Copy { count: 1999 });
+        let metadata = SerializedFileReader::new(std::fs::File::open(path)?).unwrap().metadata().clone();
+        assert_eq!(metadata.file_metadata().num_rows(), 1999);
+        let types = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| (c.physical_type(), c.logical_type_ref().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(types[0], (Type::FIXED_LEN_BYTE_ARRAY, Some(LogicalType::decimal(0, 38))));
+        assert_eq!(types[1], (Type::INT32, Some(LogicalType::integer(16, true))));
+        assert_eq!(types[2], (Type::DOUBLE, None));
+        assert_eq!(types[3], (Type::BYTE_ARRAY, Some(LogicalType::String)));
+
+        // Round trip; fields must name columns of the table
+        assert_eq!(s.execute(&format!("copy t2 from '{}';", path))?, ResultSet::Copy { count: 1999 });
+        let rows = |result| match result {
+            ResultSet::Scan { rows, .. } => rows,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(rows(s.execute("select * from t2;")?), rows(s.execute("select * from t1;")?));
+        assert!(s.execute(&format!("copy t3 from '{}';", path)).is_err());
+        s.execute(&format!("copy t2 to '{}';", path))?;
+        s.execute("delete from t2;")?;
+        // LOAD DATA imports the same rows, in bulk
+        assert_eq!(s.execute(&format!("load data '{}' into t2;", path))?, ResultSet::Copy { count: 1999 });
+        assert_eq!(rows(s.execute("select * from t2;")?), rows(s.execute("select * from t1;")?));
+        s.execute("delete from t2;")?;
+        assert!(s.execute(&format!("copy t2 from '{}';", dir.path().join("missing").display())).is_err());
+
+        // A failed import leaves nothing behind
+        s.execute("insert into t2 (a) values (1999);")?;
+        assert!(s.execute(&format!("copy t2 from '{}';", path)).is_err());
+        assert!(s.execute(&format!("load data '{}' into t2;", path)).is_err());
+        match s.execute("select * from t2;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 1),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
-use std::collections::BTreeMap;
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt::{self, Display};
 
-use crate::{error::{Error, Result}, sql::types::{DataType, Value}};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::sql::functions;
+use crate::{error::{Error, Result}, sql::types::{DataType, Value, compare_floats}};
 
 /// Abstract Syntax Tree (AST) node definitions for SQL statements
 #[derive(Debug, PartialEq)]
@@ -9,6 +14,35 @@ pub enum Statement {
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        /// Optional PARTITION BY clause
+        partition_by: Option<PartitionBy>,
+        /// Options from WITH (name = value, ...), e.g. storage = 'columnar'
+        /// or audit = true (see `schema::TABLE_OPTIONS`)
+        options: BTreeMap<String, Consts>,
+    },
+    /// CREATE INDEX [name] ON table (columns) [WHERE predicate]: a secondary
+    /// index over columns of a table, of only the rows matching the predicate
+    CreateIndex {
+        /// None to name it after the table and columns
+        name: Option<String>,
+        table_name: String,
+        columns: Vec<String>,
+        predicate: Option<Expression>,
+    },
+    /// CREATE MATERIALIZED VIEW name AS query: a table holding the query's
+    /// rows, keyed by its first column
+    CreateMaterializedView {
+        name: String,
+        query: Box<Statement>,
+    },
+    /// REFRESH MATERIALIZED VIEW name: replaces the view's rows with its query's
+    RefreshMaterializedView {
+        name: String,
+    },
+    /// DROP TABLE name [CASCADE]: CASCADE also drops the objects depending on it
+    DropTable {
+        name: String,
+        cascade: bool,
     },
     /// INSERT statement
     Insert {
@@ -20,7 +54,11 @@ pub enum Statement {
     Select {
         /// Column expressions with optional aliases (e.g., Count(*) as cnt)
         select: Vec<(Expression, Option<String>)>,
+        /// Optimizer hints from a /*+ ... */ comment after SELECT
+        hints: Vec<Hint>,
         from: FromItem,
+        /// AS OF VERSION clause: read the snapshot of that MVCC version
+        as_of: Option<u64>,
         where_clause: Option<Expression>,
         /// GROUP BY expression (None means entire table is one group)
         group_by: Option<Expression>,
@@ -28,6 +66,8 @@ pub enum Statement {
         order_by: Vec<(String, OrderDirection)>,
         limit: Option<Expression>,
         offset: Option<Expression>,
+        /// FOR UPDATE: lock the rows read until the transaction ends
+        for_update: bool,
     },
     /// UPDATE statement
     Update {
@@ -43,6 +83,132 @@ pub enum Statement {
         table_name: String,
         where_clause: Option<Expression>,
     },
+    /// COPY statement, exporting a table to or importing it from a file
+    Copy {
+        table_name: String,
+        direction: CopyDirection,
+        path: String,
+    },
+    /// LOAD DATA 'path' INTO table: bulk loads a file's rows into a table
+    LoadData {
+        table_name: String,
+        path: String,
+    },
+    /// BEGIN: starts an explicit transaction
+    Begin,
+    /// COMMIT: commits the open transaction
+    Commit,
+    /// ROLLBACK: discards the open transaction
+    Rollback,
+    /// SET name = value: changes a session setting, e.g. autocommit
+    Set {
+        name: String,
+        /// The value as written, e.g. `off` or `1`
+        value: String,
+    },
+    /// SHOW STATS: per-table write conflict and plan cache counters, a scan of
+    /// `system.stats`
+    ShowStats,
+    /// SHOW CREATE TABLE name [AS OF VERSION n]: the DDL recreating the table
+    /// and its indexes, as the catalog held it at that version with AS OF
+    ShowCreateTable {
+        name: String,
+        as_of: Option<u64>,
+    },
+    /// EXPLAIN [ANALYZE] [FORMAT = format] statement: the statement's plan
+    /// tree, with ANALYZE executed to show each node's row count and time
+    Explain {
+        statement: Box<Statement>,
+        analyze: bool,
+        format: ExplainFormat,
+    },
+}
+
+/// Output format of EXPLAIN
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExplainFormat {
+    /// A row of text per node, indented under its parent
+    #[default]
+    Tree,
+    /// One row holding the plan as a JSON document
+    Json,
+    /// One row holding the plan as a Graphviz dot graph
+    Dot,
+}
+
+impl ExplainFormat {
+    /// Looks up a format by (case-insensitive) name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "tree" => Some(Self::Tree),
+            "json" => Some(Self::Json),
+            "dot" => Some(Self::Dot),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ExplainFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Tree => "TREE",
+            Self::Json => "JSON",
+            Self::Dot => "DOT",
+        })
+    }
+}
+
+/// Direction of a COPY statement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyDirection {
+    /// COPY table TO 'path'
+    To,
+    /// COPY table FROM 'path'
+    From,
+}
+
+impl Statement {
+    /// The statement as canonical SQL text, ending in a semicolon: parsing
+    /// it gives back the same statement (see `Display`)
+    pub fn to_sql(&self) -> String {
+        format!("{};", self)
+    }
+
+    /// Version a statement reads as of, from its AS OF VERSION clause
+    pub fn as_of(&self) -> Option<u64> {
+        match self {
+            Statement::Select { as_of, .. } | Statement::ShowCreateTable { as_of, .. } => *as_of,
+            Statement::Explain { statement, .. } => statement.as_of(),
+            _ => None,
+        }
+    }
+
+    /// Calls `f` with every subexpression of a query or DML statement (see
+    /// `Expression::transform`), e.g. to apply session settings
+    ///
+    /// Expressions stored in the schema, column defaults and index predicates,
+    /// are left as written.
+    pub fn transform_expressions(&mut self, f: &mut impl FnMut(&mut Expression)) {
+        match self {
+            Statement::Select { select, from, where_clause, group_by, having, limit, offset, .. } => {
+                select.iter_mut().for_each(|(expr, _)| expr.transform(f));
+                from.transform_expressions(f);
+                [where_clause, group_by, having, limit, offset]
+                    .into_iter()
+                    .flatten()
+                    .for_each(|expr| expr.transform(f));
+            }
+            Statement::Insert { values, .. } => values.iter_mut().flatten().for_each(|expr| expr.transform(f)),
+            Statement::Update { columns, where_clause, .. } => {
+                columns.values_mut().chain(where_clause.as_mut()).for_each(|expr| expr.transform(f))
+            }
+            Statement::Delete { where_clause, .. } => where_clause.iter_mut().for_each(|expr| expr.transform(f)),
+            Statement::CreateMaterializedView { query: statement, .. } | Statement::Explain { statement, .. } => {
+                statement.transform_expressions(f)
+            }
+            _ => {}
+        }
+    }
 }
 
 /// FROM clause item - represents a table or join expression
@@ -63,6 +229,16 @@ pub enum FromItem {
     },
 }
 
+impl FromItem {
+    fn transform_expressions(&mut self, f: &mut impl FnMut(&mut Expression)) {
+        if let FromItem::Join { left, right, predicate, .. } = self {
+            left.transform_expressions(f);
+            right.transform_expressions(f);
+            predicate.iter_mut().for_each(|expr| expr.transform(f));
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum JoinType {
     Cross,
@@ -71,13 +247,40 @@ pub enum JoinType {
     Right,
 }
 
+/// Optimizer hint, overriding the planner's choice of access path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    /// INDEX(table index): read the table through the index
+    Index { table: String, index: String },
+    /// NO_INDEX or NO_INDEX(table): read the table (or every table) without
+    /// secondary indexes
+    NoIndex { table: Option<String> },
+}
+
+impl Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hint::Index { table, index } => write!(f, "INDEX({} {})", table, index),
+            Hint::NoIndex { table: Some(table) } => write!(f, "NO_INDEX({})", table),
+            Hint::NoIndex { table: None } => f.write_str("NO_INDEX"),
+        }
+    }
+}
+
 /// Sort direction (ascending or descending)
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderDirection {
     Asc,
     Desc,
 }
 
+/// PARTITION BY clause for CREATE TABLE statements
+#[derive(Debug, PartialEq)]
+pub enum PartitionBy {
+    /// PARTITION BY HASH (column) PARTITIONS n
+    Hash { column: String, partitions: u64 },
+}
+
 /// Column definition for CREATE TABLE statements
 #[derive(Debug, PartialEq)]
 pub struct Column {
@@ -86,10 +289,14 @@ pub struct Column {
     pub nullable: Option<bool>,
     pub default: Option<Expression>,
     pub primary_key: bool,
+    /// COLLATE name (e.g., nocase)
+    pub collation: Option<String>,
 }
 
 /// Expression types (column refs, constants, operations, aggregate functions)
-#[derive(Debug, PartialEq, Clone)]
+///
+/// Serializable, so schemas can store them (e.g., partial index predicates).
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Expression {
     /// Column reference
     Field(String),
@@ -97,8 +304,11 @@ pub enum Expression {
     Consts(Consts),
     /// Binary operation (e.g., equality comparison)
     Operation(Operation),
-    /// Aggregate function: Function(name, column) e.g., Function("count", "id")
+    /// Aggregate function: Function(name, column) e.g., Function("count", "id"),
+    /// or Function("count", ALL_ROWS) for COUNT(*)
     Function(String, String),
+    /// Scalar function call: ScalarFunction(name, args) e.g., distance(loc, point(0, 0))
+    ScalarFunction(String, Vec<Expression>),
 }
 
 /// Implements From trait to convert Consts into Expression
@@ -109,23 +319,464 @@ impl From<Consts> for Expression {
 }
 
 /// Constant values in SQL expressions
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Consts {
     Null,
     Boolean(bool),
-    Integer(i64),
+    Integer(i128),
     Float(f64),
     String(String),
 }
 
 /// Binary operations
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Operation {
     /// Equality comparison (e.g., tbl1.id = tbl2.id)
     /// Uses Box<Expression> because the operand type (column, constant, etc.) is determined at runtime
     Equal(Box<Expression>, Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
+    /// Arithmetic; integer overflow is an error, not wrapped
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    /// Integer division truncates toward zero; dividing integers by zero
+    /// fails, or gives NULL with `SET sql_mode = lenient`
+    Divide(Box<Expression>, Box<Expression>),
+    /// Remainder, with the sign of the dividend; zero divisors as for Divide
+    Modulo(Box<Expression>, Box<Expression>),
+}
+
+/// Column of `COUNT(*)`, standing for whole rows
+pub const ALL_ROWS: &str = "*";
+
+impl Expression {
+    /// Calls `f` with every column the expression references
+    pub fn walk_fields(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            Expression::Function(_, col) if col == ALL_ROWS => {}
+            Expression::Field(col) | Expression::Function(_, col) => f(col),
+            Expression::Consts(_) => {}
+            Expression::Operation(operation) => {
+                let (l, r) = operation.operands();
+                l.walk_fields(f);
+                r.walk_fields(f);
+            }
+            Expression::ScalarFunction(_, args) => args.iter().for_each(|arg| arg.walk_fields(f)),
+        }
+    }
+
+    /// Calls `f` with every subexpression, operands before the operation, to rewrite them
+    pub fn transform(&mut self, f: &mut impl FnMut(&mut Expression)) {
+        match self {
+            Expression::Operation(operation) => {
+                let (l, r) = operation.operands_mut();
+                l.transform(f);
+                r.transform(f);
+            }
+            Expression::ScalarFunction(_, args) => args.iter_mut().for_each(|arg| arg.transform(f)),
+            Expression::Field(_) | Expression::Consts(_) | Expression::Function(..) => {}
+        }
+        f(self)
+    }
+}
+
+impl Expression {
+    /// Binding strength when printed: comparisons 0, + and - 1, *, / and % 2, atoms 3
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Operation(operation) => operation.precedence(),
+            Expression::ScalarFunction(name, args) if args.len() == 2 => {
+                bigint_operator(name).map_or(3, symbol_precedence)
+            }
+            _ => 3,
+        }
+    }
+}
+
+/// Functions the analyzer puts in place of arithmetic over a BIGINT operand,
+/// by operator, since results of other integer arithmetic must fit INTEGER
+/// (see `evaluate_arithmetic`); they print as the operations
+pub const BIGINT_FUNCTIONS: [(&str, &str); 5] =
+    [("+", "bigint_add"), ("-", "bigint_sub"), ("*", "bigint_mul"), ("/", "bigint_div"), ("%", "bigint_mod")];
+
+/// The operator a BIGINT function stands for, see `BIGINT_FUNCTIONS`
+pub fn bigint_operator(name: &str) -> Option<&'static str> {
+    BIGINT_FUNCTIONS.iter().find(|(_, function)| name.eq_ignore_ascii_case(function)).map(|(symbol, _)| *symbol)
+}
+
+fn symbol_precedence(symbol: &str) -> u8 {
+    match symbol {
+        "+" | "-" => 1,
+        "*" | "/" | "%" => 2,
+        _ => 0,
+    }
+}
+
+impl Operation {
+    fn precedence(&self) -> u8 {
+        symbol_precedence(self.symbol())
+    }
+
+    /// Arithmetic as the BIGINT function standing for it (see
+    /// `BIGINT_FUNCTIONS`); a comparison is returned as is
+    pub fn into_bigint(self) -> Expression {
+        let Some((_, function)) = BIGINT_FUNCTIONS.iter().find(|(symbol, _)| *symbol == self.symbol()) else {
+            return Expression::Operation(self);
+        };
+        match self {
+            Operation::Add(l, r)
+            | Operation::Subtract(l, r)
+            | Operation::Multiply(l, r)
+            | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) => Expression::ScalarFunction(String::from(*function), Vec::from([*l, *r])),
+            operation => Expression::Operation(operation),
+        }
+    }
+
+    pub(crate) fn symbol(&self) -> &'static str {
+        match self {
+            Operation::Equal(..) => "=",
+            Operation::GreaterThan(..) => ">",
+            Operation::LessThan(..) => "<",
+            Operation::Add(..) => "+",
+            Operation::Subtract(..) => "-",
+            Operation::Multiply(..) => "*",
+            Operation::Divide(..) => "/",
+            Operation::Modulo(..) => "%",
+        }
+    }
+
+    /// Left and right operands
+    pub fn operands(&self) -> (&Expression, &Expression) {
+        match self {
+            Operation::Equal(l, r)
+            | Operation::GreaterThan(l, r)
+            | Operation::LessThan(l, r)
+            | Operation::Add(l, r)
+            | Operation::Subtract(l, r)
+            | Operation::Multiply(l, r)
+            | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) => (l, r),
+        }
+    }
+
+    fn operands_mut(&mut self) -> (&mut Expression, &mut Expression) {
+        match self {
+            Operation::Equal(l, r)
+            | Operation::GreaterThan(l, r)
+            | Operation::LessThan(l, r)
+            | Operation::Add(l, r)
+            | Operation::Subtract(l, r)
+            | Operation::Multiply(l, r)
+            | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) => (l, r),
+        }
+    }
+}
+
+/// Prints a statement as SQL text that parses back to the same statement
+///
+/// Keywords are uppercase and the trailing semicolon is left to the caller.
+/// Parentheses are added only where precedence needs them.
+impl Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::CreateTable { name, columns, partition_by, options } => {
+                write!(f, "CREATE TABLE {} (", name)?;
+                write_list(f, columns)?;
+                f.write_str(")")?;
+                if let Some(PartitionBy::Hash { column, partitions }) = partition_by {
+                    write!(f, " PARTITION BY HASH ({}) PARTITIONS {}", column, partitions)?;
+                }
+                if !options.is_empty() {
+                    f.write_str(" WITH (")?;
+                    write_list(f, options.iter().map(|(name, value)| format!("{} = {}", name, value)))?;
+                    f.write_str(")")?;
+                }
+                Ok(())
+            }
+            Statement::Insert { table_name, columns, values } => {
+                write!(f, "INSERT INTO {}", table_name)?;
+                if let Some(columns) = columns {
+                    f.write_str(" (")?;
+                    write_list(f, columns)?;
+                    f.write_str(")")?;
+                }
+                f.write_str(" VALUES ")?;
+                for (i, row) in values.iter().enumerate() {
+                    f.write_str(if i == 0 { "(" } else { ", (" })?;
+                    write_list(f, row.iter().map(Operand))?;
+                    f.write_str(")")?;
+                }
+                Ok(())
+            }
+            Statement::Select {
+                select,
+                hints,
+                from,
+                as_of,
+                where_clause,
+                group_by,
+                having,
+                order_by,
+                limit,
+                offset,
+                for_update,
+            } => {
+                f.write_str("SELECT ")?;
+                if !hints.is_empty() {
+                    f.write_str("/*+ ")?;
+                    for hint in hints {
+                        write!(f, "{} ", hint)?;
+                    }
+                    f.write_str("*/ ")?;
+                }
+                if select.is_empty() {
+                    f.write_str("*")?;
+                }
+                for (i, (expr, alias)) in select.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", expr)?;
+                    if let Some(alias) = alias {
+                        write!(f, " AS {}", alias)?;
+                    }
+                }
+                write!(f, " FROM {}", from)?;
+                if let Some(version) = as_of {
+                    write!(f, " AS OF VERSION {}", version)?;
+                }
+                if let Some(expr) = where_clause {
+                    write!(f, " WHERE {}", expr)?;
+                }
+                if let Some(expr) = group_by {
+                    write!(f, " GROUP BY {}", Operand(expr))?;
+                }
+                if let Some(expr) = having {
+                    write!(f, " HAVING {}", expr)?;
+                }
+                for (i, (column, direction)) in order_by.iter().enumerate() {
+                    f.write_str(if i == 0 { " ORDER BY " } else { ", " })?;
+                    let direction = match direction {
+                        OrderDirection::Asc => "ASC",
+                        OrderDirection::Desc => "DESC",
+                    };
+                    write!(f, "{} {}", column, direction)?;
+                }
+                if let Some(expr) = limit {
+                    write!(f, " LIMIT {}", Operand(expr))?;
+                }
+                if let Some(expr) = offset {
+                    write!(f, " OFFSET {}", Operand(expr))?;
+                }
+                if *for_update {
+                    f.write_str(" FOR UPDATE")?;
+                }
+                Ok(())
+            }
+            Statement::Update { table_name, columns, where_clause } => {
+                write!(f, "UPDATE {} SET ", table_name)?;
+                for (i, (column, expr)) in columns.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {}", column, Operand(expr))?;
+                }
+                if let Some(expr) = where_clause {
+                    write!(f, " WHERE {}", expr)?;
+                }
+                Ok(())
+            }
+            Statement::Delete { table_name, where_clause } => {
+                write!(f, "DELETE FROM {}", table_name)?;
+                if let Some(expr) = where_clause {
+                    write!(f, " WHERE {}", expr)?;
+                }
+                Ok(())
+            }
+            Statement::CreateIndex { name, table_name, columns, predicate } => {
+                f.write_str("CREATE INDEX ")?;
+                if let Some(name) = name {
+                    write!(f, "{} ", name)?;
+                }
+                write!(f, "ON {} ({})", table_name, columns.join(", "))?;
+                if let Some(expr) = predicate {
+                    write!(f, " WHERE {}", expr)?;
+                }
+                Ok(())
+            }
+            Statement::CreateMaterializedView { name, query } => {
+                write!(f, "CREATE MATERIALIZED VIEW {} AS {}", name, query)
+            }
+            Statement::RefreshMaterializedView { name } => write!(f, "REFRESH MATERIALIZED VIEW {}", name),
+            Statement::DropTable { name, cascade } => {
+                write!(f, "DROP TABLE {}", name)?;
+                if *cascade {
+                    f.write_str(" CASCADE")?;
+                }
+                Ok(())
+            }
+            Statement::Copy { table_name, direction, path } => {
+                let direction = match direction {
+                    CopyDirection::To => "TO",
+                    CopyDirection::From => "FROM",
+                };
+                write!(f, "COPY {} {} {}", table_name, direction, Consts::String(path.clone()))
+            }
+            Statement::LoadData { table_name, path } => {
+                write!(f, "LOAD DATA {} INTO {}", Consts::String(path.clone()), table_name)
+            }
+            Statement::Begin => f.write_str("BEGIN"),
+            Statement::Commit => f.write_str("COMMIT"),
+            Statement::Rollback => f.write_str("ROLLBACK"),
+            Statement::Set { name, value } => write!(f, "SET {} = {}", name, value),
+            Statement::ShowStats => f.write_str("SHOW STATS"),
+            Statement::ShowCreateTable { name, as_of } => {
+                write!(f, "SHOW CREATE TABLE {}", name)?;
+                if let Some(version) = as_of {
+                    write!(f, " AS OF VERSION {}", version)?;
+                }
+                Ok(())
+            }
+            Statement::Explain { statement, analyze, format } => {
+                f.write_str("EXPLAIN ")?;
+                if *analyze {
+                    f.write_str("ANALYZE ")?;
+                }
+                if *format != ExplainFormat::Tree {
+                    write!(f, "FORMAT = {} ", format)?;
+                }
+                write!(f, "{}", statement)
+            }
+        }
+    }
+}
+
+impl Display for FromItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromItem::Table { name } => f.write_str(name),
+            FromItem::Join { left, right, join_type, predicate } => {
+                let keyword = match join_type {
+                    JoinType::Cross => "CROSS JOIN",
+                    JoinType::Inner => "JOIN",
+                    JoinType::Left => "LEFT JOIN",
+                    JoinType::Right => "RIGHT JOIN",
+                };
+                write!(f, "{} {} {}", left, keyword, right)?;
+                match predicate {
+                    // The parser stores RIGHT JOIN operands swapped, as a LEFT JOIN's
+                    Some(Expression::Operation(Operation::Equal(l, r))) => {
+                        let (l, r) = match join_type {
+                            JoinType::Right => (r, l),
+                            _ => (l, r),
+                        };
+                        write!(f, " ON {} = {}", Operand(l), Operand(r))
+                    }
+                    Some(expr) => write!(f, " ON {}", expr),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.datatype)?;
+        match self.nullable {
+            Some(true) => f.write_str(" NULL")?,
+            Some(false) => f.write_str(" NOT NULL")?,
+            None => {}
+        }
+        if let Some(expr) = &self.default {
+            write!(f, " DEFAULT {}", Operand(expr))?;
+        }
+        if self.primary_key {
+            f.write_str(" PRIMARY KEY")?;
+        }
+        if let Some(collation) = &self.collation {
+            write!(f, " COLLATE {}", collation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints an expression where a comparison is allowed at the top (WHERE and HAVING)
+impl Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Field(name) => f.write_str(name),
+            Expression::Consts(consts) => write!(f, "{}", consts),
+            Expression::Operation(operation) => {
+                let (l, r) = operation.operands();
+                write_operation(f, l, operation.symbol(), r)
+            }
+            Expression::Function(name, column) => write!(f, "{}({})", name, column),
+            Expression::ScalarFunction(name, args) => {
+                if let (Some(symbol), [l, r]) = (bigint_operator(name), args.as_slice()) {
+                    return write_operation(f, l, symbol, r);
+                }
+                write!(f, "{}(", name)?;
+                write_list(f, args.iter().map(Operand))?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// An expression in a position that only takes arithmetic, so a comparison is parenthesized
+struct Operand<'a>(&'a Expression);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_operand(f, self.0, self.0.precedence() == 0)
+    }
+}
+
+fn write_operation(f: &mut fmt::Formatter<'_>, l: &Expression, symbol: &str, r: &Expression) -> fmt::Result {
+    let precedence = symbol_precedence(symbol);
+    // Operators associate to the left, and comparisons don't chain
+    write_operand(f, l, l.precedence() == 0 || l.precedence() < precedence)?;
+    write!(f, " {} ", symbol)?;
+    write_operand(f, r, r.precedence() <= precedence)
+}
+
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expression, parenthesize: bool) -> fmt::Result {
+    match parenthesize {
+        true => write!(f, "({})", expr),
+        false => write!(f, "{}", expr),
+    }
+}
+
+fn write_list<T: Display>(f: &mut fmt::Formatter<'_>, items: impl IntoIterator<Item = T>) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Prints a constant as a SQL literal
+///
+/// Floats keep a decimal point or exponent so they read back as floats.
+/// Infinities print as out-of-range literals; NaN has no literal form.
+impl Display for Consts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Consts::Null => f.write_str("NULL"),
+            Consts::Boolean(true) => f.write_str("TRUE"),
+            Consts::Boolean(false) => f.write_str("FALSE"),
+            Consts::Integer(i) => write!(f, "{}", i),
+            Consts::Float(v) if v.is_infinite() => f.write_str(if *v > 0.0 { "1e999" } else { "-1e999" }),
+            Consts::Float(v) => write!(f, "{:?}", v),
+            Consts::String(s) => write!(f, "'{}'", s.replace('\'', "''")),
+        }
+    }
 }
 
 /// Evaluates an expression against row data
@@ -159,74 +810,157 @@ pub fn evaluate_expr(
             Consts::Boolean(b) => Value::Boolean(*b),
             Consts::Integer(i) => Value::Integer(*i),
             Consts::Float(f) => Value::Float(*f),
-            Consts::String(s) => Value::String(s.clone()),
+            Consts::String(s) => Value::String(s.as_str().into()),
         }),
         // Operation: recursively evaluate left and right expressions, then compare
-        Expression::Operation(operation) => match operation {
-            Operation::Equal(lexpr, rexpr) => {
-                // Recursively evaluate left expression
-                let lv = evaluate_expr(lexpr, lcols, lrows, rcols, rrows)?;
-                // Recursively evaluate right expression (swap params since Field uses lcols)
-                let rv = evaluate_expr(rexpr, rcols, rrows, lcols, lrows)?;
-                Ok(match (lv, rv) {
-                    // Return true/false for equality comparison
-                    (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l == r),
-                    (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l == r),
-                    (Value::Integer(l), Value::Float(r)) => Value::Boolean(l as f64 == r),
-                    (Value::Float(l), Value::Integer(r)) => Value::Boolean(l == r as f64),
-                    (Value::Float(l), Value::Float(r)) => Value::Boolean(l == r),
-                    (Value::String(l), Value::String(r)) => Value::Boolean(l == r),
-                    (Value::Null, _) => Value::Null,
-                    (_, Value::Null) => Value::Null,
-                    (l, r) => {
-                        return Err(Error::Internal(format!(
-                            "can not compare expression {} and {}",
-                            l, r
-                        )))
-                    }
-                })
-            }
-            Operation::GreaterThan(lexpr, rexpr) => {
-                let lv = evaluate_expr(lexpr, lcols, lrows, rcols, rrows)?;
-                let rv = evaluate_expr(rexpr, rcols, rrows, lcols, lrows)?;
-                Ok(match (lv, rv) {
-                    (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l > r),
-                    (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l > r),
-                    (Value::Integer(l), Value::Float(r)) => Value::Boolean(l as f64 > r),
-                    (Value::Float(l), Value::Integer(r)) => Value::Boolean(l > r as f64),
-                    (Value::Float(l), Value::Float(r)) => Value::Boolean(l > r),
-                    (Value::String(l), Value::String(r)) => Value::Boolean(l > r),
-                    (Value::Null, _) => Value::Null,
-                    (_, Value::Null) => Value::Null,
-                    (l, r) => {
-                        return Err(Error::Internal(format!(
-                            "can not compare expression {} and {}",
-                            l, r
-                        )))
-                    }
-                })
-            }
-            Operation::LessThan(lexpr, rexpr) => {
-                let lv = evaluate_expr(lexpr, lcols, lrows, rcols, rrows)?;
-                let rv = evaluate_expr(rexpr, rcols, rrows, lcols, lrows)?;
-                Ok(match (lv, rv) {
-                    (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l < r),
-                    (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l < r),
-                    (Value::Integer(l), Value::Float(r)) => Value::Boolean((l as f64) < r),
-                    (Value::Float(l), Value::Integer(r)) => Value::Boolean(l < r as f64),
-                    (Value::Float(l), Value::Float(r)) => Value::Boolean(l < r),
-                    (Value::String(l), Value::String(r)) => Value::Boolean(l < r),
-                    (Value::Null, _) => Value::Null,
-                    (_, Value::Null) => Value::Null,
-                    (l, r) => {
-                        return Err(Error::Internal(format!(
-                            "can not compare expression {} and {}",
-                            l, r
-                        )))
-                    }
-                })
-            }
-        },
+        Expression::Operation(operation) => {
+            let (lexpr, rexpr) = operation.operands();
+            // Recursively evaluate both sides (swap params for the right, since Field uses lcols)
+            let lv = evaluate_expr(lexpr, lcols, lrows, rcols, rrows)?;
+            let rv = evaluate_expr(rexpr, rcols, rrows, lcols, lrows)?;
+            evaluate_operation(operation, lv, rv)
+        }
+        // Scalar function: evaluate the arguments, then apply the function
+        #[cfg(feature = "std")]
+        Expression::ScalarFunction(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| evaluate_expr(arg, lcols, lrows, rcols, rrows))
+                .collect::<Result<Vec<_>>>()?;
+            functions::call(name, args)
+        }
         _ => return Err(Error::Internal("unexpected expression".into())),
     }
+}
+
+/// Applies an operation to two evaluated operands
+///
+/// Shared by row-at-a-time and batch evaluation, so both agree on semantics.
+pub(crate) fn evaluate_operation(operation: &Operation, l: Value, r: Value) -> Result<Value> {
+    match operation {
+        Operation::Equal(..) | Operation::GreaterThan(..) | Operation::LessThan(..) => {
+            evaluate_comparison(operation, l, r)
+        }
+        _ => evaluate_arithmetic(operation.symbol(), l, r, DataType::Integer),
+    }
+}
+
+/// Compares two evaluated operands, yielding a boolean or NULL
+///
+/// Floats compare like they sort (`compare_floats`): NaN equals NaN and is
+/// greater than every number.
+fn evaluate_comparison(operation: &Operation, l: Value, r: Value) -> Result<Value> {
+    Ok(match operation {
+        Operation::Equal(..) => match (l, r) {
+            // Return true/false for equality comparison
+            (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l == r),
+            (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l == r),
+            (Value::Integer(l), Value::Float(r)) => Value::Boolean(compare_floats(l as f64, r).is_eq()),
+            (Value::Float(l), Value::Integer(r)) => Value::Boolean(compare_floats(l, r as f64).is_eq()),
+            (Value::Float(l), Value::Float(r)) => Value::Boolean(compare_floats(l, r).is_eq()),
+            (Value::String(l), Value::String(r)) => Value::Boolean(l == r),
+            (l @ Value::Point(..), r @ Value::Point(..)) => Value::Boolean(l == r),
+            (Value::Uuid(l), Value::Uuid(r)) => Value::Boolean(l == r),
+            // UUID literals are written as strings
+            (l @ Value::Uuid(_), Value::String(r)) | (Value::String(r), l @ Value::Uuid(_)) => {
+                Value::Boolean(Value::parse_uuid(&r) == Some(l))
+            }
+            (Value::Null, _) => Value::Null,
+            (_, Value::Null) => Value::Null,
+            (l, r) => {
+                return Err(Error::Internal(format!(
+                    "can not compare expression {} and {}",
+                    l, r
+                )))
+            }
+        },
+        Operation::GreaterThan(..) => match (l, r) {
+            (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l > r),
+            (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l > r),
+            (Value::Integer(l), Value::Float(r)) => Value::Boolean(compare_floats(l as f64, r).is_gt()),
+            (Value::Float(l), Value::Integer(r)) => Value::Boolean(compare_floats(l, r as f64).is_gt()),
+            (Value::Float(l), Value::Float(r)) => Value::Boolean(compare_floats(l, r).is_gt()),
+            (Value::String(l), Value::String(r)) => Value::Boolean(l > r),
+            (Value::Uuid(l), Value::Uuid(r)) => Value::Boolean(l > r),
+            (Value::Null, _) => Value::Null,
+            (_, Value::Null) => Value::Null,
+            (l, r) => {
+                return Err(Error::Internal(format!(
+                    "can not compare expression {} and {}",
+                    l, r
+                )))
+            }
+        },
+        Operation::LessThan(..) => match (l, r) {
+            (Value::Boolean(l), Value::Boolean(r)) => Value::Boolean(l < r),
+            (Value::Integer(l), Value::Integer(r)) => Value::Boolean(l < r),
+            (Value::Integer(l), Value::Float(r)) => Value::Boolean(compare_floats(l as f64, r).is_lt()),
+            (Value::Float(l), Value::Integer(r)) => Value::Boolean(compare_floats(l, r as f64).is_lt()),
+            (Value::Float(l), Value::Float(r)) => Value::Boolean(compare_floats(l, r).is_lt()),
+            (Value::String(l), Value::String(r)) => Value::Boolean(l < r),
+            (Value::Uuid(l), Value::Uuid(r)) => Value::Boolean(l < r),
+            (Value::Null, _) => Value::Null,
+            (_, Value::Null) => Value::Null,
+            (l, r) => {
+                return Err(Error::Internal(format!(
+                    "can not compare expression {} and {}",
+                    l, r
+                )))
+            }
+        },
+        _ => return Err(Error::Internal("unexpected comparison operation".into())),
+    })
+}
+
+/// Applies an arithmetic operation to two evaluated operands
+///
+/// Integer arithmetic is checked: overflow and division by zero are errors.
+/// Results must fit the operands' type, `datatype`: INTEGER, or BIGINT for
+/// the BIGINT functions (see `BIGINT_FUNCTIONS`). An operand beyond INTEGER
+/// can only be a BIGINT though. Mixing integers and floats yields a float,
+/// where division by zero gives infinity or NaN; NULL operands yield NULL.
+pub(crate) fn evaluate_arithmetic(symbol: &str, l: Value, r: Value, datatype: DataType) -> Result<Value> {
+    let (l, r) = match (l, r) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        (Value::Integer(l), Value::Integer(r)) => {
+            let datatype = match i64::try_from(l).and(i64::try_from(r)) {
+                Ok(_) => datatype,
+                Err(_) => DataType::BigInt,
+            };
+            let result = match symbol {
+                "+" => l.checked_add(r),
+                "-" => l.checked_sub(r),
+                "*" => l.checked_mul(r),
+                "/" | "%" if r == 0 => return Err(Error::Internal("division by zero".into())),
+                "/" => l.checked_div(r),
+                "%" => l.checked_rem(r),
+                _ => return Err(Error::Internal("unexpected arithmetic operation".into())),
+            };
+            return result
+                .map(Value::Integer)
+                .filter(|result| datatype.contains(result))
+                .ok_or(Error::Internal(format!("integer overflow: {} {} {}", l, symbol, r)));
+        }
+        (Value::Integer(l), Value::Float(r)) => (l as f64, r),
+        (Value::Float(l), Value::Integer(r)) => (l, r as f64),
+        (Value::Float(l), Value::Float(r)) => (l, r),
+        (l, r) => {
+            return Err(Error::Internal(format!(
+                "can not apply {} to {} and {}",
+                symbol, l, r
+            )))
+        }
+    };
+    Ok(Value::Float(match symbol {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" => l / r,
+        _ => l % r,
+    }))
+}
+
+/// Evaluates an expression that references no columns (e.g., INSERT values, defaults)
+pub fn evaluate_const_expr(expr: &Expression) -> Result<Value> {
+    evaluate_expr(expr, &Vec::new(), &Vec::new(), &Vec::new(), &Vec::new())
 }
\ No newline at end of file
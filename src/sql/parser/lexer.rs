@@ -1,6 +1,7 @@
 //! SQL Lexer - Tokenizes SQL input text into a stream of tokens
 
-use std::{fmt::Display, iter::Peekable, str::Chars};
+use alloc::{format, string::{String, ToString}};
+use core::{fmt::Display, iter::Peekable, str::Chars};
 
 use crate::error::{Result, Error};
 
@@ -15,6 +16,8 @@ pub enum Token {
     String(String),
     /// Numeric literal (integer or floating-point)
     Number(String),
+    /// Optimizer hint comment `/*+ ... */`, holding the text between the markers
+    Hint(String),
     /// Operators and punctuation
     OpenParen,
     CloseParen,
@@ -24,18 +27,21 @@ pub enum Token {
     Plus,
     Minus,
     Slash,
+    Percent,
     Equal,
     GreaterThan,
     LessThan,
+    Period,
 }
 
 impl Display for Token {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.write_str(match self {
             Token::Keyword(keyword) => keyword.to_str(),
             Token::Ident(ident) => ident,
             Token::String(v) => v,
             Token::Number(n) => n,
+            Token::Hint(h) => h,
             Token::OpenParen => "(",
             Token::CloseParen => ")",
             Token::Comma => ",",
@@ -44,9 +50,11 @@ impl Display for Token {
             Token::Plus => "+",
             Token::Minus => "-",
             Token::Slash => "/",
+            Token::Percent => "%",
             Token::Equal => "=",
             Token::GreaterThan => ">",
             Token::LessThan => "<",
+            Token::Period => ".",
         })
     }
 }
@@ -57,9 +65,13 @@ pub enum Keyword {
     // DDL keywords
     Create,
     Table,
+    Drop,
+    Cascade,
     // Data type keywords
     Int,
     Integer,
+    SmallInt,
+    BigInt,
     Boolean,
     Bool,
     String,
@@ -67,6 +79,8 @@ pub enum Keyword {
     Varchar,
     Float,
     Double,
+    Point,
+    Uuid,
     // DML keywords
     Select,
     From,
@@ -100,6 +114,30 @@ pub enum Keyword {
     On,
     Group,
     Having,
+    // Partitioning keywords
+    Partition,
+    Partitions,
+    Hash,
+    Collate,
+    // Historical read keywords
+    Of,
+    // Table options
+    With,
+    // Secondary indexes
+    Index,
+    // Bulk import and export
+    Copy,
+    To,
+    Load,
+    // Transaction control
+    Begin,
+    Commit,
+    Rollback,
+    Show,
+    For,
+    // Query plans
+    Explain,
+    Analyze,
 }
 
 impl Keyword {
@@ -108,8 +146,12 @@ impl Keyword {
         Some(match ident.to_uppercase().as_ref() {
             "CREATE" => Keyword::Create,
             "TABLE" => Keyword::Table,
+            "DROP" => Keyword::Drop,
+            "CASCADE" => Keyword::Cascade,
             "INT" => Keyword::Int,
             "INTEGER" => Keyword::Integer,
+            "SMALLINT" => Keyword::SmallInt,
+            "BIGINT" => Keyword::BigInt,
             "BOOLEAN" => Keyword::Boolean,
             "BOOL" => Keyword::Bool,
             "STRING" => Keyword::String,
@@ -117,6 +159,8 @@ impl Keyword {
             "VARCHAR" => Keyword::Varchar,
             "FLOAT" => Keyword::Float,
             "DOUBLE" => Keyword::Double,
+            "POINT" => Keyword::Point,
+            "UUID" => Keyword::Uuid,
             "SELECT" => Keyword::Select,
             "FROM" => Keyword::From,
             "INSERT" => Keyword::Insert,
@@ -147,6 +191,23 @@ impl Keyword {
             "ON" => Keyword::On,
             "GROUP" => Keyword::Group,
             "HAVING" => Keyword::Having,
+            "PARTITION" => Keyword::Partition,
+            "PARTITIONS" => Keyword::Partitions,
+            "HASH" => Keyword::Hash,
+            "COLLATE" => Keyword::Collate,
+            "OF" => Keyword::Of,
+            "WITH" => Keyword::With,
+            "INDEX" => Keyword::Index,
+            "COPY" => Keyword::Copy,
+            "TO" => Keyword::To,
+            "LOAD" => Keyword::Load,
+            "BEGIN" => Keyword::Begin,
+            "COMMIT" => Keyword::Commit,
+            "ROLLBACK" => Keyword::Rollback,
+            "SHOW" => Keyword::Show,
+            "FOR" => Keyword::For,
+            "EXPLAIN" => Keyword::Explain,
+            "ANALYZE" => Keyword::Analyze,
             _ => return None,
         })
     }
@@ -156,8 +217,12 @@ impl Keyword {
         match self {
             Keyword::Create => "CREATE",
             Keyword::Table => "TABLE",
+            Keyword::Drop => "DROP",
+            Keyword::Cascade => "CASCADE",
             Keyword::Int => "INT",
             Keyword::Integer => "INTEGER",
+            Keyword::SmallInt => "SMALLINT",
+            Keyword::BigInt => "BIGINT",
             Keyword::Boolean => "BOOLEAN",
             Keyword::Bool => "BOOL",
             Keyword::String => "STRING",
@@ -165,6 +230,8 @@ impl Keyword {
             Keyword::Varchar => "VARCHAR",
             Keyword::Float => "FLOAT",
             Keyword::Double => "DOUBLE",
+            Keyword::Point => "POINT",
+            Keyword::Uuid => "UUID",
             Keyword::Select => "SELECT",
             Keyword::From => "FROM",
             Keyword::Insert => "INSERT",
@@ -195,33 +262,88 @@ impl Keyword {
             Keyword::On => "ON",
             Keyword::Group => "GROUP",
             Keyword::Having => "HAVING",
+            Keyword::Partition => "PARTITION",
+            Keyword::Partitions => "PARTITIONS",
+            Keyword::Hash => "HASH",
+            Keyword::Collate => "COLLATE",
+            Keyword::Of => "OF",
+            Keyword::With => "WITH",
+            Keyword::Index => "INDEX",
+            Keyword::Copy => "COPY",
+            Keyword::To => "TO",
+            Keyword::Load => "LOAD",
+            Keyword::Begin => "BEGIN",
+            Keyword::Commit => "COMMIT",
+            Keyword::Rollback => "ROLLBACK",
+            Keyword::Show => "SHOW",
+            Keyword::For => "FOR",
+            Keyword::Explain => "EXPLAIN",
+            Keyword::Analyze => "ANALYZE",
         }
     }
 }
 
 impl Display for Keyword {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.write_str(self.to_str())
     }
 }
 
+/// A location in the SQL text (line and column are 1-based, in characters)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn start() -> Self {
+        Self { offset: 0, line: 1, column: 1 }
+    }
+
+    /// Advances past a character
+    fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Source range of a token: from `start` up to (excluding) `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
 /// SQL lexical analyzer (lexer/tokenizer)
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    /// Position of the next unread character
+    pos: Position,
 }
 
 impl<'a> Iterator for Lexer<'a> {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.scan() {
-            Ok(Some(token)) => Some(Ok(token)),
-            Ok(None) => self
-                .iter
-                .peek()
-                .map(|c| Err(Error::Parse(format!("[Lexer] Unexpeted character {}", c)))),
-            Err(err) => Some(Err(err)),
-        }
+        Some(match self.next_spanned()? {
+            Ok((token, _)) => Ok(token),
+            Err(Error::Parse(msg)) => Err(Error::Parse(format!("{} at {}", msg, self.pos))),
+            Err(err) => Err(err),
+        })
     }
 }
 
@@ -230,13 +352,47 @@ impl<'a> Lexer<'a> {
     pub fn new(sql_text: &'a str) -> Self {
         Self {
             iter: sql_text.chars().peekable(),
+            pos: Position::start(),
         }
     }
 
+    /// Position of the next unread character (where a failed scan stopped)
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Scans the next token along with its source span
+    ///
+    /// Error messages carry no position, see `position()`.
+    pub fn next_spanned(&mut self) -> Option<Result<(Token, Span)>> {
+        self.erase_whitespace();
+        let start = self.pos;
+        match self.scan() {
+            Ok(Some(token)) => Some(Ok((token, Span { start, end: self.pos }))),
+            Ok(None) => self
+                .iter
+                .peek()
+                .map(|c| Err(Error::Parse(format!("[Lexer] Unexpeted character {}", c)))),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Skips the next character, e.g., to resume after an unexpected character
+    pub fn skip_char(&mut self) {
+        self.bump();
+    }
+
+    /// Consumes the next character, tracking the position
+    fn bump(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.pos.advance(c);
+        Some(c)
+    }
+
     /// Consumes the next character if it satisfies the predicate
     fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<char> {
         self.iter.peek().filter(|&c| predicate(*c))?;
-        self.iter.next()
+        self.bump()
     }
 
     /// Consumes consecutive characters while they satisfy the predicate
@@ -251,7 +407,7 @@ impl<'a> Lexer<'a> {
     /// Peeks and consumes if the character maps to a token (for single-char tokens)
     fn next_if_token<F: Fn(char) -> Option<Token>>(&mut self, predicate: F) -> Option<Token> {
         let token = self.iter.peek().and_then(|c| predicate(*c))?;
-        self.iter.next();
+        self.bump();
         Some(token)
     }
 
@@ -263,10 +419,13 @@ impl<'a> Lexer<'a> {
     /// Scans and returns the next token
     fn scan(&mut self) -> Result<Option<Token>> {
         self.erase_whitespace();
+        if self.iter.clone().take(2).eq(['/', '*']) {
+            return self.scan_comment();
+        }
         match self.iter.peek() {
             Some('\'') => self.scan_string(),
             Some(c) if c.is_ascii_digit() => Ok(self.scan_number()),
-            Some(c) if c.is_alphabetic() => Ok(self.scan_ident()),
+            Some(c) if c.is_alphabetic() || *c == '_' => Ok(self.scan_ident()),
             Some(_) => Ok(self.scan_symbol()),
             None => Ok(None),
         }
@@ -274,11 +433,13 @@ impl<'a> Lexer<'a> {
 
     /// Scans a string literal (enclosed in single quotes)
     fn scan_string(&mut self) -> Result<Option<Token>> {
-        self.iter.next();
+        self.bump();
         let mut val = String::new();
 
         loop {
-            match self.iter.next() {
+            match self.bump() {
+                // A doubled quote stands for one quote inside the string
+                Some('\'') if self.next_if(|c| c == '\'').is_some() => val.push('\''),
                 Some('\'') => break,
                 Some(c) => val.push(c),
                 None => return Err(Error::Parse(format!("[Lexer] Unexpected end of string"))),
@@ -287,21 +448,57 @@ impl<'a> Lexer<'a> {
         Ok(Some(Token::String(val)))
     }
 
-    /// Scans a numeric literal (integer or floating-point)
+    /// Scans a `/* ... */` comment, returning it as a hint if it starts with
+    /// `/*+`, else skipping it for the token after
+    fn scan_comment(&mut self) -> Result<Option<Token>> {
+        self.bump();
+        self.bump();
+        let hint = self.next_if(|c| c == '+').is_some();
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('*') if self.next_if(|c| c == '/').is_some() => break,
+                Some(c) => text.push(c),
+                None => return Err(Error::Parse("[Lexer] Unexpected end of comment".into())),
+            }
+        }
+        match hint {
+            true => Ok(Some(Token::Hint(text.trim().to_string()))),
+            false => self.scan(),
+        }
+    }
+
+    /// Scans a numeric literal (integer, hex integer, or floating-point with exponent)
+    ///
+    /// Signs are separate tokens; the parser folds them into literals.
     fn scan_number(&mut self) -> Option<Token> {
         let mut val = self.next_while(|c| c.is_ascii_digit())?;
+        if val == "0"
+            && let Some(x) = self.next_if(|c| c == 'x' || c == 'X')
+        {
+            val.push(x);
+            val.extend(self.next_while(|c| c.is_ascii_hexdigit()));
+            return Some(Token::Number(val));
+        }
         if let Some(sep) = self.next_if(|c| c == '.') {
             val.push(sep);
             while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
                 val.push(c);
             }
         }
+        if let Some(e) = self.next_if(|c| c == 'e' || c == 'E') {
+            val.push(e);
+            if let Some(sign) = self.next_if(|c| c == '+' || c == '-') {
+                val.push(sign);
+            }
+            val.extend(self.next_while(|c| c.is_ascii_digit()));
+        }
         Some(Token::Number(val))
     }
 
     /// Scans an identifier or keyword
     fn scan_ident(&mut self) -> Option<Token> {
-        let mut val = self.next_if(|c| c.is_alphabetic())?.to_string();
+        let mut val = self.next_if(|c| c.is_alphabetic() || c == '_')?.to_string();
         while let Some(c) = self.next_if(|c| c.is_alphanumeric() || c == '_') {
             val.push(c);
         }
@@ -320,9 +517,11 @@ impl<'a> Lexer<'a> {
             '+' => Some(Token::Plus),
             '-' => Some(Token::Minus),
             '/' => Some(Token::Slash),
+            '%' => Some(Token::Percent),
             '=' => Some(Token::Equal),
             '>' => Some(Token::GreaterThan),
             '<' => Some(Token::LessThan),
+            '.' => Some(Token::Period),
             _ => None,
         })
     }
@@ -334,7 +533,7 @@ mod tests {
 
     use super::Lexer;
     use crate::{
-        error::Result,
+        error::{Error, Result},
         sql::parser::lexer::{Keyword, Token},
     };
 
@@ -395,6 +594,78 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lexer_comments() -> Result<()> {
+        let tokens = Lexer::new("select /*+ INDEX(t i) */ a /* note: * / */ from t/**/;")
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Hint("INDEX(t i)".to_string()),
+                Token::Ident("a".to_string()),
+                Token::Keyword(Keyword::From),
+                Token::Ident("t".to_string()),
+                Token::Semicolon,
+            ]
+        );
+        assert_eq!(Lexer::new("a / *b").count(), 4);
+        assert!(Lexer::new("select /* a").collect::<Result<Vec<_>>>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_number() -> Result<()> {
+        let tokens = Lexer::new("1e9 2.5E-3 0xFF 0X1a -7 1.")
+            .peekable()
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Number("1e9".to_string()),
+                Token::Number("2.5E-3".to_string()),
+                Token::Number("0xFF".to_string()),
+                Token::Number("0X1a".to_string()),
+                Token::Minus,
+                Token::Number("7".to_string()),
+                Token::Number("1.".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_string_escape() -> Result<()> {
+        let tokens = Lexer::new("'it''s' '''' ''").peekable().collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![Token::String("it's".into()), Token::String("'".into()), Token::String("".into())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_underscore_ident() -> Result<()> {
+        let tokens = Lexer::new("_audit_T1 a_1").peekable().collect::<Result<Vec<_>>>()?;
+        assert_eq!(tokens, vec![Token::Ident("_audit_t1".into()), Token::Ident("a_1".into())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_position() -> Result<()> {
+        let mut lexer = Lexer::new("select a,\n  'é' from");
+        let mut spans = Vec::new();
+        while let Some(item) = lexer.next_spanned() {
+            let (_, span) = item?;
+            spans.push((span.start.line, span.start.column, span.end.offset - span.start.offset));
+        }
+        assert_eq!(spans, vec![(1, 1, 6), (1, 8, 1), (1, 9, 1), (2, 3, 4), (2, 7, 4)]);
+
+        let err = Lexer::new("select\n  #").collect::<Result<Vec<_>>>().unwrap_err();
+        assert_eq!(err, Error::Parse("[Lexer] Unexpeted character # at line 2, column 3".into()));
+        Ok(())
+    }
+
     #[test]
     fn test_lexer_insert_into() -> Result<()> {
         let tokens1 = Lexer::new("insert into tbl values (1, 2, '3', true, false, 4.55);")
@@ -5,8 +5,14 @@
 //! - Parser: parses tokens into AST nodes
 //! - AST: abstract syntax tree definitions
 
-use std::collections::BTreeMap;
-use std::iter::Peekable;
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 use ast::Column;
 use crate::sql::parser::ast::{Expression, Operation, OrderDirection};
 use crate::sql::parser::lexer::{Keyword, Lexer, Token};
@@ -14,21 +20,104 @@ use crate::error::{Result, Error};
 use super::types::DataType;
 
 pub mod ast;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod generator;
 mod lexer;
 
+pub use lexer::{Position, Span};
+
 /// SQL Parser - Converts tokens into Abstract Syntax Tree (AST)
 pub struct Parser<'a> {
-    lexer: Peekable<Lexer<'a>>,
+    lexer: Lexer<'a>,
+    /// One-token lookahead buffer
+    peeked: Option<Option<Result<(Token, Span)>>>,
+    /// Span of the most recently peeked or consumed token
+    span: Option<Span>,
+    /// Whether the most recently consumed token ended a statement
+    after_semicolon: bool,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new parser for the given SQL input
     pub fn new(input: &'a str) -> Self {
-        Parser { lexer: Lexer::new(input).peekable() }
+        Parser { lexer: Lexer::new(input), peeked: None, span: None, after_semicolon: false }
     }
 
     /// Parses the input SQL statement into an AST
+    ///
+    /// Parse errors end with the position of the offending token, e.g.
+    /// "... at line 3, column 14"; see `span()` for its full extent.
     pub fn parse(&mut self) -> Result<ast::Statement> {
+        self.parse_complete().map_err(|err| self.positioned(err))
+    }
+
+    /// Parses a script of semicolon-terminated statements
+    ///
+    /// After a syntax error the parser skips to the next semicolon and carries on,
+    /// so every error in the script is reported (with its position), in order.
+    pub fn parse_script(&mut self) -> core::result::Result<Vec<ast::Statement>, Vec<Error>> {
+        let mut stmts = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.peek() {
+                Ok(None) => break,
+                Ok(Some(_)) => match self.parse_statement().and_then(|stmt| {
+                    self.next_expect(Token::Semicolon)?;
+                    Ok(stmt)
+                }) {
+                    Ok(stmt) => stmts.push(stmt),
+                    Err(err) => {
+                        errors.push(self.positioned(err));
+                        self.recover();
+                    }
+                },
+                Err(err) => {
+                    errors.push(self.positioned(err));
+                    self.recover();
+                }
+            }
+        }
+        if errors.is_empty() { Ok(stmts) } else { Err(errors) }
+    }
+
+    /// Span of the token the parser stopped at, e.g., the offending token after an error
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// Appends the current position to a parse error
+    fn positioned(&self, err: Error) -> Error {
+        match (err, self.span) {
+            (Error::Parse(msg), Some(span)) => Error::Parse(format!("{} at {}", msg, span.start)),
+            (err, _) => err,
+        }
+    }
+
+    /// Skips past the next semicolon (or to the end of input) after an error
+    fn recover(&mut self) {
+        // The statement already ended when the failing token was its semicolon
+        if self.peeked.is_none() && self.after_semicolon {
+            return;
+        }
+        loop {
+            match self.peek() {
+                Ok(None) => break,
+                Ok(Some(token)) => {
+                    self.peeked = None;
+                    if token == Token::Semicolon {
+                        break;
+                    }
+                }
+                // Lexer errors don't consume the offending character
+                Err(_) => {
+                    self.peeked = None;
+                    self.lexer.skip_char();
+                }
+            }
+        }
+    }
+
+    fn parse_complete(&mut self) -> Result<ast::Statement> {
         let stmt = self.parse_statement()?;
         self.next_expect(Token::Semicolon)?;
         if let Some(token) = self.peek()? {
@@ -40,11 +129,19 @@ impl<'a> Parser<'a> {
     /// Parses a statement based on the first token
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         match self.peek()? {
-            Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Create)) | Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_select(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_insert(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_update(),
             Some(Token::Keyword(Keyword::Delete)) => self.parse_delete(),
+            Some(Token::Keyword(Keyword::Copy)) => self.parse_copy(),
+            Some(Token::Keyword(Keyword::Load)) => self.parse_load(),
+            Some(Token::Keyword(Keyword::Begin | Keyword::Commit | Keyword::Rollback)) => self.parse_transaction(),
+            Some(Token::Keyword(Keyword::Set)) => self.parse_set(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_show(),
+            Some(Token::Keyword(Keyword::Explain)) => self.parse_explain(),
+            // Not a keyword, so columns may still be named refresh
+            Some(Token::Ident(ident)) if ident == "refresh" => self.parse_refresh(),
             Some(t) => Err(Error::Parse(format!("[Parser] Unexpected token {}", t))),
             None => Err(Error::Parse(format!("[Parser] Unexpected end of input"))),
         }
@@ -55,8 +152,16 @@ impl<'a> Parser<'a> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_create_index(),
+                Token::Ident(ident) if ident == "materialized" => self.parse_ddl_create_materialized_view(),
                 token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
             },
+            Token::Keyword(Keyword::Drop) => {
+                self.next_expect(Token::Keyword(Keyword::Table))?;
+                let name = self.next_ident()?;
+                let cascade = self.next_if_token(Token::Keyword(Keyword::Cascade)).is_some();
+                Ok(ast::Statement::DropTable { name, cascade })
+            }
             token => Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
         }
     }
@@ -74,7 +179,107 @@ impl<'a> Parser<'a> {
             }
         }
         self.next_expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name: table_name, columns })
+        let partition_by = self.parse_ddl_partition_by()?;
+        let options = self.parse_ddl_with()?;
+        Ok(ast::Statement::CreateTable { name: table_name, columns, partition_by, options })
+    }
+
+    /// Parses CREATE MATERIALIZED VIEW name AS SELECT ...
+    fn parse_ddl_create_materialized_view(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Ident("view".into()))?;
+        let name = self.next_ident()?;
+        self.next_expect(Token::Keyword(Keyword::As))?;
+        Ok(ast::Statement::CreateMaterializedView { name, query: Box::new(self.parse_select()?) })
+    }
+
+    /// Parses REFRESH MATERIALIZED VIEW name
+    fn parse_refresh(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Ident("refresh".into()))?;
+        self.next_expect(Token::Ident("materialized".into()))?;
+        self.next_expect(Token::Ident("view".into()))?;
+        Ok(ast::Statement::RefreshMaterializedView { name: self.next_ident()? })
+    }
+
+    /// Parses CREATE INDEX [name] ON table (column, ...) [WHERE predicate]
+    fn parse_ddl_create_index(&mut self) -> Result<ast::Statement> {
+        let name = match self.next_if_token(Token::Keyword(Keyword::On)) {
+            Some(_) => None,
+            None => {
+                let name = self.next_ident()?;
+                self.next_expect(Token::Keyword(Keyword::On))?;
+                Some(name)
+            }
+        };
+        let table_name = self.next_ident()?;
+        self.next_expect(Token::OpenParen)?;
+        let mut columns = vec![self.next_ident()?];
+        while self.next_if_token(Token::Comma).is_some() {
+            columns.push(self.next_ident()?);
+        }
+        self.next_expect(Token::CloseParen)?;
+        let predicate = self.parse_where_clause()?;
+        Ok(ast::Statement::CreateIndex { name, table_name, columns, predicate })
+    }
+
+    /// Parses optional WITH (name = value, ...) clause of table options
+    ///
+    /// Values are literals. Which options exist is checked with the schema
+    /// (see `schema::TABLE_OPTIONS`).
+    fn parse_ddl_with(&mut self) -> Result<BTreeMap<String, ast::Consts>> {
+        let mut options = BTreeMap::new();
+        if self.next_if_token(Token::Keyword(Keyword::With)).is_none() {
+            return Ok(options);
+        }
+        self.next_expect(Token::OpenParen)?;
+        loop {
+            let option = self.next_ident()?;
+            self.next_expect(Token::Equal)?;
+            let value = match self.next()? {
+                Token::String(s) => ast::Consts::String(s),
+                Token::Number(n) => parse_number(&n)?,
+                Token::Minus => match self.next()? {
+                    Token::Number(n) => parse_number(&format!("-{}", n))?,
+                    token => {
+                        return Err(Error::Parse(format!("[Parser] Invalid value -{} for table option {}", token, option)));
+                    }
+                },
+                Token::Keyword(Keyword::True) => ast::Consts::Boolean(true),
+                Token::Keyword(Keyword::False) => ast::Consts::Boolean(false),
+                Token::Keyword(Keyword::Null) => ast::Consts::Null,
+                token => {
+                    return Err(Error::Parse(format!("[Parser] Invalid value {} for table option {}", token, option)));
+                }
+            };
+            if options.insert(option.clone(), value).is_some() {
+                return Err(Error::Parse(format!("[Parser] Repeated table option {}", option)));
+            }
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Token::CloseParen)?;
+        Ok(options)
+    }
+
+    /// Parses optional PARTITION BY HASH (column) PARTITIONS n clause
+    fn parse_ddl_partition_by(&mut self) -> Result<Option<ast::PartitionBy>> {
+        if self.next_if_token(Token::Keyword(Keyword::Partition)).is_none() {
+            return Ok(None);
+        }
+        self.next_expect(Token::Keyword(Keyword::By))?;
+        self.next_expect(Token::Keyword(Keyword::Hash))?;
+        self.next_expect(Token::OpenParen)?;
+        let column = self.next_ident()?;
+        self.next_expect(Token::CloseParen)?;
+        self.next_expect(Token::Keyword(Keyword::Partitions))?;
+        let partitions = match self.next()? {
+            Token::Number(n) => n.parse::<u64>()?,
+            token => return Err(Error::Parse(format!("[Parser] Expected partition count, got token {}", token))),
+        };
+        if partitions == 0 {
+            return Err(Error::Parse("[Parser] Partition count must be positive".into()));
+        }
+        Ok(Some(ast::PartitionBy::Hash { column, partitions }))
     }
 
     /// Parses column definition in CREATE TABLE
@@ -83,14 +288,19 @@ impl<'a> Parser<'a> {
             name: self.next_ident()?,
             datatype: match self.next()? {
                 Token::Keyword(Keyword::Int) | Token::Keyword(Keyword::Integer) => DataType::Integer,
+                Token::Keyword(Keyword::SmallInt) => DataType::SmallInt,
+                Token::Keyword(Keyword::BigInt) => DataType::BigInt,
                 Token::Keyword(Keyword::Bool) | Token::Keyword(Keyword::Boolean) => DataType::Boolean,
                 Token::Keyword(Keyword::Float) | Token::Keyword(Keyword::Double) => DataType::Float,
+                Token::Keyword(Keyword::Point) => DataType::Point,
+                Token::Keyword(Keyword::Uuid) => DataType::Uuid,
                 Token::Keyword(Keyword::String) | Token::Keyword(Keyword::Text) | Token::Keyword(Keyword::Varchar) => DataType::String,
                 token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
             },
             nullable: None,
             default: None,
             primary_key: false,
+            collation: None,
         };
 
         while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
@@ -105,6 +315,7 @@ impl<'a> Parser<'a> {
                     self.next_expect(Token::Keyword(Keyword::Key))?;
                     column.primary_key = true;
                 }
+                Keyword::Collate => column.collation = Some(self.next_ident()?),
                 k => return Err(Error::Parse(format!("[Parser] Unexpected keyword {}", k))),
             }
         }
@@ -114,9 +325,12 @@ impl<'a> Parser<'a> {
 
     /// Parses SELECT statement
     fn parse_select(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Select))?;
         Ok(ast::Statement::Select {
+            hints: self.parse_hints()?,
             select: self.parse_select_clause()?,
             from: self.parse_from_clause()?,
+            as_of: self.parse_as_of_clause()?,
             where_clause: self.parse_where_clause()?,
             group_by: self.parse_group_clause()?,
             having: self.parse_having_clause()?,
@@ -138,6 +352,13 @@ impl<'a> Parser<'a> {
                     None
                 }
             },
+            for_update: match self.next_if_token(Token::Keyword(Keyword::For)) {
+                Some(_) => {
+                    self.next_expect(Token::Keyword(Keyword::Update))?;
+                    true
+                }
+                None => false,
+            },
         })
     }
 
@@ -235,10 +456,112 @@ impl<'a> Parser<'a> {
             where_clause: self.parse_where_clause()?,
         })
     }
-    
-    /// Parses comparison expression (e.g., col = value)
+
+    /// Parses COPY table TO|FROM 'path'
+    fn parse_copy(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Copy))?;
+        let table_name = self.next_ident()?;
+        let direction = match self.next()? {
+            Token::Keyword(Keyword::To) => ast::CopyDirection::To,
+            Token::Keyword(Keyword::From) => ast::CopyDirection::From,
+            token => return Err(Error::Parse(format!("[Parser] Expected TO or FROM, got token {}", token))),
+        };
+        let path = match self.next()? {
+            Token::String(path) => path,
+            token => return Err(Error::Parse(format!("[Parser] Expected file path, got token {}", token))),
+        };
+        Ok(ast::Statement::Copy { table_name, direction, path })
+    }
+
+    /// Parses LOAD DATA 'path' INTO table
+    fn parse_load(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Load))?;
+        match self.next_ident()?.as_str() {
+            "data" => {}
+            name => return Err(Error::Parse(format!("[Parser] Unknown LOAD target {}", name))),
+        }
+        let path = match self.next()? {
+            Token::String(path) => path,
+            token => return Err(Error::Parse(format!("[Parser] Expected file path, got token {}", token))),
+        };
+        self.next_expect(Token::Keyword(Keyword::Into))?;
+        let table_name = self.next_ident()?;
+        Ok(ast::Statement::LoadData { table_name, path })
+    }
+
+    /// Parses BEGIN, COMMIT or ROLLBACK, optionally followed by TRANSACTION
+    fn parse_transaction(&mut self) -> Result<ast::Statement> {
+        let stmt = match self.next()? {
+            Token::Keyword(Keyword::Begin) => ast::Statement::Begin,
+            Token::Keyword(Keyword::Commit) => ast::Statement::Commit,
+            Token::Keyword(Keyword::Rollback) => ast::Statement::Rollback,
+            token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+        };
+        self.next_if_token(Token::Ident("transaction".into()));
+        Ok(stmt)
+    }
+
+    /// Parses SET name = value, the value being a word, number or string
+    fn parse_set(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Set))?;
+        let name = self.next_ident()?;
+        self.next_expect(Token::Equal)?;
+        let value = match self.next()? {
+            Token::Ident(value) | Token::Number(value) | Token::String(value) => value,
+            // e.g. ON, TRUE or DEFAULT
+            Token::Keyword(keyword) => keyword.to_str().to_lowercase(),
+            token => return Err(Error::Parse(format!("[Parser] Expected setting value, got token {}", token))),
+        };
+        Ok(ast::Statement::Set { name, value })
+    }
+
+    /// Parses SHOW STATS or SHOW CREATE TABLE name [AS OF VERSION n]
+    fn parse_show(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Show))?;
+        if self.next_if_token(Token::Keyword(Keyword::Create)).is_some() {
+            self.next_expect(Token::Keyword(Keyword::Table))?;
+            let name = self.next_ident()?;
+            return Ok(ast::Statement::ShowCreateTable { name, as_of: self.parse_as_of_clause()? });
+        }
+        match self.next_ident()?.as_str() {
+            "stats" => Ok(ast::Statement::ShowStats),
+            name => Err(Error::Parse(format!("[Parser] Unknown SHOW target {}", name))),
+        }
+    }
+
+    /// Parses EXPLAIN [ANALYZE] [FORMAT = TREE | JSON | DOT] followed by the
+    /// explained statement
+    fn parse_explain(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Explain))?;
+        let analyze = self.next_if_token(Token::Keyword(Keyword::Analyze)).is_some();
+        // Not a keyword, so columns may still be named format
+        let format = match self.next_if(|t| matches!(t, Token::Ident(ident) if ident.eq_ignore_ascii_case("format"))) {
+            Some(_) => {
+                self.next_expect(Token::Equal)?;
+                let name = self.next_ident()?;
+                ast::ExplainFormat::from_name(&name)
+                    .ok_or_else(|| Error::Parse(format!("[Parser] Unknown EXPLAIN format {}", name)))?
+            }
+            None => ast::ExplainFormat::Tree,
+        };
+        let statement = match self.peek()? {
+            Some(Token::Keyword(Keyword::Select | Keyword::Insert | Keyword::Update | Keyword::Delete)) => {
+                self.parse_statement()?
+            }
+            Some(t) => return Err(Error::Parse(format!("[Parser] Cannot EXPLAIN {}", t))),
+            None => return Err(Error::Parse("[Parser] Unexpected end of input".into())),
+        };
+        Ok(ast::Statement::Explain { statement: Box::new(statement), analyze, format })
+    }
+
+    /// Parses comparison expression (e.g., col = value), or a bare boolean expression
     fn parse_opreation_expr(&mut self) -> Result<ast::Expression> {
         let left = self.parse_expression()?;
+        let is_operator = |t: &Token| matches!(t, Token::Equal | Token::GreaterThan | Token::LessThan);
+        if !self.peek()?.is_some_and(|t| is_operator(&t)) {
+            // e.g., WHERE within(loc, point(0, 0), point(1, 1))
+            return Ok(left);
+        }
         Ok(match self.next()? {
             Token::Equal => ast::Expression::Operation(Operation::Equal(
                 Box::new(left),
@@ -256,26 +579,95 @@ impl<'a> Parser<'a> {
         })
     }
 
-    /// Parses an expression (identifier, constant, or function call)
+    /// Parses an arithmetic expression (e.g., a + b * 2)
     fn parse_expression(&mut self) -> Result<ast::Expression> {
+        let mut expr = self.parse_term()?;
+        while let Some(token) = self.next_if(|t| matches!(t, Token::Plus | Token::Minus)) {
+            let right = Box::new(self.parse_term()?);
+            expr = ast::Expression::Operation(match token {
+                Token::Plus => Operation::Add(Box::new(expr), right),
+                _ => Operation::Subtract(Box::new(expr), right),
+            });
+        }
+        Ok(expr)
+    }
+
+    /// Parses a multiplicative term (binds tighter than + and -)
+    fn parse_term(&mut self) -> Result<ast::Expression> {
+        let mut expr = self.parse_atom()?;
+        while let Some(token) = self.next_if(|t| matches!(t, Token::Asterisk | Token::Slash | Token::Percent)) {
+            let right = Box::new(self.parse_atom()?);
+            expr = ast::Expression::Operation(match token {
+                Token::Asterisk => Operation::Multiply(Box::new(expr), right),
+                Token::Slash => Operation::Divide(Box::new(expr), right),
+                _ => Operation::Modulo(Box::new(expr), right),
+            });
+        }
+        Ok(expr)
+    }
+
+    /// Parses an atom (identifier, constant, function call, or parenthesized expression)
+    fn parse_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
+            // e.g., a - (b - c), or a comparison used as a value: (a = 1)
+            Token::OpenParen => {
+                let expr = self.parse_opreation_expr()?;
+                self.next_expect(Token::CloseParen)?;
+                expr
+            }
             Token::Ident(ident) => {
                 if self.next_if_token(Token::OpenParen).is_some() {
-                    let col_name = self.next_ident()?;
-                    self.next_expect(Token::CloseParen)?;
-                    ast::Expression::Function(ident, col_name)
+                    // COUNT(*) counts rows, NULLs included
+                    if ident.eq_ignore_ascii_case("count") && self.next_if_token(Token::Asterisk).is_some() {
+                        self.next_expect(Token::CloseParen)?;
+                        return Ok(ast::Expression::Function(ident, ast::ALL_ROWS.into()));
+                    }
+                    // EXTRACT(field FROM date) is extract('field', date)
+                    if ident.eq_ignore_ascii_case("extract")
+                        && let Some(Token::Ident(_)) = self.peek()?
+                    {
+                        let field = self.next_ident()?.to_lowercase();
+                        self.next_expect(Token::Keyword(Keyword::From))?;
+                        let date = self.parse_expression()?;
+                        self.next_expect(Token::CloseParen)?;
+                        return Ok(ast::Expression::ScalarFunction("extract".into(), vec![ast::Consts::String(field).into(), date]));
+                    }
+                    let mut args = self.parse_function_args()?;
+                    // Aggregate functions take a single column
+                    match args.as_slice() {
+                        [ast::Expression::Field(_)] if is_aggregate(&ident) => match args.remove(0) {
+                            ast::Expression::Field(col_name) => ast::Expression::Function(ident, col_name),
+                            _ => unreachable!(),
+                        },
+                        _ => ast::Expression::ScalarFunction(ident.to_lowercase(), args),
+                    }
                 } else {
                     ast::Expression::Field(ident)
                 }
             }
-            Token::Number(n) => {
-                // Distinguish integer from float (both tokenized as Number)
-                if n.chars().all(|c| c.is_ascii_digit()) {
-                    ast::Consts::Integer(n.parse()?).into()
-                } else {
-                    ast::Consts::Float(n.parse()?).into()
-                }
+            // POINT(x, y) literal
+            Token::Keyword(Keyword::Point) => {
+                self.next_expect(Token::OpenParen)?;
+                ast::Expression::ScalarFunction("point".into(), self.parse_function_args()?)
+            }
+            // UUID() generator (the keyword also names the data type)
+            Token::Keyword(Keyword::Uuid) => {
+                self.next_expect(Token::OpenParen)?;
+                ast::Expression::ScalarFunction("uuid".into(), self.parse_function_args()?)
             }
+            Token::Number(n) => parse_number(&n)?.into(),
+            // Signed literal (e.g., -5), or negation of an expression
+            Token::Minus => match self.peek()? {
+                Some(Token::Number(n)) => {
+                    self.next()?;
+                    parse_number(&format!("-{}", n))?.into()
+                }
+                _ => ast::Expression::Operation(Operation::Subtract(
+                    Box::new(ast::Consts::Integer(0).into()),
+                    Box::new(self.parse_atom()?),
+                )),
+            },
+            Token::Plus => self.parse_atom()?,
             Token::String(s) => ast::Consts::String(s).into(),
             Token::Keyword(Keyword::True) => ast::Consts::Boolean(true).into(),
             Token::Keyword(Keyword::False) => ast::Consts::Boolean(false).into(),
@@ -289,10 +681,58 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// Parses a function argument list after the opening parenthesis
+    fn parse_function_args(&mut self) -> Result<Vec<ast::Expression>> {
+        let mut args = Vec::new();
+        if self.next_if_token(Token::CloseParen).is_some() {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expression()?);
+            match self.next()? {
+                Token::CloseParen => break,
+                Token::Comma => {}
+                token => return Err(Error::Parse(format!("[Parser] Unexpected token {}", token))),
+            }
+        }
+        Ok(args)
+    }
+
+    /// Parses the optimizer hints of /*+ ... */ comments after SELECT, e.g.
+    /// `/*+ INDEX(t idx_a) NO_INDEX(t2) */`
+    fn parse_hints(&mut self) -> Result<Vec<ast::Hint>> {
+        let mut hints = Vec::new();
+        while let Some(Token::Hint(text)) = self.next_if(|t| matches!(t, Token::Hint(_))) {
+            let mut parser = Parser::new(&text);
+            while parser.peek()?.is_some() {
+                hints.push(match parser.next()? {
+                    Token::Keyword(Keyword::Index) => {
+                        parser.next_expect(Token::OpenParen)?;
+                        let table = parser.next_ident()?;
+                        let index = parser.next_ident()?;
+                        parser.next_expect(Token::CloseParen)?;
+                        ast::Hint::Index { table, index }
+                    }
+                    Token::Ident(name) if name == "no_index" => {
+                        let table = match parser.next_if_token(Token::OpenParen) {
+                            Some(_) => {
+                                let table = parser.next_ident()?;
+                                parser.next_expect(Token::CloseParen)?;
+                                Some(table)
+                            }
+                            None => None,
+                        };
+                        ast::Hint::NoIndex { table }
+                    }
+                    token => return Err(Error::Parse(format!("[Parser] Unknown hint {}", token))),
+                });
+            }
+        }
+        Ok(hints)
+    }
+
     /// Parses SELECT clause (column list with optional aliases)
     fn parse_select_clause(&mut self) -> Result<Vec<(Expression, Option<String>)>> {
-        self.next_expect(Token::Keyword(Keyword::Select))?;
-
         let mut select = Vec::new();
         // SELECT * returns empty vec (all columns)
         if self.next_if_token(Token::Asterisk).is_some() {
@@ -300,7 +740,8 @@ impl<'a> Parser<'a> {
         }
 
         loop {
-            let expr = self.parse_expression()?;
+            // A comparison gives a boolean column, e.g. SELECT a > 5 AS big
+            let expr = self.parse_opreation_expr()?;
             let alias = match self.next_if_token(Token::Keyword(Keyword::As)) {
                 Some(_) => Some(self.next_ident()?),
                 None => None,
@@ -349,11 +790,28 @@ impl<'a> Parser<'a> {
         Ok(item)
     }
 
-    /// Parses a single table reference
+    /// Parses optional AS OF VERSION n clause
+    ///
+    /// VERSION isn't reserved, so it stays usable as a column name.
+    fn parse_as_of_clause(&mut self) -> Result<Option<u64>> {
+        if self.next_if_token(Token::Keyword(Keyword::As)).is_none() {
+            return Ok(None);
+        }
+        self.next_expect(Token::Keyword(Keyword::Of))?;
+        self.next_expect(Token::Ident("version".into()))?;
+        match self.next()? {
+            Token::Number(n) => Ok(Some(n.parse::<u64>()?)),
+            token => Err(Error::Parse(format!("[Parser] Expected version number, got token {}", token))),
+        }
+    }
+
+    /// Parses a single table reference, optionally schema-qualified (system.transactions)
     fn parse_from_table_clause(&mut self) -> Result<ast::FromItem> {
-        Ok(ast::FromItem::Table {
-            name: self.next_ident()?,
-        })
+        let mut name = self.next_ident()?;
+        if self.next_if_token(Token::Period).is_some() {
+            name = format!("{}.{}", name, self.next_ident()?);
+        }
+        Ok(ast::FromItem::Table { name })
     }
 
     /// Parses JOIN type if present
@@ -436,18 +894,44 @@ impl<'a> Parser<'a> {
 
     /// Peeks at the next token
     fn peek(&mut self) -> Result<Option<Token>> {
-        self.lexer.peek().cloned().transpose()
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next_spanned());
+        }
+        match self.peeked.as_ref().and_then(|p| p.as_ref()) {
+            Some(Ok((token, span))) => {
+                self.span = Some(*span);
+                Ok(Some(token.clone()))
+            }
+            // Lexer errors and end of input point at where the lexer stopped
+            Some(Err(err)) => {
+                let pos = self.lexer.position();
+                self.span = Some(Span { start: pos, end: pos });
+                Err(err.clone())
+            }
+            None => {
+                let pos = self.lexer.position();
+                self.span = Some(Span { start: pos, end: pos });
+                Ok(None)
+            }
+        }
     }
 
     /// Consumes and returns the next token
     fn next(&mut self) -> Result<Token> {
-        self.lexer.next().unwrap_or_else(|| Err(Error::Parse(format!("[Parser] Unexpected end of input"))))
+        let token = self.peek()?;
+        self.peeked = None;
+        self.after_semicolon = token == Some(Token::Semicolon);
+        token.ok_or_else(|| Error::Parse(format!("[Parser] Unexpected end of input")))
     }
 
     /// Expects and consumes an identifier
     fn next_ident(&mut self) -> Result<String> {
         match self.next()? {
             Token::Ident(ident) => Ok(ident),
+            Token::Keyword(keyword) => Err(Error::Parse(format!(
+                "[Parser] Expected ident, got reserved word {}",
+                keyword
+            ))),
             token => Err(Error::Parse(format!(
                 "[Parser] Expected ident, got token {}",
                 token
@@ -484,9 +968,39 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Parses a numeric literal (optionally signed) into an integer or float constant
+///
+/// Plain digits and 0x-prefixed hex are integers; decimals and exponents are floats.
+fn parse_number(n: &str) -> Result<ast::Consts> {
+    let (sign, digits) = match n.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", n),
+    };
+    let out_of_range = || Error::Parse(format!("[Parser] Integer {} is out of range", n));
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        if hex.is_empty() {
+            return Err(Error::Parse(format!("[Parser] Invalid hex literal {}", n)));
+        }
+        return i128::from_str_radix(&format!("{}{}", sign, hex), 16)
+            .map(ast::Consts::Integer)
+            .map_err(|_| out_of_range());
+    }
+    if digits.chars().all(|c| c.is_ascii_digit()) {
+        return n.parse().map(ast::Consts::Integer).map_err(|_| out_of_range());
+    }
+    Ok(ast::Consts::Float(n.parse()?))
+}
+
+/// Returns whether a function name is an aggregate function
+fn is_aggregate(name: &str) -> bool {
+    matches!(name.to_uppercase().as_ref(), "COUNT" | "SUM" | "MIN" | "MAX" | "AVG")
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{error::Result, sql::parser::ast::{self, Consts, Expression, OrderDirection}};
+    use std::collections::BTreeMap;
+
+    use crate::{error::{Error, Result}, sql::parser::ast::{self, Consts, Expression, OrderDirection}};
 
     use super::Parser;
 
@@ -524,6 +1038,190 @@ mod tests {
 
         let stmt3 = Parser::new(sql3).parse();
         assert!(stmt3.is_err());
+
+        let sql4 = "create table tbl1 (a int primary key) partition by hash (a) partitions 8;";
+        match Parser::new(sql4).parse()? {
+            ast::Statement::CreateTable { partition_by, .. } => assert_eq!(
+                partition_by,
+                Some(ast::PartitionBy::Hash { column: "a".into(), partitions: 8 })
+            ),
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("create table tbl1 (a int primary key) partition by hash (a) partitions 0;").parse().is_err());
+
+        let sql5 = "create table tbl1 (a int primary key) with (storage = 'columnar', ttl = -60, Audit = true);";
+        match Parser::new(sql5).parse()? {
+            ast::Statement::CreateTable { options, .. } => assert_eq!(
+                options,
+                BTreeMap::from([
+                    ("audit".into(), Consts::Boolean(true)),
+                    ("storage".into(), Consts::String("columnar".into())),
+                    ("ttl".into(), Consts::Integer(-60)),
+                ])
+            ),
+            _ => unreachable!(),
+        }
+        for sql in [
+            "create table tbl1 (a int primary key) with (audit = yes);",
+            "create table tbl1 (a int primary key) with (audit = true, audit = false);",
+            "create table tbl1 (a int primary key) with ();",
+        ] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_function() -> Result<()> {
+        let stmt = Parser::new("select a from tbl1 where within(b, point(0, 1.5), point(2, 3));").parse()?;
+        let point = |x: Consts, y: Consts| Expression::ScalarFunction("point".into(), vec![x.into(), y.into()]);
+        match stmt {
+            ast::Statement::Select { where_clause, .. } => assert_eq!(
+                where_clause,
+                Some(Expression::ScalarFunction(
+                    "within".into(),
+                    vec![
+                        Expression::Field("b".into()),
+                        point(Consts::Integer(0), Consts::Float(1.5)),
+                        point(Consts::Integer(2), Consts::Integer(3)),
+                    ]
+                ))
+            ),
+            _ => unreachable!(),
+        }
+
+        // EXTRACT(field FROM date) is a call with the field as a string
+        let stmt = Parser::new("select extract(YEAR from date_add(d, 1, 'day')) from tbl1;").parse()?;
+        let expect = Parser::new("select extract('year', date_add(d, 1, 'day')) from tbl1;").parse()?;
+        assert_eq!(stmt, expect);
+        assert!(Parser::new("select extract(year, d) from tbl1;").parse().is_err());
+
+        // Aggregates still take a single column
+        match Parser::new("select count(a) from tbl1;").parse()? {
+            ast::Statement::Select { select, .. } => {
+                assert_eq!(select, vec![(Expression::Function("count".into(), "a".into()), None)])
+            }
+            _ => unreachable!(),
+        }
+        match Parser::new("select COUNT(*) from tbl1;").parse()? {
+            ast::Statement::Select { select, .. } => {
+                assert_eq!(select, vec![(Expression::Function("count".into(), "*".into()), None)])
+            }
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("select sum(*) from tbl1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_arithmetic() -> Result<()> {
+        let stmt = Parser::new("update tbl1 set a = a - 1 + b * 2 / c;").parse()?;
+        let field = |name: &str| Box::new(Expression::Field(name.into()));
+        let int = |i| Box::new(Expression::Consts(Consts::Integer(i)));
+        let op = |o| Box::new(Expression::Operation(o));
+        match stmt {
+            ast::Statement::Update { columns, .. } => assert_eq!(
+                columns["a"],
+                Expression::Operation(ast::Operation::Add(
+                    op(ast::Operation::Subtract(field("a"), int(1))),
+                    op(ast::Operation::Divide(op(ast::Operation::Multiply(field("b"), int(2))), field("c"))),
+                ))
+            ),
+            _ => unreachable!(),
+        }
+
+        // Parentheses override precedence, and may hold a comparison
+        let stmt = Parser::new("update tbl1 set a = a - (b - 1) * 2, b = (a = 1);").parse()?;
+        match stmt {
+            ast::Statement::Update { columns, .. } => {
+                assert_eq!(
+                    columns["a"],
+                    Expression::Operation(ast::Operation::Subtract(
+                        field("a"),
+                        op(ast::Operation::Multiply(op(ast::Operation::Subtract(field("b"), int(1))), int(2))),
+                    ))
+                );
+                assert_eq!(columns["b"], Expression::Operation(ast::Operation::Equal(field("a"), int(1))));
+            }
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("update tbl1 set a = (a + 1;").parse().is_err());
+
+        // % binds like * and /, from the left
+        let stmt = Parser::new("update tbl1 set a = a + b % 3 * 2;").parse()?;
+        match stmt {
+            ast::Statement::Update { columns, .. } => assert_eq!(
+                columns["a"],
+                Expression::Operation(ast::Operation::Add(
+                    field("a"),
+                    op(ast::Operation::Multiply(op(ast::Operation::Modulo(field("b"), int(3))), int(2))),
+                ))
+            ),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_number() -> Result<()> {
+        let stmt = Parser::new("insert into tbl1 values (-5, 1e9, 0xFF, -0x10, -2.5e-1, +3, 4 - -1, -a);").parse()?;
+        let int = |i| Box::new(Expression::Consts(Consts::Integer(i)));
+        match stmt {
+            ast::Statement::Insert { values, .. } => assert_eq!(
+                values[0],
+                vec![
+                    Consts::Integer(-5).into(),
+                    Consts::Float(1e9).into(),
+                    Consts::Integer(255).into(),
+                    Consts::Integer(-16).into(),
+                    Consts::Float(-0.25).into(),
+                    Consts::Integer(3).into(),
+                    Expression::Operation(ast::Operation::Subtract(int(4), int(-1))),
+                    Expression::Operation(ast::Operation::Subtract(int(0), Box::new(Expression::Field("a".into())))),
+                ]
+            ),
+            _ => unreachable!(),
+        }
+        // i128::MIN only fits with its sign
+        assert!(Parser::new("insert into tbl1 values (-170141183460469231731687303715884105728);").parse().is_ok());
+        assert!(Parser::new("insert into tbl1 values (170141183460469231731687303715884105728);").parse().is_err());
+        assert!(Parser::new("insert into tbl1 values (0x);").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_error_position() -> Result<()> {
+        let mut parser = Parser::new("select a\nfrom tbl\nwhere a > 1 + 2);");
+        assert_eq!(
+            parser.parse(),
+            Err(Error::Parse("[Parser] Expected token ;, got ) at line 3, column 16".into()))
+        );
+        let span = parser.span().unwrap();
+        assert_eq!((span.start.offset, span.end.offset), (33, 34));
+
+        // Errors at the end of input point just past the last token
+        let mut parser = Parser::new("select a from ");
+        assert!(parser.parse().is_err());
+        assert_eq!(parser.span().map(|s| (s.start.line, s.start.column)), Some((1, 15)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_script() -> Result<()> {
+        let script = "select a from tbl;\nselect from tbl;\ninsert into tbl values (1);\nupdate tbl set # = 1;\nselect ;\ndelete from tbl;";
+        let errors = Parser::new(script).parse_script().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                Error::Parse("[Parser] Unexpected expression token FROM at line 2, column 8".into()),
+                Error::Parse("[Lexer] Unexpeted character # at line 4, column 16".into()),
+                Error::Parse("[Parser] Unexpected expression token ; at line 5, column 8".into()),
+            ]
+        );
+
+        let stmts = Parser::new("select a from tbl; delete from tbl;").parse_script().unwrap();
+        assert_eq!(stmts.len(), 2);
+        assert_eq!(Parser::new("  ").parse_script(), Ok(vec![]));
         Ok(())
     }
 
@@ -571,6 +1269,180 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parser_as_of() -> Result<()> {
+        let stmt = Parser::new("select * from t1 join t2 on a = b as of version 42 where a > 1;").parse()?;
+        assert_eq!(stmt.as_of(), Some(42));
+        assert_eq!(Parser::new("select * from t1;").parse()?.as_of(), None);
+        assert!(Parser::new("select * from t1 as of 42;").parse().is_err());
+        assert!(Parser::new("select * from t1 as of version x;").parse().is_err());
+        let stmt = Parser::new("show create table t1 as of version 7;").parse()?;
+        assert_eq!(stmt, ast::Statement::ShowCreateTable { name: "t1".into(), as_of: Some(7) });
+        assert_eq!(stmt.to_string(), "SHOW CREATE TABLE t1 AS OF VERSION 7");
+        assert_eq!(Parser::new("SHOW CREATE TABLE t1;").parse()?.as_of(), None);
+        assert!(Parser::new("show create t1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_copy() -> Result<()> {
+        assert_eq!(
+            Parser::new("copy t1 to 'out.parquet';").parse()?,
+            ast::Statement::Copy {
+                table_name: "t1".into(),
+                direction: ast::CopyDirection::To,
+                path: "out.parquet".into(),
+            }
+        );
+        match Parser::new("copy t1 from 'in.parquet';").parse()? {
+            ast::Statement::Copy { direction, .. } => assert_eq!(direction, ast::CopyDirection::From),
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("copy t1 into 'out.parquet';").parse().is_err());
+        assert!(Parser::new("copy t1 to out;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_load() -> Result<()> {
+        let stmt = Parser::new("LOAD DATA 'in.parquet' INTO t1;").parse()?;
+        assert_eq!(stmt, ast::Statement::LoadData { table_name: "t1".into(), path: "in.parquet".into() });
+        assert_eq!(stmt.to_string(), "LOAD DATA 'in.parquet' INTO t1");
+        for sql in ["load 'in.parquet' into t1;", "load data in.parquet into t1;", "load data 'in.parquet' t1;"] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_create_index() -> Result<()> {
+        let stmt = Parser::new("create index idx_ab on t1 (a, b);").parse()?;
+        assert_eq!(
+            stmt,
+            ast::Statement::CreateIndex {
+                name: Some("idx_ab".into()),
+                table_name: "t1".into(),
+                columns: vec!["a".into(), "b".into()],
+                predicate: None,
+            }
+        );
+        assert_eq!(stmt.to_string(), "CREATE INDEX idx_ab ON t1 (a, b)");
+        let stmt = Parser::new("create index on t1(a) where active = true;").parse()?;
+        assert_eq!(stmt.to_string(), "CREATE INDEX ON t1 (a) WHERE active = TRUE");
+        for sql in ["create index i on t1 (a) where;", "create index i t1 (a);", "create index i on t1 ();", "create index i on t1 a;"] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_drop_table() -> Result<()> {
+        let stmt = Parser::new("drop table t1;").parse()?;
+        assert_eq!(stmt, ast::Statement::DropTable { name: "t1".into(), cascade: false });
+        let stmt = Parser::new("DROP TABLE t1 CASCADE;").parse()?;
+        assert_eq!(stmt.to_string(), "DROP TABLE t1 CASCADE");
+        for sql in ["drop t1;", "drop table;", "drop table t1 restrict;"] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_hints() -> Result<()> {
+        let stmt = Parser::new("select /*+ INDEX(t1 idx_a) no_index(t2) */ a from t1 /* join */ join t2 on a = b;").parse()?;
+        match &stmt {
+            ast::Statement::Select { hints, .. } => assert_eq!(
+                hints,
+                &vec![
+                    ast::Hint::Index { table: "t1".into(), index: "idx_a".into() },
+                    ast::Hint::NoIndex { table: Some("t2".into()) },
+                ]
+            ),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        assert_eq!(stmt.to_string(), "SELECT /*+ INDEX(t1 idx_a) NO_INDEX(t2) */ a FROM t1 JOIN t2 ON a = b");
+        for sql in ["select /*+ index(t1) */ a from t1;", "select /*+ seqscan */ a from t1;", "select a /*+ no_index */ from t1;"] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_explain() -> Result<()> {
+        for (sql, analyze) in [("explain select * from t1;", false), ("EXPLAIN ANALYZE delete from t1;", true)] {
+            match Parser::new(sql).parse()? {
+                ast::Statement::Explain { statement, analyze: a, .. } => {
+                    assert_eq!(a, analyze);
+                    assert!(matches!(*statement, ast::Statement::Select { .. } | ast::Statement::Delete { .. }));
+                }
+                stmt => panic!("unexpected statement {:?}", stmt),
+            }
+        }
+        let stmt = Parser::new("explain analyze select a from t1 where a > 1;").parse()?;
+        assert_eq!(stmt.to_string(), "EXPLAIN ANALYZE SELECT a FROM t1 WHERE a > 1");
+        let stmt = Parser::new("explain analyze format = json select a from t1;").parse()?;
+        assert!(matches!(stmt, ast::Statement::Explain { analyze: true, format: ast::ExplainFormat::Json, .. }));
+        assert_eq!(stmt.to_string(), "EXPLAIN ANALYZE FORMAT = JSON SELECT a FROM t1");
+        let stmt = Parser::new("explain format = TREE select format from t1;").parse()?;
+        assert_eq!(stmt.to_string(), "EXPLAIN SELECT format FROM t1");
+        for sql in [
+            "explain;",
+            "explain begin;",
+            "explain explain select * from t1;",
+            "explain analyze;",
+            "explain format = xml select * from t1;",
+            "explain format json select * from t1;",
+        ] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_materialized_view() -> Result<()> {
+        let stmt = Parser::new("CREATE MATERIALIZED VIEW v AS SELECT b, count(*) AS n FROM t1 GROUP BY b;").parse()?;
+        let ast::Statement::CreateMaterializedView { name, query } = &stmt else {
+            panic!("expected CREATE MATERIALIZED VIEW, got {:?}", stmt);
+        };
+        assert_eq!(name, "v");
+        assert!(matches!(**query, ast::Statement::Select { .. }));
+        assert_eq!(stmt.to_string(), "CREATE MATERIALIZED VIEW v AS SELECT b, count(*) AS n FROM t1 GROUP BY b");
+        let stmt = Parser::new("refresh materialized view v;").parse()?;
+        assert_eq!(stmt, ast::Statement::RefreshMaterializedView { name: "v".into() });
+        // Both words stay usable as column names
+        Parser::new("select refresh, materialized from t1;").parse()?;
+        for sql in [
+            "create materialized view v;",
+            "create materialized view v as insert into t1 values (1);",
+            "create view v as select * from t1;",
+            "refresh view v;",
+            "refresh materialized view;",
+        ] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_transaction() -> Result<()> {
+        for (sql, stmt) in [
+            ("begin;", ast::Statement::Begin),
+            ("BEGIN TRANSACTION;", ast::Statement::Begin),
+            ("commit;", ast::Statement::Commit),
+            ("rollback transaction;", ast::Statement::Rollback),
+            ("set autocommit = off;", ast::Statement::Set { name: "autocommit".into(), value: "off".into() }),
+            ("SET AUTOCOMMIT = ON;", ast::Statement::Set { name: "autocommit".into(), value: "on".into() }),
+            ("set autocommit = 0;", ast::Statement::Set { name: "autocommit".into(), value: "0".into() }),
+            ("SHOW STATS;", ast::Statement::ShowStats),
+        ] {
+            assert_eq!(Parser::new(sql).parse()?, stmt, "{}", sql);
+        }
+        for sql in ["begin work;", "set autocommit;", "set autocommit = (1);", "set = 1;", "show tables;", "show;"] {
+            assert!(Parser::new(sql).parse().is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_parser_select() -> Result<()> {
         let sql = "select * from tbl1 where a = 100 limit 10 offset 20;";
@@ -579,9 +1451,11 @@ mod tests {
             stmt,
             ast::Statement::Select {
                 select: vec![],
+                hints: vec![],
                 from: ast::FromItem::Table {
                     name: "tbl1".into()
                 },
+                as_of: None,
                 where_clause: Some(ast::Expression::Operation(ast::Operation::Equal(
                     Box::new(ast::Expression::Field("a".into())),
                     Box::new(ast::Expression::Consts(Consts::Integer(100)))
@@ -591,6 +1465,7 @@ mod tests {
                 order_by: vec![],
                 limit: Some(Expression::Consts(Consts::Integer(10))),
                 offset: Some(Expression::Consts(Consts::Integer(20))),
+                for_update: false,
             }
         );
 
@@ -600,10 +1475,12 @@ mod tests {
             stmt,
             ast::Statement::Select {
                 select: vec![],
+                hints: vec![],
                 from: ast::FromItem::Table {
                     name: "tbl1".into()
                 },
                 group_by: None,
+                as_of: None,
                 where_clause: None,
                 having: None,
                 order_by: vec![
@@ -613,6 +1490,7 @@ mod tests {
                 ],
                 limit: None,
                 offset: None,
+                for_update: false,
             }
         );
 
@@ -621,6 +1499,7 @@ mod tests {
         assert_eq!(
             stmt,
             ast::Statement::Select {
+                hints: vec![],
                 select: vec![
                     (Expression::Field("a".into()), Some("col1".into())),
                     (Expression::Field("b".into()), Some("col2".into())),
@@ -635,10 +1514,12 @@ mod tests {
                     ("b".to_string(), OrderDirection::Asc),
                     ("c".to_string(), OrderDirection::Desc),
                 ],
+                as_of: None,
                 where_clause: None,
                 having: None,
                 limit: None,
                 offset: None,
+                for_update: false,
             }
         );
 
@@ -648,6 +1529,7 @@ mod tests {
             stmt,
             ast::Statement::Select {
                 select: vec![],
+                hints: vec![],
                 from: ast::FromItem::Join {
                     left: Box::new(ast::FromItem::Join {
                         left: Box::new(ast::FromItem::Table {
@@ -666,11 +1548,13 @@ mod tests {
                     predicate: None
                 },
                 having: None,
+                as_of: None,
                 where_clause: None,
                 group_by: None,
                 order_by: vec![],
                 limit: None,
                 offset: None,
+                for_update: false,
             }
         );
 
@@ -679,6 +1563,7 @@ mod tests {
         assert_eq!(
             stmt,
             ast::Statement::Select {
+                hints: vec![],
                 select: vec![
                     (ast::Expression::Function("count".into(), "a".into()), None),
                     (ast::Expression::Function("min".into(), "b".into()), None),
@@ -687,6 +1572,7 @@ mod tests {
                 from: ast::FromItem::Table {
                     name: "tbl1".into()
                 },
+                as_of: None,
                 where_clause: None,
                 group_by: Some(ast::Expression::Field("a".into())),
                 having: Some(ast::Expression::Operation(ast::Operation::Equal(
@@ -696,9 +1582,35 @@ mod tests {
                 order_by: vec![],
                 limit: None,
                 offset: None,
+                for_update: false,
             }
         );
 
+        // FOR UPDATE ends the statement
+        match Parser::new("select * from tbl1 where a = 1 limit 1 for update;").parse()? {
+            ast::Statement::Select { for_update, .. } => assert!(for_update),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        assert!(Parser::new("select * from tbl1 for;").parse().is_err());
+        assert!(Parser::new("select * from tbl1 for update limit 1;").parse().is_err());
+
+        // A comparison is an output column, and prints back without parentheses
+        let stmt = Parser::new("select a > 5 as big, b from tbl1;").parse()?;
+        match &stmt {
+            ast::Statement::Select { select, .. } => assert_eq!(
+                select[0],
+                (
+                    Expression::Operation(ast::Operation::GreaterThan(
+                        Box::new(Expression::Field("a".into())),
+                        Box::new(Expression::Consts(Consts::Integer(5)))
+                    )),
+                    Some("big".into())
+                )
+            ),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        assert_eq!(stmt.to_string(), "SELECT a > 5 AS big, b FROM tbl1");
+
         Ok(())
     }
 
@@ -725,4 +1637,41 @@ mod tests {
 
         Ok(())
     }
+    #[test]
+    fn test_to_sql() -> Result<()> {
+        let sql = "select  a, count(b) as n from t1 left join t2 on a = x where ((a + 1) * 2) > 3 \
+                   group by a order by a desc limit 10;";
+        assert_eq!(
+            Parser::new(sql).parse()?.to_sql(),
+            "SELECT a, count(b) AS n FROM t1 LEFT JOIN t2 ON a = x WHERE (a + 1) * 2 > 3 GROUP BY a \
+             ORDER BY a DESC LIMIT 10;"
+        );
+
+        // Every kind of statement parses back from its text, which is canonical
+        for sql in [
+            "create table t1 (a int primary key, b text not null default 'x' collate nocase) \
+             partition by hash (a) partitions 2 with (audit = true);",
+            "create index on t1 (b) where b = 'y';",
+            "drop table t1 cascade;",
+            "insert into t1 (a, b) values (1, 'it''s'), (-2, null);",
+            "select /*+ no_index */ * from t1 as of version 3 where a > 1 - -1 offset 2 for update;",
+            "update t1 set b = 'z', a = a % 3 where not_a_keyword = 1.5;",
+            "delete from t1;",
+            "copy t1 to 'out.csv';",
+            "load data 'in.parquet' into t1;",
+            "begin;",
+            "commit;",
+            "rollback;",
+            "set autocommit = off;",
+            "show stats;",
+            "show create table t1;",
+            "explain analyze format = dot select * from t1;",
+        ] {
+            let stmt = Parser::new(sql).parse()?;
+            let canonical = stmt.to_sql();
+            assert_eq!(Parser::new(&canonical).parse()?, stmt, "{}", sql);
+            assert_eq!(Parser::new(&canonical).parse()?.to_sql(), canonical);
+        }
+        Ok(())
+    }
 }
@@ -2,20 +2,32 @@
 //!
 //! Converts AST statements into executable plan nodes.
 //! Each node represents an operation (CREATE TABLE, INSERT, SELECT, UPDATE, DELETE).
+//! Nodes producing rows carry their output columns (names and types), so
+//! executors don't re-derive them.
 
 use std::collections::BTreeMap;
 
-use crate::{error::Result, sql::{engine::Transaction, executor::{Executor, ResultSet}, parser::ast::{self, Expression, OrderDirection}, plan::planner::Planner, schema::Table}};
+use crate::{error::Result, sql::{analyzer::{BoundStatement, Scope, ScopeColumn}, engine::Transaction, executor::{Executor, MemoryBudget, ResultSet}, parser::ast::{self, ExplainFormat, Expression, OrderDirection}, plan::planner::Planner, schema::{Index, Table}, types::Value}};
 
 mod planner;
 
 /// Execution plan node types
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node {
     /// CREATE TABLE execution node
     CreateTable {
         schema: Table,
     },
+    /// CREATE INDEX execution node
+    CreateIndex {
+        table_name: String,
+        index: Index,
+    },
+    /// DROP TABLE execution node
+    DropTable {
+        table_name: String,
+        cascade: bool,
+    },
     /// INSERT execution node
     Insert {
         table_name: String,
@@ -27,6 +39,62 @@ pub enum Node {
         table_name: String,
         /// Optional WHERE clause filter
         filter: Option<Expression>,
+        /// Columns the query reads, None for all; columnar tables skip the rest
+        /// and return them as NULL
+        columns: Option<Vec<String>>,
+        /// Rows to stop reading after, pushed down from LIMIT; None for all
+        limit: Option<usize>,
+        /// Whether to read in descending primary key order, for ORDER BY pk DESC
+        reverse: bool,
+        output: Scope,
+    },
+
+    /// Primary key lookup execution node
+    ///
+    /// Replaces a Scan whose filter is an equality on the primary key,
+    /// reading the single row directly instead of scanning the table.
+    Get {
+        table_name: String,
+        key: Value,
+        output: Scope,
+    },
+
+    /// Index scan execution node
+    ///
+    /// Replaces a filtered Scan whose columns an index covers, or one an
+    /// INDEX hint names. Without `fetch` the rows come from the index entries
+    /// alone, with the columns the index lacks NULL.
+    IndexScan {
+        table_name: String,
+        index: String,
+        /// Value of the index's first column to read the entries of, from an
+        /// equality in the filter; None reads the whole index
+        value: Option<Value>,
+        filter: Option<Expression>,
+        /// Whether each entry's row is read too, for columns the index lacks
+        fetch: bool,
+        /// Rows to stop reading after, pushed down from LIMIT; None for all
+        limit: Option<usize>,
+        output: Scope,
+    },
+
+    /// Row count execution node
+    ///
+    /// Replaces an aggregate of just COUNT(*) over an unfiltered Scan,
+    /// answering from the table's row count instead of reading its rows.
+    RowCount {
+        table_name: String,
+        output: Scope,
+    },
+
+    /// Row lock execution node, for SELECT ... FOR UPDATE
+    ///
+    /// Sits on a table's Scan or Get and locks each row it returns until the
+    /// transaction ends, passing the rows on unchanged.
+    Lock {
+        source: Box<Node>,
+        table_name: String,
+        output: Scope,
     },
 
     /// UPDATE execution node
@@ -47,24 +115,41 @@ pub enum Node {
     Order {
         source: Box<Node>,
         order_by: Vec<(String, OrderDirection)>,
+        /// Source tables, used to resolve column collations
+        tables: Vec<String>,
+        output: Scope,
+    },
+
+    /// ORDER BY under a LIMIT, keeping only the first `limit` rows
+    TopN {
+        source: Box<Node>,
+        order_by: Vec<(String, OrderDirection)>,
+        /// Source tables, used to resolve column collations
+        tables: Vec<String>,
+        /// Rows to keep, those OFFSET skips included
+        limit: usize,
+        output: Scope,
     },
 
     /// LIMIT execution node
     Limit {
         source: Box<Node>,
         limit: usize,
+        output: Scope,
     },
 
     /// OFFSET execution node
     Offset {
         source: Box<Node>,
         offset: usize,
+        output: Scope,
     },
 
     /// Projection execution node (column selection)
     Projection {
         source: Box<Node>,
         exprs: Vec<(Expression, Option<String>)>,
+        output: Scope,
     },
 
     /// Nested Loop Join execution node
@@ -78,6 +163,30 @@ pub enum Node {
         predicate: Option<Expression>,
         /// true for LEFT/RIGHT JOIN, false for INNER/CROSS JOIN
         outer: bool,
+        /// Rows to stop joining after, pushed down from LIMIT; None for all
+        limit: Option<usize>,
+        /// Left columns followed by right columns
+        output: Scope,
+    },
+
+    /// Hash Join execution node, for an equality between a left and a right column
+    ///
+    /// Builds a hash table over the right rows and probes it with each left row.
+    /// Time complexity: O(n + m). Chosen only for key types whose equality
+    /// matches their hashing (not floats, which mix NaN and -0.0).
+    HashJoin {
+        left: Box<Node>,
+        right: Box<Node>,
+        /// Join key column of the left rows
+        left_key: String,
+        /// Join key column of the right rows
+        right_key: String,
+        /// true for LEFT/RIGHT JOIN, false for INNER JOIN
+        outer: bool,
+        /// Rows to stop joining after, pushed down from LIMIT; None for all
+        limit: Option<usize>,
+        /// Left columns followed by right columns
+        output: Scope,
     },
 
     /// Aggregate execution node (COUNT, SUM, MIN, MAX, AVG)
@@ -87,25 +196,251 @@ pub enum Node {
         exprs: Vec<(Expression, Option<String>)>,
         /// GROUP BY expression (group key)
         group_by: Option<Expression>,
+        /// Source tables, used to resolve column collations
+        tables: Vec<String>,
+        output: Scope,
+    },
+
+    /// COPY execution node, exporting or importing a whole table
+    Copy {
+        table_name: String,
+        direction: ast::CopyDirection,
+        path: String,
+    },
+
+    /// LOAD DATA execution node, bulk loading a file into a table
+    LoadData {
+        table_name: String,
+        path: String,
+    },
+
+    /// CREATE MATERIALIZED VIEW execution node, creating the view's table
+    /// and storing the query's rows in it
+    CreateMaterializedView {
+        schema: Table,
+        query: Box<Node>,
+    },
+
+    /// REFRESH MATERIALIZED VIEW execution node
+    RefreshMaterializedView {
+        table_name: String,
+    },
+
+    /// SHOW CREATE TABLE execution node, returning the table's name and DDL
+    /// (see `Table::to_ddl`)
+    ShowCreateTable {
+        table_name: String,
+        output: Scope,
     },
 
     /// Filter execution node for HAVING clause
     Filter {
         source: Box<Node>,
         predicate: Expression,
+        output: Scope,
+    },
+
+    /// EXPLAIN execution node, returning the source's plan tree as text in
+    /// the format; with `analyze` it runs the source, adding row counts and times
+    Explain {
+        source: Box<Node>,
+        analyze: bool,
+        format: ExplainFormat,
+        output: Scope,
     },
 }
 
+impl Node {
+    /// Name of the node type, e.g. for tracing spans
+    pub fn name(&self) -> &'static str {
+        match self {
+            Node::CreateTable { .. } => "CreateTable",
+            Node::CreateIndex { .. } => "CreateIndex",
+            Node::DropTable { .. } => "DropTable",
+            Node::Insert { .. } => "Insert",
+            Node::Scan { .. } => "Scan",
+            Node::IndexScan { .. } => "IndexScan",
+            Node::Get { .. } => "Get",
+            Node::RowCount { .. } => "RowCount",
+            Node::Lock { .. } => "Lock",
+            Node::Update { .. } => "Update",
+            Node::Delete { .. } => "Delete",
+            Node::Order { .. } => "Order",
+            Node::TopN { .. } => "TopN",
+            Node::Limit { .. } => "Limit",
+            Node::Offset { .. } => "Offset",
+            Node::Projection { .. } => "Projection",
+            Node::NestedLoopJoin { .. } => "NestedLoopJoin",
+            Node::HashJoin { .. } => "HashJoin",
+            Node::Aggregate { .. } => "Aggregate",
+            Node::Copy { .. } => "Copy",
+            Node::LoadData { .. } => "LoadData",
+            Node::CreateMaterializedView { .. } => "CreateMaterializedView",
+            Node::RefreshMaterializedView { .. } => "RefreshMaterializedView",
+            Node::ShowCreateTable { .. } => "ShowCreateTable",
+            Node::Filter { .. } => "Filter",
+            Node::Explain { .. } => "Explain",
+        }
+    }
+
+    /// Input nodes, in the order their executors are built
+    pub fn children(&self) -> Vec<&Node> {
+        match self {
+            Node::Lock { source, .. }
+            | Node::Update { source, .. }
+            | Node::Delete { source, .. }
+            | Node::Order { source, .. }
+            | Node::TopN { source, .. }
+            | Node::Limit { source, .. }
+            | Node::Offset { source, .. }
+            | Node::Projection { source, .. }
+            | Node::Aggregate { source, .. }
+            | Node::Filter { source, .. }
+            | Node::Explain { source, .. } => vec![source],
+            Node::CreateMaterializedView { query, .. } => vec![query],
+            Node::NestedLoopJoin { left, right, .. } | Node::HashJoin { left, right, .. } => vec![left, right],
+            Node::CreateTable { .. }
+            | Node::CreateIndex { .. }
+            | Node::DropTable { .. }
+            | Node::Insert { .. }
+            | Node::Scan { .. }
+            | Node::IndexScan { .. }
+            | Node::Get { .. }
+            | Node::RowCount { .. }
+            | Node::Copy { .. }
+            | Node::LoadData { .. }
+            | Node::RefreshMaterializedView { .. }
+            | Node::ShowCreateTable { .. } => vec![],
+        }
+    }
+
+    /// One-line description of the node, as EXPLAIN shows it, e.g.
+    /// `Scan t1 (filter: a > 1, limit: 10)`
+    pub fn describe(&self) -> String {
+        let order = |order_by: &[(String, OrderDirection)]| {
+            let columns = order_by.iter().map(|(col, direction)| match direction {
+                OrderDirection::Asc => col.clone(),
+                OrderDirection::Desc => format!("{} DESC", col),
+            });
+            format!("order: {}", columns.collect::<Vec<_>>().join(", "))
+        };
+        let exprs = |exprs: &[(Expression, Option<String>)]| {
+            let exprs = exprs.iter().map(|(expr, alias)| match alias {
+                Some(alias) => format!("{} AS {}", expr, alias),
+                None => expr.to_string(),
+            });
+            exprs.collect::<Vec<_>>().join(", ")
+        };
+        let (target, details): (Option<&str>, Vec<String>) = match self {
+            Node::CreateTable { schema } | Node::CreateMaterializedView { schema, .. } => (Some(&schema.name), vec![]),
+            Node::Insert { table_name, values, .. } => (Some(table_name), vec![format!("values: {}", values.len())]),
+            Node::Scan { table_name, filter, limit, reverse, .. } => {
+                let mut details = filter.iter().map(|expr| format!("filter: {}", expr)).collect::<Vec<_>>();
+                details.extend(limit.map(|limit| format!("limit: {}", limit)));
+                if *reverse {
+                    details.push("reverse".into());
+                }
+                (Some(table_name), details)
+            }
+            Node::CreateIndex { table_name, index } => (Some(table_name), vec![format!("index: {}", index.name)]),
+            Node::DropTable { table_name, cascade } => (Some(table_name), cascade.then(|| "cascade".to_string()).into_iter().collect()),
+            Node::IndexScan { table_name, index, value, filter, fetch, limit, .. } => {
+                let mut details = vec![format!("index: {}", index)];
+                details.extend(value.iter().map(|value| format!("value: {}", value)));
+                details.extend(filter.iter().map(|expr| format!("filter: {}", expr)));
+                details.extend(fetch.then(|| "fetch".to_string()));
+                details.extend(limit.map(|limit| format!("limit: {}", limit)));
+                (Some(table_name), details)
+            }
+            Node::Get { table_name, key, .. } => (Some(table_name), vec![format!("key: {}", key)]),
+            Node::RowCount { table_name, .. }
+            | Node::RefreshMaterializedView { table_name }
+            | Node::ShowCreateTable { table_name, .. }
+            | Node::Lock { table_name, .. }
+            | Node::Update { table_name, .. }
+            | Node::Delete { table_name, .. } => (Some(table_name), vec![]),
+            Node::Copy { table_name, path, .. } | Node::LoadData { table_name, path } => {
+                (Some(table_name), vec![format!("path: {}", path)])
+            }
+            Node::Order { order_by, .. } => (None, vec![order(order_by)]),
+            Node::TopN { order_by, limit, .. } => (None, vec![order(order_by), format!("limit: {}", limit)]),
+            Node::Limit { limit, .. } => (None, vec![format!("limit: {}", limit)]),
+            Node::Offset { offset, .. } => (None, vec![format!("offset: {}", offset)]),
+            Node::Projection { exprs: select, .. } => (None, vec![exprs(select)]),
+            Node::NestedLoopJoin { predicate, outer, limit, .. } => {
+                let mut details = predicate.iter().map(|expr| format!("on: {}", expr)).collect::<Vec<_>>();
+                details.extend(outer.then(|| "outer".to_string()));
+                details.extend(limit.map(|limit| format!("limit: {}", limit)));
+                (None, details)
+            }
+            Node::HashJoin { left_key, right_key, outer, limit, .. } => {
+                let mut details = vec![format!("on: {} = {}", left_key, right_key)];
+                details.extend(outer.then(|| "outer".to_string()));
+                details.extend(limit.map(|limit| format!("limit: {}", limit)));
+                (None, details)
+            }
+            Node::Aggregate { exprs: select, group_by, .. } => {
+                let mut details = vec![exprs(select)];
+                details.extend(group_by.iter().map(|expr| format!("group by: {}", expr)));
+                (None, details)
+            }
+            Node::Filter { predicate, .. } => (None, vec![format!("predicate: {}", predicate)]),
+            Node::Explain { analyze, .. } => (None, analyze.then(|| "analyze".to_string()).into_iter().collect()),
+        };
+        let mut label = self.name().to_string();
+        if let Some(target) = target {
+            label = format!("{} {}", label, target);
+        }
+        if !details.is_empty() {
+            label = format!("{} ({})", label, details.join(", "));
+        }
+        label
+    }
+
+    /// Output columns of the node (empty for DDL and DML nodes, which return no rows)
+    pub fn output(&self) -> &[ScopeColumn] {
+        match self {
+            Node::Scan { output, .. }
+            | Node::IndexScan { output, .. }
+            | Node::Get { output, .. }
+            | Node::RowCount { output, .. }
+            | Node::Lock { output, .. }
+            | Node::Order { output, .. }
+            | Node::TopN { output, .. }
+            | Node::Limit { output, .. }
+            | Node::Offset { output, .. }
+            | Node::Projection { output, .. }
+            | Node::NestedLoopJoin { output, .. }
+            | Node::HashJoin { output, .. }
+            | Node::Aggregate { output, .. }
+            | Node::Filter { output, .. }
+            | Node::ShowCreateTable { output, .. }
+            | Node::Explain { output, .. } => &output.columns,
+            Node::CreateTable { .. }
+            | Node::CreateIndex { .. }
+            | Node::DropTable { .. }
+            | Node::Insert { .. }
+            | Node::Update { .. }
+            | Node::Delete { .. }
+            | Node::Copy { .. }
+            | Node::LoadData { .. }
+            | Node::CreateMaterializedView { .. }
+            | Node::RefreshMaterializedView { .. } => &[],
+        }
+    }
+}
+
 /// Execution plan wrapper
 ///
 /// Wraps a plan node tree for execution. Built from an AST statement
 /// and executed against a transaction.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Plan(pub Node);
 
 impl Plan {
-    /// Builds an execution plan from an AST statement
-    pub fn build(stmt: ast::Statement) -> Result<Self> {
+    /// Builds an execution plan from a bound statement (see `analyzer`)
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "plan", level = "debug", skip_all, err(level = "debug")))]
+    pub fn build(stmt: BoundStatement) -> Result<Self> {
         Planner::new().build(stmt)
     }
 
@@ -114,7 +449,16 @@ impl Plan {
     /// The transaction must have `'static` lifetime bound for
     /// recursive executor building.
     pub fn execute<T: Transaction + 'static>(self, txn: &mut T) -> Result<ResultSet> {
-        <dyn Executor<T>>::build(self.0).execute(txn)
+        self.execute_with_budget(txn, &MemoryBudget::default())
+    }
+
+    /// Executes the plan, failing if buffered rows exceed the memory budget
+    pub fn execute_with_budget<T: Transaction + 'static>(
+        self,
+        txn: &mut T,
+        budget: &MemoryBudget,
+    ) -> Result<ResultSet> {
+        <dyn Executor<T>>::build(self.0, budget).execute(txn)
     }
 }
 
@@ -127,8 +471,12 @@ mod tests {
                 ast::{self, Expression},
                 Parser,
             },
+            analyzer::{Analyzer, Scope},
+            engine::{kv::KVEngine, Engine},
             plan::{Node, Plan},
+            types::{DataType, Value},
         },
+        storage::memory::MemoryEngine,
     };
 
     #[test]
@@ -142,7 +490,7 @@ mod tests {
         );
         ";
         let stmt1 = Parser::new(sql1).parse()?;
-        let p1 = Plan::build(stmt1);
+        let p1 = Plan::build(stmt1.into());
 
         let sql2 = "
         create            table tbl1 (
@@ -153,7 +501,7 @@ mod tests {
         );
         ";
         let stmt2 = Parser::new(sql2).parse()?;
-        let p2 = Plan::build(stmt2);
+        let p2 = Plan::build(stmt2.into());
         assert_eq!(p1, p2);
 
         Ok(())
@@ -163,7 +511,7 @@ mod tests {
     fn test_plan_insert() -> Result<()> {
         let sql1 = "insert into tbl1 values (1, 2, 3, 'a', true);";
         let stmt1 = Parser::new(sql1).parse()?;
-        let p1 = Plan::build(stmt1)?;
+        let p1 = Plan::build(stmt1.into())?;
         assert_eq!(
             p1,
             Plan(Node::Insert {
@@ -181,7 +529,7 @@ mod tests {
 
         let sql2 = "insert into tbl2 (c1, c2, c3) values (3, 'a', true),(4, 'b', false);";
         let stmt2 = Parser::new(sql2).parse()?;
-        let p2 = Plan::build(stmt2)?;
+        let p2 = Plan::build(stmt2.into())?;
         assert_eq!(
             p2,
             Plan(Node::Insert {
@@ -209,15 +557,189 @@ mod tests {
     fn test_plan_select() -> Result<()> {
         let sql = "select * from tbl1;";
         let stmt = Parser::new(sql).parse()?;
-        let p = Plan::build(stmt)?;
+        let p = Plan::build(stmt.into())?;
         assert_eq!(
             p,
             Plan(Node::Scan {
                 table_name: "tbl1".to_string(),
                 filter: None,
+                columns: None,
+                limit: None,
+                reverse: false,
+                output: Scope::default(),
             })
         );
 
         Ok(())
     }
+
+    #[test]
+    fn test_plan_output() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key, b text, c float);")?;
+        kvengine.session()?.execute("create table t2 (d bigint primary key, e uuid);")?;
+        let txn = kvengine.begin()?;
+        let output = |sql: &str| -> Result<Vec<(String, Option<DataType>)>> {
+            let stmt = Analyzer::new(&txn).analyze(Parser::new(sql).parse()?)?;
+            let plan = Plan::build(stmt)?;
+            Ok(plan.0.output().iter().map(|c| (c.name.clone(), c.datatype)).collect())
+        };
+
+        assert_eq!(
+            output("select b as name, a + c, a * 2, point(c, c) from t1 order by name;")?,
+            vec![
+                ("name".into(), Some(DataType::String)),
+                ("?column?".into(), Some(DataType::Float)),
+                ("?column?".into(), Some(DataType::Integer)),
+                ("point".into(), Some(DataType::Point)),
+            ]
+        );
+        assert_eq!(
+            output("select b, count(a), avg(c), max(b) as m from t1 group by b;")?,
+            vec![
+                ("b".into(), Some(DataType::String)),
+                ("count".into(), Some(DataType::Integer)),
+                ("avg".into(), Some(DataType::Float)),
+                ("m".into(), Some(DataType::String)),
+            ]
+        );
+        assert_eq!(
+            output("select * from t2 right join t1 on d = a limit 1;")?.len(),
+            5
+        );
+        assert_eq!(output("select * from t1 join t2 on a = d;")?[3], ("d".into(), Some(DataType::BigInt)));
+        assert!(output("delete from t1;")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_point_lookup() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key, b text);")?;
+        let txn = kvengine.begin()?;
+        let plan = |sql: &str| -> Result<Node> {
+            Ok(Plan::build(Analyzer::new(&txn).analyze(Parser::new(sql).parse()?)?)?.0)
+        };
+
+        match plan("select * from t1 where a = 1;")? {
+            Node::Get { table_name, key, .. } => assert_eq!((table_name.as_str(), key), ("t1", Value::Integer(1))),
+            node => panic!("unexpected plan {:?}", node),
+        }
+        match plan("delete from t1 where 2 = a;")? {
+            Node::Delete { source, .. } => assert!(matches!(*source, Node::Get { .. })),
+            node => panic!("unexpected plan {:?}", node),
+        }
+        // FOR UPDATE locks the looked up row
+        match plan("select * from t1 where a = 1 for update;")? {
+            Node::Lock { source, table_name, .. } => {
+                assert_eq!(table_name, "t1");
+                assert!(matches!(*source, Node::Get { .. }));
+            }
+            node => panic!("unexpected plan {:?}", node),
+        }
+        // Non-key columns, other operators and mismatched types still scan
+        for sql in ["select * from t1 where b = 'x';", "select * from t1 where a > 1;", "select * from t1 where a = 1.0;"] {
+            assert!(matches!(plan(sql)?, Node::Scan { .. }), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_row_count() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key, b text);")?;
+        let txn = kvengine.begin()?;
+        let plan = |sql: &str| -> Result<String> {
+            Ok(format!("{:?}", Plan::build(Analyzer::new(&txn).analyze(Parser::new(sql).parse()?)?)?.0))
+        };
+
+        for sql in ["select count(*) from t1;", "select count(*) as n from t1 limit 1;"] {
+            assert!(plan(sql)?.contains("RowCount"), "{}", sql);
+        }
+        // Filters, groups, other aggregates and column counts read the rows
+        for sql in [
+            "select count(*) from t1 where b = 'x';",
+            "select count(*) from t1 where a = 1;",
+            "select b, count(*) from t1 group by b;",
+            "select count(*), count(b) from t1;",
+            "select count(a) from t1;",
+        ] {
+            assert!(!plan(sql)?.contains("RowCount"), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_limit_pushdown() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key, b text);")?;
+        kvengine.session()?.execute("create table t2 (c int primary key);")?;
+        let txn = kvengine.begin()?;
+        let plan = |sql: &str| -> Result<String> {
+            Ok(format!("{:?}", Plan::build(Analyzer::new(&txn).analyze(Parser::new(sql).parse()?)?)?.0))
+        };
+
+        // Scans read LIMIT rows, plus the ones OFFSET skips, filters applied
+        for (sql, limit) in [
+            ("select * from t1 limit 2;", 2),
+            ("select b from t1 where b = 'x' limit 2;", 2),
+            ("select * from t1 limit 2 offset 3;", 5),
+        ] {
+            assert!(plan(sql)?.contains("Scan { table_name: \"t1\", filter: "), "{}", sql);
+            assert_eq!(plan(sql)?.matches(&format!("limit: Some({})", limit)).count(), 1, "{}", sql);
+        }
+        // Left and cross joins also limit their left input, inner joins only themselves
+        assert_eq!(plan("select * from t1 left join t2 on a = c limit 2;")?.matches("limit: Some(2)").count(), 2);
+        assert_eq!(plan("select * from t1 cross join t2 limit 2;")?.matches("limit: Some(2)").count(), 2);
+        assert_eq!(plan("select * from t1 join t2 on a = c limit 2;")?.matches("limit: Some(2)").count(), 1);
+        // Sorts and aggregates need every row, though sorts only keep the first ones
+        for sql in ["select * from t1 order by b limit 2;", "select b, count(a) from t1 group by b limit 2;"] {
+            assert!(!plan(sql)?.contains("limit: Some"), "{}", sql);
+        }
+        let top = plan("select * from t1 order by b limit 2 offset 1;")?;
+        assert!(top.contains("TopN") && top.contains("limit: 3") && !top.contains("Order"), "{}", top);
+        assert!(plan("select * from t1 order by b;")?.contains("Order"));
+
+        // ORDER BY the primary key descending reads the table backwards, with
+        // the limit pushed into the scan
+        for sql in ["select * from t1 order by a desc limit 2;", "select b from t1 where b = 'x' order by a desc;"] {
+            let reversed = plan(sql)?;
+            assert!(reversed.contains("reverse: true") && !reversed.contains("Order"), "{}", reversed);
+        }
+        assert!(plan("select * from t1 order by a desc limit 2;")?.contains("limit: Some(2)"));
+        for sql in [
+            "select * from t1 order by b desc limit 2;",
+            "select * from t1 order by a desc, b limit 2;",
+            "select count(a) from t1 group by a order by a desc;",
+        ] {
+            assert!(!plan(sql)?.contains("reverse: true"), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_hash_join() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        kvengine.session()?.execute("create table t1 (a int primary key, b text, c float);")?;
+        kvengine.session()?.execute("create table t2 (d bigint primary key, e text, f float);")?;
+        let txn = kvengine.begin()?;
+        let plan = |sql: &str| -> Result<String> {
+            Ok(format!("{:?}", Plan::build(Analyzer::new(&txn).analyze(Parser::new(sql).parse()?)?)?.0))
+        };
+
+        // Column equalities of hashable types, integers of any width included
+        for sql in ["select * from t1 join t2 on a = d;", "select * from t1 left join t2 on b = e;", "select * from t2 right join t1 on d = a;"] {
+            assert!(plan(sql)?.contains("HashJoin"), "{}", sql);
+        }
+        // Expressions, floats, mixed types and cross joins keep the nested loop
+        for sql in [
+            "select * from t1 join t2 on a + 0 = d;",
+            "select * from t1 join t2 on c = f;",
+            "select * from t1 join t2 on a = f;",
+            "select * from t1 cross join t2;",
+        ] {
+            assert!(plan(sql)?.contains("NestedLoopJoin"), "{}", sql);
+        }
+        Ok(())
+    }
 }
@@ -1,44 +1,107 @@
-use crate::{error::{Error, Result}, sql::{parser::ast::{self, Expression}, plan::{Node, Plan}, schema::{self, Table}, types::Value}};
+use std::collections::BTreeMap;
+
+use crate::{error::{Error, Result}, sql::{analyzer::{self, BoundStatement, Scope, ScopeColumn}, engine::system, executor::view, functions, parser::ast::{self, Expression, evaluate_const_expr}, plan::{Node, Plan}, schema::{self, Index, Table}, types::{DataType, Value}}};
 
 /// Query planner - converts AST into execution plan nodes
-pub struct Planner;
+pub struct Planner {
+    /// Secondary indexes of the statement's tables, by table name
+    indexes: BTreeMap<String, Vec<Index>>,
+}
 
 impl Planner {
     pub fn new() -> Self {
-        Self {}
+        Self { indexes: BTreeMap::new() }
     }
 
-    /// Builds an execution plan from an AST statement
-    pub fn build(&mut self, stmt: ast::Statement) -> Result<Plan> {
-        Ok(Plan(self.build_statement(stmt)?))
+    /// Builds an execution plan from a bound statement
+    pub fn build(&mut self, stmt: BoundStatement) -> Result<Plan> {
+        self.indexes = stmt.indexes;
+        Ok(Plan(self.build_statement(stmt.statement, &stmt.scope)?))
     }
 
-    pub fn build_statement(&self, stmt: ast::Statement) -> Result<Node> {
+    /// Builds the node tree of a statement; `scope` holds its source columns
+    pub fn build_statement(&self, stmt: ast::Statement, scope: &Scope) -> Result<Node> {
         Ok(match stmt {
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable {
+            ast::Statement::CreateTable { name, columns, partition_by, options } => Node::CreateTable {
                 schema: Table {
+                    audit: options.get("audit") == Some(&ast::Consts::Boolean(true)),
+                    indexes: vec![],
+                    view: None,
+                    views: vec![],
+                    readers: vec![],
+                    storage: match options.get("storage") {
+                        Some(ast::Consts::String(name)) => schema::StorageFormat::from_name(name)?,
+                        _ => schema::StorageFormat::Row,
+                    },
+                    // Table::validate checks the options are known, with values of their types
+                    options: options
+                        .into_iter()
+                        .map(|(name, value)| Ok((name, evaluate_const_expr(&value.into())?)))
+                        .collect::<Result<_>>()?,
+                    partition: match partition_by {
+                        Some(ast::PartitionBy::Hash { column, partitions }) => {
+                            // Rows are looked up by primary key, so only it can pick the shard
+                            if !columns.iter().any(|c| c.primary_key && c.name == column) {
+                                return Err(Error::Internal(format!(
+                                    "table {} can only be hash partitioned by its primary key",
+                                    name
+                                )));
+                            }
+                            Some(schema::Partition::Hash { partitions })
+                        }
+                        None => None,
+                    },
                     name,
                     columns: columns
                         .into_iter()
                         .map(|c| {
                             let nullable = c.nullable.unwrap_or(!c.primary_key);
+                            let mut default_fn = None;
                             let default = match c.default {
-                                Some(expr) => Some(Value::from_expression(expr)),
+                                Some(ast::Expression::ScalarFunction(name, args))
+                                    if args.is_empty() && functions::is_volatile(&name) =>
+                                {
+                                    default_fn = Some(name);
+                                    None
+                                }
+                                Some(expr) => Some(evaluate_const_expr(&expr)?),
                                 None if nullable => Some(Value::Null),
                                 None => None,
                             };
 
-                            schema::Column {
+                            Ok(schema::Column {
                                 name: c.name,
                                 datatype: c.datatype,
                                 nullable,
                                 default,
+                                default_fn,
                                 primary_key: c.primary_key,
-                            }
+                                collation: match c.collation {
+                                    Some(name) => schema::Collation::from_name(&name)?,
+                                    None => schema::Collation::Binary,
+                                },
+                            })
                         })
-                        .collect(),
+                        .collect::<Result<_>>()?,
+                },
+            },
+            ast::Statement::CreateIndex { name, table_name, columns, predicate } => Node::CreateIndex {
+                index: Index {
+                    // Named like Postgres names them, e.g. t1_a_b_idx
+                    name: name.unwrap_or_else(|| format!("{}_{}_idx", table_name, columns.join("_"))),
+                    columns,
+                    predicate,
+                    building: false,
                 },
+                table_name,
             },
+            ast::Statement::DropTable { name, cascade } => Node::DropTable { table_name: name, cascade },
+            ast::Statement::CreateMaterializedView { name, query } => {
+                let sql = query.to_string();
+                let query = self.build_statement(*query, scope)?;
+                Node::CreateMaterializedView { schema: view::view_table(&name, sql, query.output())?, query: Box::new(query) }
+            }
+            ast::Statement::RefreshMaterializedView { name } => Node::RefreshMaterializedView { table_name: name },
             ast::Statement::Insert { table_name, columns, values } => Node::Insert {
                 table_name,
                 columns: columns.unwrap_or_default(),
@@ -46,7 +109,10 @@ impl Planner {
             },
             ast::Statement::Select {
                 select,
+                hints,
                 from,
+                // Already applied: the statement runs in a transaction pinned to it
+                as_of: _,
                 // WHERE clause - should be an Operation variant (e.g., Equal, GreaterThan, LessThan)
                 // not a Function variant
                 where_clause,
@@ -55,14 +121,48 @@ impl Planner {
                 order_by,
                 limit,
                 offset,
+                for_update,
             } => {
                 // Build scan node from FROM clause (single table or join result)
                 // Also determines the Scan filter condition
-                let mut node = self.build_from_item(from, &where_clause)?;
+                let tables = from_tables(&from);
+                let mut node = point_lookup(self.build_from_item(from, &where_clause, scope)?);
+                if for_update {
+                    if group_by.is_some() || select.iter().any(|(expr, _)| matches!(expr, Expression::Function(..))) {
+                        return Err(Error::Internal("FOR UPDATE is not allowed with aggregates".into()));
+                    }
+                    node = lock_rows(node);
+                }
+                // A single-table scan only needs the columns the query mentions
+                let mut needed = None;
+                if let Node::Scan { columns, .. } = &mut node
+                    && !select.is_empty()
+                {
+                    let mut referenced = Vec::new();
+                    let add = |referenced: &mut Vec<String>, col: &str| {
+                        if !referenced.iter().any(|c| c == col) {
+                            referenced.push(col.to_string());
+                        }
+                    };
+                    select.iter().for_each(|(expr, _)| expr.walk_fields(&mut |col| add(&mut referenced, col)));
+                    for expr in group_by.iter().chain(&having) {
+                        expr.walk_fields(&mut |col| add(&mut referenced, col));
+                    }
+                    order_by.iter().for_each(|(col, _)| add(&mut referenced, col));
+                    // Columns only the WHERE clause reads, which a partial index may answer for
+                    needed = Some(referenced.clone());
+                    where_clause.iter().for_each(|expr| expr.walk_fields(&mut |col| add(&mut referenced, col)));
+                    *columns = Some(referenced);
+                }
+                node = self.index_scan(node, &hints, needed.as_deref());
 
                 // aggregate - detect aggregate functions in select expressions、group by
                 let mut has_agg = false;
-                if !select.is_empty() {
+                if let Some(table_name) = count_only(&select, &group_by, &having, &node) {
+                    // Answered from the table's row count, without reading rows
+                    has_agg = true;
+                    node = Node::RowCount { output: select_output(&select, &node), table_name };
+                } else if !select.is_empty() {
                     for (expr, _) in select.iter() {
                         if let ast::Expression::Function(_, _) = expr {
                             has_agg = true;
@@ -73,10 +173,13 @@ impl Planner {
                         has_agg = true;
                     }
                     if has_agg {
+                        let output = select_output(&select, &node);
                         node = Node::Aggregate {
                             source: Box::new(node),
                             exprs: select.clone(),
                             group_by,
+                            tables: tables.clone(),
+                            output,
                         }
                     }
                 }
@@ -84,21 +187,26 @@ impl Planner {
                 // having
                 if let Some(expr) = having {
                     node = Node::Filter {
+                        output: passthrough(&node),
                         source: Box::new(node),
                         predicate: expr,
                     }
                 }
 
-                if !order_by.is_empty() {
+                // ORDER BY pk DESC over a table reads it backwards instead of sorting
+                if !order_by.is_empty() && (has_agg || !reverse_scan(&mut node, &order_by)) {
                     node = Node::Order {
+                        output: passthrough(&node),
                         source: Box::new(node),
                         order_by,
+                        tables,
                     }
                 }
 
                 // OFFSET - must be processed before LIMIT when both are present
                 if let Some(expr) = offset {
                     node = Node::Offset {
+                        output: passthrough(&node),
                         source: Box::new(node),
                         offset: match Value::from_expression(expr) {
                             Value::Integer(i) => i as usize,
@@ -107,14 +215,21 @@ impl Planner {
                     }
                 }
 
-                // LIMIT
+                // LIMIT - also pushed into the scans and joins below, the rows
+                // OFFSET skips included
                 if let Some(expr) = limit {
+                    let limit = match Value::from_expression(expr) {
+                        Value::Integer(i) => i as usize,
+                        _ => return Err(Error::Internal("invalid limit".into())),
+                    };
+                    let needed = match &node {
+                        Node::Offset { offset, .. } => limit.saturating_add(*offset),
+                        _ => limit,
+                    };
                     node = Node::Limit {
-                        source: Box::new(node),
-                        limit: match Value::from_expression(expr) {
-                            Value::Integer(i) => i as usize,
-                            _ => return Err(Error::Internal("invalid limit".into())),
-                        },
+                        output: passthrough(&node),
+                        source: Box::new(push_limit(node, needed)),
+                        limit,
                     }
                 }
                 
@@ -127,6 +242,7 @@ impl Planner {
                 // GROUP BY implementation needed to handle non-aggregate columns properly.
                 if !select.is_empty() && !has_agg {
                     node = Node::Projection {
+                        output: select_output(&select, &node),
                         source: Box::new(node),
                         exprs: select,
                     }
@@ -140,10 +256,14 @@ impl Planner {
                 where_clause,
             } => Node::Update {
                 table_name: table_name.clone(),
-                source: Box::new(Node::Scan {
+                source: Box::new(point_lookup(Node::Scan {
                     table_name,
                     filter: where_clause,
-                }),
+                    columns: None,
+                    limit: None,
+                    reverse: false,
+                    output: scope.clone(),
+                })),
                 columns,
             },
             ast::Statement::Delete {
@@ -151,19 +271,145 @@ impl Planner {
                 where_clause,
             } => Node::Delete {
                 table_name: table_name.clone(),
-                source: Box::new(Node::Scan {
+                source: Box::new(point_lookup(Node::Scan {
                     table_name,
                     filter: where_clause,
-                }),
+                    columns: None,
+                    limit: None,
+                    reverse: false,
+                    output: scope.clone(),
+                })),
             },
+            ast::Statement::Copy { table_name, direction, path } => Node::Copy { table_name, direction, path },
+            ast::Statement::LoadData { table_name, path } => Node::LoadData { table_name, path },
+            ast::Statement::Explain { statement, analyze, format } => Node::Explain {
+                source: Box::new(self.build_statement(*statement, scope)?),
+                analyze,
+                format,
+                output: Scope {
+                    columns: vec![ScopeColumn {
+                        table: None,
+                        name: "plan".into(),
+                        datatype: Some(DataType::String),
+                        nullable: false,
+                        primary_key: false,
+                    }],
+                },
+            },
+            ast::Statement::ShowStats => Node::Scan {
+                table_name: system::STATS.to_string(),
+                filter: None,
+                columns: None,
+                limit: None,
+                reverse: false,
+                output: table_output(scope, system::STATS),
+            },
+            ast::Statement::ShowCreateTable { name, .. } => Node::ShowCreateTable {
+                table_name: name,
+                output: Scope {
+                    columns: ["table", "create_table"]
+                        .into_iter()
+                        .map(|name| ScopeColumn {
+                            table: None,
+                            name: name.into(),
+                            datatype: Some(DataType::String),
+                            nullable: false,
+                            primary_key: false,
+                        })
+                        .collect(),
+                },
+            },
+            stmt @ (ast::Statement::Begin | ast::Statement::Commit | ast::Statement::Rollback | ast::Statement::Set { .. }) => {
+                return Err(Error::Internal(format!("{} has no plan", stmt)));
+            }
         })
     }
 
-    fn build_from_item(&self, item: ast::FromItem, filter: &Option<Expression>) -> Result<Node> {
+    /// Turns a table scan into an index scan, following the hints
+    ///
+    /// Unhinted, a filtered scan reading only columns an index holds (its own
+    /// and the primary key) becomes an index-only scan, preferring an index
+    /// whose first column the filter equates to a constant, so only that
+    /// value's entries are read. An INDEX hint uses its index regardless,
+    /// fetching the rows if the index lacks columns; NO_INDEX keeps the scan.
+    fn index_scan(&self, node: Node, hints: &[ast::Hint], needed: Option<&[String]>) -> Node {
+        let Node::Scan { table_name, filter, columns, output, .. } = &node else {
+            return node;
+        };
+        let Some(indexes) = self.indexes.get(table_name) else {
+            return node;
+        };
+        let no_index = hints.iter().any(|hint| {
+            matches!(hint, ast::Hint::NoIndex { table } if table.as_ref().is_none_or(|table| table == table_name))
+        });
+        if no_index {
+            return node;
+        }
+        // A partial index only holds the rows its predicate selects
+        let usable = |index: &Index| match &index.predicate {
+            Some(predicate) => filter.as_ref().is_some_and(|filter| implies(filter, predicate)),
+            None => true,
+        };
+        // ...and leaves nothing to filter when the query asks for exactly those rows
+        let redundant = |index: &Index| match (&index.predicate, filter) {
+            (Some(predicate), Some(filter)) => implies(predicate, filter),
+            _ => false,
+        };
+        let indexes = indexes.iter().filter(|index| usable(index));
+        let hinted = hints.iter().find_map(|hint| match hint {
+            ast::Hint::Index { table, index } if table == table_name => {
+                indexes.clone().find(|candidate| candidate.name == *index)
+            }
+            _ => None,
+        });
+        let covers = |index: &Index| {
+            let read = if redundant(index) { needed } else { columns.as_deref() };
+            let mut read = output.columns.iter().map(|c| &c.name).filter(|col| match read {
+                Some(read) => read.contains(col),
+                None => true,
+            });
+            read.all(|col| index.columns.contains(col) || output.resolve(col).is_ok_and(|(_, column)| column.primary_key))
+        };
+        let lookup = |index: &Index| {
+            let Some(Expression::Operation(ast::Operation::Equal(l, r))) = filter else {
+                return None;
+            };
+            let (col, consts) = field_const(l, r)?;
+            let (_, column) = output.resolve(col).ok().filter(|_| *col == index.columns[0])?;
+            const_value(column, consts)
+        };
+        let (index, value) = match hinted {
+            Some(index) => (index, lookup(index)),
+            None if filter.is_some() => {
+                let mut covering = indexes.filter(|index| covers(index));
+                let found = covering.clone().find_map(|index| Some((index, Some(lookup(index)?))));
+                match found.or_else(|| covering.next().map(|index| (index, None))) {
+                    Some(found) => found,
+                    None => return node,
+                }
+            }
+            None => return node,
+        };
+        Node::IndexScan {
+            table_name: table_name.clone(),
+            index: index.name.clone(),
+            value,
+            filter: if redundant(index) { None } else { filter.clone() },
+            fetch: !covers(index),
+            limit: None,
+            output: output.clone(),
+        }
+    }
+
+    fn build_from_item(&self, item: ast::FromItem, filter: &Option<Expression>, scope: &Scope) -> Result<Node> {
         Ok(match item {
             ast::FromItem::Table { name } => Node::Scan { 
+                output: table_output(scope, &name),
                 table_name: name, 
                 filter: filter.clone(),
+                columns: None,
+                limit: None,
+                reverse: false,
             },
             ast::FromItem::Join { 
                 left, 
@@ -182,14 +428,305 @@ impl Planner {
                     _ => true, // LEFT and RIGHT joins are both outer joins
                 };
 
-                Node::NestedLoopJoin {
-                    // Recursively build join nodes (base case: single table)
-                    left: Box::new(self.build_from_item(*left, filter)?),
-                    right: Box::new(self.build_from_item(*right, filter)?),
-                    predicate,
-                    outer,
+                // Recursively build join nodes (base case: single table)
+                let left = self.build_from_item(*left, filter, scope)?;
+                let right = self.build_from_item(*right, filter, scope)?;
+                // Outer joins pad unmatched left rows with NULLs
+                let padded = right.output().iter().map(|c| ScopeColumn { nullable: c.nullable || outer, ..c.clone() });
+                let output = Scope {
+                    columns: left.output().iter().cloned().chain(padded).collect(),
+                };
+                match hash_join_keys(&predicate, &left, &right) {
+                    Some((left_key, right_key)) => Node::HashJoin {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        left_key,
+                        right_key,
+                        outer,
+                        limit: None,
+                        output,
+                    },
+                    None => Node::NestedLoopJoin {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        predicate,
+                        outer,
+                        limit: None,
+                        output,
+                    },
                 }
             },
         })
     }
 }
+
+/// The key columns of a join on `left_column = right_column`, if it can hash them
+///
+/// Both columns need a known type whose values are equal exactly when they
+/// hash equal: integers of any width, or matching booleans, strings or UUIDs.
+/// Anything else keeps the nested loop join and its comparison semantics.
+fn hash_join_keys(predicate: &Option<Expression>, left: &Node, right: &Node) -> Option<(String, String)> {
+    let Some(Expression::Operation(ast::Operation::Equal(l, r))) = predicate else {
+        return None;
+    };
+    let (Expression::Field(l), Expression::Field(r)) = (&**l, &**r) else {
+        return None;
+    };
+    let datatype = |node: &Node, name: &str| node.output().iter().find(|c| c.name == name).and_then(|c| c.datatype);
+    let hashable = match (datatype(left, l)?, datatype(right, r)?) {
+        (ldt, rdt) if ldt.is_integer() && rdt.is_integer() => true,
+        (ldt @ (DataType::Boolean | DataType::String | DataType::Uuid), rdt) => ldt == rdt,
+        _ => false,
+    };
+    hashable.then(|| (l.clone(), r.clone()))
+}
+
+/// Pushes a limit of `n` rows through nodes that pass rows on one for one,
+/// into scans and joins, so they stop once they have produced enough
+///
+/// An ORDER BY becomes a top-N sort keeping `n` rows. Nodes needing every
+/// input row (aggregates, HAVING) stop the push. Outer and cross joins emit at least one row per left row (when any),
+/// so their left input gets the limit too.
+fn push_limit(node: Node, n: usize) -> Node {
+    let min = |limit: Option<usize>| Some(limit.map_or(n, |limit| limit.min(n)));
+    match node {
+        Node::Offset { source, offset, output } => {
+            Node::Offset { source: Box::new(push_limit(*source, n)), offset, output }
+        }
+        Node::Projection { source, exprs, output } => {
+            Node::Projection { source: Box::new(push_limit(*source, n)), exprs, output }
+        }
+        Node::Order { source, order_by, tables, output } => Node::TopN { source, order_by, tables, limit: n, output },
+        // Only the rows passed on get locked
+        Node::Lock { source, table_name, output } => {
+            Node::Lock { source: Box::new(push_limit(*source, n)), table_name, output }
+        }
+        Node::Scan { table_name, filter, columns, limit, reverse, output } => {
+            Node::Scan { table_name, filter, columns, limit: min(limit), reverse, output }
+        }
+        Node::IndexScan { table_name, index, value, filter, fetch, limit, output } => {
+            Node::IndexScan { table_name, index, value, filter, fetch, limit: min(limit), output }
+        }
+        Node::NestedLoopJoin { left, right, predicate, outer, limit, output } => {
+            let left = match outer || predicate.is_none() {
+                true => Box::new(push_limit(*left, n)),
+                false => left,
+            };
+            Node::NestedLoopJoin { left, right, predicate, outer, limit: min(limit), output }
+        }
+        Node::HashJoin { left, right, left_key, right_key, outer, limit, output } => {
+            let left = match outer {
+                true => Box::new(push_limit(*left, n)),
+                false => left,
+            };
+            Node::HashJoin { left, right, left_key, right_key, outer, limit: min(limit), output }
+        }
+        node => node,
+    }
+}
+
+/// Locks the rows each table's Scan or Get returns, for SELECT ... FOR UPDATE
+fn lock_rows(node: Node) -> Node {
+    match node {
+        Node::Scan { ref table_name, .. } | Node::Get { ref table_name, .. } => Node::Lock {
+            table_name: table_name.clone(),
+            output: passthrough(&node),
+            source: Box::new(node),
+        },
+        Node::NestedLoopJoin { left, right, predicate, outer, limit, output } => Node::NestedLoopJoin {
+            left: Box::new(lock_rows(*left)),
+            right: Box::new(lock_rows(*right)),
+            predicate,
+            outer,
+            limit,
+            output,
+        },
+        Node::HashJoin { left, right, left_key, right_key, outer, limit, output } => Node::HashJoin {
+            left: Box::new(lock_rows(*left)),
+            right: Box::new(lock_rows(*right)),
+            left_key,
+            right_key,
+            outer,
+            limit,
+            output,
+        },
+        node => node,
+    }
+}
+
+/// Turns a scan filtered by `pk = constant` into a primary key lookup
+fn point_lookup(node: Node) -> Node {
+    if let Node::Scan { table_name, filter: Some(Expression::Operation(ast::Operation::Equal(l, r))), output, .. } = &node
+        && let Some(key) = primary_key_value(l, r, output)
+    {
+        return Node::Get { table_name: table_name.clone(), key, output: output.clone() };
+    }
+    node
+}
+
+/// Makes a table scan read in descending primary key order if that's the
+/// order asked for, returning whether it did
+fn reverse_scan(node: &mut Node, order_by: &[(String, ast::OrderDirection)]) -> bool {
+    let scan = match node {
+        Node::Lock { source, .. } => source.as_mut(),
+        node => node,
+    };
+    if let ([(col, ast::OrderDirection::Desc)], Node::Scan { reverse, output, .. }) = (order_by, scan)
+        && output.resolve(col).is_ok_and(|(_, column)| column.primary_key)
+    {
+        *reverse = true;
+        return true;
+    }
+    false
+}
+
+/// The table of a query selecting only COUNT(*) over all of its rows
+fn count_only(
+    select: &[(Expression, Option<String>)],
+    group_by: &Option<Expression>,
+    having: &Option<Expression>,
+    node: &Node,
+) -> Option<String> {
+    match (select, node) {
+        ([(Expression::Function(func, col), _)], Node::Scan { table_name, filter: None, .. })
+            if func.eq_ignore_ascii_case("count") && col == ast::ALL_ROWS && group_by.is_none() && having.is_none() =>
+        {
+            Some(table_name.clone())
+        }
+        _ => None,
+    }
+}
+
+/// The key of an equality between the primary key and a constant
+///
+/// Only matches a constant of the key's type, so the lookup finds
+/// exactly the rows the filter would.
+fn primary_key_value(l: &Expression, r: &Expression, output: &Scope) -> Option<Value> {
+    let (col, consts) = field_const(l, r)?;
+    let pk = output.columns.iter().find(|c| c.primary_key && c.name == *col)?;
+    const_value(pk, consts)
+}
+
+/// The column and constant of a comparison between the two, either way round
+fn field_const<'a>(l: &'a Expression, r: &'a Expression) -> Option<(&'a String, &'a ast::Consts)> {
+    match (l, r) {
+        (Expression::Field(col), Expression::Consts(c)) | (Expression::Consts(c), Expression::Field(col)) => Some((col, c)),
+        _ => None,
+    }
+}
+
+/// Whether every row matching `filter` also matches `predicate`
+///
+/// Only single comparisons of one column with a constant are reasoned about; strings
+/// are compared for equality alone, since a collation may order them differently.
+fn implies(filter: &Expression, predicate: &Expression) -> bool {
+    use std::cmp::Ordering::*;
+    if filter == predicate {
+        return true;
+    }
+    let (Some((col, op, value)), Some((pcol, pop, pvalue))) = (comparison(filter), comparison(predicate)) else {
+        return false;
+    };
+    if col != pcol || value.datatype().is_none() || value.datatype() != pvalue.datatype() {
+        return false;
+    }
+    let ordered = !matches!(value, Value::String(_));
+    match (op, pop, value.partial_cmp(&pvalue)) {
+        (Equal, Equal, Some(Equal)) => true,
+        (Equal | Greater, Greater, Some(Greater)) | (Equal | Less, Less, Some(Less)) => ordered,
+        (Greater, Greater, Some(Equal)) | (Less, Less, Some(Equal)) => ordered,
+        _ => false,
+    }
+}
+
+/// A filter as `column <op> constant`, with a bare column meaning `column = TRUE`
+fn comparison(expr: &Expression) -> Option<(&String, std::cmp::Ordering, Value)> {
+    use std::cmp::Ordering::*;
+    let (op, l, r) = match expr {
+        Expression::Field(col) => return Some((col, Equal, Value::Boolean(true))),
+        Expression::Operation(ast::Operation::Equal(l, r)) => (Equal, l, r),
+        Expression::Operation(ast::Operation::GreaterThan(l, r)) => (Greater, l, r),
+        Expression::Operation(ast::Operation::LessThan(l, r)) => (Less, l, r),
+        _ => return None,
+    };
+    let (col, consts) = field_const(l, r)?;
+    // `c > col` reads as `col < c`
+    let op = if matches!(**l, Expression::Field(_)) { op } else { op.reverse() };
+    Some((col, op, Value::from_expression(consts.clone().into())))
+}
+
+/// A constant as a value of the column's type, None if it's of another type
+fn const_value(column: &ScopeColumn, consts: &ast::Consts) -> Option<Value> {
+    match (column.datatype?, Value::from_expression(consts.clone().into())) {
+        (DataType::Uuid, Value::String(s)) => Value::parse_uuid(&s),
+        (dt, v @ Value::Integer(_)) if dt.is_integer() => Some(v),
+        (dt, v) if v.datatype() == Some(dt) => Some(v),
+        _ => None,
+    }
+}
+
+/// Columns of one table in the statement scope
+///
+/// A table joined with itself appears in the scope twice; its columns are taken once.
+fn table_output(scope: &Scope, table: &str) -> Scope {
+    let mut columns: Vec<ScopeColumn> = Vec::new();
+    for c in scope.columns.iter().filter(|c| c.table.as_deref() == Some(table)) {
+        if columns.iter().any(|seen| seen.name == c.name) {
+            break;
+        }
+        columns.push(c.clone());
+    }
+    Scope { columns }
+}
+
+/// Output of a node that passes its source rows through (filter, sort, limit)
+fn passthrough(source: &Node) -> Scope {
+    Scope { columns: source.output().to_vec() }
+}
+
+/// Output columns of a select list (projection or aggregate) over a source node
+///
+/// Names follow the alias, else the column or function name, else `?column?`.
+/// Types are left unknown where they can't be inferred, e.g., in unbound plans.
+fn select_output(select: &[(Expression, Option<String>)], source: &Node) -> Scope {
+    let source = passthrough(source);
+    let columns = select
+        .iter()
+        .map(|(expr, alias)| {
+            let resolved = |col: &str| source.resolve(col).ok().map(|(_, c)| c.clone());
+            let (name, table, datatype, nullable) = match expr {
+                Expression::Field(col) => match resolved(col) {
+                    Some(c) => (col.clone(), c.table, c.datatype, c.nullable),
+                    None => (col.clone(), None, None, true),
+                },
+                Expression::Function(func, col) if col == ast::ALL_ROWS => {
+                    (func.clone(), None, Some(DataType::Integer), false)
+                }
+                Expression::Function(func, col) => (
+                    func.clone(),
+                    None,
+                    resolved(col).and_then(|c| analyzer::aggregate_type(func, &c).ok().flatten()),
+                    analyzer::aggregate_nullable(func),
+                ),
+                Expression::ScalarFunction(name, _) => {
+                    (name.clone(), None, analyzer::expr_type(expr, &source, "SELECT").ok().flatten(), true)
+                }
+                _ => ("?column?".into(), None, analyzer::expr_type(expr, &source, "SELECT").ok().flatten(), true),
+            };
+            ScopeColumn { table, name: alias.clone().unwrap_or(name), datatype, nullable, primary_key: false }
+        })
+        .collect();
+    Scope { columns }
+}
+
+/// Names of the tables referenced by a FROM clause
+fn from_tables(item: &ast::FromItem) -> Vec<String> {
+    match item {
+        ast::FromItem::Table { name } => vec![name.clone()],
+        ast::FromItem::Join { left, right, .. } => {
+            let mut tables = from_tables(left);
+            tables.extend(from_tables(right));
+            tables
+        }
+    }
+}
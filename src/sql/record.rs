@@ -0,0 +1,391 @@
+//! Records - Rust structs as the rows of a table
+//!
+//! A `Record` type knows its table's columns, so it can write the table's
+//! CREATE TABLE statement, INSERT statements of its values, and be read back
+//! from query results with `Session::query_as`. With the `derive` feature,
+//! `#[derive(Record)]` implements it for a struct with named fields:
+//!
+//! ```ignore
+//! #[derive(Record)]
+//! #[record(table = "users")] // by default the struct name in snake_case
+//! struct User {
+//!     #[record(primary_key)]
+//!     id: i64,
+//!     #[record(rename = "full_name")]
+//!     name: String,
+//!     email: Option<String>,
+//! }
+//! ```
+//!
+//! Field types map to column types through `SqlValue`; `Option` fields are
+//! the nullable columns. The same conversions read single values of query
+//! results, by column name or position:
+//!
+//! ```ignore
+//! for row in session.execute("select id, email from users;")?.rows()? {
+//!     let id: i64 = row.get("id");
+//!     let email = row.try_get::<Option<String>>(1)?;
+//! }
+//! ```
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        parser::ast,
+        schema::literal,
+        types::{DataType, Row, Value},
+    },
+};
+
+#[cfg(feature = "derive")]
+pub use rustdb_derive::Record;
+
+/// A Rust type stored in a column
+pub trait SqlValue: Sized {
+    /// Type of the column
+    const DATATYPE: DataType;
+    /// Whether the column holds NULLs, as `None`
+    const NULLABLE: bool = false;
+
+    fn to_value(&self) -> Value;
+    /// Converts a value of the column, failing on other types and NULL
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+/// Error for a value not of the type being read
+fn mismatch<T: SqlValue>(value: &Value) -> Error {
+    match value.datatype() {
+        Some(datatype) => Error::Internal(format!("expected {}, got {} {}", T::DATATYPE, datatype, value)),
+        None => Error::Internal(format!("expected {}, got NULL", T::DATATYPE)),
+    }
+}
+
+macro_rules! integer_value {
+    ($($type:ty => $datatype:ident),*) => {$(
+        impl SqlValue for $type {
+            const DATATYPE: DataType = DataType::$datatype;
+
+            fn to_value(&self) -> Value {
+                Value::Integer((*self).into())
+            }
+
+            fn from_value(value: Value) -> Result<Self> {
+                match value {
+                    Value::Integer(i) => <$type>::try_from(i).map_err(|_| {
+                        Error::Internal(format!("integer {} out of range for {}", i, stringify!($type)))
+                    }),
+                    value => Err(mismatch::<Self>(&value)),
+                }
+            }
+        }
+    )*};
+}
+
+integer_value!(i16 => SmallInt, i32 => Integer, i64 => Integer, i128 => BigInt);
+
+impl SqlValue for bool {
+    const DATATYPE: DataType = DataType::Boolean;
+
+    fn to_value(&self) -> Value {
+        Value::Boolean(*self)
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            value => Err(mismatch::<Self>(&value)),
+        }
+    }
+}
+
+impl SqlValue for f64 {
+    const DATATYPE: DataType = DataType::Float;
+
+    fn to_value(&self) -> Value {
+        Value::Float(*self)
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Float(f) => Ok(f),
+            value => Err(mismatch::<Self>(&value)),
+        }
+    }
+}
+
+impl SqlValue for String {
+    const DATATYPE: DataType = DataType::String;
+
+    fn to_value(&self) -> Value {
+        Value::String(self.as_str().into())
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            value => Err(mismatch::<Self>(&value)),
+        }
+    }
+}
+
+impl<T: SqlValue> SqlValue for Option<T> {
+    const DATATYPE: DataType = T::DATATYPE;
+    const NULLABLE: bool = true;
+
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, T::to_value)
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// A column of a record's table
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordColumn {
+    pub name: &'static str,
+    pub datatype: DataType,
+    pub nullable: bool,
+    pub primary_key: bool,
+}
+
+/// A type whose values are the rows of a table
+pub trait Record: Sized {
+    /// Name of the table
+    const TABLE: &'static str;
+
+    /// Columns of the table, in the order of `to_row`
+    fn columns() -> Vec<RecordColumn>;
+    /// The row of the record
+    fn to_row(&self) -> Row;
+    /// Reads a record from a result row with the given column names
+    fn from_row(columns: &[String], row: Row) -> Result<Self>;
+
+    /// CREATE TABLE statement of the table, as SQL text for `Session::execute`
+    fn create_table_sql() -> String {
+        let columns = Self::columns().into_iter().map(|c| ast::Column {
+            name: c.name.into(),
+            datatype: c.datatype,
+            nullable: Some(c.nullable),
+            default: None,
+            primary_key: c.primary_key,
+            collation: None,
+        });
+        let stmt = ast::Statement::CreateTable {
+            name: Self::TABLE.into(),
+            columns: columns.collect(),
+            partition_by: None,
+            options: Default::default(),
+        };
+        stmt.to_sql()
+    }
+
+    /// INSERT statement of records, as SQL text for `Session::execute`
+    fn insert_sql<'a>(records: impl IntoIterator<Item = &'a Self>) -> String
+    where
+        Self: 'a,
+    {
+        let stmt = ast::Statement::Insert {
+            table_name: Self::TABLE.into(),
+            columns: Some(Self::columns().into_iter().map(|c| c.name.into()).collect()),
+            values: records.into_iter().map(|r| r.to_row().iter().map(literal).collect()).collect(),
+        };
+        stmt.to_sql()
+    }
+}
+
+/// A column of a result row, by name or position from 0
+pub trait ColumnIndex {
+    /// Position of the column among a result's columns
+    fn position(&self, columns: &[String]) -> Result<usize>;
+}
+
+impl ColumnIndex for &str {
+    fn position(&self, columns: &[String]) -> Result<usize> {
+        columns
+            .iter()
+            .position(|column| column == self)
+            .ok_or_else(|| Error::Internal(format!("column {} is not in the result", self)))
+    }
+}
+
+impl ColumnIndex for usize {
+    fn position(&self, columns: &[String]) -> Result<usize> {
+        match *self < columns.len() {
+            true => Ok(*self),
+            false => Err(Error::Internal(format!(
+                "column {} is out of range, the result has {} columns",
+                self,
+                columns.len()
+            ))),
+        }
+    }
+}
+
+/// A row of a query result (see `ResultSet::rows`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResultRow<'a> {
+    columns: &'a [String],
+    values: &'a Row,
+}
+
+impl<'a> ResultRow<'a> {
+    pub fn new(columns: &'a [String], values: &'a Row) -> Self {
+        Self { columns, values }
+    }
+
+    pub fn columns(&self) -> &'a [String] {
+        self.columns
+    }
+
+    pub fn values(&self) -> &'a Row {
+        self.values
+    }
+
+    /// Reads a column's value as a Rust type, panicking if it's missing or
+    /// of another type; see `try_get`
+    #[track_caller]
+    pub fn get<T: SqlValue>(&self, column: impl ColumnIndex) -> T {
+        self.try_get(column).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Reads a column's value as a Rust type
+    pub fn try_get<T: SqlValue>(&self, column: impl ColumnIndex) -> Result<T> {
+        let i = column.position(self.columns)?;
+        T::from_value(self.values[i].clone()).map_err(|err| column_error(&self.columns[i], err))
+    }
+}
+
+/// Names the column of a failed conversion
+fn column_error(column: &str, err: Error) -> Error {
+    match err {
+        Error::Internal(message) => Error::Internal(format!("column {}: {}", column, message)),
+        err => err,
+    }
+}
+
+/// Takes the value of a column out of a result row, for `Record::from_row`
+pub fn take_column<T: SqlValue>(columns: &[String], row: &mut Row, name: &str) -> Result<T> {
+    let value = &mut row[name.position(columns)?];
+    T::from_value(std::mem::replace(value, Value::Null)).map_err(|err| column_error(name, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqlValue, take_column};
+    use crate::{
+        error::{Error, Result},
+        sql::{
+            engine::{Engine, kv::KVEngine},
+            types::Value,
+        },
+        storage::memory::MemoryEngine,
+    };
+
+    #[test]
+    fn test_sql_value() -> Result<()> {
+        assert_eq!(i16::from_value(Value::Integer(-3))?, -3);
+        assert_eq!(Option::<String>::from_value(Value::Null)?, None);
+        assert_eq!(Some(1.5).to_value(), Value::Float(1.5));
+        assert_eq!(
+            i16::from_value(Value::Integer(40000)),
+            Err(Error::Internal("integer 40000 out of range for i16".into()))
+        );
+        assert_eq!(bool::from_value(Value::Null), Err(Error::Internal("expected BOOLEAN, got NULL".into())));
+
+        let columns = vec!["a".to_string(), "b".to_string()];
+        let mut row = vec![Value::Integer(1), Value::String("x".into())];
+        assert_eq!(take_column::<String>(&columns, &mut row, "b")?, "x");
+        assert_eq!(
+            take_column::<i64>(&columns, &mut row, "b"),
+            Err(Error::Internal("column b: expected INTEGER, got NULL".into()))
+        );
+        assert!(take_column::<i64>(&columns, &mut row, "c").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_row() -> Result<()> {
+        let mut s = KVEngine::new(MemoryEngine::new()).session()?;
+        s.execute("create table t (id int primary key, name text, score float);")?;
+        s.execute("insert into t values (1, 'a', 0.5), (2, null, 1.5);")?;
+        let result = s.execute("select id, name, score from t;")?;
+        let rows = result.rows()?.collect::<Vec<_>>();
+        assert_eq!(rows[0].get::<i64>("id"), 1);
+        assert_eq!(rows[0].get::<String>(1), "a");
+        assert_eq!(rows[1].try_get::<Option<String>>("name")?, None);
+        assert_eq!(rows[1].try_get::<f64>(2)?, 1.5);
+        assert_eq!(rows[1].columns(), ["id", "name", "score"]);
+
+        assert_eq!(
+            rows[1].try_get::<String>("name"),
+            Err(Error::Internal("column name: expected STRING, got NULL".into()))
+        );
+        assert_eq!(
+            rows[0].try_get::<i64>("score"),
+            Err(Error::Internal("column score: expected INTEGER, got FLOAT 0.5".into()))
+        );
+        assert_eq!(rows[0].try_get::<i64>("rank"), Err(Error::Internal("column rank is not in the result".into())));
+        assert_eq!(
+            rows[0].try_get::<i64>(3),
+            Err(Error::Internal("column 3 is out of range, the result has 3 columns".into()))
+        );
+        assert!(std::panic::catch_unwind(|| rows[0].get::<bool>("id")).is_err());
+        assert!(s.execute("delete from t;")?.rows().is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_record() -> Result<()> {
+        use super::Record;
+
+        #[derive(Debug, PartialEq, Record)]
+        struct OrderItem {
+            #[record(primary_key)]
+            id: i64,
+            #[record(rename = "qty")]
+            quantity: i16,
+            note: Option<String>,
+        }
+
+        #[derive(Debug, PartialEq, Record)]
+        #[record(table = "order_item")]
+        struct Quantity {
+            #[record(primary_key)]
+            id: i64,
+            qty: i16,
+        }
+
+        assert_eq!(
+            OrderItem::create_table_sql(),
+            "CREATE TABLE order_item (id INTEGER NOT NULL PRIMARY KEY, qty SMALLINT NOT NULL, note STRING NULL);"
+        );
+        let items = [
+            OrderItem { id: 1, quantity: 2, note: None },
+            OrderItem { id: 2, quantity: 5, note: Some("it's".into()) },
+        ];
+        assert_eq!(
+            OrderItem::insert_sql(&items[1..]),
+            "INSERT INTO order_item (id, qty, note) VALUES (2, 5, 'it''s');"
+        );
+
+        let mut s = KVEngine::new(MemoryEngine::new()).session()?;
+        s.execute(&OrderItem::create_table_sql())?;
+        s.execute(&OrderItem::insert_sql(&items[..1]))?;
+        s.insert_records(&items[1..])?;
+        assert_eq!(s.query_as::<OrderItem>("select * from order_item;")?, items);
+        // Columns are matched by name, not position
+        assert_eq!(
+            s.query_as::<Quantity>("select qty, id from order_item where id = 2;")?,
+            vec![Quantity { id: 2, qty: 5 }]
+        );
+        assert!(s.query_as::<OrderItem>("select id, qty from order_item;").is_err());
+        assert!(s.query_as::<OrderItem>("delete from order_item;").is_err());
+        Ok(())
+    }
+}
@@ -1,12 +1,103 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
 use serde::{Deserialize, Serialize};
 
-use crate::{error::{Error, Result}, sql::types::{DataType, Row, Value}};
+use crate::{
+    error::{Error, Result},
+    sql::{
+        parser::{
+            Parser,
+            ast::{self, Consts, Expression, Operation},
+        },
+        types::{DataType, Row, Value, canonical_float},
+    },
+};
 
 /// Table schema definition
-#[derive(Debug, PartialEq, Serialize, Deserialize)] 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    /// Row placement across shards (None stores all rows under one prefix)
+    pub partition: Option<Partition>,
+    /// How rows are laid out in the KV layer
+    pub storage: StorageFormat,
+    /// Whether changes are logged to an audit table (see `executor::audit`)
+    pub audit: bool,
+    /// Options of `WITH (name = value, ...)` as given to CREATE TABLE;
+    /// `storage` and `audit` above are read from theirs
+    pub options: BTreeMap<String, Value>,
+    /// Secondary indexes, in creation order
+    pub indexes: Vec<Index>,
+    /// SELECT query of a materialized view, whose rows the table stores
+    /// (see `executor::view`); None for tables
+    pub view: Option<String>,
+    /// Materialized views kept up to date with the table's row changes
+    pub views: Vec<String>,
+    /// Materialized views reading the table, maintained or not, which can
+    /// only be dropped along with it
+    pub readers: Vec<String>,
+}
+
+/// Options CREATE TABLE takes in `WITH (name = value, ...)`, with the type
+/// of their values
+pub const TABLE_OPTIONS: &[(&str, DataType)] = &[
+    // Whether to keep an audit table of row changes
+    ("audit", DataType::Boolean),
+    // Storage format name, see `StorageFormat`
+    ("storage", DataType::String),
+];
+
+/// Secondary index over columns of a table (CREATE INDEX)
+///
+/// Each row has one entry keyed by its indexed values and primary key,
+/// holding those values, so queries reading only them needn't fetch rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    /// WHERE predicate of a partial index, which only has entries for the
+    /// rows matching it
+    pub predicate: Option<Expression>,
+    /// Set while CREATE INDEX builds the index online: writers already keep
+    /// its entries, but queries don't use it until it's complete
+    pub building: bool,
+}
+
+/// Table storage layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum StorageFormat {
+    /// Each row is one value under its primary key
+    #[default]
+    Row,
+    /// Each column value is stored separately, column by column, so scans
+    /// can read just the columns a query uses
+    Columnar,
+}
+
+impl StorageFormat {
+    /// Looks up a format by its (case-insensitive) `WITH (storage = ...)` name
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_ref() {
+            "row" => Self::Row,
+            "columnar" => Self::Columnar,
+            _ => return Err(Error::Internal(format!("unknown storage format {}", name))),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Row => "row",
+            Self::Columnar => "columnar",
+        }
+    }
+}
+
+/// Table partitioning scheme
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Partition {
+    /// Rows are spread over `partitions` shards by a hash of the primary key
+    Hash { partitions: u64 },
 }
 
 impl Table {
@@ -44,11 +135,17 @@ impl Table {
                     col.name, self.name
                 )));
             }
+            if col.collation != Collation::Binary && col.datatype != DataType::String {
+                return Err(Error::Internal(format!(
+                    "Collation {} is only supported for string column {} in table {}",
+                    col.collation.name(), col.name, self.name
+                )));
+            }
             // Validate default value type matches column type
             if let Some(default_val) = &col.default {
                 match default_val.datatype() {
-                    Some(dt) => {
-                        if dt != col.datatype {
+                    Some(_) => {
+                        if !col.datatype.contains(default_val) {
                             return Err(Error::Internal(format!(
                                 "Default value for column {} mismatch in table {}",
                                 col.name, self.name
@@ -60,9 +157,61 @@ impl Table {
             }
         }
 
+        if let Some(Partition::Hash { partitions: 0 }) = self.partition {
+            return Err(Error::Internal(format!(
+                "table {} must have at least one partition",
+                self.name
+            )));
+        }
+        for (i, index) in self.indexes.iter().enumerate() {
+            if self.indexes[..i].iter().any(|other| other.name == index.name) {
+                return Err(Error::Internal(format!(
+                    "index {} already exists on table {}",
+                    index.name, self.name
+                )));
+            }
+            if index.columns.is_empty() {
+                return Err(Error::Internal(format!("index {} has no columns", index.name)));
+            }
+            self.index_columns(index)?;
+        }
+
+        for (name, value) in &self.options {
+            match TABLE_OPTIONS.iter().find(|(option, _)| option == name) {
+                Some((_, datatype)) if value.datatype() == Some(*datatype) => {}
+                Some((_, datatype)) => {
+                    return Err(Error::Internal(format!(
+                        "table option {} must be of type {}, got {}",
+                        name, datatype, value
+                    )));
+                }
+                None => return Err(Error::Internal(format!("unknown table option {}", name))),
+            }
+        }
+
+        if self.partition.is_some() && self.storage == StorageFormat::Columnar {
+            return Err(Error::Internal(format!(
+                "columnar table {} can't be partitioned",
+                self.name
+            )));
+        }
+
         Ok(())
     }
 
+    /// Returns the shard a primary key is stored in, or None if the table is not partitioned
+    ///
+    /// Uses FNV-1a over the encoded key so placement is stable across builds.
+    pub fn shard_of(&self, pk: &Value) -> Result<Option<u64>> {
+        let Some(Partition::Hash { partitions }) = self.partition else {
+            return Ok(None);
+        };
+        let hash = bincode::serialize(pk)?
+            .iter()
+            .fold(0xcbf29ce484222325u64, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3));
+        Ok(Some(hash % partitions))
+    }
+
     /// Extracts primary key value from a row
     pub fn get_primary_key(&self, row: &Row) -> Result<Value> {
         let pos = self
@@ -73,6 +222,80 @@ impl Table {
         Ok(row[pos].clone())
     }
 
+    /// Looks up an index by name
+    pub fn index(&self, name: &str) -> Result<&Index> {
+        self.indexes.iter().find(|index| index.name == name).ok_or_else(|| {
+            Error::Internal(format!("index {} does not exist on table {}", name, self.name))
+        })
+    }
+
+    /// Row positions of an index's columns
+    pub fn index_columns(&self, index: &Index) -> Result<Vec<usize>> {
+        index
+            .columns
+            .iter()
+            .map(|name| {
+                self.columns.iter().position(|c| c.name == *name).ok_or_else(|| {
+                    Error::Internal(format!(
+                        "column {} of index {} does not exist in table {}",
+                        name, index.name, self.name
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the collation of a column (binary for unknown columns)
+    pub fn collation(&self, col_name: &str) -> Collation {
+        self.columns
+            .iter()
+            .find(|c| c.name == col_name)
+            .map_or(Collation::Binary, |c| c.collation)
+    }
+
+    /// Rewrites comparisons against collated columns to compare collation keys
+    ///
+    /// e.g., with `b COLLATE nocase`, `b = 'Foo'` becomes
+    /// `collate(b, 'nocase') = collate('Foo', 'nocase')`.
+    pub fn collate_filter(&self, expr: Expression) -> Expression {
+        let collation = |e: &Expression| match e {
+            Expression::Field(col) => self.collation(col),
+            _ => Collation::Binary,
+        };
+        let rewrite = |l: Box<Expression>, r: Box<Expression>| {
+            let (l, r) = (self.collate_filter(*l), self.collate_filter(*r));
+            let c = match collation(&l) {
+                Collation::Binary => collation(&r),
+                c => c,
+            };
+            if c == Collation::Binary {
+                return (Box::new(l), Box::new(r));
+            }
+            let wrap = |e| {
+                Box::new(Expression::ScalarFunction(
+                    "collate".into(),
+                    vec![e, Consts::String(c.name().into()).into()],
+                ))
+            };
+            (wrap(l), wrap(r))
+        };
+        match expr {
+            Expression::Operation(Operation::Equal(l, r)) => {
+                let (l, r) = rewrite(l, r);
+                Expression::Operation(Operation::Equal(l, r))
+            }
+            Expression::Operation(Operation::GreaterThan(l, r)) => {
+                let (l, r) = rewrite(l, r);
+                Expression::Operation(Operation::GreaterThan(l, r))
+            }
+            Expression::Operation(Operation::LessThan(l, r)) => {
+                let (l, r) = rewrite(l, r);
+                Expression::Operation(Operation::LessThan(l, r))
+            }
+            expr => expr,
+        }
+    }
+
     /// Returns the column index for a given column name
     pub fn get_col_index(&self, col_name: &str) -> Result<usize> {
         self.columns
@@ -80,15 +303,164 @@ impl Table {
             .position(|c| c.name == col_name)
             .ok_or(Error::Internal(format!("column {} not found", col_name)))
     }
+
+    /// The CREATE TABLE statement recreating the table, without its indexes
+    ///
+    /// Every column states its nullability; collations and defaults are
+    /// left out when they're the defaults.
+    pub fn create_statement(&self) -> ast::Statement {
+        let columns = self.columns.iter().map(|c| ast::Column {
+            name: c.name.clone(),
+            datatype: c.datatype,
+            nullable: Some(c.nullable),
+            default: match (&c.default, &c.default_fn) {
+                (_, Some(name)) => Some(Expression::ScalarFunction(name.clone(), Vec::new())),
+                (Some(Value::Null), None) | (None, None) => None,
+                (Some(value), None) => Some(literal(value)),
+            },
+            primary_key: c.primary_key,
+            collation: match c.collation {
+                Collation::Binary => None,
+                collation => Some(collation.name().into()),
+            },
+        });
+        ast::Statement::CreateTable {
+            name: self.name.clone(),
+            columns: columns.collect(),
+            partition_by: self.partition.map(|Partition::Hash { partitions }| ast::PartitionBy::Hash {
+                column: self.columns.iter().find(|c| c.primary_key).map(|c| c.name.clone()).unwrap_or_default(),
+                partitions,
+            }),
+            options: self
+                .options
+                .iter()
+                .map(|(name, value)| match literal(value) {
+                    Expression::Consts(value) => (name.clone(), value),
+                    // Options are booleans, numbers and strings
+                    _ => unreachable!("table option {} = {}", name, value),
+                })
+                .collect(),
+        }
+    }
+
+    /// The query of a materialized view, None for tables
+    pub fn view_query(&self) -> Result<Option<ast::Statement>> {
+        self.view.as_ref().map(|query| Parser::new(&format!("{};", query)).parse()).transpose()
+    }
+
+    /// The CREATE INDEX statements of the table's indexes, in creation
+    /// order; indexes still building are left out
+    pub fn index_statements(&self) -> Vec<ast::Statement> {
+        self.indexes
+            .iter()
+            .filter(|index| !index.building)
+            .map(|index| ast::Statement::CreateIndex {
+                name: Some(index.name.clone()),
+                table_name: self.name.clone(),
+                columns: index.columns.clone(),
+                predicate: index.predicate.clone(),
+            })
+            .collect()
+    }
+
+    /// The table's DDL as a script: its CREATE TABLE statement, or CREATE
+    /// MATERIALIZED VIEW for a view, then its CREATE INDEX statements, one
+    /// per line with semicolons
+    pub fn to_ddl(&self) -> Result<String> {
+        let create = match self.view_query()? {
+            Some(query) => ast::Statement::CreateMaterializedView { name: self.name.clone(), query: Box::new(query) },
+            None => self.create_statement(),
+        };
+        let statements = [create].into_iter().chain(self.index_statements());
+        Ok(statements.map(|stmt| stmt.to_sql()).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// An expression evaluating to a value
+pub(crate) fn literal(value: &Value) -> Expression {
+    match value {
+        Value::Null => Consts::Null.into(),
+        Value::Boolean(b) => Consts::Boolean(*b).into(),
+        Value::Integer(i) => Consts::Integer(*i).into(),
+        // NaN has no literal
+        Value::Float(f) if f.is_nan() => Expression::Operation(Operation::Divide(
+            Box::new(Consts::Float(0.0).into()),
+            Box::new(Consts::Float(0.0).into()),
+        )),
+        Value::Float(f) => Consts::Float(*f).into(),
+        Value::String(s) => Consts::String(s.to_string()).into(),
+        Value::Point(x, y) => {
+            Expression::ScalarFunction("point".into(), vec![literal(&Value::Float(*x)), literal(&Value::Float(*y))])
+        }
+        // UUID literals are written as strings
+        Value::Uuid(_) => Consts::String(value.to_string()).into(),
+    }
 }
 
 /// Column schema definition
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
     pub nullable: bool,
     pub default: Option<Value>,
+    /// Volatile function computing the default per row (e.g., uuid()), instead of `default`
+    pub default_fn: Option<String>,
     /// Whether this column is the primary key
     pub primary_key: bool,
+    /// String comparison rules (ordering, grouping and primary key uniqueness)
+    pub collation: Collation,
+}
+
+/// String comparison rules of a column
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Collation {
+    /// Byte-wise comparison
+    #[default]
+    Binary,
+    /// ASCII case-insensitive comparison
+    NoCase,
+    /// Unicode case-insensitive comparison
+    Unicode,
+}
+
+impl Collation {
+    /// Looks up a collation by its (case-insensitive) COLLATE name
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_ref() {
+            "binary" => Self::Binary,
+            "nocase" => Self::NoCase,
+            "unicode" => Self::Unicode,
+            _ => return Err(Error::Internal(format!("unknown collation {}", name))),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::NoCase => "nocase",
+            Self::Unicode => "unicode",
+        }
+    }
+
+    /// Maps a value to the key it compares by
+    ///
+    /// Floats map to their canonical value, so NaNs and zeros of either sign
+    /// meet in keys; other non-string values are unchanged.
+    pub fn fold(&self, value: &Value) -> Value {
+        match (self, value) {
+            (Self::NoCase, Value::String(s)) => Value::String(s.to_ascii_lowercase().into()),
+            (Self::Unicode, Value::String(s)) => Value::String(s.to_lowercase().into()),
+            (_, Value::Float(f)) => Value::Float(canonical_float(*f)),
+            (_, v) => v.clone(),
+        }
+    }
+
+    /// Compares two values under this collation
+    pub fn compare(&self, a: &Value, b: &Value) -> Option<Ordering> {
+        match self {
+            Self::Binary => a.partial_cmp(b),
+            _ => self.fold(a).partial_cmp(&self.fold(b)),
+        }
+    }
 }
@@ -0,0 +1,226 @@
+//! SQL logic test runner (sqllogictest format, tests only)
+//!
+//! Runs the `.slt` scripts in `tests/slt`, each against a fresh database, so
+//! SQL behavior can be pinned down without writing Rust. Records are
+//! separated by blank lines:
+//!
+//! ```text
+//! # comment
+//! statement ok
+//! create table t1 (a int primary key, b text)
+//!
+//! statement count 2
+//! insert into t1 values (1, 'x'), (2, null)
+//!
+//! statement error duplicate
+//! insert into t1 values (1, 'y')
+//!
+//! query IT rowsort
+//! select a, b from t1
+//! ----
+//! 1 x
+//! 2 NULL
+//! ```
+//!
+//! `statement error` and `query error` take an optional substring of the
+//! expected message. A query lists a type letter per column (I integer,
+//! R float, T text, B boolean, ...; only their number is checked) and a sort
+//! mode: `nosort` (the default), `rowsort` or `valuesort`. Results are one
+//! row per line with values separated by spaces, NULL for nulls and
+//! `(empty)` for empty strings; runs of whitespace compare equal.
+//! `skipif <db>` and `onlyif <db>` guard the next record (this database is
+//! `rustdb`), `hash-threshold` is ignored and `halt` ends the script.
+
+use crate::{
+    error::{Error, Result},
+    sql::{
+        engine::{Engine, Session, kv::KVEngine},
+        executor::ResultSet,
+        types::Value,
+    },
+    storage::memory::MemoryEngine,
+};
+
+/// Name `skipif` and `onlyif` match
+const DB: &str = "rustdb";
+
+/// Runs a script on a fresh database, failing with the line of the first mismatch
+pub fn run(script: &str) -> Result<()> {
+    let mut session = KVEngine::new(MemoryEngine::new()).session()?;
+    let lines = script.lines().map(str::trim_end).collect::<Vec<_>>();
+    let mut i = 0;
+    let mut skip = false;
+    while i < lines.len() {
+        let number = i + 1;
+        let words = lines[i].split_whitespace().collect::<Vec<_>>();
+        i += 1;
+        match words.first() {
+            None => continue,
+            Some(word) if word.starts_with('#') => continue,
+            Some(&"halt") => break,
+            Some(&"hash-threshold") => continue,
+            Some(&"skipif") => {
+                skip |= words.get(1) == Some(&DB);
+                continue;
+            }
+            Some(&"onlyif") => {
+                skip |= words.get(1) != Some(&DB);
+                continue;
+            }
+            Some(&"statement") | Some(&"query") => {}
+            Some(_) => return Err(Error::Internal(format!("line {}: unknown record {}", number, lines[number - 1]))),
+        }
+
+        // The SQL runs to a blank line, or to ---- before query results
+        let start = i;
+        while i < lines.len() && !lines[i].trim().is_empty() && lines[i].trim() != "----" {
+            i += 1;
+        }
+        let sql = lines[start..i].join("\n");
+        let mut expected = Vec::new();
+        if i < lines.len() && lines[i].trim() == "----" {
+            i += 1;
+            while i < lines.len() && !lines[i].trim().is_empty() {
+                expected.push(lines[i]);
+                i += 1;
+            }
+        }
+        if std::mem::take(&mut skip) {
+            continue;
+        }
+        check(&mut session, &words, &sql, &expected)
+            .map_err(|message| Error::Internal(format!("line {}: {}\n{}", number, message, sql)))?;
+    }
+    Ok(())
+}
+
+/// Runs one record, describing how its outcome differs from the expected one
+fn check(
+    session: &mut Session<KVEngine<MemoryEngine>>,
+    record: &[&str],
+    sql: &str,
+    expected: &[&str],
+) -> std::result::Result<(), String> {
+    let sql = match sql.trim_end().ends_with(';') {
+        true => sql.to_string(),
+        false => format!("{};", sql),
+    };
+    let result = session.execute(&sql);
+    match record[1..] {
+        ["ok"] => result.map(|_| ()).map_err(|err| format!("statement failed: {}", err)),
+        ["count", count] => match result {
+            Ok(ResultSet::Insert { count: n } | ResultSet::Update { count: n } | ResultSet::Delete { count: n }) => {
+                match n.to_string() == count {
+                    true => Ok(()),
+                    false => Err(format!("statement affected {} rows, expected {}", n, count)),
+                }
+            }
+            Ok(result) => Err(format!("statement returned {:?}, expected a count", result)),
+            Err(err) => Err(format!("statement failed: {}", err)),
+        },
+        ["error", ref message @ ..] => match result {
+            Ok(_) => Err("statement succeeded, expected an error".into()),
+            Err(err) if err.to_string().contains(&message.join(" ")) => Ok(()),
+            Err(err) => Err(format!("error {:?} does not contain {:?}", err.to_string(), message.join(" "))),
+        },
+        [types, ref mode @ ..] if record[0] == "query" => {
+            let (columns, rows) = match result {
+                Ok(ResultSet::Scan { columns, rows, .. }) => (columns, rows),
+                Ok(result) => return Err(format!("query returned {:?}", result)),
+                Err(err) => return Err(format!("query failed: {}", err)),
+            };
+            if types.len() != columns.len() {
+                return Err(format!("query returned {} columns, types list {}", columns.len(), types.len()));
+            }
+            let mut actual = rows
+                .iter()
+                .map(|row| normalize(&row.iter().map(format_value).collect::<Vec<_>>().join(" ")))
+                .collect::<Vec<_>>();
+            let mut expected = expected.iter().map(|line| normalize(line)).collect::<Vec<_>>();
+            match mode.first().copied().unwrap_or("nosort") {
+                "nosort" => {}
+                "rowsort" => {
+                    actual.sort();
+                    expected.sort();
+                }
+                "valuesort" => {
+                    let values = |lines: &[String]| {
+                        let mut values = lines.iter().flat_map(|l| l.split(' ')).map(String::from).collect::<Vec<_>>();
+                        values.sort();
+                        values
+                    };
+                    (actual, expected) = (values(&actual), values(&expected));
+                }
+                mode => return Err(format!("unknown sort mode {}", mode)),
+            }
+            match actual == expected {
+                true => Ok(()),
+                false => Err(format!(
+                    "query results differ\nexpected:\n{}\nactual:\n{}",
+                    expected.join("\n"),
+                    actual.join("\n")
+                )),
+            }
+        }
+        _ => Err(format!("malformed record {}", record.join(" "))),
+    }
+}
+
+/// A value as written in expected results
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) if s.is_empty() => "(empty)".into(),
+        value => value.to_string(),
+    }
+}
+
+/// Collapses runs of whitespace, so columns may be aligned freely
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::run;
+    use crate::error::Result;
+
+    #[test]
+    fn test_slt_files() -> Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/slt");
+        let mut paths = fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "slt"));
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            if let Err(err) = run(&fs::read_to_string(&path)?) {
+                panic!("{}: {}", path.display(), err);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let script = "
+statement ok
+create table t1 (a int primary key)
+
+statement ok
+insert into t1 values (1), (2)
+
+query I
+select a from t1
+----
+1
+3
+";
+        let err = run(script).unwrap_err().to_string();
+        assert!(err.contains("line 8: query results differ"), "{}", err);
+        assert!(run("statement error\nselect * from t1\n").is_ok());
+        assert!(run("statement ok\nselect * from t1\n").unwrap_err().to_string().contains("line 1"));
+        assert!(run("skipif rustdb\nstatement ok\nselect * from t1\n\nhalt\nstatement ok\nbad\n").is_ok());
+        assert!(run("onlyif sqlite\nstatement ok\nselect * from t1\n").is_ok());
+    }
+}
@@ -1,25 +1,76 @@
-use std::{cmp::Ordering, fmt::Display, hash::Hash};
+use alloc::{sync::Arc, vec::Vec};
+use core::{cmp::Ordering, fmt::Display, hash::Hash};
 
 use serde::{Deserialize, Serialize};
 use crate::sql::parser::ast::{Consts, Expression};
 
 /// Supported SQL data types
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     Boolean,
+    /// 16-bit integer (SMALLINT)
+    SmallInt,
+    /// 64-bit integer (INT / INTEGER)
     Integer,
+    /// 128-bit integer (BIGINT)
+    BigInt,
     Float,
     String,
+    Point,
+    Uuid,
+}
+
+impl DataType {
+    /// Returns whether this is one of the integer widths
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Self::SmallInt | Self::Integer | Self::BigInt)
+    }
+
+    /// Returns whether a value can be stored in a column of this type
+    ///
+    /// Integer values are checked against the range of the column's width.
+    pub fn contains(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::SmallInt, Value::Integer(i)) => i16::try_from(*i).is_ok(),
+            (Self::Integer, Value::Integer(i)) => i64::try_from(*i).is_ok(),
+            (Self::BigInt, Value::Integer(_)) => true,
+            (dt, v) => v.datatype().as_ref() == Some(dt),
+        }
+    }
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Boolean => "BOOLEAN",
+            Self::SmallInt => "SMALLINT",
+            Self::Integer => "INTEGER",
+            Self::BigInt => "BIGINT",
+            Self::Float => "FLOAT",
+            Self::String => "STRING",
+            Self::Point => "POINT",
+            Self::Uuid => "UUID",
+        })
+    }
 }
 
 /// Runtime value type for expressions
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     Null,
     Boolean(bool),
-    Integer(i64),
+    /// Integer of any width; columns restrict the range (see `DataType::contains`)
+    Integer(i128),
+    /// NaN and infinities are valid values. NaN equals itself and sorts above
+    /// every number, and -0.0 equals 0.0 (see `compare_floats`); keys hold
+    /// them as `canonical_float`.
     Float(f64),
-    String(String),
+    /// Shared text, so cloning rows (e.g. in joins and aggregates) doesn't copy it
+    String(Arc<str>),
+    /// 2D point (x, y), e.g., longitude and latitude
+    Point(f64, f64),
+    /// UUID stored as its 16 raw bytes
+    Uuid([u8; 16]),
 }
 
 impl Value {
@@ -30,11 +81,24 @@ impl Value {
             Expression::Consts(Consts::Boolean(b)) => Self::Boolean(b),
             Expression::Consts(Consts::Integer(i)) => Self::Integer(i),
             Expression::Consts(Consts::Float(f)) => Self::Float(f),
-            Expression::Consts(Consts::String(s)) => Self::String(s),
+            Expression::Consts(Consts::String(s)) => Self::String(s.into()),
             _ => unreachable!(), // Column expressions are handled separately
         }
     }
 
+    /// Parses a UUID from its text form, with or without hyphens
+    pub fn parse_uuid(s: &str) -> Option<Self> {
+        let hex: Vec<u8> = s.bytes().filter(|b| *b != b'-').collect();
+        if hex.len() != 32 || s.len() - hex.len() > 4 {
+            return None;
+        }
+        let mut bytes = [0; 16];
+        for (i, pair) in hex.chunks(2).enumerate() {
+            bytes[i] = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some(Self::Uuid(bytes))
+    }
+
     /// Returns the data type of the value, or None if it's Null
     pub fn datatype(&self) -> Option<DataType> {
         match self {
@@ -43,12 +107,14 @@ impl Value {
             Self::Integer(_) => Some(DataType::Integer),
             Self::Float(_) => Some(DataType::Float),
             Self::String(_) => Some(DataType::String),
+            Self::Point(..) => Some(DataType::Point),
+            Self::Uuid(_) => Some(DataType::Uuid),
         }
     }
 }
 
 impl Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Value::Null => write!(f, "{}", "NULL"),
             Value::Boolean(b) if *b => write!(f, "{}", "TRUE"),
@@ -56,34 +122,83 @@ impl Display for Value {
             Value::Integer(v) => write!(f, "{}", v),
             Value::Float(v) => write!(f, "{}", v),
             Value::String(v) => write!(f, "{}", v),
+            Value::Point(x, y) => write!(f, "POINT({}, {})", x, y),
+            Value::Uuid(bytes) => {
+                for (i, b) in bytes.iter().enumerate() {
+                    if matches!(i, 4 | 6 | 8 | 10) {
+                        write!(f, "-")?;
+                    }
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
 /// Implements partial ordering for Value comparison (used by ORDER BY)
 impl PartialOrd for Value {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
             (Value::Null, Value::Null) => Some(Ordering::Equal),
             (Value::Null, _) => Some(Ordering::Less),
             (_, Value::Null) => Some(Ordering::Greater),
             (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
             (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
-            (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
-            (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
-            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Float(b)) => Some(compare_floats(*a as f64, *b)),
+            (Value::Float(a), Value::Integer(b)) => Some(compare_floats(*a, *b as f64)),
+            (Value::Float(a), Value::Float(b)) => Some(compare_floats(*a, *b)),
             (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Uuid(a), Value::Uuid(b)) => a.partial_cmp(b),
             (_, _) => None,
         }
     }
 }
 
+/// Orders floats totally, with NaN above every number
+///
+/// Sorting needs a total order, so NaN can't be incomparable here. SQL
+/// comparisons use the same order, so `NaN = NaN` holds.
+pub fn compare_floats(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// The one representative of floats that compare equal: a single NaN, and
+/// 0.0 for -0.0, so equal floats hash and encode alike
+pub fn canonical_float(f: f64) -> f64 {
+    match f {
+        f if f.is_nan() => f64::NAN,
+        // Matches -0.0 too
+        0.0 => 0.0,
+        f => f,
+    }
+}
+
+/// Equality consistent with the order and hash: floats compare by
+/// `compare_floats`, other values of the same type by content
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => compare_floats(*a, *b) == Ordering::Equal,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Point(ax, ay), Value::Point(bx, by)) => {
+                compare_floats(*ax, *bx) == Ordering::Equal && compare_floats(*ay, *by) == Ordering::Equal
+            }
+            (Value::Uuid(a), Value::Uuid(b)) => a == b,
+            (_, _) => false,
+        }
+    }
+}
+
 /// Implements Hash for Value to enable use as HashMap key (required for GROUP BY)
 ///
 /// Uses a type discriminator byte (write_u8) to distinguish between variants,
 /// then delegates to the underlying type's hash implementation.
 impl Hash for Value {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         match self {
             Value::Null => state.write_u8(0),
             Value::Boolean(v) => {
@@ -96,12 +211,21 @@ impl Hash for Value {
             }
             Value::Float(v) => {
                 state.write_u8(3);
-                v.to_be_bytes().hash(state);
+                canonical_float(*v).to_be_bytes().hash(state);
             }
             Value::String(v) => {
                 state.write_u8(4);
                 v.hash(state);
             }
+            Value::Point(x, y) => {
+                state.write_u8(5);
+                canonical_float(*x).to_be_bytes().hash(state);
+                canonical_float(*y).to_be_bytes().hash(state);
+            }
+            Value::Uuid(v) => {
+                state.write_u8(6);
+                v.hash(state);
+            }
         }
     }
 }
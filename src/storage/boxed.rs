@@ -0,0 +1,149 @@
+//! Boxed storage engines - `Engine` as a trait object
+//!
+//! `Engine::EngineIterator` is a generic associated type and `scan` takes
+//! `impl RangeBounds`, so `dyn Engine` isn't possible. `DynEngine` is the
+//! object-safe form of the trait: scans take explicit bounds and return a
+//! boxed iterator. Every `Engine` is a `DynEngine`, and a boxed `DynEngine`
+//! is an `Engine` again, so a `BoxEngine` can go anywhere an engine can,
+//! wrappers (`CompressedEngine`, `EncryptedEngine`) and `Database` included:
+//!
+//! ```ignore
+//! let engine: BoxEngine = Box::new(MemoryEngine::new());
+//! let engine: BoxEngine = Box::new(CompressedEngine::new(engine, Compression::Lz4));
+//! let db = Database::new(engine);
+//! ```
+//!
+//! Each call goes through a vtable and each scan allocates its iterator,
+//! which generic code avoids; boxing pays off where the engine stack is
+//! only known at runtime.
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::{
+    error::Result,
+    storage::engine::{Engine, EngineIterator, Syncer},
+};
+
+/// Object-safe storage engine interface, see `Engine`
+pub trait DynEngine {
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    fn delete(&mut self, key: Vec<u8>) -> Result<()>;
+    fn scan(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Box<dyn EngineIterator + '_>;
+    fn flush(&mut self) -> Result<()>;
+    fn begin_flush(&mut self) -> Result<Syncer>;
+    fn compact(&mut self) -> Result<()>;
+}
+
+/// A storage engine chosen at runtime, shareable across threads like the
+/// engines `grpc` serves
+pub type BoxEngine = Box<dyn DynEngine + Send + Sync>;
+
+impl<E: Engine> DynEngine for E {
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        Engine::set(self, key, value)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Engine::get(self, key)
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        Engine::delete(self, key)
+    }
+
+    fn scan(&self, start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Box<dyn EngineIterator + '_> {
+        Box::new(Engine::scan(self, (start, end)))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Engine::flush(self)
+    }
+
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        Engine::begin_flush(self)
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        Engine::compact(self)
+    }
+}
+
+impl<T: DynEngine + ?Sized> Engine for Box<T> {
+    type EngineIterator<'a>
+        = Box<dyn EngineIterator + 'a>
+    where
+        T: 'a;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        (**self).set(key, value)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        (**self).delete(key)
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        (**self).scan(range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        (**self).begin_flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        (**self).compact()
+    }
+}
+
+impl<I: EngineIterator + ?Sized> EngineIterator for Box<I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::BoxEngine;
+    use crate::{
+        db::Database,
+        error::Result,
+        sql::executor::ResultSet,
+        storage::{
+            compress::{CompressedEngine, Compression},
+            engine::Engine,
+            memory::MemoryEngine,
+            testutil::test_engine_conformance,
+        },
+    };
+
+    #[test]
+    fn test_box_engine() -> Result<()> {
+        test_engine_conformance(|| -> BoxEngine { Box::new(MemoryEngine::new()) })?;
+
+        // Wrappers layer over boxed engines, and box again
+        let engine: BoxEngine = Box::new(MemoryEngine::new());
+        let mut engine: BoxEngine = Box::new(CompressedEngine::new(engine, Compression::None));
+        engine.set(b"a".to_vec(), b"1".to_vec())?;
+        engine.set(b"b".to_vec(), b"2".to_vec())?;
+        engine.set(b"c".to_vec(), b"3".to_vec())?;
+        engine.delete(b"c".to_vec())?;
+        assert_eq!(engine.get(b"a".to_vec())?, Some(b"1".to_vec()));
+        let keys = engine.scan(b"a".to_vec()..).rev().map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(keys, vec![b"b".to_vec(), b"a".to_vec()]);
+        assert_eq!(engine.scan_prefix(b"b".to_vec()).count(), 1);
+
+        let mut s = Database::new(engine).session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("insert into t1 values (1, 'x'), (2, 'y');")?;
+        match s.execute("select * from t1 where a > 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 1),
+            result => panic!("unexpected result {:?}", result),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! Value compression as an engine wrapper
+//!
+//! `CompressedEngine` compresses values on write and decompresses them on
+//! read, over any storage engine. Every stored value starts with a one-byte
+//! header naming its codec, so records written with different settings (or
+//! left uncompressed because they didn't shrink) can be read back side by
+//! side. Keys are left as-is, since engines order and scan by them.
+//!
+//! Codecs are behind features: `lz4` (LZ4 block format) and `snappy`.
+//! The wrapper must own the data from the start; values written to the
+//! inner engine directly lack the header.
+
+use std::ops::RangeBounds;
+
+use crate::{error::{Error, Result}, storage::engine::{Engine, EngineIterator, EngineOptions, Syncer}};
+
+/// Value compression codec
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Compression {
+    /// Store values uncompressed (still with a header)
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "snappy")]
+    Snappy,
+}
+
+impl Compression {
+    /// Parses a codec name, e.g. from a config file; codecs built without
+    /// their feature are unknown
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_ref() {
+            "none" => Self::None,
+            #[cfg(feature = "lz4")]
+            "lz4" => Self::Lz4,
+            #[cfg(feature = "snappy")]
+            "snappy" => Self::Snappy,
+            _ => return Err(Error::Internal(format!("unknown compression {}", name))),
+        })
+    }
+
+    /// Header byte of records written with this codec
+    fn tag(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 1,
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => 2,
+        }
+    }
+
+    fn compress(&self, value: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(value.to_vec()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(value)),
+            #[cfg(feature = "snappy")]
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(value)
+                .map_err(|e| Error::Internal(e.to_string())),
+        }
+    }
+}
+
+/// Encodes a value as [codec tag][payload], falling back to raw if compression doesn't help
+fn encode(compression: Compression, value: &[u8]) -> Result<Vec<u8>> {
+    let compressed = compression.compress(value)?;
+    let (tag, payload) = match compressed.len() < value.len() {
+        true => (compression.tag(), compressed),
+        false => (Compression::None.tag(), value.to_vec()),
+    };
+    let mut record = Vec::with_capacity(payload.len() + 1);
+    record.push(tag);
+    record.extend(payload);
+    Ok(record)
+}
+
+/// Decodes a stored record by its codec tag
+fn decode(record: Vec<u8>) -> Result<Vec<u8>> {
+    let Some((tag, payload)) = record.split_first() else {
+        return Err(Error::Internal("compressed record is missing its header".into()));
+    };
+    match tag {
+        0 => Ok(payload.to_vec()),
+        #[cfg(feature = "lz4")]
+        1 => lz4_flex::decompress_size_prepended(payload).map_err(|e| Error::Internal(e.to_string())),
+        #[cfg(feature = "snappy")]
+        2 => snap::raw::Decoder::new()
+            .decompress_vec(payload)
+            .map_err(|e| Error::Internal(e.to_string())),
+        tag => Err(Error::Internal(format!(
+            "value compressed with codec {}, which this build doesn't support",
+            tag
+        ))),
+    }
+}
+
+/// Storage engine wrapper that compresses values
+pub struct CompressedEngine<E: Engine> {
+    inner: E,
+    compression: Compression,
+}
+
+impl<E: Engine> CompressedEngine<E> {
+    pub fn new(inner: E, compression: Compression) -> Self {
+        Self { inner, compression }
+    }
+
+    /// Wraps an engine with the compression setting of the options
+    pub fn with_options(inner: E, options: &EngineOptions) -> Self {
+        Self::new(inner, options.compression)
+    }
+}
+
+impl<E: Engine> Engine for CompressedEngine<E> {
+    type EngineIterator<'a>
+        = CompressedIterator<E::EngineIterator<'a>>
+    where
+        Self: 'a;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let record = encode(self.compression, &value)?;
+        self.inner.set(key, record)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)?.map(decode).transpose()
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        self.inner.begin_flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.inner.compact()
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        CompressedIterator { inner: self.inner.scan(range) }
+    }
+}
+
+/// Iterator decompressing the values of an inner engine iterator
+pub struct CompressedIterator<I> {
+    inner: I,
+}
+
+impl<I: EngineIterator> CompressedIterator<I> {
+    fn map(item: Result<(Vec<u8>, Vec<u8>)>) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (key, record) = item?;
+        Ok((key, decode(record)?))
+    }
+}
+
+impl<I: EngineIterator> EngineIterator for CompressedIterator<I> {}
+
+impl<I: EngineIterator> Iterator for CompressedIterator<I> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(Self::map)
+    }
+}
+
+impl<I: EngineIterator> DoubleEndedIterator for CompressedIterator<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(Self::map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompressedEngine, Compression};
+    use crate::{
+        error::Result,
+        storage::{engine::Engine, memory::MemoryEngine, testutil::test_engine_conformance},
+    };
+
+    fn check_codec(compression: Compression) -> Result<()> {
+        test_engine_conformance(|| CompressedEngine::new(MemoryEngine::new(), compression))?;
+        let mut eng = CompressedEngine::new(MemoryEngine::new(), compression);
+        let big = b"abcdefgh".repeat(100);
+        eng.set(b"a".to_vec(), big.clone())?;
+        eng.set(b"b".to_vec(), b"x".to_vec())?;
+        eng.set(b"c".to_vec(), vec![])?;
+        assert_eq!(eng.get(b"a".to_vec())?, Some(big.clone()));
+        assert_eq!(eng.get(b"c".to_vec())?, Some(vec![]));
+
+        let items = eng.scan(..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            items,
+            vec![(b"a".to_vec(), big.clone()), (b"b".to_vec(), b"x".to_vec()), (b"c".to_vec(), vec![])]
+        );
+        let (last, _) = eng.scan_prefix(b"b".to_vec()).next_back().unwrap()?;
+        assert_eq!(last, b"b".to_vec());
+
+        // Repetitive values shrink, unless compression is off
+        let stored = eng.inner.get(b"a".to_vec())?.unwrap();
+        assert_eq!(stored.len() < big.len(), compression != Compression::None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_engine() -> Result<()> {
+        check_codec(Compression::None)?;
+        #[cfg(feature = "lz4")]
+        check_codec(Compression::Lz4)?;
+        #[cfg(feature = "snappy")]
+        check_codec(Compression::Snappy)?;
+
+        // Stored values without a header are rejected
+        let mut eng = CompressedEngine::with_options(MemoryEngine::new(), &Default::default());
+        eng.inner.set(b"raw".to_vec(), vec![])?;
+        assert!(eng.get(b"raw".to_vec()).is_err());
+        Ok(())
+    }
+}
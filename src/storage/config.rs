@@ -0,0 +1,254 @@
+//! Storage engine selection at runtime
+//!
+//! Binaries and servers pick their engine from a config file or flags
+//! rather than a type parameter: `open` returns an `AnyEngine`, which
+//! dispatches to the engine it was opened with, so one
+//! `Database<AnyEngine>` type serves every choice.
+//!
+//! Config files hold `key = value` lines, with `#` comments:
+//!
+//! ```text
+//! # rustdb.conf
+//! engine = memory
+//! path = /var/lib/rustdb
+//! compression = lz4
+//! durability = commit
+//! ```
+//!
+//! Only the `memory` engine exists so far, and it ignores the path; kinds
+//! of file-based engines are added along with the engines.
+
+use std::{
+    fs,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        compress::{CompressedEngine, CompressedIterator, Compression},
+        engine::{Durability, Engine, EngineIterator, EngineOptions, Syncer},
+        memory::{MemoryEngine, MemoryEngineIterator},
+    },
+};
+
+/// Kind of storage engine to open
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EngineKind {
+    #[default]
+    Memory,
+}
+
+impl EngineKind {
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_ref() {
+            "memory" => Self::Memory,
+            _ => return Err(Error::Internal(format!("unknown storage engine {}", name))),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+        }
+    }
+}
+
+/// Storage settings of a config file
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub engine: EngineKind,
+    /// Where file-based engines keep their data
+    pub path: Option<PathBuf>,
+    pub options: EngineOptions,
+}
+
+impl Config {
+    /// Parses config file text
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(Error::Internal(format!("line {}: expected key = value", i + 1)));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "engine" => config.engine = EngineKind::from_name(value)?,
+                "path" => config.path = Some(value.into()),
+                "compression" => config.options.compression = Compression::from_name(value)?,
+                "durability" => config.options.durability = Durability::from_name(value)?,
+                key => return Err(Error::Internal(format!("line {}: unknown setting {}", i + 1, key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and parses a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Opens the configured engine
+    pub fn open(&self) -> Result<AnyEngine> {
+        let path = self.path.as_deref().unwrap_or(Path::new("."));
+        open(path, self.engine, &self.options)
+    }
+}
+
+/// Opens an engine of the given kind, with its data at `path` (unused by
+/// the memory engine)
+pub fn open(_path: &Path, kind: EngineKind, options: &EngineOptions) -> Result<AnyEngine> {
+    match kind {
+        EngineKind::Memory if options.compression == Compression::None => Ok(AnyEngine::Memory(MemoryEngine::new())),
+        EngineKind::Memory => Ok(AnyEngine::Compressed(CompressedEngine::with_options(MemoryEngine::new(), options))),
+    }
+}
+
+/// A storage engine chosen at runtime
+pub enum AnyEngine {
+    Memory(MemoryEngine),
+    Compressed(CompressedEngine<MemoryEngine>),
+}
+
+impl Engine for AnyEngine {
+    type EngineIterator<'a> = AnyEngineIterator<'a>;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Memory(engine) => engine.set(key, value),
+            Self::Compressed(engine) => engine.set(key, value),
+        }
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Memory(engine) => engine.get(key),
+            Self::Compressed(engine) => engine.get(key),
+        }
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Memory(engine) => engine.delete(key),
+            Self::Compressed(engine) => engine.delete(key),
+        }
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        match self {
+            Self::Memory(engine) => AnyEngineIterator::Memory(engine.scan(range)),
+            Self::Compressed(engine) => AnyEngineIterator::Compressed(engine.scan(range)),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Memory(engine) => engine.flush(),
+            Self::Compressed(engine) => engine.flush(),
+        }
+    }
+
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        match self {
+            Self::Memory(engine) => engine.begin_flush(),
+            Self::Compressed(engine) => engine.begin_flush(),
+        }
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        match self {
+            Self::Memory(engine) => engine.compact(),
+            Self::Compressed(engine) => engine.compact(),
+        }
+    }
+}
+
+/// Iterator of an `AnyEngine`, of the engine it dispatches to
+pub enum AnyEngineIterator<'a> {
+    Memory(MemoryEngineIterator<'a>),
+    Compressed(CompressedIterator<MemoryEngineIterator<'a>>),
+}
+
+impl EngineIterator for AnyEngineIterator<'_> {}
+
+impl Iterator for AnyEngineIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Memory(iter) => iter.next(),
+            Self::Compressed(iter) => iter.next(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for AnyEngineIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Memory(iter) => iter.next_back(),
+            Self::Compressed(iter) => iter.next_back(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{AnyEngine, Config, EngineKind};
+    use crate::{
+        db::Database,
+        error::Result,
+        storage::{
+            compress::Compression,
+            engine::{Durability, Engine},
+            testutil::test_engine_conformance,
+        },
+    };
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let config = Config::parse("# storage\nengine = MEMORY\npath = /tmp/db  # unused\n\ncompression = none\n")?;
+        assert_eq!(config.engine, EngineKind::Memory);
+        assert_eq!(config.path.as_deref(), Some(Path::new("/tmp/db")));
+        assert_eq!(config.options.compression, Compression::None);
+        assert_eq!(config.options.durability, Durability::Commit);
+        assert!(matches!(config.open()?, AnyEngine::Memory(_)));
+        assert_eq!(Config::parse("durability = Off")?.options.durability, Durability::Off);
+
+        // Databases opened from a config flush as it says
+        let db = Database::from_config(&Config::parse("durability = off")?)?;
+        assert_eq!(db.kv_txn()?.durability(), Durability::Off);
+        assert_eq!(Database::from_config(&config)?.kv_txn()?.durability(), Durability::Commit);
+
+        // Kinds of engines that don't exist yet are unknown, like any other name
+        let invalid = ["engine memory", "engine = btree", "engine = lsm", "cache = 1", "compression = zstd", "durability = never"];
+        for text in invalid {
+            assert!(Config::parse(text).is_err(), "{}", text);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_open() -> Result<()> {
+        let config = Config::parse(if cfg!(feature = "lz4") { "compression = lz4" } else { "" })?;
+        test_engine_conformance(|| config.open().unwrap())?;
+        let mut engine = config.open()?;
+        engine.set(b"a".to_vec(), b"x".repeat(100))?;
+        engine.set(b"b".to_vec(), vec![])?;
+        assert_eq!(engine.get(b"a".to_vec())?, Some(b"x".repeat(100)));
+        assert_eq!(engine.scan_prefix(b"b".to_vec()).next_back().transpose()?, Some((b"b".to_vec(), vec![])));
+
+        let db = Database::open(Path::new("data"), EngineKind::Memory, &config.options)?;
+        let mut s = db.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("insert into t1 values (1);")?;
+        assert!(s.execute("select * from t1;").is_ok());
+        Ok(())
+    }
+}
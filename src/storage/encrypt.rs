@@ -0,0 +1,253 @@
+//! Encryption at rest as an engine wrapper (feature `encryption`)
+//!
+//! `EncryptedEngine` encrypts values with AES-256-GCM before they reach the
+//! inner engine; each record is [nonce (12 bytes)][ciphertext + tag], with a
+//! fresh random nonce per write. Tampered or foreign records fail to decrypt.
+//!
+//! Keys are stored in plaintext by default, since engines order and scan by
+//! them. With `with_encrypted_keys` they're encrypted too, using AES-256-GCM-SIV
+//! with a fixed nonce: equal keys encrypt equally, so point reads still work,
+//! but key order is lost and scans decrypt and sort the whole keyspace.
+//!
+//! Value and key ciphers use separate subkeys derived from the user's key.
+
+use std::ops::RangeBounds;
+
+use aes_gcm::{
+    Aes256Gcm, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aes::{
+        Aes256,
+        cipher::{BlockEncrypt, generic_array::GenericArray},
+    },
+};
+use aes_gcm_siv::Aes256GcmSiv;
+
+use crate::{
+    error::{Error, Result},
+    storage::engine::{Engine, EngineIterator, Syncer},
+};
+
+const NONCE_LEN: usize = 12;
+
+/// Storage engine wrapper that encrypts values, and optionally keys
+pub struct EncryptedEngine<E: Engine> {
+    inner: E,
+    values: Aes256Gcm,
+    /// Key cipher, when keys are encrypted
+    keys: Option<Aes256GcmSiv>,
+}
+
+impl<E: Engine> EncryptedEngine<E> {
+    /// Wraps an engine, encrypting values with the given 256-bit key
+    pub fn new(inner: E, key: [u8; 32]) -> Self {
+        Self {
+            inner,
+            values: Aes256Gcm::new(&subkey(&key, 0).into()),
+            keys: None,
+        }
+    }
+
+    /// Wraps an engine, encrypting both keys and values (see module docs for the cost)
+    pub fn with_encrypted_keys(inner: E, key: [u8; 32]) -> Self {
+        Self {
+            keys: Some(Aes256GcmSiv::new(&subkey(&key, 1).into())),
+            ..Self::new(inner, key)
+        }
+    }
+
+    fn seal_key(&self, key: Vec<u8>) -> Result<Vec<u8>> {
+        match &self.keys {
+            Some(cipher) => cipher
+                .encrypt(&[0; NONCE_LEN].into(), key.as_slice())
+                .map_err(|_| Error::Internal("failed to encrypt key".into())),
+            None => Ok(key),
+        }
+    }
+
+    fn seal_value(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .values
+            .encrypt(&nonce, value)
+            .map_err(|_| Error::Internal("failed to encrypt value".into()))?;
+        let mut record = nonce.to_vec();
+        record.extend(ciphertext);
+        Ok(record)
+    }
+}
+
+fn open_key(cipher: &Aes256GcmSiv, key: Vec<u8>) -> Result<Vec<u8>> {
+    cipher
+        .decrypt(&[0; NONCE_LEN].into(), key.as_slice())
+        .map_err(|_| Error::Internal("failed to decrypt key".into()))
+}
+
+fn open_value(values: &Aes256Gcm, record: Vec<u8>) -> Result<Vec<u8>> {
+    if record.len() < NONCE_LEN {
+        return Err(Error::Internal("encrypted record is too short".into()));
+    }
+    let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+    values
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Internal("failed to decrypt value, wrong key or corrupt data".into()))
+}
+
+/// Derives a cipher subkey from the user key (two AES blocks of a domain-separated counter)
+fn subkey(key: &[u8; 32], domain: u8) -> [u8; 32] {
+    let aes = Aes256::new(key.into());
+    let mut out = [0; 32];
+    for (i, half) in out.chunks_mut(16).enumerate() {
+        let mut block = GenericArray::from([0; 16]);
+        block[0] = domain;
+        block[1] = i as u8;
+        aes.encrypt_block(&mut block);
+        half.copy_from_slice(&block);
+    }
+    out
+}
+
+impl<E: Engine> Engine for EncryptedEngine<E> {
+    type EngineIterator<'a>
+        = EncryptedIterator<'a, E::EngineIterator<'a>>
+    where
+        Self: 'a;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let record = self.seal_value(&value)?;
+        let key = self.seal_key(key)?;
+        self.inner.set(key, record)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let key = self.seal_key(key)?;
+        self.inner.get(key)?.map(|r| open_value(&self.values, r)).transpose()
+    }
+
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        let key = self.seal_key(key)?;
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        self.inner.begin_flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.inner.compact()
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        match &self.keys {
+            None => EncryptedIterator::Ordered { inner: self.inner.scan(range), values: &self.values },
+            // Encrypted keys are unordered: decrypt everything, then filter and sort
+            Some(keys) => {
+                let mut items = self
+                    .inner
+                    .scan(..)
+                    .map(|item| {
+                        let (key, record) = item?;
+                        Ok((open_key(keys, key)?, open_value(&self.values, record)?))
+                    })
+                    .filter(|item| item.as_ref().map_or(true, |(key, _)| range.contains(key)))
+                    .collect::<Vec<_>>();
+                items.sort_by(|a, b| match (a, b) {
+                    (Ok((a, _)), Ok((b, _))) => a.cmp(b),
+                    // Errors go first, so they aren't missed
+                    (Err(_), _) => std::cmp::Ordering::Less,
+                    (_, Err(_)) => std::cmp::Ordering::Greater,
+                });
+                EncryptedIterator::Sorted(items.into_iter())
+            }
+        }
+    }
+}
+
+/// Iterator decrypting the entries of an inner engine scan
+pub enum EncryptedIterator<'a, I> {
+    /// Plaintext keys: decrypt values as the inner scan goes
+    Ordered { inner: I, values: &'a Aes256Gcm },
+    /// Encrypted keys: entries decrypted and sorted up front
+    Sorted(std::vec::IntoIter<Result<(Vec<u8>, Vec<u8>)>>),
+}
+
+impl<'a, I: EngineIterator> EngineIterator for EncryptedIterator<'a, I> {}
+
+impl<'a, I: EngineIterator> Iterator for EncryptedIterator<'a, I> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Ordered { inner, values } => inner.next().map(|item| {
+                let (key, record) = item?;
+                Ok((key, open_value(values, record)?))
+            }),
+            Self::Sorted(items) => items.next(),
+        }
+    }
+}
+
+impl<'a, I: EngineIterator> DoubleEndedIterator for EncryptedIterator<'a, I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Ordered { inner, values } => inner.next_back().map(|item| {
+                let (key, record) = item?;
+                Ok((key, open_value(values, record)?))
+            }),
+            Self::Sorted(items) => items.next_back(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EncryptedEngine;
+    use crate::{
+        error::Result,
+        storage::{engine::Engine, memory::MemoryEngine, testutil::test_engine_conformance},
+    };
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn check_engine(mut eng: EncryptedEngine<MemoryEngine>) -> Result<()> {
+        eng.set(b"b".to_vec(), b"secret2".to_vec())?;
+        eng.set(b"a".to_vec(), b"secret1".to_vec())?;
+        eng.set(b"c".to_vec(), vec![])?;
+        assert_eq!(eng.get(b"a".to_vec())?, Some(b"secret1".to_vec()));
+        assert_eq!(eng.get(b"x".to_vec())?, None);
+
+        let items = eng.scan(b"a".to_vec()..b"c".to_vec()).collect::<Result<Vec<_>>>()?;
+        assert_eq!(items, vec![(b"a".to_vec(), b"secret1".to_vec()), (b"b".to_vec(), b"secret2".to_vec())]);
+        let (last, _) = eng.scan(..).next_back().unwrap()?;
+        assert_eq!(last, b"c".to_vec());
+
+        eng.delete(b"a".to_vec())?;
+        assert_eq!(eng.get(b"a".to_vec())?, None);
+
+        // Nothing readable reaches the inner engine
+        for (key, value) in eng.inner.scan(..).collect::<Result<Vec<_>>>()? {
+            assert!(!value.windows(6).any(|w| w == b"secret"));
+            assert_eq!(eng.keys.is_some(), key != b"b" && key != b"c");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_engine() -> Result<()> {
+        check_engine(EncryptedEngine::new(MemoryEngine::new(), KEY))?;
+        check_engine(EncryptedEngine::with_encrypted_keys(MemoryEngine::new(), KEY))?;
+        test_engine_conformance(|| EncryptedEngine::new(MemoryEngine::new(), KEY))?;
+        test_engine_conformance(|| EncryptedEngine::with_encrypted_keys(MemoryEngine::new(), KEY))?;
+
+        // A different key can't read the data
+        let mut eng = EncryptedEngine::new(MemoryEngine::new(), KEY);
+        eng.set(b"a".to_vec(), b"value".to_vec())?;
+        let other = EncryptedEngine::new(eng.inner, [8; 32]);
+        assert!(other.get(b"a".to_vec()).is_err());
+        Ok(())
+    }
+}
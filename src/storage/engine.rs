@@ -1,122 +1,173 @@
-use std::ops::{Bound, RangeBounds};
+use std::{
+    ops::{Bound, RangeBounds},
+    time::Duration,
+};
+
+use crate::{
+    error::{Error, Result},
+    storage::compress::Compression,
+};
+
+/// Options for setting up a storage engine
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    /// Value compression, applied by wrapping the engine in `CompressedEngine`
+    pub compression: Compression,
+    /// When transactions flush their writes, see `Durability`
+    pub durability: Durability,
+}
+
+/// Interval of `Durability::Periodic` when set by name
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When MVCC transactions flush the engine (`Engine::flush`), trading
+/// safety against a crash for write throughput
+///
+/// Only engines that buffer writes, like a disk engine's write-ahead log,
+/// are affected; for the memory engine a crash loses everything anyway.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Durability {
+    /// Every write is flushed before it returns: a crash loses nothing
+    /// written, though writes of unfinished transactions are discarded
+    Always,
+    /// Each commit is flushed before it returns: a crash loses no committed
+    /// transaction
+    #[default]
+    Commit,
+    /// A commit is flushed once the last flush is older than the interval: a
+    /// crash loses at most the commits of one interval
+    Periodic(Duration),
+    /// Nothing is flushed until `Mvcc::close`: a crash loses whatever the
+    /// engine hadn't written out on its own
+    Off,
+}
+
+impl Durability {
+    /// Parses `always`, `commit`, `periodic` (every `DEFAULT_SYNC_INTERVAL`) or `off`
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_ref() {
+            "always" => Self::Always,
+            "commit" => Self::Commit,
+            "periodic" => Self::Periodic(DEFAULT_SYNC_INTERVAL),
+            "off" => Self::Off,
+            _ => return Err(Error::Internal(format!("unknown durability {}", name))),
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Commit => "commit",
+            Self::Periodic(_) => "periodic",
+            Self::Off => "off",
+        }
+    }
+}
 
-use crate::error::Result;
+/// The rest of a flush begun by `Engine::begin_flush`, making its writes durable
+pub type Syncer = Box<dyn FnOnce() -> Result<()> + Send>;
 
 /// Abstract storage engine interface (byte-level operations)
 ///
-/// Different from sql::engine::Engine which operates on tables.
+/// Different from sql::engine::Engine which operates on tables. Reads take
+/// `&self`, so callers sharing an engine behind a `RwLock` (like `Mvcc`) can
+/// serve many readers at once; only writes need exclusive access.
 pub trait Engine {
     type EngineIterator<'a>: EngineIterator
     where
         Self: 'a;
 
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
-    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
     fn delete(&mut self, key: Vec<u8>) -> Result<()>;
-    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_>;
-
-    /// Prefix scan using lexicographic ordering
-    ///
-    /// Converts prefix scan to range scan by incrementing the last character.
-    /// For example, prefix "apple" becomes range ["apple", "applf").
-    fn scan_prefix(&mut self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
-        let start = Bound::Included(prefix.clone());
-        let mut bound_prefix = prefix.clone();
-        if let Some(last) = bound_prefix.iter_mut().last() {
-            *last += 1;
-        };
-        let end = Bound::Excluded(bound_prefix);
-        self.scan((start, end))
-    }
-}
-
-/// Storage engine iterator trait (supports reverse traversal)
-pub trait EngineIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
-
-#[cfg(test)]
-mod tests {
-    use super::Engine;
-    use crate::{error::Result, storage::memory::MemoryEngine};
-    use std::ops::Bound;
-
-    fn test_point_opt(mut eng: impl Engine) -> Result<()> {
-        assert_eq!(eng.get(b"not exist".to_vec())?, None);
-
-        eng.set(b"aa".to_vec(), vec![1, 2, 3, 4])?;
-        assert_eq!(eng.get(b"aa".to_vec())?, Some(vec![1, 2, 3, 4]));
-
-        eng.set(b"aa".to_vec(), vec![5, 6, 7, 8])?;
-        assert_eq!(eng.get(b"aa".to_vec())?, Some(vec![5, 6, 7, 8]));
-
-        eng.delete(b"aa".to_vec())?;
-        assert_eq!(eng.get(b"aa".to_vec())?, None);
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_>;
 
-        assert_eq!(eng.get(b"".to_vec())?, None);
-        eng.set(b"".to_vec(), vec![])?;
-        assert_eq!(eng.get(b"".to_vec())?, Some(vec![]));
-
-        eng.set(b"cc".to_vec(), vec![5, 6, 7, 8])?;
-        assert_eq!(eng.get(b"cc".to_vec())?, Some(vec![5, 6, 7, 8]));
+    /// Writes buffered changes to durable storage; engines that don't buffer
+    /// (like the memory engine) have nothing to do
+    fn flush(&mut self) -> Result<()> {
         Ok(())
     }
 
-    fn test_scan(mut eng: impl Engine) -> Result<()> {
-        eng.set(b"nnaes".to_vec(), b"value1".to_vec())?;
-        eng.set(b"amhue".to_vec(), b"value2".to_vec())?;
-        eng.set(b"meeae".to_vec(), b"value3".to_vec())?;
-        eng.set(b"uujeh".to_vec(), b"value4".to_vec())?;
-        eng.set(b"anehe".to_vec(), b"value5".to_vec())?;
-
-        let start = Bound::Included(b"a".to_vec());
-        let end = Bound::Excluded(b"e".to_vec());
-
-        let mut iter = eng.scan((start.clone(), end.clone()));
-        let (key1, _) = iter.next().expect("no value founded")?;
-        assert_eq!(key1, b"amhue".to_vec());
+    /// Starts a flush that finishes without the engine: writes buffered
+    /// changes out, and returns what makes them durable (e.g. an fsync of a
+    /// cloned file handle) for the caller to run once it has let go of the
+    /// engine, so writers needn't wait on the disk. By default the whole
+    /// flush runs here.
+    fn begin_flush(&mut self) -> Result<Syncer> {
+        self.flush()?;
+        Ok(Box::new(|| Ok(())))
+    }
 
-        let (key2, _) = iter.next().expect("no value founded")?;
-        assert_eq!(key2, b"anehe".to_vec());
-        drop(iter);
+    /// Reclaims the space of deleted and overwritten keys, e.g. by merging
+    /// an LSM tree's runs or rewriting a log; engines that reclaim it as they
+    /// go (like the memory engine) have nothing to do
+    fn compact(&mut self) -> Result<()> {
+        Ok(())
+    }
 
-        let start = Bound::Included(b"b".to_vec());
-        let end = Bound::Excluded(b"z".to_vec());
-        let mut iter2 = eng.scan((start, end));
+    /// Prefix scan using lexicographic ordering, see `prefix_range`
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
+        self.scan(prefix_range(prefix))
+    }
+}
 
-        let (key3, _) = iter2.next_back().expect("no value founded")?;
-        assert_eq!(key3, b"uujeh".to_vec());
+/// Range of the keys starting with a prefix
+///
+/// The end bound is the prefix with its last byte incremented, e.g. prefix
+/// "apple" becomes range ["apple", "applf"). Trailing 0xFF bytes can't be
+/// incremented, so they're dropped and the carry goes to the byte before:
+/// [0x61, 0xFF] ends at [0x62]. A prefix of only 0xFF bytes (or an empty one)
+/// runs to the end of the keyspace.
+pub fn prefix_range(prefix: Vec<u8>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let mut end = prefix.clone();
+    while end.last() == Some(&0xff) {
+        end.pop();
+    }
+    let end = match end.last_mut() {
+        Some(last) => {
+            *last += 1;
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    };
+    (Bound::Included(prefix), end)
+}
 
-        let (key4, _) = iter2.next_back().expect("no value founded")?;
-        assert_eq!(key4, b"nnaes".to_vec());
+/// FNV-1a, stable across builds, for hashes that are stored or decide where
+/// keys are stored
+pub(crate) fn fnv1a(key: &[u8], seed: u64) -> u64 {
+    key.iter().fold(seed, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
 
-        let (key5, _) = iter2.next_back().expect("no value founded")?;
-        assert_eq!(key5, b"meeae".to_vec());
+/// Storage engine iterator trait (supports reverse traversal)
+pub trait EngineIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
 
-        Ok(())
-    }
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
 
-    fn test_scan_prefix(mut eng: impl Engine) -> Result<()> {
-        eng.set(b"ccnaes".to_vec(), b"value1".to_vec())?;
-        eng.set(b"camhue".to_vec(), b"value2".to_vec())?;
-        eng.set(b"deeae".to_vec(), b"value3".to_vec())?;
-        eng.set(b"eeujeh".to_vec(), b"value4".to_vec())?;
-        eng.set(b"canehe".to_vec(), b"value5".to_vec())?;
-        eng.set(b"aanehe".to_vec(), b"value6".to_vec())?;
-
-        let prefix = b"ca".to_vec();
-        let mut iter = eng.scan_prefix(prefix);
-        let (key1, _) = iter.next().transpose()?.unwrap();
-        assert_eq!(key1, b"camhue".to_vec());
-        let (key2, _) = iter.next().transpose()?.unwrap();
-        assert_eq!(key2, b"canehe".to_vec());
+    use super::prefix_range;
+    use crate::{
+        error::Result,
+        storage::{memory::MemoryEngine, testutil::test_engine_conformance},
+    };
 
-        Ok(())
+    #[test]
+    fn test_prefix_range() {
+        for (prefix, end) in [
+            (&b"apple"[..], Bound::Excluded(b"applf".to_vec())),
+            (&[0x61, 0xff], Bound::Excluded(vec![0x62])),
+            (&[0x00, 0x61, 0xff, 0xff], Bound::Excluded(vec![0x00, 0x62])),
+            (&[0xfe, 0xff], Bound::Excluded(vec![0xff])),
+            (&[0xff, 0xff], Bound::Unbounded),
+            (&[], Bound::Unbounded),
+        ] {
+            assert_eq!(prefix_range(prefix.to_vec()), (Bound::Included(prefix.to_vec()), end), "{:x?}", prefix);
+        }
     }
 
     #[test]
     fn test_memory() -> Result<()> {
-        test_point_opt(MemoryEngine::new())?;
-        test_scan(MemoryEngine::new())?;
-        test_scan_prefix(MemoryEngine::new())?;
-        Ok(())
+        test_engine_conformance(MemoryEngine::new)
     }
 }
@@ -0,0 +1,475 @@
+//! MVCC garbage collection
+//!
+//! Every write adds a version of its key, and old versions stay until they
+//! are collected. A version can go once a newer committed one hides it from
+//! every snapshot that can still be read: those of active transactions, and
+//! AS OF reads of the last `retain_versions` versions. The newest hidden
+//! version goes as well if it's a delete or has expired, leaving nothing.
+//!
+//! `Mvcc::collect_garbage` runs a pass in the caller. `GcWorker` runs passes
+//! on a background thread instead, a batch of keys at a time under each
+//! shard's lock and at a limited rate, so foreground queries aren't stalled;
+//! it can be paused and resumed, and lets the engine compact after each pass
+//! (`Engine::compact`).
+
+use std::{collections::HashSet, ops::Bound, thread, time::Duration};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        engine::{Engine, prefix_range},
+        mvcc::{Mvcc, MvccKey, MvccKeyPrefix, MvccTransaction, Version, now_millis},
+    },
+};
+
+/// Versions AS OF reads can go back by default
+pub const DEFAULT_GC_RETAIN_VERSIONS: u64 = 1000;
+
+/// Settings of garbage collection
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    /// How many of the latest versions stay readable AS OF
+    pub retain_versions: u64,
+    /// Version keys read per batch, under one shard lock
+    pub batch_keys: usize,
+    /// Version keys a background pass reads per second at most, None for no limit
+    pub keys_per_second: Option<usize>,
+    /// Pause between background passes
+    pub interval: Duration,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            retain_versions: DEFAULT_GC_RETAIN_VERSIONS,
+            batch_keys: 1000,
+            keys_per_second: Some(100_000),
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Garbage collection work done
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcStats {
+    /// Passes completed
+    pub passes: u64,
+    /// Version keys read
+    pub keys_scanned: u64,
+    /// Versions deleted
+    pub versions_removed: u64,
+    /// Error of the last failed background pass
+    pub last_error: Option<String>,
+}
+
+/// Version below which hidden versions can be collected, once the
+/// transactions active when it was taken have finished
+///
+/// A snapshot doesn't see the versions active when it began, even after
+/// they commit, so the versions they hide stay needed until every
+/// transaction that may have seen them active is gone.
+struct Horizon {
+    version: Version,
+    waiting: HashSet<Version>,
+}
+
+impl<E: Engine> Mvcc<E> {
+    /// Removes the versions no snapshot can read anymore, in one pass
+    ///
+    /// Waits for the transactions active now to finish first.
+    pub fn collect_garbage(&self, options: &GcOptions) -> Result<GcStats> {
+        let horizon = self.gc_horizon(options.retain_versions)?;
+        while !self.gc_ready(&horizon)? {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let mut stats = GcStats::default();
+        self.gc_pass(horizon.version, options.batch_keys, &mut stats, |_| Ok(true))?;
+        Ok(stats)
+    }
+
+    /// Takes the horizon: the oldest active version, or the next one if none
+    /// is, kept `retain_versions` behind the next version
+    fn gc_horizon(&self, retain_versions: u64) -> Result<Horizon> {
+        let meta = self.shards[0].read()?;
+        let next = match meta.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 1,
+        };
+        let active = MvccTransaction::scan_active(&*meta)?;
+        let oldest = active.keys().next().copied().unwrap_or(next);
+        Ok(Horizon { version: oldest.min(next.saturating_sub(retain_versions)), waiting: active.into_keys().collect() })
+    }
+
+    /// Whether the transactions active when the horizon was taken have finished
+    fn gc_ready(&self, horizon: &Horizon) -> Result<bool> {
+        Ok(self.active_transactions()?.iter().all(|(version, _)| !horizon.waiting.contains(version)))
+    }
+
+    /// Collects the versions below a horizon in every shard, a batch at a
+    /// time, calling `pace` with each batch's size between batches; returns
+    /// false if `pace` stopped the pass early
+    fn gc_pass(
+        &self,
+        horizon: Version,
+        batch_keys: usize,
+        stats: &mut GcStats,
+        mut pace: impl FnMut(usize) -> Result<bool>,
+    ) -> Result<bool> {
+        // Aborted versions are left to their owners to discard
+        let aborted = MvccTransaction::scan_invisible(&*self.shards[0].read()?)?;
+        let mut prefix = MvccKeyPrefix::Version(vec![]).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        let now = now_millis()?;
+        for shard in self.shards.iter() {
+            let mut start = Bound::Included(prefix.clone());
+            loop {
+                let (scanned, resume) = {
+                    let mut engine = shard.write()?;
+                    let batch = GcBatch { horizon, aborted: &aborted, now, size: batch_keys };
+                    batch.collect(&mut *engine, &prefix, start, stats)?
+                };
+                if !pace(scanned)? {
+                    return Ok(false);
+                }
+                match resume {
+                    Some(key) => start = Bound::Excluded(key),
+                    None => break,
+                }
+            }
+        }
+        stats.passes += 1;
+        Ok(true)
+    }
+}
+
+/// One batch of a garbage collection pass
+struct GcBatch<'a> {
+    horizon: Version,
+    aborted: &'a HashSet<Version>,
+    /// Time (unix millis) expiry is judged at
+    now: u64,
+    /// Version keys to read, rounded up to the last version of a key
+    size: usize,
+}
+
+impl GcBatch<'_> {
+    /// Deletes the collectable versions among the batch's version keys from
+    /// `start`, returning how many keys were read and the last one, if
+    /// the next batch should resume after it
+    fn collect<E: Engine>(
+        &self,
+        engine: &mut E,
+        prefix: &[u8],
+        start: Bound<Vec<u8>>,
+        stats: &mut GcStats,
+    ) -> Result<(usize, Option<Vec<u8>>)> {
+        // Raw key, version, and whether it's a delete
+        let mut versions: Vec<(Vec<u8>, Version, bool)> = Vec::new();
+        let mut last = None;
+        let mut resume = None;
+        let mut iter = engine.scan((start, prefix_range(prefix.to_vec()).1));
+        while let Some((key, value)) = iter.next().transpose()? {
+            let MvccKey::Version(raw_key, version) = MvccKey::decode(key.clone())? else {
+                return Err(Error::Internal(format!("unexpected key {:?} among versions", key)));
+            };
+            // A batch ends between keys, so it sees every version of its keys
+            if versions.len() >= self.size && versions.last().is_some_and(|(last, ..)| *last != raw_key) {
+                resume = last;
+                break;
+            }
+            let deleted = bincode::deserialize::<Option<Vec<u8>>>(&value)?.is_none();
+            versions.push((raw_key, version, deleted));
+            last = Some(key);
+        }
+        drop(iter);
+
+        for group in versions.chunk_by(|a, b| a.0 == b.0) {
+            let hidden = group
+                .iter()
+                .filter(|(_, version, _)| *version < self.horizon && !self.aborted.contains(version))
+                .collect::<Vec<_>>();
+            let Some(((key, newest, deleted), older)) = hidden.split_last() else {
+                continue;
+            };
+            let mut garbage = older.iter().map(|(_, version, _)| *version).collect::<Vec<_>>();
+            let expired = engine
+                .get(MvccKey::Ttl(key.clone(), *newest).encode()?)?
+                .map(|value| bincode::deserialize::<u64>(&value))
+                .transpose()?
+                .is_some_and(|expires_at| expires_at <= self.now);
+            if *deleted || expired {
+                garbage.push(*newest);
+            }
+            for version in garbage {
+                engine.delete(MvccKey::Version(key.clone(), version).encode()?)?;
+                engine.delete(MvccKey::Ttl(key.clone(), version).encode()?)?;
+                stats.versions_removed += 1;
+            }
+        }
+        stats.keys_scanned += versions.len() as u64;
+        Ok((versions.len(), resume))
+    }
+}
+
+/// Background thread collecting garbage, see `Mvcc::start_gc`
+///
+/// Dropping the worker stops it, like `stop`.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct GcWorker {
+    shared: Arc<GcShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// State a worker shares with its thread
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Default)]
+struct GcShared {
+    state: Mutex<GcState>,
+    /// Signalled on pause, resume and stop
+    wake: Condvar,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Default)]
+struct GcState {
+    paused: bool,
+    stopped: bool,
+    stats: GcStats,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl GcShared {
+    /// Sleeps for `timeout`, and then for as long as the worker is paused;
+    /// returns false once it's stopped
+    fn wait(&self, timeout: Duration) -> Result<bool> {
+        let state = self.state.lock()?;
+        let (state, _) = self.wake.wait_timeout_while(state, timeout, |state| !state.stopped)?;
+        let state = self.wake.wait_while(state, |state| state.paused && !state.stopped)?;
+        Ok(!state.stopped)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut GcState)) -> Result<()> {
+        f(&mut *self.state.lock()?);
+        self.wake.notify_all();
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl<E: Engine + Send + Sync + 'static> Mvcc<E> {
+    /// Starts collecting garbage on a background thread, a pass every
+    /// `options.interval`
+    ///
+    /// A pass begins once the transactions active at the end of the previous
+    /// interval have finished (see `collect_garbage`), and reads at most
+    /// `options.keys_per_second` version keys per second.
+    pub fn start_gc(&self, options: GcOptions) -> GcWorker {
+        let shared = Arc::new(GcShared::default());
+        let (mvcc, thread_shared) = (self.clone(), shared.clone());
+        let thread = thread::spawn(move || GcWorker::run(&mvcc, &thread_shared, &options));
+        GcWorker { shared, thread: Some(thread) }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl GcWorker {
+    /// Pauses collection after the batch in progress, until `resume`
+    pub fn pause(&self) -> Result<()> {
+        self.shared.update(|state| state.paused = true)
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        self.shared.update(|state| state.paused = false)
+    }
+
+    pub fn is_paused(&self) -> Result<bool> {
+        Ok(self.shared.state.lock()?.paused)
+    }
+
+    /// Work done so far
+    pub fn stats(&self) -> Result<GcStats> {
+        Ok(self.shared.state.lock()?.stats.clone())
+    }
+
+    /// Stops the worker after the batch in progress, waiting for its thread
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        self.shared.update(|state| state.stopped = true)?;
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| Error::Internal("gc thread panicked".into())),
+            None => Ok(()),
+        }
+    }
+
+    fn run<E: Engine>(mvcc: &Mvcc<E>, shared: &GcShared, options: &GcOptions) {
+        let mut horizon = None;
+        // Passes begin after a wait, which holds while paused
+        let mut interval = Duration::ZERO;
+        while shared.wait(interval).unwrap_or(false) {
+            if let Err(err) = Self::pass(mvcc, shared, options, &mut horizon) {
+                let _ = shared.update(|state| state.stats.last_error = Some(err.to_string()));
+            }
+            interval = options.interval;
+        }
+    }
+
+    /// Runs a pass if the horizon is ready, taking one first if there's none
+    /// from an earlier call; returns false if stopped meanwhile
+    fn pass<E: Engine>(
+        mvcc: &Mvcc<E>,
+        shared: &GcShared,
+        options: &GcOptions,
+        horizon: &mut Option<Horizon>,
+    ) -> Result<bool> {
+        let next = match horizon.take() {
+            Some(next) => next,
+            None => mvcc.gc_horizon(options.retain_versions)?,
+        };
+        if !mvcc.gc_ready(&next)? {
+            *horizon = Some(next);
+            return Ok(true);
+        }
+        let mut stats = GcStats::default();
+        let result = mvcc.gc_pass(next.version, options.batch_keys, &mut stats, |keys| {
+            let pause = match options.keys_per_second {
+                Some(rate) => Duration::from_secs_f64(keys as f64 / rate.max(1) as f64),
+                None => Duration::ZERO,
+            };
+            shared.wait(pause)
+        });
+        shared.update(|state| {
+            state.stats.passes += stats.passes;
+            state.stats.keys_scanned += stats.keys_scanned;
+            state.stats.versions_removed += stats.versions_removed;
+        })?;
+        let finished = result?;
+        if finished {
+            for shard in mvcc.shards.iter() {
+                shard.write()?.compact()?;
+            }
+        }
+        Ok(finished)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Drop for GcWorker {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{GcOptions, GcStats};
+    use crate::{
+        error::Result,
+        storage::{
+            engine::Engine,
+            memory::MemoryEngine,
+            mvcc::{Mvcc, MvccKeyPrefix},
+        },
+    };
+
+    /// Number of stored versions, in every shard
+    fn versions(mvcc: &Mvcc<MemoryEngine>) -> Result<usize> {
+        let mut prefix = MvccKeyPrefix::Version(vec![]).encode()?;
+        prefix.truncate(prefix.len() - 2);
+        let mut count = 0;
+        for shard in mvcc.shards.iter() {
+            count += shard.read()?.scan_prefix(prefix.clone()).count();
+        }
+        Ok(count)
+    }
+
+    #[test]
+    fn test_collect_garbage() -> Result<()> {
+        let mvcc = Mvcc::sharded(vec![MemoryEngine::new(), MemoryEngine::new()]);
+        for (key, value) in [("a", "1"), ("b", "1"), ("a", "2")] {
+            let tx = mvcc.begin()?;
+            tx.set(key.into(), value.into())?;
+            tx.commit()?;
+        }
+        let tx = mvcc.begin()?;
+        tx.delete(b"b".to_vec())?;
+        tx.set_with_ttl(b"c".to_vec(), b"1".to_vec(), Duration::ZERO)?;
+        tx.commit()?;
+        let reader = mvcc.begin()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), b"3".to_vec())?;
+        tx.commit()?;
+        assert_eq!(versions(&mvcc)?, 6);
+
+        // The reader's snapshot keeps a = 2, and holds off a new horizon
+        let horizon = mvcc.gc_horizon(0)?;
+        assert!(!mvcc.gc_ready(&horizon)?);
+        let mut stats = GcStats::default();
+        assert!(mvcc.gc_pass(horizon.version, 1, &mut stats, |_| Ok(true))?);
+        assert_eq!((stats.keys_scanned, stats.versions_removed), (6, 4));
+        assert_eq!(reader.get(b"a".to_vec())?, Some(b"2".to_vec()));
+        assert_eq!(reader.get(b"b".to_vec())?, None);
+        reader.commit()?;
+        assert!(mvcc.gc_ready(&horizon)?);
+
+        let stats = mvcc.collect_garbage(&GcOptions { retain_versions: 0, ..Default::default() })?;
+        assert_eq!((stats.keys_scanned, stats.versions_removed), (2, 1));
+        let tx = mvcc.begin()?;
+        assert_eq!(tx.get(b"a".to_vec())?, Some(b"3".to_vec()));
+        tx.commit()?;
+
+        // Retained versions stay readable AS OF
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), b"4".to_vec())?;
+        tx.commit()?;
+        assert_eq!(mvcc.collect_garbage(&GcOptions::default())?.versions_removed, 0);
+        let tx = mvcc.begin_as_of(6)?;
+        assert_eq!(tx.get(b"a".to_vec())?, Some(b"3".to_vec()));
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_worker() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let options = GcOptions {
+            retain_versions: 0,
+            batch_keys: 2,
+            keys_per_second: None,
+            interval: Duration::from_millis(1),
+        };
+        let worker = mvcc.start_gc(options);
+        worker.pause()?;
+        assert!(worker.is_paused()?);
+        // A batch in progress finishes first
+        std::thread::sleep(Duration::from_millis(10));
+        for i in 0..20u8 {
+            let tx = mvcc.begin()?;
+            tx.set(vec![i % 4], vec![i])?;
+            tx.commit()?;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(versions(&mvcc)?, 20);
+
+        worker.resume()?;
+        while versions(&mvcc)? > 4 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let stats = worker.stats()?;
+        assert!(stats.passes > 0 && stats.versions_removed >= 16 && stats.last_error.is_none());
+        worker.stop()?;
+        for i in 0..4u8 {
+            assert_eq!(mvcc.begin()?.get(vec![i])?, Some(vec![16 + i]));
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,30 @@
 //! Storage layer - KV store and MVCC implementation
 //!
 //! This module provides:
-//! - Abstract storage engine trait
+//! - Abstract storage engine trait, and its boxed form for runtime-chosen engines
 //! - In-memory storage implementation
-//! - MVCC transaction support
+//! - Conformance tests for engine implementations
+//! - MVCC transaction support, checked by a deterministic simulation (tests)
+//! - MVCC garbage collection, in the caller or on a background thread
 //! - Ordered key encoding for prefix scanning
+//! - Read-only snapshot files of a store's data
+//! - Value compression wrapper (features `lz4`, `snappy`)
+//! - Runtime engine selection from config files
+//! - Encryption-at-rest wrapper (feature `encryption`)
+//! - Primary/replica streaming replication
 
 pub mod mvcc;
+pub mod gc;
 pub mod engine;
+pub mod boxed;
+pub mod testutil;
 pub mod memory;
-pub mod keycode;
\ No newline at end of file
+pub mod keycode;
+pub mod snapshot;
+pub mod compress;
+pub mod config;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+pub mod replication;
+#[cfg(test)]
+mod simulation;
\ No newline at end of file
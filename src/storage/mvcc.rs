@@ -1,47 +1,329 @@
-use std::{collections::{BTreeMap, HashSet}, sync::{Arc, Mutex, MutexGuard}, u64};
+use std::{collections::{BTreeMap, HashMap, HashSet}, ops::Bound, sync::{Arc, Condvar, Mutex, RwLock, atomic::{AtomicBool, AtomicU64, Ordering}}, thread, time::Duration, u64};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{error::{Error, Result}, storage::{engine::Engine, keycode::{deserialize_key, serialize_key}}};
+use crate::{error::{Error, Result}, storage::{engine::{Durability, Engine, fnv1a, prefix_range}, keycode::{self, deserialize_key, serialize_key}, replication::{ReplicatedWrite, ReplicationLog}}};
 
 /// Transaction version number type
 pub type Version = u64;
 
+/// Number of finished transactions whose records are kept
+pub const TXN_RECORD_RETENTION: u64 = 1000;
+
 /// MVCC storage engine wrapper
 ///
-/// Uses the underlying storage engine (Engine trait) for CRUD operations.
+/// Uses the underlying storage engines (Engine trait) for CRUD operations.
+/// The key space is split across one or more engine shards, each behind its
+/// own `RwLock`: reads share a shard, writes lock only the shard their key
+/// hashes to. Shard 0 also holds the transaction metadata (version counter
+/// and active set), so begins serialize on it.
 pub struct Mvcc<E: Engine> {
-    engine: Arc<Mutex<E>>,
+    pub(crate) shards: Arc<[RwLock<E>]>,
+    /// Receives committed write sets when this node is a replication primary
+    log: Option<ReplicationLog>,
+    /// Set by `close`; new transactions are refused
+    closed: Arc<AtomicBool>,
+    /// Whether the store was last shut down by `close`
+    clean_shutdown: bool,
+    /// Durability of new transactions
+    durability: Durability,
+    /// Engine flushes shared by committing transactions
+    group_commit: Arc<GroupCommit>,
+    /// Whether every transaction is a read-only one, see `with_read_only`
+    read_only: bool,
 }
 
 impl<E: Engine> Clone for Mvcc<E> {
     fn clone(&self) -> Self {
-        Self { engine: self.engine.clone() }
+        Self {
+            shards: self.shards.clone(),
+            log: self.log.clone(),
+            closed: self.closed.clone(),
+            clean_shutdown: self.clean_shutdown,
+            durability: self.durability,
+            group_commit: self.group_commit.clone(),
+            read_only: self.read_only,
+        }
     }
 }
 
 impl<E: Engine> Mvcc<E> {
     pub fn new(eng: E) -> Self {
+        Self::sharded(vec![eng])
+    }
+
+    /// Splits the key space across the given engines, one lock per shard
+    ///
+    /// Keys are routed by a stable hash, so reopening persistent engines
+    /// requires passing them in the same order.
+    pub fn sharded(mut engines: Vec<E>) -> Self {
+        assert!(!engines.is_empty(), "mvcc needs at least one engine shard");
+        // A marker that can't be read or consumed counts as an unclean shutdown
+        let clean_shutdown = Self::take_shutdown_marker(&mut engines[0]).unwrap_or(false);
         Self {
-            engine: Arc::new(Mutex::new(eng)),
+            shards: engines.into_iter().map(RwLock::new).collect(),
+            log: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            clean_shutdown,
+            durability: Durability::default(),
+            group_commit: Arc::default(),
+            read_only: false,
+        }
+    }
+
+    /// Removes the marker `close` leaves, returning whether it was there
+    fn take_shutdown_marker(engine: &mut E) -> Result<bool> {
+        let key = MvccKey::CleanShutdown.encode()?;
+        if engine.get(key.clone())?.is_none() {
+            return Ok(false);
         }
+        engine.delete(key)?;
+        engine.flush()?;
+        Ok(true)
+    }
+
+    /// Whether the store was shut down by `close` when it was last open,
+    /// rather than crashing or being dropped with work in flight
+    ///
+    /// Engines with a write-ahead log can skip replaying it after a clean
+    /// shutdown, since `close` flushed everything.
+    pub fn clean_shutdown(&self) -> bool {
+        self.clean_shutdown
+    }
+
+    /// Shuts the store down: refuses new transactions, waits up to
+    /// `timeout` for the active ones to finish, flushes every shard and
+    /// leaves a marker for the next startup (see `clean_shutdown`)
+    ///
+    /// On timeout the store stays closed but unmarked, and the error names
+    /// the transactions still running; `abort` can end them before retrying.
+    pub fn close(&self, timeout: Duration) -> Result<()> {
+        self.closed.store(true, Ordering::SeqCst);
+        let deadline = now_millis()? + timeout.as_millis() as u64;
+        loop {
+            let active = self.active_transactions()?;
+            if active.is_empty() {
+                break;
+            }
+            if now_millis()? >= deadline {
+                let versions = active.iter().map(|(version, _)| version.to_string()).collect::<Vec<_>>();
+                return Err(Error::Internal(format!(
+                    "timed out waiting for active transactions {} to finish",
+                    versions.join(", ")
+                )));
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        for shard in self.shards.iter().skip(1) {
+            shard.write()?.flush()?;
+        }
+        // The marker goes last, so it's only durable once the data is
+        let mut meta = self.shards[0].write()?;
+        meta.flush()?;
+        meta.set(MvccKey::CleanShutdown.encode()?, vec![])?;
+        meta.flush()
+    }
+
+    /// Appends every committed write set to the log, in commit order
+    pub fn with_replication_log(mut self, log: ReplicationLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Sets when transactions flush the engine, unless they set their own
+    /// (`MvccTransaction::set_durability`)
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Serves every transaction as a read-only one over the latest committed
+    /// data, never writing to the engine, e.g. a `ReadOnlySnapshotEngine`
+    pub fn with_read_only(mut self) -> Self {
+        self.read_only = true;
+        self
     }
 
     pub fn begin(&self) -> Result<MvccTransaction<E>> {
-        MvccTransaction::begin(self.engine.clone())
+        if self.read_only {
+            let next_version = match self.shards[0].read()?.get(MvccKey::NextVersion.encode()?)? {
+                Some(value) => bincode::deserialize::<Version>(&value)?,
+                None => 1,
+            };
+            return self.begin_as_of(next_version - 1);
+        }
+        let mut txn = MvccTransaction::begin(self.shards.clone(), &self.closed)?;
+        txn.log = self.log.clone();
+        txn.state.durability = self.durability;
+        txn.group_commit = self.group_commit.clone();
+        Ok(txn)
+    }
+
+    /// Begins a transaction that rejects writes
+    pub fn begin_read_only(&self) -> Result<MvccTransaction<E>> {
+        let mut txn = self.begin()?;
+        txn.state.read_only = true;
+        Ok(txn)
+    }
+
+    /// Begins a serializable transaction
+    ///
+    /// On top of snapshot isolation's write conflicts it rules out phantoms:
+    /// each prefix it scans becomes a predicate lock, and a concurrent
+    /// transaction's write under a locked prefix fails the scan, or the
+    /// commit if it comes later, with a write conflict.
+    pub fn begin_serializable(&self) -> Result<MvccTransaction<E>> {
+        let mut txn = self.begin()?;
+        txn.state.serializable = true;
+        Ok(txn)
+    }
+
+    /// Lists active transactions with how long they've been running, oldest first
+    pub fn active_transactions(&self) -> Result<Vec<(Version, Duration)>> {
+        let now = now_millis()?;
+        Ok(MvccTransaction::scan_active(&*self.shards[0].read()?)?
+            .into_iter()
+            .map(|(version, started_at)| (version, Duration::from_millis(now.saturating_sub(started_at))))
+            .collect())
+    }
+
+    /// Forcibly aborts an active transaction, e.g. one left open by a stuck client
+    ///
+    /// Its writes are discarded and the version stays invisible to every
+    /// snapshot, even if its owner keeps writing; the owner's commit fails.
+    pub fn abort(&self, version: Version) -> Result<()> {
+        let mut meta = self.shards[0].write()?;
+        if meta.get(MvccKey::TxnActive(version).encode()?)?.is_none() {
+            return Err(Error::Internal(format!("transaction {} is not active", version)));
+        }
+        meta.set(MvccKey::TxnAborted(version).encode()?, vec![])?;
+        drop(meta);
+
+        let txn = MvccTransaction {
+            shards: self.shards.clone(),
+            state: TransactionState {
+                version,
+                active_versions: HashSet::new(),
+                read_only: false,
+                pinned: false,
+                serializable: false,
+                durability: Durability::default(),
+            },
+            log: None,
+            group_commit: Arc::default(),
+            on_commit: Arc::default(),
+        };
+        txn.discard(TransactionStatus::Aborted)
+    }
+
+    /// Begins a read-only transaction pinned to the snapshot of a past version
+    pub fn begin_as_of(&self, version: Version) -> Result<MvccTransaction<E>> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::Internal("database is closed".into()));
+        }
+        let mut txn = MvccTransaction::begin_as_of(self.shards.clone(), version)?;
+        txn.log = self.log.clone();
+        Ok(txn)
+    }
+}
+
+/// Group commit: committing transactions share engine flushes
+///
+/// A commit takes a ticket once its writes are done. With no flush running
+/// it leads one, which covers every ticket taken so far; otherwise it waits
+/// for the running flush, and returns if that covered its ticket or leads
+/// the next. Under concurrency one flush then serves a whole group of
+/// commits, rather than each paying for its own. The leader only holds each
+/// shard to write out its buffers (`Engine::begin_flush`), and makes them
+/// durable after, so commits keep writing and join the next group meanwhile.
+#[derive(Default)]
+struct GroupCommit {
+    state: Mutex<GroupCommitState>,
+    /// Signalled when a flush ends
+    flushed: Condvar,
+    /// When (unix millis) a commit last flushed, for `Durability::Periodic`
+    last: AtomicU64,
+}
+
+#[derive(Default)]
+struct GroupCommitState {
+    /// Last ticket taken
+    tickets: u64,
+    /// Last ticket a finished flush covered
+    flushed: u64,
+    flushing: bool,
+}
+
+impl GroupCommit {
+    /// Returns once a flush begun after the call has finished, running it
+    /// unless another commit's does
+    fn sync(&self, flush: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut state = self.state.lock()?;
+        state.tickets += 1;
+        let ticket = state.tickets;
+        while state.flushing {
+            state = self.flushed.wait(state)?;
+            if state.flushed >= ticket {
+                return Ok(());
+            }
+        }
+        let covered = state.tickets;
+        state.flushing = true;
+        drop(state);
+
+        let result = flush();
+        let mut state = self.state.lock()?;
+        state.flushing = false;
+        if result.is_ok() {
+            state.flushed = covered;
+        }
+        self.flushed.notify_all();
+        result
     }
 }
 
 /// MVCC transaction
+///
+/// Cloning yields another handle to the same transaction (same version and
+/// snapshot), which lets the SQL layer and raw KV callers share one commit.
 pub struct MvccTransaction<E: Engine> {
-    engine: Arc<Mutex<E>>,
+    shards: Arc<[RwLock<E>]>,
     state: TransactionState,
+    log: Option<ReplicationLog>,
+    /// Engine flushes, shared by the store's transactions
+    group_commit: Arc<GroupCommit>,
+    /// Called after the commit, shared by the transaction's handles
+    on_commit: Arc<Mutex<Vec<CommitHook>>>,
+}
+
+/// A call made after a transaction commits, see `MvccTransaction::on_commit`
+pub type CommitHook = Box<dyn FnOnce() -> Result<()> + Send>;
+
+impl<E: Engine> Clone for MvccTransaction<E> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+            state: self.state.clone(),
+            log: self.log.clone(),
+            group_commit: self.group_commit.clone(),
+            on_commit: self.on_commit.clone(),
+        }
+    }
 }
 
 /// Transaction state for MVCC visibility checks
+#[derive(Clone)]
 pub struct TransactionState {
     pub version: Version,
     pub active_versions: HashSet<Version>,
+    pub read_only: bool,
+    /// Reading a past version's snapshot; the version belongs to another transaction
+    pub pinned: bool,
+    /// Scans take predicate locks, see `Mvcc::begin_serializable`
+    pub serializable: bool,
+    /// When writes are flushed to the engine
+    pub durability: Durability,
 }
 
 impl TransactionState {
@@ -53,6 +335,9 @@ impl TransactionState {
     }
 }
 
+/// A write set entry's key, with the raw keys it records as written
+type WriteEntry = (Vec<u8>, Vec<Vec<u8>>);
+
 /// MVCC key types for storage operations
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum MvccKey {
@@ -64,6 +349,27 @@ pub enum MvccKey {
     TxnWrite(Version, #[serde(with = "serde_bytes")] Vec<u8>),
     /// Versioned data key
     Version(#[serde(with = "serde_bytes")] Vec<u8>, Version),
+    /// Expiry time (unix millis) of a versioned data key written with a TTL
+    Ttl(#[serde(with = "serde_bytes")] Vec<u8>, Version),
+    /// Last replication log sequence applied by a replica
+    ReplicaCheckpoint,
+    /// Outcome of a finished transaction, for auditing
+    TxnRecord(Version),
+    /// Version forcibly aborted while active; it stays invisible for good
+    TxnAborted(Version),
+    /// Intent lock on a raw key, held by an active version (SELECT ... FOR UPDATE)
+    Lock(#[serde(with = "serde_bytes")] Vec<u8>, Version),
+    /// Lock set entry, to release the version's locks when it finishes
+    TxnLock(Version, #[serde(with = "serde_bytes")] Vec<u8>),
+    /// Predicate lock: a prefix a serializable version scanned
+    TxnScan(Version, #[serde(with = "serde_bytes")] Vec<u8>),
+    /// Write set entry for a whole batch of raw keys (by its first key), see
+    /// `MvccTransaction::set_batch`; the value lists the keys
+    TxnBatch(Version, #[serde(with = "serde_bytes")] Vec<u8>),
+    /// Version of the key encoding (`keycode::FORMAT_VERSION`) the store was written with
+    KeyFormat,
+    /// Left by `Mvcc::close`, removed again at startup
+    CleanShutdown,
 }
 
 impl MvccKey {
@@ -88,6 +394,16 @@ pub enum MvccKeyPrefix {
     TxnActive,
     TxnWrite(Version),
     Version(#[serde(with = "serde_bytes")] Vec<u8>),
+    Ttl(#[serde(with = "serde_bytes")] Vec<u8>),
+    ReplicaCheckpoint,
+    TxnRecord,
+    TxnAborted,
+    Lock(#[serde(with = "serde_bytes")] Vec<u8>),
+    TxnLock(Version),
+    TxnScan(Version),
+    TxnBatch(Version),
+    KeyFormat,
+    CleanShutdown,
 }
 
 impl MvccKeyPrefix {
@@ -98,8 +414,26 @@ impl MvccKeyPrefix {
 
 impl<E: Engine> MvccTransaction<E> {
     /// Begins a new transaction
-    pub fn begin(eng: Arc<Mutex<E>>) -> Result<Self> {
-        let mut engine = eng.lock()?;
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "mvcc.begin", level = "debug", skip_all, fields(version)))]
+    pub fn begin(shards: Arc<[RwLock<E>]>, closed: &AtomicBool) -> Result<Self> {
+        let mut engine = shards[0].write()?;
+        // Checked under the lock, so `close` sees every version begun before it
+        if closed.load(Ordering::SeqCst) {
+            return Err(Error::Internal("database is closed".into()));
+        }
+
+        // Stores from before the marker use the first format
+        match engine.get(MvccKey::KeyFormat.encode()?)?.as_deref() {
+            Some([keycode::FORMAT_VERSION]) => {}
+            Some(version) => {
+                return Err(Error::Internal(format!(
+                    "store uses key format {:?}, expected {}",
+                    version,
+                    keycode::FORMAT_VERSION
+                )));
+            }
+            None => engine.set(MvccKey::KeyFormat.encode()?, vec![keycode::FORMAT_VERSION])?,
+        }
 
         let next_version = match engine.get(MvccKey::NextVersion.encode()?)? {
             Some(value) => bincode::deserialize(&value)?,
@@ -111,86 +445,390 @@ impl<E: Engine> MvccTransaction<E> {
             bincode::serialize(&(next_version + 1))?
         )?;
 
-        let active_versions = Self::scan_active(&mut engine)?;
+        let active_versions = Self::scan_invisible(&*engine)?;
 
-        engine.set(MvccKey::TxnActive(next_version).encode()?, vec![])?;
+        engine.set(MvccKey::TxnActive(next_version).encode()?, bincode::serialize(&now_millis()?)?)?;
 
+        drop(engine);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("version", next_version);
         Ok(Self {
-            engine: eng.clone(),
+            shards,
             state: TransactionState {
                 version: next_version,
                 active_versions,
-            }
+                read_only: false,
+                pinned: false,
+                serializable: false,
+                durability: Durability::default(),
+            },
+            log: None,
+            group_commit: Arc::default(),
+            on_commit: Arc::default(),
         })
     }
 
-    /// Commits the transaction (cleans up metadata only)
+    /// Begins a read-only transaction seeing the data as of a past version
+    ///
+    /// It sees every version up to and including `version` that has
+    /// committed by now. It registers no version of its own, so commit and
+    /// rollback have nothing to clean up.
+    pub fn begin_as_of(shards: Arc<[RwLock<E>]>, version: Version) -> Result<Self> {
+        let engine = shards[0].read()?;
+        let next_version = match engine.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 1,
+        };
+        if version == 0 || version >= next_version {
+            return Err(Error::Internal(format!("version {} does not exist", version)));
+        }
+        let active_versions = Self::scan_invisible(&*engine)?;
+
+        drop(engine);
+        Ok(Self {
+            shards,
+            state: TransactionState {
+                version,
+                active_versions,
+                read_only: true,
+                pinned: true,
+                serializable: false,
+                durability: Durability::default(),
+            },
+            log: None,
+            group_commit: Arc::default(),
+            on_commit: Arc::default(),
+        })
+    }
+
+    /// Returns the version of this transaction
+    pub fn version(&self) -> Version {
+        self.state.version
+    }
+
+    /// Whether scans take predicate locks, see `Mvcc::begin_serializable`
+    pub fn is_serializable(&self) -> bool {
+        self.state.serializable
+    }
+
+    /// Commits the transaction (cleans up metadata only), then calls the
+    /// hooks added by `on_commit`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.commit", level = "debug", skip_all, fields(version = self.state.version))
+    )]
     pub fn commit(&self) -> Result<()> {
-        let mut engine = self.engine.lock()?;
+        let result = self.commit_writes();
+        let hooks = std::mem::take(&mut *self.on_commit.lock()?);
+        let called = hooks.into_iter().try_for_each(|hook| hook());
+        result.and(called)
+    }
 
-        let mut delete_keys = Vec::new();
-        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnWrite(self.state.version).encode()?);
-        while let Some((key, _)) = iter.next().transpose()? {
-            delete_keys.push(key);
+    /// Calls `hook` after the transaction commits through any of its handles
+    ///
+    /// It's called even if the commit fails, as the commit may have taken
+    /// effect, e.g. with only the flush failing.
+    pub fn on_commit(&self, hook: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+        self.on_commit.lock()?.push(Box::new(hook));
+        Ok(())
+    }
+
+    fn commit_writes(&self) -> Result<()> {
+        if self.state.pinned {
+            return Ok(());
         }
-        drop(iter);
+        if self.shards[0].read()?.get(MvccKey::TxnAborted(self.state.version).encode()?)?.is_some() {
+            self.discard(TransactionStatus::RolledBack)?;
+            return Err(Error::Internal(format!("transaction {} was aborted", self.state.version)));
+        }
+        if self.state.serializable
+            && let Err(err) = self.check_predicate_locks()
+        {
+            self.discard(TransactionStatus::RolledBack)?;
+            return Err(err);
+        }
+        let Some(log) = &self.log else {
+            let mut count = 0;
+            self.for_each_shard(|engine| {
+                for (entry, raw_keys) in Self::scan_writes(engine, self.state.version)? {
+                    count += raw_keys.len() as u64;
+                    engine.delete(entry)?;
+                }
+                Self::release_locks(engine, self.state.version)
+            })?;
+            self.finish(&mut *self.shards[0].write()?, TransactionStatus::Committed, count)?;
+            return self.sync();
+        };
 
-        for key in delete_keys.into_iter() {
-            engine.delete(key)?;
+        // The metadata shard stays locked throughout, so the log order matches
+        // commit order, and an abort can't slip in after the check here
+        let mut meta = self.shards[0].write()?;
+        if meta.get(MvccKey::TxnAborted(self.state.version).encode()?)?.is_some() {
+            drop(meta);
+            self.discard(TransactionStatus::RolledBack)?;
+            return Err(Error::Internal(format!("transaction {} was aborted", self.state.version)));
         }
+        let mut delete_keys = Vec::with_capacity(self.shards.len());
+        let mut writes = Vec::new();
+        for (i, shard) in self.shards.iter().enumerate() {
+            let guard = match i {
+                0 => None,
+                _ => Some(shard.read()?),
+            };
+            let engine = guard.as_deref().unwrap_or(&*meta);
+            let mut keys = Vec::new();
+            for (entry, raw_keys) in Self::scan_writes(engine, self.state.version)? {
+                for raw_key in raw_keys {
+                    let value = match engine.get(MvccKey::Version(raw_key.clone(), self.state.version).encode()?)? {
+                        Some(value) => bincode::deserialize(&value)?,
+                        None => None,
+                    };
+                    let expires_at = engine
+                        .get(MvccKey::Ttl(raw_key.clone(), self.state.version).encode()?)?
+                        .map(|v| bincode::deserialize(&v))
+                        .transpose()?;
+                    writes.push(ReplicatedWrite { key: raw_key, value, expires_at });
+                }
+                keys.push(entry);
+            }
+            delete_keys.push(keys);
+        }
+        let count = writes.len() as u64;
+        if !writes.is_empty() {
+            log.append(self.state.version, writes)?;
+        }
+
+        for (i, keys) in delete_keys.into_iter().enumerate() {
+            let mut guard = match i {
+                0 => None,
+                _ => Some(self.shards[i].write()?),
+            };
+            let engine = guard.as_deref_mut().unwrap_or(&mut *meta);
+            keys.into_iter().try_for_each(|key| engine.delete(key))?;
+            Self::release_locks(engine, self.state.version)?;
+        }
+        self.finish(&mut meta, TransactionStatus::Committed, count)?;
+        drop(meta);
+        self.sync()
+    }
+
+    /// When this transaction's writes are flushed to the engine
+    pub fn durability(&self) -> Durability {
+        self.state.durability
+    }
 
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
+    /// Sets when this transaction's writes are flushed to the engine
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.state.durability = durability;
+    }
+
+    /// Flushes every shard after a commit, as its durability asks, together
+    /// with concurrent commits (see `GroupCommit`)
+    fn sync(&self) -> Result<()> {
+        match self.state.durability {
+            Durability::Off => return Ok(()),
+            Durability::Always | Durability::Commit => {}
+            Durability::Periodic(interval) => {
+                let now = now_millis()?;
+                let last = &self.group_commit.last;
+                let synced = last.load(Ordering::SeqCst);
+                if now.saturating_sub(synced) < interval.as_millis() as u64
+                    || last.compare_exchange(synced, now, Ordering::SeqCst, Ordering::SeqCst).is_err()
+                {
+                    // Recent enough, or another commit is flushing now
+                    return Ok(());
+                }
+            }
+        }
+        self.group_commit.sync(|| {
+            let syncers = self.shards.iter().map(|shard| shard.write()?.begin_flush()).collect::<Result<Vec<_>>>()?;
+            syncers.into_iter().try_for_each(|sync| sync())
+        })
     }
 
     /// Rolls back the transaction (deletes all data and metadata)
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.rollback", level = "debug", skip_all, fields(version = self.state.version))
+    )]
     pub fn rollback(&self) -> Result<()> {
-        let mut engine = self.engine.lock()?;
-        let mut delete_keys = Vec::new();
+        if self.state.pinned {
+            return Ok(());
+        }
+        self.discard(TransactionStatus::RolledBack)
+    }
 
-        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnWrite(self.state.version).encode()?);
-        while let Some((key, _)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
-                MvccKey::TxnWrite(_, raw_key) => {
-                    delete_keys.push(MvccKey::Version(raw_key, self.state.version).encode()?);
+    /// Deletes the transaction's writes and finishes it with the given status
+    fn discard(&self, status: TransactionStatus) -> Result<()> {
+        let mut count = 0;
+        self.for_each_shard(|engine| {
+            let mut delete_keys = Vec::new();
+            for (entry, raw_keys) in Self::scan_writes(engine, self.state.version)? {
+                for raw_key in raw_keys {
+                    count += 1;
+                    delete_keys.push(MvccKey::Version(raw_key.clone(), self.state.version).encode()?);
+                    delete_keys.push(MvccKey::Ttl(raw_key, self.state.version).encode()?);
                 }
-                _ => {
-                    return Err(Error::Internal(format!(
-                        "unexpected key: {:?}",
-                        String::from_utf8(key)
-                    )))
+                delete_keys.push(entry);
+            }
+            delete_keys.into_iter().try_for_each(|key| engine.delete(key))?;
+            Self::release_locks(engine, self.state.version)
+        })?;
+
+        self.finish(&mut *self.shards[0].write()?, status, count)
+    }
+
+    /// Records the outcome and retires the active marker, on the metadata shard
+    ///
+    /// An aborted transaction keeps its aborted record and can't commit.
+    fn finish(&self, meta: &mut E, status: TransactionStatus, keys: u64) -> Result<()> {
+        let scans = meta
+            .scan_prefix(MvccKeyPrefix::TxnScan(self.state.version).encode()?)
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<Vec<_>>>()?;
+        scans.into_iter().try_for_each(|key| meta.delete(key))?;
+        let aborted = meta.get(MvccKey::TxnAborted(self.state.version).encode()?)?.is_some();
+        if aborted && status != TransactionStatus::Aborted {
+            meta.delete(MvccKey::TxnActive(self.state.version).encode()?)?;
+            return match status {
+                TransactionStatus::Committed => {
+                    Err(Error::Internal(format!("transaction {} was aborted", self.state.version)))
                 }
+                _ => Ok(()),
+            };
+        }
+        let record = TransactionRecord {
+            committed_at: match status {
+                TransactionStatus::Committed => Some(now_millis()?),
+                _ => None,
+            },
+            status,
+            keys,
+        };
+        meta.set(MvccKey::TxnRecord(self.state.version).encode()?, bincode::serialize(&record)?)?;
+        if let Some(expired) = self.state.version.checked_sub(TXN_RECORD_RETENTION) {
+            meta.delete(MvccKey::TxnRecord(expired).encode()?)?;
+        }
+        meta.delete(MvccKey::TxnActive(self.state.version).encode()?)
+    }
+
+    /// Lists active and recently finished transactions, by version
+    ///
+    /// Active transactions report the size of their write set so far.
+    pub fn transactions(&self) -> Result<Vec<TransactionInfo>> {
+        let meta = self.shards[0].read()?;
+        let mut infos = BTreeMap::new();
+        let mut iter = meta.scan_prefix(MvccKeyPrefix::TxnRecord.encode()?);
+        while let Some((key, value)) = iter.next().transpose()? {
+            if let MvccKey::TxnRecord(version) = MvccKey::decode(key)? {
+                let record: TransactionRecord = bincode::deserialize(&value)?;
+                infos.insert(version, TransactionInfo { version, record });
             }
-            delete_keys.push(key);
         }
         drop(iter);
-
-        for key in delete_keys.into_iter() {
-            engine.delete(key)?;
+        let active = Self::scan_active(&*meta)?;
+        drop(meta);
+
+        for version in active.into_keys() {
+            let mut keys = 0;
+            for shard in self.shards.iter() {
+                let writes = Self::scan_writes(&*shard.read()?, version)?;
+                keys += writes.iter().map(|(_, raw_keys)| raw_keys.len() as u64).sum::<u64>();
+            }
+            let record = TransactionRecord { status: TransactionStatus::Active, committed_at: None, keys };
+            infos.insert(version, TransactionInfo { version, record });
         }
-
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
+        Ok(infos.into_values().collect())
     }
 
     pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
-        self.write_inner(key, Some(value))
+        self.write_inner(key, Some(value), None)
+    }
+
+    /// Sets a key that expires after `ttl`
+    ///
+    /// Once expired, the key reads as deleted in `get` and `scan_prefix`,
+    /// like a Redis key with EXPIRE. A later plain `set` clears the TTL.
+    pub fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let expires_at = now_millis()?.saturating_add(ttl.as_millis() as u64);
+        self.write_inner(key, Some(value), Some(expires_at))
     }
 
     pub fn delete(&self, key: Vec<u8>) -> Result<()> {
-        self.write_inner(key, None)
+        self.write_inner(key, None, None)
+    }
+
+    /// Sets many keys at once, e.g. a chunk of a bulk load
+    ///
+    /// Each shard is locked once for all of its keys, which are conflict
+    /// checked before any of them is written, and the write set records them
+    /// in one batch entry rather than one entry per key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.set_batch", level = "trace", skip_all, fields(version = self.state.version))
+    )]
+    pub fn set_batch(&self, writes: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::Internal("cannot write in a read-only transaction".into()));
+        }
+        let mut shards = BTreeMap::<usize, Vec<_>>::new();
+        for (key, value) in writes {
+            shards.entry(self.shard_index(&key)).or_default().push((key, value));
+        }
+        for (i, writes) in shards {
+            let mut engine = self.shards[i].write()?;
+            for (key, _) in &writes {
+                self.check_conflict(&*engine, key)?;
+            }
+
+            // Another batch of this transaction starting at the same key shares the entry
+            let entry = MvccKey::TxnBatch(self.state.version, writes[0].0.clone()).encode()?;
+            let mut keys = match engine.get(entry.clone())? {
+                Some(value) => bincode::deserialize::<Vec<Vec<u8>>>(&value)?,
+                None => Vec::new(),
+            };
+            keys.extend(writes.iter().map(|(key, _)| key.clone()));
+            engine.set(entry, bincode::serialize(&keys)?)?;
+
+            for (key, value) in writes {
+                engine.set(
+                    MvccKey::Version(key.clone(), self.state.version).encode()?,
+                    bincode::serialize(&Some(value))?,
+                )?;
+                engine.delete(MvccKey::Ttl(key, self.state.version).encode()?)?;
+            }
+            if self.state.durability == Durability::Always {
+                engine.flush()?;
+            }
+        }
+        Ok(())
     }
 
     /// Gets the value for a key respecting MVCC visibility
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.get", level = "trace", skip_all, fields(version = self.state.version))
+    )]
     pub fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let mut engine = self.engine.lock()?;
+        let engine = self.shard(&key).read()?;
 
         let from = MvccKey::Version(key.clone(), 0).encode()?;
         let to = MvccKey::Version(key.clone(), self.state.version).encode()?;
+        let raw_key = key;
         let mut iter = engine.scan(from..=to).rev();
 
         while let Some((key, value)) = iter.next().transpose()? {
             match MvccKey::decode(key.clone())? {
                 MvccKey::Version(_, version) => {
                     if self.state.is_visible(version) {
+                        drop(iter);
+                        // An expired version hides the key just like a delete
+                        let ttl_key = MvccKey::Ttl(raw_key, version).encode()?;
+                        if let Some(expires_at) = engine.get(ttl_key)?
+                            && bincode::deserialize::<u64>(&expires_at)? <= now_millis()?
+                        {
+                            return Ok(None);
+                        }
                         return Ok(bincode::deserialize(&value)?);
                     }
                 }
@@ -207,42 +845,222 @@ impl<E: Engine> MvccTransaction<E> {
 
     /// Scans keys with prefix, returning latest visible version per key
     pub fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<ScanResult>> {
-        let mut eng = self.engine.lock()?;
-        let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
-        enc_prefix.truncate(enc_prefix.len() - 2);
+        self.scan_prefix_limit(prefix, usize::MAX, |_, _| Ok(true))
+    }
+
+    /// Scans keys with prefix like `scan_prefix`, keeping only the entries
+    /// `accept` takes, and stops reading once `limit` are kept
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.scan_prefix", level = "trace", skip_all, fields(version = self.state.version))
+    )]
+    pub fn scan_prefix_limit(
+        &self,
+        prefix: Vec<u8>,
+        limit: usize,
+        accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<ScanResult>> {
+        self.scan_inner(prefix, None, limit, false, accept)
+    }
+
+    /// Scans keys with prefix like `scan_prefix_limit`, but only those after
+    /// the raw key `after`, so a scan can resume where an earlier one stopped
+    pub fn scan_prefix_after(&self, prefix: Vec<u8>, after: Vec<u8>, limit: usize) -> Result<Vec<ScanResult>> {
+        self.scan_inner(prefix, Some(after), limit, false, |_, _| Ok(true))
+    }
+
+    /// Scans keys with prefix like `scan_prefix_limit`, from the last key
+    /// backwards: the entries come in descending key order, and the scan stops
+    /// once `limit` are kept
+    pub fn scan_prefix_rev(
+        &self,
+        prefix: Vec<u8>,
+        limit: usize,
+        accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<ScanResult>> {
+        self.scan_inner(prefix, None, limit, true, accept)
+    }
 
-        let mut iter = eng.scan_prefix(enc_prefix);
+    fn scan_inner(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+        reverse: bool,
+        mut accept: impl FnMut(&[u8], &[u8]) -> Result<bool>,
+    ) -> Result<Vec<ScanResult>> {
+        let enc_prefix = Self::version_prefix(prefix.clone())?;
+        let mut range = prefix_range(enc_prefix);
+        // Past every version of the key scanned to, in the scan's direction
+        if let Some(after) = after {
+            match reverse {
+                true => range.1 = Bound::Excluded(MvccKey::Version(after, 0).encode()?),
+                false => range.0 = Bound::Excluded(MvccKey::Version(after, u64::MAX).encode()?),
+            }
+        }
+        let now = now_millis()?;
+        if self.state.serializable {
+            let lock = MvccKey::TxnScan(self.state.version, prefix.clone()).encode()?;
+            self.shards[0].write()?.set(lock, vec![])?;
+        }
+
+        // Shards hold disjoint keys, so their results merge without conflicts.
+        // Each shard's first (or last) `limit` keys include its share of the
+        // overall first.
         let mut results = BTreeMap::new();
-        while let Some((key, value)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
-                MvccKey::Version(raw_key, version) => {
-                    if self.state.is_visible(version) {
-                        match bincode::deserialize(&value)? {
-                            Some(raw_value) => results.insert(raw_key, raw_value),
-                            None => results.remove(&raw_key),
-                        };
+        for shard in self.shards.iter() {
+            let eng = shard.read()?;
+            let expiries = Self::scan_ttl(&*eng, prefix.clone())?;
+            let mut iter = eng.scan(range.clone());
+            // The key being read, and its latest visible version so far
+            let mut current: Option<Vec<u8>> = None;
+            let mut visible: Option<(Version, Vec<u8>)> = None;
+            let mut kept = 0;
+            while kept < limit {
+                let next = if reverse { iter.next_back() } else { iter.next() };
+                let next = match next.transpose()? {
+                    Some((key, value)) => match MvccKey::decode(key.clone())? {
+                        // A concurrent write the snapshot misses is a phantom
+                        MvccKey::Version(raw_key, version)
+                            if self.state.serializable && !self.state.is_visible(version) =>
+                        {
+                            return Err(write_conflict(&raw_key, version));
+                        }
+                        MvccKey::Version(raw_key, version) => Some((raw_key, version, value)),
+                        _ => return Err(Error::Internal(format!("Unexpected key {:?}", String::from_utf8(key)))),
+                    },
+                    None => None,
+                };
+                // A key's versions are adjacent, so it's done once another key begins.
+                // They ascend, so backwards the first visible one is the latest.
+                if let (Some(key), Some((raw_key, version, value))) = (&current, &next)
+                    && key == raw_key
+                {
+                    if self.state.is_visible(*version) && (!reverse || visible.is_none()) {
+                        visible = Some((*version, value.clone()));
                     }
+                    continue;
                 }
-                _ => {
-                    return Err(Error::Internal(format!(
-                        "Unexpected key {:?}",
-                        String::from_utf8(key)
-                    )))
+                if let (Some(key), Some((version, value))) = (current.take(), visible.take())
+                    && let Some(value) = bincode::deserialize::<Option<Vec<u8>>>(&value)?
+                    // Keys whose visible version has expired are left out
+                    && expiries.get(&(key.clone(), version)).is_none_or(|expires_at| *expires_at > now)
+                    && accept(&key, &value)?
+                {
+                    results.insert(key, value);
+                    kept += 1;
+                }
+                match next {
+                    Some((raw_key, version, value)) => {
+                        visible = self.state.is_visible(version).then_some((version, value));
+                        current = Some(raw_key);
+                    }
+                    None => break,
                 }
             }
         }
+        let results: Box<dyn Iterator<Item = _>> = match reverse {
+            true => Box::new(results.into_iter().rev()),
+            false => Box::new(results.into_iter()),
+        };
+        Ok(results.take(limit).map(|(key, value)| ScanResult { key, value }).collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mvcc.write", level = "trace", skip_all, fields(version = self.state.version))
+    )]
+    pub(crate) fn write_inner(&self, key: Vec<u8>, value: Option<Vec<u8>>, expires_at: Option<u64>) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::Internal("cannot write in a read-only transaction".into()));
+        }
+        let mut engine = self.shard(&key).write()?;
+        self.check_conflict(&*engine, &key)?;
 
-        Ok(results
-            .into_iter()
-            .map(|(key, value)| ScanResult { key, value })
-            .collect())
+        engine.set(
+            MvccKey::TxnWrite(self.state.version, key.clone()).encode()?,
+            vec![]
+        )?;
+
+        engine.set(
+            MvccKey::Version(key.clone(), self.state.version).encode()?,
+            bincode::serialize(&value)?,
+        )?;
+
+        // Rewriting a key within the same transaction replaces any earlier TTL
+        let ttl_key = MvccKey::Ttl(key, self.state.version).encode()?;
+        match expires_at {
+            Some(expires_at) => engine.set(ttl_key, bincode::serialize(&expires_at)?)?,
+            None => engine.delete(ttl_key)?,
+        }
+
+        if self.state.durability == Durability::Always {
+            engine.flush()?;
+        }
+        Ok(())
     }
 
-    fn write_inner(&self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<()> {
-        let mut engine = self.engine.lock()?;
+    /// Takes an intent lock on a key until the transaction finishes, like
+    /// SELECT ... FOR UPDATE on its row
+    ///
+    /// Fails with a write conflict where a write would: if a version of the
+    /// key is invisible to this transaction, or another one holds the lock.
+    /// While held, other transactions' writes and locks on the key fail
+    /// immediately, instead of conflicting once this one writes it back.
+    pub fn lock(&self, key: Vec<u8>) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::Internal("cannot lock in a read-only transaction".into()));
+        }
+        let mut engine = self.shard(&key).write()?;
+        self.check_conflict(&*engine, &key)?;
+        engine.set(MvccKey::Lock(key.clone(), self.state.version).encode()?, vec![])?;
+        engine.set(MvccKey::TxnLock(self.state.version, key).encode()?, vec![])
+    }
 
+    /// Fails with a write conflict if a concurrent transaction wrote under a
+    /// prefix this serializable one scanned
+    fn check_predicate_locks(&self) -> Result<()> {
+        let mut prefixes = Vec::new();
+        let meta = self.shards[0].read()?;
+        let mut iter = meta.scan_prefix(MvccKeyPrefix::TxnScan(self.state.version).encode()?);
+        while let Some((key, _)) = iter.next().transpose()? {
+            if let MvccKey::TxnScan(_, prefix) = MvccKey::decode(key)? {
+                prefixes.push(prefix);
+            }
+        }
+        drop(iter);
+        drop(meta);
+
+        for prefix in prefixes {
+            let enc_prefix = Self::version_prefix(prefix)?;
+            for shard in self.shards.iter() {
+                let engine = shard.read()?;
+                let mut iter = engine.scan_prefix(enc_prefix.clone());
+                while let Some((key, _)) = iter.next().transpose()? {
+                    if let MvccKey::Version(raw_key, version) = MvccKey::decode(key)?
+                        && !self.state.is_visible(version)
+                    {
+                        return Err(write_conflict(&raw_key, version));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encoded prefix of the versions of every raw key starting with `prefix`
+    fn version_prefix(prefix: Vec<u8>) -> Result<Vec<u8>> {
+        // Drops the terminator ending the encoded raw key
+        let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
+        enc_prefix.truncate(enc_prefix.len() - 2);
+        Ok(enc_prefix)
+    }
+
+    /// Fails with a write conflict if a version of the key is invisible to
+    /// the transaction, or another transaction holds a lock on it
+    fn check_conflict(&self, engine: &E, key: &[u8]) -> Result<()> {
         let from = MvccKey::Version(
-            key.clone(),
+            key.to_vec(),
             self.state
                 .active_versions
                 .iter()
@@ -251,14 +1069,14 @@ impl<E: Engine> MvccTransaction<E> {
                 .unwrap_or(self.state.version + 1),
         )
         .encode()?;
-        let to = MvccKey::Version(key.clone(), u64::MAX).encode()?;
+        let to = MvccKey::Version(key.to_vec(), u64::MAX).encode()?;
 
         // Conflict detection: check for newer versions
         if let Some((k, _)) = engine.scan(from..=to).last().transpose()? {
             match MvccKey::decode(k.clone())? {
                 MvccKey::Version(_, version) => {
                     if !self.state.is_visible(version) {
-                        return Err(Error::WriteConflict);
+                        return Err(write_conflict(key, version));
                     }
                 }
                 _ => {
@@ -270,27 +1088,94 @@ impl<E: Engine> MvccTransaction<E> {
             }
         }
 
-        engine.set(
-            MvccKey::TxnWrite(self.state.version, key.clone()).encode()?,
-            vec![]
-        )?;
+        let mut locks = engine.scan_prefix(MvccKeyPrefix::Lock(key.to_vec()).encode()?);
+        while let Some((k, _)) = locks.next().transpose()? {
+            if let MvccKey::Lock(_, version) = MvccKey::decode(k)?
+                && version != self.state.version
+            {
+                return Err(write_conflict(key, version));
+            }
+        }
+        Ok(())
+    }
 
-        engine.set(
-            MvccKey::Version(key.clone(), self.state.version).encode()?,
-            bincode::serialize(&value)?,
-        )?;
+    /// Removes a finished version's locks from one shard
+    fn release_locks(engine: &mut E, version: Version) -> Result<()> {
+        let keys = engine
+            .scan_prefix(MvccKeyPrefix::TxnLock(version).encode()?)
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            if let MvccKey::TxnLock(_, raw_key) = MvccKey::decode(key.clone())? {
+                engine.delete(MvccKey::Lock(raw_key, version).encode()?)?;
+            }
+            engine.delete(key)?;
+        }
+        Ok(())
+    }
+
+    /// Shard holding a raw key's versions, TTLs and write-set entries
+    fn shard(&self, key: &[u8]) -> &RwLock<E> {
+        &self.shards[self.shard_index(key)]
+    }
 
+    fn shard_index(&self, key: &[u8]) -> usize {
+        (fnv1a(key, 0xcbf29ce484222325) % self.shards.len() as u64) as usize
+    }
+
+    /// Runs `f` on each shard in turn, under that shard's write lock
+    fn for_each_shard(&self, mut f: impl FnMut(&mut E) -> Result<()>) -> Result<()> {
+        for shard in self.shards.iter() {
+            f(&mut *shard.write()?)?;
+        }
         Ok(())
     }
 
-    fn scan_active(engine: &mut MutexGuard<E>) -> Result<HashSet<Version>> {
-        let mut active_versions = HashSet::new();
-        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnActive.encode()?);
+    /// Write-set entries of a version in one shard, each with the raw keys
+    /// it stands for: its own for a `TxnWrite`, the listed ones for a `TxnBatch`
+    fn scan_writes(engine: &E, version: Version) -> Result<Vec<WriteEntry>> {
+        let mut writes = Vec::new();
+        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnWrite(version).encode()?);
+        while let Some((key, _)) = iter.next().transpose()? {
+            match MvccKey::decode(key.clone())? {
+                MvccKey::TxnWrite(_, raw_key) => writes.push((key, vec![raw_key])),
+                _ => {
+                    return Err(Error::Internal(format!(
+                        "unexpected key: {:?}",
+                        String::from_utf8(key)
+                    )))
+                }
+            }
+        }
+        drop(iter);
+        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnBatch(version).encode()?);
+        while let Some((key, value)) = iter.next().transpose()? {
+            writes.push((key, bincode::deserialize(&value)?));
+        }
+        Ok(writes)
+    }
 
+    /// Versions a new snapshot must not see: active and aborted ones
+    pub(super) fn scan_invisible(engine: &E) -> Result<HashSet<Version>> {
+        let mut versions: HashSet<Version> = Self::scan_active(engine)?.into_keys().collect();
+        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnAborted.encode()?);
         while let Some((key, _)) = iter.next().transpose()? {
+            if let MvccKey::TxnAborted(version) = MvccKey::decode(key)? {
+                versions.insert(version);
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Active versions and their begin times (unix millis)
+    pub(super) fn scan_active(engine: &E) -> Result<BTreeMap<Version, u64>> {
+        let mut active_versions = BTreeMap::new();
+        let mut iter = engine.scan_prefix(MvccKeyPrefix::TxnActive.encode()?);
+
+        while let Some((key, value)) = iter.next().transpose()? {
             match MvccKey::decode(key.clone())? {
                 MvccKey::TxnActive(version) => {
-                    active_versions.insert(version);
+                    active_versions.insert(version, bincode::deserialize(&value)?);
                 }
                 _ => {
                     return Err(Error::Internal(format!(
@@ -302,6 +1187,88 @@ impl<E: Engine> MvccTransaction<E> {
         }
         Ok(active_versions)
     }
+
+    /// Collects expiry times of all versions whose key starts with the prefix
+    fn scan_ttl(engine: &E, prefix: Vec<u8>) -> Result<HashMap<(Vec<u8>, Version), u64>> {
+        let mut enc_prefix = MvccKeyPrefix::Ttl(prefix).encode()?;
+        enc_prefix.truncate(enc_prefix.len() - 2);
+
+        let mut expiries = HashMap::new();
+        let mut iter = engine.scan_prefix(enc_prefix);
+        while let Some((key, value)) = iter.next().transpose()? {
+            match MvccKey::decode(key.clone())? {
+                MvccKey::Ttl(raw_key, version) => {
+                    expiries.insert((raw_key, version), bincode::deserialize(&value)?);
+                }
+                _ => {
+                    return Err(Error::Internal(format!(
+                        "unexpected key: {:?}",
+                        String::from_utf8(key)
+                    )))
+                }
+            }
+        }
+        Ok(expiries)
+    }
+}
+
+/// Write conflict on a raw key with the version blocking it
+fn write_conflict(key: &[u8], version: Version) -> Error {
+    Error::WriteConflict { key: key.escape_ascii().to_string(), table: None, version }
+}
+
+/// Current wall-clock time in milliseconds since the unix epoch
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) fn now_millis() -> Result<u64> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// In the browser std has no clock (`SystemTime::now` panics), so ask JavaScript
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) fn now_millis() -> Result<u64> {
+    Ok(js_sys::Date::now() as u64)
+}
+
+/// Lifecycle state of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    Active,
+    Committed,
+    RolledBack,
+    /// Forcibly ended by `Mvcc::abort`
+    Aborted,
+}
+
+impl std::fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TransactionStatus::Active => "active",
+            TransactionStatus::Committed => "committed",
+            TransactionStatus::RolledBack => "rolled back",
+            TransactionStatus::Aborted => "aborted",
+        })
+    }
+}
+
+/// Stored outcome of a transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub status: TransactionStatus,
+    /// Commit time in unix millis, for committed transactions
+    pub committed_at: Option<u64>,
+    /// Number of keys written
+    pub keys: u64,
+}
+
+/// A transaction listed by `MvccTransaction::transactions`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionInfo {
+    pub version: Version,
+    pub record: TransactionRecord,
 }
 
 /// Scan result containing key-value pair
@@ -313,12 +1280,60 @@ pub struct ScanResult {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        ops::RangeBounds,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
     use crate::{
         error::{Error, Result},
-        storage::{engine::Engine, memory::MemoryEngine},
+        storage::{
+            engine::{Durability, Engine, Syncer},
+            memory::{MemoryEngine, MemoryEngineIterator},
+        },
     };
 
-    use super::Mvcc;
+    use super::{Mvcc, MvccKeyPrefix};
+
+    /// Memory engine counting its flushes, whose syncs take a few milliseconds
+    struct FlushCounter(MemoryEngine, Arc<AtomicUsize>);
+
+    impl Engine for FlushCounter {
+        type EngineIterator<'a> = MemoryEngineIterator<'a>;
+
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+            self.0.set(key, value)
+        }
+
+        fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+            self.0.get(key)
+        }
+
+        fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+            self.0.delete(key)
+        }
+
+        fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+            self.0.scan(range)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.begin_flush()?()
+        }
+
+        fn begin_flush(&mut self) -> Result<Syncer> {
+            let flushes = self.1.clone();
+            Ok(Box::new(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                flushes.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }))
+        }
+    }
 
     #[test]
     fn test_get() -> Result<()> {
@@ -366,6 +1381,131 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_many_versions() -> Result<()> {
+        // Version 255 and its multiples encode with a trailing 0xFF byte,
+        // which the prefix scans over a transaction's keys must handle
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        for i in 0..300u32 {
+            let tx = mvcc.begin()?;
+            tx.set(b"key".to_vec(), i.to_be_bytes().to_vec())?;
+            if i % 7 == 0 {
+                tx.rollback()?;
+            } else {
+                tx.commit()?;
+            }
+        }
+        let tx = mvcc.begin()?;
+        let result = tx.scan_prefix(b"key".to_vec())?;
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 299u32.to_be_bytes().to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_format() -> Result<()> {
+        // The first transaction records the key format, later ones check it
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        mvcc.begin()?.commit()?;
+        let key = super::MvccKey::KeyFormat.encode()?;
+        assert_eq!(mvcc.shards[0].read()?.get(key.clone())?, Some(vec![crate::storage::keycode::FORMAT_VERSION]));
+        mvcc.begin()?.commit()?;
+
+        mvcc.shards[0].write()?.set(key, vec![crate::storage::keycode::FORMAT_VERSION + 1])?;
+        assert!(mvcc.begin().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_close() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        assert!(!mvcc.clean_shutdown());
+        let tx = mvcc.begin()?;
+        tx.set(b"key".to_vec(), b"val".to_vec())?;
+
+        // An active transaction holds up the close, which refuses new ones meanwhile
+        assert!(mvcc.close(Duration::from_millis(20)).is_err());
+        assert!(mvcc.begin().is_err());
+        assert!(mvcc.begin_as_of(1).is_err());
+        tx.commit()?;
+        mvcc.close(Duration::from_secs(1))?;
+
+        // The next startup consumes the marker
+        let engine = std::mem::replace(&mut *mvcc.shards[0].write()?, MemoryEngine::new());
+        let mvcc = Mvcc::new(engine);
+        assert!(mvcc.clean_shutdown());
+        assert_eq!(mvcc.begin()?.get(b"key".to_vec())?, Some(b"val".to_vec()));
+        let engine = std::mem::replace(&mut *mvcc.shards[0].write()?, MemoryEngine::new());
+        assert!(!Mvcc::new(engine).clean_shutdown());
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability() -> Result<()> {
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let mvcc = Mvcc::sharded(vec![
+            FlushCounter(MemoryEngine::new(), flushes.clone()),
+            FlushCounter(MemoryEngine::new(), flushes.clone()),
+        ]);
+        // Two writes, then a commit, flush each shard this many times
+        for (durability, expected) in [
+            (Durability::Commit, 2),
+            (Durability::Always, 4),
+            (Durability::Off, 0),
+            (Durability::Periodic(Duration::from_secs(3600)), 2),
+            (Durability::Periodic(Duration::from_secs(3600)), 0),
+        ] {
+            flushes.store(0, Ordering::SeqCst);
+            let mut tx = mvcc.begin()?;
+            tx.set_durability(durability);
+            tx.set(b"a".to_vec(), vec![1])?;
+            tx.set_batch(vec![(b"b".to_vec(), vec![2])])?;
+            tx.commit()?;
+            assert_eq!(flushes.load(Ordering::SeqCst), expected, "{:?}", durability);
+        }
+
+        // Rollbacks and read-only transactions flush nothing
+        flushes.store(0, Ordering::SeqCst);
+        let mvcc = mvcc.with_durability(Durability::Commit);
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), vec![3])?;
+        tx.rollback()?;
+        mvcc.begin_as_of(1)?.commit()?;
+        assert_eq!(flushes.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_commit() -> Result<()> {
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let mvcc = Mvcc::new(FlushCounter(MemoryEngine::new(), flushes.clone()));
+        let (threads, commits) = (16, 5);
+        let barrier = Arc::new(std::sync::Barrier::new(threads));
+        let handles = (0..threads as u8)
+            .map(|i| {
+                let (mvcc, barrier) = (mvcc.clone(), barrier.clone());
+                std::thread::spawn(move || -> Result<()> {
+                    barrier.wait();
+                    for j in 0..commits as u8 {
+                        let tx = mvcc.begin()?;
+                        tx.set(vec![i, j], vec![])?;
+                        tx.commit()?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        // Commits made while a flush syncs share the next one, so there are
+        // about two flushes per round of commits, far fewer than commits
+        let flushes = flushes.load(Ordering::SeqCst);
+        assert!(flushes > 0 && flushes * 4 <= threads * commits, "{} flushes", flushes);
+        assert_eq!(mvcc.begin()?.scan_prefix(vec![])?.len(), threads * commits);
+        Ok(())
+    }
+
     #[test]
     fn test_scan_prefix() -> Result<()> {
         let mvcc = Mvcc::new(MemoryEngine::new());
@@ -429,6 +1569,64 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scan_prefix_rev() -> Result<()> {
+        let mvcc = Mvcc::sharded(vec![MemoryEngine::new(), MemoryEngine::new()]);
+        let tx = mvcc.begin()?;
+        for (key, value) in [("a1", "v1"), ("a2", "v2"), ("a3", "v3"), ("a4", "v4"), ("b1", "v5")] {
+            tx.set(key.as_bytes().to_vec(), value.as_bytes().to_vec())?;
+        }
+        tx.commit()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"a2".to_vec(), b"v2-1".to_vec())?;
+        tx.delete(b"a3".to_vec())?;
+        tx.commit()?;
+
+        // Newer versions the snapshot can't see are skipped
+        let reader = mvcc.begin()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"a4".to_vec(), b"v4-1".to_vec())?;
+        tx.commit()?;
+
+        let scan = |limit| -> Result<Vec<(String, String)>> {
+            Ok(reader
+                .scan_prefix_rev(b"a".to_vec(), limit, |_, _| Ok(true))?
+                .into_iter()
+                .map(|r| (String::from_utf8(r.key).unwrap(), String::from_utf8(r.value).unwrap()))
+                .collect())
+        };
+        let all = vec![("a4".into(), "v4".into()), ("a2".into(), "v2-1".into()), ("a1".into(), "v1".into())];
+        assert_eq!(scan(usize::MAX)?, all);
+        assert_eq!(scan(2)?, all[..2]);
+
+        let filtered = reader.scan_prefix_rev(b"a".to_vec(), 1, |key, _| Ok(key.ends_with(b"1")))?;
+        assert_eq!(filtered.iter().map(|r| r.key.clone()).collect::<Vec<_>>(), vec![b"a1".to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_prefix_after() -> Result<()> {
+        let mvcc = Mvcc::sharded(vec![MemoryEngine::new(), MemoryEngine::new()]);
+        let tx = mvcc.begin()?;
+        for key in ["a1", "a2", "a3", "a4", "b1"] {
+            tx.set(key.as_bytes().to_vec(), key.as_bytes().to_vec())?;
+        }
+        tx.commit()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"a2".to_vec(), b"a2-1".to_vec())?;
+        tx.delete(b"a3".to_vec())?;
+        tx.commit()?;
+
+        // Pages resume past every version of the last key read
+        let reader = mvcc.begin()?;
+        let keys = |results: Vec<super::ScanResult>| results.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys(reader.scan_prefix_after(b"a".to_vec(), b"a1".to_vec(), 1)?), vec![b"a2".to_vec()]);
+        assert_eq!(keys(reader.scan_prefix_after(b"a".to_vec(), b"a2".to_vec(), 10)?), vec![b"a4".to_vec()]);
+        assert_eq!(keys(reader.scan_prefix_after(b"a".to_vec(), b"a0".to_vec(), 10)?).len(), 3);
+        assert!(reader.scan_prefix_after(b"a".to_vec(), b"a4".to_vec(), 10)?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_scan_isolation() -> Result<()> {
         let mvcc = Mvcc::new(MemoryEngine::new());
@@ -550,9 +1748,10 @@ mod tests {
         tx1.set(b"key1".to_vec(), b"val1-1".to_vec())?;
         tx1.set(b"key1".to_vec(), b"val1-2".to_vec())?;
 
+        // The conflict names the key and the version that wrote it first
         assert_eq!(
             tx2.set(b"key1".to_vec(), b"val1-3".to_vec()),
-            Err(Error::WriteConflict)
+            Err(Error::WriteConflict { key: "key1".into(), table: None, version: tx1.version() })
         );
 
         let tx3 = mvcc.begin()?;
@@ -561,13 +1760,46 @@ mod tests {
 
         assert_eq!(
             tx1.set(b"key5".to_vec(), b"val6-1".to_vec()),
-            Err(Error::WriteConflict)
+            Err(Error::WriteConflict { key: "key5".into(), table: None, version: tx3.version() })
         );
 
         tx1.commit()?;
         Ok(())
     }
 
+    #[test]
+    fn test_set_batch() -> Result<()> {
+        let mvcc = Mvcc::sharded((0..4).map(|_| MemoryEngine::new()).collect());
+        let tx = mvcc.begin()?;
+        tx.set_batch((0..20u8).map(|i| (vec![b'k', i], vec![i])).collect())?;
+        assert_eq!(tx.transactions()?.last().map(|info| info.record.keys), Some(20));
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        assert_eq!(tx1.scan_prefix(b"k".to_vec())?.len(), 20);
+        let tx2 = mvcc.begin()?;
+        tx2.set(vec![b'k', 7], vec![70])?;
+
+        // A conflict on any key fails the whole batch before it writes
+        assert_eq!(
+            tx1.set_batch(vec![(vec![b'k', 7], vec![0])]),
+            Err(Error::WriteConflict { key: "k\\x07".into(), table: None, version: tx2.version() })
+        );
+        tx2.commit()?;
+        tx1.rollback()?;
+
+        // Rolled back batches leave nothing behind
+        let tx3 = mvcc.begin()?;
+        tx3.set_batch((0..20u8).map(|i| (vec![b'k', i], vec![0])).collect())?;
+        tx3.set_batch(vec![(vec![b'k', 30], vec![30])])?;
+        tx3.rollback()?;
+        let tx4 = mvcc.begin()?;
+        assert_eq!(tx4.get(vec![b'k', 3])?, Some(vec![3]));
+        assert_eq!(tx4.get(vec![b'k', 7])?, Some(vec![70]));
+        assert_eq!(tx4.get(vec![b'k', 30])?, None);
+        Ok(())
+    }
+
     #[test]
     fn test_delete() -> Result<()> {
         let mvcc = Mvcc::new(MemoryEngine::new());
@@ -613,15 +1845,98 @@ mod tests {
         tx1.delete(b"key1".to_vec())?;
         tx1.set(b"key2".to_vec(), b"val2-1".to_vec())?;
 
+        assert!(matches!(tx2.delete(b"key1".to_vec()), Err(Error::WriteConflict { .. })));
+        assert!(matches!(tx2.delete(b"key2".to_vec()), Err(Error::WriteConflict { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        tx1.lock(b"key1".to_vec())?;
+        tx1.lock(b"key1".to_vec())?;
+
+        // Others can't write or lock the key while it's locked, only read it
         assert_eq!(
-            tx2.delete(b"key1".to_vec()),
-            Err(Error::WriteConflict)
+            tx2.set(b"key1".to_vec(), b"val1-2".to_vec()),
+            Err(Error::WriteConflict { key: "key1".into(), table: None, version: tx1.version() })
         );
+        assert!(matches!(tx2.lock(b"key1".to_vec()), Err(Error::WriteConflict { .. })));
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+
+        // A concurrent write keeps the key from being locked
+        tx2.set(b"key2".to_vec(), b"val2-2".to_vec())?;
+        assert!(matches!(tx1.lock(b"key2".to_vec()), Err(Error::WriteConflict { .. })));
+
+        // The holder writes the key as usual; finishing releases the lock
+        tx1.set(b"key1".to_vec(), b"val1-1".to_vec())?;
+        tx1.commit()?;
+        tx2.commit()?;
+        let tx3 = mvcc.begin()?;
+        tx3.lock(b"key1".to_vec())?;
+        tx3.lock(b"key3".to_vec())?;
+        tx3.rollback()?;
+        let tx4 = mvcc.begin()?;
+        tx4.set(b"key1".to_vec(), b"val1-4".to_vec())?;
+        tx4.set(b"key3".to_vec(), b"val3".to_vec())?;
+        tx4.commit()?;
+        assert_eq!(mvcc.begin()?.get(b"key1".to_vec())?, Some(b"val1-4".to_vec()));
+
+        assert!(mvcc.begin_read_only()?.lock(b"key1".to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serializable_scan() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx = mvcc.begin()?;
+        tx.set(b"k1".to_vec(), vec![1])?;
+        tx.set(b"k2".to_vec(), vec![2])?;
+        tx.commit()?;
+
+        // An insert into a scanned prefix committed meanwhile fails the scanner's commit
+        let tx1 = mvcc.begin_serializable()?;
+        assert_eq!(tx1.scan_prefix(b"k".to_vec())?.len(), 2);
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"k3".to_vec(), vec![3])?;
+        tx2.commit()?;
+        tx1.set(b"total".to_vec(), vec![3])?;
         assert_eq!(
-            tx2.delete(b"key2".to_vec()),
-            Err(Error::WriteConflict)
+            tx1.commit(),
+            Err(Error::WriteConflict { key: "k3".into(), table: None, version: tx2.version() })
         );
+        assert_eq!(mvcc.begin()?.get(b"total".to_vec())?, None);
 
+        // Scanning past a concurrent write fails right away
+        let tx3 = mvcc.begin()?;
+        tx3.delete(b"k1".to_vec())?;
+        let tx4 = mvcc.begin_serializable()?;
+        assert!(matches!(tx4.scan_prefix(b"k".to_vec()), Err(Error::WriteConflict { .. })));
+        tx4.rollback()?;
+        // Snapshot transactions and other prefixes are unaffected
+        let tx5 = mvcc.begin()?;
+        assert_eq!(tx5.scan_prefix(b"k".to_vec())?.len(), 3);
+        let tx6 = mvcc.begin_serializable()?;
+        assert!(tx6.scan_prefix(b"j".to_vec())?.is_empty());
+        tx6.set(b"total".to_vec(), vec![0])?;
+        tx3.commit()?;
+        tx5.commit()?;
+        tx6.commit()?;
+
+        // Finished transactions hold no predicate locks
+        let tx7 = mvcc.begin_serializable()?;
+        assert_eq!(tx7.scan_prefix(b"k".to_vec())?.len(), 2);
+        tx7.commit()?;
+        let meta = mvcc.shards[0].read()?;
+        assert_eq!(meta.scan_prefix(MvccKeyPrefix::TxnScan(tx7.version()).encode()?).count(), 0);
         Ok(())
     }
 
@@ -741,4 +2056,106 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_set_with_ttl() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set_with_ttl(b"key2".to_vec(), b"val2".to_vec(), Duration::ZERO)?;
+        tx.set_with_ttl(b"key3".to_vec(), b"val3".to_vec(), Duration::from_secs(3600))?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        assert_eq!(tx1.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx1.get(b"key2".to_vec())?, None);
+        assert_eq!(tx1.get(b"key3".to_vec())?, Some(b"val3".to_vec()));
+        assert_eq!(
+            tx1.scan_prefix(b"key".to_vec())?,
+            vec![
+                super::ScanResult {
+                    key: b"key1".to_vec(),
+                    value: b"val1".to_vec()
+                },
+                super::ScanResult {
+                    key: b"key3".to_vec(),
+                    value: b"val3".to_vec()
+                },
+            ]
+        );
+
+        // Expired keys stay hidden until rewritten, and a plain set clears the TTL
+        tx1.set(b"key2".to_vec(), b"val2-1".to_vec())?;
+        tx1.set_with_ttl(b"key1".to_vec(), b"val1-1".to_vec(), Duration::ZERO)?;
+        tx1.set(b"key1".to_vec(), b"val1-2".to_vec())?;
+        tx1.commit()?;
+
+        let tx2 = mvcc.begin()?;
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1-2".to_vec()));
+        assert_eq!(tx2.get(b"key2".to_vec())?, Some(b"val2-1".to_vec()));
+
+        tx2.set_with_ttl(b"key3".to_vec(), b"val3-1".to_vec(), Duration::ZERO)?;
+        tx2.rollback()?;
+
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(b"key3".to_vec())?, Some(b"val3".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_reads() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+
+        // Readers share the engine: a held read lock doesn't block them
+        let tx1 = mvcc.begin_read_only()?;
+        let guard = mvcc.shards[0].read()?;
+        let readers = (0..4)
+            .map(|_| {
+                let tx = tx1.clone();
+                std::thread::spawn(move || tx.get(b"key1".to_vec()))
+            })
+            .collect::<Vec<_>>();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap()?, Some(b"val1".to_vec()));
+        }
+        drop(guard);
+        tx1.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded() -> Result<()> {
+        let mvcc = Mvcc::sharded((0..4).map(|_| MemoryEngine::new()).collect());
+        let tx = mvcc.begin()?;
+        for i in 0..20u8 {
+            tx.set(vec![b'k', i], vec![i])?;
+        }
+        tx.commit()?;
+
+        // Keys spread over every shard, and scans merge them back in order
+        assert!(mvcc.shards.iter().all(|shard| shard.read().unwrap().scan(..).count() > 0));
+        let tx1 = mvcc.begin()?;
+        let keys = tx1.scan_prefix(b"k".to_vec())?.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys, (0..20u8).map(|i| vec![b'k', i]).collect::<Vec<_>>());
+
+        // Isolation and rollback hold across shards
+        let tx2 = mvcc.begin()?;
+        tx2.delete(vec![b'k', 3])?;
+        tx2.set(vec![b'k', 7], vec![70])?;
+        assert_eq!(tx1.get(vec![b'k', 3])?, Some(vec![3]));
+        assert_eq!(
+            tx1.set(vec![b'k', 7], vec![0]),
+            Err(Error::WriteConflict { key: "k\\x07".into(), table: None, version: tx2.version() })
+        );
+        tx2.rollback()?;
+
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(vec![b'k', 3])?, Some(vec![3]));
+        assert_eq!(tx3.get(vec![b'k', 7])?, Some(vec![7]));
+        assert_eq!(tx3.scan_prefix(b"k".to_vec())?.len(), 20);
+        Ok(())
+    }
 }
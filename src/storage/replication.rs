@@ -0,0 +1,515 @@
+//! Primary/replica streaming replication
+//!
+//! A primary `Mvcc` appends each committed write set to a `ReplicationLog`.
+//! `ReplicationServer` streams the log over TCP, and a `Replica` applies the
+//! records in commit order. The replica persists the sequence number of the
+//! last applied record, committed along with the record's writes, so a
+//! reconnect resumes from that checkpoint. The log only keeps its newest
+//! records (`ReplicationLog::with_retention`); a replica whose checkpoint
+//! falls behind them can't resume, and needs a fresh copy of the primary.
+//!
+//! Wire protocol: the replica sends its checkpoint as a big-endian u64, the
+//! primary answers with the sequence number of its oldest kept record (also
+//! a big-endian u64), then sends frames of `[u32 length][bincode CommitRecord]`.
+//!
+//! The TCP and thread based parts aren't built for the browser
+//! (`wasm32-unknown-unknown`); logs and `Replica::apply` still are.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        engine::Engine,
+        mvcc::{Mvcc, MvccKey, MvccTransaction, Version},
+    },
+};
+
+/// A single key write of a committed transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicatedWrite {
+    pub key: Vec<u8>,
+    /// None for deletes
+    pub value: Option<Vec<u8>>,
+    /// Absolute expiry (unix millis) for keys written with a TTL
+    pub expires_at: Option<u64>,
+}
+
+/// Write set of one committed transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommitRecord {
+    /// Position in the log, starting at 1
+    pub seq: u64,
+    /// Version of the transaction on the primary
+    pub version: Version,
+    pub writes: Vec<ReplicatedWrite>,
+}
+
+/// Records a `ReplicationLog` keeps by default
+pub const LOG_RETENTION: usize = 100_000;
+
+/// In-memory log of committed write sets, shared by the primary and its server
+///
+/// The log keeps its newest records, up to its retention, dropping older
+/// ones as commits append more; replicas can catch up from any record kept.
+#[derive(Clone)]
+pub struct ReplicationLog {
+    inner: Arc<(Mutex<Records>, Condvar)>,
+}
+
+struct Records {
+    kept: VecDeque<CommitRecord>,
+    /// Records dropped from the front so far
+    dropped: u64,
+    retention: usize,
+}
+
+impl Default for ReplicationLog {
+    fn default() -> Self {
+        Self::with_retention(LOG_RETENTION)
+    }
+}
+
+impl ReplicationLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A log keeping the given number of newest records
+    pub fn with_retention(retention: usize) -> Self {
+        let records = Records { kept: VecDeque::new(), dropped: 0, retention: retention.max(1) };
+        Self { inner: Arc::new((Mutex::new(records), Condvar::new())) }
+    }
+
+    /// Appends a committed write set and returns its sequence number
+    pub(crate) fn append(&self, version: Version, writes: Vec<ReplicatedWrite>) -> Result<u64> {
+        let (records, cond) = &*self.inner;
+        let mut records = records.lock()?;
+        let seq = records.dropped + records.kept.len() as u64 + 1;
+        records.kept.push_back(CommitRecord { seq, version, writes });
+        while records.kept.len() > records.retention {
+            records.kept.pop_front();
+            records.dropped += 1;
+        }
+        cond.notify_all();
+        Ok(seq)
+    }
+
+    /// Sequence number of the newest record (0 when empty)
+    pub fn last_seq(&self) -> Result<u64> {
+        let records = self.inner.0.lock()?;
+        Ok(records.dropped + records.kept.len() as u64)
+    }
+
+    /// Sequence number of the oldest record kept, or the next one while
+    /// none is
+    pub fn first_seq(&self) -> Result<u64> {
+        Ok(self.inner.0.lock()?.dropped + 1)
+    }
+
+    /// Returns the records after `seq`, waiting up to `timeout` for new ones;
+    /// fails if some were already dropped
+    pub fn wait_after(&self, seq: u64, timeout: Duration) -> Result<Vec<CommitRecord>> {
+        let (records, cond) = &*self.inner;
+        let mut records = records.lock()?;
+        if records.dropped + records.kept.len() as u64 <= seq {
+            records = cond.wait_timeout(records, timeout)?.0;
+        }
+        if seq < records.dropped {
+            return Err(Error::Internal(format!(
+                "replication log no longer has record {}, it starts at {}",
+                seq + 1,
+                records.dropped + 1
+            )));
+        }
+        Ok(records.kept.iter().skip((seq - records.dropped) as usize).cloned().collect())
+    }
+}
+
+/// TCP server streaming a replication log to replicas
+///
+/// Each replica connection is served by its own thread. Dropping the server
+/// stops accepting and ends all streams.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct ReplicationServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl ReplicationServer {
+    /// Binds the address and starts serving the log in the background
+    pub fn start(addr: impl ToSocketAddrs, log: ReplicationLog) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let stop = shutdown.clone();
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let (log, stop) = (log.clone(), stop.clone());
+                    // A failed stream only affects its replica, which can reconnect
+                    thread::spawn(move || Self::serve(stream, log, stop));
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shutdown,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Streams records after the replica's checkpoint until shutdown or disconnect
+    fn serve(mut stream: TcpStream, log: ReplicationLog, stop: Arc<AtomicBool>) -> Result<()> {
+        let mut buf = [0; 8];
+        stream.read_exact(&mut buf)?;
+        let mut seq = u64::from_be_bytes(buf);
+        stream.write_all(&log.first_seq()?.to_be_bytes())?;
+
+        while !stop.load(Ordering::SeqCst) {
+            for record in log.wait_after(seq, Duration::from_millis(100))? {
+                let frame = bincode::serialize(&record)?;
+                stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+                stream.write_all(&frame)?;
+                seq = record.seq;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Drop for ReplicationServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake the blocking accept so the thread can observe the flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.accept_thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Read-only copy of a primary, kept up to date by applying its commit records
+pub struct Replica<E: Engine> {
+    mvcc: Mvcc<E>,
+}
+
+impl<E: Engine> Clone for Replica<E> {
+    fn clone(&self) -> Self {
+        Self {
+            mvcc: self.mvcc.clone(),
+        }
+    }
+}
+
+impl<E: Engine + Send + Sync + 'static> Replica<E> {
+    pub fn new(engine: E) -> Self {
+        Self {
+            mvcc: Mvcc::new(engine),
+        }
+    }
+
+    /// Begins a read-only transaction on the replicated data
+    pub fn begin(&self) -> Result<MvccTransaction<E>> {
+        self.mvcc.begin_read_only()
+    }
+
+    /// Sequence number of the last applied record (0 if none)
+    pub fn checkpoint(&self) -> Result<u64> {
+        let (seq, pending) = self.stored_checkpoint()?;
+        Ok(match pending {
+            Some(_) => seq - 1,
+            None => seq,
+        })
+    }
+
+    /// The stored checkpoint, with the version of the transaction applying
+    /// its record if that hasn't committed: it's still active, or was aborted
+    ///
+    /// The checkpoint is stored before the transaction commits, together
+    /// with its version, so the record counts as applied exactly when its
+    /// writes are visible, even after a crash between the two.
+    fn stored_checkpoint(&self) -> Result<(u64, Option<Version>)> {
+        let engine = self.mvcc.shards[0].read()?;
+        let Some(value) = engine.get(MvccKey::ReplicaCheckpoint.encode()?)? else {
+            return Ok((0, None));
+        };
+        let (seq, version): (u64, Version) = bincode::deserialize(&value)?;
+        let pending = engine.get(MvccKey::TxnActive(version).encode()?)?.is_some()
+            || engine.get(MvccKey::TxnAborted(version).encode()?)?.is_some();
+        Ok((seq, pending.then_some(version)))
+    }
+
+    /// Stores the checkpoint, counting once the transaction of the given
+    /// version commits (0 for none)
+    fn set_checkpoint(&self, seq: u64, version: Version) -> Result<()> {
+        let mut engine = self.mvcc.shards[0].write()?;
+        engine.set(MvccKey::ReplicaCheckpoint.encode()?, bincode::serialize(&(seq, version))?)
+    }
+
+    /// Applies a commit record, skipping records that were already applied
+    ///
+    /// Records are applied one at a time, in order.
+    pub fn apply(&self, record: &CommitRecord) -> Result<()> {
+        let (seq, pending) = self.stored_checkpoint()?;
+        // An apply that crashed left its transaction active, holding its
+        // writes, which the record's own would conflict with
+        if let Some(version) = pending {
+            if self.mvcc.active_transactions()?.iter().any(|(active, _)| *active == version) {
+                self.mvcc.abort(version)?;
+            }
+            self.set_checkpoint(seq - 1, 0)?;
+        }
+        let checkpoint = self.checkpoint()?;
+        if record.seq <= checkpoint {
+            return Ok(());
+        }
+        if record.seq != checkpoint + 1 {
+            return Err(Error::Internal(format!(
+                "replication gap: expected record {}, got {}",
+                checkpoint + 1,
+                record.seq
+            )));
+        }
+
+        let txn = self.mvcc.begin()?;
+        self.set_checkpoint(record.seq, txn.version())?;
+        let written = record
+            .writes
+            .iter()
+            .try_for_each(|write| txn.write_inner(write.key.clone(), write.value.clone(), write.expires_at));
+        match written {
+            // A failed commit leaves the transaction active or aborted
+            Ok(()) => txn.commit(),
+            Err(err) => {
+                self.set_checkpoint(checkpoint, 0)?;
+                txn.rollback().and(Err(err))
+            }
+        }
+    }
+
+    /// Connects to a primary and applies its stream in the background
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn follow(&self, addr: impl ToSocketAddrs) -> Result<ReplicaStream> {
+        let mut stream = TcpStream::connect(addr)?;
+        let checkpoint = self.checkpoint()?;
+        stream.write_all(&checkpoint.to_be_bytes())?;
+        let mut first = [0; 8];
+        stream.read_exact(&mut first)?;
+        let first = u64::from_be_bytes(first);
+        if checkpoint + 1 < first {
+            return Err(Error::Internal(format!(
+                "replica is at record {}, but the primary's log starts at {}; it needs a fresh copy",
+                checkpoint, first
+            )));
+        }
+
+        let control = stream.try_clone()?;
+        let replica = self.clone();
+        let thread = thread::spawn(move || -> Result<()> {
+            loop {
+                let mut len = [0; 4];
+                match stream.read_exact(&mut len) {
+                    Ok(()) => {}
+                    // The primary went away or the stream was stopped
+                    Err(_) => return Ok(()),
+                }
+                let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+                stream.read_exact(&mut frame)?;
+                replica.apply(&bincode::deserialize(&frame)?)?;
+            }
+        });
+
+        Ok(ReplicaStream {
+            control,
+            thread: Some(thread),
+        })
+    }
+
+    /// Blocks until the replica has applied `seq`, returning false on timeout
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn wait_for(&self, seq: u64, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        while self.checkpoint()? < seq {
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        Ok(true)
+    }
+}
+
+/// Handle to a running replication stream
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct ReplicaStream {
+    control: TcpStream,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl ReplicaStream {
+    /// Disconnects from the primary and returns the stream's outcome
+    pub fn stop(mut self) -> Result<()> {
+        let _ = self.control.shutdown(std::net::Shutdown::Both);
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| Error::Internal("replication thread panicked".into()))?,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{CommitRecord, Replica, ReplicatedWrite, ReplicationLog, ReplicationServer};
+    use crate::{
+        error::Result,
+        storage::{memory::MemoryEngine, mvcc::Mvcc},
+    };
+
+    #[test]
+    fn test_replication() -> Result<()> {
+        let log = ReplicationLog::new();
+        let primary = Mvcc::new(MemoryEngine::new()).with_replication_log(log.clone());
+        let server = ReplicationServer::start("127.0.0.1:0", log.clone())?;
+
+        let tx = primary.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx.commit()?;
+        // Rolled back and read-only transactions are not shipped
+        let tx = primary.begin()?;
+        tx.set(b"key3".to_vec(), b"val3".to_vec())?;
+        tx.rollback()?;
+        primary.begin()?.commit()?;
+        assert_eq!(log.last_seq()?, 1);
+
+        // Catch up from scratch, then follow live commits
+        let replica = Replica::new(MemoryEngine::new());
+        let stream = replica.follow(server.local_addr())?;
+        let tx = primary.begin()?;
+        tx.delete(b"key1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2-1".to_vec())?;
+        tx.commit()?;
+        assert!(replica.wait_for(2, Duration::from_secs(5))?);
+        stream.stop()?;
+
+        let tx = replica.begin()?;
+        assert_eq!(tx.get(b"key1".to_vec())?, None);
+        assert_eq!(tx.get(b"key2".to_vec())?, Some(b"val2-1".to_vec()));
+        assert_eq!(tx.get(b"key3".to_vec())?, None);
+        assert!(tx.set(b"key4".to_vec(), b"val4".to_vec()).is_err());
+
+        // Resume from the checkpoint after reconnecting
+        let tx = primary.begin()?;
+        tx.set(b"key4".to_vec(), b"val4".to_vec())?;
+        tx.commit()?;
+        let stream = replica.follow(server.local_addr())?;
+        assert!(replica.wait_for(3, Duration::from_secs(5))?);
+        stream.stop()?;
+        assert_eq!(replica.checkpoint()?, 3);
+        assert_eq!(replica.begin()?.get(b"key4".to_vec())?, Some(b"val4".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_crash() -> Result<()> {
+        let replica = Replica::new(MemoryEngine::new());
+        let write = |key: &[u8], value: Option<&[u8]>| ReplicatedWrite {
+            key: key.to_vec(),
+            value: value.map(|v| v.to_vec()),
+            expires_at: None,
+        };
+        let record = |seq, writes| CommitRecord { seq, version: seq, writes };
+        replica.apply(&record(1, vec![write(b"a", Some(b"1")), write(b"b", Some(b"1"))]))?;
+
+        // An apply that stops short of its commit doesn't count, and the
+        // record is applied anew, once
+        let txn = replica.mvcc.begin()?;
+        replica.set_checkpoint(2, txn.version())?;
+        txn.delete(b"a".to_vec())?;
+        drop(txn);
+        assert_eq!(replica.checkpoint()?, 1);
+        let second = record(2, vec![write(b"a", None), write(b"b", Some(b"2"))]);
+        replica.apply(&second)?;
+        replica.apply(&second)?;
+        assert_eq!(replica.checkpoint()?, 2);
+        let txn = replica.begin()?;
+        assert_eq!(txn.get(b"a".to_vec())?, None);
+        assert_eq!(txn.get(b"b".to_vec())?, Some(b"2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_abort() -> Result<()> {
+        // An abort racing a commit fails it or comes too late, and only the
+        // commits that succeed are logged
+        let log = ReplicationLog::new();
+        let primary = Mvcc::new(MemoryEngine::new()).with_replication_log(log.clone());
+        let mut committed = Vec::new();
+        for i in 0..200u8 {
+            let tx = primary.begin()?;
+            tx.set(vec![i], vec![i])?;
+            let (mvcc, version) = (primary.clone(), tx.version());
+            let abort = thread::spawn(move || mvcc.abort(version));
+            if tx.commit().is_ok() {
+                committed.push(version);
+            }
+            let _ = abort.join();
+        }
+        let logged = log.wait_after(0, Duration::ZERO)?.into_iter().map(|record| record.version).collect::<Vec<_>>();
+        assert_eq!(logged, committed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_retention() -> Result<()> {
+        let log = ReplicationLog::with_retention(2);
+        let primary = Mvcc::new(MemoryEngine::new()).with_replication_log(log.clone());
+        for i in 0..3u8 {
+            let tx = primary.begin()?;
+            tx.set(vec![i], vec![i])?;
+            tx.commit()?;
+        }
+        assert_eq!((log.first_seq()?, log.last_seq()?), (2, 3));
+        assert!(log.wait_after(0, Duration::ZERO).is_err());
+        assert_eq!(log.wait_after(1, Duration::ZERO)?.len(), 2);
+
+        // A replica behind the kept records can't follow
+        let server = ReplicationServer::start("127.0.0.1:0", log.clone())?;
+        let replica = Replica::new(MemoryEngine::new());
+        assert!(replica.follow(server.local_addr()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,497 @@
+//! Snapshot files: a store's committed data frozen in one immutable file
+//!
+//! `Mvcc::export_snapshot` writes the data a transaction sees as a sorted
+//! string table, which another process opens with `ReadOnlySnapshotEngine`
+//! and serves through an MVCC layer in read-only mode (see
+//! `Database::open_snapshot`). The file holds:
+//!
+//! - blocks of sorted key/value entries, each about `BLOCK_SIZE` bytes
+//! - metadata: the first key, position and checksum of every block, and a
+//!   bloom filter over the keys
+//! - a footer: the metadata's position and the magic bytes
+//!
+//! Opening reads only the metadata; blocks are read as lookups and scans
+//! reach them.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    ops::{Bound, RangeBounds},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    storage::{
+        engine::{Engine, EngineIterator, fnv1a},
+        keycode,
+        mvcc::{Mvcc, MvccKey},
+    },
+};
+
+/// Identifies snapshot files, and their format version
+const MAGIC: &[u8; 8] = b"RDBSNAP1";
+
+/// Entry bytes after which a block is closed
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Raw keys read per page while exporting
+const EXPORT_PAGE_KEYS: usize = 1024;
+
+/// Bloom filter density, about a 1% false positive rate
+const BLOOM_BITS_PER_KEY: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry(#[serde(with = "serde_bytes")] Vec<u8>, #[serde(with = "serde_bytes")] Vec<u8>);
+
+/// Where a block is stored
+#[derive(Debug, Serialize, Deserialize)]
+struct BlockHandle {
+    #[serde(with = "serde_bytes")]
+    first_key: Vec<u8>,
+    offset: u64,
+    len: u64,
+    /// FNV-1a of the block's bytes
+    checksum: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    blocks: Vec<BlockHandle>,
+    bloom: BloomFilter,
+    entries: u64,
+}
+
+/// Writes a snapshot file from entries added in ascending key order
+pub struct SnapshotWriter {
+    file: BufWriter<File>,
+    offset: u64,
+    block: Vec<Entry>,
+    block_bytes: usize,
+    blocks: Vec<BlockHandle>,
+    /// Every key added, for the bloom filter sized once they're all known
+    keys: Vec<Vec<u8>>,
+}
+
+impl SnapshotWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(Self {
+            file,
+            offset: MAGIC.len() as u64,
+            block: Vec::new(),
+            block_bytes: 0,
+            blocks: Vec::new(),
+            keys: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        if self.keys.last().is_some_and(|last| *last >= key) {
+            return Err(Error::Internal(format!("snapshot key {:?} out of order", key)));
+        }
+        self.keys.push(key.clone());
+        self.block_bytes += key.len() + value.len();
+        self.block.push(Entry(key, value));
+        if self.block_bytes >= BLOCK_SIZE {
+            self.finish_block()?;
+        }
+        Ok(())
+    }
+
+    fn finish_block(&mut self) -> Result<()> {
+        let Some(Entry(first_key, _)) = self.block.first() else {
+            return Ok(());
+        };
+        let first_key = first_key.clone();
+        let bytes = bincode::serialize(&std::mem::take(&mut self.block))?;
+        self.file.write_all(&bytes)?;
+        self.blocks.push(BlockHandle {
+            first_key,
+            offset: self.offset,
+            len: bytes.len() as u64,
+            checksum: fnv1a(&bytes, 0xcbf29ce484222325),
+        });
+        self.offset += bytes.len() as u64;
+        self.block_bytes = 0;
+        Ok(())
+    }
+
+    /// Writes the metadata and footer, and syncs the file; returns the
+    /// number of entries
+    pub fn finish(mut self) -> Result<u64> {
+        self.finish_block()?;
+        let mut bloom = BloomFilter::new(self.keys.len(), BLOOM_BITS_PER_KEY);
+        self.keys.iter().for_each(|key| bloom.insert(key));
+        let entries = self.keys.len() as u64;
+        let metadata = bincode::serialize(&Metadata { blocks: self.blocks, bloom, entries })?;
+        self.file.write_all(&metadata)?;
+        self.file.write_all(&self.offset.to_le_bytes())?;
+        self.file.write_all(MAGIC)?;
+        self.file.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        Ok(entries)
+    }
+}
+
+/// Storage engine over a snapshot file, which it never writes
+///
+/// Writes fail, so MVCC on top must be read-only (`Mvcc::with_read_only`).
+pub struct ReadOnlySnapshotEngine {
+    file: Mutex<File>,
+    metadata: Metadata,
+}
+
+impl ReadOnlySnapshotEngine {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+        let footer_len = (MAGIC.len() + 8) as u64;
+        let mut magic = [0; MAGIC.len()];
+        if len >= footer_len + MAGIC.len() as u64 {
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut magic)?;
+        }
+        if magic != *MAGIC {
+            return Err(Error::Internal(format!("{} is not a snapshot file", path.display())));
+        }
+
+        let mut footer = [0; MAGIC.len() + 8];
+        file.seek(SeekFrom::Start(len - footer_len))?;
+        file.read_exact(&mut footer)?;
+        let offset = u64::from_le_bytes(footer[..8].try_into().expect("8 bytes"));
+        if footer[8..] != *MAGIC || offset > len - footer_len {
+            return Err(Error::Internal(format!("snapshot file {} is truncated", path.display())));
+        }
+        let mut metadata = vec![0; (len - footer_len - offset) as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut metadata)?;
+        Ok(Self { file: Mutex::new(file), metadata: bincode::deserialize(&metadata)? })
+    }
+
+    /// Number of entries in the snapshot
+    pub fn len(&self) -> u64 {
+        self.metadata.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metadata.entries == 0
+    }
+
+    fn read_block(&self, index: usize) -> Result<Vec<Entry>> {
+        let handle = &self.metadata.blocks[index];
+        let mut bytes = vec![0; handle.len as usize];
+        {
+            let mut file = self.file.lock()?;
+            file.seek(SeekFrom::Start(handle.offset))?;
+            file.read_exact(&mut bytes)?;
+        }
+        if fnv1a(&bytes, 0xcbf29ce484222325) != handle.checksum {
+            return Err(Error::Internal(format!("snapshot block at {} is corrupted", handle.offset)));
+        }
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    /// Index of the block that would hold a key
+    fn block_of(&self, key: &[u8]) -> Option<usize> {
+        self.metadata.blocks.partition_point(|block| *block.first_key <= *key).checked_sub(1)
+    }
+}
+
+impl Engine for ReadOnlySnapshotEngine {
+    type EngineIterator<'a> = SnapshotIterator<'a>;
+
+    fn set(&mut self, _key: Vec<u8>, _value: Vec<u8>) -> Result<()> {
+        Err(Error::Internal("snapshot is read-only".into()))
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if !self.metadata.bloom.may_contain(&key) {
+            return Ok(None);
+        }
+        let Some(index) = self.block_of(&key) else {
+            return Ok(None);
+        };
+        let block = self.read_block(index)?;
+        Ok(block
+            .binary_search_by(|Entry(k, _)| k.cmp(&key))
+            .ok()
+            .map(|i| block.into_iter().nth(i).expect("found entry").1))
+    }
+
+    fn delete(&mut self, _key: Vec<u8>) -> Result<()> {
+        Err(Error::Internal("snapshot is read-only".into()))
+    }
+
+    fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+        let blocks = &self.metadata.blocks;
+        let front_block = match range.start_bound() {
+            Bound::Included(key) | Bound::Excluded(key) => self.block_of(key).unwrap_or(0),
+            Bound::Unbounded => 0,
+        };
+        let back_block = match range.end_bound() {
+            Bound::Included(key) => blocks.partition_point(|block| block.first_key <= *key),
+            Bound::Excluded(key) => blocks.partition_point(|block| block.first_key < *key),
+            Bound::Unbounded => blocks.len(),
+        };
+        SnapshotIterator {
+            engine: self,
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+            front_block,
+            back_block: back_block.max(front_block),
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+        }
+    }
+}
+
+/// Snapshot file iterator, reading a block at a time from either end
+pub struct SnapshotIterator<'a> {
+    engine: &'a ReadOnlySnapshotEngine,
+    range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// Blocks not read yet, from `front_block` up to `back_block`
+    front_block: usize,
+    back_block: usize,
+    /// Entries of the blocks read from the front and from the back
+    front: VecDeque<(Vec<u8>, Vec<u8>)>,
+    back: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SnapshotIterator<'_> {
+    /// Reads a block's entries within the range, ending the scan on an error
+    fn read(&mut self, index: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        match self.engine.read_block(index) {
+            Ok(block) => Ok(block
+                .into_iter()
+                .filter(|Entry(key, _)| self.range.contains(key))
+                .map(|Entry(key, value)| (key, value))
+                .collect()),
+            Err(err) => {
+                self.front_block = self.back_block;
+                self.front.clear();
+                self.back.clear();
+                Err(err)
+            }
+        }
+    }
+}
+
+impl EngineIterator for SnapshotIterator<'_> {}
+
+impl Iterator for SnapshotIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.front.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.front_block == self.back_block {
+                return self.back.pop_front().map(Ok);
+            }
+            let index = self.front_block;
+            self.front_block += 1;
+            match self.read(index) {
+                Ok(entries) => self.front.extend(entries),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for SnapshotIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.back.pop_back() {
+                return Some(Ok(entry));
+            }
+            if self.front_block == self.back_block {
+                return self.front.pop_back().map(Ok);
+            }
+            self.back_block -= 1;
+            match self.read(self.back_block) {
+                Ok(entries) => self.back.extend(entries),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl<E: Engine> Mvcc<E> {
+    /// Writes the data a new transaction sees to a snapshot file, for
+    /// `ReadOnlySnapshotEngine`, returning how many keys it holds
+    ///
+    /// The snapshot keeps only the latest committed value of each key, as
+    /// version 1, and no TTLs: it stays as it was when exported.
+    pub fn export_snapshot(&self, path: &Path) -> Result<u64> {
+        let txn = self.begin_read_only()?;
+        let result = (|| {
+            let mut writer = SnapshotWriter::create(path)?;
+            // Metadata keys sort around the versions by their tags
+            writer.add(MvccKey::NextVersion.encode()?, bincode::serialize(&2u64)?)?;
+            let mut after = None;
+            loop {
+                let page = match after.take() {
+                    Some(after) => txn.scan_prefix_after(vec![], after, EXPORT_PAGE_KEYS)?,
+                    None => txn.scan_prefix_limit(vec![], EXPORT_PAGE_KEYS, |_, _| Ok(true))?,
+                };
+                let full = page.len() == EXPORT_PAGE_KEYS;
+                for result in page {
+                    writer.add(MvccKey::Version(result.key.clone(), 1).encode()?, bincode::serialize(&Some(result.value))?)?;
+                    after = Some(result.key);
+                }
+                if !full {
+                    break;
+                }
+            }
+            writer.add(MvccKey::KeyFormat.encode()?, vec![keycode::FORMAT_VERSION])?;
+            Ok(writer.finish()? - 2)
+        })();
+        txn.rollback()?;
+        result
+    }
+}
+
+/// Answers whether a key might be in the file, with no false negatives, so
+/// lookups of absent keys rarely read a block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BloomFilter {
+    #[serde(with = "serde_bytes")]
+    bits: Vec<u8>,
+    /// Number of hash functions (bit probes per key)
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for the expected number of keys
+    fn new(keys: usize, bits_per_key: usize) -> Self {
+        // Optimal probe count is bits_per_key * ln(2)
+        let hashes = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        let bits = (keys * bits_per_key).max(64);
+        Self { bits: vec![0; bits.div_ceil(8)], hashes }
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        for bit in self.probes(key) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns false if the key was definitely never inserted
+    fn may_contain(&self, key: &[u8]) -> bool {
+        self.probes(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Bit positions of a key, by double hashing (h1 + i * h2)
+    fn probes(&self, key: &[u8]) -> impl Iterator<Item = usize> + use<> {
+        let len = self.bits.len() as u64 * 8;
+        let h1 = fnv1a(key, 0xcbf29ce484222325);
+        // Odd, so the probes cycle through every bit
+        let h2 = fnv1a(key, 0x84222325cbf29ce4) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::{BLOOM_BITS_PER_KEY, BloomFilter, ReadOnlySnapshotEngine, SnapshotWriter};
+    use crate::{
+        error::Result,
+        storage::{engine::Engine, memory::MemoryEngine, mvcc::Mvcc},
+    };
+
+    #[test]
+    fn test_snapshot_engine() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot");
+        let mut writer = SnapshotWriter::create(&path)?;
+        for i in 0..1000u32 {
+            writer.add(format!("key{:04}", i).into_bytes(), vec![i as u8; 20])?;
+        }
+        assert!(writer.add(b"key0000".to_vec(), vec![]).is_err());
+        assert_eq!(writer.finish()?, 1000);
+
+        let mut engine = ReadOnlySnapshotEngine::open(&path)?;
+        assert!(engine.metadata.blocks.len() > 1);
+        assert_eq!(engine.get(b"key0500".to_vec())?, Some(vec![244; 20]));
+        assert_eq!(engine.get(b"key1000".to_vec())?, None);
+        assert_eq!(engine.get(b"a".to_vec())?, None);
+        assert!(engine.set(b"a".to_vec(), vec![]).is_err());
+        assert!(engine.delete(b"key0001".to_vec()).is_err());
+
+        // Scans read across blocks from both ends
+        let keys = |range: (Bound<Vec<u8>>, Bound<Vec<u8>>)| -> Result<Vec<String>> {
+            engine.scan(range).map(|r| Ok(String::from_utf8(r?.0).unwrap())).collect()
+        };
+        assert_eq!(keys((Bound::Unbounded, Bound::Unbounded))?.len(), 1000);
+        let range = keys((Bound::Excluded(b"key0100".to_vec()), Bound::Included(b"key0900".to_vec())))?;
+        assert_eq!((range.len(), range[0].as_str(), range[799].as_str()), (800, "key0101", "key0900"));
+        let mut iter = engine.scan_prefix(b"key0".to_vec());
+        assert_eq!(iter.next_back().transpose()?.unwrap().0, b"key0999");
+        assert_eq!(iter.next().transpose()?.unwrap().0, b"key0000");
+        assert_eq!(iter.rev().count(), 998);
+        assert_eq!(engine.scan_prefix(b"zzz".to_vec()).count(), 0);
+
+        std::fs::write(&path, b"not a snapshot")?;
+        assert!(ReadOnlySnapshotEngine::open(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_filter() -> Result<()> {
+        let mut filter = BloomFilter::new(1000, BLOOM_BITS_PER_KEY);
+        for i in 0..1000u32 {
+            filter.insert(format!("key{}", i).as_bytes());
+        }
+        // No false negatives
+        assert!((0..1000u32).all(|i| filter.may_contain(format!("key{}", i).as_bytes())));
+
+        // False positives stay near the configured rate
+        let false_positives = (1000..11000u32)
+            .filter(|i| filter.may_contain(format!("key{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        // Filters round-trip through their stored form
+        let decoded: BloomFilter = bincode::deserialize(&bincode::serialize(&filter)?)?;
+        assert_eq!(decoded, filter);
+        assert!(!BloomFilter::new(10, BLOOM_BITS_PER_KEY).may_contain(b"key0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_snapshot() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), b"1".to_vec())?;
+        tx.set(b"b".to_vec(), b"1".to_vec())?;
+        tx.commit()?;
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), b"2".to_vec())?;
+        tx.delete(b"b".to_vec())?;
+        tx.commit()?;
+        // Uncommitted writes stay out
+        let pending = mvcc.begin()?;
+        pending.set(b"c".to_vec(), b"1".to_vec())?;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot");
+        assert_eq!(mvcc.export_snapshot(&path)?, 1);
+        pending.commit()?;
+
+        let snapshot = Mvcc::new(ReadOnlySnapshotEngine::open(&path)?).with_read_only();
+        let tx = snapshot.begin()?;
+        assert_eq!(tx.get(b"a".to_vec())?, Some(b"2".to_vec()));
+        assert_eq!(tx.get(b"b".to_vec())?, None);
+        assert_eq!(tx.get(b"c".to_vec())?, None);
+        assert!(tx.set(b"c".to_vec(), b"2".to_vec()).is_err());
+        tx.commit()?;
+        Ok(())
+    }
+}
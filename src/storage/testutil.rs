@@ -0,0 +1,200 @@
+//! Storage engine conformance tests
+//!
+//! `test_engine_conformance` checks that an `Engine` implementation behaves
+//! like the memory engine: point reads and writes, ordered range scans in
+//! both directions, prefix scans, and edge cases such as the empty key,
+//! empty values, keys around the 0x00 and 0xFF byte boundaries and large
+//! values. Engines outside this crate can run it from their own tests:
+//!
+//! ```ignore
+//! #[test]
+//! fn test_conformance() -> Result<()> {
+//!     rustdb::storage::testutil::test_engine_conformance(|| MyEngine::open_temp())
+//! }
+//! ```
+//!
+//! Each check gets a fresh engine from `make_engine`, and failures panic
+//! like the `assert!` of any test. Engine errors are returned.
+
+use std::ops::Bound;
+
+use crate::{error::Result, storage::engine::Engine};
+
+/// Runs every conformance check, each on a new engine
+pub fn test_engine_conformance<E: Engine>(make_engine: impl Fn() -> E) -> Result<()> {
+    test_point_ops(make_engine())?;
+    test_edge_keys(make_engine())?;
+    test_empty_values(make_engine())?;
+    test_scan(make_engine())?;
+    test_reverse_scan(make_engine())?;
+    test_scan_prefix(make_engine())?;
+    test_prefix_boundaries(make_engine())?;
+    test_large_values(make_engine())?;
+    Ok(())
+}
+
+/// Collects a scan's keys
+fn keys(iter: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>) -> Result<Vec<Vec<u8>>> {
+    iter.map(|item| item.map(|(key, _)| key)).collect()
+}
+
+/// Sets, overwrites and deletes single keys
+pub fn test_point_ops(mut eng: impl Engine) -> Result<()> {
+    assert_eq!(eng.get(b"not exist".to_vec())?, None);
+
+    eng.set(b"aa".to_vec(), vec![1, 2, 3, 4])?;
+    assert_eq!(eng.get(b"aa".to_vec())?, Some(vec![1, 2, 3, 4]));
+
+    eng.set(b"aa".to_vec(), vec![5, 6, 7, 8])?;
+    assert_eq!(eng.get(b"aa".to_vec())?, Some(vec![5, 6, 7, 8]));
+
+    eng.delete(b"aa".to_vec())?;
+    assert_eq!(eng.get(b"aa".to_vec())?, None);
+    // Deleting a missing key is no error
+    eng.delete(b"aa".to_vec())?;
+
+    eng.set(b"cc".to_vec(), vec![5, 6, 7, 8])?;
+    assert_eq!(eng.get(b"cc".to_vec())?, Some(vec![5, 6, 7, 8]));
+    assert_eq!(eng.get(b"c".to_vec())?, None);
+    assert_eq!(eng.get(b"ccc".to_vec())?, None);
+    Ok(())
+}
+
+/// The empty key and keys of 0x00 and 0xFF bytes are ordinary keys, kept
+/// in byte order
+pub fn test_edge_keys(mut eng: impl Engine) -> Result<()> {
+    let edge = [vec![0xff, 0xff], vec![0xff], vec![0x00, 0xff], vec![0x00, 0x00], vec![0x00], vec![]];
+    for (i, key) in edge.iter().enumerate() {
+        eng.set(key.clone(), vec![i as u8])?;
+    }
+    for (i, key) in edge.iter().enumerate() {
+        assert_eq!(eng.get(key.clone())?, Some(vec![i as u8]), "{:x?}", key);
+    }
+    let mut sorted = edge.to_vec();
+    sorted.reverse();
+    assert_eq!(keys(eng.scan(..))?, sorted);
+
+    eng.delete(vec![])?;
+    assert_eq!(eng.get(vec![])?, None);
+    assert_eq!(keys(eng.scan(..))?.first(), Some(&vec![0x00]));
+    Ok(())
+}
+
+/// Empty values are stored, and differ from missing keys
+pub fn test_empty_values(mut eng: impl Engine) -> Result<()> {
+    eng.set(b"a".to_vec(), vec![])?;
+    eng.set(vec![], vec![])?;
+    assert_eq!(eng.get(b"a".to_vec())?, Some(vec![]));
+    assert_eq!(eng.get(vec![])?, Some(vec![]));
+
+    let items = eng.scan(..).collect::<Result<Vec<_>>>()?;
+    assert_eq!(items, vec![(vec![], vec![]), (b"a".to_vec(), vec![])]);
+
+    eng.set(b"a".to_vec(), b"x".to_vec())?;
+    eng.set(b"a".to_vec(), vec![])?;
+    assert_eq!(eng.get(b"a".to_vec())?, Some(vec![]));
+    Ok(())
+}
+
+/// Range scans honor each kind of bound
+pub fn test_scan(mut eng: impl Engine) -> Result<()> {
+    for key in [b"nnaes", b"amhue", b"meeae", b"uujeh", b"anehe"] {
+        eng.set(key.to_vec(), b"value".to_vec())?;
+    }
+
+    let start = Bound::Included(b"a".to_vec());
+    let end = Bound::Excluded(b"e".to_vec());
+    assert_eq!(keys(eng.scan((start, end)))?, vec![b"amhue".to_vec(), b"anehe".to_vec()]);
+
+    assert_eq!(keys(eng.scan(b"anehe".to_vec()..b"nnaes".to_vec()))?, vec![b"anehe".to_vec(), b"meeae".to_vec()]);
+    assert_eq!(keys(eng.scan(b"anehe".to_vec()..=b"meeae".to_vec()))?, vec![b"anehe".to_vec(), b"meeae".to_vec()]);
+    let start = Bound::Excluded(b"anehe".to_vec());
+    assert_eq!(keys(eng.scan((start, Bound::Unbounded)))?.len(), 3);
+    assert_eq!(keys(eng.scan(..b"amhue".to_vec()))?, Vec::<Vec<u8>>::new());
+    assert_eq!(keys(eng.scan(b"v".to_vec()..))?, Vec::<Vec<u8>>::new());
+    assert_eq!(keys(eng.scan(b"b".to_vec()..b"b".to_vec()))?, Vec::<Vec<u8>>::new());
+    Ok(())
+}
+
+/// Scans run backwards, and from both ends at once
+pub fn test_reverse_scan(mut eng: impl Engine) -> Result<()> {
+    for key in [b"nnaes", b"amhue", b"meeae", b"uujeh", b"anehe"] {
+        eng.set(key.to_vec(), b"value".to_vec())?;
+    }
+
+    let mut iter = eng.scan(b"b".to_vec()..b"z".to_vec());
+    assert_eq!(iter.next_back().transpose()?.map(|(k, _)| k), Some(b"uujeh".to_vec()));
+    assert_eq!(iter.next_back().transpose()?.map(|(k, _)| k), Some(b"nnaes".to_vec()));
+    assert_eq!(iter.next_back().transpose()?.map(|(k, _)| k), Some(b"meeae".to_vec()));
+    assert!(iter.next_back().is_none());
+    drop(iter);
+
+    let mut forward = keys(eng.scan(..))?;
+    forward.reverse();
+    assert_eq!(keys(eng.scan(..).rev())?, forward);
+
+    // Both ends meet in the middle, yielding each key once
+    let mut iter = eng.scan(..);
+    assert_eq!(iter.next().transpose()?.map(|(k, _)| k), Some(b"amhue".to_vec()));
+    assert_eq!(iter.next_back().transpose()?.map(|(k, _)| k), Some(b"uujeh".to_vec()));
+    assert_eq!(keys(iter)?, vec![b"anehe".to_vec(), b"meeae".to_vec(), b"nnaes".to_vec()]);
+    Ok(())
+}
+
+/// Prefix scans return exactly the keys starting with the prefix
+pub fn test_scan_prefix(mut eng: impl Engine) -> Result<()> {
+    for key in [&b"ccnaes"[..], b"camhue", b"deeae", b"eeujeh", b"canehe", b"aanehe", b"c", b"ca", b"cb"] {
+        eng.set(key.to_vec(), b"value".to_vec())?;
+    }
+
+    let expect = vec![b"ca".to_vec(), b"camhue".to_vec(), b"canehe".to_vec()];
+    assert_eq!(keys(eng.scan_prefix(b"ca".to_vec()))?, expect);
+    assert_eq!(keys(eng.scan_prefix(b"ca".to_vec()).rev())?, expect.into_iter().rev().collect::<Vec<_>>());
+    assert_eq!(keys(eng.scan_prefix(b"c".to_vec()))?.len(), 6);
+    assert_eq!(keys(eng.scan_prefix(b"canehe".to_vec()))?, vec![b"canehe".to_vec()]);
+    assert_eq!(keys(eng.scan_prefix(b"x".to_vec()))?, Vec::<Vec<u8>>::new());
+    Ok(())
+}
+
+/// Keys with 0x00 and 0xFF bytes after the prefix belong to it, the next
+/// prefix up doesn't; prefixes may end in 0xFF bytes themselves
+pub fn test_prefix_boundaries(mut eng: impl Engine) -> Result<()> {
+    let inside = [vec![b'a'], vec![b'a', 0x00], vec![b'a', 0x00, 0xff], vec![b'a', 0xff], vec![b'a', 0xff, 0xff]];
+    for key in inside.iter().chain(&[vec![0x60, 0xff], vec![b'b'], vec![b'b', 0x00]]) {
+        eng.set(key.clone(), key.clone())?;
+    }
+
+    assert_eq!(keys(eng.scan_prefix(b"a".to_vec()))?, inside.to_vec());
+    let (last, value) = eng.scan_prefix(b"a".to_vec()).next_back().transpose()?.unwrap();
+    assert_eq!(last, vec![b'a', 0xff, 0xff]);
+    assert_eq!(value, last);
+    assert_eq!(keys(eng.scan_prefix(vec![b'a', 0x00]))?, vec![vec![b'a', 0x00], vec![b'a', 0x00, 0xff]]);
+
+    let end = vec![b'a', 0xff, 0xff];
+    assert_eq!(keys(eng.scan(vec![b'a', 0xff]..=end.clone()))?, vec![vec![b'a', 0xff], end]);
+
+    assert_eq!(keys(eng.scan_prefix(vec![b'a', 0xff]))?, vec![vec![b'a', 0xff], vec![b'a', 0xff, 0xff]]);
+    assert_eq!(keys(eng.scan_prefix(vec![b'a', 0xff, 0xff]))?, vec![vec![b'a', 0xff, 0xff]]);
+    assert_eq!(keys(eng.scan_prefix(vec![0x60, 0xff]))?, vec![vec![0x60, 0xff]]);
+    assert_eq!(keys(eng.scan_prefix(vec![]))?.len(), 8);
+
+    // All-0xFF prefixes run to the end of the keyspace
+    eng.set(vec![0xff], vec![])?;
+    eng.set(vec![0xff, 0xff, 0x00], vec![])?;
+    assert_eq!(keys(eng.scan_prefix(vec![0xff, 0xff]))?, vec![vec![0xff, 0xff, 0x00]]);
+    assert_eq!(keys(eng.scan_prefix(vec![0xff]).rev())?, vec![vec![0xff, 0xff, 0x00], vec![0xff]]);
+    Ok(())
+}
+
+/// Large keys and values come back whole
+pub fn test_large_values(mut eng: impl Engine) -> Result<()> {
+    let value = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let key = vec![b'k'; 4096];
+    eng.set(key.clone(), value.clone())?;
+    eng.set(b"small".to_vec(), b"v".to_vec())?;
+    assert_eq!(eng.get(key.clone())?, Some(value.clone()));
+
+    let items = eng.scan(..).collect::<Result<Vec<_>>>()?;
+    assert_eq!(items, vec![(key, value), (b"small".to_vec(), b"v".to_vec())]);
+    Ok(())
+}
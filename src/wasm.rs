@@ -0,0 +1,155 @@
+//! Browser bindings (feature `wasm`)
+//!
+//! Runs an in-memory database inside a WebAssembly module, for an in-browser
+//! playground. Build with
+//! `cargo build --target wasm32-unknown-unknown --features wasm` and generate
+//! the JavaScript glue with `wasm-bindgen`. From JavaScript:
+//!
+//! ```js
+//! execute("create table t (a int primary key, b text);");
+//! JSON.parse(execute("select * from t;"));
+//! ```
+//!
+//! Queries return `{"columns": [{"name", "type", "nullable", "table"}], "rows": [[...]]}`.
+//! Other statements return `{"tag": "INSERT", "count": 1}` and the like.
+//! Integers beyond JavaScript's safe range, and UUIDs, are JSON strings.
+//! Points are `{"x", "y"}` objects. Errors are thrown as JavaScript errors.
+
+use std::cell::RefCell;
+
+use serde_json::{Value as Json, json};
+use wasm_bindgen::prelude::{JsError, wasm_bindgen};
+
+use crate::{
+    error::Error,
+    sql::{
+        engine::{Engine, Session, kv::KVEngine},
+        executor::ResultSet,
+        types::Value,
+    },
+    storage::memory::MemoryEngine,
+};
+
+thread_local! {
+    /// The page's database, created on first use
+    static SESSION: RefCell<Option<Session<KVEngine<MemoryEngine>>>> = const { RefCell::new(None) };
+}
+
+/// Executes a SQL statement on the page's database, returning its result as JSON text
+#[wasm_bindgen]
+pub fn execute(sql: &str) -> std::result::Result<String, JsError> {
+    SESSION
+        .with_borrow_mut(|session| {
+            let session = match session {
+                Some(session) => session,
+                None => session.insert(KVEngine::new(MemoryEngine::new()).session()?),
+            };
+            Ok(to_json(session.execute(sql)?).to_string())
+        })
+        .map_err(|err: Error| JsError::new(&err.to_string()))
+}
+
+/// Discards the page's database, starting over empty
+#[wasm_bindgen]
+pub fn reset() {
+    SESSION.with_borrow_mut(|session| *session = None);
+}
+
+/// JSON form of a statement result
+pub fn to_json(result: ResultSet) -> Json {
+    let completion = |tag: &str, count: usize| json!({ "tag": tag, "count": count });
+    match result {
+        ResultSet::Scan { columns, rows, metadata } => {
+            let columns = columns
+                .into_iter()
+                .zip(metadata)
+                .map(|(name, m)| {
+                    json!({
+                        "name": name,
+                        "type": m.datatype.map(|dt| dt.to_string()),
+                        "nullable": m.nullable,
+                        "table": m.table,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let rows = rows
+                .iter()
+                .map(|row| row.iter().map(value_to_json).collect())
+                .collect::<Vec<Json>>();
+            json!({ "columns": columns, "rows": rows })
+        }
+        ResultSet::CreateTable { table_name } => json!({ "tag": "CREATE TABLE", "table": table_name }),
+        ResultSet::Insert { count } => completion("INSERT", count),
+        ResultSet::Update { count } => completion("UPDATE", count),
+        ResultSet::Delete { count } => completion("DELETE", count),
+        ResultSet::Copy { count } => completion("COPY", count),
+        ResultSet::Command { tag } => json!({ "tag": tag }),
+    }
+}
+
+/// Largest integer a JavaScript number holds exactly
+const MAX_SAFE_INTEGER: i128 = (1 << 53) - 1;
+
+fn value_to_json(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Boolean(b) => json!(b),
+        Value::Integer(i) if i.abs() <= MAX_SAFE_INTEGER => json!(*i as i64),
+        Value::Integer(i) => json!(i.to_string()),
+        // NaN and infinities have no JSON form and become null
+        Value::Float(f) => json!(f),
+        Value::String(s) => json!(s),
+        Value::Point(x, y) => json!({ "x": x, "y": y }),
+        Value::Uuid(_) => json!(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value as Json, json};
+
+    use super::to_json;
+    use crate::{
+        error::Result,
+        sql::engine::{Engine, kv::KVEngine},
+        storage::memory::MemoryEngine,
+    };
+
+    /// Runs statements on a fresh database (`execute` errors need a JavaScript host)
+    fn execute_all(sqls: &[&str]) -> Result<Vec<Json>> {
+        let mut session = KVEngine::new(MemoryEngine::new()).session()?;
+        sqls.iter().map(|sql| Ok(to_json(session.execute(sql)?))).collect()
+    }
+
+    #[test]
+    fn test_to_json() -> Result<()> {
+        let results = execute_all(&[
+            "create table t1 (a bigint primary key, b text, c float, d point, e uuid);",
+            "insert into t1 values
+                (1, 'x', 1.5, point(1, 2), '67e55044-10b1-426f-9247-bb680e5fe0c8'),
+                (9007199254740993, null, null, null, null);",
+            "select a, b, d, e from t1;",
+            "select count(a) from t1;",
+        ])?;
+        assert_eq!(results[0], json!({ "tag": "CREATE TABLE", "table": "t1" }));
+        assert_eq!(results[1], json!({ "tag": "INSERT", "count": 2 }));
+        assert_eq!(
+            results[2],
+            json!({
+                "columns": [
+                    { "name": "a", "type": "BIGINT", "nullable": false, "table": "t1" },
+                    { "name": "b", "type": "STRING", "nullable": true, "table": "t1" },
+                    { "name": "d", "type": "POINT", "nullable": true, "table": "t1" },
+                    { "name": "e", "type": "UUID", "nullable": true, "table": "t1" },
+                ],
+                "rows": [
+                    [1, "x", { "x": 1.0, "y": 2.0 }, "67e55044-10b1-426f-9247-bb680e5fe0c8"],
+                    ["9007199254740993", null, null, null],
+                ],
+            })
+        );
+        assert_eq!(results[3]["rows"], json!([[2]]));
+        assert!(execute_all(&["select * from missing;"]).is_err());
+        Ok(())
+    }
+}